@@ -0,0 +1,183 @@
+use clap::{Parser, Subcommand};
+use jwst_logger::{error, info, init_logger};
+use jwst_rpc::{migrate_workspace, MigrateProgress};
+use jwst_storage::{ArchiveManifest, JwstStorage};
+use std::{path::PathBuf, process::exit};
+
+#[derive(Parser)]
+#[command(name = "octobase-cli", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Copy a workspace from another server into a local database,
+    /// run it again to resume an interrupted migration
+    Migrate {
+        /// Sync endpoint of the workspace on the source server, e.g. wss://host/collaboration/id
+        #[arg(long)]
+        from: String,
+        /// Id of the workspace to migrate
+        #[arg(long)]
+        workspace: String,
+        /// Token passed to the source server
+        #[arg(long)]
+        token: Option<String>,
+        /// Destination database, a sqlite database named jwst is used by default
+        #[arg(long, env = "DATABASE_URL")]
+        database: Option<String>,
+    },
+    /// Back up the doc and the blobs of a workspace into a directory
+    Export {
+        /// Id of the workspace to back up
+        #[arg(long)]
+        workspace: String,
+        /// Directory the archive is written to
+        #[arg(long)]
+        output: PathBuf,
+        /// Only back up what changed since the archive passed as --base
+        #[arg(long, requires = "base")]
+        incremental: bool,
+        /// Manifest of the previous backup, or the directory containing it
+        #[arg(long, requires = "incremental")]
+        base: Option<PathBuf>,
+        /// Database to back up, a sqlite database named jwst is used by default
+        #[arg(long, env = "DATABASE_URL")]
+        database: Option<String>,
+    },
+    /// Restore a workspace from a full backup followed by its incremental backups
+    Import {
+        /// Archive directories, oldest first
+        #[arg(required = true)]
+        archives: Vec<PathBuf>,
+        /// Keep the guid of the archived doc, to move a workspace instead of copying it
+        #[arg(long)]
+        preserve_identity: bool,
+        /// Destination database, a sqlite database named jwst is used by default
+        #[arg(long, env = "DATABASE_URL")]
+        database: Option<String>,
+    },
+}
+
+async fn open_storage(database: Option<String>) -> JwstStorage {
+    match database {
+        Some(database) => JwstStorage::new(&database).await,
+        None => JwstStorage::new_with_sqlite("jwst").await,
+    }
+    .expect("Cannot create database")
+}
+
+async fn migrate(from: String, workspace: String, token: Option<String>, database: Option<String>) {
+    let storage = open_storage(database).await;
+
+    let progress = MigrateProgress::default();
+    match migrate_workspace(
+        &from,
+        token.as_deref(),
+        None,
+        &storage,
+        &workspace,
+        &progress,
+    )
+    .await
+    {
+        Ok(report) => info!(
+            "migrated {workspace}: {} blocks, {} blobs downloaded, {} blobs already present",
+            report.blocks, report.blobs, report.skipped_blobs
+        ),
+        Err(e) => {
+            error!(
+                "failed to migrate {workspace} ({}/{} blobs done): {e}",
+                progress.blobs_done(),
+                progress.blobs_total()
+            );
+            exit(1);
+        }
+    }
+}
+
+async fn export(
+    workspace: String,
+    output: PathBuf,
+    base: Option<PathBuf>,
+    database: Option<String>,
+) {
+    let storage = open_storage(database).await;
+
+    let result = match base {
+        Some(base) => match ArchiveManifest::load(&base).await {
+            Ok(base) => {
+                storage
+                    .export_workspace_archive_incremental(&workspace, &base, &output)
+                    .await
+            }
+            Err(e) => Err(e),
+        },
+        None => storage.export_workspace_archive(&workspace, &output).await,
+    };
+    match result {
+        Ok(manifest) => info!(
+            "exported {workspace} to {}: archive {}, {} blobs",
+            output.display(),
+            manifest.id,
+            manifest.included_blobs.len()
+        ),
+        Err(e) => {
+            error!("failed to export {workspace}: {e}");
+            exit(1);
+        }
+    }
+}
+
+async fn import(archives: Vec<PathBuf>, preserve_identity: bool, database: Option<String>) {
+    let storage = open_storage(database).await;
+
+    match storage
+        .import_workspace_archives_with(&archives, preserve_identity)
+        .await
+    {
+        Ok(import) => {
+            info!(
+                "imported {} from {} archives, up to archive {}",
+                import.manifest.workspace,
+                archives.len(),
+                import.manifest.id
+            );
+            for (old, new) in import.guids {
+                info!("doc guid {old} is now {new}");
+            }
+        }
+        Err(e) => {
+            error!("failed to import archives: {e}");
+            exit(1);
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    init_logger();
+
+    match Cli::parse().command {
+        Command::Migrate {
+            from,
+            workspace,
+            token,
+            database,
+        } => migrate(from, workspace, token, database).await,
+        Command::Export {
+            workspace,
+            output,
+            base,
+            database,
+            ..
+        } => export(workspace, output, base, database).await,
+        Command::Import {
+            archives,
+            preserve_identity,
+            database,
+        } => import(archives, preserve_identity, database).await,
+    }
+}
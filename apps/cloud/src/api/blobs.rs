@@ -1,7 +1,7 @@
 use crate::{context::Context, error_status::ErrorStatus};
 use axum::{
     body::StreamBody,
-    extract::{BodyStream, Path},
+    extract::{multipart::Field, BodyStream, Multipart, Path},
     headers::ContentLength,
     response::{IntoResponse, Response},
     Extension, Json, TypedHeader,
@@ -14,14 +14,35 @@ use http::{
         CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH,
         LAST_MODIFIED,
     },
-    HeaderMap, HeaderValue,
+    HeaderMap, HeaderValue, StatusCode,
 };
 use jwst::{error, BlobStorage};
+use jwst_storage::JwstStorage;
 use mime::APPLICATION_OCTET_STREAM;
+use serde::Serialize;
 use std::sync::Arc;
 
+// size limit of every uploaded blob
+const MAX_BLOB_SIZE: u64 = 10 * 1024 * 1024;
+// size limit of the whole body of a batch upload
+pub(super) const MAX_BATCH_SIZE: usize = 50 * 1024 * 1024;
+
+/// Result of a file in a batch upload, `error` is set if the file was not saved.
+#[derive(Default, Serialize)]
+pub struct BlobUploadResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+    size: u64,
+    /// the same blob was already stored in the workspace
+    dedup: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
 impl Context {
-    async fn get_blob(
+    pub(super) async fn get_blob(
         &self,
         workspace: Option<String>,
         id: String,
@@ -138,7 +159,7 @@ pub async fn upload_blob(
     TypedHeader(length): TypedHeader<ContentLength>,
     stream: BodyStream,
 ) -> Response {
-    if length.0 > 10 * 1024 * 1024 {
+    if length.0 > MAX_BLOB_SIZE {
         return ErrorStatus::PayloadTooLarge.into_response();
     }
 
@@ -172,7 +193,7 @@ pub async fn upload_blob_in_workspace(
     TypedHeader(length): TypedHeader<ContentLength>,
     stream: BodyStream,
 ) -> Response {
-    if length.0 > 10 * 1024 * 1024 {
+    if length.0 > MAX_BLOB_SIZE {
         return ErrorStatus::PayloadTooLarge.into_response();
     }
 
@@ -192,6 +213,124 @@ pub async fn upload_blob_in_workspace(
     ctx.upload_blob(stream, Some(workspace_id)).await
 }
 
+async fn upload_blob_part(
+    storage: &JwstStorage,
+    workspace: &str,
+    field: Field<'_>,
+) -> BlobUploadResult {
+    let name = field
+        .file_name()
+        .or_else(|| field.name())
+        .map(ToOwned::to_owned);
+
+    let mut size = 0;
+    let mut failure = None;
+    let stream = field
+        .take_while(|chunk| {
+            match chunk {
+                Ok(bytes) if size + bytes.len() as u64 > MAX_BLOB_SIZE => {
+                    failure = Some("Upload file size exceeds 10MB".to_owned())
+                }
+                Ok(bytes) => size += bytes.len() as u64,
+                Err(e) => failure = Some(e.to_string()),
+            }
+            future::ready(failure.is_none())
+        })
+        .filter_map(|chunk| future::ready(chunk.ok()));
+
+    let stored = storage
+        .blobs()
+        .put_blob_dedup(Some(workspace.to_owned()), stream)
+        .await;
+
+    match (stored, failure) {
+        (Ok((hash, dedup)), None) => BlobUploadResult {
+            name,
+            hash: Some(hash),
+            size,
+            dedup,
+            error: None,
+        },
+        (Ok((hash, dedup)), Some(failure)) => {
+            // only a partial file was saved
+            if !dedup {
+                let _ = storage
+                    .blobs()
+                    .delete_blob(Some(workspace.to_owned()), hash)
+                    .await;
+            }
+            BlobUploadResult {
+                name,
+                error: Some(failure),
+                ..Default::default()
+            }
+        }
+        (Err(e), _) => {
+            error!("Failed to upload blob: {}", e);
+            BlobUploadResult {
+                name,
+                error: Some("Server error, please try again later.".to_owned()),
+                ..Default::default()
+            }
+        }
+    }
+}
+
+/// Save every file part of a multipart body one by one, a failed file does not
+/// stop the others, so the response is a 207 with the result of each file.
+async fn upload_blob_parts(
+    storage: &JwstStorage,
+    workspace: String,
+    length: u64,
+    mut multipart: Multipart,
+) -> Response {
+    if length > MAX_BATCH_SIZE as u64 {
+        return ErrorStatus::BatchTooLarge.into_response();
+    }
+
+    let mut results = vec![];
+    loop {
+        match multipart.next_field().await {
+            Ok(Some(field)) => results.push(upload_blob_part(storage, &workspace, field).await),
+            Ok(None) => break,
+            Err(e) => {
+                // the rest of the body can't be parsed anymore
+                error!("Failed to read multipart body: {}", e);
+                results.push(BlobUploadResult {
+                    error: Some(e.to_string()),
+                    ..Default::default()
+                });
+                break;
+            }
+        }
+    }
+
+    (StatusCode::MULTI_STATUS, Json(results)).into_response()
+}
+
+pub async fn upload_blobs_in_workspace(
+    Extension(ctx): Extension<Arc<Context>>,
+    Extension(claims): Extension<Arc<Claims>>,
+    Path(workspace_id): Path<String>,
+    TypedHeader(length): TypedHeader<ContentLength>,
+    multipart: Multipart,
+) -> Response {
+    match ctx
+        .db
+        .can_read_workspace(claims.user.id.clone(), workspace_id.clone())
+        .await
+    {
+        Ok(true) => (),
+        Ok(false) => return ErrorStatus::Forbidden.into_response(),
+        Err(e) => {
+            error!("Failed to check read workspace: {}", e);
+            return ErrorStatus::InternalServerError.into_response();
+        }
+    }
+
+    upload_blob_parts(&ctx.storage, workspace_id, length.0, multipart).await
+}
+
 pub async fn create_workspace(
     Extension(ctx): Extension<Arc<Context>>,
     Extension(claims): Extension<Arc<Claims>>,
@@ -216,3 +355,109 @@ pub async fn create_workspace(
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{
+        body::Body,
+        extract::DefaultBodyLimit,
+        http::Request,
+        routing::{post, Router},
+    };
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    const BOUNDARY: &str = "batch-boundary";
+
+    fn app(storage: Arc<JwstStorage>) -> Router {
+        Router::new().route(
+            "/batch",
+            post(
+                move |TypedHeader(length): TypedHeader<ContentLength>, multipart: Multipart| {
+                    let storage = storage.clone();
+                    async move { upload_blob_parts(&storage, "test".into(), length.0, multipart).await }
+                },
+            )
+            .layer(DefaultBodyLimit::max(MAX_BATCH_SIZE)),
+        )
+    }
+
+    async fn upload(storage: Arc<JwstStorage>, files: &[(&str, Vec<u8>)]) -> (StatusCode, Value) {
+        let mut body = vec![];
+        for (name, data) in files {
+            body.extend_from_slice(
+                format!(
+                    "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{name}\"\r\n\r\n"
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{BOUNDARY}--\r\n").as_bytes());
+
+        let request = Request::post("/batch")
+            .header(
+                CONTENT_TYPE,
+                format!("multipart/form-data; boundary={BOUNDARY}"),
+            )
+            .header(CONTENT_LENGTH, body.len())
+            .body(Body::from(body))
+            .unwrap();
+        let response = app(storage).oneshot(request).await.unwrap();
+
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn batch_upload_partial_success() {
+        let storage = Arc::new(JwstStorage::new("sqlite::memory:").await.unwrap());
+
+        let (status, results) = upload(
+            storage.clone(),
+            &[
+                ("a.png", vec![1, 2, 3]),
+                ("b.png", vec![4, 5]),
+                ("copy.png", vec![1, 2, 3]),
+                ("large.png", vec![0; MAX_BLOB_SIZE as usize + 1]),
+            ],
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::MULTI_STATUS);
+        let results = results.as_array().unwrap();
+        assert_eq!(results.len(), 4);
+
+        assert_eq!(results[0]["name"], "a.png");
+        assert_eq!(results[0]["size"], 3);
+        assert_eq!(results[0]["dedup"], false);
+        assert_eq!(results[1]["dedup"], false);
+        assert_eq!(results[2]["hash"], results[0]["hash"]);
+        assert_eq!(results[2]["dedup"], true);
+        assert_eq!(results[3]["name"], "large.png");
+        assert_eq!(results[3]["error"], "Upload file size exceeds 10MB");
+        assert_eq!(results[3].get("hash"), None);
+
+        // only the complete files are stored
+        assert_eq!(storage.blobs().count("test").await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn batch_upload_too_large() {
+        let storage = Arc::new(JwstStorage::new("sqlite::memory:").await.unwrap());
+
+        // each file is under the limit, but not all of them
+        let files = vec![("part.png", vec![0; 9 * 1024 * 1024]); 6];
+        let (status, result) = upload(storage.clone(), &files).await;
+
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            result,
+            json!({ "message": "Upload batch size exceeds 50MB" })
+        );
+        assert_eq!(storage.blobs().count("test").await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,476 @@
+use super::*;
+use cloud_database::{CloudDatabase, ExportFormat, ExportJob, ExportJobStatus};
+use futures::stream;
+use hmac::Mac;
+use http::{header::CONTENT_DISPOSITION, HeaderMap, HeaderValue};
+use jwst::info;
+use jwst_storage::JwstStorage;
+use pulldown_cmark::{html, Options, Parser};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{self, Cursor, Write},
+    path::Path as FsPath,
+};
+use tokio::{
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        Mutex,
+    },
+    task::spawn_blocking,
+};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+// blob namespace of the export results, apart from the blobs of the workspaces
+pub(super) const EXPORT_BLOBS: &str = "__exports__";
+// seconds a download link stays valid
+const DOWNLOAD_URL_TTL: i64 = 60 * 60;
+
+#[derive(Deserialize)]
+pub struct StartExport {
+    format: ExportFormat,
+}
+
+#[derive(Deserialize)]
+pub struct SignedDownload {
+    expires: i64,
+    signature: String,
+}
+
+#[derive(Serialize)]
+struct ExportJobResponse {
+    #[serde(flatten)]
+    job: ExportJob,
+    /// Signed link to the result once the job is done.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+}
+
+/// Export jobs waiting for a worker. Jobs are persisted in the database first,
+/// the queue only holds their ids.
+pub struct ExportQueue {
+    tx: UnboundedSender<String>,
+    rx: Mutex<UnboundedReceiver<String>>,
+    workers: usize,
+    jobs_per_user: u64,
+    retention: Duration,
+}
+
+impl ExportQueue {
+    pub fn new(workers: usize, jobs_per_user: u64, retention: Duration) -> Self {
+        let (tx, rx) = unbounded_channel();
+        Self {
+            tx,
+            rx: Mutex::new(rx),
+            workers: workers.max(1),
+            jobs_per_user,
+            retention,
+        }
+    }
+
+    /// `EXPORT_WORKERS` jobs run at once, a user can have `EXPORT_JOBS_PER_USER` jobs
+    /// pending or running, results are kept `EXPORT_RETENTION_HOURS` after the job ends.
+    pub fn from_env() -> Self {
+        let var = |name: &str, default: u64| {
+            dotenvy::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+        Self::new(
+            var("EXPORT_WORKERS", 2) as usize,
+            var("EXPORT_JOBS_PER_USER", 2),
+            Duration::hours(var("EXPORT_RETENTION_HOURS", 24) as i64),
+        )
+    }
+
+    fn push(&self, job_id: String) {
+        // the receiver lives as long as the queue
+        let _ = self.tx.send(job_id);
+    }
+
+    async fn next(&self) -> Option<String> {
+        self.rx.lock().await.recv().await
+    }
+}
+
+impl Context {
+    fn sign_export(&self, job_id: &str, expires: i64) -> String {
+        let mut mac = self.key.url.clone();
+        mac.update(format!("{job_id}:{expires}").as_bytes());
+        URL_SAFE_ENGINE.encode(mac.finalize().into_bytes())
+    }
+
+    fn verify_export(&self, job_id: &str, download: &SignedDownload) -> bool {
+        let Ok(signature) = URL_SAFE_ENGINE.decode(&download.signature) else {
+            return false;
+        };
+        let mut mac = self.key.url.clone();
+        mac.update(format!("{job_id}:{}", download.expires).as_bytes());
+        download.expires >= Utc::now().timestamp() && mac.verify_slice(&signature).is_ok()
+    }
+
+    fn export_download_url(&self, job_id: &str) -> String {
+        let expires = Utc::now().timestamp() + DOWNLOAD_URL_TTL;
+        format!(
+            "{}/api/jobs/{job_id}/download?expires={expires}&signature={}",
+            self.site_url,
+            self.sign_export(job_id, expires)
+        )
+    }
+}
+
+/// Queue the jobs a restart interrupted, start the workers and the removal of expired results.
+pub async fn start_export_workers(ctx: Arc<Context>) {
+    resume_export_jobs(&ctx.db, &ctx.exports).await;
+
+    for _ in 0..ctx.exports.workers {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            while let Some(job_id) = ctx.exports.next().await {
+                run_export_job(&ctx.db, &ctx.storage, job_id).await;
+            }
+        });
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            remove_expired_exports(&ctx.db, &ctx.storage, ctx.exports.retention).await;
+        }
+    });
+}
+
+async fn resume_export_jobs(db: &CloudDatabase, queue: &ExportQueue) {
+    match db.get_unfinished_export_jobs().await {
+        Ok(jobs) => {
+            info!("resume {} export jobs", jobs.len());
+            for job in jobs {
+                queue.push(job.id);
+            }
+        }
+        Err(e) => error!("Failed to get unfinished export jobs: {:?}", e),
+    }
+}
+
+/// Run a job from the start, jobs that ended meanwhile are skipped.
+async fn run_export_job(db: &CloudDatabase, storage: &JwstStorage, job_id: String) {
+    let job = match db.start_export_job(job_id.clone()).await {
+        Ok(Some(job)) => job,
+        Ok(None) => return,
+        Err(e) => {
+            error!("Failed to start export job {job_id}: {:?}", e);
+            return;
+        }
+    };
+
+    info!(
+        "export {} of workspace {}",
+        job.format.as_str(),
+        job.workspace_id
+    );
+    let result = export_workspace(db, storage, &job).await.map_err(|e| {
+        error!("Failed to export workspace {}: {:?}", job.workspace_id, e);
+        e.to_string()
+    });
+    if let Err(e) = db.finish_export_job(job_id.clone(), result).await {
+        error!("Failed to finish export job {job_id}: {:?}", e);
+    }
+}
+
+// write the result of a job into the blob storage and return its hash
+async fn export_workspace(
+    db: &CloudDatabase,
+    storage: &JwstStorage,
+    job: &ExportJob,
+) -> anyhow::Result<String> {
+    let data = match job.format {
+        ExportFormat::Archive => {
+            let dir = std::env::temp_dir().join(format!("affine-export-{}", job.id));
+            // left over if the server stopped during the export
+            let stale = dir.clone();
+            spawn_blocking(move || remove_dir(&stale)).await??;
+
+            storage
+                .export_workspace_archive(&job.workspace_id, &dir)
+                .await?;
+            db.update_export_job_progress(job.id.clone(), 50).await?;
+
+            spawn_blocking(move || {
+                let zipped = zip_dir(&dir);
+                remove_dir(&dir)?;
+                zipped
+            })
+            .await??
+        }
+        ExportFormat::Markdown | ExportFormat::Html => {
+            let workspace = storage.get_workspace(&job.workspace_id).await?;
+            db.update_export_job_progress(job.id.clone(), 20).await?;
+
+            let markdown = spawn_blocking(move || workspace.export_to_notion_format()).await??;
+            if job.format == ExportFormat::Html {
+                render_html(&job.workspace_id, &markdown).into_bytes()
+            } else {
+                markdown.into_bytes()
+            }
+        }
+    };
+    db.update_export_job_progress(job.id.clone(), 80).await?;
+
+    Ok(storage
+        .blobs()
+        .put_blob(Some(EXPORT_BLOBS.into()), stream::iter([Bytes::from(data)]))
+        .await?)
+}
+
+fn remove_dir(dir: &FsPath) -> io::Result<()> {
+    match std::fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// zip the files under `dir`, named by their path relative to it
+fn zip_dir(dir: &FsPath) -> anyhow::Result<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(vec![]));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in std::fs::read_dir(&current)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let name = path.strip_prefix(dir)?.to_string_lossy().replace('\\', "/");
+            zip.start_file(name, options)?;
+            zip.write_all(&std::fs::read(&path)?)?;
+        }
+    }
+
+    Ok(zip.finish()?.into_inner())
+}
+
+fn render_html(title: &str, markdown: &str) -> String {
+    let mut body = String::new();
+    html::push_html(&mut body, Parser::new_ext(markdown, Options::ENABLE_TABLES));
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{title}</title>\n</head>\n<body>\n{body}</body>\n</html>\n"
+    )
+}
+
+async fn remove_expired_exports(db: &CloudDatabase, storage: &JwstStorage, retention: Duration) {
+    match db.delete_expired_export_jobs(Utc::now() - retention).await {
+        Ok(blobs) => {
+            for blob in blobs {
+                if let Err(e) = storage
+                    .blobs()
+                    .delete_blob(Some(EXPORT_BLOBS.into()), blob.clone())
+                    .await
+                {
+                    error!("Failed to delete export {blob}: {:?}", e);
+                }
+            }
+        }
+        Err(e) => error!("Failed to delete expired export jobs: {:?}", e),
+    }
+}
+
+/// Queue an export of a workspace, poll it with [get_export_job].
+pub async fn start_export(
+    Extension(ctx): Extension<Arc<Context>>,
+    Extension(claims): Extension<Arc<Claims>>,
+    Path(workspace_id): Path<String>,
+    Query(payload): Query<StartExport>,
+) -> Response {
+    match ctx
+        .db
+        .can_read_workspace(claims.user.id.clone(), workspace_id.clone())
+        .await
+    {
+        Ok(true) => (),
+        Ok(false) => return ErrorStatus::Forbidden.into_response(),
+        Err(e) => {
+            error!("Failed to check read permission: {:?}", e);
+            return ErrorStatus::InternalServerError.into_response();
+        }
+    }
+
+    match ctx
+        .db
+        .create_export_job(
+            claims.user.id.clone(),
+            workspace_id,
+            payload.format,
+            ctx.exports.jobs_per_user,
+        )
+        .await
+    {
+        Ok(Some(job)) => {
+            ctx.exports.push(job.id.clone());
+            (StatusCode::ACCEPTED, Json(job)).into_response()
+        }
+        Ok(None) => ErrorStatus::TooManyExports.into_response(),
+        Err(e) => {
+            error!("Failed to create export job: {:?}", e);
+            ErrorStatus::InternalServerError.into_response()
+        }
+    }
+}
+
+/// Status of an export job of the user, with a signed download link once it is done.
+pub async fn get_export_job(
+    Extension(ctx): Extension<Arc<Context>>,
+    Extension(claims): Extension<Arc<Claims>>,
+    Path(job_id): Path<String>,
+) -> Response {
+    match ctx.db.get_export_job(job_id).await {
+        Ok(Some(job)) if job.user_id == claims.user.id => {
+            let url =
+                (job.status == ExportJobStatus::Done).then(|| ctx.export_download_url(&job.id));
+            Json(ExportJobResponse { job, url }).into_response()
+        }
+        Ok(_) => ErrorStatus::NotFound.into_response(),
+        Err(e) => {
+            error!("Failed to get export job: {:?}", e);
+            ErrorStatus::InternalServerError.into_response()
+        }
+    }
+}
+
+/// Result of an export job, authorized by the signature of the link instead of a token.
+pub async fn download_export(
+    Extension(ctx): Extension<Arc<Context>>,
+    Path(job_id): Path<String>,
+    Query(download): Query<SignedDownload>,
+    method: http::Method,
+    headers: HeaderMap,
+) -> Response {
+    if !ctx.verify_export(&job_id, &download) {
+        return ErrorStatus::Forbidden.into_response();
+    }
+
+    let (blob, workspace_id, format) = match ctx.db.get_export_job(job_id).await {
+        Ok(Some(ExportJob {
+            blob: Some(blob),
+            workspace_id,
+            format,
+            ..
+        })) => (blob, workspace_id, format),
+        Ok(_) => return ErrorStatus::NotFound.into_response(),
+        Err(e) => {
+            error!("Failed to get export job: {:?}", e);
+            return ErrorStatus::InternalServerError.into_response();
+        }
+    };
+
+    let mut response = ctx
+        .get_blob(Some(EXPORT_BLOBS.into()), blob, method, headers)
+        .await;
+    let extension = match format {
+        ExportFormat::Archive => "zip",
+        ExportFormat::Markdown => "md",
+        ExportFormat::Html => "html",
+    };
+    if let Ok(disposition) = HeaderValue::from_str(&format!(
+        "attachment; filename=\"{workspace_id}.{extension}\""
+    )) {
+        response
+            .headers_mut()
+            .insert(CONTENT_DISPOSITION, disposition);
+    }
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cloud_database::CreateUser;
+
+    async fn read_export(storage: &JwstStorage, db: &CloudDatabase, job_id: &str) -> Vec<u8> {
+        let job = db.get_export_job(job_id.into()).await.unwrap().unwrap();
+        assert_eq!(job.status, ExportJobStatus::Done, "{:?}", job.error);
+        assert_eq!(job.progress, 100);
+        storage
+            .blobs()
+            .get(EXPORT_BLOBS, &job.blob.unwrap())
+            .await
+            .unwrap()
+            .blob
+    }
+
+    #[tokio::test]
+    async fn resume_export_jobs_after_restart() {
+        let db = CloudDatabase::init_pool("sqlite::memory:").await.unwrap();
+        let storage = JwstStorage::new("sqlite::memory:").await.unwrap();
+        let (user, _) = db
+            .create_user(CreateUser {
+                avatar_url: None,
+                email: "export@xxx.xx".to_string(),
+                name: "export".to_string(),
+                password: "xxx".to_string(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        let workspace = db.create_normal_workspace(user.id.clone()).await.unwrap();
+        storage
+            .create_workspace(&workspace.id)
+            .await
+            .unwrap()
+            .with_trx(|mut t| {
+                let page = t.create("page", "affine:page");
+                page.set(&mut t.trx, "title", "Exported");
+            });
+
+        let queue = ExportQueue::new(1, 2, Duration::hours(1));
+        let create = |format| {
+            db.create_export_job(
+                user.id.clone(),
+                workspace.id.clone(),
+                format,
+                queue.jobs_per_user,
+            )
+        };
+        let markdown = create(ExportFormat::Markdown).await.unwrap().unwrap();
+        let html = create(ExportFormat::Html).await.unwrap().unwrap();
+        // the user already has two unfinished jobs
+        assert!(create(ExportFormat::Archive).await.unwrap().is_none());
+
+        // the server stops while the markdown export runs, the html one never started
+        db.start_export_job(markdown.id.clone()).await.unwrap();
+        db.update_export_job_progress(markdown.id.clone(), 20)
+            .await
+            .unwrap();
+
+        // both run again after the restart
+        resume_export_jobs(&db, &queue).await;
+        for _ in 0..2 {
+            let job_id = queue.next().await.unwrap();
+            run_export_job(&db, &storage, job_id).await;
+        }
+        assert!(db.get_unfinished_export_jobs().await.unwrap().is_empty());
+
+        let exported = read_export(&storage, &db, &markdown.id).await;
+        assert!(String::from_utf8(exported)
+            .unwrap()
+            .contains("# Exported\n"));
+        let exported = String::from_utf8(read_export(&storage, &db, &html.id).await).unwrap();
+        assert!(exported.starts_with("<!DOCTYPE html>"));
+        assert!(exported.contains("<h1>Exported</h1>"));
+
+        // finished jobs don't count towards the limit
+        let archive = create(ExportFormat::Archive).await.unwrap().unwrap();
+        run_export_job(&db, &storage, archive.id.clone()).await;
+        assert!(read_export(&storage, &db, &archive.id)
+            .await
+            .starts_with(b"PK"));
+
+        // results are removed once the jobs expire
+        remove_expired_exports(&db, &storage, Duration::seconds(-1)).await;
+        assert!(db.get_export_job(archive.id).await.unwrap().is_none());
+        assert_eq!(storage.blobs().count(EXPORT_BLOBS).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,160 @@
+use super::*;
+use dashmap::mapref::entry::Entry;
+use jwst_rpc::{migrate_workspace, MigrateProgress, MigrateReport};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+#[derive(Deserialize)]
+pub struct StartMigration {
+    /// Sync endpoint of the workspace on the source server.
+    from: String,
+    token: Option<String>,
+}
+
+/// Migration of a workspace running in the background.
+#[derive(Default)]
+pub struct MigrationJob {
+    progress: MigrateProgress,
+    result: Mutex<Option<Result<MigrateReport, String>>>,
+}
+
+impl MigrationJob {
+    fn finished(&self) -> bool {
+        self.result.lock().unwrap().is_some()
+    }
+}
+
+#[derive(Serialize)]
+struct MigrationStatus {
+    synced: bool,
+    blobs_total: usize,
+    blobs_done: usize,
+    finished: bool,
+    blocks: Option<u32>,
+    error: Option<String>,
+}
+
+impl From<&MigrationJob> for MigrationStatus {
+    fn from(job: &MigrationJob) -> Self {
+        let result = job.result.lock().unwrap();
+        Self {
+            synced: job.progress.synced(),
+            blobs_total: job.progress.blobs_total(),
+            blobs_done: job.progress.blobs_done(),
+            finished: result.is_some(),
+            blocks: result
+                .as_ref()
+                .and_then(|result| result.as_ref().ok())
+                .map(|report| report.blocks),
+            error: result
+                .as_ref()
+                .and_then(|result| result.as_ref().err())
+                .cloned(),
+        }
+    }
+}
+
+/// Hosts workspaces can be migrated from, a comma separated `MIGRATION_HOSTS`.
+/// Nothing can be migrated if it is not set.
+fn allowed_hosts() -> Vec<String> {
+    dotenvy::var("MIGRATION_HOSTS")
+        .map(|hosts| {
+            hosts
+                .split(',')
+                .map(|host| host.trim().to_owned())
+                .filter(|host| !host.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+async fn check_owner(ctx: &Context, claims: &Claims, workspace_id: &str) -> Option<Response> {
+    match ctx
+        .db
+        .get_permission(claims.user.id.clone(), workspace_id.to_owned())
+        .await
+    {
+        Ok(Some(p)) if p.is_owner() => None,
+        Ok(_) => Some(ErrorStatus::Forbidden.into_response()),
+        Err(e) => {
+            error!("Failed to get permission: {:?}", e);
+            Some(ErrorStatus::InternalServerError.into_response())
+        }
+    }
+}
+
+/// Start copying a workspace from another server into this one, the doc is merged
+/// into the existing workspace. Starting it again after a failure resumes the migration.
+pub async fn start_migration(
+    Extension(ctx): Extension<Arc<Context>>,
+    Extension(claims): Extension<Arc<Claims>>,
+    Path(workspace_id): Path<String>,
+    Json(payload): Json<StartMigration>,
+) -> Response {
+    if let Some(resp) = check_owner(&ctx, &claims, &workspace_id).await {
+        return resp;
+    }
+
+    let hosts = allowed_hosts();
+    let allowed = reqwest::Url::parse(&payload.from)
+        .ok()
+        .map_or(false, |url| {
+            url.host_str().map_or(false, |host| {
+                hosts.iter().any(|h| h.eq_ignore_ascii_case(host))
+            })
+        });
+    if !allowed {
+        return ErrorStatus::BadRequest.into_response();
+    }
+
+    let job = Arc::new(MigrationJob::default());
+    match ctx.migrations.entry(workspace_id.clone()) {
+        Entry::Occupied(entry) if !entry.get().finished() => {
+            return ErrorStatus::ConflictMigration.into_response()
+        }
+        Entry::Occupied(mut entry) => {
+            entry.insert(job.clone());
+        }
+        Entry::Vacant(entry) => {
+            entry.insert(job.clone());
+        }
+    }
+
+    tokio::spawn({
+        let ctx = ctx.clone();
+        async move {
+            let result = migrate_workspace(
+                &payload.from,
+                payload.token.as_deref(),
+                Some(&hosts),
+                &ctx.storage,
+                &workspace_id,
+                &job.progress,
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to migrate workspace {workspace_id}: {:?}", e);
+                e.to_string()
+            });
+            *job.result.lock().unwrap() = Some(result);
+        }
+    });
+
+    StatusCode::ACCEPTED.into_response()
+}
+
+/// Progress of the last migration of a workspace.
+pub async fn get_migration(
+    Extension(ctx): Extension<Arc<Context>>,
+    Extension(claims): Extension<Arc<Claims>>,
+    Path(workspace_id): Path<String>,
+) -> Response {
+    if let Some(resp) = check_owner(&ctx, &claims, &workspace_id).await {
+        return resp;
+    }
+
+    match ctx.migrations.get(&workspace_id) {
+        Some(job) => Json(MigrationStatus::from(job.as_ref())).into_response(),
+        None => ErrorStatus::NotFound.into_response(),
+    }
+}
@@ -1,8 +1,14 @@
 mod blobs;
+mod export;
+mod migrate;
+mod notifications;
+mod oauth;
 mod permissions;
 
 use axum::{
-    extract::{Path, Query},
+    body::Bytes,
+    extract::{DefaultBodyLimit, Path, Query},
+    middleware::from_fn_with_state,
     response::{IntoResponse, Response},
     routing::{delete, get, post, put, Router},
     Extension, Json,
@@ -10,17 +16,21 @@ use axum::{
 use base64::Engine;
 use chrono::{Duration, Utc};
 use cloud_database::{
-    Claims, MakeToken, RefreshToken, UpdateWorkspace, User, UserQuery, UserToken,
+    Claims, MakeToken, RefreshToken, UpdateWorkspace, UserQuery, UserToken, UserWithNonce,
     WorkspaceSearchInput,
 };
 use http::StatusCode;
 use jwst::{error, BlobStorage, JwstError};
+use jwst_rpc::handle_poll;
 use lib0::any::Any;
 use std::sync::Arc;
 use tower::ServiceBuilder;
 
 use crate::{
-    context::Context, error_status::ErrorStatus, layer::make_firebase_auth_layer,
+    context::Context,
+    error_status::ErrorStatus,
+    layer::make_firebase_auth_layer,
+    load::{shed_load, Priority},
     utils::URL_SAFE_ENGINE,
 };
 
@@ -30,16 +40,33 @@ pub use ws::*;
 mod user_channel;
 pub use user_channel::*;
 
+pub use export::{start_export_workers, ExportQueue};
+pub use migrate::MigrationJob;
+pub use notifications::{start_notification_worker, Notifications};
+pub use oauth::AuthProviders;
+
 pub fn make_rest_route(ctx: Arc<Context>) -> Router {
     Router::new()
         .route("/healthz", get(health_check))
         .route("/user", get(query_user))
         .route("/user/token", post(make_token))
-        .route("/blob", put(blobs::upload_blob))
+        .route("/auth/:provider/login", get(oauth::oauth_login))
+        .route("/auth/:provider/callback", get(oauth::oauth_callback))
+        .route(
+            "/blob",
+            put(blobs::upload_blob).route_layer(from_fn_with_state(Priority::Normal, shed_load)),
+        )
         .route("/blob/:name", get(blobs::get_blob))
         .route("/invitation/:path", post(permissions::accept_invitation))
         .nest_service("/global/sync", get(global_ws_handler))
-        .route("/public/doc/:id", get(get_public_doc))
+        .route(
+            "/public/doc/:id",
+            get(get_public_doc).route_layer(from_fn_with_state(Priority::Normal, shed_load)),
+        )
+        .route(
+            "/jobs/:id/download",
+            get(export::download_export).route_layer(from_fn_with_state(Priority::Low, shed_load)),
+        )
         // TODO: Will consider this permission in the future
         .route(
             "/workspace/:id/blob/:name",
@@ -64,10 +91,49 @@ pub fn make_rest_route(ctx: Arc<Context>) -> Router {
                         .post(permissions::invite_member)
                         .delete(permissions::leave_workspace),
                 )
-                .route("/workspace/:id/doc", get(get_doc))
-                .route("/workspace/:id/search", post(search_workspace))
-                .route("/workspace/:id/blob", put(blobs::upload_blob_in_workspace))
+                .route(
+                    "/workspace/:id/doc",
+                    get(get_doc).route_layer(from_fn_with_state(Priority::Normal, shed_load)),
+                )
+                .route(
+                    "/workspace/:id/search",
+                    post(search_workspace)
+                        .route_layer(from_fn_with_state(Priority::Low, shed_load)),
+                )
+                .route(
+                    "/workspace/:id/export",
+                    post(export::start_export)
+                        .route_layer(from_fn_with_state(Priority::Low, shed_load)),
+                )
+                .route("/jobs/:id", get(export::get_export_job))
+                .route("/workspace/:id/poll", post(poll_workspace))
+                .route(
+                    "/workspace/:id/migrate",
+                    get(migrate::get_migration)
+                        .post(migrate::start_migration)
+                        .route_layer(from_fn_with_state(Priority::Low, shed_load)),
+                )
+                .route(
+                    "/workspace/:id/blob",
+                    put(blobs::upload_blob_in_workspace)
+                        .route_layer(from_fn_with_state(Priority::Normal, shed_load)),
+                )
+                .route(
+                    "/workspace/:id/blob/batch",
+                    post(blobs::upload_blobs_in_workspace)
+                        .layer(DefaultBodyLimit::max(blobs::MAX_BATCH_SIZE))
+                        .route_layer(from_fn_with_state(Priority::Low, shed_load)),
+                )
                 .route("/permission/:id", delete(permissions::remove_user))
+                .route("/user/notifications", get(notifications::get_notifications))
+                .route(
+                    "/user/notifications/count",
+                    get(notifications::get_unread_count),
+                )
+                .route(
+                    "/user/notifications/read",
+                    post(notifications::mark_notifications_read),
+                )
                 .layer(
                     ServiceBuilder::new()
                         .layer(make_firebase_auth_layer(ctx.key.jwt_decode.clone())),
@@ -137,35 +203,7 @@ async fn make_token(
     };
 
     match user {
-        Ok(Some(user)) => {
-            let refresh = refresh.unwrap_or_else(|| {
-                let refresh = RefreshToken {
-                    expires: Utc::now().naive_utc() + Duration::days(180),
-                    user_id: user.id.clone(),
-                    token_nonce: user.token_nonce.unwrap(),
-                };
-
-                let json = serde_json::to_string(&refresh).unwrap();
-
-                let data = ctx.encrypt_aes(json.as_bytes());
-
-                URL_SAFE_ENGINE.encode(data)
-            });
-
-            let claims = Claims {
-                exp: Utc::now().naive_utc() + Duration::minutes(10),
-                user: User {
-                    id: user.id,
-                    name: user.name,
-                    email: user.email,
-                    avatar_url: user.avatar_url,
-                    created_at: user.created_at.unwrap_or_default().naive_local(),
-                },
-            };
-            let token = ctx.sign_jwt(&claims);
-
-            Json(UserToken { token, refresh }).into_response()
-        }
+        Ok(Some(user)) => Json(make_user_token(&ctx, user.into(), refresh)).into_response(),
         Ok(None) => ErrorStatus::Unauthorized.into_response(),
         Err(e) => {
             error!("Failed to make token: {:?}", e);
@@ -174,6 +212,30 @@ async fn make_token(
     }
 }
 
+fn make_user_token(ctx: &Context, user: UserWithNonce, refresh: Option<String>) -> UserToken {
+    let refresh = refresh.unwrap_or_else(|| {
+        let refresh = RefreshToken {
+            expires: Utc::now().naive_utc() + Duration::days(180),
+            user_id: user.user.id.clone(),
+            token_nonce: user.token_nonce,
+        };
+
+        let json = serde_json::to_string(&refresh).unwrap();
+
+        let data = ctx.encrypt_aes(json.as_bytes());
+
+        URL_SAFE_ENGINE.encode(data)
+    });
+
+    let claims = Claims {
+        exp: Utc::now().naive_utc() + Duration::minutes(10),
+        user: user.user,
+    };
+    let token = ctx.sign_jwt(&claims);
+
+    UserToken { token, refresh }
+}
+
 async fn get_workspaces(
     Extension(ctx): Extension<Arc<Context>>,
     Extension(claims): Extension<Arc<Claims>>,
@@ -332,6 +394,51 @@ async fn get_workspace_doc(ctx: Arc<Context>, workspace_id: String) -> Response
     }
 }
 
+// how long a poll waits for changes before answering 204
+const POLL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Long-poll sync for clients that can't use websocket, see [`handle_poll`].
+async fn poll_workspace(
+    Extension(ctx): Extension<Arc<Context>>,
+    Extension(claims): Extension<Arc<Claims>>,
+    Path(workspace_id): Path<String>,
+    body: Bytes,
+) -> Response {
+    match ctx
+        .db
+        .can_read_workspace(claims.user.id.clone(), workspace_id.clone())
+        .await
+    {
+        Ok(true) => (),
+        Ok(false) => return ErrorStatus::Forbidden.into_response(),
+        Err(e) => {
+            error!("Failed to get permission: {:?}", e);
+            return ErrorStatus::InternalServerError.into_response();
+        }
+    }
+
+    // the updates are checked against the permission of the user, like the ones of a
+    // websocket, readers of a public workspace can't write to it
+    match handle_poll(
+        ctx.clone(),
+        workspace_id,
+        &claims.user.id,
+        &body,
+        POLL_TIMEOUT,
+    )
+    .await
+    {
+        Ok(Some(reply)) => reply.into_response(),
+        Ok(None) => StatusCode::NO_CONTENT.into_response(),
+        Err(JwstError::WorkspaceNotFound(_)) => ErrorStatus::NotFound.into_response(),
+        Err(JwstError::Permission(_)) => ErrorStatus::Forbidden.into_response(),
+        Err(e) => {
+            error!("Failed to poll workspace: {:?}", e);
+            ErrorStatus::BadRequest.into_response()
+        }
+    }
+}
+
 /// Resolves to [`SearchResults`]
 ///
 /// [`SearchResults`]: jwst::SearchResults
@@ -0,0 +1,644 @@
+use super::*;
+use async_trait::async_trait;
+use cloud_database::{CloudDatabase, Notification, NotificationEvent, NotificationKind};
+use http::header::AUTHORIZATION;
+use jwst::{
+    plugins::{PluginImpl, PluginRegister},
+    ChangesSubscription, Workspace, WorkspaceChanges,
+};
+use jwst_storage::JwstStorage;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Mutex as StdMutex,
+};
+use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    Mutex,
+};
+
+/// Block property holding the id of the user a block mentions.
+pub const MENTION_PROPERTY: &str = "mention";
+const DEFAULT_PAGE_SIZE: u64 = 20;
+const MAX_PAGE_SIZE: u64 = 100;
+const FCM_ENDPOINT: &str = "https://fcm.googleapis.com/fcm/send";
+
+#[derive(Deserialize)]
+pub struct NotificationPage {
+    #[serde(default)]
+    offset: u64,
+    limit: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct MarkRead {
+    /// Every unread notification of the user if not set.
+    ids: Option<Vec<String>>,
+}
+
+#[derive(Serialize)]
+struct UnreadCount {
+    unread: u64,
+}
+
+/// Delivers the notifications out of band, e.g. as push notifications of the mobile clients.
+#[async_trait]
+pub trait NotificationPusher: Send + Sync {
+    async fn push(&self, notification: &Notification) -> anyhow::Result<()>;
+}
+
+/// Pushes through the legacy HTTP API of Firebase Cloud Messaging, to the topic
+/// `user-{id}` of the recipient that its clients subscribe to.
+pub struct FcmPusher {
+    client: Client,
+    endpoint: String,
+    server_key: String,
+}
+
+impl FcmPusher {
+    pub fn new(client: Client, endpoint: String, server_key: String) -> Self {
+        Self {
+            client,
+            endpoint,
+            server_key,
+        }
+    }
+}
+
+#[async_trait]
+impl NotificationPusher for FcmPusher {
+    async fn push(&self, notification: &Notification) -> anyhow::Result<()> {
+        let title = match notification.event.kind {
+            NotificationKind::Mention => "You were mentioned",
+            NotificationKind::InvitationAccepted => "Your invitation was accepted",
+        };
+        self.client
+            .post(&self.endpoint)
+            .header(AUTHORIZATION, format!("key={}", self.server_key))
+            .json(&json!({
+                "to": format!("/topics/user-{}", notification.user_id),
+                "notification": { "title": title },
+                "data": notification,
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+// blocks that may mention users, read once the transaction changing them is committed
+struct MentionCandidates {
+    workspace_id: String,
+    blocks: Vec<String>,
+    // the user whose sync update changed the blocks, `None` for changes of the server
+    actor: Option<String>,
+}
+
+/// Looks for mentions in the blocks changed in a workspace, registered as a plugin of
+/// the workspaces loaded by the storage, see [Notifications::mention_watch].
+#[derive(Clone)]
+pub struct MentionWatch {
+    tx: UnboundedSender<MentionCandidates>,
+}
+
+/// The mentions of a loaded workspace, kept and dropped with it.
+pub struct MentionPlugin {
+    // the user each block mentions as of the last notification
+    mentioned: Arc<StdMutex<HashMap<String, String>>>,
+    _sub: ChangesSubscription,
+}
+
+impl PluginImpl for MentionPlugin {}
+
+impl PluginRegister for MentionWatch {
+    type Plugin = MentionPlugin;
+
+    fn setup(self, ws: &mut Workspace) -> Result<MentionPlugin, Box<dyn std::error::Error>> {
+        // mentions made before the workspace was loaded were notified already
+        let mentioned = ws.with_trx(|t| {
+            ws.blocks(&t.trx, |blocks| {
+                blocks
+                    .filter_map(|block| {
+                        let user = mentioned_user(block.get(&t.trx, MENTION_PROPERTY))?;
+                        Some((block.id(), user))
+                    })
+                    .collect::<HashMap<_, _>>()
+            })
+        });
+        let mentioned = Arc::new(StdMutex::new(mentioned));
+
+        let workspace_id = ws.id();
+        let tx = self.tx;
+        let sub = ws.observe_changes({
+            let mentioned = mentioned.clone();
+            move |trx, changes| {
+                {
+                    let mut mentioned = mentioned.lock().unwrap();
+                    for id in &changes.removed {
+                        mentioned.remove(id);
+                    }
+                }
+                let blocks = mention_candidates(changes);
+                if !blocks.is_empty() {
+                    // the receiver lives as long as the notifications
+                    let _ = tx.send(MentionCandidates {
+                        workspace_id: workspace_id.clone(),
+                        blocks,
+                        actor: trx
+                            .origin()
+                            .and_then(|origin| std::str::from_utf8(origin.as_ref()).ok())
+                            .map(str::to_owned),
+                    });
+                }
+            }
+        });
+
+        Ok(MentionPlugin {
+            mentioned,
+            _sub: sub,
+        })
+    }
+}
+
+impl MentionPlugin {
+    // the mentions among `current`, the mentions of `blocks` now, that changed since they
+    // were last seen, blocks no longer mentioning anyone are forgotten
+    fn changed(&self, blocks: &[String], current: &[(String, String)]) -> Vec<(String, String)> {
+        let current = current.iter().cloned().collect::<HashMap<_, _>>();
+        let mut mentioned = self.mentioned.lock().unwrap();
+        let mut changed = vec![];
+        for id in blocks {
+            match current.get(id) {
+                Some(user) => {
+                    if mentioned.insert(id.clone(), user.clone()).as_ref() != Some(user) {
+                        changed.push((id.clone(), user.clone()));
+                    }
+                }
+                None => {
+                    mentioned.remove(id);
+                }
+            }
+        }
+        changed
+    }
+}
+
+/// Notifications of the users, stored in the database and optionally pushed.
+pub struct Notifications {
+    pusher: Option<Box<dyn NotificationPusher>>,
+    tx: UnboundedSender<MentionCandidates>,
+    rx: Mutex<UnboundedReceiver<MentionCandidates>>,
+}
+
+impl Notifications {
+    pub fn new(pusher: Option<Box<dyn NotificationPusher>>) -> Self {
+        let (tx, rx) = unbounded_channel();
+        Self {
+            pusher,
+            tx,
+            rx: Mutex::new(rx),
+        }
+    }
+
+    /// Pushed through FCM with the server key `FCM_SERVER_KEY` if it's set,
+    /// `FCM_ENDPOINT` replaces the endpoint of the API.
+    pub fn from_env(client: Client) -> Self {
+        let pusher = dotenvy::var("FCM_SERVER_KEY").ok().map(|server_key| {
+            let endpoint = dotenvy::var("FCM_ENDPOINT").unwrap_or_else(|_| FCM_ENDPOINT.into());
+            Box::new(FcmPusher::new(client, endpoint, server_key)) as Box<dyn NotificationPusher>
+        });
+        Self::new(pusher)
+    }
+
+    /// Store `event` for each recipient and push it, errors are logged.
+    pub async fn notify(
+        &self,
+        db: &CloudDatabase,
+        event: NotificationEvent,
+        recipients: &[String],
+    ) {
+        let notifications = match db.create_notifications(&event, recipients).await {
+            Ok(notifications) => notifications,
+            Err(e) => {
+                error!("Failed to create notifications: {:?}", e);
+                return;
+            }
+        };
+        if let Some(pusher) = &self.pusher {
+            for notification in notifications {
+                if let Err(e) = pusher.push(&notification).await {
+                    error!("Failed to push notification {}: {:?}", notification.id, e);
+                }
+            }
+        }
+    }
+
+    /// Plugin looking for new mentions in the blocks changed in a workspace as long as
+    /// it's loaded, see [jwst::WorkspacePlugins::register].
+    pub fn mention_watch(&self) -> MentionWatch {
+        MentionWatch {
+            tx: self.tx.clone(),
+        }
+    }
+
+    async fn next(&self) -> Option<MentionCandidates> {
+        self.rx.lock().await.recv().await
+    }
+}
+
+// blocks added, or whose mention property changed
+fn mention_candidates(changes: &WorkspaceChanges) -> Vec<String> {
+    let key = format!("prop:{MENTION_PROPERTY}");
+    changes
+        .added
+        .iter()
+        .chain(
+            changes
+                .updated
+                .iter()
+                .filter(|(_, block)| block.keys.contains(&key))
+                .map(|(id, _)| id),
+        )
+        .filter(|id| !changes.removed.contains(*id))
+        .cloned()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+// the user mentioned by the value of the mention property of a block
+fn mentioned_user(mention: Option<Any>) -> Option<String> {
+    match mention {
+        Some(Any::String(user)) if !user.is_empty() => Some(user.into()),
+        _ => None,
+    }
+}
+
+// the blocks among `blocks` that mention a user, with the user they mention
+fn mentions(workspace: &Workspace, blocks: &[String]) -> Vec<(String, String)> {
+    workspace.with_trx(|t| {
+        blocks
+            .iter()
+            .filter_map(|id| {
+                let block = workspace.get(&t.trx, id)?;
+                let user = mentioned_user(block.get(&t.trx, MENTION_PROPERTY))?;
+                Some((id.clone(), user))
+            })
+            .collect()
+    })
+}
+
+// the mentions among `blocks` that are new since they were last seen
+fn new_mentions(workspace: &Workspace, blocks: &[String]) -> Vec<(String, String)> {
+    let current = mentions(workspace, blocks);
+    workspace
+        .with_plugin::<MentionPlugin, _>(|plugin| plugin.changed(blocks, &current))
+        .unwrap_or(current)
+}
+
+async fn notify_mentions(
+    db: &CloudDatabase,
+    storage: &JwstStorage,
+    notifications: &Notifications,
+    MentionCandidates {
+        workspace_id,
+        blocks,
+        actor,
+    }: MentionCandidates,
+) {
+    let mentions = match storage.get_workspace(&workspace_id).await {
+        Ok(workspace) => new_mentions(&workspace, &blocks),
+        Err(e) => {
+            error!("Failed to get workspace {}: {:?}", workspace_id, e);
+            return;
+        }
+    };
+    for (block_id, user_id) in mentions {
+        // users don't need to be told about mentioning themselves
+        if actor.as_ref() == Some(&user_id) {
+            continue;
+        }
+        // users who can't read the workspace don't learn about it
+        match db
+            .can_read_workspace(user_id.clone(), workspace_id.clone())
+            .await
+        {
+            Ok(true) => (),
+            Ok(false) => continue,
+            Err(e) => {
+                error!("Failed to check read permission: {:?}", e);
+                continue;
+            }
+        }
+        let event = NotificationEvent {
+            kind: NotificationKind::Mention,
+            workspace_id: workspace_id.clone(),
+            actor: actor.clone(),
+            block_id: Some(block_id),
+        };
+        notifications.notify(db, event, &[user_id]).await;
+    }
+}
+
+/// Start turning the mentions found in the watched workspaces into notifications.
+pub fn start_notification_worker(ctx: Arc<Context>) {
+    tokio::spawn(async move {
+        while let Some(candidates) = ctx.notifications.next().await {
+            notify_mentions(&ctx.db, &ctx.storage, &ctx.notifications, candidates).await;
+        }
+    });
+}
+
+/// Notify the owner of a workspace that `user_id` accepted an invitation to it.
+pub(super) async fn notify_invitation_accepted(
+    ctx: &Context,
+    workspace_id: String,
+    user_id: String,
+) {
+    let owner = match ctx.db.get_workspace_owner(workspace_id.clone()).await {
+        Ok(Some(owner)) => owner,
+        Ok(None) => return,
+        Err(e) => {
+            error!("Failed to get workspace owner: {:?}", e);
+            return;
+        }
+    };
+    let event = NotificationEvent {
+        kind: NotificationKind::InvitationAccepted,
+        workspace_id,
+        actor: Some(user_id),
+        block_id: None,
+    };
+    ctx.notifications.notify(&ctx.db, event, &[owner.id]).await;
+}
+
+/// Notifications of the user, newest first, `limit` at most per page.
+pub async fn get_notifications(
+    Extension(ctx): Extension<Arc<Context>>,
+    Extension(claims): Extension<Arc<Claims>>,
+    Query(page): Query<NotificationPage>,
+) -> Response {
+    let limit = page
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    match ctx
+        .db
+        .get_notifications(claims.user.id.clone(), page.offset, limit)
+        .await
+    {
+        Ok(notifications) => Json(notifications).into_response(),
+        Err(e) => {
+            error!("Failed to get notifications: {:?}", e);
+            ErrorStatus::InternalServerError.into_response()
+        }
+    }
+}
+
+/// Number of unread notifications of the user, e.g. for a badge.
+pub async fn get_unread_count(
+    Extension(ctx): Extension<Arc<Context>>,
+    Extension(claims): Extension<Arc<Claims>>,
+) -> Response {
+    match ctx
+        .db
+        .count_unread_notifications(claims.user.id.clone())
+        .await
+    {
+        Ok(unread) => Json(UnreadCount { unread }).into_response(),
+        Err(e) => {
+            error!("Failed to count notifications: {:?}", e);
+            ErrorStatus::InternalServerError.into_response()
+        }
+    }
+}
+
+/// Mark notifications of the user as read, returns how many are still unread.
+pub async fn mark_notifications_read(
+    Extension(ctx): Extension<Arc<Context>>,
+    Extension(claims): Extension<Arc<Claims>>,
+    Json(payload): Json<MarkRead>,
+) -> Response {
+    let user_id = claims.user.id.clone();
+    if let Err(e) = ctx
+        .db
+        .mark_notifications_read(user_id.clone(), payload.ids)
+        .await
+    {
+        error!("Failed to mark notifications as read: {:?}", e);
+        return ErrorStatus::InternalServerError.into_response();
+    }
+
+    match ctx.db.count_unread_notifications(user_id).await {
+        Ok(unread) => Json(UnreadCount { unread }).into_response(),
+        Err(e) => {
+            error!("Failed to count notifications: {:?}", e);
+            ErrorStatus::InternalServerError.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cloud_database::{CreateUser, PermissionType};
+    use jwst::WorkspacePlugins;
+    use std::sync::Mutex as StdMutex;
+
+    #[derive(Clone, Default)]
+    struct RecordingPusher(Arc<StdMutex<Vec<Notification>>>);
+
+    #[async_trait]
+    impl NotificationPusher for RecordingPusher {
+        async fn push(&self, notification: &Notification) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(notification.clone());
+            Ok(())
+        }
+    }
+
+    async fn create_user(db: &CloudDatabase, name: &str) -> String {
+        db.create_user(CreateUser {
+            avatar_url: None,
+            email: format!("{name}@xxx.xx"),
+            name: name.to_string(),
+            password: "xxx".to_string(),
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .0
+        .id
+    }
+
+    #[test]
+    fn extract_mentions() {
+        let mut workspace = Workspace::new("test");
+        let candidates = Arc::new(StdMutex::new(vec![]));
+        let _sub = workspace.observe_changes({
+            let candidates = candidates.clone();
+            move |_, changes| candidates.lock().unwrap().push(mention_candidates(changes))
+        });
+
+        workspace.with_trx(|mut t| {
+            let block = t.create("mention", "affine:mention");
+            block.set(&mut t.trx, MENTION_PROPERTY, "user");
+            t.create("text", "affine:text");
+        });
+        workspace.with_trx(|mut t| {
+            let block = workspace.get(&t.trx, "mention").unwrap();
+            block.set(&mut t.trx, "title", "unrelated");
+        });
+        workspace.with_trx(|mut t| {
+            let block = workspace.get(&t.trx, "text").unwrap();
+            block.set(&mut t.trx, MENTION_PROPERTY, "other");
+        });
+        assert_eq!(
+            candidates.lock().unwrap().clone(),
+            vec![
+                vec!["mention".to_owned(), "text".to_owned()],
+                vec![],
+                vec!["text".to_owned()],
+            ]
+        );
+
+        workspace.with_trx(|mut t| {
+            let block = t.create("empty", "affine:mention");
+            block.set(&mut t.trx, MENTION_PROPERTY, "");
+        });
+        assert_eq!(
+            mentions(
+                &workspace,
+                &["mention", "text", "empty", "missing"].map(String::from)
+            ),
+            vec![
+                ("mention".to_owned(), "user".to_owned()),
+                ("text".to_owned(), "other".to_owned()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn notify_mentioned_members() {
+        let db = CloudDatabase::init_pool("sqlite::memory:").await.unwrap();
+        let storage = JwstStorage::new("sqlite::memory:").await.unwrap();
+        let owner = create_user(&db, "owner").await;
+        let member = create_user(&db, "member").await;
+        let outsider = create_user(&db, "outsider").await;
+        let workspace = db.create_normal_workspace(owner.clone()).await.unwrap();
+        let (permission, _) = db
+            .create_permission("member@xxx.xx", workspace.id.clone(), PermissionType::Write)
+            .await
+            .unwrap()
+            .unwrap();
+        db.accept_permission(permission).await.unwrap().unwrap();
+
+        let pusher = RecordingPusher::default();
+        let notifications = Notifications::new(Some(Box::new(pusher.clone())));
+        storage.docs().set_workspace_plugins(
+            WorkspacePlugins::default().register(notifications.mention_watch()),
+        );
+        let ws = storage.get_workspace(&workspace.id).await.unwrap();
+        ws.with_trx(|mut t| {
+            for (id, user) in [("a", &member), ("b", &outsider), ("c", &owner)] {
+                let block = t.create(id, "affine:mention");
+                block.set(&mut t.trx, MENTION_PROPERTY, user.clone());
+            }
+        });
+
+        let candidates = notifications.next().await.unwrap();
+        assert_eq!(candidates.workspace_id, workspace.id);
+        notify_mentions(&db, &storage, &notifications, candidates).await;
+
+        // one notification for each member mentioned, outsiders are not told
+        let member_notifications = db.get_notifications(member.clone(), 0, 10).await.unwrap();
+        assert_eq!(member_notifications.len(), 1);
+        assert_eq!(
+            member_notifications[0].event,
+            NotificationEvent {
+                kind: NotificationKind::Mention,
+                workspace_id: workspace.id.clone(),
+                actor: None,
+                block_id: Some("a".into()),
+            }
+        );
+        assert_eq!(
+            db.get_notifications(owner.clone(), 0, 10)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(db
+            .get_notifications(outsider, 0, 10)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let mut pushed = pusher
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|notification| notification.user_id.clone())
+            .collect::<Vec<_>>();
+        pushed.sort();
+        let mut expected = vec![member.clone(), owner.clone()];
+        expected.sort();
+        assert_eq!(pushed, expected);
+
+        // setting the same mention again doesn't notify, a new one names who made it
+        ws.with_trx_origin(owner.as_str(), |mut t| {
+            let block = ws.get(&t.trx, "a").unwrap();
+            block.set(&mut t.trx, MENTION_PROPERTY, member.clone());
+            let block = t.create("d", "affine:mention");
+            block.set(&mut t.trx, MENTION_PROPERTY, member.clone());
+        });
+        let candidates = notifications.next().await.unwrap();
+        assert_eq!(candidates.actor.as_ref(), Some(&owner));
+        notify_mentions(&db, &storage, &notifications, candidates).await;
+        let member_notifications = db.get_notifications(member.clone(), 0, 10).await.unwrap();
+        assert_eq!(member_notifications.len(), 2);
+        assert_eq!(
+            member_notifications[0].event,
+            NotificationEvent {
+                kind: NotificationKind::Mention,
+                workspace_id: workspace.id.clone(),
+                actor: Some(owner.clone()),
+                block_id: Some("d".into()),
+            }
+        );
+
+        // nor does mentioning oneself
+        ws.with_trx_origin(member.as_str(), |mut t| {
+            let block = t.create("e", "affine:mention");
+            block.set(&mut t.trx, MENTION_PROPERTY, member.clone());
+        });
+        let candidates = notifications.next().await.unwrap();
+        notify_mentions(&db, &storage, &notifications, candidates).await;
+        assert_eq!(
+            db.get_notifications(member.clone(), 0, 10)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // set up again like for a reloaded workspace, the previous subscription is dropped
+        // and the mentions notified already are known
+        let mut ws = ws;
+        ws.register_plugin(notifications.mention_watch()).unwrap();
+        ws.with_trx(|mut t| {
+            let block = ws.get(&t.trx, "a").unwrap();
+            block.set(&mut t.trx, MENTION_PROPERTY, member.clone());
+            t.create("f", "affine:text");
+        });
+        let candidates = notifications.next().await.unwrap();
+        assert_eq!(candidates.blocks, vec!["a", "f"]);
+        notify_mentions(&db, &storage, &notifications, candidates).await;
+        assert_eq!(db.get_notifications(member, 0, 10).await.unwrap().len(), 2);
+        assert!(notifications.rx.lock().await.try_recv().is_err());
+    }
+}
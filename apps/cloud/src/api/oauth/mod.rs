@@ -0,0 +1,210 @@
+mod provider;
+
+pub use provider::{AuthError, AuthProvider, OidcConfig, OidcProvider, PendingLogin};
+
+use super::make_user_token;
+use crate::{context::Context, error_status::ErrorStatus};
+use axum::{
+    extract::{Path, Query},
+    response::{IntoResponse, Redirect, Response},
+    Extension, Json,
+};
+use cloud_database::OAuthLogin;
+use dashmap::DashMap;
+use jwst_logger::{error, info, warn};
+use reqwest::Client;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+// how long a started sign in can wait for its callback
+const PENDING_LOGIN_TTL: Duration = Duration::from_secs(600);
+// most sign ins waiting for their callback at once
+const MAX_PENDING_LOGINS: usize = 10000;
+
+/// Configured external authentication providers, keyed by their lowercase name in the url.
+pub struct AuthProviders {
+    providers: HashMap<String, Arc<dyn AuthProvider>>,
+    // started sign ins by their state, with the time they were started
+    pending: DashMap<String, (Instant, PendingLogin)>,
+}
+
+impl AuthProviders {
+    /// Providers are listed in `OIDC_PROVIDERS` (comma separated), each of them
+    /// is configured by its own `OIDC_{NAME}_*` variables, see [OidcConfig::from_env].
+    pub fn from_env(client: Client) -> Self {
+        let providers = dotenvy::var("OIDC_PROVIDERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|name| name.trim().to_lowercase())
+            .filter(|name| !name.is_empty())
+            .filter_map(|name| match OidcConfig::from_env(&name) {
+                Some(config) => {
+                    info!("enable oidc provider {}: {}", name, config.issuer);
+                    let provider: Arc<dyn AuthProvider> =
+                        Arc::new(OidcProvider::new(&name, config, client.clone()));
+                    Some((name, provider))
+                }
+                None => {
+                    error!("oidc provider {} is not fully configured, skipped", name);
+                    None
+                }
+            })
+            .collect();
+
+        Self::new(providers)
+    }
+
+    pub fn new(providers: HashMap<String, Arc<dyn AuthProvider>>) -> Self {
+        Self {
+            providers,
+            pending: DashMap::new(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn AuthProvider>> {
+        self.providers.get(name).cloned()
+    }
+
+    // `false` if too many sign ins are waiting for their callback
+    fn add_pending(&self, login: PendingLogin) -> bool {
+        if self.pending.len() >= MAX_PENDING_LOGINS {
+            self.pending
+                .retain(|_, (started, _)| started.elapsed() < PENDING_LOGIN_TTL);
+            if self.pending.len() >= MAX_PENDING_LOGINS {
+                return false;
+            }
+        }
+        self.pending
+            .insert(login.state.clone(), (Instant::now(), login));
+        true
+    }
+
+    // removed in one step, so concurrent callbacks can't both redeem the state
+    fn take_pending(&self, state: &str) -> Option<PendingLogin> {
+        let (_, (started, login)) = self.pending.remove(state)?;
+        (started.elapsed() < PENDING_LOGIN_TTL).then_some(login)
+    }
+}
+
+impl Context {
+    fn oauth_redirect_uri(&self, provider: &str) -> String {
+        format!(
+            "{}/api/auth/{provider}/callback",
+            self.site_url.trim_end_matches('/')
+        )
+    }
+}
+
+pub async fn oauth_login(
+    Extension(ctx): Extension<Arc<Context>>,
+    Path(provider): Path<String>,
+) -> Response {
+    // stored with the identities, so a provider is linked under one name
+    let provider = provider.to_lowercase();
+    let Some(auth) = ctx.auth.get(&provider) else {
+        return ErrorStatus::NotFound.into_response();
+    };
+
+    let login = PendingLogin::new(&provider);
+    match auth
+        .authorize_url(&ctx.oauth_redirect_uri(&provider), &login)
+        .await
+    {
+        Ok(url) => {
+            if !ctx.auth.add_pending(login) {
+                warn!("too many {} logins in progress", provider);
+                return ErrorStatus::Overloaded.into_response();
+            }
+            Redirect::to(&url).into_response()
+        }
+        Err(e) => {
+            error!("Failed to start {} login: {}", provider, e);
+            ErrorStatus::InternalServerError.into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CallbackQuery {
+    code: Option<String>,
+    state: String,
+    error: Option<String>,
+}
+
+pub async fn oauth_callback(
+    Extension(ctx): Extension<Arc<Context>>,
+    Path(provider): Path<String>,
+    Query(query): Query<CallbackQuery>,
+) -> Response {
+    let provider = provider.to_lowercase();
+    let Some(auth) = ctx.auth.get(&provider) else {
+        return ErrorStatus::NotFound.into_response();
+    };
+
+    // the state can only be used once, whatever the result is
+    let login = match ctx.auth.take_pending(&query.state) {
+        Some(login) if login.provider == provider => login,
+        _ => return ErrorStatus::BadRequest.into_response(),
+    };
+
+    let code = match (query.code, query.error) {
+        (Some(code), None) => code,
+        (_, error) => {
+            info!("{} login was not authorized: {:?}", provider, error);
+            return ErrorStatus::Unauthorized.into_response();
+        }
+    };
+
+    let identity = match auth
+        .exchange_code(&code, &ctx.oauth_redirect_uri(&provider), &login)
+        .await
+    {
+        Ok(identity) => identity,
+        Err(e @ (AuthError::InvalidToken(_) | AuthError::UnverifiedEmail)) => {
+            warn!("Rejected {} login: {}", provider, e);
+            return ErrorStatus::Unauthorized.into_response();
+        }
+        Err(e) => {
+            error!("Failed to finish {} login: {}", provider, e);
+            return ErrorStatus::InternalServerError.into_response();
+        }
+    };
+
+    match ctx.db.oauth_user_login(&identity).await {
+        Ok(OAuthLogin::User(user)) => {
+            Json(make_user_token(&ctx, user.into(), None)).into_response()
+        }
+        Ok(OAuthLogin::Conflict { email }) => {
+            info!(
+                "{} login conflicts with registered user {}",
+                provider, email
+            );
+            ErrorStatus::ConflictAccount.into_response()
+        }
+        Err(e) => {
+            error!("Failed to login {} user: {:?}", provider, e);
+            ErrorStatus::InternalServerError.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn redeem_pending_once() {
+        let auth = AuthProviders::new(HashMap::new());
+        let login = PendingLogin::new("mock");
+        let state = login.state.clone();
+        assert!(auth.add_pending(login));
+
+        assert_eq!(auth.take_pending(&state).unwrap().provider, "mock");
+        assert!(auth.take_pending(&state).is_none());
+        assert!(auth.take_pending("unknown").is_none());
+    }
+}
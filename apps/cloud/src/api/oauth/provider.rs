@@ -0,0 +1,624 @@
+use crate::utils::{CacheControl, URL_SAFE_ENGINE};
+use async_trait::async_trait;
+use base64::Engine;
+use chrono::{Duration, NaiveDateTime, Utc};
+use cloud_database::OAuthIdentity;
+use http::header::CACHE_CONTROL;
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use jwst_logger::{info, warn};
+use rand::{thread_rng, Rng};
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, fmt};
+use tokio::sync::RwLock;
+
+const DEFAULT_SCOPES: [&str; 3] = ["openid", "email", "profile"];
+// seconds of clock skew tolerated when validating `exp`, `nbf` and `iat`
+const DEFAULT_LEEWAY: u64 = 60;
+// used when the JWKS response has no usable `Cache-Control`
+const DEFAULT_JWKS_TTL: i64 = 3600;
+// an unknown `kid` triggers a JWKS refresh at most this often
+const MIN_JWKS_REFRESH: i64 = 60;
+
+#[derive(Debug)]
+pub enum AuthError {
+    Discovery(String),
+    Exchange(String),
+    InvalidToken(String),
+    UnverifiedEmail,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Discovery(e) => write!(f, "discovery failed: {e}"),
+            AuthError::Exchange(e) => write!(f, "code exchange failed: {e}"),
+            AuthError::InvalidToken(e) => write!(f, "invalid id token: {e}"),
+            AuthError::UnverifiedEmail => write!(f, "email is not verified"),
+        }
+    }
+}
+
+/// State of a sign in that was started but not yet called back.
+#[derive(Debug, Clone)]
+pub struct PendingLogin {
+    pub provider: String,
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+fn random_token() -> String {
+    let data: [u8; 32] = thread_rng().gen();
+    URL_SAFE_ENGINE.encode(data)
+}
+
+impl PendingLogin {
+    pub fn new(provider: &str) -> Self {
+        Self {
+            provider: provider.to_owned(),
+            state: random_token(),
+            nonce: random_token(),
+            code_verifier: random_token(),
+        }
+    }
+
+    /// PKCE `S256` challenge of the code verifier
+    pub fn code_challenge(&self) -> String {
+        URL_SAFE_ENGINE.encode(Sha256::digest(self.code_verifier.as_bytes()))
+    }
+}
+
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+    /// Url the user agent is redirected to for signing in.
+    async fn authorize_url(
+        &self,
+        redirect_uri: &str,
+        login: &PendingLogin,
+    ) -> Result<String, AuthError>;
+
+    /// Exchange the authorization code of the callback for a verified identity.
+    async fn exchange_code(
+        &self,
+        code: &str,
+        redirect_uri: &str,
+        login: &PendingLogin,
+    ) -> Result<OAuthIdentity, AuthError>;
+}
+
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub scopes: Vec<String>,
+    pub leeway: u64,
+}
+
+impl OidcConfig {
+    /// Read `OIDC_{NAME}_ISSUER`, `OIDC_{NAME}_CLIENT_ID`, `OIDC_{NAME}_CLIENT_SECRET`
+    /// and the optional `OIDC_{NAME}_SCOPES` and `OIDC_{NAME}_CLOCK_SKEW`.
+    pub fn from_env(name: &str) -> Option<Self> {
+        let prefix = format!("OIDC_{}", name.to_uppercase());
+        let var = |key: &str| dotenvy::var(format!("{prefix}_{key}")).ok();
+
+        Some(Self {
+            issuer: var("ISSUER")?.trim_end_matches('/').to_owned(),
+            client_id: var("CLIENT_ID")?,
+            client_secret: var("CLIENT_SECRET")?,
+            scopes: var("SCOPES")
+                .map(|scopes| {
+                    scopes
+                        .split(|c| c == ' ' || c == ',')
+                        .filter(|s| !s.is_empty())
+                        .map(ToOwned::to_owned)
+                        .collect()
+                })
+                .unwrap_or_else(|| DEFAULT_SCOPES.iter().map(|s| s.to_string()).collect()),
+            leeway: var("CLOCK_SKEW")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(DEFAULT_LEEWAY),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+struct JwksCache {
+    fetched: NaiveDateTime,
+    expires: NaiveDateTime,
+    keys: HashMap<String, DecodingKey>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    iat: Option<i64>,
+    nonce: Option<String>,
+    email: Option<String>,
+    // some providers send it as a string
+    email_verified: Option<Value>,
+    name: Option<String>,
+    picture: Option<String>,
+}
+
+/// A generic OpenID Connect provider using the authorization code flow with PKCE.
+pub struct OidcProvider {
+    name: String,
+    config: OidcConfig,
+    client: Client,
+    metadata: RwLock<Option<ProviderMetadata>>,
+    jwks: RwLock<JwksCache>,
+}
+
+impl OidcProvider {
+    pub fn new(name: &str, config: OidcConfig, client: Client) -> Self {
+        Self {
+            name: name.to_owned(),
+            config,
+            client,
+            metadata: RwLock::new(None),
+            jwks: RwLock::new(JwksCache {
+                fetched: NaiveDateTime::MIN,
+                expires: NaiveDateTime::MIN,
+                keys: HashMap::new(),
+            }),
+        }
+    }
+
+    pub async fn metadata(&self) -> Result<ProviderMetadata, AuthError> {
+        if let Some(metadata) = self.metadata.read().await.as_ref() {
+            return Ok(metadata.clone());
+        }
+
+        let endpoint = format!("{}/.well-known/openid-configuration", self.config.issuer);
+        let metadata = self
+            .client
+            .get(endpoint)
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map_err(|e| AuthError::Discovery(e.to_string()))?
+            .json::<ProviderMetadata>()
+            .await
+            .map_err(|e| AuthError::Discovery(e.to_string()))?;
+
+        if metadata.issuer.trim_end_matches('/') != self.config.issuer {
+            return Err(AuthError::Discovery(format!(
+                "issuer mismatch: {}",
+                metadata.issuer
+            )));
+        }
+
+        *self.metadata.write().await = Some(metadata.clone());
+
+        Ok(metadata)
+    }
+
+    async fn refresh_jwks(&self) -> Result<(), AuthError> {
+        let metadata = self.metadata().await?;
+        let resp = self
+            .client
+            .get(metadata.jwks_uri)
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map_err(|e| AuthError::Discovery(e.to_string()))?;
+
+        let now = Utc::now().naive_utc();
+        let ttl = resp
+            .headers()
+            .get(CACHE_CONTROL)
+            .and_then(|h| h.to_str().ok())
+            .and_then(CacheControl::parse)
+            .and_then(|cache| cache.max_age)
+            .unwrap_or_else(|| Duration::seconds(DEFAULT_JWKS_TTL));
+
+        let jwks = resp
+            .json::<JwkSet>()
+            .await
+            .map_err(|e| AuthError::Discovery(e.to_string()))?;
+
+        let keys = jwks
+            .keys
+            .iter()
+            .filter_map(|jwk| {
+                let kid = jwk.common.key_id.clone()?;
+                match DecodingKey::from_jwk(jwk) {
+                    Ok(key) => Some((kid, key)),
+                    Err(e) => {
+                        warn!("{} ignore unsupported jwk {}: {}", self.name, kid, e);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        let mut cache = self.jwks.write().await;
+        cache.fetched = now;
+        cache.expires = now + ttl;
+        cache.keys = keys;
+
+        Ok(())
+    }
+
+    async fn decoding_key(&self, kid: &str) -> Result<DecodingKey, AuthError> {
+        let now = Utc::now().naive_utc();
+        {
+            let cache = self.jwks.read().await;
+            if cache.expires > now {
+                if let Some(key) = cache.keys.get(kid) {
+                    return Ok(key.clone());
+                }
+                // the provider may have rotated its keys, but don't let
+                // forged tokens make us hammer the jwks endpoint
+                if cache.fetched + Duration::seconds(MIN_JWKS_REFRESH) > now {
+                    return Err(AuthError::InvalidToken(format!("unknown kid {kid}")));
+                }
+            }
+        }
+
+        self.refresh_jwks().await?;
+
+        self.jwks
+            .read()
+            .await
+            .keys
+            .get(kid)
+            .cloned()
+            .ok_or_else(|| AuthError::InvalidToken(format!("unknown kid {kid}")))
+    }
+
+    pub async fn verify_id_token(
+        &self,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<OAuthIdentity, AuthError> {
+        let header = decode_header(id_token).map_err(|e| AuthError::InvalidToken(e.to_string()))?;
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(AuthError::InvalidToken(format!(
+                "unsupported algorithm {:?}",
+                header.alg
+            )));
+        }
+        let kid = header
+            .kid
+            .ok_or_else(|| AuthError::InvalidToken("missing kid".into()))?;
+        let key = self.decoding_key(&kid).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = self.config.leeway;
+        validation.validate_nbf = true;
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_issuer(&[&self.config.issuer, &format!("{}/", self.config.issuer)]);
+
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|e| AuthError::InvalidToken(e.to_string()))?
+            .claims;
+
+        if claims.iat.unwrap_or_default() > Utc::now().timestamp() + self.config.leeway as i64 {
+            return Err(AuthError::InvalidToken("issued in the future".into()));
+        }
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(AuthError::InvalidToken("nonce mismatch".into()));
+        }
+
+        let verified = match claims.email_verified {
+            Some(Value::Bool(verified)) => verified,
+            Some(Value::String(verified)) => verified == "true",
+            _ => false,
+        };
+        let email = match claims.email {
+            Some(email) if verified => email,
+            _ => return Err(AuthError::UnverifiedEmail),
+        };
+
+        Ok(OAuthIdentity {
+            provider: self.name.clone(),
+            subject: claims.sub,
+            name: claims.name.unwrap_or_else(|| email.clone()),
+            email,
+            picture: claims.picture,
+        })
+    }
+}
+
+#[async_trait]
+impl AuthProvider for OidcProvider {
+    async fn authorize_url(
+        &self,
+        redirect_uri: &str,
+        login: &PendingLogin,
+    ) -> Result<String, AuthError> {
+        let metadata = self.metadata().await?;
+
+        Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", self.config.client_id.as_str()),
+                ("redirect_uri", redirect_uri),
+                ("scope", self.config.scopes.join(" ").as_str()),
+                ("state", login.state.as_str()),
+                ("nonce", login.nonce.as_str()),
+                ("code_challenge", login.code_challenge().as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map(String::from)
+        .map_err(|e| AuthError::Discovery(e.to_string()))
+    }
+
+    async fn exchange_code(
+        &self,
+        code: &str,
+        redirect_uri: &str,
+        login: &PendingLogin,
+    ) -> Result<OAuthIdentity, AuthError> {
+        let metadata = self.metadata().await?;
+
+        let token = self
+            .client
+            .post(metadata.token_endpoint)
+            .basic_auth(&self.config.client_id, Some(&self.config.client_secret))
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", redirect_uri),
+                ("client_id", self.config.client_id.as_str()),
+                ("code_verifier", login.code_verifier.as_str()),
+            ])
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map_err(|e| AuthError::Exchange(e.to_string()))?
+            .json::<TokenResponse>()
+            .await
+            .map_err(|e| AuthError::Exchange(e.to_string()))?;
+
+        let identity = self.verify_id_token(&token.id_token, &login.nonce).await?;
+        info!("{} verified user {}", self.name, identity.subject);
+
+        Ok(identity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, routing::get, routing::post, Form, Json, Router, Server};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+    use std::{net::TcpListener, sync::Arc};
+
+    const PRIVATE_KEY: &[u8] = include_bytes!("../../../fixtures/oidc/private.pem");
+    const JWKS: &str = include_str!("../../../fixtures/oidc/jwks.json");
+
+    struct MockOidc {
+        issuer: String,
+        id_token: std::sync::Mutex<String>,
+    }
+
+    async fn mock_server() -> Arc<MockOidc> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let mock = Arc::new(MockOidc {
+            issuer,
+            id_token: Default::default(),
+        });
+
+        let app = Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                get(|State(mock): State<Arc<MockOidc>>| async move {
+                    Json(json!({
+                        "issuer": mock.issuer,
+                        "authorization_endpoint": format!("{}/authorize", mock.issuer),
+                        "token_endpoint": format!("{}/token", mock.issuer),
+                        "jwks_uri": format!("{}/jwks", mock.issuer),
+                    }))
+                }),
+            )
+            .route(
+                "/jwks",
+                get(|| async { ([(CACHE_CONTROL, "max-age=3600")], JWKS) }),
+            )
+            .route(
+                "/token",
+                post(
+                    |State(mock): State<Arc<MockOidc>>,
+                     Form(form): Form<HashMap<String, String>>| async move {
+                        assert_eq!(form["grant_type"], "authorization_code");
+                        assert_eq!(form["code"], "code");
+                        assert!(form.contains_key("code_verifier"));
+                        Json(json!({
+                            "access_token": "access",
+                            "token_type": "Bearer",
+                            "id_token": mock.id_token.lock().unwrap().clone(),
+                        }))
+                    },
+                ),
+            )
+            .with_state(mock.clone());
+
+        tokio::spawn(
+            Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        mock
+    }
+
+    fn provider(mock: &MockOidc) -> OidcProvider {
+        OidcProvider::new(
+            "mock",
+            OidcConfig {
+                issuer: mock.issuer.clone(),
+                client_id: "client".into(),
+                client_secret: "secret".into(),
+                scopes: vec!["openid".into(), "email".into()],
+                leeway: DEFAULT_LEEWAY,
+            },
+            Client::new(),
+        )
+    }
+
+    fn sign(mock: &MockOidc, kid: &str, claims: Value) -> String {
+        let mut claims = claims;
+        let now = Utc::now().timestamp();
+        let defaults = json!({
+            "iss": mock.issuer,
+            "aud": "client",
+            "sub": "user",
+            "iat": now,
+            "exp": now + 300,
+            "nonce": "nonce",
+            "email": "user@example.com",
+            "email_verified": true,
+        });
+        for (key, value) in defaults.as_object().unwrap() {
+            claims
+                .as_object_mut()
+                .unwrap()
+                .entry(key)
+                .or_insert(value.clone());
+        }
+
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.into());
+        encode(
+            &header,
+            &claims,
+            &EncodingKey::from_rsa_pem(PRIVATE_KEY).unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn discovery() {
+        let mock = mock_server().await;
+        let provider = provider(&mock);
+
+        let metadata = provider.metadata().await.unwrap();
+        assert_eq!(metadata.token_endpoint, format!("{}/token", mock.issuer));
+
+        let login = PendingLogin::new("mock");
+        let url = provider
+            .authorize_url("http://localhost/callback", &login)
+            .await
+            .unwrap();
+        let url = Url::parse(&url).unwrap();
+        let params = url.query_pairs().into_owned().collect::<HashMap<_, _>>();
+
+        assert_eq!(url.path(), "/authorize");
+        assert_eq!(params["client_id"], "client");
+        assert_eq!(params["scope"], "openid email");
+        assert_eq!(params["state"], login.state);
+        assert_eq!(params["nonce"], login.nonce);
+        assert_eq!(params["code_challenge"], login.code_challenge());
+        assert_eq!(params["code_challenge_method"], "S256");
+
+        // issuer in the discovery document must match the configured one
+        let mut config = provider.config.clone();
+        config.issuer = format!("{}/other", mock.issuer);
+        let other = OidcProvider::new("other", config, Client::new());
+        assert!(other.metadata().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn token_verification() {
+        let mock = mock_server().await;
+        let provider = provider(&mock);
+
+        let identity = provider
+            .verify_id_token(&sign(&mock, "test", json!({ "name": "User" })), "nonce")
+            .await
+            .unwrap();
+        assert_eq!(identity.provider, "mock");
+        assert_eq!(identity.subject, "user");
+        assert_eq!(identity.email, "user@example.com");
+        assert_eq!(identity.name, "User");
+
+        for (kid, claims, nonce) in [
+            ("test", json!({ "aud": "other" }), "nonce"),
+            ("test", json!({ "iss": "http://evil" }), "nonce"),
+            ("test", json!({}), "other"),
+            ("test", json!({ "email_verified": false }), "nonce"),
+            ("unknown", json!({}), "nonce"),
+        ] {
+            assert!(provider
+                .verify_id_token(&sign(&mock, kid, claims), nonce)
+                .await
+                .is_err());
+        }
+
+        // a payload swapped under a valid signature is rejected
+        let token = sign(&mock, "test", json!({}));
+        let forged = sign(&mock, "test", json!({ "sub": "admin" }));
+        let parts = token.split('.').collect::<Vec<_>>();
+        let forged_payload = forged.split('.').nth(1).unwrap();
+        let tampered = [parts[0], forged_payload, parts[2]].join(".");
+        assert!(provider.verify_id_token(&tampered, "nonce").await.is_err());
+
+        // the full code exchange ends with the same verification
+        *mock.id_token.lock().unwrap() = sign(&mock, "test", json!({}));
+        let login = PendingLogin {
+            nonce: "nonce".into(),
+            ..PendingLogin::new("mock")
+        };
+        let identity = provider
+            .exchange_code("code", "http://localhost/callback", &login)
+            .await
+            .unwrap();
+        assert_eq!(identity.email, "user@example.com");
+    }
+
+    #[tokio::test]
+    async fn clock_skew() {
+        let mock = mock_server().await;
+        let provider = provider(&mock);
+        let now = Utc::now().timestamp();
+
+        // within the allowed skew
+        for claims in [
+            json!({ "exp": now - 30 }),
+            json!({ "iat": now + 30 }),
+            json!({ "nbf": now + 30 }),
+        ] {
+            assert!(provider
+                .verify_id_token(&sign(&mock, "test", claims), "nonce")
+                .await
+                .is_ok());
+        }
+
+        // beyond the allowed skew
+        for claims in [
+            json!({ "exp": now - 300 }),
+            json!({ "iat": now + 300 }),
+            json!({ "nbf": now + 300 }),
+        ] {
+            assert!(provider
+                .verify_id_token(&sign(&mock, "test", claims), "nonce")
+                .await
+                .is_err());
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use super::notifications;
 use crate::{
     context::Context,
     error_status::ErrorStatus,
@@ -248,7 +249,13 @@ pub async fn accept_invitation(
     {
         Ok(Some(p)) => {
             if let Some(user_id) = p.user_id.clone() {
-                ctx.user_channel.update_user(user_id, ctx.clone());
+                ctx.user_channel.update_user(user_id.clone(), ctx.clone());
+
+                let ctx = ctx.clone();
+                let workspace_id = p.workspace_id.clone();
+                tokio::spawn(async move {
+                    notifications::notify_invitation_accepted(&ctx, workspace_id, user_id).await
+                });
             };
 
             Json(p).into_response()
@@ -1,13 +1,16 @@
 use super::*;
+use async_trait::async_trait;
 use axum::{
-    extract::{
-        ws::{close_code, CloseFrame, Message, WebSocketUpgrade},
-        Path,
-    },
+    body::Body,
+    extract::{ws::WebSocketUpgrade, Path},
+    http::Request,
+    middleware::{self, Next},
     response::Response,
 };
 use base64::Engine;
-use jwst_rpc::handle_socket;
+use cloud_database::PermissionType;
+use jwst::{WorkspacePermission, WorkspaceUser};
+use jwst_rpc::{handle_authenticated_socket, handle_user_socket, ContextImpl};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
@@ -16,67 +19,256 @@ pub struct WebSocketAuthentication {
     protocol: String,
 }
 
+/// Who opens a sync connection, resolved before the websocket upgrade
+/// and passed to the connection handler as a request extension.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncAccess {
+    pub user_id: String,
+    /// `None` if the workspace is only readable because it's public.
+    pub role: Option<PermissionType>,
+}
+
+impl SyncAccess {
+    /// The user the connection syncs the workspace for, with the permission of its role.
+    pub fn workspace_user(&self) -> WorkspaceUser {
+        WorkspaceUser::new(&self.user_id, granted_permission(self.role.as_ref()))
+    }
+}
+
+/// Permission a role grants in the doc of a workspace, public workspaces are read only
+/// to anyone but their members.
+pub(crate) fn granted_permission(role: Option<&PermissionType>) -> WorkspacePermission {
+    match role {
+        Some(PermissionType::Owner | PermissionType::Admin) => WorkspacePermission::Admin,
+        Some(PermissionType::Write) => WorkspacePermission::Write,
+        Some(PermissionType::Read) | None => WorkspacePermission::ReadOnly,
+    }
+}
+
+/// Checks done on the upgrade request of the sync endpoint, so rejected clients
+/// get a status code instead of a socket that is closed right after the upgrade.
+///
+/// Clients opening the socket without a token authenticate in-band instead, see
+/// [handle_authenticated_socket].
+#[async_trait]
+pub trait SyncAuthorizer {
+    async fn authorize_sync(
+        &self,
+        workspace_id: &str,
+        token: Option<&str>,
+    ) -> Result<SyncAccess, ErrorStatus>;
+}
+
+#[async_trait]
+impl SyncAuthorizer for Context {
+    async fn authorize_sync(
+        &self,
+        workspace_id: &str,
+        token: Option<&str>,
+    ) -> Result<SyncAccess, ErrorStatus> {
+        let user: Option<RefreshToken> = token
+            .and_then(|token| URL_SAFE_ENGINE.decode(token).ok())
+            .and_then(|byte| match self.decrypt_aes(byte) {
+                Ok(data) => data,
+                Err(_) => None,
+            })
+            .and_then(|data| serde_json::from_slice(&data).ok());
+        let Some(user) = user else {
+            return Err(ErrorStatus::Unauthorized);
+        };
+
+        let internal_error = |e| {
+            error!("failed to authorize sync of {}: {}", workspace_id, e);
+            ErrorStatus::InternalServerError
+        };
+        if !self
+            .db
+            .verify_refresh_token(&user)
+            .await
+            .map_err(internal_error)?
+        {
+            return Err(ErrorStatus::Unauthorized);
+        }
+        if !self
+            .db
+            .workspace_exists(workspace_id.into())
+            .await
+            .map_err(internal_error)?
+        {
+            return Err(ErrorStatus::NotFoundWorkspace(workspace_id.into()));
+        }
+        if !self
+            .db
+            .can_read_workspace(user.user_id.clone(), workspace_id.into())
+            .await
+            .map_err(internal_error)?
+        {
+            return Err(ErrorStatus::Forbidden);
+        }
+
+        let role = self
+            .db
+            .get_permission(user.user_id.clone(), workspace_id.into())
+            .await
+            .map_err(internal_error)?;
+
+        Ok(SyncAccess {
+            user_id: user.user_id,
+            role,
+        })
+    }
+}
+
 pub fn make_ws_route() -> Router {
-    Router::new().route("/:id", get(ws_handler))
+    ws_route::<Context>()
+}
+
+fn ws_route<C>() -> Router
+where
+    C: SyncAuthorizer + ContextImpl<'static> + Send + Sync + 'static,
+{
+    Router::new().route(
+        "/:id",
+        get(ws_handler::<C>).route_layer(middleware::from_fn(authorize::<C>)),
+    )
 }
 
 #[derive(Deserialize)]
 struct Param {
-    token: String,
+    token: Option<String>,
 }
 
-async fn ws_handler(
-    Extension(ctx): Extension<Arc<Context>>,
+async fn authorize<C>(
+    Extension(ctx): Extension<Arc<C>>,
     Path(workspace): Path<String>,
     Query(Param { token }): Query<Param>,
-    ws: WebSocketUpgrade,
-) -> Response {
-    let user: Option<RefreshToken> = URL_SAFE_ENGINE
-        .decode(token)
-        .ok()
-        .and_then(|byte| match ctx.decrypt_aes(byte) {
-            Ok(data) => data,
-            Err(_) => None,
-        })
-        .and_then(|data| serde_json::from_slice(&data).ok());
-
-    let user = if let Some(user) = user {
-        if let Ok(true) = ctx.db.verify_refresh_token(&user).await {
-            Some(user.user_id)
-        } else {
-            None
-        }
-    } else {
-        None
+    mut req: Request<Body>,
+    next: Next<Body>,
+) -> Response
+where
+    C: SyncAuthorizer + Send + Sync + 'static,
+{
+    // authenticated over the socket
+    let Some(token) = token else {
+        return next.run(req).await;
     };
+    match ctx.authorize_sync(&workspace, Some(&token)).await {
+        Ok(access) => {
+            req.extensions_mut().insert(access);
+            next.run(req).await
+        }
+        Err(status) => status.into_response(),
+    }
+}
 
+async fn ws_handler<C>(
+    Extension(ctx): Extension<Arc<C>>,
+    access: Option<Extension<SyncAccess>>,
+    Path(workspace): Path<String>,
+    ws: WebSocketUpgrade,
+) -> Response
+where
+    C: ContextImpl<'static> + Send + Sync + 'static,
+{
     ws.protocols(["AFFiNE"])
-        .on_upgrade(move |mut socket| async move {
-            let user_id = if let Some(user_id) = user {
-                if let Ok(true) = ctx
-                    .db
-                    .can_read_workspace(user_id.clone(), workspace.clone())
-                    .await
-                {
-                    Some(user_id)
-                } else {
-                    None
+        .on_upgrade(move |socket| async move {
+            match access {
+                // the role resolved on the upgrade request, permissions are only queried
+                // for the sockets authenticated in-band
+                Some(Extension(access)) => {
+                    handle_user_socket(socket, workspace, ctx.clone(), access.workspace_user())
+                        .await
                 }
-            } else {
-                None
-            };
-            let user_id = if let Some(user_id) = user_id {
-                user_id
-            } else {
-                let _ = socket
-                    .send(ws::Message::Close(Some(CloseFrame {
-                        code: close_code::POLICY,
-                        reason: "Unauthorized".into(),
-                    })))
-                    .await;
-                return;
-            };
-
-            handle_socket(socket, workspace, ctx.clone(), user_id).await
+                None => handle_authenticated_socket(socket, workspace, ctx.clone()).await,
+            }
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::header::{
+        CONNECTION, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_PROTOCOL, SEC_WEBSOCKET_VERSION, UPGRADE,
+    };
+    use jwst_rpc::Channels;
+    use jwst_storage::JwstStorage;
+    use std::net::SocketAddr;
+
+    struct TestContext {
+        storage: JwstStorage,
+        channel: Channels,
+    }
+
+    impl ContextImpl<'_> for TestContext {
+        fn get_storage(&self) -> &JwstStorage {
+            &self.storage
+        }
+
+        fn get_channel(&self) -> &Channels {
+            &self.channel
+        }
+    }
+
+    // tokens are user ids, `guest` has no access to any workspace
+    #[async_trait]
+    impl SyncAuthorizer for TestContext {
+        async fn authorize_sync(
+            &self,
+            workspace_id: &str,
+            token: Option<&str>,
+        ) -> Result<SyncAccess, ErrorStatus> {
+            match (workspace_id, token) {
+                (_, None) => Err(ErrorStatus::Unauthorized),
+                ("missing", _) => Err(ErrorStatus::NotFoundWorkspace(workspace_id.into())),
+                (_, Some("guest")) => Err(ErrorStatus::Forbidden),
+                (_, Some(user)) => Ok(SyncAccess {
+                    user_id: user.into(),
+                    role: Some(PermissionType::Write),
+                }),
+            }
+        }
+    }
+
+    async fn server() -> SocketAddr {
+        let context = Arc::new(TestContext {
+            storage: JwstStorage::new("sqlite::memory:").await.unwrap(),
+            channel: Default::default(),
+        });
+        let app = ws_route::<TestContext>().layer(Extension(context));
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    async fn upgrade(addr: SocketAddr, workspace: &str, token: Option<&str>) -> u16 {
+        let mut url = format!("http://{addr}/{workspace}");
+        if let Some(token) = token {
+            url.push_str(&format!("?token={token}"));
+        }
+        reqwest::Client::new()
+            .get(url)
+            .header(CONNECTION, "upgrade")
+            .header(UPGRADE, "websocket")
+            .header(SEC_WEBSOCKET_VERSION, "13")
+            .header(SEC_WEBSOCKET_KEY, "dGhlIHNhbXBsZSBub25jZQ==")
+            .header(SEC_WEBSOCKET_PROTOCOL, "AFFiNE")
+            .send()
+            .await
+            .unwrap()
+            .status()
+            .as_u16()
+    }
+
+    #[tokio::test]
+    async fn reject_before_upgrade() {
+        let addr = server().await;
+
+        // authenticated after the upgrade
+        assert_eq!(upgrade(addr, "test", None).await, 101);
+        assert_eq!(upgrade(addr, "missing", Some("user")).await, 404);
+        assert_eq!(upgrade(addr, "test", Some("guest")).await, 403);
+        assert_eq!(upgrade(addr, "test", Some("user")).await, 101);
+    }
+}
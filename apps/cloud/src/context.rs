@@ -1,29 +1,38 @@
+use crate::api::{granted_permission, SyncAuthorizer};
 use aes_gcm::aead::Aead;
 use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use async_trait::async_trait;
 use chrono::{NaiveDateTime, Utc};
 use cloud_components::MailContext;
-use cloud_database::CloudDatabase;
-use cloud_database::{Claims, GoogleClaims};
+use cloud_database::{Claims, CloudDatabase, GoogleClaims};
+use dashmap::DashMap;
+use hmac::{Hmac, Mac};
 use http::header::CACHE_CONTROL;
 use jsonwebtoken::{decode_header, DecodingKey, EncodingKey};
-use jwst::SearchResults;
+use jwst::{SearchResults, WorkspacePermission, WorkspacePlugins, WorkspaceUser};
 use jwst_logger::{error, info};
-use jwst_rpc::{Channels, ContextImpl};
+use jwst_rpc::{BandwidthUsage, Channels, ContextImpl, SyncSessions};
 use jwst_storage::JwstStorage;
 use rand::{thread_rng, Rng};
 use reqwest::Client;
 use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 use tokio::sync::{RwLock, RwLockReadGuard};
 use x509_parser::prelude::parse_x509_pem;
 
-use crate::api::UserChannel;
+use crate::api::{AuthProviders, ExportQueue, MigrationJob, Notifications, UserChannel};
+use crate::load::{LoadMonitor, LoadThresholds};
 use crate::utils::CacheControl;
 
+// how often the bandwidth counters are added to the totals in the storage
+const BANDWIDTH_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
 pub struct KeyContext {
     pub jwt_encode: EncodingKey,
     pub jwt_decode: DecodingKey,
     pub aes: Aes256Gcm,
+    /// Signs links that are used without a token, e.g. export downloads.
+    pub url: Hmac<Sha256>,
 }
 
 struct FirebaseContext {
@@ -36,12 +45,19 @@ pub struct Context {
     pub key: KeyContext,
     pub site_url: String,
     pub http_client: Client,
+    pub auth: AuthProviders,
     firebase: RwLock<FirebaseContext>,
     pub mail: MailContext,
     pub db: CloudDatabase,
     pub storage: JwstStorage,
     pub channel: Channels,
     pub user_channel: UserChannel,
+    pub sessions: SyncSessions,
+    pub bandwidth: BandwidthUsage,
+    pub migrations: DashMap<String, Arc<MigrationJob>>,
+    pub exports: ExportQueue,
+    pub notifications: Notifications,
+    pub load: Arc<LoadMonitor>,
 }
 
 impl Context {
@@ -54,6 +70,9 @@ impl Context {
             let hash = hasher.finalize();
 
             let aes = Aes256Gcm::new_from_slice(&hash[..]).unwrap();
+            let url =
+                <Hmac<Sha256> as Mac>::new_from_slice(&Sha256::digest(format!("url:{key_env}")))
+                    .expect("HMAC takes keys of any size");
 
             let jwt_encode = EncodingKey::from_secret(key_env.as_bytes());
             let jwt_decode = DecodingKey::from_secret(key_env.as_bytes());
@@ -61,6 +80,7 @@ impl Context {
                 jwt_encode,
                 jwt_decode,
                 aes,
+                url,
             }
         };
 
@@ -89,16 +109,31 @@ impl Context {
         .await
         .expect("Cannot create storage");
 
+        let http_client = Client::new();
+
+        // mentions are looked for in every workspace as long as it's loaded
+        let notifications = Notifications::from_env(http_client.clone());
+        storage.docs().set_workspace_plugins(
+            WorkspacePlugins::default().register(notifications.mention_watch()),
+        );
+
         Self {
             db: cloud_db,
             key,
             firebase,
             mail,
-            http_client: Client::new(),
+            auth: AuthProviders::from_env(http_client.clone()),
+            notifications,
+            http_client,
             storage,
             site_url,
             channel: RwLock::new(HashMap::new()),
             user_channel: UserChannel::new(),
+            sessions: SyncSessions::default(),
+            bandwidth: BandwidthUsage::default(),
+            migrations: DashMap::new(),
+            exports: ExportQueue::from_env(),
+            load: Arc::new(LoadMonitor::new(LoadThresholds::from_env())),
         }
     }
 
@@ -262,6 +297,7 @@ impl Context {
     }
 }
 
+#[async_trait]
 impl ContextImpl<'_> for Context {
     fn get_storage(&self) -> &JwstStorage {
         &self.storage
@@ -270,4 +306,53 @@ impl ContextImpl<'_> for Context {
     fn get_channel(&self) -> &Channels {
         &self.channel
     }
+
+    fn sync_sessions(&self) -> Option<&SyncSessions> {
+        Some(&self.sessions)
+    }
+
+    fn bandwidth_usage(&self) -> Option<&BandwidthUsage> {
+        Some(&self.bandwidth)
+    }
+
+    async fn authenticate(&self, workspace_id: &str, token: &str) -> Option<String> {
+        self.authorize_sync(workspace_id, Some(token))
+            .await
+            .ok()
+            .map(|access| access.user_id)
+    }
+
+    // identifiers of the sync connections are user ids, asked for the sockets that
+    // authenticate in-band and for the polls, see [granted_permission]
+    async fn workspace_user(&self, workspace_id: &str, identifier: &str) -> WorkspaceUser {
+        let granted = match self
+            .db
+            .get_permission(identifier.into(), workspace_id.into())
+            .await
+        {
+            Ok(role) => granted_permission(role.as_ref()),
+            Err(e) => {
+                error!("failed to get permission of {identifier} in {workspace_id}: {e}");
+                WorkspacePermission::ReadOnly
+            }
+        };
+        WorkspaceUser::new(identifier, granted)
+    }
+
+    async fn authenticated_user(&self, _workspace_id: &str, identifier: &str) -> Option<String> {
+        Some(identifier.to_owned())
+    }
+}
+
+/// Add the bandwidth counters to the totals in the storage every minute.
+pub fn start_bandwidth_flush(ctx: Arc<Context>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(BANDWIDTH_FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = ctx.bandwidth.flush(&ctx.storage).await {
+                error!("failed to flush bandwidth usage: {}", e);
+            }
+        }
+    });
 }
@@ -12,10 +12,15 @@ pub enum ErrorStatus {
     NotFoundInvitation,
     InternalServerError,
     PayloadTooLarge,
+    BatchTooLarge,
     BadRequest,
     Forbidden,
     Unauthorized,
     ConflictInvitation,
+    ConflictAccount,
+    ConflictMigration,
+    TooManyExports,
+    Overloaded,
 }
 
 #[derive(Serialize)]
@@ -57,6 +62,10 @@ impl IntoResponse for ErrorStatus {
                 StatusCode::PAYLOAD_TOO_LARGE,
                 "Upload file size exceeds 10MB",
             ),
+            ErrorStatus::BatchTooLarge => error_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "Upload batch size exceeds 50MB",
+            ),
             ErrorStatus::BadRequest => {
                 error_response(StatusCode::BAD_REQUEST, "Request parameter error.")
             }
@@ -67,6 +76,22 @@ impl IntoResponse for ErrorStatus {
             ErrorStatus::ConflictInvitation => {
                 error_response(StatusCode::CONFLICT, "Invitation failed.")
             }
+            ErrorStatus::ConflictAccount => error_response(
+                StatusCode::CONFLICT,
+                "The email is already registered with another sign in method.",
+            ),
+            ErrorStatus::ConflictMigration => error_response(
+                StatusCode::CONFLICT,
+                "A migration of this workspace is already running.",
+            ),
+            ErrorStatus::TooManyExports => error_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many exports are running, please try again later.",
+            ),
+            ErrorStatus::Overloaded => error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "The server is overloaded, please try again later.",
+            ),
         }
     }
 }
@@ -1,11 +1,27 @@
-use super::*;
+use crate::{
+    error_status::ErrorStatus,
+    utils::{Engine, URL_SAFE_ENGINE},
+};
 use axum::{
-    body::BoxBody,
-    http::{Response, Uri},
-    response::IntoResponse,
+    body::{boxed, Full},
+    http::{
+        header::{
+            ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_TYPE, ETAG, IF_NONE_MATCH,
+            VARY,
+        },
+        HeaderMap, StatusCode, Uri,
+    },
+    response::{IntoResponse, Response},
     routing::get,
+    Router,
+};
+use flate2::{write::GzEncoder, Compression};
+use jwst_logger::info;
+use jwst_static::{
+    rust_embed::{self, EmbeddedFile},
+    RustEmbed,
 };
-use jwst_static::{fetch_static_response, rust_embed, RustEmbed};
+use std::{io::Write, path::Path};
 
 #[cfg(debug_assertions)]
 #[derive(RustEmbed)]
@@ -26,13 +42,359 @@ struct Frontend;
 #[exclude = "*.map"]
 struct Frontend;
 
-async fn frontend_handler(uri: Uri) -> Response<BoxBody> {
+type StaticFileFetcher = fn(&str) -> Option<EmbeddedFile>;
+
+const INDEX_HTML: &str = "index.html";
+/// hashed assets never change, so the browser can cache them forever
+const IMMUTABLE_CACHE: &str = "public, max-age=31536000, immutable";
+/// entry points must be revalidated with the etag on every load
+const NO_CACHE: &str = "no-cache";
+/// only small text assets are compressed on the fly, larger ones should be pre-compressed
+const ON_THE_FLY_COMPRESS_RANGE: std::ops::RangeInclusive<usize> = 1024..=512 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    /// server preference order
+    const ALL: [Encoding; 2] = [Encoding::Brotli, Encoding::Gzip];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Brotli => "br",
+            Self::Gzip => "gzip",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Brotli => "br",
+            Self::Gzip => "gz",
+        }
+    }
+}
+
+/// Encodings accepted by client, ordered by server preference.
+fn accepted_encodings(headers: &HeaderMap) -> Vec<Encoding> {
+    let accepted = headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .filter_map(|token| {
+            let mut parts = token.split(';').map(|s| s.trim());
+            let name = parts.next()?.to_ascii_lowercase();
+            let rejected = parts
+                .filter_map(|p| p.strip_prefix("q="))
+                .any(|q| q.parse::<f32>().map(|q| q <= 0.0).unwrap_or(false));
+            (!rejected).then_some(name)
+        })
+        .collect::<Vec<_>>();
+
+    Encoding::ALL
+        .into_iter()
+        .filter(|encoding| {
+            accepted
+                .iter()
+                .any(|name| name == encoding.name() || name == "*")
+        })
+        .collect()
+}
+
+/// Bundlers put a content hash into the file name, e.g. `main.3f2a9c1b.js` or `app-3f2a9c1b.css`.
+fn is_hashed(path: &str) -> bool {
+    let name = Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+
+    name.split(|c| c == '.' || c == '-' || c == '_')
+        .any(|segment| segment.len() >= 8 && segment.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+fn is_compressible(mimetype: &str) -> bool {
+    mimetype.starts_with("text/")
+        || mimetype.contains("javascript")
+        || mimetype.contains("json")
+        || mimetype.contains("xml")
+}
+
+fn gzip(data: &[u8]) -> Option<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).ok()?;
+    encoder.finish().ok()
+}
+
+// If-None-Match uses the weak comparison
+fn etag_matched(headers: &HeaderMap, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == etag || tag == "*")
+}
+
+fn serve_static(fetcher: StaticFileFetcher, path: &str, headers: &HeaderMap) -> Response {
+    let path = if path.is_empty() { INDEX_HTML } else { path };
+
+    let (path, file) = match fetcher(path) {
+        Some(file) => (path, file),
+        // spa fallback: the route without extension should be handled by frontend router
+        None if Path::new(path).extension().is_none() => match fetcher(INDEX_HTML) {
+            Some(file) => (INDEX_HTML, file),
+            None => return ErrorStatus::NotFound.into_response(),
+        },
+        None => return ErrorStatus::NotFound.into_response(),
+    };
+
+    let hashed = is_hashed(path);
+    // weak, the identity and compressed bodies share it
+    let etag = (!hashed).then(|| {
+        format!(
+            "W/\"{}\"",
+            URL_SAFE_ENGINE.encode(file.metadata.sha256_hash())
+        )
+    });
+
+    if let Some(etag) = &etag {
+        if etag_matched(headers, etag) {
+            return (
+                StatusCode::NOT_MODIFIED,
+                [
+                    (ETAG, etag.as_str()),
+                    (VARY, ACCEPT_ENCODING.as_str()),
+                    (CACHE_CONTROL, NO_CACHE),
+                ],
+            )
+                .into_response();
+        }
+    }
+
+    let mimetype = file.metadata.mimetype();
+    let mut builder = Response::builder()
+        .header(CONTENT_TYPE, mimetype)
+        .header(VARY, ACCEPT_ENCODING.as_str())
+        .header(
+            CACHE_CONTROL,
+            if hashed { IMMUTABLE_CACHE } else { NO_CACHE },
+        );
+    if let Some(etag) = etag {
+        builder = builder.header(ETAG, etag);
+    }
+
+    let encodings = accepted_encodings(headers);
+
+    // prefer the pre-compressed sibling generated at build time
+    for encoding in &encodings {
+        if let Some(compressed) = fetcher(&format!("{path}.{}", encoding.extension())) {
+            return builder
+                .header(CONTENT_ENCODING, encoding.name())
+                .body(boxed(Full::from(compressed.data)))
+                .unwrap();
+        }
+    }
+
+    if encodings.contains(&Encoding::Gzip)
+        && is_compressible(mimetype)
+        && ON_THE_FLY_COMPRESS_RANGE.contains(&file.data.len())
+    {
+        if let Some(compressed) = gzip(&file.data) {
+            return builder
+                .header(CONTENT_ENCODING, Encoding::Gzip.name())
+                .body(boxed(Full::from(compressed)))
+                .unwrap();
+        }
+    }
+
+    builder.body(boxed(Full::from(file.data))).unwrap()
+}
+
+fn frontend_handler(fetcher: StaticFileFetcher, uri: Uri, headers: HeaderMap) -> Response {
     info!("get static {:?}", uri);
-    fetch_static_response(uri.clone(), true, Some(Frontend::get))
-        .await
-        .into_response()
+    let path = uri.path().trim_start_matches('/');
+
+    // never shadow api routes, unmatched api should be 404 instead of index page
+    if path == "api" || path.starts_with("api/") {
+        return ErrorStatus::NotFound.into_response();
+    }
+
+    serve_static(fetcher, path, &headers)
+}
+
+fn static_files_with(router: Router, fetcher: StaticFileFetcher) -> Router {
+    router.fallback_service(get(move |uri: Uri, headers: HeaderMap| async move {
+        frontend_handler(fetcher, uri, headers)
+    }))
 }
 
 pub fn static_files(router: Router) -> Router {
-    router.fallback_service(get(frontend_handler))
+    static_files_with(router, Frontend::get)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{body::Body, http::Request};
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+    use tower::ServiceExt;
+
+    #[derive(RustEmbed)]
+    #[folder = "fixtures/static"]
+    struct Fixtures;
+
+    fn app() -> Router {
+        static_files_with(
+            Router::new().nest(
+                "/api",
+                Router::new().route("/healthz", get(|| async { StatusCode::OK })),
+            ),
+            Fixtures::get,
+        )
+    }
+
+    async fn request(uri: &str, headers: &[(&str, &str)]) -> Response {
+        let mut request = Request::builder().uri(uri);
+        for (key, value) in headers {
+            request = request.header(*key, *value);
+        }
+        app()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn body(resp: Response) -> Vec<u8> {
+        hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    #[test]
+    fn encoding_negotiation() {
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT_ENCODING, value.parse().unwrap());
+            headers
+        };
+
+        assert_eq!(accepted_encodings(&HeaderMap::new()), vec![]);
+        assert_eq!(
+            accepted_encodings(&headers("gzip, deflate, br")),
+            vec![Encoding::Brotli, Encoding::Gzip]
+        );
+        assert_eq!(
+            accepted_encodings(&headers("gzip;q=1.0, br;q=0")),
+            vec![Encoding::Gzip]
+        );
+        assert_eq!(
+            accepted_encodings(&headers("*")),
+            vec![Encoding::Brotli, Encoding::Gzip]
+        );
+        assert_eq!(accepted_encodings(&headers("identity")), vec![]);
+
+        assert!(is_hashed("assets/main.3f2a9c1b.js"));
+        assert!(is_hashed("_next/static/chunks/app-7e5c8e1a9b1c2d3e.js"));
+        assert!(!is_hashed("index.html"));
+        assert!(!is_hashed("favicon.ico"));
+    }
+
+    #[tokio::test]
+    async fn precompressed_assets() {
+        let plain = Fixtures::get("app.0a1b2c3d.js").unwrap().data;
+
+        let resp = request("/app.0a1b2c3d.js", &[("Accept-Encoding", "gzip, br")]).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[CONTENT_ENCODING], "br");
+        assert_eq!(resp.headers()[CACHE_CONTROL], IMMUTABLE_CACHE);
+        assert!(resp.headers().get(ETAG).is_none());
+        assert_eq!(
+            body(resp).await,
+            Fixtures::get("app.0a1b2c3d.js.br").unwrap().data.to_vec()
+        );
+
+        let resp = request("/app.0a1b2c3d.js", &[("Accept-Encoding", "gzip")]).await;
+        assert_eq!(resp.headers()[CONTENT_ENCODING], "gzip");
+        let mut decoded = vec![];
+        GzDecoder::new(body(resp).await.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, plain.to_vec());
+
+        let resp = request("/app.0a1b2c3d.js", &[]).await;
+        assert!(resp.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(body(resp).await, plain.to_vec());
+    }
+
+    #[tokio::test]
+    async fn on_the_fly_compression() {
+        let plain = Fixtures::get("style.css").unwrap().data;
+
+        let resp = request("/style.css", &[("Accept-Encoding", "br, gzip")]).await;
+        assert_eq!(resp.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(resp.headers()[CACHE_CONTROL], NO_CACHE);
+        let mut decoded = vec![];
+        GzDecoder::new(body(resp).await.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, plain.to_vec());
+    }
+
+    #[tokio::test]
+    async fn spa_fallback() {
+        let index = Fixtures::get(INDEX_HTML).unwrap().data.to_vec();
+
+        for uri in ["/", "/index.html", "/workspace/abc/page"] {
+            let resp = request(uri, &[]).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.headers()[CACHE_CONTROL], NO_CACHE);
+            assert_eq!(body(resp).await, index);
+        }
+
+        // missing asset with extension is not an app route
+        let resp = request("/missing.js", &[]).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn etag_revalidation() {
+        let resp = request("/", &[]).await;
+        let etag = resp.headers()[ETAG].to_str().unwrap().to_owned();
+        assert!(etag.starts_with("W/\""));
+        assert_eq!(resp.headers()[VARY], "accept-encoding");
+
+        let resp = request("/", &[("If-None-Match", etag.as_str())]).await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.headers()[ETAG], etag.as_str());
+        assert_eq!(resp.headers()[VARY], "accept-encoding");
+
+        // the compressed body carries the same validator
+        let resp = request("/", &[("Accept-Encoding", "gzip")]).await;
+        assert_eq!(resp.headers()[ETAG], etag.as_str());
+        let strong = etag.trim_start_matches("W/");
+        let resp = request("/", &[("If-None-Match", strong)]).await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+        let resp = request("/", &[("If-None-Match", "\"outdated\"")]).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn api_not_shadowed() {
+        let resp = request("/api/healthz", &[]).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        for uri in ["/api", "/api/not-exists", "/api/workspace/abc/unknown"] {
+            let resp = request(uri, &[]).await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            assert_ne!(resp.headers()[CONTENT_TYPE], "text/html");
+        }
+    }
 }
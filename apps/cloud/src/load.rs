@@ -0,0 +1,390 @@
+//! Load shedding: while the server is overloaded, bulk requests are rejected so
+//! collaborative editing stays usable.
+
+use axum::{
+    extract::State,
+    http::{header::RETRY_AFTER, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{context::Context, error_status::ErrorStatus};
+
+// storage latencies the p99 is computed from
+const LATENCY_SAMPLES: usize = 200;
+
+// how often the runtime and the storage are probed
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+// workspace read by the storage probe, it has no flags so the query is cheap
+const PROBE_WORKSPACE: &str = "__load_probe";
+
+// time a rejected client is asked to wait before retrying
+const RETRY_AFTER_SECS: u64 = 5;
+
+// pressure from which routes of normal priority are rejected too
+const SEVERE_PRESSURE: f64 = 2.0;
+
+/// Priority of a route under pressure, assigned with [shed_load]. Routes without
+/// a priority are always admitted, e.g. the sync sockets and small doc requests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    /// Rejected once the server is severely overloaded.
+    Normal,
+    /// Rejected as soon as the server is overloaded, e.g. exports, search and batches.
+    Low,
+}
+
+impl Priority {
+    fn max_pressure(self) -> f64 {
+        match self {
+            Priority::Normal => SEVERE_PRESSURE,
+            Priority::Low => 1.0,
+        }
+    }
+}
+
+/// The server is overloaded once one of the signals exceeds its threshold.
+#[derive(Clone, Debug)]
+pub struct LoadThresholds {
+    /// Requests being handled at once.
+    pub in_flight: usize,
+    /// p99 latency of the storage.
+    pub storage_latency: Duration,
+    /// Delay of the runtime scheduling a task that is ready, a proxy of its queue depth.
+    pub runtime_delay: Duration,
+}
+
+impl Default for LoadThresholds {
+    fn default() -> Self {
+        Self {
+            in_flight: 512,
+            storage_latency: Duration::from_millis(500),
+            runtime_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+impl LoadThresholds {
+    /// `LOAD_MAX_IN_FLIGHT` requests, `LOAD_MAX_STORAGE_LATENCY_MS` and
+    /// `LOAD_MAX_RUNTIME_DELAY_MS`, the defaults for those not set.
+    pub fn from_env() -> Self {
+        let var = |name: &str| dotenvy::var(name).ok().and_then(|value| value.parse().ok());
+        let default = Self::default();
+        Self {
+            in_flight: var("LOAD_MAX_IN_FLIGHT")
+                .map(|value| value as usize)
+                .unwrap_or(default.in_flight),
+            storage_latency: var("LOAD_MAX_STORAGE_LATENCY_MS")
+                .map(Duration::from_millis)
+                .unwrap_or(default.storage_latency),
+            runtime_delay: var("LOAD_MAX_RUNTIME_DELAY_MS")
+                .map(Duration::from_millis)
+                .unwrap_or(default.runtime_delay),
+        }
+    }
+}
+
+/// Pressure signals of the server, see [LoadMonitor::pressure].
+#[derive(Default)]
+pub struct LoadMonitor {
+    thresholds: LoadThresholds,
+    in_flight: AtomicUsize,
+    storage_latencies: Mutex<VecDeque<Duration>>,
+    runtime_delay_us: AtomicU64,
+    shed: AtomicU64,
+}
+
+/// A request in flight, counted until it's dropped.
+pub struct InFlight<'a>(&'a LoadMonitor);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl LoadMonitor {
+    pub fn new(thresholds: LoadThresholds) -> Self {
+        Self {
+            thresholds,
+            ..Default::default()
+        }
+    }
+
+    pub fn enter(&self) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(self)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    pub fn record_storage_latency(&self, latency: Duration) {
+        let mut latencies = self.storage_latencies.lock().unwrap();
+        if latencies.len() == LATENCY_SAMPLES {
+            latencies.pop_front();
+        }
+        latencies.push_back(latency);
+    }
+
+    /// p99 of the last recorded storage latencies.
+    pub fn storage_latency(&self) -> Duration {
+        let mut latencies = self
+            .storage_latencies
+            .lock()
+            .unwrap()
+            .iter()
+            .copied()
+            .collect::<Vec<_>>();
+        if latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        latencies.sort_unstable();
+        let index = (latencies.len() * 99 + 99) / 100 - 1;
+        latencies[index]
+    }
+
+    pub fn record_runtime_delay(&self, delay: Duration) {
+        self.runtime_delay_us
+            .store(delay.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn runtime_delay(&self) -> Duration {
+        Duration::from_micros(self.runtime_delay_us.load(Ordering::Relaxed))
+    }
+
+    /// Highest ratio of a signal to its threshold, the server is overloaded above 1.
+    pub fn pressure(&self) -> f64 {
+        let ratio = |value: u128, threshold: u128| value as f64 / threshold.max(1) as f64;
+        [
+            ratio(self.in_flight() as u128, self.thresholds.in_flight as u128),
+            ratio(
+                self.storage_latency().as_nanos(),
+                self.thresholds.storage_latency.as_nanos(),
+            ),
+            ratio(
+                self.runtime_delay().as_nanos(),
+                self.thresholds.runtime_delay.as_nanos(),
+            ),
+        ]
+        .into_iter()
+        .fold(0.0, f64::max)
+    }
+
+    fn admit(&self, priority: Priority) -> bool {
+        if self.pressure() > priority.max_pressure() {
+            self.shed.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    /// The signals in the Prometheus text format.
+    pub fn metrics(&self) -> String {
+        format!(
+            "# TYPE load_pressure gauge\n\
+             load_pressure {}\n\
+             # TYPE load_in_flight_requests gauge\n\
+             load_in_flight_requests {}\n\
+             # TYPE load_storage_latency_p99_seconds gauge\n\
+             load_storage_latency_p99_seconds {}\n\
+             # TYPE load_runtime_delay_seconds gauge\n\
+             load_runtime_delay_seconds {}\n\
+             # TYPE load_shed_requests_total counter\n\
+             load_shed_requests_total {}\n",
+            self.pressure(),
+            self.in_flight(),
+            self.storage_latency().as_secs_f64(),
+            self.runtime_delay().as_secs_f64(),
+            self.shed.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Count the requests in flight, wraps all routes.
+pub async fn track_load<B>(
+    Extension(monitor): Extension<Arc<LoadMonitor>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let _in_flight = monitor.enter();
+    next.run(req).await
+}
+
+/// Reject the requests of a route under pressure, the priority is its state:
+/// `.route_layer(middleware::from_fn_with_state(Priority::Low, shed_load))`.
+/// - Return 503 Service Unavailable with `Retry-After` if the request is rejected.
+pub async fn shed_load<B>(
+    State(priority): State<Priority>,
+    Extension(monitor): Extension<Arc<LoadMonitor>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if !monitor.admit(priority) {
+        return (
+            [(RETRY_AFTER, RETRY_AFTER_SECS.to_string())],
+            ErrorStatus::Overloaded,
+        )
+            .into_response();
+    }
+    next.run(req).await
+}
+
+pub async fn metrics(Extension(monitor): Extension<Arc<LoadMonitor>>) -> String {
+    monitor.metrics()
+}
+
+/// Probe the delay of the runtime and the latency of the storage once in a while.
+pub fn start_load_monitor(ctx: Arc<Context>) {
+    tokio::spawn({
+        let ctx = ctx.clone();
+        async move {
+            loop {
+                // a busy runtime wakes the task up late
+                let start = Instant::now();
+                tokio::time::sleep(PROBE_INTERVAL).await;
+                ctx.load
+                    .record_runtime_delay(start.elapsed().saturating_sub(PROBE_INTERVAL));
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PROBE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let start = Instant::now();
+            // a failing storage is as unusable as a slow one
+            let latency = match ctx.storage.workspace_flags(PROBE_WORKSPACE).await {
+                Ok(_) => start.elapsed(),
+                Err(_) => ctx.load.thresholds.storage_latency * 2,
+            };
+            ctx.load.record_storage_latency(latency);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        middleware,
+        routing::{get, Router},
+    };
+    use http::StatusCode;
+    use tower::ServiceExt;
+
+    fn monitor() -> Arc<LoadMonitor> {
+        Arc::new(LoadMonitor::new(LoadThresholds {
+            in_flight: 4,
+            storage_latency: Duration::from_millis(100),
+            runtime_delay: Duration::from_millis(50),
+        }))
+    }
+
+    fn app(monitor: Arc<LoadMonitor>) -> Router {
+        Router::new()
+            .route(
+                "/export",
+                get(|| async {})
+                    .route_layer(middleware::from_fn_with_state(Priority::Low, shed_load)),
+            )
+            .route(
+                "/notifications",
+                get(|| async {})
+                    .route_layer(middleware::from_fn_with_state(Priority::Normal, shed_load)),
+            )
+            .route("/doc", get(|| async {}))
+            .route("/metrics", get(metrics))
+            .layer(middleware::from_fn(track_load))
+            .layer(Extension(monitor))
+    }
+
+    async fn status(monitor: &Arc<LoadMonitor>, uri: &str) -> StatusCode {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = app(monitor.clone()).oneshot(request).await.unwrap();
+        if response.status() == StatusCode::SERVICE_UNAVAILABLE {
+            assert_eq!(response.headers()[RETRY_AFTER], "5");
+        }
+        response.status()
+    }
+
+    #[test]
+    fn pressure_signals() {
+        let monitor = monitor();
+        assert_eq!(monitor.pressure(), 0.0);
+
+        let in_flight = [monitor.enter(), monitor.enter()];
+        assert_eq!(monitor.pressure(), 0.5);
+        drop(in_flight);
+        assert_eq!(monitor.in_flight(), 0);
+
+        monitor.record_runtime_delay(Duration::from_millis(75));
+        assert_eq!(monitor.pressure(), 1.5);
+        monitor.record_runtime_delay(Duration::ZERO);
+
+        // a single slow query out of 100 is below the p99
+        monitor.record_storage_latency(Duration::from_millis(300));
+        for _ in 0..99 {
+            monitor.record_storage_latency(Duration::from_millis(10));
+        }
+        assert_eq!(monitor.storage_latency(), Duration::from_millis(10));
+        monitor.record_storage_latency(Duration::from_millis(300));
+        assert_eq!(monitor.storage_latency(), Duration::from_millis(300));
+        assert_eq!(monitor.pressure(), 3.0);
+
+        // old samples are dropped
+        for _ in 0..LATENCY_SAMPLES {
+            monitor.record_storage_latency(Duration::from_millis(20));
+        }
+        assert_eq!(monitor.storage_latency(), Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn selective_rejection() {
+        let monitor = monitor();
+        for uri in ["/export", "/notifications", "/doc"] {
+            assert_eq!(status(&monitor, uri).await, StatusCode::OK);
+        }
+
+        // overloaded, only the low priority routes are rejected
+        monitor.record_storage_latency(Duration::from_millis(150));
+        assert_eq!(
+            status(&monitor, "/export").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(status(&monitor, "/notifications").await, StatusCode::OK);
+        assert_eq!(status(&monitor, "/doc").await, StatusCode::OK);
+
+        // severely overloaded, routes without priority are still admitted
+        let _in_flight = (0..12).map(|_| monitor.enter()).collect::<Vec<_>>();
+        assert_eq!(
+            status(&monitor, "/export").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            status(&monitor, "/notifications").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(status(&monitor, "/doc").await, StatusCode::OK);
+
+        let request = Request::get("/metrics").body(Body::empty()).unwrap();
+        let response = app(monitor.clone()).oneshot(request).await.unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let metrics = String::from_utf8(body.to_vec()).unwrap();
+        assert!(metrics.contains("load_in_flight_requests 13\n"));
+        assert!(metrics.contains("load_shed_requests_total 3\n"));
+    }
+}
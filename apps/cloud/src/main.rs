@@ -1,4 +1,4 @@
-use axum::{Extension, Router, Server};
+use axum::{middleware, routing::get, Extension, Router, Server};
 use http::Method;
 use jwst_logger::{error, info, init_logger};
 use std::{net::SocketAddr, sync::Arc};
@@ -9,6 +9,7 @@ mod context;
 mod error_status;
 mod files;
 mod layer;
+mod load;
 mod utils;
 
 #[tokio::main]
@@ -28,13 +29,20 @@ async fn main() {
         .allow_headers(Any);
 
     let context = Arc::new(context::Context::new().await);
+    api::start_export_workers(context.clone()).await;
+    api::start_notification_worker(context.clone());
+    load::start_load_monitor(context.clone());
+    context::start_bandwidth_flush(context.clone());
 
     let app = files::static_files(
         Router::new()
+            .route("/metrics", get(load::metrics))
             .nest(
                 "/api",
                 api::make_rest_route(context.clone()).nest("/sync", api::make_ws_route()),
             )
+            .layer(middleware::from_fn(load::track_load))
+            .layer(Extension(context.load.clone()))
             .layer(Extension(context.clone()))
             .layer(cors),
     );
@@ -49,6 +57,9 @@ async fn main() {
     {
         error!("Server shutdown due to error: {}", e);
     }
+    if let Err(e) = context.bandwidth.flush(&context.storage).await {
+        error!("failed to flush bandwidth usage: {}", e);
+    }
 
     info!("Server shutdown complete");
 }
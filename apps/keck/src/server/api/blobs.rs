@@ -1,7 +1,9 @@
 use super::*;
 
-use axum::{body::Bytes, response::Response};
-use utoipa::ToSchema;
+use axum::{body::Bytes, extract::Query, middleware, response::Response};
+use jwst::{BlobReference, JwstError, DEFAULT_BLOB_PROPERTY_KEYS};
+use jwst_storage::{BandwidthScope, BlobUploadError, MAX_CHECKED_BLOBS};
+use utoipa::{IntoParams, ToSchema};
 
 #[derive(Serialize, ToSchema)]
 struct BlobStatus {
@@ -68,6 +70,10 @@ pub async fn get_blob(
     let (workspace, hash) = params;
     info!("get_blob: {}, {}", workspace, hash);
     if let Ok(blob) = context.storage.blobs().get(&workspace, &hash).await {
+        context
+            .bandwidth
+            .counter(BandwidthScope::Workspace(workspace))
+            .add_sent(blob.blob.len());
         blob.blob.into_response()
     } else {
         StatusCode::NOT_FOUND.into_response()
@@ -151,12 +157,406 @@ pub async fn delete_blob(
     }
 }
 
-pub fn blobs_apis(router: Router) -> Router {
-    router.route(
-        "/blobs/:workspace/:blob",
-        head(check_blob)
-            .get(get_blob)
-            .post(set_blob)
-            .delete(delete_blob),
+#[derive(Deserialize, ToSchema)]
+pub struct BlobCheck {
+    hashes: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BlobInfo {
+    exists: bool,
+    /// Size in bytes, only set if the blob exists.
+    size: Option<u64>,
+    /// Content type the blob is served with, only set if the blob exists.
+    content_type: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BlobCheckResult {
+    /// Whether each hash of the request is stored, in the same order.
+    exists: Vec<bool>,
+}
+
+// size of each hash stored in `workspace`, or the response to answer instead
+async fn stored_blobs(
+    context: &Context,
+    workspace: &str,
+    hashes: &[String],
+) -> Result<HashMap<String, Option<u64>>, Response> {
+    if hashes.len() > MAX_CHECKED_BLOBS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("at most {MAX_CHECKED_BLOBS} hashes can be checked at once"),
+        )
+            .into_response());
+    }
+
+    context
+        .storage
+        .check_blobs(workspace, hashes)
+        .await
+        .map_err(|e| {
+            error!("Failed to check blobs of {}: {:?}", workspace, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        })
+}
+
+/// Check which `Blob`s are already stored in `Workspace`
+/// - Return 200 with the presence of each hash, clients only need to upload the missing ones.
+/// - Return 400 Bad Request if more than 500 hashes are requested.
+#[utoipa::path(
+    post,
+    tag = "Blobs",
+    context_path = "/api/workspace",
+    path = "/{workspace}/blobs/check",
+    params(
+        ("workspace", description = "workspace id"),
+    ),
+    request_body(
+        content = BlobCheck,
+    ),
+    responses(
+        (status = 200, description = "Presence of the blobs", body = BlobCheckResult),
+        (status = 400, description = "Too many hashes"),
+        (status = 500, description = "Failed to query blobs"),
+    )
+)]
+pub async fn check_blobs(
+    Extension(context): Extension<Arc<Context>>,
+    Path(workspace): Path<String>,
+    Json(payload): Json<BlobCheck>,
+) -> Response {
+    info!(
+        "check_blobs: {}, {} hashes",
+        workspace,
+        payload.hashes.len()
+    );
+    match stored_blobs(&context, &workspace, &payload.hashes).await {
+        Ok(blobs) => Json(BlobCheckResult {
+            exists: payload
+                .hashes
+                .iter()
+                .map(|hash| matches!(blobs.get(hash), Some(Some(_))))
+                .collect(),
+        })
+        .into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Check many `Blob`s of `Workspace` at once, instead of a `HEAD` request per blob
+/// - Return 200 with an entry for every requested hash, missing blobs included.
+/// - Return 400 Bad Request if more than 500 hashes are requested.
+#[utoipa::path(
+    post,
+    tag = "Blobs",
+    context_path = "/api/blobs",
+    path = "/{workspace}/exists",
+    params(
+        ("workspace", description = "workspace id"),
+    ),
+    request_body(
+        content = Vec<String>,
+        description = "blob hashes",
+    ),
+    responses(
+        (status = 200, description = "Blob info by hash", body = HashMap<String, BlobInfo>),
+        (status = 400, description = "Too many hashes"),
+        (status = 500, description = "Failed to query blobs"),
+    )
+)]
+pub async fn blobs_exist(
+    Extension(context): Extension<Arc<Context>>,
+    Path(workspace): Path<String>,
+    Json(hashes): Json<Vec<String>>,
+) -> Response {
+    info!("blobs_exist: {}, {} hashes", workspace, hashes.len());
+    match stored_blobs(&context, &workspace, &hashes).await {
+        Ok(blobs) => Json(
+            blobs
+                .into_iter()
+                .map(|(hash, size)| {
+                    let info = BlobInfo {
+                        exists: size.is_some(),
+                        size,
+                        // blobs are stored without their type and always served as binary
+                        content_type: size.map(|_| "application/octet-stream".into()),
+                    };
+                    (hash, info)
+                })
+                .collect::<HashMap<_, _>>(),
+        )
+        .into_response(),
+        Err(response) => response,
+    }
+}
+
+#[derive(Deserialize, Default, ToSchema)]
+pub struct BlobUploadInit {
+    /// Hash of the whole blob, the upload is rejected on completion if it doesn't match.
+    hash: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct BlobUploadStatus {
+    /// Id of the upload, used in place of the blob hash until it's completed.
+    id: String,
+    /// Bytes uploaded so far, where the next chunk starts.
+    length: u64,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct BlobUploadOffset {
+    /// Position of the chunk in the blob, must be the length uploaded so far.
+    offset: u64,
+}
+
+fn upload_error(workspace: &str, upload_id: &str, e: BlobUploadError) -> Response {
+    match e {
+        BlobUploadError::NotFound(_) => StatusCode::NOT_FOUND.into_response(),
+        BlobUploadError::OffsetMismatch { length, .. } => (
+            StatusCode::CONFLICT,
+            Json(BlobUploadStatus {
+                id: upload_id.to_owned(),
+                length,
+            }),
+        )
+            .into_response(),
+        BlobUploadError::TooLarge { .. } => {
+            (StatusCode::PAYLOAD_TOO_LARGE, e.to_string()).into_response()
+        }
+        BlobUploadError::Changed(_) => (StatusCode::CONFLICT, e.to_string()).into_response(),
+        BlobUploadError::HashMismatch { .. } => {
+            (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response()
+        }
+        BlobUploadError::Storage(e) => {
+            error!(
+                "Failed to upload blob {} to {}: {:?}",
+                upload_id, workspace, e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Start a resumable `Blob` upload
+/// - Return 200 with the id of the upload, chunks are appended to it one after another.
+#[utoipa::path(
+    post,
+    tag = "Blobs",
+    context_path = "/api/blobs",
+    path = "/{workspace}/init",
+    params(
+        ("workspace", description = "workspace id"),
+    ),
+    request_body(
+        content = BlobUploadInit,
+    ),
+    responses(
+        (status = 200, description = "Upload started", body = BlobUploadStatus),
+        (status = 500, description = "Failed to start the upload"),
     )
+)]
+pub async fn init_blob_upload(
+    Extension(context): Extension<Arc<Context>>,
+    Path(workspace): Path<String>,
+    payload: Option<Json<BlobUploadInit>>,
+) -> Response {
+    info!("init_blob_upload: {}", workspace);
+    let Json(payload) = payload.unwrap_or_default();
+    match context
+        .storage
+        .init_blob_upload(&workspace, payload.hash.as_deref())
+        .await
+    {
+        Ok(id) => Json(BlobUploadStatus { id, length: 0 }).into_response(),
+        Err(e) => {
+            error!("Failed to start blob upload to {}: {:?}", workspace, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Append a chunk to a `Blob` upload
+/// - Return 200 with the length uploaded so far.
+/// - Return 404 Not Found if the upload doesn't exist, it was completed or abandoned.
+/// - Return 409 Conflict with the length uploaded so far if `offset` is not the end of the
+///   upload, the upload resumes from there.
+/// - Return 413 Payload Too Large if the upload would exceed the most bytes an upload may have.
+#[utoipa::path(
+    patch,
+    tag = "Blobs",
+    context_path = "/api/blobs",
+    path = "/{workspace}/{upload_id}",
+    params(
+        ("workspace", description = "workspace id"),
+        ("upload_id", description = "upload id"),
+        BlobUploadOffset,
+    ),
+    request_body(
+        content = Vec<u8>,
+    ),
+    responses(
+        (status = 200, description = "Chunk was appended", body = BlobUploadStatus),
+        (status = 404, description = "Upload not found"),
+        (status = 409, description = "Chunk doesn't continue the upload", body = BlobUploadStatus),
+        (status = 413, description = "Upload is too large"),
+    )
+)]
+pub async fn append_blob_upload(
+    Extension(context): Extension<Arc<Context>>,
+    Path((workspace, upload_id)): Path<(String, String)>,
+    Query(BlobUploadOffset { offset }): Query<BlobUploadOffset>,
+    body: Bytes,
+) -> Response {
+    info!(
+        "append_blob_upload: {}, {}, {}bytes at {}",
+        workspace,
+        upload_id,
+        body.len(),
+        offset
+    );
+    match context
+        .storage
+        .append_blob_upload(&workspace, &upload_id, offset, &body)
+        .await
+    {
+        Ok(length) => Json(BlobUploadStatus {
+            id: upload_id,
+            length,
+        })
+        .into_response(),
+        Err(e) => upload_error(&workspace, &upload_id, e),
+    }
+}
+
+/// Complete a `Blob` upload
+/// - Return 200 with the hash of the stored `Blob`.
+/// - Return 404 Not Found if the upload doesn't exist.
+/// - Return 409 Conflict if a chunk was appended while it was completed, it can be retried.
+/// - Return 422 Unprocessable Entity if the `Blob` doesn't have the hash the upload was
+///   started with, the upload is dropped.
+#[utoipa::path(
+    post,
+    tag = "Blobs",
+    context_path = "/api/blobs",
+    path = "/{workspace}/{upload_id}/complete",
+    params(
+        ("workspace", description = "workspace id"),
+        ("upload_id", description = "upload id"),
+    ),
+    responses(
+        (status = 200, description = "Hash of the stored blob", body = String),
+        (status = 404, description = "Upload not found"),
+        (status = 409, description = "Upload was appended to meanwhile"),
+        (status = 422, description = "Blob doesn't have the expected hash"),
+    )
+)]
+pub async fn complete_blob_upload(
+    Extension(context): Extension<Arc<Context>>,
+    Path((workspace, upload_id)): Path<(String, String)>,
+) -> Response {
+    info!("complete_blob_upload: {}, {}", workspace, upload_id);
+    match context
+        .storage
+        .complete_blob_upload(&workspace, &upload_id)
+        .await
+    {
+        Ok(hash) => hash.into_response(),
+        Err(e) => upload_error(&workspace, &upload_id, e),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct BlobAuditQuery {
+    /// Block properties holding blob hashes, comma separated, default to the AFFiNE ones.
+    keys: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct BlobAuditResult {
+    /// References to blobs that are not stored.
+    missing: Vec<BlobReference>,
+    /// Stored blobs that are not referenced by any block.
+    unreferenced: Vec<String>,
+}
+
+/// Audit blob references of `Workspace`
+/// - Return 200 with references to missing blobs and blobs without references.
+/// - Return 404 Not Found if `Workspace` not exists.
+#[utoipa::path(
+    get,
+    tag = "Blobs",
+    context_path = "/api/admin/workspaces",
+    path = "/{workspace}/blob_audit",
+    params(
+        ("workspace", description = "workspace id"),
+        BlobAuditQuery,
+    ),
+    responses(
+        (status = 200, description = "Blob audit report", body = BlobAuditResult),
+        (status = 404, description = "Workspace not found"),
+        (status = 500, description = "Failed to audit blobs"),
+    )
+)]
+pub async fn blob_audit(
+    Extension(context): Extension<Arc<Context>>,
+    Path(workspace): Path<String>,
+    Query(query): Query<BlobAuditQuery>,
+) -> Response {
+    info!("blob_audit: {}", workspace);
+    let keys = match &query.keys {
+        Some(keys) => keys
+            .split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .collect::<Vec<_>>(),
+        None => DEFAULT_BLOB_PROPERTY_KEYS.to_vec(),
+    };
+
+    match context
+        .storage
+        .audit_blob_references(&workspace, &keys)
+        .await
+    {
+        Ok(audit) => Json(BlobAuditResult {
+            missing: audit.missing,
+            unreferenced: audit.unreferenced,
+        })
+        .into_response(),
+        Err(JwstError::WorkspaceNotFound(_)) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            error!("Failed to audit blobs of {}: {:?}", workspace, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub fn blobs_apis(router: Router) -> Router {
+    // uploads and deletions count towards the mutation rate limit of the workspace
+    let mutations = Router::new()
+        .route(
+            "/blobs/:workspace/:blob",
+            head(check_blob)
+                .get(get_blob)
+                .post(set_blob)
+                .patch(append_blob_upload)
+                .delete(delete_blob),
+        )
+        .route("/blobs/:workspace/init", post(init_blob_upload))
+        .route(
+            "/blobs/:workspace/:blob/complete",
+            post(complete_blob_upload),
+        )
+        .route_layer(middleware::from_fn(super::limits::limit_mutations));
+
+    let admin = Router::new()
+        .route("/admin/workspaces/:workspace/blob_audit", get(blob_audit))
+        .route_layer(middleware::from_fn(super::authenticate_admin));
+
+    router
+        .merge(mutations)
+        .merge(admin)
+        .route("/blobs/:workspace/exists", post(blobs_exist))
+        .route("/workspace/:workspace/blobs/check", post(check_blobs))
 }
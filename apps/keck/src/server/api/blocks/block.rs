@@ -1,12 +1,46 @@
 use super::*;
 use axum::{extract::Query, response::Response};
-use jwst::DocStorage;
+use http::{header::IF_MATCH, HeaderMap};
+use jwst::{Block, DocStorage, PropertyKey, Workspace};
 use lib0::any::Any;
 use serde_json::Value as JsonValue;
+use yrs::ReadTxn;
+
+// properties a single request can set on a block
+const MAX_BLOCK_PROPERTIES: usize = 1024;
+
+// check the `If-Match` header against the revision of a block, `*` matches any existing block.
+// this is best-effort, concurrent edits merged from peers can share a revision,
+// see [jwst::Block::revision]
+fn revision_matches<T: ReadTxn>(
+    headers: &HeaderMap,
+    workspace: &Workspace,
+    trx: &T,
+    block_id: &str,
+) -> bool {
+    let Some(expected) = headers.get(IF_MATCH) else {
+        return true;
+    };
+    let expected = expected.to_str().unwrap_or_default().trim();
+    let expected = expected.trim_start_matches("W/").trim_matches('"');
+
+    workspace
+        .get(trx, block_id)
+        .map(|block| expected == "*" || expected == block.revision(trx).to_string())
+        .unwrap_or_default()
+}
+
+#[derive(Serialize)]
+struct BlockWithThumbnail {
+    #[serde(flatten)]
+    block: Block,
+    thumbnail_url: Option<String>,
+}
 
 /// Get a `Block` by id
 /// - Return 200 and `Block`'s data if `Block` is exists.
 /// - Return 404 Not Found if `Workspace` or `Block` not exists.
+/// - Return 422 Unprocessable Entity if `Block` is malformed.
 #[utoipa::path(
     get,
     tag = "Blocks",
@@ -19,6 +53,7 @@ use serde_json::Value as JsonValue;
     responses(
         (status = 200, description = "Get block", body = Block),
         (status = 404, description = "Workspace or block content not found"),
+        (status = 422, description = "Block content is malformed"),
     )
 )]
 pub async fn get_block(
@@ -27,11 +62,23 @@ pub async fn get_block(
 ) -> Response {
     let (ws_id, block) = params;
     info!("get_block: {}, {}", ws_id, block);
-    if let Ok(workspace) = context.storage.get_workspace(ws_id).await {
-        if let Some(block) = workspace.with_trx(|t| workspace.get(&t.trx, block)) {
-            Json(block).into_response()
-        } else {
-            StatusCode::NOT_FOUND.into_response()
+    if let Ok(workspace) = context.storage.get_workspace(&ws_id).await {
+        match workspace.with_trx(|t| workspace.try_get(&t.trx, block)) {
+            Ok(Some(block)) => {
+                let thumbnail_url = workspace
+                    .with_trx(|t| block.thumbnail(&t.trx))
+                    .map(|blob_id| context.blob_url(&ws_id, &blob_id));
+                Json(BlockWithThumbnail {
+                    block,
+                    thumbnail_url,
+                })
+                .into_response()
+            }
+            Ok(None) => StatusCode::NOT_FOUND.into_response(),
+            Err(e) => {
+                error!("get_block: {}", e);
+                (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response()
+            }
         }
     } else {
         StatusCode::NOT_FOUND.into_response()
@@ -40,7 +87,10 @@ pub async fn get_block(
 
 /// Create or set `Block` with content
 /// - Return 200 and `Block`'s data if `Block`'s content set successful.
+/// - Return 403 Forbidden if the content has a `sys:` property, they are managed by the server.
 /// - Return 404 Not Found if `Workspace` not exists.
+/// - Return 412 Precondition Failed if `If-Match` doesn't match the revision of `Block`.
+/// - Return 413 Payload Too Large if the content has more than 1024 properties.
 #[utoipa::path(
     post,
     tag = "Blocks",
@@ -57,21 +107,43 @@ pub async fn get_block(
     ),
     responses(
         (status = 200, description = "Block created and content was set", body = Block),
+        (status = 403, description = "System properties can't be set"),
         (status = 404, description = "Workspace not found"),
+        (status = 412, description = "Block revision doesn't match"),
+        (status = 413, description = "Too many properties"),
     )
 )]
 pub async fn set_block(
     Extension(context): Extension<Arc<Context>>,
     Path(params): Path<(String, String)>,
+    headers: HeaderMap,
     Json(payload): Json<JsonValue>,
 ) -> Response {
     let (ws_id, block) = params;
     info!("set_block: {}, {}", ws_id, block);
     if let Ok(workspace) = context.storage.get_workspace(&ws_id).await {
+        if let Some(block_content) = payload.as_object() {
+            if block_content.len() > MAX_BLOCK_PROPERTIES {
+                return (
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!("at most {MAX_BLOCK_PROPERTIES} properties can be set at once"),
+                )
+                    .into_response();
+            }
+            if let Err(e) = block_content
+                .keys()
+                .try_for_each(|key| workspace.system_keys().check(PropertyKey::new(key)))
+            {
+                return (StatusCode::FORBIDDEN, e.to_string()).into_response();
+            }
+        }
         let mut update = None;
 
         // set block content
         let block = workspace.with_trx(|mut t| {
+            if !revision_matches(&headers, &workspace, &t.trx, &block) {
+                return None;
+            }
             let block = t.create(&block, "text");
 
             // set block content
@@ -89,8 +161,11 @@ pub async fn set_block(
                 }
             }
 
-            block
+            Some(block)
         });
+        let Some(block) = block else {
+            return StatusCode::PRECONDITION_FAILED.into_response();
+        };
 
         if let Some(update) = update {
             if let Err(e) = context.storage.docs().write_update(ws_id, &update).await {
@@ -105,9 +180,22 @@ pub async fn set_block(
     }
 }
 
+#[derive(Deserialize, utoipa::IntoParams)]
+pub struct BlockHistoryQuery {
+    /// Name of a property, to list who changed it and when instead.
+    key: Option<String>,
+}
+
 /// Get `Block` history
 /// - Return 200 and `Block`'s history if `Block` exists.
 /// - Return 404 Not Found if `Workspace` or `Block` not exists.
+///
+/// With `key` the changes of a property are resolved from the stored updates instead, this
+/// runs in the background and is cached until the workspace changes:
+/// - Return 202 Accepted while the history is resolved, ask again later.
+/// - Return 200 and the changes of the property once it is done.
+/// - Return 404 Not Found if nothing is stored for `Workspace`.
+/// - Return 500 Internal Server Error if the history could not be resolved.
 #[utoipa::path(
     get,
     tag = "Blocks",
@@ -116,18 +204,25 @@ pub async fn set_block(
     params(
         ("workspace", description = "workspace id"),
         ("block", description = "block id"),
+        BlockHistoryQuery,
     ),
     responses(
         (status = 200, description = "Get block history", body = [BlockHistory]),
+        (status = 202, description = "Property history is being resolved", body = schema::PropertyHistory),
         (status = 404, description = "Workspace or block not found"),
+        (status = 500, description = "Failed to resolve property history", body = schema::PropertyHistory),
     )
 )]
 pub async fn get_block_history(
     Extension(context): Extension<Arc<Context>>,
     Path(params): Path<(String, String)>,
+    Query(query): Query<BlockHistoryQuery>,
 ) -> Response {
     let (ws_id, block) = params;
     info!("get_block_history: {}, {}", ws_id, block);
+    if let Some(key) = query.key {
+        return get_property_history(context, &ws_id, &block, &key).await;
+    }
     if let Ok(workspace) = context.storage.get_workspace(&ws_id).await {
         workspace.with_trx(|t| {
             if let Some(block) = workspace.get(&t.trx, block) {
@@ -141,9 +236,78 @@ pub async fn get_block_history(
     }
 }
 
+async fn get_property_history(
+    context: Arc<Context>,
+    ws_id: &str,
+    block: &str,
+    key: &str,
+) -> Response {
+    let seq = match context.storage.last_update_seq(ws_id).await {
+        Ok(Some(seq)) => seq,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            error!("failed to find last update of {}: {}", ws_id, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    match context.property_history(ws_id, block, key, seq) {
+        PropertyHistoryJob::Running => {
+            (StatusCode::ACCEPTED, Json(schema::PropertyHistory::Running)).into_response()
+        }
+        PropertyHistoryJob::Done(history) => Json(schema::PropertyHistory::Done {
+            history: history.iter().map(Into::into).collect(),
+        })
+        .into_response(),
+        PropertyHistoryJob::Failed(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(schema::PropertyHistory::Failed { error }),
+        )
+            .into_response(),
+    }
+}
+
+/// Get the blocks linking to a `Block`
+/// - Return 200 and the linking blocks with the property holding the link.
+/// - Return 404 Not Found if `Workspace` or `Block` not exists.
+#[utoipa::path(
+    get,
+    tag = "Blocks",
+    context_path = "/api/block",
+    path = "/{workspace}/{block}/backlinks",
+    params(
+        ("workspace", description = "workspace id"),
+        ("block", description = "block id"),
+    ),
+    responses(
+        (status = 200, description = "Get block backlinks", body = [BlockRef]),
+        (status = 404, description = "Workspace or block not found"),
+    )
+)]
+pub async fn get_block_backlinks(
+    Extension(context): Extension<Arc<Context>>,
+    Path(params): Path<(String, String)>,
+) -> Response {
+    let (ws_id, block) = params;
+    info!("get_block_backlinks: {}, {}", ws_id, block);
+    if let Ok(workspace) = context.storage.get_workspace(&ws_id).await {
+        workspace.with_trx(|t| {
+            if workspace.exists(&t.trx, &block) {
+                Json(workspace.backlinks(&t.trx, &block)).into_response()
+            } else {
+                StatusCode::NOT_FOUND.into_response()
+            }
+        })
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
+
 /// Delete block
 /// - Return 204 No Content if delete successful.
 /// - Return 404 Not Found if `Workspace` or `Block` not exists.
+/// - Return 409 Conflict if `Block` is pinned.
+/// - Return 412 Precondition Failed if `If-Match` doesn't match the revision of `Block`.
 #[utoipa::path(
     delete,
     tag = "Blocks",
@@ -156,26 +320,38 @@ pub async fn get_block_history(
     responses(
         (status = 204, description = "Block successfully deleted"),
         (status = 404, description = "Workspace or block not found"),
+        (status = 409, description = "Block is pinned"),
+        (status = 412, description = "Block revision doesn't match"),
     )
 )]
 pub async fn delete_block(
     Extension(context): Extension<Arc<Context>>,
     Path(params): Path<(String, String)>,
+    headers: HeaderMap,
 ) -> StatusCode {
     let (ws_id, block) = params;
     info!("delete_block: {}, {}", ws_id, block);
     if let Ok(workspace) = context.storage.get_workspace(&ws_id).await {
-        if let Some(update) = workspace.with_trx(|mut t| {
-            if t.remove(&block) {
-                Some(t.trx.encode_update_v1())
-            } else {
-                None
+        // checked in the transaction of the removal, so no edit can slip in between
+        match workspace.with_trx(|mut t| {
+            if !revision_matches(&headers, &workspace, &t.trx, &block) {
+                return Ok(Err(StatusCode::PRECONDITION_FAILED));
             }
+            t.try_remove(&block)
+                .map(|removed| Ok(removed.then(|| t.trx.encode_update_v1())))
         }) {
-            if let Err(e) = context.storage.docs().write_update(ws_id, &update).await {
-                error!("db write error: {}", e.to_string());
+            Ok(Err(status)) => return status,
+            Ok(Ok(Some(update))) => {
+                if let Err(e) = context.storage.docs().write_update(ws_id, &update).await {
+                    error!("db write error: {}", e.to_string());
+                }
+                return StatusCode::NO_CONTENT;
+            }
+            Ok(Ok(None)) => {}
+            Err(e) => {
+                info!("delete_block: {}", e);
+                return StatusCode::CONFLICT;
             }
-            return StatusCode::NO_CONTENT;
         }
     }
     StatusCode::NOT_FOUND
@@ -237,6 +413,8 @@ pub async fn get_block_children(
 /// Insert a another `Block` into a `Block`'s children
 /// - Return 200 and `Block`'s data if insert successful.
 /// - Return 404 Not Found if `Workspace` or `Block` not exists.
+/// - Return 412 Precondition Failed if `If-Match` doesn't match the revision of `Block`.
+/// - Return 422 Unprocessable Entity if the children would be nested too deep.
 #[utoipa::path(
     post,
     tag = "Blocks",
@@ -254,12 +432,15 @@ pub async fn get_block_children(
     responses(
         (status = 200, description = "Block inserted", body = Block),
         (status = 404, description = "Workspace or block not found"),
+        (status = 412, description = "Block revision doesn't match"),
+        (status = 422, description = "Block would be nested too deep"),
         (status = 500, description = "Failed to insert block")
     )
 )]
 pub async fn insert_block_children(
     Extension(context): Extension<Arc<Context>>,
     Path(params): Path<(String, String)>,
+    headers: HeaderMap,
     Json(payload): Json<InsertChildren>,
 ) -> Response {
     let (ws_id, block) = params;
@@ -268,7 +449,21 @@ pub async fn insert_block_children(
         let mut update = None;
 
         if let Some(block) = workspace.with_trx(|t| workspace.get(&t.trx, block)) {
+            let (InsertChildren::Push(child)
+            | InsertChildren::InsertBefore { id: child, .. }
+            | InsertChildren::InsertAfter { id: child, .. }
+            | InsertChildren::InsertAt { id: child, .. }) = &payload;
+            if let Err(e) = workspace.with_trx(|t| match workspace.get(&t.trx, child) {
+                Some(child) => t.check_nesting(&block, &child),
+                None => Ok(()),
+            }) {
+                return (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response();
+            }
             let block = workspace.with_trx(|mut t| {
+                // checked in the transaction of the insertion, so no edit can slip in between
+                if !revision_matches(&headers, &workspace, &t.trx, &block.id()) {
+                    return None;
+                }
                 let mut changed = false;
                 match payload {
                     InsertChildren::Push(block_id) => {
@@ -301,8 +496,11 @@ pub async fn insert_block_children(
                     update = Some(t.trx.encode_update_v1());
                 }
 
-                block
+                Some(block)
             });
+            let Some(block) = block else {
+                return StatusCode::PRECONDITION_FAILED.into_response();
+            };
 
             if let Some(update) = update {
                 if let Err(e) = context.storage.docs().write_update(ws_id, &update).await {
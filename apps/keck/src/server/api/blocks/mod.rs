@@ -8,10 +8,15 @@ pub use block::{
 };
 pub use workspace::{
     delete_workspace, get_workspace, history_workspace, history_workspace_clients, set_workspace,
-    workspace_client,
+    workspace_client, workspace_presence, workspace_size, workspace_stats,
 };
 
 use super::*;
+use axum::{
+    http::Request,
+    middleware::{self, Next},
+    response::Response,
+};
 use schema::InsertChildren;
 use utoipa::OpenApi;
 
@@ -21,7 +26,26 @@ use utoipa::OpenApi;
         workspace::get_workspace,
         workspace::set_workspace,
         workspace::delete_workspace,
+        workspace::apply_updates,
+        workspace::patch_workspace,
         workspace::workspace_client,
+        workspace::workspace_state,
+        workspace::workspace_presence,
+        workspace::workspace_stats,
+        workspace::workspace_size,
+        workspace::compaction_stats,
+        workspace::workspace_flags,
+        workspace::set_workspace_flag,
+        workspace::clear_workspace_flag,
+        workspace::get_mutation_limit,
+        workspace::set_mutation_limit,
+        workspace::clear_mutation_limit,
+        workspace::get_default_mutation_limit,
+        workspace::set_default_mutation_limit,
+        workspace::validate_timestamps,
+        workspace::repair_timestamps,
+        workspace::get_workspace_metadata,
+        workspace::set_workspace_metadata,
         workspace::history_workspace_clients,
         workspace::history_workspace,
         workspace::get_workspace_block,
@@ -29,22 +53,36 @@ use utoipa::OpenApi;
         block::get_block,
         block::set_block,
         block::get_block_history,
+        block::get_block_backlinks,
         block::get_block_children,
         block::delete_block,
         block::insert_block_children,
         block::remove_block_children,
+        super::blobs::check_blobs,
+        super::blobs::blobs_exist,
+        super::blobs::init_blob_upload,
+        super::blobs::append_blob_upload,
+        super::blobs::complete_blob_upload,
     ),
     components(
         schemas(
-            schema::InsertChildren,
+            schema::InsertChildren, schema::Presence, schema::Collaborator,
             schema::Workspace, schema::Block, schema::BlockRawHistory,
             jwst::BlockHistory, jwst::HistoryOperation, jwst::RawHistory,
-            jwst::SearchResults, jwst::SearchResult
+            jwst::SearchResults, jwst::SearchResult, jwst::WorkspaceStats, jwst::ContentStats,
+            schema::WorkspaceSize, schema::AdminWorkspaceStats, super::blobs::BlobInfo,
+            super::blobs::BlobCheck, super::blobs::BlobCheckResult,
+            super::blobs::BlobUploadInit, super::blobs::BlobUploadStatus,
+            schema::SetFlag, schema::WorkspaceMetadata, schema::SetWorkspaceMetadata,
+            super::Flags, jwst::BlockRef, schema::ExportTooLarge, schema::PropertyHistory,
+            schema::PropertyHistoryEntry, schema::WorkspaceState, super::CompactionStats,
+            schema::MutationRateLimit, super::MutationRateLimited, jwst::TimestampRepair
         )
     ),
     tags(
         (name = "Workspace", description = "Read and write remote workspace"),
-        (name = "Blocks", description = "Read and write remote blocks")
+        (name = "Blocks", description = "Read and write remote blocks"),
+        (name = "Blobs", description = "Read and write remote blobs")
     )
 )]
 struct ApiDoc;
@@ -96,6 +134,7 @@ fn doc_apis(router: Router) -> Router {
 fn block_apis(router: Router) -> Router {
     let block_operation = Router::new()
         .route("/history", get(block::get_block_history))
+        .route("/backlinks", get(block::get_block_backlinks))
         .route(
             "/children",
             get(block::get_block_children).post(block::insert_block_children),
@@ -115,6 +154,24 @@ fn block_apis(router: Router) -> Router {
 fn workspace_apis(router: Router) -> Router {
     router
         .route("/block/:workspace/client", get(workspace::workspace_client))
+        .route("/block/:workspace/state", get(workspace::workspace_state))
+        .route(
+            "/block/:workspace/presence",
+            get(workspace::workspace_presence),
+        )
+        .route("/block/:workspace/stats", get(workspace::workspace_size))
+        .route(
+            "/block/:workspace/metadata",
+            get(workspace::get_workspace_metadata).put(workspace::set_workspace_metadata),
+        )
+        .route(
+            "/workspace/:workspace/updates/batch",
+            post(workspace::apply_updates),
+        )
+        .route(
+            "/workspace/:workspace/flags",
+            get(workspace::workspace_flags),
+        )
         .route(
             "/block/:workspace/history",
             get(workspace::history_workspace_clients),
@@ -127,6 +184,7 @@ fn workspace_apis(router: Router) -> Router {
             "/block/:workspace",
             get(workspace::get_workspace)
                 .post(workspace::set_workspace)
+                .patch(workspace::patch_workspace)
                 .delete(workspace::delete_workspace),
         )
         .route(
@@ -136,6 +194,69 @@ fn workspace_apis(router: Router) -> Router {
         .route("/search/:workspace", get(workspace::workspace_search))
 }
 
+// not rate limited, admins have to be able to act on busy workspaces,
+// all of them need the admin token
+fn admin_apis(router: Router) -> Router {
+    router
+        .route(
+            "/admin/workspaces/:workspace/flags/:flag",
+            put(workspace::set_workspace_flag).delete(workspace::clear_workspace_flag),
+        )
+        .route(
+            "/admin/workspaces/:workspace/rate_limit",
+            get(workspace::get_mutation_limit)
+                .put(workspace::set_mutation_limit)
+                .delete(workspace::clear_mutation_limit),
+        )
+        .route(
+            "/admin/workspaces/:workspace/stats",
+            get(workspace::workspace_stats),
+        )
+        .route(
+            "/admin/workspaces/:workspace/timestamps",
+            get(workspace::validate_timestamps).post(workspace::repair_timestamps),
+        )
+        .route("/admin/compaction", get(workspace::compaction_stats))
+        .route(
+            "/admin/rate_limit",
+            get(workspace::get_default_mutation_limit).put(workspace::set_default_mutation_limit),
+        )
+        .route_layer(middleware::from_fn(super::authenticate_admin))
+}
+
+/// Reject the requests for relayed workspaces, see [RelayWorkspaces].
+/// - Return 409 Conflict if the workspace is relayed, its doc is not kept on the server.
+///
+/// [RelayWorkspaces]: jwst_rpc::RelayWorkspaces
+async fn reject_relayed<B>(
+    Extension(context): Extension<Arc<Context>>,
+    params: Option<Path<HashMap<String, String>>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if let Some(workspace) = params
+        .as_ref()
+        .and_then(|Path(params)| params.get("workspace"))
+    {
+        if context.relay.contains(workspace) {
+            return (
+                StatusCode::CONFLICT,
+                format!(
+                    "Workspace({workspace:?}) is in relay mode, \
+                     it's only synced between its clients and has no blocks on the server"
+                ),
+            )
+                .into_response();
+        }
+    }
+    next.run(req).await
+}
+
 pub fn blocks_apis(router: Router) -> Router {
-    workspace_apis(block_apis(router))
+    router.merge(
+        workspace_apis(block_apis(Router::new()))
+            .route_layer(middleware::from_fn(super::limits::limit_mutations))
+            .merge(admin_apis(Router::new()))
+            .route_layer(middleware::from_fn(reject_relayed)),
+    )
 }
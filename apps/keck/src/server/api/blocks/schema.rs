@@ -1,7 +1,12 @@
 pub use std::collections::HashMap;
 
-use serde::Deserialize;
+use jwst::{ContentStats, WorkspaceStats};
+use jwst_rpc::RateLimit;
+use jwst_storage::{PropertyChange, WorkspaceStorageStats};
+use lib0::any::Any;
+use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
+use yrs::StateVector;
 
 #[derive(Default, Deserialize, PartialEq, Debug, ToSchema)]
 pub struct Workspace {
@@ -24,12 +29,116 @@ pub struct Block {
     created: u64,
     #[serde(rename = "sys:children")]
     children: Vec<String>,
+    /// Link to the preview image blob, only returned when getting a single block.
+    thumbnail_url: Option<String>,
 }
 
 #[derive(Deserialize, PartialEq, Debug, ToSchema)]
 #[schema(example = json!([12345, 946684800000_u64, "add"]))]
 pub struct BlockRawHistory(u64, u64, String);
 
+#[derive(Serialize, PartialEq, Debug, ToSchema)]
+#[schema(example = json!({"clients": [{"client_id": 12345, "name": "alice", "cursor": {"x": 10, "y": 20}}]}))]
+pub struct Presence {
+    pub(super) clients: Vec<Collaborator>,
+}
+
+#[derive(Serialize, PartialEq, Debug, ToSchema)]
+pub struct Collaborator {
+    pub(super) client_id: u64,
+    pub(super) name: Option<String>,
+    #[schema(value_type = Object)]
+    pub(super) cursor: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct WorkspaceSize {
+    pub(super) content: ContentStats,
+    /// Stored updates, they are merged into one once there are too many.
+    pub(super) updates: u64,
+    pub(super) update_bytes: u64,
+    /// Seconds since the oldest stored update was written.
+    pub(super) snapshot_age: Option<u64>,
+    pub(super) blobs: u64,
+    pub(super) blob_bytes: u64,
+    /// Bytes sent to the clients of the workspace, sync traffic and blob downloads.
+    pub(super) bandwidth_sent: u64,
+    /// Bytes received from the sync connections of the workspace.
+    pub(super) bandwidth_received: u64,
+}
+
+impl From<WorkspaceStorageStats> for WorkspaceSize {
+    fn from(stats: WorkspaceStorageStats) -> Self {
+        Self {
+            content: stats.content,
+            updates: stats.updates,
+            update_bytes: stats.update_bytes,
+            snapshot_age: stats.snapshot_age.map(|age| age.as_secs()),
+            blobs: stats.blobs,
+            blob_bytes: stats.blob_bytes,
+            bandwidth_sent: stats.bandwidth.sent,
+            bandwidth_received: stats.bandwidth.received,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct AdminWorkspaceStats {
+    #[serde(flatten)]
+    pub(super) crdt: WorkspaceStats,
+    pub(super) size: WorkspaceSize,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct WorkspaceMetadata {
+    pub(super) name: Option<String>,
+    pub(super) avatar: Option<String>,
+    /// Changes whenever the name or avatar does, edits are conditional on it.
+    pub(super) revision: String,
+}
+
+impl From<jwst::WorkspaceMetadata> for WorkspaceMetadata {
+    fn from(metadata: jwst::WorkspaceMetadata) -> Self {
+        Self {
+            revision: metadata.revision(),
+            name: metadata.name,
+            avatar: metadata.avatar,
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetWorkspaceMetadata {
+    pub(super) name: Option<String>,
+    pub(super) avatar: Option<String>,
+    /// Revision the edit is based on, it's rejected if the metadata changed since.
+    /// Without it the edit always applies.
+    pub(super) revision: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetFlag {
+    pub enabled: bool,
+}
+
+/// Limit of the REST mutations of a workspace, a rate of 0 disables it.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct MutationRateLimit {
+    /// Mutations per second on average.
+    pub rate: f64,
+    /// Mutations accepted at once after being idle.
+    pub burst: u32,
+}
+
+impl From<RateLimit> for MutationRateLimit {
+    fn from(limit: RateLimit) -> Self {
+        Self {
+            rate: limit.rate,
+            burst: limit.burst,
+        }
+    }
+}
+
 #[derive(Deserialize, ToSchema)]
 #[schema(example = json!({"Push": "jwstRf4rMzua7E"}))]
 
@@ -39,3 +148,78 @@ pub enum InsertChildren {
     InsertAfter { id: String, after: String },
     InsertAt { id: String, pos: u32 },
 }
+
+#[derive(Serialize, ToSchema)]
+pub struct ExportTooLarge {
+    /// Blocks the export would contain.
+    pub blocks: usize,
+    pub max_blocks: usize,
+}
+
+/// Progress of resolving the history of a block property.
+#[derive(Serialize, ToSchema)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum PropertyHistory {
+    /// The stored updates are being replayed, ask again later.
+    Running,
+    Done {
+        history: Vec<PropertyHistoryEntry>,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+#[derive(Serialize, ToSchema)]
+#[schema(example = json!({
+    "seq": 42,
+    "timestamp": 946684800000_i64,
+    "clients": [12345],
+    "old": "draft",
+    "new": "final",
+}))]
+pub struct PropertyHistoryEntry {
+    /// Id of the stored update that changed the property.
+    pub(super) seq: i32,
+    /// Milliseconds since the epoch when the update was stored.
+    pub(super) timestamp: i64,
+    /// Clients that wrote the property, the same ids the workspace history reports.
+    pub(super) clients: Vec<u64>,
+    /// Value before the change, missing if the property was not set.
+    #[schema(value_type = Object)]
+    pub(super) old: Option<Any>,
+    #[schema(value_type = Object)]
+    pub(super) new: Option<Any>,
+}
+
+impl From<&PropertyChange> for PropertyHistoryEntry {
+    fn from(change: &PropertyChange) -> Self {
+        Self {
+            seq: change.seq,
+            timestamp: change.timestamp.timestamp_millis(),
+            clients: change.write.clients.clone(),
+            old: change.write.old.clone(),
+            new: change.write.new.clone(),
+        }
+    }
+}
+
+/// State vector of a workspace, by client id.
+#[derive(Serialize, ToSchema)]
+#[schema(example = json!({"state_vector": {"12345": 42}}))]
+pub struct WorkspaceState {
+    /// The clock each client reached, any difference to the state the client knows
+    /// means changes of others.
+    pub(super) state_vector: HashMap<u64, u32>,
+}
+
+impl From<&StateVector> for WorkspaceState {
+    fn from(state_vector: &StateVector) -> Self {
+        Self {
+            state_vector: state_vector
+                .iter()
+                .map(|(client, clock)| (*client, *clock))
+                .collect(),
+        }
+    }
+}
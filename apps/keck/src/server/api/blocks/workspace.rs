@@ -1,130 +1,1072 @@
 use super::*;
 use axum::{
+    body::{Bytes, StreamBody},
     extract::{Path, Query},
     http::header,
     response::Response,
 };
-use jwst::{parse_history, parse_history_client, DocStorage};
+use jwst::{
+    parse_history, parse_history_client, DocStorage, JwstError, JwstResult, MetadataError,
+    PatchError, PatchFailure, PatchOperation, SearchOptions, TimestampRepair,
+};
+use jwst_rpc::RateLimit;
+use jwst_storage::{BandwidthScope, WorkspaceStorageStats};
+use lib0::{
+    decoding::{Cursor, Read},
+    encoding::Write,
+};
+use std::time::Duration;
 use utoipa::IntoParams;
+use yrs::{
+    updates::{decoder::Decode, encoder::Encode},
+    ReadTxn, Transact, Update,
+};
+
+// clients without awareness changes for longer are not listed as collaborators
+const PRESENCE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+// blocks serialized between two yields of the workspace export
+const EXPORT_CHUNK_BLOCKS: usize = 256;
+
+#[derive(Deserialize, IntoParams)]
+pub struct UpdatesQuery {
+    /// `v2` if the updates are in the v2 encoding, only accepted if the `v2_encoding`
+    /// flag of the workspace is set. The response is always in the v1 encoding.
+    encoding: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct ExportQuery {
+    /// Only export this block and its descendants.
+    root: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct TimestampsQuery {
+    /// Creation time of the workspace in unix milliseconds, block timestamps before it are
+    /// broken.
+    created_at: i64,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct BlockListQuery {
+    /// `updated` to list the most recently updated blocks first, the doc order by default.
+    sort: Option<String>,
+}
 
 /// Get a exists `Workspace` by id
 /// - Return 200 Ok and `Workspace`'s data if `Workspace` is exists.
+///   The data is streamed a few blocks at a time, `root` limits it to a block tree.
+/// - Return 404 Not Found if `Workspace` or the `root` block not exists.
+/// - Return 413 Payload Too Large with the block count if the export has too many blocks.
+#[utoipa::path(
+    get,
+    tag = "Workspace",
+    context_path = "/api/block",
+    path = "/{workspace}",
+    params(
+        ("workspace", description = "workspace id"),
+        ExportQuery,
+    ),
+    responses(
+        (status = 200, description = "Get workspace data", body = Workspace),
+        (status = 404, description = "Workspace or root block not found"),
+        (status = 413, description = "Too many blocks to export", body = schema::ExportTooLarge),
+    )
+)]
+pub async fn get_workspace(
+    Extension(context): Extension<Arc<Context>>,
+    Path(ws_id): Path<String>,
+    Query(query): Query<ExportQuery>,
+) -> Response {
+    info!("get_workspace: {}", ws_id);
+    let Ok(workspace) = context.storage.get_workspace(&ws_id).await else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Workspace({ws_id:?}) not found"),
+        )
+            .into_response();
+    };
+    let export = match workspace.json_export(query.root.as_deref(), EXPORT_CHUNK_BLOCKS) {
+        Ok(export) => export,
+        Err(e) => return (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    };
+    if export.blocks() > context.export_max_blocks {
+        return (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(schema::ExportTooLarge {
+                blocks: export.blocks(),
+                max_blocks: context.export_max_blocks,
+            }),
+        )
+            .into_response();
+    }
+
+    // yield between chunks so a large export doesn't hold a runtime worker
+    let body = futures::stream::try_unfold(export, |mut export| async move {
+        tokio::task::yield_now().await;
+        Ok::<_, serde_json::Error>(export.next_chunk()?.map(|chunk| (chunk, export)))
+    });
+    (
+        [(header::CONTENT_TYPE, "application/json")],
+        StreamBody::new(body),
+    )
+        .into_response()
+}
+
+/// Create a `Workspace` by id
+/// - Return 200 Ok and `Workspace`'s data if init success or `Workspace` is exists.
+/// - Return 500 Internal Server Error if init failed.
+#[utoipa::path(
+    post,
+    tag = "Workspace",
+    context_path = "/api/block",
+    path = "/{workspace}",
+    params(
+        ("workspace", description = "workspace id"),
+    ),
+    responses(
+        (status = 200, description = "Return workspace data", body = Workspace),
+        (status = 500, description = "Failed to init a workspace")
+    )
+)]
+pub async fn set_workspace(
+    Extension(context): Extension<Arc<Context>>,
+    Path(workspace): Path<String>,
+) -> Response {
+    info!("set_workspace: {}", workspace);
+
+    match context.storage.create_workspace(workspace).await {
+        Ok(workspace) => Json(workspace).into_response(),
+        Err(e) => {
+            error!("Failed to init doc: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Apply a batch of updates to `Workspace`
+///
+/// The body is the concatenation of the updates, each prefixed with its length as a
+/// lib0 var uint, they are applied in a single transaction. The response has the same
+/// framing: the update that the batch effectively applied, then the state vector
+/// of the workspace afterwards.
+/// - Return 200 Ok and the delta and state vector.
+/// - Return 400 Bad Request if the body or an update is malformed, nothing is applied then,
+///   or if the encoding is not enabled for the workspace.
+/// - Return 404 Not Found if `Workspace` not exists.
+#[utoipa::path(
+    post,
+    tag = "Workspace",
+    context_path = "/api/workspace",
+    path = "/{workspace}/updates/batch",
+    params(
+        ("workspace", description = "workspace id"),
+        UpdatesQuery,
+    ),
+    request_body(
+        content = Vec<u8>,
+        description = "length prefixed updates",
+        content_type = "application/octet-stream"
+    ),
+    responses(
+        (status = 200, description = "Length prefixed delta and state vector", body = Vec<u8>),
+        (status = 400, description = "Malformed updates"),
+        (status = 404, description = "Workspace not found"),
+    )
+)]
+pub async fn apply_updates(
+    Extension(context): Extension<Arc<Context>>,
+    Path(ws_id): Path<String>,
+    Query(query): Query<UpdatesQuery>,
+    body: Bytes,
+) -> Response {
+    let mut updates = vec![];
+    let mut cursor = Cursor::new(&body);
+    while cursor.has_content() {
+        let Ok(update) = cursor.read_buf() else {
+            return (StatusCode::BAD_REQUEST, "malformed update frame").into_response();
+        };
+        updates.push(update.to_vec());
+    }
+    info!("apply_updates: {}, {} updates", ws_id, updates.len());
+
+    let Ok(workspace) = context.storage.get_workspace(&ws_id).await else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Workspace({ws_id:?}) not found"),
+        )
+            .into_response();
+    };
+    match query.encoding.as_deref() {
+        None | Some("v1") => {}
+        Some("v2") => {
+            match context.workspace_flags(&ws_id).await {
+                Ok(flags) if flags.v2_encoding => {}
+                Ok(_) => {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        "v2 encoded updates are not enabled for the workspace",
+                    )
+                        .into_response()
+                }
+                Err(e) => {
+                    error!("Failed to get flags of {}: {:?}", ws_id, e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            }
+            let decoded = updates
+                .iter()
+                .map(|update| Update::decode_v2(update).map(|update| update.encode_v1()))
+                .collect::<Result<Vec<_>, _>>();
+            match decoded {
+                Ok(decoded) => updates = decoded,
+                Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+            }
+        }
+        Some(encoding) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("unknown update encoding {encoding:?}"),
+            )
+                .into_response()
+        }
+    }
+    match workspace.apply_updates(&updates) {
+        Ok((delta, state_vector)) => {
+            if let Err(e) = context.storage.docs().write_update(ws_id, &delta).await {
+                error!("db write error: {}", e.to_string());
+            }
+
+            let mut response = vec![];
+            response.write_buf(delta);
+            response.write_buf(state_vector.encode_v1());
+            response.into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+/// Edit `Workspace` with JSON Patch style operations
+///
+/// For clients that can't sync the doc, the operations are turned into a change of the
+/// workspace that merges with the edits of the other clients, see [jwst::PatchOperation]
+/// for the paths. Either all operations are applied or none.
+/// The returned state vector changes when others edit the workspace, so the client can
+/// poll it to know when to fetch the workspace again.
+/// - Return 200 Ok and the state vector after the patch.
+/// - Return 400 Bad Request if an operation is invalid.
+/// - Return 403 Forbidden if an operation writes a `sys:` property.
+/// - Return 404 Not Found if `Workspace` not exists.
+/// - Return 409 Conflict if a `test` operation failed.
+#[utoipa::path(
+    patch,
+    tag = "Workspace",
+    context_path = "/api/block",
+    path = "/{workspace}",
+    params(
+        ("workspace", description = "workspace id"),
+    ),
+    request_body(
+        content = String,
+        description = "json patch operations",
+        content_type = "application/json"
+    ),
+    responses(
+        (status = 200, description = "Patch applied", body = schema::WorkspaceState),
+        (status = 400, description = "Invalid operation"),
+        (status = 403, description = "System properties can't be written"),
+        (status = 404, description = "Workspace not found"),
+        (status = 409, description = "Test operation failed"),
+    )
+)]
+pub async fn patch_workspace(
+    Extension(context): Extension<Arc<Context>>,
+    Path(ws_id): Path<String>,
+    Json(operations): Json<Vec<PatchOperation>>,
+) -> Response {
+    info!(
+        "patch_workspace: {}, {} operations",
+        ws_id,
+        operations.len()
+    );
+    let Ok(workspace) = context.storage.get_workspace(&ws_id).await else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Workspace({ws_id:?}) not found"),
+        )
+            .into_response();
+    };
+    match workspace.apply_patch(&operations) {
+        Ok((update, state_vector)) => {
+            if let Err(e) = context.storage.docs().write_update(ws_id, &update).await {
+                error!("db write error: {}", e.to_string());
+            }
+            Json(schema::WorkspaceState::from(&state_vector)).into_response()
+        }
+        Err(
+            e @ PatchError::Operation {
+                reason: PatchFailure::TestFailed,
+                ..
+            },
+        ) => (StatusCode::CONFLICT, e.to_string()).into_response(),
+        Err(
+            e @ PatchError::Operation {
+                reason: PatchFailure::Rejected(JwstError::SystemKeyDenied(_)),
+                ..
+            },
+        ) => (StatusCode::FORBIDDEN, e.to_string()).into_response(),
+        Err(e @ PatchError::Operation { .. }) => {
+            (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+        }
+    }
+}
+
+/// Delete a exists `Workspace` by id
+/// - Return 204 No Content if delete successful.
+/// - Return 404 Not Found if `Workspace` not exists.
+/// - Return 500 Internal Server Error if delete failed.
+#[utoipa::path(
+    delete,
+    tag = "Workspace",
+    context_path = "/api/block",
+    path = "/{workspace}",
+    params(
+        ("workspace", description = "workspace id"),
+    ),
+    responses(
+        (status = 204, description = "Workspace data deleted"),
+        (status = 404, description = "Workspace not exists"),
+        (status = 500, description = "Failed to delete workspace")
+    )
+)]
+pub async fn delete_workspace(
+    Extension(context): Extension<Arc<Context>>,
+    Path(workspace): Path<String>,
+) -> Response {
+    info!("delete_workspace: {}", workspace);
+    if context.storage.docs().delete(workspace).await.is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Get current client id of server
+///
+/// When the server initializes or get the `Workspace`, a `Client` will be created. This `Client` will not be destroyed until the server restarts.
+/// Therefore, the `Client ID` in the history generated by modifying `Block` through HTTP API will remain unchanged until the server restarts.
+///
+/// This interface return the client id that server will used.
+#[utoipa::path(
+    get,
+    tag = "Workspace",
+    context_path = "/api/block",
+    path = "/{workspace}/client",
+    params(
+        ("workspace", description = "workspace id"),
+    ),
+    responses(
+        (status = 200, description = "Get workspace client id", body = u64),
+        (status = 404, description = "Workspace not found")
+    )
+)]
+pub async fn workspace_client(
+    Extension(context): Extension<Arc<Context>>,
+    Path(ws_id): Path<String>,
+) -> Response {
+    if let Ok(workspace) = context.storage.get_workspace(&ws_id).await {
+        Json(workspace.client_id()).into_response()
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            format!("Workspace({ws_id:?}) not found"),
+        )
+            .into_response()
+    }
+}
+
+/// Get current state vector of `Workspace`
+///
+/// Clients editing the workspace with patches poll this to notice the changes of others,
+/// it differs from the state vector of their last patch once someone else edited it.
+/// - Return 200 Ok and the state vector.
+/// - Return 404 Not Found if `Workspace` not exists.
+#[utoipa::path(
+    get,
+    tag = "Workspace",
+    context_path = "/api/block",
+    path = "/{workspace}/state",
+    params(
+        ("workspace", description = "workspace id"),
+    ),
+    responses(
+        (status = 200, description = "Get workspace state vector", body = schema::WorkspaceState),
+        (status = 404, description = "Workspace not found")
+    )
+)]
+pub async fn workspace_state(
+    Extension(context): Extension<Arc<Context>>,
+    Path(ws_id): Path<String>,
+) -> Response {
+    if let Ok(workspace) = context.storage.get_workspace(&ws_id).await {
+        let state_vector = workspace.doc().transact().state_vector();
+        Json(schema::WorkspaceState::from(&state_vector)).into_response()
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            format!("Workspace({ws_id:?}) not found"),
+        )
+            .into_response()
+    }
+}
+
+/// Get CRDT stats of `Workspace`
+///
+/// Shows the state vector, pending updates and gc status of the workspace,
+/// used to diagnose clients that can't finish syncing, along with the size of the workspace.
+/// - Return 200 Ok and the stats.
+/// - Return 404 Not Found if `Workspace` not exists.
+#[utoipa::path(
+    get,
+    tag = "Workspace",
+    context_path = "/api/admin/workspaces",
+    path = "/{workspace}/stats",
+    params(
+        ("workspace", description = "workspace id"),
+    ),
+    responses(
+        (status = 200, description = "Get workspace stats", body = AdminWorkspaceStats),
+        (status = 404, description = "Workspace not found"),
+        (status = 500, description = "Failed to collect workspace size")
+    )
+)]
+pub async fn workspace_stats(
+    Extension(context): Extension<Arc<Context>>,
+    Path(ws_id): Path<String>,
+) -> Response {
+    info!("workspace_stats: {}", ws_id);
+    if let Ok(workspace) = context.storage.get_workspace(&ws_id).await {
+        let crdt = workspace.with_trx(|t| workspace.stats(&t.trx));
+        match storage_stats(&context, &ws_id).await {
+            Ok(size) => Json(schema::AdminWorkspaceStats {
+                crdt,
+                size: size.into(),
+            })
+            .into_response(),
+            Err(e) => {
+                error!("Failed to collect size of {}: {:?}", ws_id, e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            format!("Workspace({ws_id:?}) not found"),
+        )
+            .into_response()
+    }
+}
+
+/// Get the compactions of the stored workspace updates
+///
+/// Open workspaces are compacted in the background once their stored updates grow past
+/// the configured thresholds, see [CompactionConfig](super::CompactionConfig).
+/// - Return 200 Ok and the compactions performed since the server started.
+#[utoipa::path(
+    get,
+    tag = "Workspace",
+    context_path = "/api/admin",
+    path = "/compaction",
+    responses(
+        (status = 200, description = "Get compaction stats", body = CompactionStats)
+    )
+)]
+pub async fn compaction_stats(Extension(context): Extension<Arc<Context>>) -> Response {
+    Json(context.compaction_stats()).into_response()
+}
+
+/// Get size of `Workspace`
+///
+/// Counts the blocks of the workspace by flavour, the depth of the block tree,
+/// what is stored for the workspace: updates and blobs, and the traffic with its clients.
+/// The block counts are cached for a short while when the workspace doesn't change.
+/// - Return 200 Ok and the stats.
+/// - Return 404 Not Found if `Workspace` not exists.
+#[utoipa::path(
+    get,
+    tag = "Workspace",
+    context_path = "/api/block",
+    path = "/{workspace}/stats",
+    params(
+        ("workspace", description = "workspace id"),
+    ),
+    responses(
+        (status = 200, description = "Get workspace size", body = WorkspaceSize),
+        (status = 404, description = "Workspace not found"),
+        (status = 500, description = "Failed to collect workspace size")
+    )
+)]
+pub async fn workspace_size(
+    Extension(context): Extension<Arc<Context>>,
+    Path(ws_id): Path<String>,
+) -> Response {
+    info!("workspace_size: {}", ws_id);
+    match storage_stats(&context, &ws_id).await {
+        Ok(stats) => Json(schema::WorkspaceSize::from(stats)).into_response(),
+        Err(JwstError::WorkspaceNotFound(_)) => (
+            StatusCode::NOT_FOUND,
+            format!("Workspace({ws_id:?}) not found"),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Failed to collect size of {}: {:?}", ws_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+// includes the traffic that isn't flushed to the storage yet
+async fn storage_stats(context: &Context, ws_id: &str) -> JwstResult<WorkspaceStorageStats> {
+    let mut stats = context.storage.workspace_stats(ws_id).await?;
+    stats.bandwidth = stats.bandwidth
+        + context
+            .bandwidth
+            .pending(&BandwidthScope::Workspace(ws_id.into()));
+    Ok(stats)
+}
+
+// flags can only be read and changed for existing workspaces
+async fn workspace_missing(context: &Context, ws_id: &str) -> Option<Response> {
+    match context.storage.docs().exists(ws_id.into()).await {
+        Ok(true) => None,
+        Ok(false) => Some(
+            (
+                StatusCode::NOT_FOUND,
+                format!("Workspace({ws_id:?}) not found"),
+            )
+                .into_response(),
+        ),
+        Err(e) => {
+            error!("Failed to check workspace {}: {:?}", ws_id, e);
+            Some(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+/// Get feature flags of `Workspace`
+///
+/// Flags are set by the server admins and gate features that are rolled out gradually,
+/// clients can only read them.
+/// - Return 200 Ok and the flags, flags that are not set are `false`.
+/// - Return 404 Not Found if `Workspace` not exists.
+#[utoipa::path(
+    get,
+    tag = "Workspace",
+    context_path = "/api/workspace",
+    path = "/{workspace}/flags",
+    params(
+        ("workspace", description = "workspace id"),
+    ),
+    responses(
+        (status = 200, description = "Get workspace flags", body = Flags),
+        (status = 404, description = "Workspace not found"),
+        (status = 500, description = "Failed to get workspace flags")
+    )
+)]
+pub async fn workspace_flags(
+    Extension(context): Extension<Arc<Context>>,
+    Path(ws_id): Path<String>,
+) -> Response {
+    info!("workspace_flags: {}", ws_id);
+    if let Some(resp) = workspace_missing(&context, &ws_id).await {
+        return resp;
+    }
+    match context.workspace_flags(&ws_id).await {
+        Ok(flags) => Json(flags).into_response(),
+        Err(e) => {
+            error!("Failed to get flags of {}: {:?}", ws_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Set a feature flag of `Workspace`
+/// - Return 204 No Content if the flag was set.
+/// - Return 404 Not Found if `Workspace` not exists.
+#[utoipa::path(
+    put,
+    tag = "Workspace",
+    context_path = "/api/admin/workspaces",
+    path = "/{workspace}/flags/{flag}",
+    params(
+        ("workspace", description = "workspace id"),
+        ("flag", description = "flag name"),
+    ),
+    request_body(
+        content = SetFlag,
+        description = "json",
+        content_type = "application/json"
+    ),
+    responses(
+        (status = 204, description = "Flag set"),
+        (status = 404, description = "Workspace not found"),
+        (status = 500, description = "Failed to set the flag")
+    )
+)]
+pub async fn set_workspace_flag(
+    Extension(context): Extension<Arc<Context>>,
+    Path((ws_id, flag)): Path<(String, String)>,
+    Json(payload): Json<schema::SetFlag>,
+) -> Response {
+    info!("set_workspace_flag: {}, {}", ws_id, flag);
+    if let Some(resp) = workspace_missing(&context, &ws_id).await {
+        return resp;
+    }
+    match context
+        .set_workspace_flag(&ws_id, &flag, payload.enabled)
+        .await
+    {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            error!("Failed to set flag {} of {}: {:?}", flag, ws_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Clear a feature flag of `Workspace`, it's `false` afterwards
+/// - Return 204 No Content if the flag was cleared.
+/// - Return 404 Not Found if the flag was not set.
+#[utoipa::path(
+    delete,
+    tag = "Workspace",
+    context_path = "/api/admin/workspaces",
+    path = "/{workspace}/flags/{flag}",
+    params(
+        ("workspace", description = "workspace id"),
+        ("flag", description = "flag name"),
+    ),
+    responses(
+        (status = 204, description = "Flag cleared"),
+        (status = 404, description = "Flag not set"),
+        (status = 500, description = "Failed to clear the flag")
+    )
+)]
+pub async fn clear_workspace_flag(
+    Extension(context): Extension<Arc<Context>>,
+    Path((ws_id, flag)): Path<(String, String)>,
+) -> Response {
+    info!("clear_workspace_flag: {}, {}", ws_id, flag);
+    match context.clear_workspace_flag(&ws_id, &flag).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            error!("Failed to clear flag {} of {}: {:?}", flag, ws_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Find the broken block timestamps of `Workspace`
+///
+/// Block creation times and history entries written by clients with broken clocks, before
+/// the creation of the workspace or far in the future, which break the sort by update time.
+/// - Return 200 Ok and the broken timestamps, with the value a repair replaces them with.
+/// - Return 404 Not Found if `Workspace` not exists.
+#[utoipa::path(
+    get,
+    tag = "Workspace",
+    context_path = "/api/admin/workspaces",
+    path = "/{workspace}/timestamps",
+    params(
+        ("workspace", description = "workspace id"),
+        TimestampsQuery,
+    ),
+    responses(
+        (status = 200, description = "Broken timestamps", body = [TimestampRepair]),
+        (status = 404, description = "Workspace not found"),
+    )
+)]
+pub async fn validate_timestamps(
+    Extension(context): Extension<Arc<Context>>,
+    Path(ws_id): Path<String>,
+    Query(query): Query<TimestampsQuery>,
+) -> Response {
+    info!("validate_timestamps: {}", ws_id);
+    match context.storage.get_workspace(&ws_id).await {
+        Ok(workspace) => {
+            let found =
+                workspace.with_trx(|t| workspace.validate_timestamps(&t.trx, query.created_at));
+            Json(found).into_response()
+        }
+        Err(_) => (
+            StatusCode::NOT_FOUND,
+            format!("Workspace({ws_id:?}) not found"),
+        )
+            .into_response(),
+    }
+}
+
+/// Repair the broken block timestamps of `Workspace`
+///
+/// Replace the timestamps found by the validation with the creation time of the workspace.
+/// - Return 200 Ok and the replaced timestamps with their original values.
 /// - Return 404 Not Found if `Workspace` not exists.
 #[utoipa::path(
-    get,
+    post,
     tag = "Workspace",
-    context_path = "/api/block",
-    path = "/{workspace}",
+    context_path = "/api/admin/workspaces",
+    path = "/{workspace}/timestamps",
     params(
         ("workspace", description = "workspace id"),
+        TimestampsQuery,
     ),
     responses(
-        (status = 200, description = "Get workspace data", body = Workspace),
-        (status = 404, description = "Workspace not found")
+        (status = 200, description = "Repaired timestamps", body = [TimestampRepair]),
+        (status = 404, description = "Workspace not found"),
     )
 )]
-pub async fn get_workspace(
+pub async fn repair_timestamps(
     Extension(context): Extension<Arc<Context>>,
     Path(ws_id): Path<String>,
+    Query(query): Query<TimestampsQuery>,
 ) -> Response {
-    info!("get_workspace: {}", ws_id);
-    if let Ok(workspace) = context.storage.get_workspace(&ws_id).await {
-        Json(workspace).into_response()
-    } else {
-        (
+    info!("repair_timestamps: {}", ws_id);
+    match context.storage.get_workspace(&ws_id).await {
+        Ok(workspace) => {
+            let (repaired, update) = workspace.with_trx(|mut t| {
+                let repaired = workspace.repair_timestamps(&mut t.trx, query.created_at);
+                (repaired, t.trx.encode_update_v1())
+            });
+            if !repaired.is_empty() {
+                if let Err(e) = context.storage.docs().write_update(ws_id, &update).await {
+                    error!("db write error: {}", e.to_string());
+                }
+            }
+            Json(repaired).into_response()
+        }
+        Err(_) => (
             StatusCode::NOT_FOUND,
             format!("Workspace({ws_id:?}) not found"),
         )
-            .into_response()
+            .into_response(),
     }
 }
 
-/// Create a `Workspace` by id
-/// - Return 200 Ok and `Workspace`'s data if init success or `Workspace` is exists.
-/// - Return 500 Internal Server Error if init failed.
+/// Get the mutation rate limit of `Workspace`
+///
+/// Requests changing the blocks or blobs of a workspace faster are rejected with 429 Too Many
+/// Requests, sync connections are not limited.
+/// - Return 200 Ok and the limit, the default one if none is set for the workspace.
 #[utoipa::path(
-    post,
+    get,
     tag = "Workspace",
-    context_path = "/api/block",
-    path = "/{workspace}",
+    context_path = "/api/admin/workspaces",
+    path = "/{workspace}/rate_limit",
     params(
         ("workspace", description = "workspace id"),
     ),
     responses(
-        (status = 200, description = "Return workspace data", body = Workspace),
-        (status = 500, description = "Failed to init a workspace")
+        (status = 200, description = "Get workspace rate limit", body = MutationRateLimit),
+        (status = 500, description = "Failed to get the rate limit")
     )
 )]
-pub async fn set_workspace(
+pub async fn get_mutation_limit(
     Extension(context): Extension<Arc<Context>>,
-    Path(workspace): Path<String>,
+    Path(ws_id): Path<String>,
 ) -> Response {
-    info!("set_workspace: {}", workspace);
+    info!("get_mutation_limit: {}", ws_id);
+    match context.mutation_limit(&ws_id).await {
+        Ok(limit) => Json(schema::MutationRateLimit::from(limit)).into_response(),
+        Err(e) => {
+            error!("Failed to get rate limit of {}: {:?}", ws_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
 
-    match context.storage.create_workspace(workspace).await {
-        Ok(workspace) => Json(workspace).into_response(),
+/// Set the mutation rate limit of `Workspace`, it applies to the next request
+/// - Return 204 No Content if the limit was set.
+/// - Return 400 Bad Request if the limit is invalid.
+#[utoipa::path(
+    put,
+    tag = "Workspace",
+    context_path = "/api/admin/workspaces",
+    path = "/{workspace}/rate_limit",
+    params(
+        ("workspace", description = "workspace id"),
+    ),
+    request_body(
+        content = MutationRateLimit,
+        description = "json",
+        content_type = "application/json"
+    ),
+    responses(
+        (status = 204, description = "Rate limit set"),
+        (status = 400, description = "Invalid rate limit"),
+        (status = 500, description = "Failed to set the rate limit")
+    )
+)]
+pub async fn set_mutation_limit(
+    Extension(context): Extension<Arc<Context>>,
+    Path(ws_id): Path<String>,
+    Json(payload): Json<schema::MutationRateLimit>,
+) -> Response {
+    info!("set_mutation_limit: {}", ws_id);
+    let limit = match RateLimit::new(payload.rate, payload.burst) {
+        Ok(limit) => limit,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    match context.set_mutation_limit(&ws_id, Some(limit)).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
         Err(e) => {
-            error!("Failed to init doc: {}", e);
+            error!("Failed to set rate limit of {}: {:?}", ws_id, e);
             StatusCode::INTERNAL_SERVER_ERROR.into_response()
         }
     }
 }
 
-/// Delete a exists `Workspace` by id
-/// - Return 204 No Content if delete successful.
-/// - Return 404 Not Found if `Workspace` not exists.
-/// - Return 500 Internal Server Error if delete failed.
+/// Clear the mutation rate limit of `Workspace`, the default one applies afterwards
+/// - Return 204 No Content.
 #[utoipa::path(
     delete,
     tag = "Workspace",
-    context_path = "/api/block",
-    path = "/{workspace}",
+    context_path = "/api/admin/workspaces",
+    path = "/{workspace}/rate_limit",
     params(
         ("workspace", description = "workspace id"),
     ),
     responses(
-        (status = 204, description = "Workspace data deleted"),
-        (status = 404, description = "Workspace not exists"),
-        (status = 500, description = "Failed to delete workspace")
+        (status = 204, description = "Rate limit cleared"),
+        (status = 500, description = "Failed to clear the rate limit")
     )
 )]
-pub async fn delete_workspace(
+pub async fn clear_mutation_limit(
     Extension(context): Extension<Arc<Context>>,
-    Path(workspace): Path<String>,
+    Path(ws_id): Path<String>,
 ) -> Response {
-    info!("delete_workspace: {}", workspace);
-    if context.storage.docs().delete(workspace).await.is_err() {
-        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-    };
+    info!("clear_mutation_limit: {}", ws_id);
+    match context.set_mutation_limit(&ws_id, None).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            error!("Failed to clear rate limit of {}: {:?}", ws_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Get the default mutation rate limit
+///
+/// The limit of the workspaces without one of their own.
+/// - Return 200 Ok and the limit.
+#[utoipa::path(
+    get,
+    tag = "Workspace",
+    context_path = "/api/admin",
+    path = "/rate_limit",
+    responses(
+        (status = 200, description = "Get default rate limit", body = MutationRateLimit)
+    )
+)]
+pub async fn get_default_mutation_limit(Extension(context): Extension<Arc<Context>>) -> Response {
+    Json(schema::MutationRateLimit::from(
+        context.mutation_limits.default_limit(),
+    ))
+    .into_response()
+}
 
+/// Set the default mutation rate limit, it applies to the next request
+///
+/// The limit is kept until the server restarts, `KECK_MUTATION_RATE_LIMIT` applies again
+/// afterwards.
+/// - Return 204 No Content if the limit was set.
+/// - Return 400 Bad Request if the limit is invalid.
+#[utoipa::path(
+    put,
+    tag = "Workspace",
+    context_path = "/api/admin",
+    path = "/rate_limit",
+    request_body(
+        content = MutationRateLimit,
+        description = "json",
+        content_type = "application/json"
+    ),
+    responses(
+        (status = 204, description = "Default rate limit set"),
+        (status = 400, description = "Invalid rate limit")
+    )
+)]
+pub async fn set_default_mutation_limit(
+    Extension(context): Extension<Arc<Context>>,
+    Json(payload): Json<schema::MutationRateLimit>,
+) -> Response {
+    let limit = match RateLimit::new(payload.rate, payload.burst) {
+        Ok(limit) => limit,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    info!("set default mutation rate limit: {}", limit);
+    context.mutation_limits.set_default_limit(limit);
     StatusCode::NO_CONTENT.into_response()
 }
 
-/// Get current client id of server
+/// Get the metadata of `Workspace`
+/// - Return 200 Ok and the name and avatar with their revision.
+/// - Return 404 Not Found if `Workspace` not exists.
+#[utoipa::path(
+    get,
+    tag = "Workspace",
+    context_path = "/api/block",
+    path = "/{workspace}/metadata",
+    params(
+        ("workspace", description = "workspace id"),
+    ),
+    responses(
+        (status = 200, description = "Get workspace metadata", body = WorkspaceMetadata),
+        (status = 404, description = "Workspace not found")
+    )
+)]
+pub async fn get_workspace_metadata(
+    Extension(context): Extension<Arc<Context>>,
+    Path(ws_id): Path<String>,
+) -> Response {
+    info!("get_workspace_metadata: {}", ws_id);
+    if let Some(resp) = workspace_missing(&context, &ws_id).await {
+        return resp;
+    }
+    match context.storage.get_workspace(&ws_id).await {
+        Ok(workspace) => {
+            Json(schema::WorkspaceMetadata::from(workspace.metadata())).into_response()
+        }
+        Err(e) => {
+            error!("Failed to get workspace {}: {:?}", ws_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Change the name or avatar of `Workspace`
 ///
-/// When the server initializes or get the `Workspace`, a `Client` will be created. This `Client` will not be destroyed until the server restarts.
-/// Therefore, the `Client ID` in the history generated by modifying `Block` through HTTP API will remain unchanged until the server restarts.
+/// With `revision`, the edit only applies if the metadata is still at this revision,
+/// so concurrent edits can't overwrite each other unnoticed.
+/// - Return 200 Ok and the metadata afterwards.
+/// - Return 400 Bad Request if neither the name nor the avatar is set.
+/// - Return 404 Not Found if `Workspace` not exists.
+/// - Return 409 Conflict and the current metadata if it changed since `revision`.
+#[utoipa::path(
+    put,
+    tag = "Workspace",
+    context_path = "/api/block",
+    path = "/{workspace}/metadata",
+    params(
+        ("workspace", description = "workspace id"),
+    ),
+    request_body(
+        content = SetWorkspaceMetadata,
+        description = "json",
+        content_type = "application/json"
+    ),
+    responses(
+        (status = 200, description = "Metadata changed", body = WorkspaceMetadata),
+        (status = 400, description = "Nothing to change"),
+        (status = 404, description = "Workspace not found"),
+        (status = 409, description = "Metadata changed since the revision", body = WorkspaceMetadata)
+    )
+)]
+pub async fn set_workspace_metadata(
+    Extension(context): Extension<Arc<Context>>,
+    Path(ws_id): Path<String>,
+    Json(payload): Json<schema::SetWorkspaceMetadata>,
+) -> Response {
+    info!("set_workspace_metadata: {}", ws_id);
+    if payload.name.is_none() && payload.avatar.is_none() {
+        return (StatusCode::BAD_REQUEST, "name or avatar is required").into_response();
+    }
+    if let Some(resp) = workspace_missing(&context, &ws_id).await {
+        return resp;
+    }
+    let workspace = match context.storage.get_workspace(&ws_id).await {
+        Ok(workspace) => workspace,
+        Err(e) => {
+            error!("Failed to get workspace {}: {:?}", ws_id, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    // both edits share the transaction, the revision is checked by the first one
+    let result: Result<_, MetadataError> = workspace.with_trx(|mut t| {
+        let mut revision = payload.revision.as_deref();
+        let mut metadata = None;
+        if let Some(name) = &payload.name {
+            metadata = Some(t.set_name(name, revision.take())?);
+        }
+        if let Some(avatar) = &payload.avatar {
+            metadata = Some(t.set_avatar(avatar, revision)?);
+        }
+        Ok((
+            metadata.expect("name or avatar is set"),
+            t.trx.encode_update_v1(),
+        ))
+    });
+    match result {
+        Ok((metadata, update)) => {
+            if let Err(e) = context.storage.docs().write_update(ws_id, &update).await {
+                error!("db write error: {}", e.to_string());
+            }
+            Json(schema::WorkspaceMetadata::from(metadata)).into_response()
+        }
+        Err(MetadataError::Conflict { current, .. }) => (
+            StatusCode::CONFLICT,
+            Json(schema::WorkspaceMetadata::from(current)),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Failed to set metadata of {}: {:?}", ws_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Get active collaborators of `Workspace`
 ///
-/// This interface return the client id that server will used.
+/// Return clients that changed their awareness state within the past 5 minutes,
+/// with the `user.name` and `cursor` fields of their state.
+/// - Return 200 Ok and the collaborators.
+/// - Return 404 Not Found if `Workspace` not exists.
 #[utoipa::path(
     get,
     tag = "Workspace",
     context_path = "/api/block",
-    path = "/{workspace}/client",
+    path = "/{workspace}/presence",
     params(
         ("workspace", description = "workspace id"),
     ),
     responses(
-        (status = 200, description = "Get workspace client id", body = u64),
+        (status = 200, description = "Get active collaborators", body = Presence),
         (status = 404, description = "Workspace not found")
     )
 )]
-pub async fn workspace_client(
+pub async fn workspace_presence(
     Extension(context): Extension<Arc<Context>>,
     Path(ws_id): Path<String>,
 ) -> Response {
+    info!("workspace_presence: {}", ws_id);
     if let Ok(workspace) = context.storage.get_workspace(&ws_id).await {
-        Json(workspace.client_id()).into_response()
+        let mut clients = workspace
+            .active_awareness_states(PRESENCE_TIMEOUT)
+            .into_iter()
+            .map(|(client_id, state)| schema::Collaborator {
+                client_id,
+                name: state
+                    .pointer("/user/name")
+                    .and_then(|name| name.as_str())
+                    .map(ToOwned::to_owned),
+                cursor: state.get("cursor").cloned(),
+            })
+            .collect::<Vec<_>>();
+        clients.sort_by_key(|client| client.client_id);
+
+        Json(schema::Presence { clients }).into_response()
     } else {
         (
             StatusCode::NOT_FOUND,
@@ -140,6 +1082,42 @@ pub async fn workspace_client(
 pub struct BlockSearchQuery {
     /// Search by title and text.
     query: String,
+    /// Only blocks of these flavors, comma separated.
+    flavors: Option<String>,
+    /// Only blocks created at or after this timestamp (ms).
+    created_after: Option<u64>,
+    /// Only blocks created at or before this timestamp (ms).
+    created_before: Option<u64>,
+    /// Only blocks last updated at or after this timestamp (ms).
+    updated_after: Option<u64>,
+    /// Only blocks last updated at or before this timestamp (ms).
+    updated_before: Option<u64>,
+    /// Only this block and its descendants.
+    root: Option<String>,
+}
+
+impl From<BlockSearchQuery> for SearchOptions {
+    fn from(query: BlockSearchQuery) -> Self {
+        Self {
+            query: query.query,
+            flavors: query
+                .flavors
+                .map(|flavors| {
+                    flavors
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|flavor| !flavor.is_empty())
+                        .map(ToOwned::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            created_after: query.created_after,
+            created_before: query.created_before,
+            updated_after: query.updated_after,
+            updated_before: query.updated_before,
+            root: query.root,
+        }
+    }
 }
 
 /// Search workspace blocks of server
@@ -161,14 +1139,14 @@ pub struct BlockSearchQuery {
 pub async fn workspace_search(
     Extension(context): Extension<Arc<Context>>,
     Path(ws_id): Path<String>,
-    query: Query<BlockSearchQuery>,
+    Query(query): Query<BlockSearchQuery>,
 ) -> Response {
-    let query_text = &query.query;
-    info!("workspace_search: {ws_id:?} query = {query_text:?}");
+    let options = SearchOptions::from(query);
+    info!("workspace_search: {ws_id:?} options = {options:?}");
     if let Ok(mut workspace) = context.storage.get_workspace(&ws_id).await {
-        match workspace.search(query_text) {
+        match workspace.search(options.clone()) {
             Ok(list) => {
-                debug!("workspace_search: {ws_id:?} query = {query_text:?}; {list:#?}");
+                debug!("workspace_search: {ws_id:?} options = {options:?}; {list:#?}");
                 Json(list).into_response()
             }
             Err(err) => {
@@ -195,10 +1173,12 @@ pub async fn workspace_search(
     path = "/{workspace}/blocks",
     params(
         ("workspace", description = "workspace id"),
-        Pagination
+        Pagination,
+        BlockListQuery
     ),
     responses(
         (status = 200, description = "Get Blocks", body = PageData<[Block]>),
+        (status = 400, description = "Unknown sort option"),
         (status = 404, description = "Workspace or block not found"),
     )
 )]
@@ -206,16 +1186,32 @@ pub async fn get_workspace_block(
     Extension(context): Extension<Arc<Context>>,
     Path(ws_id): Path<String>,
     Query(pagination): Query<Pagination>,
+    Query(query): Query<BlockListQuery>,
 ) -> Response {
     let Pagination { offset, limit } = pagination;
     info!("get_workspace_block: {ws_id:?}");
+    let sort_by_updated = match query.sort.as_deref() {
+        None => false,
+        Some("updated") => true,
+        Some(sort) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("Unknown sort option {sort:?}"),
+            )
+                .into_response()
+        }
+    };
     if let Ok(workspace) = context.storage.get_workspace(&ws_id).await {
         let total = workspace.block_count() as usize;
 
         let data = workspace.with_trx(|t| {
-            workspace.blocks(&t.trx, |blocks| {
-                blocks.skip(offset).take(limit).collect::<Vec<_>>()
-            })
+            if sort_by_updated {
+                workspace.blocks_sorted_by_updated(&t.trx, offset, limit)
+            } else {
+                workspace.blocks(&t.trx, |blocks| {
+                    blocks.skip(offset).take(limit).collect::<Vec<_>>()
+                })
+            }
         });
 
         let status = if data.is_empty() {
@@ -321,20 +1317,48 @@ pub async fn history_workspace(
     }
 }
 
-#[cfg(all(test, feature = "sqlite"))]
+#[cfg(test)]
 mod test {
     use super::*;
+    use axum_test_helper::TestClient;
 
-    #[tokio::test]
-    async fn workspace() {
-        use axum_test_helper::TestClient;
+    async fn test_client() -> (Arc<Context>, TestClient) {
+        let storage = JwstStorage::new("sqlite::memory:").await.unwrap();
+        let context = Arc::new(Context::new(Some(storage)).await);
+
+        let app = super::workspace_apis(Router::new()).layer(Extension(context.clone()));
+
+        (context, TestClient::new(app))
+    }
+
+    // the workspace as stored, the changes only made in memory are lost on restart
+    async fn stored_workspace(context: &Context, id: &str) -> jwst::Workspace {
+        let workspace = jwst::Workspace::new(id);
+        let updates = context.storage.stored_updates(id).await.unwrap();
+        workspace.apply_updates(&updates).unwrap();
+        workspace
+    }
+
+    // y-sync awareness message setting the state of a client
+    fn awareness_message(client: u64, state: &str) -> Vec<u8> {
+        use lib0::encoding::Write;
 
-        let pool = DbPool::init_memory_pool().await.unwrap();
-        let context = Arc::new(Context::new(Some(pool)).await);
+        let mut update = vec![];
+        update.write_var(1u32);
+        update.write_var(client);
+        update.write_var(1u32);
+        update.write_string(state);
 
-        let app = super::workspace_apis(Router::new()).layer(Extension(context));
+        let mut message = vec![];
+        // message type: awareness
+        message.write_var(1u32);
+        message.write_buf(update);
+        message
+    }
 
-        let client = TestClient::new(app);
+    #[tokio::test]
+    async fn workspace() {
+        let (_, client) = test_client().await;
 
         let resp = client.post("/block/test").send().await;
 
@@ -351,4 +1375,353 @@ mod test {
             schema::Workspace::default()
         );
     }
+
+    #[tokio::test]
+    async fn workspace_presence() {
+        let (context, client) = test_client().await;
+
+        let resp = client.get("/block/test/presence").send().await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let mut workspace = context.storage.create_workspace("test").await.unwrap();
+        workspace.sync_decode_message(&awareness_message(
+            7,
+            r#"{"user":{"name":"alice"},"cursor":{"x":10,"y":20}}"#,
+        ));
+        // an empty state is not a collaborator
+        workspace.sync_decode_message(&awareness_message(8, "{}"));
+
+        let resp = client.get("/block/test/presence").send().await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.json::<serde_json::Value>().await,
+            serde_json::json!({
+                "clients": [{"client_id": 7, "name": "alice", "cursor": {"x": 10, "y": 20}}]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn workspace_flags() {
+        use lib0::encoding::Write;
+        use yrs::{Doc, GetString, StateVector, Text};
+
+        let storage = JwstStorage::new("sqlite::memory:").await.unwrap();
+        let mut context = Context::new(Some(storage)).await;
+        context.admin_token = Some("admin".into());
+        let context = Arc::new(context);
+        let client = TestClient::new(blocks_apis(Router::new()).layer(Extension(context.clone())));
+        context.storage.create_workspace("test").await.unwrap();
+
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        text.push(&mut doc.transact_mut(), "v2");
+        let update = doc
+            .transact()
+            .encode_state_as_update_v2(&StateVector::default());
+        let mut body = vec![];
+        body.write_buf(&update);
+        let apply = || {
+            client
+                .post("/workspace/test/updates/batch?encoding=v2")
+                .body(body.clone())
+                .send()
+        };
+
+        // v2 updates are only accepted with the flag
+        assert_eq!(apply().await.status(), StatusCode::BAD_REQUEST);
+        let resp = client.get("/workspace/test/flags").send().await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.json::<serde_json::Value>().await["v2_encoding"], false);
+
+        // the admin apis need the admin token
+        let set_flag = |token: &'static str| {
+            client
+                .put("/admin/workspaces/test/flags/v2_encoding")
+                .header("Authorization", format!("Bearer {token}"))
+                .json(&serde_json::json!({ "enabled": true }))
+                .send()
+        };
+        assert_eq!(set_flag("wrong").await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(set_flag("admin").await.status(), StatusCode::NO_CONTENT);
+
+        let resp = client.get("/workspace/test/flags").send().await;
+        assert_eq!(resp.json::<serde_json::Value>().await["v2_encoding"], true);
+        assert_eq!(apply().await.status(), StatusCode::OK);
+
+        let workspace = context.storage.get_workspace("test").await.unwrap();
+        let doc = workspace.doc();
+        let text = doc.get_or_insert_text("text");
+        assert_eq!(text.get_string(&doc.transact()), "v2");
+    }
+
+    #[tokio::test]
+    async fn patch_workspace() {
+        use lib0::any::Any;
+
+        let (context, client) = test_client().await;
+        let patch =
+            |operations: serde_json::Value| client.patch("/block/test").json(&operations).send();
+
+        let resp = patch(serde_json::json!([])).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let workspace = context.storage.create_workspace("test").await.unwrap();
+        let resp = patch(serde_json::json!([
+            {"op": "add", "path": "/page", "value": {"sys:flavor": "affine:page", "title": "hello"}},
+            {"op": "add", "path": "/page/draft", "value": true},
+        ]))
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let state = resp.json::<serde_json::Value>().await;
+        assert_eq!(
+            state["state_vector"][workspace.client_id().to_string()],
+            workspace
+                .doc()
+                .transact()
+                .state_vector()
+                .get(&workspace.client_id())
+        );
+
+        let resp = patch(serde_json::json!([
+            {"op": "test", "path": "/page/title", "value": "changed"},
+        ]))
+        .await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let resp = patch(serde_json::json!([
+            {"op": "add", "path": "/page/sys:flavor", "value": "affine:note"},
+        ]))
+        .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = patch(serde_json::json!([
+            {"op": "remove", "path": "/page/draft"},
+            {"op": "remove", "path": "/missing"},
+        ]))
+        .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = patch(serde_json::json!([
+            {"op": "remove", "path": "/page/draft"},
+        ]))
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        workspace.with_trx(|t| {
+            let page = workspace.get(&t.trx, "page").unwrap();
+            assert_eq!(page.get(&t.trx, "title"), Some(Any::String("hello".into())));
+            assert_eq!(page.get(&t.trx, "draft"), None);
+        });
+    }
+
+    #[tokio::test]
+    async fn property_history() {
+        let (context, _) = test_client().await;
+        let client = TestClient::new(blocks_apis(Router::new()).layer(Extension(context.clone())));
+        let history = || client.get("/block/test/page/history?key=title").send();
+
+        assert_eq!(history().await.status(), StatusCode::NOT_FOUND);
+
+        context.storage.create_workspace("test").await.unwrap();
+        for title in ["draft", "final"] {
+            let resp = client
+                .post("/block/test/page")
+                .json(&serde_json::json!({ "title": title }))
+                .send()
+                .await;
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+
+        // the history is resolved in the background
+        let resp = history().await;
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        assert_eq!(
+            resp.json::<serde_json::Value>().await,
+            serde_json::json!({ "status": "running" })
+        );
+        let resolved = loop {
+            let resp = history().await;
+            if resp.status() != StatusCode::ACCEPTED {
+                assert_eq!(resp.status(), StatusCode::OK);
+                break resp.json::<serde_json::Value>().await;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        assert_eq!(resolved["status"], "done");
+        assert_eq!(
+            resolved["history"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|change| (change["old"].clone(), change["new"].clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (serde_json::Value::Null, "draft".into()),
+                ("draft".into(), "final".into()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn repair_timestamps() {
+        let storage = JwstStorage::new("sqlite::memory:").await.unwrap();
+        let mut context = Context::new(Some(storage)).await;
+        context.admin_token = Some("admin".into());
+        let context = Arc::new(context);
+        let client = TestClient::new(blocks_apis(Router::new()).layer(Extension(context.clone())));
+
+        let now = 1_700_000_000_000i64;
+        let workspace = context.storage.create_workspace("test").await.unwrap();
+        workspace.set_clock(move || now);
+        workspace.with_trx(|mut t| {
+            t.create("a", "affine:paragraph");
+            t.create("b", "affine:paragraph");
+        });
+        // a client in 2106 edits `a`
+        workspace.set_clock(|| 4_294_967_295_000);
+        workspace.with_trx(|mut t| {
+            let a = workspace.get(&t.trx, "a").unwrap();
+            a.set(&mut t.trx, "text", "edited");
+        });
+        workspace.set_clock(move || now);
+        context.storage.flush_workspace("test").await.unwrap();
+        let context = &context;
+        let stored_repairs = || async move {
+            let stored = stored_workspace(context, "test").await;
+            stored.with_trx(|t| stored.validate_timestamps(&t.trx, now - 1000))
+        };
+        assert_eq!(stored_repairs().await.len(), 1);
+
+        let client = &client;
+        let sorted = || async move {
+            let resp = client.get("/block/test/blocks?sort=updated").send().await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            resp.json::<serde_json::Value>().await["data"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|block| block["id"].as_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sorted().await, vec!["a", "b"]);
+
+        let url = format!(
+            "/admin/workspaces/test/timestamps?created_at={}",
+            now - 1000
+        );
+        let resp = client.get(&url).send().await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = client
+            .get(&url)
+            .header("Authorization", "Bearer admin")
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let found = resp.json::<serde_json::Value>().await;
+        assert_eq!(found.as_array().unwrap().len(), 1);
+        assert_eq!(found[0]["block_id"], "a");
+        assert_eq!(found[0]["original"], 4_294_967_295_000i64);
+        assert_eq!(sorted().await, vec!["a", "b"]);
+
+        let resp = client
+            .post(&url)
+            .header("Authorization", "Bearer admin")
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.json::<serde_json::Value>().await, found);
+        assert_eq!(sorted().await, vec!["b", "a"]);
+        // the repair is stored
+        assert!(stored_repairs().await.is_empty());
+
+        let resp = client
+            .get("/admin/workspaces/missing/timestamps?created_at=0")
+            .header("Authorization", "Bearer admin")
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn mutation_rate_limit() {
+        let storage = JwstStorage::new("sqlite::memory:").await.unwrap();
+        let mut context = Context::new(Some(storage)).await;
+        context.admin_token = Some("admin".into());
+        let context = Arc::new(context);
+        let client = TestClient::new(blocks_apis(Router::new()).layer(Extension(context.clone())));
+        context.storage.create_workspace("test").await.unwrap();
+
+        // a token every 2 seconds, 2 at once
+        let resp = client
+            .put("/admin/rate_limit")
+            .header("Authorization", "Bearer admin")
+            .json(&serde_json::json!({ "rate": 0.5, "burst": 2 }))
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let resp = client
+            .get("/admin/rate_limit")
+            .header("Authorization", "Bearer admin")
+            .send()
+            .await;
+        assert_eq!(
+            resp.json::<serde_json::Value>().await,
+            serde_json::json!({ "rate": 0.5, "burst": 2 })
+        );
+
+        let set_block = || {
+            client
+                .post("/block/test/block")
+                .json(&serde_json::json!({ "title": "title" }))
+                .send()
+        };
+        assert_eq!(set_block().await.status(), StatusCode::OK);
+        assert_eq!(set_block().await.status(), StatusCode::OK);
+        let resp = set_block().await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers()["retry-after"], "2");
+        let body = resp.json::<serde_json::Value>().await;
+        assert_eq!(body["code"], "workspace_rate_limited");
+        let retry_after = body["retry_after_ms"].as_u64().unwrap();
+        assert!(retry_after > 1900 && retry_after <= 2000);
+
+        // reads pass
+        let resp = client.get("/block/test/block").send().await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // ids of no workspace are not limited, nor kept
+        for _ in 0..3 {
+            let resp = client
+                .post("/block/missing/block")
+                .json(&serde_json::json!({}))
+                .send()
+                .await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        }
+        assert!(!context.mutation_limits.is_known("missing"));
+
+        // the limit of the workspace applies over the default one
+        let resp = client
+            .put("/admin/workspaces/test/rate_limit")
+            .header("Authorization", "Bearer admin")
+            .json(&serde_json::json!({ "rate": 0.0, "burst": 0 }))
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(set_block().await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn set_workspace_metadata() {
+        let (context, client) = test_client().await;
+        context.storage.create_workspace("test").await.unwrap();
+
+        let resp = client
+            .put("/block/test/metadata")
+            .json(&serde_json::json!({ "name": "name", "avatar": "avatar" }))
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let metadata = stored_workspace(&context, "test").await.metadata();
+        assert_eq!(metadata.name.as_deref(), Some("name"));
+        assert_eq!(metadata.avatar.as_deref(), Some("avatar"));
+    }
 }
@@ -0,0 +1,239 @@
+use super::*;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+// defaults of [CompactionConfig]
+const DEFAULT_COMPACTION_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const DEFAULT_COMPACTION_MIN_UPDATES: u64 = 100;
+const DEFAULT_COMPACTION_MIN_RATIO: f64 = 2.0;
+
+/// When the stored updates of the open workspaces are merged into one, see
+/// [Context::compact_workspaces].
+#[derive(Debug, Clone)]
+pub struct CompactionConfig {
+    /// How often the workspaces are checked, `None` disables the compaction.
+    pub interval: Option<Duration>,
+    /// Stored updates a workspace needs before it is compacted.
+    pub min_updates: u64,
+    /// Size of the stored updates relative to the compacted doc a workspace needs before it
+    /// is compacted, the updates grow with deleted content and overwritten values.
+    pub min_ratio: f64,
+    /// UTC hours the compaction runs in, from the first one up to the second one,
+    /// `None` for any time of the day.
+    pub hours: Option<(u32, u32)>,
+    /// Most sync connections to the server a compaction runs with, busier servers skip it.
+    pub max_connections: Option<usize>,
+}
+
+impl CompactionConfig {
+    pub(super) fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+            dotenvy::var(name).ok().and_then(|v| v.parse().ok())
+        }
+
+        Self {
+            // seconds, 0 disables the compaction
+            interval: var("KECK_COMPACTION_INTERVAL")
+                .map(Duration::from_secs)
+                .or(Some(DEFAULT_COMPACTION_INTERVAL))
+                .filter(|interval| !interval.is_zero()),
+            min_updates: var("KECK_COMPACTION_MIN_UPDATES")
+                .unwrap_or(DEFAULT_COMPACTION_MIN_UPDATES),
+            min_ratio: var("KECK_COMPACTION_MIN_RATIO").unwrap_or(DEFAULT_COMPACTION_MIN_RATIO),
+            // `start-end`, like `2-5` for 2am to 5am
+            hours: dotenvy::var("KECK_COMPACTION_HOURS").ok().map(|hours| {
+                hours
+                    .split_once('-')
+                    .and_then(|(start, end)| Some((start.parse().ok()?, end.parse().ok()?)))
+                    .filter(|(start, end)| *start < 24 && *end <= 24)
+                    .expect("Invalid compaction hours")
+            }),
+            max_connections: var("KECK_COMPACTION_MAX_CONNECTIONS"),
+        }
+    }
+
+    fn in_window(&self, hour: u32) -> bool {
+        match self.hours {
+            Some((start, end)) if start <= end => (start..end).contains(&hour),
+            // the window spans midnight
+            Some((start, end)) => hour >= start || hour < end,
+            None => true,
+        }
+    }
+}
+
+/// Compactions performed since the server started.
+#[derive(Debug, Default, Clone, Serialize)]
+#[cfg_attr(feature = "api", derive(utoipa::ToSchema))]
+pub struct CompactionStats {
+    pub compactions: u64,
+    /// Size of the stored updates removed by the compactions.
+    pub bytes_reclaimed: u64,
+    /// Unix timestamp of the last check of the workspaces.
+    pub last_run: Option<i64>,
+}
+
+impl Context {
+    /// Compactions performed so far.
+    pub fn compaction_stats(&self) -> CompactionStats {
+        self.compaction_stats.lock().unwrap().clone()
+    }
+
+    /// Merge the stored updates of the open workspaces that grew past the thresholds of
+    /// [CompactionConfig], if the server is in its compaction window and not too busy.
+    ///
+    /// Only the rows in the storage are rewritten, the docs and the sync connections are
+    /// left as is, so the clients don't notice it.
+    pub async fn compact_workspaces(&self) {
+        let config = &self.compaction;
+        if !config.in_window(OffsetDateTime::now_utc().hour() as u32) {
+            return;
+        }
+        let connections = self.channel.read().await.len();
+        if matches!(config.max_connections, Some(max) if connections > max) {
+            debug!("skip compaction, {} sync connections", connections);
+            return;
+        }
+
+        for workspace in self.list_channels().await {
+            match self.compact_workspace(&workspace).await {
+                Ok(Some(reclaimed)) => {
+                    info!("compacted workspace {}: {} bytes", workspace, reclaimed);
+                    let mut stats = self.compaction_stats.lock().unwrap();
+                    stats.compactions += 1;
+                    stats.bytes_reclaimed += reclaimed;
+                }
+                Ok(None) => {}
+                Err(e) => error!("failed to compact workspace {}: {}", workspace, e),
+            }
+        }
+        self.compaction_stats.lock().unwrap().last_run =
+            Some(OffsetDateTime::now_utc().unix_timestamp());
+    }
+
+    // returns the bytes reclaimed, `None` if the workspace is below the thresholds
+    async fn compact_workspace(&self, workspace_id: &str) -> JwstResult<Option<u64>> {
+        let config = &self.compaction;
+        // only the stored updates of relayed workspaces exist, they are merged without
+        // loading the workspace regardless of their size
+        if self.relay.contains(workspace_id) {
+            let updates = self.storage.stored_updates(workspace_id).await?;
+            if (updates.len() as u64) < config.min_updates.max(2) {
+                return Ok(None);
+            }
+            let before = updates.iter().map(Vec::len).sum::<usize>();
+            let merged = self.storage.merge_stored_updates(workspace_id).await?;
+            let after = merged.as_ref().map_or(0, Vec::len);
+            return Ok(Some(before.saturating_sub(after) as u64));
+        }
+        let before = self.storage.workspace_stats(workspace_id).await?;
+        if before.updates < config.min_updates.max(2) {
+            return Ok(None);
+        }
+        let workspace = self.storage.get_workspace(workspace_id).await?;
+        let compacted = workspace.sync_migration().len() as f64;
+        if (before.update_bytes as f64) < compacted * config.min_ratio {
+            return Ok(None);
+        }
+
+        self.storage.flush_workspace(workspace_id).await?;
+        let after = self.storage.workspace_stats(workspace_id).await?;
+        Ok(Some(before.update_bytes.saturating_sub(after.update_bytes)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lib0::any::Any;
+
+    #[test]
+    fn compaction_window() {
+        let mut config = CompactionConfig::from_env();
+        config.hours = None;
+        assert!(config.in_window(12));
+
+        config.hours = Some((2, 5));
+        assert!(config.in_window(2));
+        assert!(config.in_window(4));
+        assert!(!config.in_window(5));
+        assert!(!config.in_window(1));
+
+        // the window spans midnight
+        config.hours = Some((22, 3));
+        assert!(config.in_window(23));
+        assert!(config.in_window(0));
+        assert!(!config.in_window(3));
+        assert!(!config.in_window(12));
+    }
+
+    #[test]
+    fn compaction_config_from_env() {
+        let config = CompactionConfig::from_env();
+        assert_eq!(config.interval, Some(DEFAULT_COMPACTION_INTERVAL));
+        assert_eq!(config.min_updates, DEFAULT_COMPACTION_MIN_UPDATES);
+        assert_eq!(config.hours, None);
+
+        std::env::set_var("KECK_COMPACTION_INTERVAL", "60");
+        std::env::set_var("KECK_COMPACTION_MIN_UPDATES", "10");
+        std::env::set_var("KECK_COMPACTION_HOURS", "22-3");
+        let config = CompactionConfig::from_env();
+        assert_eq!(config.interval, Some(Duration::from_secs(60)));
+        assert_eq!(config.min_updates, 10);
+        assert_eq!(config.hours, Some((22, 3)));
+
+        std::env::set_var("KECK_COMPACTION_INTERVAL", "0");
+        assert_eq!(CompactionConfig::from_env().interval, None);
+
+        std::env::remove_var("KECK_COMPACTION_INTERVAL");
+        std::env::remove_var("KECK_COMPACTION_MIN_UPDATES");
+        std::env::remove_var("KECK_COMPACTION_HOURS");
+    }
+
+    #[tokio::test]
+    async fn compaction_thresholds() {
+        let storage = JwstStorage::new("sqlite::memory:").await.unwrap();
+        let mut context = Context::new(Some(storage)).await;
+        context.compaction.min_updates = 20;
+        context.compaction.min_ratio = 2.0;
+
+        let workspace = context.storage.create_workspace("test").await.unwrap();
+        for i in 0..10 {
+            // overwritten values leave the stored updates larger than the doc
+            let update = workspace.with_trx(|mut t| {
+                let block = t.create("block", "text");
+                block.set(&mut t.trx, "title", format!("title {i} ").repeat(100));
+                t.trx.encode_update_v1()
+            });
+            context
+                .storage
+                .docs()
+                .write_update("test".into(), &update)
+                .await
+                .unwrap();
+        }
+
+        // too few updates
+        assert_eq!(context.compact_workspace("test").await.unwrap(), None);
+
+        // the doc isn't small enough compared to the updates
+        context.compaction.min_updates = 5;
+        context.compaction.min_ratio = 1000.0;
+        assert_eq!(context.compact_workspace("test").await.unwrap(), None);
+
+        context.compaction.min_ratio = 2.0;
+        let reclaimed = context.compact_workspace("test").await.unwrap();
+        assert!(matches!(reclaimed, Some(reclaimed) if reclaimed > 0));
+
+        let stats = context.storage.workspace_stats("test").await.unwrap();
+        assert_eq!(stats.updates, 1);
+        // the compacted workspace is kept as is
+        let workspace = context.storage.get_workspace("test").await.unwrap();
+        let title = workspace.with_trx(|t| {
+            workspace
+                .get(&t.trx, "block")
+                .and_then(|block| block.get(&t.trx, "title"))
+        });
+        assert_eq!(title, Some(Any::String("title 9 ".repeat(100).into())));
+    }
+}
@@ -0,0 +1,119 @@
+use super::*;
+use jwst::JwstResult;
+use std::{collections::BTreeMap, sync::Arc};
+
+/// Rolls out the YText based block content.
+pub const YTEXT_MIGRATION: &str = "ytext_migration";
+/// Routes subdocs of the workspace.
+pub const SUBDOCS: &str = "subdocs";
+/// Accepts updates in the v2 encoding.
+pub const V2_ENCODING: &str = "v2_encoding";
+
+/// Feature flags of a workspace, server code branches on the fields
+/// instead of looking the flags up by name.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "api", derive(utoipa::ToSchema))]
+pub struct Flags {
+    pub ytext_migration: bool,
+    pub subdocs: bool,
+    pub v2_encoding: bool,
+    /// Flags the server doesn't know about, only passed to the clients.
+    pub others: BTreeMap<String, bool>,
+}
+
+impl From<BTreeMap<String, bool>> for Flags {
+    fn from(mut flags: BTreeMap<String, bool>) -> Self {
+        let mut take = |name| flags.remove(name).unwrap_or_default();
+        Self {
+            ytext_migration: take(YTEXT_MIGRATION),
+            subdocs: take(SUBDOCS),
+            v2_encoding: take(V2_ENCODING),
+            others: flags,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(super) struct CachedFlags {
+    flags: HashMap<String, Flags>,
+    // bumped on every invalidation, flags loaded while one happened are not cached
+    generation: u64,
+}
+
+impl CachedFlags {
+    fn invalidate(&mut self, workspace: Option<&str>) {
+        match workspace {
+            Some(workspace) => {
+                self.flags.remove(workspace);
+            }
+            None => self.flags.clear(),
+        }
+        self.generation += 1;
+    }
+}
+
+pub(super) type FlagsCache = Arc<RwLock<CachedFlags>>;
+
+// drop cached flags when they change, also for changes made through
+// other handles of the storage
+pub(super) fn invalidate_flags(storage: &JwstStorage, cache: FlagsCache) {
+    let mut changes = storage.subscribe_flag_changes();
+    tokio::spawn(async move {
+        loop {
+            match changes.recv().await {
+                Ok(workspace) => cache.write().await.invalidate(Some(&workspace)),
+                // missed some changes, none of the cached flags can be trusted
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {
+                    cache.write().await.invalidate(None)
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+impl Context {
+    /// Feature flags of a workspace, cached until they change.
+    pub async fn workspace_flags(&self, workspace_id: &str) -> JwstResult<Flags> {
+        let generation = {
+            let cache = self.flags.read().await;
+            if let Some(flags) = cache.flags.get(workspace_id) {
+                return Ok(flags.clone());
+            }
+            cache.generation
+        };
+
+        let flags = Flags::from(self.storage.workspace_flags(workspace_id).await?);
+        let mut cache = self.flags.write().await;
+        // the flags may have changed while they were loaded
+        if cache.generation == generation {
+            cache.flags.insert(workspace_id.to_owned(), flags.clone());
+        }
+        Ok(flags)
+    }
+
+    /// Set a flag through the storage, the cache is refreshed before returning
+    /// instead of waiting for the change notification.
+    pub async fn set_workspace_flag(
+        &self,
+        workspace_id: &str,
+        flag: &str,
+        enabled: bool,
+    ) -> JwstResult<()> {
+        self.storage
+            .set_workspace_flag(workspace_id, flag, enabled)
+            .await?;
+        self.flags.write().await.invalidate(Some(workspace_id));
+        Ok(())
+    }
+
+    /// Clear a flag like [Context::set_workspace_flag], returns `false` if it was not set.
+    pub async fn clear_workspace_flag(&self, workspace_id: &str, flag: &str) -> JwstResult<bool> {
+        let cleared = self
+            .storage
+            .clear_workspace_flag(workspace_id, flag)
+            .await?;
+        self.flags.write().await.invalidate(Some(workspace_id));
+        Ok(cleared)
+    }
+}
@@ -0,0 +1,80 @@
+use super::*;
+use jwst_storage::PropertyChange;
+use std::sync::Mutex;
+
+// most property histories kept, the finished ones are dropped once there are more
+const MAX_PROPERTY_HISTORIES: usize = 256;
+
+/// State of a property history resolved in the background, see [Context::property_history].
+#[derive(Clone)]
+pub enum PropertyHistoryJob {
+    Running,
+    Done(Arc<Vec<PropertyChange>>),
+    Failed(String),
+}
+
+// (workspace, block, property)
+type HistoryKey = (String, String, String);
+
+/// Property histories by the newest stored update they include, a new update of the
+/// workspace makes them outdated.
+#[derive(Default)]
+pub(super) struct PropertyHistories(Mutex<HashMap<HistoryKey, (i32, PropertyHistoryJob)>>);
+
+impl PropertyHistories {
+    fn finish(&self, key: HistoryKey, seq: i32, job: PropertyHistoryJob) {
+        let mut jobs = self.0.lock().unwrap();
+        // a job for a newer update may have been started meanwhile
+        if matches!(jobs.get(&key), Some((started, _)) if *started == seq) {
+            jobs.insert(key, (seq, job));
+        }
+    }
+}
+
+impl Context {
+    /// History of a property of a block up to the stored update `seq`, replaying the
+    /// stored updates is slow, so a background job is started if it's not known yet.
+    /// A failed job is reported once, the next call starts it again.
+    pub fn property_history(
+        self: &Arc<Self>,
+        workspace: &str,
+        block: &str,
+        property: &str,
+        seq: i32,
+    ) -> PropertyHistoryJob {
+        let key = (workspace.to_owned(), block.to_owned(), property.to_owned());
+        let mut jobs = self.property_histories.0.lock().unwrap();
+        match jobs.get(&key) {
+            Some((started, PropertyHistoryJob::Failed(_))) if *started == seq => {
+                return jobs.remove(&key).unwrap().1;
+            }
+            Some((started, job)) if *started == seq => return job.clone(),
+            _ => {}
+        }
+
+        if jobs.len() >= MAX_PROPERTY_HISTORIES {
+            jobs.retain(|_, (_, job)| matches!(job, PropertyHistoryJob::Running));
+        }
+        jobs.insert(key.clone(), (seq, PropertyHistoryJob::Running));
+        drop(jobs);
+
+        let context = self.clone();
+        tokio::spawn(async move {
+            let (workspace, block, property) = &key;
+            let job = match context
+                .storage
+                .property_history(workspace, block, property)
+                .await
+            {
+                Ok(history) => PropertyHistoryJob::Done(Arc::new(history)),
+                Err(e) => {
+                    error!("failed to resolve history of {block}.{property} in {workspace}: {e}");
+                    PropertyHistoryJob::Failed(e.to_string())
+                }
+            };
+            context.property_histories.finish(key, seq, job);
+        });
+
+        PropertyHistoryJob::Running
+    }
+}
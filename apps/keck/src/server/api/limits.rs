@@ -0,0 +1,127 @@
+use super::*;
+use jwst::DocStorage;
+use jwst_rpc::RateLimit;
+use std::time::Duration;
+
+// key of the workspace limit in the workspace kv storage, as `rate/burst`
+const MUTATION_RATE_LIMIT_KEY: &str = "mutation_rate_limit";
+
+impl Context {
+    /// Limit of the REST mutations of a workspace, the one set for it or the default.
+    pub async fn mutation_limit(&self, workspace_id: &str) -> JwstResult<RateLimit> {
+        self.load_mutation_limit(workspace_id).await?;
+        Ok(self.mutation_limits.limit(workspace_id))
+    }
+
+    /// Set the limit of a workspace in the storage, `None` returns it to the default.
+    /// It applies to the next request, no restart needed.
+    pub async fn set_mutation_limit(
+        &self,
+        workspace_id: &str,
+        limit: Option<RateLimit>,
+    ) -> JwstResult<()> {
+        match limit {
+            Some(limit) => {
+                self.storage
+                    .kv_set(
+                        workspace_id,
+                        MUTATION_RATE_LIMIT_KEY,
+                        limit.to_string().as_bytes(),
+                    )
+                    .await?;
+            }
+            None => {
+                self.storage
+                    .kv_delete(workspace_id, MUTATION_RATE_LIMIT_KEY)
+                    .await?;
+            }
+        }
+        info!("set mutation rate limit of {}: {:?}", workspace_id, limit);
+        self.mutation_limits.set_limit(workspace_id, limit);
+        Ok(())
+    }
+
+    /// Take a token for a REST mutation of the workspace, returns the time until the
+    /// next one if the workspace is over its limit.
+    ///
+    /// Ids of no workspace pass, the handlers reject them or create the workspace, so
+    /// requests for made up ids don't fill the limits.
+    pub async fn check_mutation(&self, workspace_id: &str) -> JwstResult<Result<(), Duration>> {
+        if !self.load_mutation_limit(workspace_id).await? {
+            return Ok(Ok(()));
+        }
+        Ok(self.mutation_limits.check(workspace_id))
+    }
+
+    // `false` if there is no such workspace, nothing is loaded for it
+    async fn load_mutation_limit(&self, workspace_id: &str) -> JwstResult<bool> {
+        if self.mutation_limits.is_known(workspace_id) {
+            return Ok(true);
+        }
+        if !self.storage.docs().exists(workspace_id.into()).await? {
+            return Ok(false);
+        }
+        let limit = self
+            .storage
+            .kv_get(workspace_id, MUTATION_RATE_LIMIT_KEY)
+            .await?
+            .and_then(|limit| {
+                let limit = String::from_utf8_lossy(&limit).parse();
+                if let Err(e) = &limit {
+                    warn!("ignore mutation rate limit of {}: {}", workspace_id, e);
+                }
+                limit.ok()
+            });
+        self.mutation_limits.set_limit(workspace_id, limit);
+        Ok(true)
+    }
+}
+
+/// Body of the 429 responses of [limit_mutations], the code tells them apart from
+/// other limits.
+#[derive(Serialize)]
+#[cfg_attr(feature = "api", derive(utoipa::ToSchema))]
+pub struct MutationRateLimited {
+    /// Always `workspace_rate_limited`.
+    pub code: String,
+    /// Milliseconds until the workspace accepts the next mutation.
+    pub retry_after_ms: u64,
+}
+
+/// Limit the requests changing a workspace, see [Context::check_mutation]. Reads pass.
+/// - Return 429 Too Many Requests with [MutationRateLimited] if the workspace is over
+///   its limit.
+#[cfg(feature = "api")]
+pub(super) async fn limit_mutations<B>(
+    Extension(context): Extension<Arc<Context>>,
+    params: Option<Path<HashMap<String, String>>>,
+    req: axum::http::Request<B>,
+    next: axum::middleware::Next<B>,
+) -> axum::response::Response {
+    use axum::http::{header, Method};
+
+    let workspace = params
+        .as_ref()
+        .and_then(|Path(params)| params.get("workspace"))
+        .filter(|_| !matches!(*req.method(), Method::GET | Method::HEAD));
+    if let Some(workspace) = workspace {
+        match context.check_mutation(workspace).await {
+            Ok(Ok(())) => {}
+            Ok(Err(wait)) => {
+                debug!("workspace {} is over its mutation rate limit", workspace);
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, wait.as_secs_f64().ceil().to_string())],
+                    Json(MutationRateLimited {
+                        code: "workspace_rate_limited".into(),
+                        retry_after_ms: wait.as_millis() as u64,
+                    }),
+                )
+                    .into_response();
+            }
+            // don't block the workspace on the storage, the limit is loaded again next time
+            Err(e) => error!("failed to load mutation rate limit of {}: {}", workspace, e),
+        }
+    }
+    next.run(req).await
+}
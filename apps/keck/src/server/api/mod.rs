@@ -2,6 +2,17 @@
 mod blobs;
 #[cfg(feature = "api")]
 mod blocks;
+mod compaction;
+mod flags;
+mod history;
+mod limits;
+#[cfg(feature = "api")]
+mod tenants;
+
+pub use compaction::{CompactionConfig, CompactionStats};
+pub use flags::Flags;
+pub use history::PropertyHistoryJob;
+pub use limits::MutationRateLimited;
 
 use super::*;
 use axum::Router;
@@ -10,11 +21,17 @@ use axum::{
     extract::{Json, Path},
     http::StatusCode,
     response::IntoResponse,
-    routing::{delete, get, head},
+    routing::{delete, get, head, post, put},
 };
-use jwst_rpc::{Channels, ContextImpl};
-use jwst_storage::JwstStorage;
-use std::collections::HashMap;
+use flags::FlagsCache;
+use futures::Future;
+use history::PropertyHistories;
+use jwst::{JwstResult, WorkspacePlugins};
+use jwst_rpc::{
+    BandwidthUsage, Channels, ContextImpl, MutationLimits, RelayWorkspaces, SyncSessions,
+};
+use jwst_storage::{JwstStorage, StorageConfig, StorageEncryption};
+use std::{collections::HashMap, sync::Mutex};
 use tokio::sync::RwLock;
 
 #[derive(Deserialize)]
@@ -36,30 +53,170 @@ pub struct PageData<T> {
     data: T,
 }
 
+// default of [Context::export_max_blocks]
+const DEFAULT_EXPORT_MAX_BLOCKS: usize = 200_000;
+
 pub struct Context {
     pub channel: Channels,
     pub storage: JwstStorage,
+    pub sessions: SyncSessions,
+    /// Traffic of the sync connections and blob downloads not flushed to the storage yet.
+    pub bandwidth: BandwidthUsage,
+    /// Public url of the server, used to build absolute links.
+    pub base_url: Option<String>,
+    /// Reject sync connections to workspaces that don't exist instead of creating them.
+    pub strict_sync: bool,
+    /// Workspaces only relayed between their clients, the block apis reject them.
+    pub relay: RelayWorkspaces,
+    /// Most blocks the workspace JSON export serves, larger exports are rejected.
+    pub export_max_blocks: usize,
+    /// Rate limits of the REST mutations per workspace, sync connections are not limited.
+    pub mutation_limits: MutationLimits,
+    /// Thresholds and schedule of merging the stored updates of the workspaces.
+    pub compaction: CompactionConfig,
+    pub shutdown: ShutdownHooks,
+    /// Bearer token of the admin apis, they are disabled without one.
+    pub admin_token: Option<String>,
+    #[cfg(feature = "api")]
+    tenants: tenants::TenantTokens,
+    flags: FlagsCache,
+    property_histories: PropertyHistories,
+    compaction_stats: Mutex<CompactionStats>,
 }
 
 impl Context {
     pub async fn new(storage: Option<JwstStorage>) -> Self {
+        let config = StorageConfig {
+            // comma separated `version:base64 key`, the first key encrypts new data
+            encryption: dotenvy::var("KECK_STORAGE_KEYS").ok().map(|keys| {
+                info!("encrypt stored docs and blobs");
+                StorageEncryption::parse(&keys).expect("Invalid storage keys")
+            }),
+            // bytes, the completed blob is held in memory
+            max_blob_upload: dotenvy::var("KECK_MAX_BLOB_UPLOAD")
+                .ok()
+                .map(|max| max.parse().expect("Invalid max blob upload")),
+        };
         let storage = if let Some(storage) = storage {
             info!("use external storage instance: {}", storage.database());
             Ok(storage)
         } else if let Ok(database_url) = dotenvy::var("DATABASE_URL") {
             info!("use external database: {}", database_url);
-            JwstStorage::new(&database_url).await
+            JwstStorage::new_with_config(&database_url, config).await
         } else {
             info!("use sqlite database: jwst.db");
-            JwstStorage::new_with_sqlite("jwst").await
+            JwstStorage::new_with_sqlite_config("jwst", config).await
         }
         .expect("Cannot create database");
 
+        let mut plugins = WorkspacePlugins::default().search(
+            dotenvy::var("KECK_SEARCH")
+                .map(|v| v == "true" || v == "1")
+                .unwrap_or(true),
+        );
+        if let Ok(dir) = dotenvy::var("KECK_INDEX_DIR") {
+            info!("keep workspace indexes in {}", dir);
+            plugins = plugins.index_directory(dir.into());
+        }
+        storage.docs().set_workspace_plugins(plugins);
+
+        let flags = FlagsCache::default();
+        flags::invalidate_flags(&storage, flags.clone());
+
         Context {
             channel: RwLock::new(HashMap::new()),
             storage,
+            sessions: SyncSessions::default(),
+            bandwidth: BandwidthUsage::default(),
+            base_url: dotenvy::var("KECK_BASE_URL")
+                .ok()
+                .map(|url| url.trim_end_matches('/').to_owned()),
+            strict_sync: dotenvy::var("KECK_STRICT_SYNC")
+                .map(|v| v == "true" || v == "1")
+                .unwrap_or(false),
+            // comma separated workspace ids, `*` for all of them
+            relay: RelayWorkspaces::parse(
+                &dotenvy::var("KECK_RELAY_WORKSPACES").unwrap_or_default(),
+            )
+            .persist(
+                dotenvy::var("KECK_RELAY_PERSIST")
+                    .map(|v| v == "true" || v == "1")
+                    .unwrap_or(true),
+            ),
+            export_max_blocks: dotenvy::var("KECK_EXPORT_MAX_BLOCKS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_EXPORT_MAX_BLOCKS),
+            // `rate/burst` of the workspaces without a limit of their own, `0/0` disables it
+            mutation_limits: dotenvy::var("KECK_MUTATION_RATE_LIMIT")
+                .map(|limit| {
+                    MutationLimits::new(limit.parse().expect("Invalid mutation rate limit"))
+                })
+                .unwrap_or_default(),
+            compaction: CompactionConfig::from_env(),
+            shutdown: ShutdownHooks::default(),
+            admin_token: dotenvy::var("KECK_ADMIN_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty()),
+            // comma separated `tenant=token`, the tenants reach their workspaces with the token
+            #[cfg(feature = "api")]
+            tenants: dotenvy::var("KECK_TENANT_TOKENS")
+                .map(|tokens| tenants::TenantTokens::parse(&tokens).expect("Invalid tenant tokens"))
+                .unwrap_or_default(),
+            flags,
+            property_histories: PropertyHistories::default(),
+            compaction_stats: Mutex::default(),
         }
     }
+
+    /// Register an async cleanup callback, run when the server shuts down.
+    /// Callbacks run one after another, the last registered first.
+    pub fn on_shutdown<F, Fut>(&self, name: impl Into<String>, hook: F)
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown.register(name, hook)
+    }
+
+    /// Persist the current state of a workspace and return once it is stored, for handlers
+    /// that must not lose changes made in memory if the server stops before the next full
+    /// migration of the sync connections. Relayed workspaces are only stored as updates, they
+    /// are merged into one without loading the workspace.
+    pub async fn flush_workspace(&self, workspace_id: &str) -> JwstResult<()> {
+        if self.relay.contains(workspace_id) {
+            self.storage.merge_stored_updates(workspace_id).await?;
+            return Ok(());
+        }
+        self.storage.flush_workspace(workspace_id).await
+    }
+
+    /// Link to a blob served by the blobs api, relative to the server if no base url is set.
+    pub fn blob_url(&self, workspace: &str, blob_id: &str) -> String {
+        format!(
+            "{}/api/blobs/{workspace}/{blob_id}",
+            self.base_url.as_deref().unwrap_or_default()
+        )
+    }
+}
+
+/// Only let requests with the admin token through, see [Context::admin_token].
+/// - Return 401 Unauthorized if the token is missing or wrong, or no admin token is set.
+#[cfg(feature = "api")]
+async fn authenticate_admin<B>(
+    Extension(context): Extension<Arc<Context>>,
+    req: axum::http::Request<B>,
+    next: axum::middleware::Next<B>,
+) -> axum::response::Response {
+    let token = req
+        .headers()
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match (token, &context.admin_token) {
+        (Some(token), Some(admin_token)) if token == admin_token => next.run(req).await,
+        _ => StatusCode::UNAUTHORIZED.into_response(),
+    }
 }
 
 impl ContextImpl<'_> for Context {
@@ -70,6 +227,23 @@ impl ContextImpl<'_> for Context {
     fn get_channel(&self) -> &Channels {
         &self.channel
     }
+
+    fn sync_sessions(&self) -> Option<&SyncSessions> {
+        Some(&self.sessions)
+    }
+
+    fn bandwidth_usage(&self) -> Option<&BandwidthUsage> {
+        Some(&self.bandwidth)
+    }
+
+    fn relay_workspaces(&self) -> Option<&RelayWorkspaces> {
+        Some(&self.relay)
+    }
+
+    // the joins of multiplexed sockets, single sockets are checked on the upgrade request
+    fn create_missing_workspaces(&self) -> bool {
+        !self.strict_sync
+    }
 }
 
 pub fn api_handler(router: Router) -> Router {
@@ -77,7 +251,7 @@ pub fn api_handler(router: Router) -> Router {
     {
         router.nest(
             "/api",
-            blobs::blobs_apis(blocks::blocks_apis(Router::new())),
+            tenants::tenant_apis(blobs::blobs_apis(blocks::blocks_apis(Router::new()))),
         )
     }
     #[cfg(not(feature = "api"))]
@@ -0,0 +1,272 @@
+use super::*;
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::{Json, Path},
+    http::{header, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, put},
+};
+use jwst_storage::TenantId;
+
+/// Bearer tokens of the tenants, a tenant only reaches its own workspaces
+/// through the tenant apis, see [jwst_storage::TenantStorage].
+#[derive(Default)]
+pub(super) struct TenantTokens(HashMap<String, TenantId>);
+
+impl TenantTokens {
+    /// Parse comma separated `tenant=token` pairs.
+    pub(super) fn parse(tokens: &str) -> JwstResult<Self> {
+        let mut parsed = HashMap::new();
+        for pair in tokens.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let Some((tenant, token)) = pair.split_once('=') else {
+                return Err(anyhow::anyhow!("invalid tenant token: {pair:?}").into());
+            };
+            if token.is_empty() {
+                return Err(anyhow::anyhow!("empty token of tenant {tenant:?}").into());
+            }
+            parsed.insert(token.to_owned(), TenantId::new(tenant)?);
+        }
+        Ok(Self(parsed))
+    }
+
+    fn tenant(&self, token: &str) -> Option<&TenantId> {
+        self.0.get(token)
+    }
+}
+
+/// Resolve the tenant of the bearer token of the request.
+/// - Return 401 Unauthorized if the token is missing or unknown.
+async fn authenticate_tenant<B>(
+    Extension(context): Extension<Arc<Context>>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let tenant = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|token| context.tenants.tenant(token))
+        .cloned();
+    let Some(tenant) = tenant else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+    req.extensions_mut().insert(tenant);
+    next.run(req).await
+}
+
+/// Get a `Workspace` of the tenant.
+/// - Return 200 Ok and the workspace.
+/// - Return 404 Not Found if the tenant has no such workspace.
+async fn get_workspace(
+    Extension(context): Extension<Arc<Context>>,
+    Extension(tenant): Extension<TenantId>,
+    Path(workspace): Path<String>,
+) -> Response {
+    info!("get_tenant_workspace: {}, {}", tenant.as_str(), workspace);
+    match context
+        .storage
+        .tenant(tenant)
+        .get_workspace(&workspace)
+        .await
+    {
+        Ok(workspace) => Json(workspace).into_response(),
+        Err(_) => (
+            StatusCode::NOT_FOUND,
+            format!("Workspace({workspace:?}) not found"),
+        )
+            .into_response(),
+    }
+}
+
+/// Create a `Workspace` of the tenant.
+/// - Return 200 Ok and the workspace, also if it exists.
+async fn set_workspace(
+    Extension(context): Extension<Arc<Context>>,
+    Extension(tenant): Extension<TenantId>,
+    Path(workspace): Path<String>,
+) -> Response {
+    info!("set_tenant_workspace: {}, {}", tenant.as_str(), workspace);
+    match context
+        .storage
+        .tenant(tenant)
+        .create_workspace(&workspace)
+        .await
+    {
+        Ok(workspace) => Json(workspace).into_response(),
+        Err(e) => {
+            error!("Failed to init doc: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Delete a `Workspace` of the tenant with its blobs.
+/// - Return 204 No Content.
+async fn delete_workspace(
+    Extension(context): Extension<Arc<Context>>,
+    Extension(tenant): Extension<TenantId>,
+    Path(workspace): Path<String>,
+) -> Response {
+    info!(
+        "delete_tenant_workspace: {}, {}",
+        tenant.as_str(),
+        workspace
+    );
+    match context
+        .storage
+        .tenant(tenant)
+        .delete_workspace(&workspace)
+        .await
+    {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            error!("Failed to delete workspace {}: {}", workspace, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Get a `Blob` of a workspace of the tenant.
+/// - Return 200 and the blob.
+/// - Return 404 Not Found if the workspace or the blob not exists.
+async fn get_blob(
+    Extension(context): Extension<Arc<Context>>,
+    Extension(tenant): Extension<TenantId>,
+    Path((workspace, hash)): Path<(String, String)>,
+) -> Response {
+    info!(
+        "get_tenant_blob: {}, {}, {}",
+        tenant.as_str(),
+        workspace,
+        hash
+    );
+    match context
+        .storage
+        .tenant(tenant)
+        .get_blob(&workspace, hash)
+        .await
+    {
+        Ok(blob) => StreamBody::new(blob).into_response(),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Store a `Blob` in a workspace of the tenant.
+/// - Return 200 and the hash of the blob.
+async fn put_blob(
+    Extension(context): Extension<Arc<Context>>,
+    Extension(tenant): Extension<TenantId>,
+    Path(workspace): Path<String>,
+    body: Bytes,
+) -> Response {
+    info!("put_tenant_blob: {}, {}", tenant.as_str(), workspace);
+    match context
+        .storage
+        .tenant(tenant)
+        .put_blob(&workspace, futures::stream::iter([body]))
+        .await
+    {
+        Ok(hash) => hash.into_response(),
+        Err(e) => {
+            error!("Failed to store blob of {}: {}", workspace, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub(super) fn tenant_apis(router: Router) -> Router {
+    router.nest(
+        "/tenant",
+        Router::new()
+            .route(
+                "/workspaces/:workspace",
+                get(get_workspace)
+                    .post(set_workspace)
+                    .delete(delete_workspace),
+            )
+            .route("/blobs/:workspace", put(put_blob))
+            .route("/blobs/:workspace/:hash", get(get_blob))
+            .route_layer(middleware::from_fn(authenticate_tenant)),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum_test_helper::TestClient;
+
+    async fn test_client() -> (Arc<Context>, TestClient) {
+        let storage = JwstStorage::new("sqlite::memory:").await.unwrap();
+        let mut context = Context::new(Some(storage)).await;
+        context.tenants = TenantTokens::parse("first=first-token,second=second-token").unwrap();
+        let context = Arc::new(context);
+
+        let app = tenant_apis(Router::new()).layer(Extension(context.clone()));
+
+        (context, TestClient::new(app))
+    }
+
+    #[test]
+    fn parse_tokens() {
+        assert!(TenantTokens::parse("").unwrap().0.is_empty());
+        assert!(TenantTokens::parse("first").is_err());
+        assert!(TenantTokens::parse("first=").is_err());
+        assert!(TenantTokens::parse("a:b=token").is_err());
+    }
+
+    #[tokio::test]
+    async fn tenant_isolation() {
+        let (context, client) = test_client().await;
+
+        let resp = client.post("/tenant/workspaces/shared").send().await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = client
+            .post("/tenant/workspaces/shared")
+            .header(header::AUTHORIZATION, "Bearer unknown")
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = client
+            .post("/tenant/workspaces/shared")
+            .header(header::AUTHORIZATION, "Bearer first-token")
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = client
+            .put("/tenant/blobs/shared")
+            .header(header::AUTHORIZATION, "Bearer first-token")
+            .body("blob")
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let hash = resp.text().await;
+
+        // another tenant doesn't see the workspace of the same id
+        let resp = client
+            .get("/tenant/workspaces/shared")
+            .header(header::AUTHORIZATION, "Bearer second-token")
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let resp = client
+            .get(&format!("/tenant/blobs/shared/{hash}"))
+            .header(header::AUTHORIZATION, "Bearer second-token")
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = client
+            .get(&format!("/tenant/blobs/shared/{hash}"))
+            .header(header::AUTHORIZATION, "Bearer first-token")
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.text().await, "blob");
+
+        // nor does the unscoped storage
+        assert!(context.storage.get_workspace("first:shared").await.is_err());
+    }
+}
@@ -1,17 +1,33 @@
 mod api;
 mod files;
+mod shutdown;
 mod sync;
 mod utils;
 
 use axum::{response::Redirect, Extension, Router, Server};
 use http::Method;
-use std::{net::SocketAddr, sync::Arc};
-use tokio::signal;
+use jwst_rpc::ContextImpl;
+use jwst_storage::DEFAULT_BLOB_UPLOAD_TTL;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{signal, time::interval};
 use tower_http::cors::{Any, CorsLayer};
 
 use api::Context;
+use shutdown::ShutdownHooks;
 use utils::*;
 
+// time given to the shutdown hooks once the server stopped
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// how often the bandwidth counters are added to the totals in the storage
+const BANDWIDTH_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+// how often abandoned blob uploads are dropped
+const BLOB_UPLOAD_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// how often the rate limit buckets of idle workspaces are dropped
+const RATE_LIMIT_CLEANUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
 async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
@@ -53,6 +69,7 @@ pub async fn start_server() {
         .allow_methods(vec![
             Method::GET,
             Method::POST,
+            Method::PATCH,
             Method::DELETE,
             Method::OPTIONS,
         ])
@@ -61,6 +78,78 @@ pub async fn start_server() {
         .allow_headers(Any);
 
     let context = Arc::new(Context::new(None).await);
+    context.on_shutdown("flush workspaces", {
+        let context = context.clone();
+        move || async move {
+            for workspace in context.list_channels().await {
+                if let Err(e) = context.flush_workspace(&workspace).await {
+                    error!("failed to flush workspace {}: {}", workspace, e);
+                }
+            }
+        }
+    });
+
+    tokio::spawn({
+        let context = context.clone();
+        async move {
+            let mut interval = interval(BANDWIDTH_FLUSH_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = context.bandwidth.flush(&context.storage).await {
+                    error!("failed to flush bandwidth usage: {}", e);
+                }
+            }
+        }
+    });
+    context.on_shutdown("flush bandwidth usage", {
+        let context = context.clone();
+        move || async move {
+            if let Err(e) = context.bandwidth.flush(&context.storage).await {
+                error!("failed to flush bandwidth usage: {}", e);
+            }
+        }
+    });
+
+    tokio::spawn({
+        let context = context.clone();
+        async move {
+            let mut interval = interval(BLOB_UPLOAD_CLEANUP_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = context
+                    .storage
+                    .cleanup_blob_uploads(DEFAULT_BLOB_UPLOAD_TTL)
+                    .await
+                {
+                    error!("failed to drop abandoned blob uploads: {}", e);
+                }
+            }
+        }
+    });
+
+    tokio::spawn({
+        let context = context.clone();
+        async move {
+            let mut interval = interval(RATE_LIMIT_CLEANUP_INTERVAL);
+            loop {
+                interval.tick().await;
+                context.mutation_limits.cleanup();
+            }
+        }
+    });
+
+    if let Some(period) = context.compaction.interval {
+        tokio::spawn({
+            let context = context.clone();
+            async move {
+                let mut interval = interval(period);
+                loop {
+                    interval.tick().await;
+                    context.compact_workspaces().await;
+                }
+            }
+        });
+    }
 
     let app = files::static_files(sync::sync_handler(api::api_handler(Router::new())))
         .layer(cors)
@@ -83,8 +172,7 @@ pub async fn start_server() {
         error!("Server shutdown due to error: {}", e);
     }
 
-    // context.docs.close().await;
-    // context.blobs.close().await;
+    context.shutdown.run(SHUTDOWN_TIMEOUT).await;
 
     info!("Server shutdown complete");
 }
@@ -0,0 +1,50 @@
+use super::*;
+use futures::{future::BoxFuture, Future};
+use std::{sync::Mutex, time::Duration};
+use tokio::time::{timeout_at, Instant};
+
+type Hook = Box<dyn FnOnce() -> BoxFuture<'static, ()> + Send>;
+
+/// Cleanup callbacks of subsystems, run after the server stopped accepting requests.
+#[derive(Default)]
+pub struct ShutdownHooks {
+    hooks: Mutex<Vec<(String, Hook)>>,
+}
+
+impl ShutdownHooks {
+    pub fn register<F, Fut>(&self, name: impl Into<String>, hook: F)
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks
+            .lock()
+            .unwrap()
+            .push((name.into(), Box::new(move || Box::pin(hook()))));
+    }
+
+    /// Run the hooks in reverse order of registration, so subsystems started later
+    /// are stopped before the ones they depend on. Hooks still running when `timeout`
+    /// elapses are aborted, and the ones that didn't start are skipped.
+    pub async fn run(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        let mut hooks = std::mem::take(&mut *self.hooks.lock().unwrap());
+
+        while let Some((name, hook)) = hooks.pop() {
+            info!("running shutdown hook: {}", name);
+            let mut task = tokio::spawn(hook());
+            match timeout_at(deadline, &mut task).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => error!("shutdown hook {} failed: {}", name, e),
+                Err(_) => {
+                    task.abort();
+                    error!("shutdown hook {} aborted after {:?}", name, timeout);
+                    for (name, _) in hooks.iter().rev() {
+                        error!("shutdown hook {} skipped", name);
+                    }
+                    return;
+                }
+            }
+        }
+    }
+}
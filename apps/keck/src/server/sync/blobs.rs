@@ -17,6 +17,7 @@ use http::{
     HeaderMap, HeaderValue, StatusCode,
 };
 use jwst::BlobStorage;
+use jwst_storage::BandwidthScope;
 use time::{format_description::well_known::Rfc2822, OffsetDateTime};
 
 #[derive(Serialize)]
@@ -82,10 +83,19 @@ impl Context {
             return header.into_response();
         };
 
-        let Ok(file) = self.storage.blobs().get_blob(workspace, id).await else {
+        let Ok(file) = self.storage.blobs().get_blob(workspace.clone(), id).await else {
             return StatusCode::NOT_FOUND.into_response()
         };
 
+        // counts what is actually streamed, downloads can be interrupted
+        let counter =
+            workspace.map(|workspace| self.bandwidth.counter(BandwidthScope::Workspace(workspace)));
+        let file = file.inspect(move |chunk| {
+            if let (Some(counter), Ok(chunk)) = (&counter, chunk) {
+                counter.add_sent(chunk.len());
+            }
+        });
+
         (header, StreamBody::new(file)).into_response()
     }
 
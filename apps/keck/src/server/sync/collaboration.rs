@@ -1,10 +1,12 @@
 use super::*;
 use axum::{
     extract::{ws::WebSocketUpgrade, Path},
-    response::Response,
+    http::StatusCode,
+    response::{IntoResponse, Response},
     Json,
 };
-use jwst_rpc::handle_socket;
+use jwst::DocStorage;
+use jwst_rpc::{handle_multiplexed_socket, handle_socket};
 use serde::Serialize;
 use std::sync::Arc;
 
@@ -25,8 +27,37 @@ pub async fn upgrade_handler(
     Path(workspace): Path<String>,
     ws: WebSocketUpgrade,
 ) -> Response {
+    // answer on the upgrade request, a socket closed right after
+    // the upgrade looks like a network failure to clients
+    if context.strict_sync {
+        match context.storage.docs().exists(workspace.clone()).await {
+            Ok(true) => {}
+            Ok(false) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    format!("Workspace({workspace:?}) not found"),
+                )
+                    .into_response()
+            }
+            Err(e) => {
+                error!("failed to check workspace {}: {:?}", workspace, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    }
+
     let identifier = Uuid::new_v4().to_string();
     ws.protocols(["AFFiNE"]).on_upgrade(|socket| async move {
         handle_socket(socket, workspace, context.clone(), identifier).await
     })
 }
+
+pub async fn multiplex_upgrade_handler(
+    Extension(context): Extension<Arc<Context>>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let identifier = Uuid::new_v4().to_string();
+    ws.protocols(["AFFiNE"]).on_upgrade(|socket| async move {
+        handle_multiplexed_socket(socket, context.clone(), identifier).await
+    })
+}
@@ -18,6 +18,10 @@ pub fn sync_handler(router: Router) -> Router {
                 ),
         )
     }
+    .route(
+        "/collaboration",
+        get(collaboration::multiplex_upgrade_handler),
+    )
     .nest_service(
         "/collaboration/:workspace",
         post(collaboration::auth_handler).get(collaboration::upgrade_handler),
@@ -5,6 +5,9 @@ mod m20230101_000002_create_google_user_table;
 mod m20230101_000003_create_workspaces_table;
 mod m20230101_000004_create_permissions_table;
 mod m20230217_000001_update_permissions_table;
+mod m20230301_000001_create_oauth_users_table;
+mod m20230401_000001_create_export_jobs_table;
+mod m20230601_000001_create_notifications_table;
 
 use async_trait::async_trait;
 
@@ -19,6 +22,9 @@ impl MigratorTrait for Migrator {
             Box::new(m20230101_000003_create_workspaces_table::Migration),
             Box::new(m20230101_000004_create_permissions_table::Migration),
             Box::new(m20230217_000001_update_permissions_table::Migration),
+            Box::new(m20230301_000001_create_oauth_users_table::Migration),
+            Box::new(m20230401_000001_create_export_jobs_table::Migration),
+            Box::new(m20230601_000001_create_notifications_table::Migration),
         ]
     }
 }
@@ -0,0 +1,66 @@
+use super::m20220101_000001_create_user_table::Users;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(OAuthUsers::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(OAuthUsers::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(OAuthUsers::UserId).string().not_null())
+                    .col(ColumnDef::new(OAuthUsers::Provider).string().not_null())
+                    .col(ColumnDef::new(OAuthUsers::Subject).string().not_null())
+                    .index(
+                        Index::create()
+                            .name("oauth_users_provider_subject_key")
+                            .col(OAuthUsers::Provider)
+                            .col(OAuthUsers::Subject)
+                            .unique(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("oauth_users_user_id_fkey")
+                            .from(OAuthUsers::Table, OAuthUsers::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_foreign_key(
+                ForeignKey::drop()
+                    .name("oauth_users_user_id_fkey")
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(OAuthUsers::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum OAuthUsers {
+    #[iden = "oauth_users"]
+    Table,
+    Id,       // STRING PRIMARY KEY,
+    UserId,   // STRING REFERENCES users(id),
+    Provider, // TEXT NOT NULL, name of the configured provider
+    Subject,  // TEXT NOT NULL, `sub` claim of the ID token, UNIQUE (provider, subject)
+}
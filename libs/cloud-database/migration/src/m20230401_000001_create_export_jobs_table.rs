@@ -0,0 +1,104 @@
+use super::{
+    m20220101_000001_create_user_table::Users, m20230101_000003_create_workspaces_table::Workspaces,
+};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ExportJobs::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ExportJobs::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(ExportJobs::WorkspaceId).string().not_null())
+                    .col(ColumnDef::new(ExportJobs::UserId).string().not_null())
+                    .col(ColumnDef::new(ExportJobs::Format).string().not_null())
+                    .col(
+                        ColumnDef::new(ExportJobs::Status)
+                            .small_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(ExportJobs::Progress)
+                            .small_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(ColumnDef::new(ExportJobs::Blob).text())
+                    .col(ColumnDef::new(ExportJobs::Error).text())
+                    .col(
+                        ColumnDef::new(ExportJobs::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(ColumnDef::new(ExportJobs::FinishedAt).timestamp_with_time_zone())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("export_jobs_workspace_id_fkey")
+                            .from(ExportJobs::Table, ExportJobs::WorkspaceId)
+                            .to(Workspaces::Table, Workspaces::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("export_jobs_user_id_fkey")
+                            .from(ExportJobs::Table, ExportJobs::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("export_jobs_user_id_status_idx")
+                    .table(ExportJobs::Table)
+                    .col(ExportJobs::UserId)
+                    .col(ExportJobs::Status)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("export_jobs_user_id_status_idx")
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(ExportJobs::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum ExportJobs {
+    Table,
+    Id,          // STRING PRIMARY KEY,
+    WorkspaceId, // STRING REFERENCES workspaces(id),
+    UserId,      // STRING REFERENCES users(id),
+    Format,      // STRING NOT NULL, archive, markdown or html
+    Status,      // SMALLINT NOT NULL DEFAULT 0,
+    Progress,    // SMALLINT NOT NULL DEFAULT 0, percent
+    Blob,        // TEXT, hash of the result in the blob storage
+    Error,       // TEXT,
+    CreatedAt,   // TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FinishedAt,  // TIMESTAMP,
+}
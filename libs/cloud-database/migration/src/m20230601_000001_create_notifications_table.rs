@@ -0,0 +1,103 @@
+use super::{
+    m20220101_000001_create_user_table::Users, m20230101_000003_create_workspaces_table::Workspaces,
+};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Notifications::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Notifications::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Notifications::UserId).string().not_null())
+                    .col(
+                        ColumnDef::new(Notifications::WorkspaceId)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(Notifications::Kind)
+                            .small_integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(Notifications::Actor).string())
+                    .col(ColumnDef::new(Notifications::BlockId).string())
+                    .col(
+                        ColumnDef::new(Notifications::Read)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .col(
+                        ColumnDef::new(Notifications::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("notifications_user_id_fkey")
+                            .from(Notifications::Table, Notifications::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("notifications_workspace_id_fkey")
+                            .from(Notifications::Table, Notifications::WorkspaceId)
+                            .to(Workspaces::Table, Workspaces::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("notifications_user_id_read_idx")
+                    .table(Notifications::Table)
+                    .col(Notifications::UserId)
+                    .col(Notifications::Read)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("notifications_user_id_read_idx")
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Notifications::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Notifications {
+    Table,
+    Id,          // STRING PRIMARY KEY,
+    UserId,      // STRING REFERENCES users(id), the recipient
+    WorkspaceId, // STRING REFERENCES workspaces(id),
+    Kind,        // SMALLINT NOT NULL, 0: mention, 1: invitation accepted
+    Actor,       // STRING, id of the user who caused the event
+    BlockId,     // STRING,
+    Read,        // BOOLEAN NOT NULL DEFAULT false,
+    CreatedAt,   // TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+}
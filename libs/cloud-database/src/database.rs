@@ -1,16 +1,20 @@
 use super::{
     model::{
-        CreateUser, GoogleClaims, Member, MemberResult, PermissionType, RefreshToken,
+        CreateUser, ExportFormat, ExportJob, ExportJobStatus, GoogleClaims, Member, MemberResult,
+        Notification, NotificationEvent, OAuthIdentity, OAuthLogin, PermissionType, RefreshToken,
         UpdateWorkspace, User, UserCred, UserInWorkspace, UserLogin, Workspace, WorkspaceDetail,
         WorkspaceType, WorkspaceWithPermission,
     },
     *,
 };
 use affine_cloud_migration::{Expr, JoinType, Migrator, MigratorTrait, Query};
+use chrono::Utc;
 use nanoid::nanoid;
 use sea_orm::{
-    prelude::*, ConnectionTrait, Database, DatabaseTransaction, QuerySelect, Set, TransactionTrait,
+    prelude::*, ConnectionTrait, Database, DatabaseTransaction, QueryOrder, QuerySelect, Set,
+    TransactionTrait,
 };
+use std::collections::{BTreeSet, HashSet};
 
 // #[derive(FromRow)]
 // struct PermissionQuery {
@@ -432,6 +436,14 @@ impl CloudDatabase {
             .map(|p| p.is_some())
     }
 
+    pub async fn workspace_exists(&self, workspace_id: String) -> Result<bool, DbErr> {
+        Workspaces::find()
+            .filter(WorkspacesColumn::Id.eq(workspace_id))
+            .count(&self.pool)
+            .await
+            .map(|c| c > 0)
+    }
+
     pub async fn is_public_workspace(&self, workspace_id: String) -> Result<bool, DbErr> {
         Workspaces::find()
             .filter(WorkspacesColumn::Id.eq(workspace_id.clone()))
@@ -638,6 +650,356 @@ impl CloudDatabase {
             Ok(user)
         }
     }
+
+    async fn link_oauth_user<C: ConnectionTrait>(
+        trx: &C,
+        user_id: String,
+        identity: &OAuthIdentity,
+    ) -> Result<(), DbErr> {
+        OAuthUsers::insert(OAuthUsersActiveModel {
+            id: Set(nanoid!()),
+            user_id: Set(user_id),
+            provider: Set(identity.provider.clone()),
+            subject: Set(identity.subject.clone()),
+        })
+        .exec(trx)
+        .await
+        .map(|_| ())
+    }
+
+    pub async fn oauth_user_login(&self, identity: &OAuthIdentity) -> Result<OAuthLogin, DbErr> {
+        let trx = self.pool.begin().await?;
+
+        let linked = OAuthUsers::find()
+            .filter(OAuthUsersColumn::Provider.eq(identity.provider.clone()))
+            .filter(OAuthUsersColumn::Subject.eq(identity.subject.clone()))
+            .one(&trx)
+            .await?;
+        if let Some(linked) = linked {
+            let user = Users::find()
+                .filter(UsersColumn::Id.eq(linked.user_id.clone()))
+                .one(&trx)
+                .await?
+                .ok_or_else(|| DbErr::RecordNotFound(linked.user_id))?;
+            trx.commit().await?;
+            return Ok(OAuthLogin::User(user));
+        }
+
+        if let Some(user) = Users::find()
+            .filter(UsersColumn::Email.eq(identity.email.clone()))
+            .one(&trx)
+            .await?
+        {
+            // only link users that have no other way to sign in
+            let has_google = GoogleUsers::find()
+                .filter(GoogleUsersColumn::UserId.eq(user.id.clone()))
+                .one(&trx)
+                .await?
+                .is_some();
+            let has_oauth = OAuthUsers::find()
+                .filter(OAuthUsersColumn::UserId.eq(user.id.clone()))
+                .one(&trx)
+                .await?
+                .is_some();
+            if user.password.is_some() || has_google || has_oauth {
+                return Ok(OAuthLogin::Conflict {
+                    email: identity.email.clone(),
+                });
+            }
+
+            Self::link_oauth_user(&trx, user.id.clone(), identity).await?;
+            trx.commit().await?;
+            return Ok(OAuthLogin::User(user));
+        }
+
+        let id = nanoid!();
+        let user = Users::insert(UsersActiveModel {
+            id: Set(id.clone()),
+            name: Set(identity.name.clone()),
+            email: Set(identity.email.clone()),
+            avatar_url: Set(identity.picture.clone()),
+            ..Default::default()
+        })
+        .exec_with_returning(&trx)
+        .await?;
+
+        self.create_workspace(&trx, id.clone(), WorkspaceType::Private)
+            .await?;
+        Self::link_oauth_user(&trx, id.clone(), identity).await?;
+        Self::update_cred(&trx, id, &identity.email).await?;
+
+        trx.commit().await?;
+
+        Ok(OAuthLogin::User(user))
+    }
+
+    /// Queue an export of a workspace, `None` if the user already has
+    /// `max_unfinished` jobs pending or running.
+    pub async fn create_export_job(
+        &self,
+        user_id: String,
+        workspace_id: String,
+        format: ExportFormat,
+        max_unfinished: u64,
+    ) -> Result<Option<ExportJob>, DbErr> {
+        let trx = self.pool.begin().await?;
+
+        // lock the user until commit, so concurrent requests of the user count one after
+        // another. sqlite has no row locks, there the insert takes the write lock before
+        // counting instead
+        Users::find_by_id(user_id.clone())
+            .lock_exclusive()
+            .one(&trx)
+            .await?;
+
+        let job = ExportJobs::insert(ExportJobsActiveModel {
+            id: Set(nanoid!()),
+            workspace_id: Set(workspace_id),
+            user_id: Set(user_id.clone()),
+            format: Set(format.as_str().to_owned()),
+            status: Set(ExportJobStatus::Pending as i16),
+            progress: Set(0),
+            created_at: Set(Some(Utc::now().into())),
+            ..Default::default()
+        })
+        .exec_with_returning(&trx)
+        .await?;
+
+        // the new job included
+        let unfinished = ExportJobs::find()
+            .filter(ExportJobsColumn::UserId.eq(user_id))
+            .filter(ExportJobsColumn::Status.is_in([
+                ExportJobStatus::Pending as i16,
+                ExportJobStatus::Running as i16,
+            ]))
+            .count(&trx)
+            .await?;
+        if unfinished > max_unfinished {
+            trx.rollback().await?;
+            return Ok(None);
+        }
+
+        trx.commit().await?;
+
+        Ok(Some(job.into()))
+    }
+
+    pub async fn get_export_job(&self, job_id: String) -> Result<Option<ExportJob>, DbErr> {
+        ExportJobs::find()
+            .filter(ExportJobsColumn::Id.eq(job_id))
+            .one(&self.pool)
+            .await
+            .map(|job| job.map(|job| job.into()))
+    }
+
+    /// Jobs pending or interrupted while running, oldest first.
+    pub async fn get_unfinished_export_jobs(&self) -> Result<Vec<ExportJob>, DbErr> {
+        ExportJobs::find()
+            .filter(ExportJobsColumn::Status.is_in([
+                ExportJobStatus::Pending as i16,
+                ExportJobStatus::Running as i16,
+            ]))
+            .order_by_asc(ExportJobsColumn::CreatedAt)
+            .all(&self.pool)
+            .await
+            .map(|jobs| jobs.into_iter().map(|job| job.into()).collect())
+    }
+
+    /// Mark a job as running from the start, `None` if it doesn't exist or is finished.
+    pub async fn start_export_job(&self, job_id: String) -> Result<Option<ExportJob>, DbErr> {
+        let started = ExportJobs::update_many()
+            .set(ExportJobsActiveModel {
+                status: Set(ExportJobStatus::Running as i16),
+                progress: Set(0),
+                ..Default::default()
+            })
+            .filter(ExportJobsColumn::Id.eq(job_id.clone()))
+            .filter(ExportJobsColumn::Status.is_in([
+                ExportJobStatus::Pending as i16,
+                ExportJobStatus::Running as i16,
+            ]))
+            .exec(&self.pool)
+            .await?;
+        if started.rows_affected == 0 {
+            return Ok(None);
+        }
+
+        self.get_export_job(job_id).await
+    }
+
+    pub async fn update_export_job_progress(
+        &self,
+        job_id: String,
+        progress: i16,
+    ) -> Result<(), DbErr> {
+        ExportJobs::update_many()
+            .set(ExportJobsActiveModel {
+                progress: Set(progress.clamp(0, 100)),
+                ..Default::default()
+            })
+            .filter(ExportJobsColumn::Id.eq(job_id))
+            .exec(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    /// Record the hash of the result blob of a job, or why it failed.
+    pub async fn finish_export_job(
+        &self,
+        job_id: String,
+        result: Result<String, String>,
+    ) -> Result<(), DbErr> {
+        let finished = match result {
+            Ok(blob) => ExportJobsActiveModel {
+                status: Set(ExportJobStatus::Done as i16),
+                progress: Set(100),
+                blob: Set(Some(blob)),
+                ..Default::default()
+            },
+            Err(error) => ExportJobsActiveModel {
+                status: Set(ExportJobStatus::Failed as i16),
+                error: Set(Some(error)),
+                ..Default::default()
+            },
+        };
+        ExportJobs::update_many()
+            .set(ExportJobsActiveModel {
+                finished_at: Set(Some(Utc::now().into())),
+                ..finished
+            })
+            .filter(ExportJobsColumn::Id.eq(job_id))
+            .exec(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    /// Delete the jobs finished before `finished_before`, returns the result blobs
+    /// that no remaining job refers to.
+    pub async fn delete_expired_export_jobs(
+        &self,
+        finished_before: DateTimeUtc,
+    ) -> Result<Vec<String>, DbErr> {
+        let trx = self.pool.begin().await?;
+
+        let expired = ExportJobs::find()
+            .filter(ExportJobsColumn::FinishedAt.lt(finished_before))
+            .all(&trx)
+            .await?;
+        if expired.is_empty() {
+            return Ok(vec![]);
+        }
+        ExportJobs::delete_many()
+            .filter(ExportJobsColumn::Id.is_in(expired.iter().map(|job| job.id.clone())))
+            .exec(&trx)
+            .await?;
+
+        // identical exports share their blob
+        let blobs = expired
+            .into_iter()
+            .filter_map(|job| job.blob)
+            .collect::<HashSet<_>>();
+        let referenced = ExportJobs::find()
+            .filter(ExportJobsColumn::Blob.is_in(blobs.iter().cloned()))
+            .all(&trx)
+            .await?
+            .into_iter()
+            .filter_map(|job| job.blob)
+            .collect::<HashSet<_>>();
+
+        trx.commit().await?;
+
+        Ok(blobs.difference(&referenced).cloned().collect())
+    }
+
+    /// Store `event` once for each recipient, the actor of the event is not notified
+    /// of it. Returns the created notifications.
+    pub async fn create_notifications(
+        &self,
+        event: &NotificationEvent,
+        recipients: &[String],
+    ) -> Result<Vec<Notification>, DbErr> {
+        let recipients = recipients
+            .iter()
+            .filter(|user_id| event.actor.as_ref() != Some(*user_id))
+            .collect::<BTreeSet<_>>();
+        if recipients.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let trx = self.pool.begin().await?;
+
+        let mut notifications = Vec::with_capacity(recipients.len());
+        for user_id in recipients {
+            let notification = Notifications::insert(NotificationsActiveModel {
+                id: Set(nanoid!()),
+                user_id: Set(user_id.clone()),
+                workspace_id: Set(event.workspace_id.clone()),
+                kind: Set(event.kind as i16),
+                actor: Set(event.actor.clone()),
+                block_id: Set(event.block_id.clone()),
+                read: Set(false),
+                created_at: Set(Some(Utc::now().into())),
+            })
+            .exec_with_returning(&trx)
+            .await?;
+            notifications.push(notification.into());
+        }
+
+        trx.commit().await?;
+
+        Ok(notifications)
+    }
+
+    /// Notifications of a user, newest first.
+    pub async fn get_notifications(
+        &self,
+        user_id: String,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<Notification>, DbErr> {
+        Notifications::find()
+            .filter(NotificationsColumn::UserId.eq(user_id))
+            .order_by_desc(NotificationsColumn::CreatedAt)
+            .order_by_desc(NotificationsColumn::Id)
+            .offset(offset)
+            .limit(limit)
+            .all(&self.pool)
+            .await
+            .map(|notifications| {
+                notifications
+                    .into_iter()
+                    .map(|notification| notification.into())
+                    .collect()
+            })
+    }
+
+    pub async fn count_unread_notifications(&self, user_id: String) -> Result<u64, DbErr> {
+        Notifications::find()
+            .filter(NotificationsColumn::UserId.eq(user_id))
+            .filter(NotificationsColumn::Read.eq(false))
+            .count(&self.pool)
+            .await
+    }
+
+    /// Mark notifications of a user as read, all of them if `ids` is `None`.
+    /// Returns how many were unread.
+    pub async fn mark_notifications_read(
+        &self,
+        user_id: String,
+        ids: Option<Vec<String>>,
+    ) -> Result<u64, DbErr> {
+        let mut update = Notifications::update_many()
+            .set(NotificationsActiveModel {
+                read: Set(true),
+                ..Default::default()
+            })
+            .filter(NotificationsColumn::UserId.eq(user_id))
+            .filter(NotificationsColumn::Read.eq(false));
+        if let Some(ids) = ids {
+            update = update.filter(NotificationsColumn::Id.is_in(ids));
+        }
+        update.exec(&self.pool).await.map(|res| res.rows_affected)
+    }
 }
 
 #[cfg(test)]
@@ -723,6 +1085,8 @@ mod tests {
         assert_eq!(workspace_owner.id, new_user.id);
         assert_eq!(new_workspace.public, false);
         assert_eq!(is_published, false);
+        assert!(pool.workspace_exists(new_workspace.id.clone()).await?);
+        assert!(!pool.workspace_exists("missing".into()).await?);
         new_workspace = pool
             .update_workspace(new_workspace.id.clone(), UpdateWorkspace { public: true })
             .await
@@ -1016,4 +1380,222 @@ mod tests {
         // assert_eq!(user3_can_not_read_workspace, false);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn database_oauth_login() -> anyhow::Result<()> {
+        use super::*;
+        let pool = CloudDatabase::init_pool("sqlite::memory:").await?;
+
+        let identity = OAuthIdentity {
+            provider: "okta".to_string(),
+            subject: "00u1".to_string(),
+            email: "oidc@xxx.xx".to_string(),
+            name: "oidc".to_string(),
+            picture: None,
+        };
+
+        let OAuthLogin::User(created) = pool.oauth_user_login(&identity).await? else {
+            panic!("should create user");
+        };
+        assert_eq!(created.email, identity.email);
+        assert_eq!(pool.get_user_workspaces(created.id.clone()).await?.len(), 1);
+
+        let OAuthLogin::User(logged) = pool.oauth_user_login(&identity).await? else {
+            panic!("should login linked user");
+        };
+        assert_eq!(logged.id, created.id);
+
+        // same email from another provider must not be merged
+        let other = OAuthIdentity {
+            provider: "azure".to_string(),
+            subject: "abc".to_string(),
+            ..identity.clone()
+        };
+        assert!(matches!(
+            pool.oauth_user_login(&other).await?,
+            OAuthLogin::Conflict { email } if email == identity.email
+        ));
+
+        // neither with a password user
+        pool.create_user(CreateUser {
+            avatar_url: None,
+            email: "password@xxx.xx".to_string(),
+            name: "password".to_string(),
+            password: "xxx".to_string(),
+        })
+        .await?
+        .unwrap();
+        let other = OAuthIdentity {
+            email: "password@xxx.xx".to_string(),
+            ..other
+        };
+        assert!(matches!(
+            pool.oauth_user_login(&other).await?,
+            OAuthLogin::Conflict { .. }
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn database_export_jobs() -> anyhow::Result<()> {
+        use super::*;
+        let pool = CloudDatabase::init_pool("sqlite::memory:").await?;
+        let (user, _) = pool
+            .create_user(CreateUser {
+                avatar_url: None,
+                email: "export@xxx.xx".to_string(),
+                name: "export".to_string(),
+                password: "xxx".to_string(),
+            })
+            .await?
+            .unwrap();
+        let workspace = pool.create_normal_workspace(user.id.clone()).await?;
+
+        let create =
+            |format| pool.create_export_job(user.id.clone(), workspace.id.clone(), format, 2);
+        let first = create(ExportFormat::Markdown).await?.unwrap();
+        let second = create(ExportFormat::Archive).await?.unwrap();
+        assert_eq!(first.status, ExportJobStatus::Pending);
+        // only two unfinished jobs per user
+        assert!(create(ExportFormat::Html).await?.is_none());
+
+        // an interrupted job is resumed from the start
+        pool.start_export_job(first.id.clone()).await?.unwrap();
+        pool.update_export_job_progress(first.id.clone(), 50)
+            .await?;
+        let unfinished = pool.get_unfinished_export_jobs().await?;
+        assert_eq!(
+            unfinished.iter().map(|job| &job.id).collect::<Vec<_>>(),
+            vec![&first.id, &second.id]
+        );
+        assert_eq!(unfinished[0].status, ExportJobStatus::Running);
+        let restarted = pool.start_export_job(first.id.clone()).await?.unwrap();
+        assert_eq!(restarted.progress, 0);
+
+        pool.finish_export_job(first.id.clone(), Ok("hash".into()))
+            .await?;
+        pool.finish_export_job(second.id.clone(), Err("failed".into()))
+            .await?;
+        let done = pool.get_export_job(first.id.clone()).await?.unwrap();
+        assert_eq!(done.status, ExportJobStatus::Done);
+        assert_eq!(done.progress, 100);
+        assert_eq!(done.blob.as_deref(), Some("hash"));
+        assert!(pool.start_export_job(first.id.clone()).await?.is_none());
+        assert!(pool.get_unfinished_export_jobs().await?.is_empty());
+        assert!(create(ExportFormat::Html).await?.is_some());
+
+        let expired = pool
+            .delete_expired_export_jobs(Utc::now() + chrono::Duration::seconds(1))
+            .await?;
+        assert_eq!(expired, vec!["hash".to_string()]);
+        assert!(pool.get_export_job(first.id).await?.is_none());
+        assert_eq!(pool.get_unfinished_export_jobs().await?.len(), 1);
+
+        // concurrent requests can't exceed the limit
+        let (a, b, c) = tokio::join!(
+            create(ExportFormat::Markdown),
+            create(ExportFormat::Archive),
+            create(ExportFormat::Html)
+        );
+        assert_eq!([a?, b?, c?].iter().flatten().count(), 1);
+        assert_eq!(pool.get_unfinished_export_jobs().await?.len(), 2);
+
+        Ok(())
+    }
+    #[tokio::test]
+    async fn database_notifications() -> anyhow::Result<()> {
+        use super::*;
+        use crate::NotificationKind;
+        let pool = CloudDatabase::init_pool("sqlite::memory:").await?;
+        let mut users = vec![];
+        for name in ["owner", "member", "other"] {
+            let (user, _) = pool
+                .create_user(CreateUser {
+                    avatar_url: None,
+                    email: format!("{name}@xxx.xx"),
+                    name: name.to_string(),
+                    password: "xxx".to_string(),
+                })
+                .await?
+                .unwrap();
+            users.push(user.id);
+        }
+        let (owner, member, other) = (users[0].clone(), users[1].clone(), users[2].clone());
+        let workspace = pool.create_normal_workspace(owner.clone()).await?;
+
+        // one notification per recipient, the actor is left out
+        let event = NotificationEvent {
+            kind: NotificationKind::Mention,
+            workspace_id: workspace.id.clone(),
+            actor: Some(owner.clone()),
+            block_id: Some("block".into()),
+        };
+        let created = pool
+            .create_notifications(
+                &event,
+                &[member.clone(), other.clone(), member.clone(), owner.clone()],
+            )
+            .await?;
+        let mut recipients = created
+            .iter()
+            .map(|notification| notification.user_id.clone())
+            .collect::<Vec<_>>();
+        recipients.sort();
+        let mut expected = vec![member.clone(), other.clone()];
+        expected.sort();
+        assert_eq!(recipients, expected);
+        assert!(created
+            .iter()
+            .all(|notification| notification.event == event && !notification.read));
+        assert!(pool
+            .get_notifications(owner.clone(), 0, 10)
+            .await?
+            .is_empty());
+
+        pool.create_notifications(
+            &NotificationEvent {
+                kind: NotificationKind::InvitationAccepted,
+                workspace_id: workspace.id.clone(),
+                actor: Some(other.clone()),
+                block_id: None,
+            },
+            &[member.clone()],
+        )
+        .await?;
+        assert_eq!(pool.count_unread_notifications(member.clone()).await?, 2);
+        let first = pool.get_notifications(member.clone(), 0, 1).await?;
+        let second = pool.get_notifications(member.clone(), 1, 1).await?;
+        assert_eq!(first.len(), 1);
+        assert_eq!(second.len(), 1);
+        assert_ne!(first[0].id, second[0].id);
+        assert!(pool
+            .get_notifications(member.clone(), 2, 1)
+            .await?
+            .is_empty());
+
+        // marked by id or all at once
+        assert_eq!(
+            pool.mark_notifications_read(member.clone(), Some(vec![first[0].id.clone()]))
+                .await?,
+            1
+        );
+        assert_eq!(pool.count_unread_notifications(member.clone()).await?, 1);
+        // only the notifications of the user
+        assert_eq!(
+            pool.mark_notifications_read(other.clone(), Some(vec![second[0].id.clone()]))
+                .await?,
+            0
+        );
+        assert_eq!(pool.mark_notifications_read(member.clone(), None).await?, 1);
+        assert_eq!(pool.count_unread_notifications(member.clone()).await?, 0);
+        assert!(pool
+            .get_notifications(member.clone(), 0, 10)
+            .await?
+            .iter()
+            .all(|notification| notification.read));
+        assert_eq!(pool.count_unread_notifications(other).await?, 1);
+
+        Ok(())
+    }
 }
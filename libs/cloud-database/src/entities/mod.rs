@@ -2,7 +2,10 @@
 
 pub mod prelude;
 
+pub mod export_jobs;
 pub mod google_users;
+pub mod notifications;
+pub mod oauth_users;
 pub mod permissions;
 pub mod users;
 pub mod workspaces;
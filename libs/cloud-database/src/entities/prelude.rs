@@ -1,6 +1,9 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.10.7
 
+pub use super::export_jobs::Entity as ExportJobs;
 pub use super::google_users::Entity as GoogleUsers;
+pub use super::notifications::Entity as Notifications;
+pub use super::oauth_users::Entity as OAuthUsers;
 pub use super::permissions::Entity as Permissions;
 pub use super::users::Entity as Users;
 pub use super::workspaces::Entity as Workspaces;
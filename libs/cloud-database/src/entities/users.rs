@@ -20,6 +20,8 @@ pub struct Model {
 pub enum Relation {
     #[sea_orm(has_many = "super::google_users::Entity")]
     GoogleUsers,
+    #[sea_orm(has_many = "super::oauth_users::Entity")]
+    OAuthUsers,
     #[sea_orm(has_many = "super::permissions::Entity")]
     Permissions,
 }
@@ -30,6 +32,12 @@ impl Related<super::google_users::Entity> for Entity {
     }
 }
 
+impl Related<super::oauth_users::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::OAuthUsers.def()
+    }
+}
+
 impl Related<super::permissions::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Permissions.def()
@@ -20,3 +20,11 @@ type PermissionColumn = <Permissions as EntityTrait>::Column;
 type GoogleUsersModel = <GoogleUsers as EntityTrait>::Model;
 type GoogleUsersActiveModel = entities::google_users::ActiveModel;
 type GoogleUsersColumn = <GoogleUsers as EntityTrait>::Column;
+type OAuthUsersActiveModel = entities::oauth_users::ActiveModel;
+type OAuthUsersColumn = <OAuthUsers as EntityTrait>::Column;
+type ExportJobsModel = <ExportJobs as EntityTrait>::Model;
+type ExportJobsActiveModel = entities::export_jobs::ActiveModel;
+type ExportJobsColumn = <ExportJobs as EntityTrait>::Column;
+type NotificationsModel = <Notifications as EntityTrait>::Model;
+type NotificationsActiveModel = entities::notifications::ActiveModel;
+type NotificationsColumn = <Notifications as EntityTrait>::Column;
@@ -1,7 +1,7 @@
 // use super::*;
 // use sqlx::{postgres::PgRow, FromRow, Result, Row};
 
-use chrono::naive::serde::{ts_milliseconds, ts_seconds};
+use chrono::naive::serde::{ts_milliseconds, ts_milliseconds_option, ts_seconds};
 use chrono::{DateTime, Utc};
 use jwst_logger::error;
 use schemars::{JsonSchema, JsonSchema_repr};
@@ -44,6 +44,21 @@ pub struct UserWithNonce {
     pub token_nonce: i16,
 }
 
+impl From<crate::UsersModel> for UserWithNonce {
+    fn from(user: crate::UsersModel) -> Self {
+        Self {
+            token_nonce: user.token_nonce.unwrap_or_default(),
+            user: User {
+                id: user.id,
+                name: user.name,
+                email: user.email,
+                avatar_url: user.avatar_url,
+                created_at: user.created_at.unwrap_or_default().naive_local(),
+            },
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct UserQuery {
     pub email: Option<String>,
@@ -81,6 +96,26 @@ pub struct CreateUser {
     pub password: String,
 }
 
+/// An identity verified by an external OAuth/OIDC provider
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct OAuthIdentity {
+    // name of the configured provider
+    pub provider: String,
+    // `sub` claim of the ID token
+    pub subject: String,
+    pub email: String,
+    pub name: String,
+    pub picture: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum OAuthLogin {
+    User(crate::UsersModel),
+    // the email is already registered through another sign in method,
+    // accounts are never merged silently
+    Conflict { email: String },
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct UserToken {
     pub token: String,
@@ -392,3 +427,163 @@ pub struct BigId {
 pub struct Count {
     pub count: i64,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// The doc and the blobs of the workspace, zipped.
+    Archive,
+    Markdown,
+    Html,
+}
+
+impl ExportFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExportFormat::Archive => "archive",
+            ExportFormat::Markdown => "markdown",
+            ExportFormat::Html => "html",
+        }
+    }
+}
+
+impl From<&str> for ExportFormat {
+    fn from(format: &str) -> Self {
+        match format {
+            "markdown" => ExportFormat::Markdown,
+            "html" => ExportFormat::Html,
+            "archive" => ExportFormat::Archive,
+            _ => {
+                error!("invalid export format: {}", format);
+                ExportFormat::Archive
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+#[repr(i16)]
+pub enum ExportJobStatus {
+    Pending = 0,
+    Running = 1,
+    Done = 2,
+    Failed = 3,
+}
+
+impl From<i16> for ExportJobStatus {
+    fn from(i: i16) -> Self {
+        match i {
+            0 => ExportJobStatus::Pending,
+            1 => ExportJobStatus::Running,
+            2 => ExportJobStatus::Done,
+            3 => ExportJobStatus::Failed,
+            _ => {
+                error!("invalid export job status: {}", i);
+                ExportJobStatus::Failed
+            }
+        }
+    }
+}
+
+/// An export of a workspace run in the background, its result is kept in the blob
+/// storage until the job expires.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ExportJob {
+    pub id: String,
+    pub workspace_id: String,
+    pub user_id: String,
+    pub format: ExportFormat,
+    pub status: ExportJobStatus,
+    /// Percent of the export done.
+    pub progress: i16,
+    /// Hash of the result in the blob storage once the job is done.
+    pub blob: Option<String>,
+    pub error: Option<String>,
+    #[serde(with = "ts_milliseconds")]
+    #[schemars(with = "i64")]
+    pub created_at: NaiveDateTime,
+    #[serde(with = "ts_milliseconds_option")]
+    #[schemars(with = "Option<i64>")]
+    pub finished_at: Option<NaiveDateTime>,
+}
+
+impl From<crate::ExportJobsModel> for ExportJob {
+    fn from(job: crate::ExportJobsModel) -> Self {
+        Self {
+            id: job.id,
+            workspace_id: job.workspace_id,
+            user_id: job.user_id,
+            format: job.format.as_str().into(),
+            status: job.status.into(),
+            progress: job.progress,
+            blob: job.blob,
+            error: job.error,
+            created_at: job.created_at.unwrap_or_default().naive_utc(),
+            finished_at: job.finished_at.map(|finished| finished.naive_utc()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+#[repr(i16)]
+pub enum NotificationKind {
+    /// A block property names the user, see the `block_id` of the notification.
+    Mention = 0,
+    /// A user accepted an invitation to a workspace the user owns.
+    InvitationAccepted = 1,
+}
+
+impl From<i16> for NotificationKind {
+    fn from(i: i16) -> Self {
+        match i {
+            0 => NotificationKind::Mention,
+            1 => NotificationKind::InvitationAccepted,
+            _ => {
+                error!("invalid notification kind: {}", i);
+                NotificationKind::Mention
+            }
+        }
+    }
+}
+
+/// Something that happened in a workspace, stored once for each user notified of it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct NotificationEvent {
+    pub kind: NotificationKind,
+    pub workspace_id: String,
+    /// User who caused the event, if known.
+    pub actor: Option<String>,
+    pub block_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Notification {
+    pub id: String,
+    /// The recipient.
+    pub user_id: String,
+    #[serde(flatten)]
+    pub event: NotificationEvent,
+    pub read: bool,
+    #[serde(with = "ts_milliseconds")]
+    #[schemars(with = "i64")]
+    pub created_at: NaiveDateTime,
+}
+
+impl From<crate::NotificationsModel> for Notification {
+    fn from(notification: crate::NotificationsModel) -> Self {
+        Self {
+            id: notification.id,
+            user_id: notification.user_id,
+            event: NotificationEvent {
+                kind: notification.kind.into(),
+                workspace_id: notification.workspace_id,
+                actor: notification.actor,
+                block_id: notification.block_id,
+            },
+            read: notification.read,
+            created_at: notification.created_at.unwrap_or_default().naive_utc(),
+        }
+    }
+}
@@ -101,5 +101,16 @@ Subscription<UpdateEvent> *workspace_observe(JWSTWorkspace *workspace,
 
 void workspace_unobserve(Subscription<UpdateEvent> *subscription);
 
+/**
+ * `func` receives the blocks changed by each committed transaction as JSON,
+ * the string is only valid during the call. It is null if the changes can't be
+ * passed as a C string.
+ */
+ChangesSubscription *workspace_observe_changes(JWSTWorkspace *workspace,
+                                               void *env,
+                                               void (*func)(void*, const char*));
+
+void workspace_unobserve_changes(ChangesSubscription *subscription);
+
 
 #endif            
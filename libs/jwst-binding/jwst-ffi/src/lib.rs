@@ -1,4 +1,4 @@
-use jwst::{Block, Workspace};
+use jwst::{Block, ChangesSubscription, Workspace};
 use lib0::any::Any;
 use std::{
     ffi::{c_void, CStr, CString},
@@ -338,3 +338,27 @@ pub unsafe extern "C" fn workspace_observe(
 pub unsafe extern "C" fn workspace_unobserve(subscription: *mut Subscription<UpdateEvent>) {
     drop(Box::from_raw(subscription))
 }
+
+/// `func` receives the blocks changed by each committed transaction as JSON,
+/// the string is only valid during the call. It is null if the changes can't be
+/// passed as a C string.
+#[no_mangle]
+pub unsafe extern "C" fn workspace_observe_changes(
+    workspace: *mut Workspace,
+    env: *mut c_void,
+    func: extern "C" fn(*mut c_void, *const c_char),
+) -> *mut ChangesSubscription {
+    Box::into_raw(Box::new(workspace.as_mut().unwrap().observe_changes(
+        move |_, changes| {
+            let json = serde_json::to_string(changes)
+                .ok()
+                .and_then(|json| CString::new(json).ok());
+            func(env, json.as_ref().map_or(ptr::null(), |json| json.as_ptr()))
+        },
+    )))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn workspace_unobserve_changes(subscription: *mut ChangesSubscription) {
+    drop(Box::from_raw(subscription))
+}
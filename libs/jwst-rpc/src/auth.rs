@@ -0,0 +1,218 @@
+use super::*;
+use lib0::{
+    decoding::{Cursor, Read},
+    encoding::Write,
+};
+use y_sync::sync::Message as YMessage;
+use yrs::updates::{decoder::Decode, encoder::Encode};
+
+/// Custom sync message sent by the client in reply to the auth challenge, carries its token.
+pub(crate) const MSG_AUTH: u8 = 104;
+
+/// Default time a client has to answer the auth challenge of [handle_authenticated_socket].
+pub const DEFAULT_AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Reason of the [YMessage::Auth] challenge sent before the client is authenticated.
+pub const AUTH_REQUIRED: &str = "authentication required";
+
+/// Frame answering the auth challenge with `token`.
+pub fn encode_auth(token: &str) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.write_string(token);
+    YMessage::Custom(MSG_AUTH, buf).encode_v1()
+}
+
+fn decode_auth(binary: &[u8]) -> Option<String> {
+    match YMessage::decode_v1(binary).ok()? {
+        YMessage::Custom(MSG_AUTH, data) => {
+            let mut cursor = Cursor::new(&data);
+            Some(cursor.read_string().ok()?.to_owned())
+        }
+        _ => None,
+    }
+}
+
+// next binary frame, `None` if the client disconnects first
+async fn next_binary(socket: &mut WebSocket) -> Option<Vec<u8>> {
+    loop {
+        match socket.next().await? {
+            Ok(Message::Binary(binary)) => return Some(binary),
+            Ok(Message::Ping(_) | Message::Pong(_)) => continue,
+            Ok(_) | Err(_) => return None,
+        }
+    }
+}
+
+// challenge the client and resolve its token to its identifier
+async fn authenticate(
+    socket: &mut WebSocket,
+    workspace_id: &str,
+    context: &Arc<impl ContextImpl<'static> + Send + Sync + 'static>,
+) -> Option<String> {
+    let challenge = YMessage::Auth(Some(AUTH_REQUIRED.into())).encode_v1();
+    socket.send(Message::Binary(challenge)).await.ok()?;
+
+    let token = match timeout(context.auth_timeout(), next_binary(socket)).await {
+        Ok(Some(binary)) => decode_auth(&binary),
+        Ok(None) => return None,
+        Err(_) => {
+            debug!("{workspace_id} auth timed out");
+            None
+        }
+    };
+    let identifier = match token {
+        Some(token) => context.authenticate(workspace_id, &token).await,
+        None => None,
+    };
+
+    let result = match &identifier {
+        Some(_) => YMessage::Auth(None),
+        None => YMessage::Auth(Some("permission denied".into())),
+    };
+    socket
+        .send(Message::Binary(result.encode_v1()))
+        .await
+        .ok()?;
+    identifier
+}
+
+/// Like [handle_socket], but the client is identified in-band instead of by the request
+/// that opened the socket, so its token doesn't end up in the access logs.
+///
+/// The server sends a [YMessage::Auth] challenge with the reason [AUTH_REQUIRED], the client
+/// replies with its token encoded by [encode_auth], which [ContextImpl::authenticate]
+/// resolves. The result is sent as a [YMessage::Auth] too, permission granted or denied,
+/// and only then is the workspace joined. Sockets that don't answer within
+/// [ContextImpl::auth_timeout] are closed.
+pub async fn handle_authenticated_socket(
+    mut socket: WebSocket,
+    workspace_id: String,
+    context: Arc<impl ContextImpl<'static> + Send + Sync + 'static>,
+) {
+    match authenticate(&mut socket, &workspace_id, &context).await {
+        Some(identifier) => handle_socket(socket, workspace_id, context, identifier).await,
+        None => {
+            info!("{} rejected an unauthenticated socket", workspace_id);
+            let _ = socket.close().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{client::prepare_connection, Channels};
+    use axum::{
+        extract::{ws::WebSocketUpgrade, Path, State},
+        routing::get,
+        Router,
+    };
+    use std::net::SocketAddr;
+    use tokio_tungstenite::tungstenite::Message as ClientMessage;
+
+    struct TestContext {
+        storage: JwstStorage,
+        channel: Channels,
+    }
+
+    #[async_trait]
+    impl ContextImpl<'_> for TestContext {
+        fn get_storage(&self) -> &JwstStorage {
+            &self.storage
+        }
+
+        fn get_channel(&self) -> &Channels {
+            &self.channel
+        }
+
+        fn auth_timeout(&self) -> Duration {
+            Duration::from_millis(200)
+        }
+
+        async fn authenticate(&self, _workspace_id: &str, token: &str) -> Option<String> {
+            (token == "valid").then(|| "user".into())
+        }
+    }
+
+    async fn server() -> SocketAddr {
+        let context = Arc::new(TestContext {
+            storage: JwstStorage::new("sqlite::memory:").await.unwrap(),
+            channel: Default::default(),
+        });
+        let app = Router::new()
+            .route(
+                "/collaboration/:workspace",
+                get(
+                    |ws: WebSocketUpgrade,
+                     Path(workspace): Path<String>,
+                     State(context): State<Arc<TestContext>>| async move {
+                        ws.on_upgrade(move |socket| {
+                            handle_authenticated_socket(socket, workspace, context)
+                        })
+                    },
+                ),
+            )
+            .with_state(context);
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    async fn next_message(socket: &mut crate::client::Socket) -> Option<YMessage> {
+        match timeout(Duration::from_secs(1), socket.next()).await {
+            Ok(Some(Ok(ClientMessage::Binary(binary)))) => YMessage::decode_v1(&binary).ok(),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn auth_message_codec() {
+        assert_eq!(decode_auth(&encode_auth("token")), Some("token".into()));
+        assert_eq!(decode_auth(&YMessage::Auth(None).encode_v1()), None);
+    }
+
+    #[tokio::test]
+    async fn authenticate_in_band() {
+        let addr = server().await;
+        let url = format!("ws://{addr}/collaboration/test");
+
+        let mut socket = prepare_connection(&url).await.unwrap();
+        assert_eq!(
+            next_message(&mut socket).await,
+            Some(YMessage::Auth(Some(AUTH_REQUIRED.into())))
+        );
+        socket
+            .send(ClientMessage::Binary(encode_auth("valid")))
+            .await
+            .unwrap();
+        assert_eq!(next_message(&mut socket).await, Some(YMessage::Auth(None)));
+        // joined the workspace, the sync starts
+        assert!(matches!(
+            next_message(&mut socket).await,
+            Some(YMessage::Sync(_))
+        ));
+
+        let mut socket = prepare_connection(&url).await.unwrap();
+        next_message(&mut socket).await.unwrap();
+        socket
+            .send(ClientMessage::Binary(encode_auth("invalid")))
+            .await
+            .unwrap();
+        assert_eq!(
+            next_message(&mut socket).await,
+            Some(YMessage::Auth(Some("permission denied".into())))
+        );
+        assert_eq!(next_message(&mut socket).await, None);
+
+        // closed if the challenge isn't answered in time
+        let mut socket = prepare_connection(&url).await.unwrap();
+        next_message(&mut socket).await.unwrap();
+        assert_eq!(
+            next_message(&mut socket).await,
+            Some(YMessage::Auth(Some("permission denied".into())))
+        );
+        assert_eq!(next_message(&mut socket).await, None);
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use y_sync::{
+    awareness::{AwarenessUpdate, AwarenessUpdateEntry},
+    sync::{Message, MessageReader},
+};
+use yrs::{
+    block::ClientID,
+    updates::{decoder::DecoderV1, encoder::Encode},
+};
+
+// frames a connection can send at once after being idle
+const BURST: f64 = 1.0;
+
+/// Clients whose state a frame carries, `None` if the frame has anything
+/// but awareness updates and must not be throttled.
+pub(crate) fn awareness_clients(binary: &[u8]) -> Option<Vec<ClientID>> {
+    let mut decoder = DecoderV1::from(binary);
+    let mut clients = vec![];
+    for message in MessageReader::new(&mut decoder) {
+        match message {
+            Ok(Message::Awareness(update)) => clients.extend(update.clients.keys()),
+            _ => return None,
+        }
+    }
+    (!clients.is_empty()).then_some(clients)
+}
+
+/// Token bucket limiting the awareness frames of a connection that are applied to
+/// `rate` per second, see [ContextImpl::awareness_rate_limit].
+///
+/// Awareness is last-writer-wins per client, so the states of frames arriving without
+/// a token are merged into the ones waiting for the next token instead of queueing
+/// behind them, and are applied together as a single frame.
+///
+/// [ContextImpl::awareness_rate_limit]: crate::ContextImpl::awareness_rate_limit
+pub(crate) struct AwarenessLimiter {
+    // tokens per second, 0 disables the limit
+    rate: f64,
+    tokens: f64,
+    refilled: Instant,
+    pending: HashMap<ClientID, AwarenessUpdateEntry>,
+}
+
+impl AwarenessLimiter {
+    pub fn new(rate: u32) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: BURST,
+            refilled: Instant::now(),
+            pending: HashMap::new(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(BURST);
+        self.refilled = now;
+    }
+
+    fn take_token(&mut self) -> bool {
+        self.refill();
+        // tolerate rounding, the deadline is computed from the same values
+        if self.tokens >= 1.0 - f64::EPSILON {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn keep(&mut self, frame: &[u8]) {
+        let mut decoder = DecoderV1::from(frame);
+        for message in MessageReader::new(&mut decoder) {
+            if let Ok(Message::Awareness(update)) = message {
+                for (client, entry) in update.clients {
+                    match self.pending.get(&client) {
+                        // a reordered frame must not roll the client back
+                        Some(kept) if kept.clock > entry.clock => {}
+                        _ => {
+                            self.pending.insert(client, entry);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// The frame if it can be applied now, otherwise its states are kept until
+    /// [AwarenessLimiter::deadline].
+    pub fn push(&mut self, frame: Vec<u8>) -> Option<Vec<u8>> {
+        if self.rate == 0.0 || (self.pending.is_empty() && self.take_token()) {
+            Some(frame)
+        } else {
+            self.keep(&frame);
+            None
+        }
+    }
+
+    /// When the kept states can be applied.
+    pub fn deadline(&self) -> Option<Instant> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let wait = ((1.0 - self.tokens) / self.rate).max(0.0);
+        Some(self.refilled + Duration::from_secs_f64(wait))
+    }
+
+    /// The kept states as one frame once a token is available.
+    pub fn take_due(&mut self) -> Option<Vec<u8>> {
+        if !self.pending.is_empty() && self.take_token() {
+            let clients = std::mem::take(&mut self.pending);
+            Some(Message::Awareness(AwarenessUpdate { clients }).encode_v1())
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::time::{sleep, sleep_until};
+
+    fn client_frame(client: ClientID, cursor: usize) -> Vec<u8> {
+        Message::Awareness(AwarenessUpdate {
+            clients: HashMap::from([(
+                client,
+                AwarenessUpdateEntry {
+                    clock: cursor as u32,
+                    json: format!(r#"{{"cursor":{cursor}}}"#),
+                },
+            )]),
+        })
+        .encode_v1()
+    }
+
+    fn awareness_frame(cursor: usize) -> Vec<u8> {
+        client_frame(1, cursor)
+    }
+
+    #[tokio::test]
+    async fn limit_awareness() {
+        let rate = 10;
+        let mut limiter = AwarenessLimiter::new(rate);
+        let mut applied = vec![];
+
+        let started = Instant::now();
+        for cursor in 0..1000 {
+            let frame = awareness_frame(cursor);
+            assert_eq!(awareness_clients(&frame), Some(vec![1]));
+            applied.extend(limiter.push(frame));
+            applied.extend(limiter.take_due());
+            sleep(Duration::from_millis(1)).await;
+        }
+        while let Some(deadline) = limiter.deadline() {
+            sleep_until(deadline.into()).await;
+            applied.extend(limiter.take_due());
+        }
+        let elapsed = started.elapsed().as_secs_f64();
+
+        assert!(applied.len() as f64 <= BURST + rate as f64 * elapsed);
+        // the latest state is never dropped
+        assert_eq!(applied.first(), Some(&awareness_frame(0)));
+        assert_eq!(applied.last(), Some(&awareness_frame(999)));
+    }
+
+    #[tokio::test]
+    async fn coalesce_awareness_clients() {
+        let mut limiter = AwarenessLimiter::new(10);
+        assert!(limiter.push(client_frame(1, 0)).is_some());
+
+        assert!(limiter.push(client_frame(1, 1)).is_none());
+        assert!(limiter.push(client_frame(2, 5)).is_none());
+        assert!(limiter.push(client_frame(1, 2)).is_none());
+        // stale state of a client arriving late
+        assert!(limiter.push(client_frame(2, 4)).is_none());
+
+        sleep_until(limiter.deadline().unwrap().into()).await;
+        let frame = limiter.take_due().unwrap();
+        assert!(limiter.deadline().is_none());
+
+        let mut decoder = DecoderV1::from(frame.as_slice());
+        let messages = MessageReader::new(&mut decoder)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let [Message::Awareness(update)] = messages.as_slice() else {
+            panic!("expected a single awareness message");
+        };
+        let mut clients = update
+            .clients
+            .iter()
+            .map(|(client, entry)| (*client, entry.clock))
+            .collect::<Vec<_>>();
+        clients.sort();
+        // every client keeps its latest state
+        assert_eq!(clients, vec![(1, 2), (2, 5)]);
+    }
+
+    #[test]
+    fn unlimited_awareness() {
+        let mut limiter = AwarenessLimiter::new(0);
+        for cursor in 0..10 {
+            assert!(limiter.push(awareness_frame(cursor)).is_some());
+        }
+        assert!(limiter.deadline().is_none());
+    }
+
+    #[test]
+    fn sync_frames_are_not_throttled() {
+        assert_eq!(awareness_clients(&jwst::sync_encode_update(&[0, 0])), None);
+        assert_eq!(awareness_clients(&[]), None);
+    }
+}
@@ -0,0 +1,407 @@
+use super::*;
+use dashmap::DashMap;
+use jwst::JwstResult;
+use jwst_storage::{Bandwidth, BandwidthScope};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Bytes sent and received, updated for every websocket frame.
+#[derive(Debug, Default)]
+pub struct BandwidthCounter {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+impl BandwidthCounter {
+    pub fn add_sent(&self, bytes: usize) {
+        self.sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_received(&self, bytes: usize) {
+        self.received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> Bandwidth {
+        Bandwidth {
+            sent: self.sent.load(Ordering::Relaxed),
+            received: self.received.load(Ordering::Relaxed),
+        }
+    }
+
+    fn take(&self) -> Bandwidth {
+        Bandwidth {
+            sent: self.sent.swap(0, Ordering::Relaxed),
+            received: self.received.swap(0, Ordering::Relaxed),
+        }
+    }
+}
+
+/// Traffic of the websocket connections per workspace and per authenticated user, see
+/// [ContextImpl::authenticated_user], kept in memory until [BandwidthUsage::flush] adds
+/// it to the totals in the storage. Multiplexed connections count the frames of every
+/// joined workspace towards it.
+///
+/// Frames are counted as they are sent and received, the size of the websocket
+/// headers is not included.
+#[derive(Debug, Default)]
+pub struct BandwidthUsage {
+    counters: DashMap<BandwidthScope, Arc<BandwidthCounter>>,
+}
+
+impl BandwidthUsage {
+    /// Counter of the traffic that is not flushed yet.
+    pub fn counter(&self, scope: BandwidthScope) -> Arc<BandwidthCounter> {
+        self.counters.entry(scope).or_default().clone()
+    }
+
+    /// Traffic of `scope` that is not flushed yet.
+    pub fn pending(&self, scope: &BandwidthScope) -> Bandwidth {
+        self.counters
+            .get(scope)
+            .map(|counter| counter.get())
+            .unwrap_or_default()
+    }
+
+    /// Add the counted traffic to the totals in the storage, counters no connection
+    /// uses anymore are dropped.
+    pub async fn flush(&self, storage: &JwstStorage) -> JwstResult<()> {
+        let scopes = self
+            .counters
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect::<Vec<_>>();
+        self.flush_scopes(storage, &scopes).await
+    }
+
+    pub(crate) async fn flush_scopes(
+        &self,
+        storage: &JwstStorage,
+        scopes: &[BandwidthScope],
+    ) -> JwstResult<()> {
+        for scope in scopes {
+            let Some(counter) = self.counters.get(scope).map(|counter| counter.clone()) else {
+                continue;
+            };
+            let bandwidth = counter.take();
+            if let Err(e) = storage.record_bandwidth(scope, bandwidth).await {
+                // counted again at the next flush
+                counter.add_sent(bandwidth.sent as usize);
+                counter.add_received(bandwidth.received as usize);
+                return Err(e);
+            }
+        }
+        // the clone above is dropped, only connections still hold a counter
+        self.counters
+            .retain(|_, counter| Arc::strong_count(counter) > 1 || !counter.get().is_empty());
+        Ok(())
+    }
+}
+
+// counts the traffic of a websocket connection, or of a workspace joined on a multiplexed
+// connection, towards its workspace and authenticated user
+pub(crate) struct ConnectionBandwidth {
+    connection: BandwidthCounter,
+    shared: Vec<Arc<BandwidthCounter>>,
+    scopes: Vec<BandwidthScope>,
+}
+
+impl ConnectionBandwidth {
+    pub(crate) fn new(usage: Option<&BandwidthUsage>, workspace: &str, user: Option<&str>) -> Self {
+        let scopes = usage
+            .map(|_| {
+                std::iter::once(BandwidthScope::Workspace(workspace.into()))
+                    .chain(user.map(|user| BandwidthScope::User(user.into())))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            connection: BandwidthCounter::default(),
+            shared: usage
+                .map(|usage| {
+                    scopes
+                        .iter()
+                        .map(|scope| usage.counter(scope.clone()))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            scopes,
+        }
+    }
+
+    pub(crate) fn sent(&self, bytes: usize) {
+        self.connection.add_sent(bytes);
+        for counter in &self.shared {
+            counter.add_sent(bytes);
+        }
+    }
+
+    pub(crate) fn received(&self, bytes: usize) {
+        self.connection.add_received(bytes);
+        for counter in &self.shared {
+            counter.add_received(bytes);
+        }
+    }
+
+    pub(crate) fn get(&self) -> Bandwidth {
+        self.connection.get()
+    }
+
+    // flush the workspace and user of a closed connection, short connections are not
+    // lost if the server stops before the next periodic flush
+    pub(crate) async fn close(self, usage: Option<&BandwidthUsage>, storage: &JwstStorage) {
+        let Self { shared, scopes, .. } = self;
+        drop(shared);
+        if let Some(usage) = usage {
+            if let Err(e) = usage.flush_scopes(storage, &scopes).await {
+                error!("failed to flush bandwidth usage: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{client::prepare_connection, Channels};
+    use axum::{
+        extract::{ws::WebSocketUpgrade, Path, State},
+        routing::get,
+        Router,
+    };
+    use jwst::Workspace;
+    use tokio_tungstenite::tungstenite::Message as ClientMessage;
+
+    struct TestContext {
+        storage: JwstStorage,
+        channel: Channels,
+        bandwidth: BandwidthUsage,
+    }
+
+    #[async_trait]
+    impl ContextImpl<'_> for TestContext {
+        fn get_storage(&self) -> &JwstStorage {
+            &self.storage
+        }
+
+        fn get_channel(&self) -> &Channels {
+            &self.channel
+        }
+
+        fn bandwidth_usage(&self) -> Option<&BandwidthUsage> {
+            Some(&self.bandwidth)
+        }
+
+        async fn authenticated_user(
+            &self,
+            _workspace_id: &str,
+            identifier: &str,
+        ) -> Option<String> {
+            (identifier != "anonymous").then(|| identifier.to_owned())
+        }
+    }
+
+    async fn test_server(context: Arc<TestContext>) -> std::net::SocketAddr {
+        let app = Router::new()
+            .route(
+                "/collaboration/:workspace",
+                get(
+                    |ws: WebSocketUpgrade,
+                     Path(workspace): Path<String>,
+                     State(context): State<Arc<TestContext>>| async move {
+                        ws.on_upgrade(move |socket| {
+                            handle_socket(socket, workspace, context, "user".into())
+                        })
+                    },
+                ),
+            )
+            .route(
+                "/multiplex",
+                get(
+                    |ws: WebSocketUpgrade, State(context): State<Arc<TestContext>>| async move {
+                        ws.on_upgrade(move |socket| {
+                            handle_multiplexed_socket(socket, context, "anonymous".into())
+                        })
+                    },
+                ),
+            )
+            .with_state(context);
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    async fn test_context() -> Arc<TestContext> {
+        Arc::new(TestContext {
+            storage: JwstStorage::new("sqlite::memory:").await.unwrap(),
+            channel: Default::default(),
+            bandwidth: BandwidthUsage::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn count_session_traffic() {
+        let context = test_context().await;
+        let addr = test_server(context.clone()).await;
+
+        let mut socket = prepare_connection(&format!("ws://{addr}/collaboration/test"))
+            .await
+            .unwrap();
+        let client = Workspace::new("test");
+        client.with_trx(|mut t| {
+            t.create("page", "affine:page");
+        });
+        let messages = [
+            client.sync_init_message().unwrap(),
+            sync_encode_update(&client.sync_migration()),
+        ];
+        for message in &messages {
+            socket
+                .send(ClientMessage::Binary(message.clone()))
+                .await
+                .unwrap();
+        }
+        let mut received = 0;
+        while let Ok(Some(Ok(message))) = timeout(Duration::from_millis(500), socket.next()).await {
+            received += message.into_data().len();
+        }
+
+        let workspace = BandwidthScope::Workspace("test".into());
+        let expected = Bandwidth {
+            sent: received as u64,
+            received: messages.iter().map(Vec::len).sum::<usize>() as u64,
+        };
+        assert!(expected.sent > 0);
+        assert_eq!(context.bandwidth.pending(&workspace), expected);
+
+        // flushed once the connection is closed
+        socket.close(None).await.unwrap();
+        let mut stored = Bandwidth::default();
+        for _ in 0..50 {
+            stored = context.storage.bandwidth(&workspace).await.unwrap();
+            if !stored.is_empty() {
+                break;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(stored, expected);
+        assert_eq!(
+            context
+                .storage
+                .bandwidth(&BandwidthScope::User("user".into()))
+                .await
+                .unwrap(),
+            expected
+        );
+    }
+
+    #[tokio::test]
+    async fn flush_bandwidth() {
+        let storage = JwstStorage::new("sqlite::memory:").await.unwrap();
+        let usage = BandwidthUsage::default();
+        let workspace = BandwidthScope::Workspace("test".into());
+        let user = BandwidthScope::User("user".into());
+
+        let connection = ConnectionBandwidth::new(Some(&usage), "test", Some("user"));
+        connection.sent(100);
+        connection.received(10);
+        let other = usage.counter(workspace.clone());
+        other.add_sent(50);
+
+        let expected = Bandwidth {
+            sent: 150,
+            received: 10,
+        };
+        assert_eq!(usage.pending(&workspace), expected);
+        usage.flush(&storage).await.unwrap();
+        assert_eq!(usage.pending(&workspace), Bandwidth::default());
+        assert_eq!(storage.bandwidth(&workspace).await.unwrap(), expected);
+        assert_eq!(
+            storage.bandwidth(&user).await.unwrap(),
+            Bandwidth {
+                sent: 100,
+                received: 10
+            }
+        );
+
+        // unused counters are dropped once flushed
+        connection.sent(1);
+        connection.close(Some(&usage), &storage).await;
+        assert!(!usage.counters.contains_key(&user));
+        assert!(usage.counters.contains_key(&workspace));
+        drop(other);
+        usage.flush(&storage).await.unwrap();
+        assert!(usage.counters.is_empty());
+        assert_eq!(storage.bandwidth(&user).await.unwrap().sent, 101);
+    }
+
+    #[tokio::test]
+    async fn count_multiplexed_traffic() {
+        let context = test_context().await;
+        let addr = test_server(context.clone()).await;
+
+        let mut socket = prepare_connection(&format!("ws://{addr}/multiplex"))
+            .await
+            .unwrap();
+        let client = Workspace::new("test");
+        client.with_trx(|mut t| {
+            t.create("page", "affine:page");
+        });
+        let messages = [
+            MultiplexMessage::Join("test".into()).encode(),
+            MultiplexMessage::Data("test".into(), client.sync_init_message().unwrap()).encode(),
+            MultiplexMessage::Data("test".into(), sync_encode_update(&client.sync_migration()))
+                .encode(),
+        ];
+        for message in &messages {
+            socket
+                .send(ClientMessage::Binary(message.clone()))
+                .await
+                .unwrap();
+        }
+        let mut received = 0;
+        while let Ok(Some(Ok(message))) = timeout(Duration::from_millis(500), socket.next()).await {
+            received += message.into_data().len();
+        }
+
+        let workspace = BandwidthScope::Workspace("test".into());
+        let expected = Bandwidth {
+            sent: received as u64,
+            received: messages.iter().map(Vec::len).sum::<usize>() as u64,
+        };
+        assert!(expected.sent > 0);
+        assert_eq!(context.bandwidth.pending(&workspace), expected);
+        // the connection isn't authenticated
+        assert_eq!(
+            context
+                .bandwidth
+                .pending(&BandwidthScope::User("anonymous".into())),
+            Bandwidth::default()
+        );
+
+        // flushed once the workspace is left
+        socket
+            .send(ClientMessage::Binary(
+                MultiplexMessage::Leave("test".into()).encode(),
+            ))
+            .await
+            .unwrap();
+        let mut stored = Bandwidth::default();
+        for _ in 0..50 {
+            stored = context.storage.bandwidth(&workspace).await.unwrap();
+            if !stored.is_empty() {
+                break;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(stored.sent, expected.sent);
+        assert_eq!(
+            stored.received,
+            expected.received + MultiplexMessage::Leave("test".into()).encode().len() as u64
+        );
+        assert!(!context
+            .bandwidth
+            .counters
+            .contains_key(&BandwidthScope::User("anonymous".into())));
+    }
+}
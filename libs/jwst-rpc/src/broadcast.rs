@@ -1,52 +1,103 @@
-use super::{debug, error, trace, ChannelItem, ContextImpl};
+use super::{channel::Delivery, debug, trace, warn, ChannelItem, ContextImpl};
 use jwst::{sync_encode_update, MapSubscription, Workspace};
 use std::sync::Arc;
+use tokio::sync::mpsc::error::TrySendError;
 use y_sync::{
     awareness::{Event, Subscription},
     sync::Message as YMessage,
 };
 use yrs::{
     updates::encoder::{Encode, Encoder, EncoderV1},
-    UpdateSubscription,
+    StateVector, UpdateSubscription,
 };
 
-fn broadcast(
+/// Send `update` to the other connections of the workspace without waiting for them,
+/// returns how it was delivered to each of them. Closed channels are removed.
+async fn deliver(
+    current_item: &ChannelItem,
+    update: Vec<u8>,
+    context: &Arc<impl ContextImpl<'static> + Send + Sync + 'static>,
+) -> Vec<(ChannelItem, Delivery)> {
+    let deliveries = context
+        .get_channel()
+        .read()
+        .await
+        .iter()
+        .filter(|(item, _)| {
+            current_item.workspace == item.workspace && current_item.uuid != item.uuid
+        })
+        .map(|(item, tx)| {
+            trace!("sending: {:?}", item);
+            let delivery = match tx.try_send(Some(update.clone())) {
+                Ok(()) => Delivery::Delivered,
+                Err(TrySendError::Full(_)) => Delivery::Dropped,
+                Err(TrySendError::Closed(_)) => Delivery::Closed,
+            };
+            (item.clone(), delivery)
+        })
+        .collect::<Vec<_>>();
+
+    let closed = deliveries
+        .iter()
+        .filter(|(_, delivery)| *delivery == Delivery::Closed)
+        .collect::<Vec<_>>();
+    if !closed.is_empty() {
+        let mut channels = context.get_channel().write().await;
+        for (item, _) in closed {
+            channels.remove(item);
+        }
+    }
+
+    deliveries
+}
+
+/// Broadcast in the background. `before` is the state of the doc a doc update was made on,
+/// connections that miss it record it in [ChannelItem::missed] to catch up from there.
+/// Lost awareness updates are superseded by the next ones.
+pub(crate) fn broadcast(
     current_item: ChannelItem,
     update: Vec<u8>,
     context: Arc<impl ContextImpl<'static> + Send + Sync + 'static>,
+    before: Option<StateVector>,
 ) {
-    tokio::spawn(async move {
-        let mut closed = vec![];
-        trace!(
-            "{} broadcast to {}: {}bytes",
-            current_item.workspace,
-            current_item.identifier,
-            update.len()
-        );
-        for (item, tx) in context.get_channel().read().await.iter() {
-            trace!("sending: {:?}", item);
-            if current_item.workspace == item.workspace && current_item.uuid != item.uuid {
-                if tx.is_closed() {
-                    closed.push(item.clone());
-                } else if let Err(e) = tx.send(Some(update.clone())).await {
-                    if !tx.is_closed() {
-                        error!("on awareness_update error: {}", e);
-                    }
-                }
+    tokio::spawn(async move { broadcast_now(&current_item, update, &context, before).await });
+}
+
+/// Like [broadcast] but returns once the update is queued for every connection, so the
+/// updates of one sender reach the others in the order they were broadcast.
+pub(crate) async fn broadcast_now(
+    current_item: &ChannelItem,
+    update: Vec<u8>,
+    context: &Arc<impl ContextImpl<'static> + Send + Sync + 'static>,
+    before: Option<StateVector>,
+) {
+    trace!(
+        "{} broadcast to {}: {}bytes",
+        current_item.workspace,
+        current_item.identifier,
+        update.len()
+    );
+    for (item, delivery) in deliver(current_item, update, context).await {
+        if delivery == Delivery::Dropped {
+            warn!("{} channel {} is full", item.workspace, item.identifier);
+            if let Some(before) = &before {
+                item.missed.record(before);
             }
         }
-        for item in closed {
-            context.get_channel().write().await.remove(&item);
-        }
-    });
+    }
 }
 
+/// Observers broadcasting the changes of a workspace to a channel, they are removed
+/// when this is dropped.
 pub struct Subscriptions {
     _doc: Option<UpdateSubscription>,
     _awareness: Subscription<Event>,
     _metadata: MapSubscription,
 }
 
+// the subscriptions are only kept to be dropped, like the ones [Workspace] holds
+unsafe impl Send for Subscriptions {}
+
 pub fn subscribe(
     context: Arc<impl ContextImpl<'static> + Send + Sync + 'static>,
     workspace: &mut Workspace,
@@ -55,34 +106,33 @@ pub fn subscribe(
     let awareness = {
         let context = context.clone();
         let item = item.clone();
+        // the awareness frames of each connection are rate limited when they are applied,
+        // see [ContextImpl::awareness_rate_limit]
         workspace.on_awareness_update(move |awareness, e| {
+            let changed = [e.added(), e.updated(), e.removed()].concat();
             trace!(
                 "workspace awareness changed: {}, {:?}",
                 item.workspace,
-                [e.added(), e.updated(), e.removed()].concat()
+                changed
             );
-            if let Ok(update) = awareness
-                .update_with_clients([e.added(), e.updated(), e.removed()].concat())
-                .map(|update| {
-                    let mut encoder = EncoderV1::new();
-                    YMessage::Awareness(update).encode(&mut encoder);
-                    encoder.to_vec()
-                })
-            {
-                broadcast(item.clone(), update, context.clone());
+            if let Ok(update) = awareness.update_with_clients(changed) {
+                let mut encoder = EncoderV1::new();
+                YMessage::Awareness(update).encode(&mut encoder);
+                broadcast(item.clone(), encoder.to_vec(), context.clone(), None);
             }
         })
     };
     let doc = {
         let item = item.clone();
-        workspace.observe(move |_, e| {
+        workspace.observe(move |trx, e| {
             debug!(
                 "workspace {} changed: {}bytes",
                 item.workspace,
                 &e.update.len()
             );
             let update = sync_encode_update(&e.update);
-            broadcast(item.clone(), update, context.clone());
+            let before = trx.before_state().clone();
+            broadcast(item.clone(), update, context.clone(), Some(before));
         })
     };
     let metadata = workspace.observe_metadata(move |_, _e| {
@@ -97,3 +147,141 @@ pub fn subscribe(
         _metadata: metadata,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        channel::MissedUpdates,
+        session::{decode_session, SyncSessions},
+        Channels,
+    };
+    use jwst_storage::JwstStorage;
+    use std::time::Duration;
+    use tokio::{sync::mpsc::channel, time::timeout};
+    use yrs::{ReadTxn, Transact};
+
+    struct TestContext {
+        storage: JwstStorage,
+        channel: Channels,
+    }
+
+    impl ContextImpl<'_> for TestContext {
+        fn get_storage(&self) -> &JwstStorage {
+            &self.storage
+        }
+
+        fn get_channel(&self) -> &Channels {
+            &self.channel
+        }
+    }
+
+    #[tokio::test]
+    async fn resync_dropped_update() {
+        let context = Arc::new(TestContext {
+            storage: JwstStorage::new("sqlite::memory:").await.unwrap(),
+            channel: Default::default(),
+        });
+        let server = context.storage.create_workspace("test").await.unwrap();
+
+        let sender = ChannelItem::new("test", "sender");
+        let (busy, closed) = (
+            ChannelItem::new("test", "busy"),
+            ChannelItem::new("test", "closed"),
+        );
+        let (busy_tx, mut busy_rx) = channel(1);
+        busy_tx.try_send(Some(vec![])).unwrap();
+        {
+            let mut channels = context.channel.write().await;
+            channels.insert(busy.clone(), busy_tx);
+            channels.insert(closed.clone(), channel(1).0);
+        }
+
+        server.with_trx(|mut t| {
+            t.create("block", "text");
+        });
+        let mut client = Workspace::new("test");
+        client.sync_decode_message(&sync_encode_update(&server.sync_migration()));
+        let before = server.doc().transact().state_vector();
+        server.with_trx(|mut t| {
+            t.create("missed", "text");
+        });
+        let update =
+            sync_encode_update(&server.doc().transact().encode_state_as_update_v1(&before));
+        let mut deliveries = deliver(&sender, update.clone(), &context).await;
+        deliveries.sort_by(|(a, _), (b, _)| a.identifier.cmp(&b.identifier));
+        assert_eq!(
+            deliveries,
+            vec![
+                (busy.clone(), Delivery::Dropped),
+                (closed.clone(), Delivery::Closed)
+            ]
+        );
+        // closed channels are cleaned up
+        assert!(!context.channel.read().await.contains_key(&closed));
+
+        // the busy connection records the state it missed the update from
+        broadcast(sender, update, context.clone(), Some(before.clone()));
+        let since = timeout(Duration::from_secs(1), busy.missed.wait())
+            .await
+            .unwrap();
+        assert_eq!(since, before);
+
+        // and converges with the corrective resync, without reconnecting
+        busy_rx.recv().await;
+        let resync = crate::resync(&context, "test", None, &since).await.unwrap();
+        assert!(resync.len() < sync_encode_update(&server.sync_migration()).len());
+        client.sync_decode_message(&resync);
+        assert!(client.with_trx(|t| client.exists(&t.trx, "missed")));
+    }
+
+    #[tokio::test]
+    async fn missed_updates_keep_oldest_state() {
+        let missed = MissedUpdates::default();
+        let (mut older, mut newer) = (StateVector::default(), StateVector::default());
+        older.set_max(1, 2);
+        newer.set_max(1, 5);
+        newer.set_max(2, 3);
+
+        missed.record(&newer);
+        missed.record(&older);
+        let since = missed.wait().await;
+        // client 2 is unknown to the older state, its changes are all resent
+        assert_eq!(since, older);
+    }
+
+    #[tokio::test]
+    async fn resync_from_acked_sequence() {
+        let context = Arc::new(TestContext {
+            storage: JwstStorage::new("sqlite::memory:").await.unwrap(),
+            channel: Default::default(),
+        });
+        let mut server = context.storage.create_workspace("test").await.unwrap();
+        let sessions = SyncSessions::default();
+        let (mut session, _) = sessions.start(&mut server, None);
+
+        server.with_trx(|mut t| {
+            t.create("acked", "text");
+        });
+        let mut client = Workspace::new("test");
+        client.sync_decode_message(&sync_encode_update(&server.sync_migration()));
+        // the client confirms what it was sent up to the second tick
+        session.tick();
+        let (_, seq) = decode_session(&session.tick()).unwrap();
+        session.confirm(seq);
+
+        server.with_trx(|mut t| {
+            t.create("missed", "text");
+        });
+        let resync = crate::resync(&context, "test", Some(&session), &StateVector::default())
+            .await
+            .unwrap();
+        // only the updates logged after the confirmed sequence are sent
+        let full = crate::resync(&context, "test", None, &StateVector::default())
+            .await
+            .unwrap();
+        assert!(resync.len() < full.len());
+        client.sync_decode_message(&resync);
+        assert!(client.with_trx(|t| client.exists(&t.trx, "missed")));
+    }
+}
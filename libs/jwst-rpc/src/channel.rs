@@ -1,12 +1,19 @@
 use nanoid::nanoid;
-use std::collections::HashMap;
-use tokio::sync::{mpsc::Sender, RwLock};
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::{mpsc::Sender, Notify, RwLock};
+use yrs::StateVector;
 
-#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct ChannelItem {
     pub workspace: String,
     pub identifier: String,
     pub(crate) uuid: String,
+    // doc updates that couldn't be delivered to this channel
+    pub(crate) missed: Arc<MissedUpdates>,
 }
 
 impl ChannelItem {
@@ -19,8 +26,80 @@ impl ChannelItem {
             workspace: workspace.as_ref().into(),
             identifier: identifier.as_ref().into(),
             uuid: nanoid!(10),
+            missed: Default::default(),
         }
     }
 }
 
+impl PartialEq for ChannelItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.workspace == other.workspace
+            && self.identifier == other.identifier
+            && self.uuid == other.uuid
+    }
+}
+
+impl Eq for ChannelItem {}
+
+impl Hash for ChannelItem {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.workspace.hash(state);
+        self.identifier.hash(state);
+        self.uuid.hash(state);
+    }
+}
+
+/// Doc updates a channel missed, tracked from the state of the doc before the first of them.
+/// Earlier updates were queued in the channel before, so the connection catches up with
+/// the changes made since that state.
+#[derive(Debug, Default)]
+pub(crate) struct MissedUpdates {
+    since: Mutex<Option<StateVector>>,
+    notify: Notify,
+}
+
+impl MissedUpdates {
+    /// Record a missed update made on top of `before`.
+    pub(crate) fn record(&self, before: &StateVector) {
+        let mut since = self.since.lock().unwrap();
+        let state = match since.take() {
+            // the broadcasts are not ordered, keep the older state of every client
+            Some(since) => {
+                let mut state = StateVector::default();
+                for (client, clock) in since.iter() {
+                    let clock = (*clock).min(before.get(client));
+                    if clock > 0 {
+                        state.set_max(*client, clock);
+                    }
+                }
+                state
+            }
+            None => before.clone(),
+        };
+        *since = Some(state);
+        self.notify.notify_one();
+    }
+
+    /// Wait for a missed update, returns the state the connection has to catch up from.
+    pub(crate) async fn wait(&self) -> StateVector {
+        loop {
+            let since = self.since.lock().unwrap().take();
+            if let Some(since) = since {
+                return since;
+            }
+            self.notify.notified().await;
+        }
+    }
+}
+
+/// Outcome of sending a broadcast message to one channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Delivery {
+    Delivered,
+    /// The channel is full, the message is lost for this channel.
+    Dropped,
+    /// The connection is gone, the channel gets removed.
+    Closed,
+}
+
 pub type Channels = RwLock<HashMap<ChannelItem, Sender<Option<Vec<u8>>>>>;
@@ -1,8 +1,8 @@
-use super::*;
+use super::{session::*, *};
 use anyhow::Context;
 use dashmap::mapref::entry::Entry;
 use futures::{SinkExt, StreamExt};
-use jwst::{DocStorage, JwstResult, Workspace};
+use jwst::{sync_encode_update, DocStorage, JwstResult, Workspace};
 use jwst_storage::JwstStorage;
 use std::{
     sync::{
@@ -13,7 +13,7 @@ use std::{
 };
 use tokio::{
     net::TcpStream,
-    sync::broadcast::{channel, Receiver},
+    sync::broadcast::{channel, error::RecvError, Receiver},
     time::sleep,
 };
 use tokio_tungstenite::{
@@ -23,9 +23,11 @@ use tokio_tungstenite::{
 };
 use url::Url;
 
-type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+pub(crate) type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+// token and sequence issued by the server to resume the sync
+type Session = Option<(String, u64)>;
 
-async fn prepare_connection(remote: &str) -> JwstResult<Socket> {
+pub(crate) async fn prepare_connection(remote: &str) -> JwstResult<Socket> {
     debug!("generate remote config");
     let uri = Url::parse(remote).context("failed to parse remote url".to_string())?;
 
@@ -42,13 +44,22 @@ async fn prepare_connection(remote: &str) -> JwstResult<Socket> {
         .0)
 }
 
-async fn init_connection(workspace: &Workspace, remote: &str) -> JwstResult<Socket> {
+async fn init_connection(
+    workspace: &Workspace,
+    remote: &str,
+    session: &Session,
+) -> JwstResult<Socket> {
     let mut socket = prepare_connection(remote).await?;
 
-    debug!("create init message");
-    let init_data = workspace
-        .sync_init_message()
-        .context("failed to create init message")?;
+    let init_data = if let Some((token, seq)) = session {
+        debug!("resume session {token} after {seq}");
+        encode_resume(token, *seq)
+    } else {
+        debug!("create init message");
+        workspace
+            .sync_init_message()
+            .context("failed to create init message")?
+    };
 
     debug!("send init message");
     socket
@@ -64,11 +75,14 @@ async fn join_sync_thread(
     workspace: &Workspace,
     socket: Socket,
     rx: &mut Receiver<Vec<u8>>,
+    session: &mut Session,
 ) -> JwstResult<bool> {
     let (mut socket_tx, mut socket_rx) = socket.split();
 
     let id = workspace.id();
     let mut workspace = workspace.clone();
+    let mut resuming = session.as_ref().map(|(token, _)| token.clone());
+    let mut local_closed = false;
     debug!("start sync thread {id}");
     let success = loop {
         tokio::select! {
@@ -82,6 +96,30 @@ async fn join_sync_thread(
                             if msg == [0, 2, 2, 0, 0] {
                                 continue;
                             }
+                            let issued = decode_session(&msg);
+                            if let (Some((issued, _)), Some(token)) = (&issued, resuming.take()) {
+                                // the server issues a new token if ours can't be resumed
+                                if *issued != token {
+                                    debug!("session rejected, fallback to full sync");
+                                    let init_data = workspace
+                                        .sync_init_message()
+                                        .context("failed to create init message")?;
+                                    if let Err(e) = socket_tx.send(Message::Binary(init_data)).await {
+                                        warn!("send init message to remote failed: {:?}", e);
+                                        break false
+                                    }
+                                }
+                            }
+                            if let Some((token, seq)) = issued {
+                                // lets the server resend what we miss from this sequence on
+                                let ack = encode_ack(&token, seq);
+                                if let Err(e) = socket_tx.send(Message::Binary(ack)).await {
+                                    warn!("send ack to remote failed: {:?}", e);
+                                    break false
+                                }
+                                *session = Some((token, seq));
+                                continue;
+                            }
                             let buffer = workspace.sync_decode_message(&msg);
                             first_sync.store(true, Ordering::Release);
                             for update in buffer {
@@ -106,7 +144,20 @@ async fn join_sync_thread(
                     },
                 }
             }
-            Ok(msg) = rx.recv() => {
+            msg = rx.recv(), if !local_closed => {
+                let msg = match msg {
+                    Ok(msg) => msg,
+                    Err(RecvError::Lagged(skipped)) => {
+                        // updates made while disconnected are lost, a resumed session won't
+                        // bring them back so the whole doc is sent instead
+                        warn!("{skipped} local updates were dropped, send the whole doc");
+                        sync_encode_update(&workspace.sync_migration())
+                    }
+                    Err(RecvError::Closed) => {
+                        local_closed = true;
+                        continue;
+                    }
+                };
                 debug!("send local update to remote: {:?}", msg);
                 if let Err(e) = socket_tx.send(Message::Binary(msg)).await {
                     warn!("send local update to remote failed: {:?}", e);
@@ -128,9 +179,10 @@ async fn run_sync(
     workspace: &Workspace,
     remote: String,
     rx: &mut Receiver<Vec<u8>>,
+    session: &mut Session,
 ) -> JwstResult<bool> {
-    let socket = init_connection(workspace, &remote).await?;
-    join_sync_thread(first_sync, workspace, socket, rx).await
+    let socket = init_connection(workspace, &remote, session).await?;
+    join_sync_thread(first_sync, workspace, socket, rx, session).await
 }
 
 fn start_sync_thread(workspace: &Workspace, remote: String, mut rx: Receiver<Vec<u8>>) {
@@ -143,12 +195,14 @@ fn start_sync_thread(workspace: &Workspace, remote: String, mut rx: Receiver<Vec
             return error!("Failed to create runtime");
         };
         rt.block_on(async move {
+            let mut session = None;
             loop {
                 match run_sync(
                     first_sync_cloned.clone(),
                     &workspace,
                     remote.clone(),
                     &mut rx,
+                    &mut session,
                 )
                 .await
                 {
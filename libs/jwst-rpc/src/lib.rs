@@ -1,27 +1,207 @@
+mod auth;
+mod awareness;
+mod bandwidth;
 mod broadcast;
 mod channel;
 mod client;
+mod migrate;
+mod multiplex;
+mod notification;
+mod poll;
+mod rate_limit;
+mod relay;
+mod session;
 
+pub use auth::{encode_auth, handle_authenticated_socket, AUTH_REQUIRED, DEFAULT_AUTH_TIMEOUT};
+pub use bandwidth::{BandwidthCounter, BandwidthUsage};
 pub use channel::Channels;
 pub use client::start_client;
+pub use migrate::{migrate_workspace, MigrateProgress, MigrateReport, MigrateSource};
+pub use multiplex::{handle_multiplexed_socket, MultiplexMessage};
+pub use notification::ServerNotification;
+pub use poll::handle_poll;
+pub use rate_limit::{MutationLimits, RateLimit, DEFAULT_MUTATION_RATE_LIMIT};
+pub use relay::RelayWorkspaces;
+pub use session::{SyncSessions, DEFAULT_SESSION_TTL, DEFAULT_UPDATE_LOG_SIZE};
 
+use async_trait::async_trait;
+use awareness::{awareness_clients, AwarenessLimiter};
 use axum::extract::ws::{Message, WebSocket};
-use broadcast::subscribe;
+use bandwidth::ConnectionBandwidth;
+use broadcast::{subscribe, Subscriptions};
 use channel::ChannelItem;
 use dashmap::mapref::entry::Entry;
 use futures::{sink::SinkExt, stream::StreamExt};
-use jwst::{debug, error, info, trace, warn};
+use jwst::{
+    debug, error, info, sync_encode_update, trace, warn, SyncPeer, WorkspacePermission,
+    WorkspaceUser,
+};
 use jwst_storage::JwstStorage;
-use std::sync::Arc;
+use std::{collections::BTreeSet, sync::Arc};
 use tokio::{
     sync::broadcast::channel as broadcast,
-    sync::mpsc::channel,
-    time::{sleep, Duration},
+    sync::mpsc::{channel, error::TrySendError},
+    time::{sleep, sleep_until, Duration},
 };
+use yrs::{ReadTxn, StateVector, Transact};
+
+/// Default max awareness updates applied per second from each connection.
+pub const DEFAULT_AWARENESS_RATE_LIMIT: u32 = 20;
 
+#[async_trait]
 pub trait ContextImpl<'a> {
     fn get_storage(&self) -> &JwstStorage;
     fn get_channel(&self) -> &Channels;
+
+    /// Max awareness updates applied per second from each connection, or from each
+    /// workspace joined on a multiplexed connection, 0 disables the limit. Their broadcast
+    /// follows the applied updates. Doc updates are never throttled.
+    fn awareness_rate_limit(&self) -> u32 {
+        DEFAULT_AWARENESS_RATE_LIMIT
+    }
+
+    /// Sessions used to resume connections without a full sync, `None` disables resumption.
+    fn sync_sessions(&self) -> Option<&SyncSessions> {
+        None
+    }
+
+    /// Traffic counters of the websocket connections, `None` disables the accounting.
+    fn bandwidth_usage(&self) -> Option<&BandwidthUsage> {
+        None
+    }
+
+    /// The authenticated user behind the connection `identifier`, its traffic is counted
+    /// towards the user as well as the workspace, see [BandwidthUsage]. Connections are
+    /// anonymous unless this is implemented.
+    async fn authenticated_user(&self, _workspace_id: &str, _identifier: &str) -> Option<String> {
+        None
+    }
+
+    /// Workspaces whose sync connections are relayed without loading them, `None` relays none.
+    fn relay_workspaces(&self) -> Option<&RelayWorkspaces> {
+        None
+    }
+
+    /// Whether joining a workspace that doesn't exist creates it, otherwise the join of a
+    /// [multiplexed socket](handle_multiplexed_socket) is answered with
+    /// [MultiplexMessage::Leave]. Workspaces are created unless this is implemented.
+    fn create_missing_workspaces(&self) -> bool {
+        true
+    }
+
+    /// Time a client has to answer the auth challenge of [handle_authenticated_socket].
+    fn auth_timeout(&self) -> Duration {
+        DEFAULT_AUTH_TIMEOUT
+    }
+
+    /// Identifier of the client presenting `token` to sync `workspace_id`, `None` rejects it.
+    /// Every token is rejected unless this is implemented.
+    async fn authenticate(&self, _workspace_id: &str, _token: &str) -> Option<String> {
+        None
+    }
+
+    /// The user behind the connection `identifier`, updates synced over the connection are
+    /// checked against its permission in the workspace, see [SyncPeer]. Every connection
+    /// may write unless this is implemented.
+    async fn workspace_user(&self, _workspace_id: &str, identifier: &str) -> WorkspaceUser {
+        WorkspaceUser::new(identifier, WorkspacePermission::Write)
+    }
+
+    /// Ids of the workspaces with live websocket connections.
+    async fn list_channels(&self) -> Vec<String> {
+        self.get_channel()
+            .read()
+            .await
+            .keys()
+            .map(|item| item.workspace.clone())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// Push `notification` to every connection of the workspace without waiting for them,
+    /// returns the number of connections it was queued for. Connections that are full or
+    /// join later miss it.
+    async fn notify_room(&self, workspace_id: &str, notification: ServerNotification) -> usize {
+        let message = notification.encode();
+        let mut sent = 0;
+        for (item, tx) in self
+            .get_channel()
+            .read()
+            .await
+            .iter()
+            .filter(|(item, _)| item.workspace == workspace_id)
+        {
+            match tx.try_send(Some(message.clone())) {
+                Ok(()) => sent += 1,
+                Err(TrySendError::Full(_)) => {
+                    warn!("{} channel {} is full", item.workspace, item.identifier)
+                }
+                // removed when the connection or the next broadcast notices it
+                Err(TrySendError::Closed(_)) => {}
+            }
+        }
+        debug!("{workspace_id} notified {sent} connections: {notification:?}");
+        sent
+    }
+}
+
+async fn decode_message(
+    context: &Arc<impl ContextImpl<'static> + Send + Sync + 'static>,
+    workspace_id: &str,
+    binary: &[u8],
+    peer: &mut SyncPeer,
+) -> Option<Vec<Vec<u8>>> {
+    let mut workspace = context
+        .get_storage()
+        .get_workspace(workspace_id)
+        .await
+        .expect("workspace not found");
+
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    match catch_unwind(AssertUnwindSafe(|| {
+        workspace.sync_decode_untrusted_message(binary, peer)
+    })) {
+        Ok(Ok(replies)) => Some(replies),
+        Ok(Err(e)) => {
+            warn!("rejected sync message for {workspace_id}: {e}");
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+// frame with the doc updates a connection missed: the ones logged since the sequence its
+// session confirmed, or the changes made since the state `since` it missed them from
+async fn resync(
+    context: &Arc<impl ContextImpl<'static> + Send + Sync + 'static>,
+    workspace_id: &str,
+    session: Option<&session::SyncSession>,
+    since: &StateVector,
+) -> Option<Vec<u8>> {
+    if let Some(missed) = session.and_then(|session| session.missed()) {
+        return Some(missed);
+    }
+    match context.get_storage().get_workspace(workspace_id).await {
+        Ok(workspace) => Some(sync_encode_update(
+            &workspace.doc().transact().encode_state_as_update_v1(since),
+        )),
+        Err(e) => {
+            error!("failed to get workspace {workspace_id}: {e}");
+            None
+        }
+    }
+}
+
+async fn refresh_awareness(
+    context: &Arc<impl ContextImpl<'static> + Send + Sync + 'static>,
+    workspace_id: &str,
+    clients: &[u64],
+) {
+    match context.get_storage().get_workspace(workspace_id).await {
+        Ok(workspace) => workspace.refresh_awareness_activity(clients),
+        Err(e) => error!("failed to get workspace {workspace_id}: {e}"),
+    }
 }
 
 pub async fn handle_socket(
@@ -30,10 +210,48 @@ pub async fn handle_socket(
     context: Arc<impl ContextImpl<'static> + Send + Sync + 'static>,
     identifier: String,
 ) {
+    sync_socket(socket, workspace_id, context, identifier, None).await
+}
+
+/// [handle_socket] for a user whose permission was resolved before the connection, like
+/// while authorizing the upgrade request, [ContextImpl::workspace_user] isn't asked again.
+/// The id of the user identifies the connection.
+pub async fn handle_user_socket(
+    socket: WebSocket,
+    workspace_id: String,
+    context: Arc<impl ContextImpl<'static> + Send + Sync + 'static>,
+    user: WorkspaceUser,
+) {
+    let identifier = user.id.clone();
+    sync_socket(socket, workspace_id, context, identifier, Some(user)).await
+}
+
+// `user` is resolved with [ContextImpl::workspace_user] if `None`
+async fn sync_socket(
+    socket: WebSocket,
+    workspace_id: String,
+    context: Arc<impl ContextImpl<'static> + Send + Sync + 'static>,
+    identifier: String,
+    user: Option<WorkspaceUser>,
+) {
+    if context
+        .relay_workspaces()
+        .map_or(false, |relay| relay.contains(&workspace_id))
+    {
+        return relay::handle_relay_socket(socket, workspace_id, context, identifier).await;
+    }
     info!("{} collaborate with workspace {}", identifier, workspace_id);
 
     let (mut socket_tx, mut socket_rx) = socket.split();
     let (tx, mut rx) = channel(100);
+    let bandwidth = ConnectionBandwidth::new(
+        context.bandwidth_usage(),
+        &workspace_id,
+        context
+            .authenticated_user(&workspace_id, &identifier)
+            .await
+            .as_deref(),
+    );
 
     let channel_item = ChannelItem::new(&workspace_id, &identifier);
     context
@@ -57,47 +275,86 @@ pub async fn handle_socket(
         }
     };
 
-    if let Ok(init_data) = {
-        let mut ws = context
-            .get_storage()
-            .create_workspace(&workspace_id)
-            .await
-            .expect("create workspace failed, please check if the workspace_id is valid or not");
-
-        let sub = subscribe(context.clone(), &mut ws, &channel_item);
-        std::mem::forget(sub);
-
-        ws.sync_init_message()
-    } {
-        if tx.send(Some(init_data)).await.is_err() {
-            context.get_channel().write().await.remove(&channel_item);
-            debug!("{workspace_id} remove channel: {identifier}");
-            // client disconnected
-            return;
-        }
-    } else {
+    let mut ws = context
+        .get_storage()
+        .create_workspace(&workspace_id)
+        .await
+        .expect("create workspace failed, please check if the workspace_id is valid or not");
+    // broadcasts the changes of the workspace until the connection is closed
+    let _subscriptions = subscribe(context.clone(), &mut ws, &channel_item);
+
+    let sent = match ws.sync_init_message() {
+        Ok(init_data) => tx.send(Some(init_data)).await.is_ok(),
+        Err(_) => false,
+    };
+    if !sent {
         context.get_channel().write().await.remove(&channel_item);
+        debug!("{workspace_id} remove channel: {identifier}");
         // client disconnected
         return;
     }
 
+    // started with the first frame of the client, a reconnecting client presents its
+    // session in it and receives the updates it missed instead of running the sync
+    let mut session = None;
+    let mut awaiting_session = context.sync_sessions().is_some();
+    let mut awareness = AwarenessLimiter::new(context.awareness_rate_limit());
+    let user = match user {
+        Some(user) => user,
+        None => context.workspace_user(&workspace_id, &identifier).await,
+    };
+    let mut peer = SyncPeer::new(user);
     loop {
         tokio::select! {
-            Some(msg) = socket_rx.next() => {
+            msg = socket_rx.next() => {
+                let Some(msg) = msg else {
+                    // client disconnected
+                    break;
+                };
                 let mut success = true;
                 if let Ok(Message::Binary(binary)) = msg {
                     debug!("recv from remote: {}bytes", binary.len());
-                    let payload = {
-                        let mut workspace = context
-                            .get_storage()
-                            .get_workspace(&workspace_id)
-                            .await
-                            .expect("workspace not found");
-
-                        use std::panic::{catch_unwind, AssertUnwindSafe};
-                        catch_unwind(AssertUnwindSafe(|| workspace.sync_decode_message(&binary)))
+                    bandwidth.received(binary.len());
+                    if let (Some((token, seq)), Some(session)) =
+                        (session::decode_ack(&binary), &mut session)
+                    {
+                        if token == session.token() {
+                            session.confirm(seq);
+                        }
+                        continue;
+                    }
+                    if let (true, Some(sessions)) = (awaiting_session, context.sync_sessions()) {
+                        awaiting_session = false;
+                        let resume = session::decode_resume(&binary);
+                        let resuming = resume.is_some();
+                        let (started, replay) = sessions.start(&mut ws, resume);
+                        for frame in std::iter::once(started.message()).chain(replay) {
+                            if tx.send(Some(frame)).await.is_err() {
+                                // client disconnected
+                                success = false;
+                                break;
+                            }
+                        }
+                        session = Some(started);
+                        if !success {
+                            break;
+                        }
+                        if resuming {
+                            continue;
+                        }
+                    }
+                    let payload = match awareness_clients(&binary) {
+                        Some(clients) => match awareness.push(binary) {
+                            Some(binary) => decode_message(&context, &workspace_id, &binary, &mut peer).await,
+                            None => {
+                                // applied later, keep the client from looking gone meanwhile
+                                refresh_awareness(&context, &workspace_id, &clients).await;
+                                None
+                            }
+                        },
+                        None => decode_message(&context, &workspace_id, &binary, &mut peer).await,
                     };
-                    if let Ok(messages) = payload {
+                    if let Some(messages) = payload {
                         for reply in messages {
                             debug!("send pipeline message by {identifier:?}");
                             if let Err(e) = tx.send(Some(reply)).await {
@@ -116,8 +373,26 @@ pub async fn handle_socket(
                     break
                 }
             },
+            _ = sleep_until(awareness.deadline().unwrap_or_else(std::time::Instant::now).into()),
+                if awareness.deadline().is_some() =>
+            {
+                if let Some(binary) = awareness.take_due() {
+                    // awareness updates have no reply
+                    decode_message(&context, &workspace_id, &binary, &mut peer).await;
+                }
+            },
+            since = channel_item.missed.wait() => {
+                debug!("{workspace_id} resync {identifier} after a dropped update");
+                if let Some(data) = resync(&context, &workspace_id, session.as_ref(), &since).await {
+                    if tx.send(Some(data)).await.is_err() {
+                        // client disconnected
+                        break;
+                    }
+                }
+            },
             Ok(msg) = server_update.recv() => {
                 debug!("recv from server update: {:?}", msg);
+                bandwidth.sent(msg.len());
                 if let Err(e) = socket_tx.send(Message::Binary(msg)).await {
                     error!("send error: {}", e);
                     break;
@@ -128,6 +403,7 @@ pub async fn handle_socket(
                     "recv from channel: {}bytes",
                     msg.as_ref().map(|v| v.len() as isize).unwrap_or(-1)
                 );
+                bandwidth.sent(msg.as_ref().map(Vec::len).unwrap_or_default());
                 if let Err(e) = socket_tx
                     .send(msg.map(Message::Binary).unwrap_or(Message::Close(None)))
                     .await
@@ -141,9 +417,29 @@ pub async fn handle_socket(
                     .get_storage()
                     .full_migrate(workspace_id.clone(), None, false)
                     .await;
+                if let (Some(sessions), Some(session)) = (context.sync_sessions(), &mut session) {
+                    sessions.touch(session.token());
+                    // skipped if the channel is full, the client keeps its previous sequence
+                    if let Err(TrySendError::Closed(_)) = tx.try_send(Some(session.tick())) {
+                        break;
+                    }
+                }
             }
         }
     }
 
+    if let (Some(sessions), Some(session)) = (context.sync_sessions(), &session) {
+        // the session can be resumed within ttl from now
+        sessions.touch(session.token());
+    }
     context.get_channel().write().await.remove(&channel_item);
+
+    let traffic = bandwidth.get();
+    debug!(
+        "{identifier} left {workspace_id}, sent {}bytes, received {}bytes",
+        traffic.sent, traffic.received
+    );
+    bandwidth
+        .close(context.bandwidth_usage(), context.get_storage())
+        .await;
 }
@@ -0,0 +1,430 @@
+use super::{client::prepare_connection, debug, info};
+use anyhow::{anyhow, Context};
+use futures::{stream, SinkExt, StreamExt};
+use jwst::{BlobStorage, JwstResult, Workspace, DEFAULT_BLOB_PROPERTY_KEYS};
+use jwst_storage::JwstStorage;
+use std::{
+    collections::{BTreeSet, HashSet},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message;
+use url::Url;
+use y_sync::sync::{Message as SyncMessage, MessageReader, SyncMessage as SyncStep};
+use yrs::{
+    updates::decoder::{Decode, DecoderV1},
+    ReadTxn, Transact,
+};
+
+// give up if the source stays quiet for this long during the initial sync
+const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Progress of a running [migrate_workspace], can be read while it runs.
+#[derive(Debug, Default)]
+pub struct MigrateProgress {
+    synced: AtomicBool,
+    blobs_total: AtomicUsize,
+    blobs_done: AtomicUsize,
+}
+
+impl MigrateProgress {
+    /// `true` once the doc is written into the destination storage.
+    pub fn synced(&self) -> bool {
+        self.synced.load(Ordering::Acquire)
+    }
+
+    pub fn blobs_total(&self) -> usize {
+        self.blobs_total.load(Ordering::Acquire)
+    }
+
+    pub fn blobs_done(&self) -> usize {
+        self.blobs_done.load(Ordering::Acquire)
+    }
+}
+
+/// Summary of a finished migration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrateReport {
+    pub blocks: u32,
+    /// Blobs downloaded from the source.
+    pub blobs: usize,
+    /// Blobs already in the destination, e.g. from a previous run.
+    pub skipped_blobs: usize,
+}
+
+// run a full sync against the sync endpoint of the source
+async fn fetch_doc(source: &Url, workspace_id: &str) -> JwstResult<Workspace> {
+    let mut socket = prepare_connection(source.as_str()).await?;
+    let mut workspace = Workspace::new(workspace_id);
+
+    let init_data = workspace
+        .sync_init_message()
+        .context("failed to create init message")?;
+    socket
+        .send(Message::Binary(init_data))
+        .await
+        .context("failed to send init message")?;
+
+    loop {
+        let frame = match timeout(SYNC_TIMEOUT, socket.next()).await {
+            Ok(Some(Ok(Message::Binary(frame)))) => frame,
+            Ok(Some(Ok(_))) => continue,
+            Ok(Some(Err(e))) => return Err(anyhow!("source closed: {e}").into()),
+            Ok(None) => return Err(anyhow!("source closed before sync finished").into()),
+            Err(_) => return Err(anyhow!("source sync timed out").into()),
+        };
+
+        let mut synced = false;
+        let mut decoder = DecoderV1::from(frame.as_slice());
+        for msg in MessageReader::new(&mut decoder) {
+            let msg = msg.context("failed to decode sync message")?;
+            synced |= matches!(msg, SyncMessage::Sync(SyncStep::SyncStep2(_)));
+            // replies are not needed, nothing is sent back to the source
+            if let Err(e) = workspace.sync_handle_message(msg) {
+                debug!("skip sync message: {e}");
+            }
+        }
+        if synced {
+            break;
+        }
+    }
+    let _ = socket.close(None).await;
+
+    Ok(workspace)
+}
+
+/// Server a workspace is migrated from, the blob endpoint depends on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrateSource {
+    Cloud,
+    Keck,
+}
+
+impl MigrateSource {
+    /// Keck serves sync on `/collaboration/:workspace`, cloud on `/api/sync/:workspace`.
+    pub fn detect(source: &Url) -> Self {
+        if source.path().starts_with("/collaboration/") {
+            Self::Keck
+        } else {
+            Self::Cloud
+        }
+    }
+
+    fn blob_path(&self, workspace_id: &str, hash: &str) -> String {
+        match self {
+            Self::Cloud => format!("/api/workspace/{workspace_id}/blob/{hash}"),
+            Self::Keck => format!("/api/blobs/{workspace_id}/{hash}"),
+        }
+    }
+}
+
+fn blob_url(source: &Url, workspace_id: &str, hash: &str) -> JwstResult<Url> {
+    let kind = MigrateSource::detect(source);
+    let mut url = source.clone();
+    let scheme = if url.scheme() == "wss" {
+        "https"
+    } else {
+        "http"
+    };
+    url.set_scheme(scheme)
+        .map_err(|_| anyhow!("invalid source url: {source}"))?;
+    url.set_query(None);
+    url.set_path(&kind.blob_path(workspace_id, hash));
+    Ok(url)
+}
+
+/// Copy a workspace from another server into `storage`.
+///
+/// `source` is the websocket sync endpoint of the workspace, `token` is passed as the
+/// `token` query parameter of the sync and as bearer token of the blob requests if the
+/// source requires one. If `allowed_hosts` is set, sources on other hosts are rejected.
+/// The doc is obtained by a full sync, then the blobs referenced by its blocks are
+/// downloaded from the blob endpoint of the source, see [MigrateSource]. Everything is
+/// content addressed, so a failed migration can be resumed by running it again, blobs
+/// already in `storage` are skipped.
+pub async fn migrate_workspace(
+    source: &str,
+    token: Option<&str>,
+    allowed_hosts: Option<&[String]>,
+    storage: &JwstStorage,
+    workspace_id: &str,
+    progress: &MigrateProgress,
+) -> JwstResult<MigrateReport> {
+    let mut source = Url::parse(source).context("failed to parse source url")?;
+    if !matches!(source.scheme(), "ws" | "wss") {
+        return Err(anyhow!("source must be a websocket url").into());
+    }
+    let host = source.host_str().unwrap_or_default();
+    if let Some(allowed) = allowed_hosts {
+        if !allowed
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host))
+        {
+            return Err(anyhow!("source host {host} is not allowed").into());
+        }
+    }
+    if let Some(token) = token {
+        source.query_pairs_mut().append_pair("token", token);
+    }
+
+    info!(
+        "migrate {workspace_id}: sync doc from {}",
+        source.host_str().unwrap_or_default()
+    );
+    let workspace = fetch_doc(&source, workspace_id).await?;
+    let (blocks, hashes) = {
+        let doc = workspace.doc();
+        let trx = doc.transact();
+        let blocks = workspace.blocks(&trx, |blocks| {
+            blocks.map(|block| block.id()).collect::<Vec<_>>()
+        });
+        let hashes = workspace
+            .find_blob_references(&trx, DEFAULT_BLOB_PROPERTY_KEYS)
+            .into_iter()
+            .map(|reference| reference.hash)
+            .collect::<BTreeSet<_>>();
+        (blocks, hashes)
+    };
+
+    let mut dest = storage.create_workspace(workspace_id).await?;
+    dest.sync_handle_message(SyncMessage::Sync(SyncStep::Update(
+        workspace.sync_migration(),
+    )))
+    .context("failed to apply source doc")?;
+    if !storage
+        .full_migrate(workspace_id.to_owned(), None, true)
+        .await
+    {
+        return Err(anyhow!("failed to write {workspace_id}").into());
+    }
+
+    let missing = {
+        let doc = dest.doc();
+        let trx = doc.transact();
+        blocks.iter().filter(|id| !dest.exists(&trx, id)).count()
+    };
+    if missing > 0 {
+        return Err(anyhow!(
+            "{missing} of {} blocks are missing after sync",
+            blocks.len()
+        )
+        .into());
+    }
+    progress.synced.store(true, Ordering::Release);
+
+    let stored = storage
+        .blobs()
+        .hashes(workspace_id)
+        .await
+        .context(format!("failed to list blobs of {workspace_id}"))?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    progress.blobs_total.store(hashes.len(), Ordering::Release);
+
+    // a redirect could point the blob requests to any other host
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .context("failed to create http client")?;
+    let mut report = MigrateReport {
+        blocks: blocks.len() as u32,
+        blobs: 0,
+        skipped_blobs: 0,
+    };
+    for hash in hashes {
+        if stored.contains(&hash) {
+            report.skipped_blobs += 1;
+        } else {
+            let mut request = client.get(blob_url(&source, workspace_id, &hash)?);
+            if let Some(token) = token {
+                request = request.bearer_auth(token);
+            }
+            let blob = request
+                .send()
+                .await
+                .and_then(|resp| resp.error_for_status())
+                .context(format!("failed to fetch blob {hash}"))?
+                .bytes()
+                .await
+                .context(format!("failed to fetch blob {hash}"))?;
+
+            let stored_hash = storage
+                .blobs()
+                .put_blob(Some(workspace_id.to_owned()), stream::iter([blob]))
+                .await?;
+            if stored_hash != hash {
+                // the bad blob is kept under its own hash, no reference points to it
+                return Err(anyhow!("blob {hash} doesn't match its content").into());
+            }
+            report.blobs += 1;
+        }
+        progress.blobs_done.fetch_add(1, Ordering::AcqRel);
+    }
+
+    info!("migrate {workspace_id} finished: {report:?}");
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{handle_socket, Channels, ContextImpl};
+    use axum::{
+        body::StreamBody,
+        extract::{ws::WebSocketUpgrade, Path, State},
+        http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+        routing::get,
+        Router,
+    };
+    use bytes::Bytes;
+    use std::{net::SocketAddr, sync::Arc};
+
+    struct TestContext {
+        storage: JwstStorage,
+        channel: Channels,
+    }
+
+    impl ContextImpl<'_> for TestContext {
+        fn get_storage(&self) -> &JwstStorage {
+            &self.storage
+        }
+
+        fn get_channel(&self) -> &Channels {
+            &self.channel
+        }
+    }
+
+    async fn source() -> (Arc<TestContext>, SocketAddr) {
+        let storage = JwstStorage::new("sqlite::memory:").await.unwrap();
+        storage.create_workspace("test").await.unwrap();
+        let context = Arc::new(TestContext {
+            storage,
+            channel: Default::default(),
+        });
+
+        let app = Router::new()
+            .route(
+                "/collaboration/:workspace",
+                get(
+                    |ws: WebSocketUpgrade,
+                     Path(workspace): Path<String>,
+                     State(context): State<Arc<TestContext>>| async move {
+                        ws.on_upgrade(move |socket| {
+                            handle_socket(socket, "test".into(), context, workspace)
+                        })
+                    },
+                ),
+            )
+            .route(
+                "/api/blobs/:workspace/:hash",
+                get(
+                    |Path((workspace, hash)): Path<(String, String)>,
+                     headers: HeaderMap,
+                     State(context): State<Arc<TestContext>>| async move {
+                        if headers.get(AUTHORIZATION).is_none() {
+                            return Err(StatusCode::UNAUTHORIZED);
+                        }
+                        context
+                            .storage
+                            .blobs()
+                            .get_blob(Some(workspace), hash)
+                            .await
+                            .map(StreamBody::new)
+                            .map_err(|_| StatusCode::NOT_FOUND)
+                    },
+                ),
+            )
+            .with_state(context.clone());
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        (context, addr)
+    }
+
+    #[tokio::test]
+    async fn migrate() {
+        let (context, addr) = source().await;
+        let blob = Bytes::from_static(b"image");
+        let hash = context
+            .storage
+            .blobs()
+            .put_blob(Some("test".into()), stream::iter([blob.clone()]))
+            .await
+            .unwrap();
+        let workspace = context.storage.get_workspace("test").await.unwrap();
+        workspace.with_trx(|mut t| {
+            let page = t.create("page", "affine:page");
+            let image = t.create("image", "affine:embed");
+            image.set(&mut t.trx, "sourceId", hash.as_str());
+            page.push_children(&mut t.trx, &image);
+        });
+
+        let dest = JwstStorage::new("sqlite::memory:").await.unwrap();
+        let url = format!("ws://{addr}/collaboration/test");
+        let progress = MigrateProgress::default();
+        let allowed = ["127.0.0.1".to_owned()];
+        let report = migrate_workspace(
+            &url,
+            Some("token"),
+            Some(&allowed),
+            &dest,
+            "test",
+            &progress,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            report,
+            MigrateReport {
+                blocks: 2,
+                blobs: 1,
+                skipped_blobs: 0
+            }
+        );
+        assert!(progress.synced());
+        assert_eq!((progress.blobs_done(), progress.blobs_total()), (1, 1));
+
+        let migrated = dest.get_workspace("test").await.unwrap();
+        migrated.with_trx(|t| {
+            let image = migrated.get(&t.trx, "image").unwrap();
+            assert_eq!(image.get(&t.trx, "sourceId").unwrap().to_string(), hash);
+        });
+        assert!(dest.blobs().exists("test", &hash).await.unwrap());
+
+        // a second run only syncs the doc again
+        let report =
+            migrate_workspace(&url, None, None, &dest, "test", &MigrateProgress::default())
+                .await
+                .unwrap();
+        assert_eq!((report.blobs, report.skipped_blobs), (0, 1));
+
+        let allowed = ["example.com".to_owned()];
+        assert!(migrate_workspace(
+            &url,
+            None,
+            Some(&allowed),
+            &dest,
+            "test",
+            &MigrateProgress::default(),
+        )
+        .await
+        .is_err());
+    }
+
+    #[test]
+    fn blob_url_of_source() {
+        let keck = Url::parse("wss://keck.example.com/collaboration/ws?token=a").unwrap();
+        assert_eq!(
+            blob_url(&keck, "ws", "hash").unwrap().as_str(),
+            "https://keck.example.com/api/blobs/ws/hash"
+        );
+        let cloud = Url::parse("ws://cloud.example.com/api/sync/ws").unwrap();
+        assert_eq!(
+            blob_url(&cloud, "ws", "hash").unwrap().as_str(),
+            "http://cloud.example.com/api/workspace/ws/blob/hash"
+        );
+    }
+}
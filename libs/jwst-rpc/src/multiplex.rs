@@ -0,0 +1,500 @@
+use super::*;
+use crate::channel::MissedUpdates;
+use jwst::DocStorage;
+use lib0::{
+    decoding::{Cursor, Read},
+    encoding::Write,
+};
+use std::collections::HashMap;
+use tokio::{
+    sync::{broadcast::error::RecvError, mpsc::Sender},
+    task::JoinHandle,
+};
+
+const MSG_JOIN: u32 = 0;
+const MSG_LEAVE: u32 = 1;
+const MSG_DATA: u32 = 2;
+
+/// A frame on a multiplexed sync socket, every frame is prefixed with the workspace id it targets.
+#[derive(Debug, PartialEq)]
+pub enum MultiplexMessage {
+    /// subscribe the socket to a workspace
+    Join(String),
+    /// unsubscribe the socket from a workspace
+    Leave(String),
+    /// y-sync payload of a workspace
+    Data(String, Vec<u8>),
+}
+
+impl MultiplexMessage {
+    pub fn decode(binary: &[u8]) -> Option<Self> {
+        let mut cursor = Cursor::new(binary);
+        let tag: u32 = cursor.read_var().ok()?;
+        let workspace = cursor.read_string().ok()?.to_owned();
+
+        match tag {
+            MSG_JOIN => Some(Self::Join(workspace)),
+            MSG_LEAVE => Some(Self::Leave(workspace)),
+            MSG_DATA => Some(Self::Data(workspace, cursor.read_buf().ok()?.to_vec())),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buffer = vec![];
+        match self {
+            Self::Join(workspace) => {
+                buffer.write_var(MSG_JOIN);
+                buffer.write_string(workspace);
+            }
+            Self::Leave(workspace) => {
+                buffer.write_var(MSG_LEAVE);
+                buffer.write_string(workspace);
+            }
+            Self::Data(workspace, data) => {
+                buffer.write_var(MSG_DATA);
+                buffer.write_string(workspace);
+                buffer.write_buf(data);
+            }
+        }
+        buffer
+    }
+}
+
+// the workspace a frame targets, without copying its payload
+fn frame_workspace(binary: &[u8]) -> Option<&str> {
+    let mut cursor = Cursor::new(binary);
+    let _tag: u32 = cursor.read_var().ok()?;
+    cursor.read_string().ok()
+}
+
+struct JoinedWorkspace {
+    item: ChannelItem,
+    forwarders: Vec<JoinHandle<()>>,
+    // broadcasts the changes of the workspace until it is left
+    subscriptions: Subscriptions,
+    awareness: AwarenessLimiter,
+    // clients the socket syncs the workspace for
+    peer: SyncPeer,
+    // traffic of the frames of the workspace
+    bandwidth: ConnectionBandwidth,
+}
+
+async fn join_workspace(
+    context: Arc<impl ContextImpl<'static> + Send + Sync + 'static>,
+    workspace_id: &str,
+    identifier: &str,
+    socket: Sender<Vec<u8>>,
+) -> Option<JoinedWorkspace> {
+    if !context.create_missing_workspaces() {
+        match context
+            .get_storage()
+            .docs()
+            .exists(workspace_id.to_owned())
+            .await
+        {
+            Ok(true) => {}
+            Ok(false) => {
+                warn!("{identifier} join missing workspace {workspace_id}");
+                return None;
+            }
+            Err(e) => {
+                error!("failed to check workspace {workspace_id}: {e}");
+                return None;
+            }
+        }
+    }
+
+    let mut ws = match context.get_storage().create_workspace(workspace_id).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            error!("failed to join workspace {workspace_id}: {e}");
+            return None;
+        }
+    };
+
+    let item = ChannelItem::new(workspace_id, identifier);
+    let (tx, mut rx) = channel(100);
+    context
+        .get_channel()
+        .write()
+        .await
+        .insert(item.clone(), tx);
+    debug!("{workspace_id} add multiplexed channel: {identifier}");
+
+    let mut server_update = match context
+        .get_storage()
+        .docs()
+        .remote()
+        .entry(workspace_id.to_owned())
+    {
+        Entry::Occupied(tx) => tx.get().subscribe(),
+        Entry::Vacant(v) => {
+            let (tx, rx) = broadcast(100);
+            v.insert(tx);
+            rx
+        }
+    };
+
+    let subscriptions = subscribe(context.clone(), &mut ws, &item);
+    let init_data = ws.sync_init_message().ok();
+
+    let forwarders = vec![
+        // tag broadcasts from other connections with the workspace id
+        tokio::spawn({
+            let socket = socket.clone();
+            let workspace_id = workspace_id.to_owned();
+            async move {
+                while let Some(msg) = rx.recv().await {
+                    let msg = if let Some(data) = msg {
+                        MultiplexMessage::Data(workspace_id.clone(), data)
+                    } else {
+                        // the channel was closed by server, e.g. permission revoked
+                        MultiplexMessage::Leave(workspace_id.clone())
+                    };
+                    if socket.send(msg.encode()).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }),
+        // tag updates written into storage by other clients with the workspace id
+        tokio::spawn({
+            let socket = socket.clone();
+            let workspace_id = workspace_id.to_owned();
+            async move {
+                loop {
+                    match server_update.recv().await {
+                        Ok(msg) => {
+                            let msg = MultiplexMessage::Data(workspace_id.clone(), msg);
+                            if socket.send(msg.encode()).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!("{workspace_id} server update lagged: {skipped}");
+                        }
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+            }
+        }),
+    ];
+
+    let joined = JoinedWorkspace {
+        item,
+        forwarders,
+        subscriptions,
+        awareness: AwarenessLimiter::new(context.awareness_rate_limit()),
+        peer: SyncPeer::new(context.workspace_user(workspace_id, identifier).await),
+        bandwidth: ConnectionBandwidth::new(
+            context.bandwidth_usage(),
+            workspace_id,
+            context
+                .authenticated_user(workspace_id, identifier)
+                .await
+                .as_deref(),
+        ),
+    };
+
+    match init_data {
+        Some(init_data)
+            if socket
+                .send(MultiplexMessage::Data(workspace_id.to_owned(), init_data).encode())
+                .await
+                .is_ok() =>
+        {
+            Some(joined)
+        }
+        _ => {
+            leave_workspace(context, joined).await;
+            None
+        }
+    }
+}
+
+async fn leave_workspace(
+    context: Arc<impl ContextImpl<'static> + Send + Sync + 'static>,
+    joined: JoinedWorkspace,
+) {
+    for forwarder in joined.forwarders {
+        forwarder.abort();
+    }
+    drop(joined.subscriptions);
+    context.get_channel().write().await.remove(&joined.item);
+    debug!(
+        "{} remove multiplexed channel: {}",
+        joined.item.workspace, joined.item.identifier
+    );
+    joined
+        .bandwidth
+        .close(context.bandwidth_usage(), context.get_storage())
+        .await;
+}
+
+// when the next awareness frame kept by the limiters of the joined workspaces is due
+fn awareness_deadline(joined: &HashMap<String, JoinedWorkspace>) -> Option<std::time::Instant> {
+    joined
+        .values()
+        .filter_map(|workspace| workspace.awareness.deadline())
+        .min()
+}
+
+// the first joined workspace that missed a doc update, with the state it missed it from
+async fn missed_update(joined: &HashMap<String, JoinedWorkspace>) -> (String, StateVector) {
+    let missed = joined
+        .iter()
+        .map(|(workspace_id, workspace)| (workspace_id.clone(), workspace.item.missed.clone()))
+        .collect::<Vec<(String, Arc<MissedUpdates>)>>();
+    if missed.is_empty() {
+        return futures::future::pending().await;
+    }
+    let waits = missed.into_iter().map(|(workspace_id, missed)| {
+        Box::pin(async move {
+            let since = missed.wait().await;
+            (workspace_id, since)
+        })
+    });
+    futures::future::select_all(waits).await.0
+}
+
+/// Sync multiple workspaces over a single socket.
+///
+/// Clients send [MultiplexMessage::Join] and [MultiplexMessage::Leave] to subscribe
+/// or unsubscribe workspaces, and wrap every y-sync message in [MultiplexMessage::Data].
+/// A join that fails, like the one of a missing workspace if
+/// [ContextImpl::create_missing_workspaces] is `false`, is answered with a
+/// [MultiplexMessage::Leave] of the workspace.
+pub async fn handle_multiplexed_socket(
+    socket: WebSocket,
+    context: Arc<impl ContextImpl<'static> + Send + Sync + 'static>,
+    identifier: String,
+) {
+    info!("{} collaborate with multiplexed socket", identifier);
+
+    let (mut socket_tx, mut socket_rx) = socket.split();
+    let (tx, mut rx) = channel(100);
+
+    let mut joined: HashMap<String, JoinedWorkspace> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            msg = socket_rx.next() => {
+                let binary = match msg {
+                    Some(Ok(Message::Binary(binary))) => binary,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+                debug!("recv multiplexed from remote: {}bytes", binary.len());
+
+                match MultiplexMessage::decode(&binary) {
+                    Some(MultiplexMessage::Join(workspace_id)) => {
+                        if joined.contains_key(&workspace_id) {
+                            continue;
+                        }
+                        if let Some(workspace) =
+                            join_workspace(context.clone(), &workspace_id, &identifier, tx.clone())
+                                .await
+                        {
+                            workspace.bandwidth.received(binary.len());
+                            joined.insert(workspace_id, workspace);
+                        } else if tx.send(MultiplexMessage::Leave(workspace_id).encode()).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(MultiplexMessage::Leave(workspace_id)) => {
+                        if let Some(workspace) = joined.remove(&workspace_id) {
+                            workspace.bandwidth.received(binary.len());
+                            leave_workspace(context.clone(), workspace).await;
+                        }
+                    }
+                    Some(MultiplexMessage::Data(workspace_id, data)) => {
+                        let Some(workspace) = joined.get_mut(&workspace_id) else {
+                            warn!("{identifier} send data to unjoined workspace {workspace_id}");
+                            continue;
+                        };
+                        workspace.bandwidth.received(binary.len());
+                        if !context.get_channel().read().await.contains_key(&workspace.item) {
+                            // channel was closed by server
+                            if let Some(workspace) = joined.remove(&workspace_id) {
+                                leave_workspace(context.clone(), workspace).await;
+                            }
+                            continue;
+                        }
+                        let data = match awareness_clients(&data) {
+                            Some(clients) => match workspace.awareness.push(data) {
+                                Some(data) => data,
+                                None => {
+                                    // applied later, keep the client from looking gone meanwhile
+                                    refresh_awareness(&context, &workspace_id, &clients).await;
+                                    continue;
+                                }
+                            },
+                            None => data,
+                        };
+
+                        let peer = &mut workspace.peer;
+                        let payload = match context.get_storage().get_workspace(&workspace_id).await {
+                            Ok(mut workspace) => {
+                                use std::panic::{catch_unwind, AssertUnwindSafe};
+                                catch_unwind(AssertUnwindSafe(|| workspace.sync_decode_untrusted_message(&data, peer)))
+                            }
+                            Err(e) => {
+                                error!("failed to get workspace {workspace_id}: {e}");
+                                continue;
+                            }
+                        };
+                        match payload {
+                            Ok(Ok(messages)) => {
+                                for reply in messages {
+                                    let reply = MultiplexMessage::Data(workspace_id.clone(), reply);
+                                    if tx.send(reply.encode()).await.is_err() {
+                                        break;
+                                    }
+                                }
+                            }
+                            Ok(Err(e)) => warn!("{identifier} send rejected sync message to {workspace_id}: {e}"),
+                            Err(_) => {}
+                        }
+                    }
+                    None => warn!("{identifier} send invalid multiplexed frame"),
+                }
+            },
+            _ = sleep_until(awareness_deadline(&joined).unwrap_or_else(std::time::Instant::now).into()),
+                if awareness_deadline(&joined).is_some() =>
+            {
+                for (workspace_id, workspace) in joined.iter_mut() {
+                    if let Some(data) = workspace.awareness.take_due() {
+                        // awareness updates have no reply
+                        decode_message(&context, workspace_id, &data, &mut workspace.peer).await;
+                    }
+                }
+            },
+            (workspace_id, since) = missed_update(&joined) => {
+                debug!("{workspace_id} resync {identifier} after a dropped update");
+                if let Some(data) = resync(&context, &workspace_id, None, &since).await {
+                    if tx.send(MultiplexMessage::Data(workspace_id, data).encode()).await.is_err() {
+                        break;
+                    }
+                }
+            },
+            Some(msg) = rx.recv() => {
+                trace!("recv from multiplexed channel: {}bytes", msg.len());
+                if let Some(workspace) = frame_workspace(&msg).and_then(|id| joined.get(id)) {
+                    workspace.bandwidth.sent(msg.len());
+                }
+                if let Err(e) = socket_tx.send(Message::Binary(msg)).await {
+                    error!("send error: {}", e);
+                    break;
+                }
+            },
+            _ = sleep(Duration::from_secs(5)) => {
+                for workspace_id in joined.keys() {
+                    context
+                        .get_storage()
+                        .full_migrate(workspace_id.clone(), None, false)
+                        .await;
+                }
+            }
+        }
+    }
+
+    for (_, workspace) in joined.drain() {
+        leave_workspace(context.clone(), workspace).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{client::prepare_connection, Channels};
+    use axum::{extract::ws::WebSocketUpgrade, routing::get, Router};
+    use tokio::time::timeout;
+    use tokio_tungstenite::tungstenite::Message as ClientMessage;
+
+    struct TestContext {
+        storage: JwstStorage,
+        channel: Channels,
+    }
+
+    impl ContextImpl<'_> for TestContext {
+        fn get_storage(&self) -> &JwstStorage {
+            &self.storage
+        }
+
+        fn get_channel(&self) -> &Channels {
+            &self.channel
+        }
+
+        fn create_missing_workspaces(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn multiplex_message_codec() {
+        let messages = [
+            MultiplexMessage::Join("a".into()),
+            MultiplexMessage::Leave("b".into()),
+            MultiplexMessage::Data("c".into(), vec![0, 2, 2, 0, 0]),
+        ];
+
+        for message in messages {
+            assert_eq!(MultiplexMessage::decode(&message.encode()), Some(message));
+        }
+
+        assert_eq!(MultiplexMessage::decode(&[9, 1, 97]), None);
+        assert_eq!(MultiplexMessage::decode(&[]), None);
+    }
+
+    #[tokio::test]
+    async fn join_missing_workspace() {
+        let context = Arc::new(TestContext {
+            storage: JwstStorage::new("sqlite::memory:").await.unwrap(),
+            channel: Default::default(),
+        });
+        context.storage.create_workspace("test").await.unwrap();
+        let app = Router::new().route(
+            "/multiplex",
+            get({
+                let context = context.clone();
+                |ws: WebSocketUpgrade| async move {
+                    ws.on_upgrade(move |socket| {
+                        handle_multiplexed_socket(socket, context, "user".into())
+                    })
+                }
+            }),
+        );
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let mut socket = prepare_connection(&format!("ws://{addr}/multiplex"))
+            .await
+            .unwrap();
+        for workspace in ["missing", "test"] {
+            let join = MultiplexMessage::Join(workspace.into());
+            socket
+                .send(ClientMessage::Binary(join.encode()))
+                .await
+                .unwrap();
+        }
+
+        let mut received = vec![];
+        while let Ok(Some(Ok(message))) = timeout(Duration::from_millis(500), socket.next()).await {
+            received.push(MultiplexMessage::decode(&message.into_data()).unwrap());
+        }
+        assert_eq!(received[0], MultiplexMessage::Leave("missing".into()));
+        assert!(received[1..]
+            .iter()
+            .all(|message| matches!(message, MultiplexMessage::Data(id, _) if id == "test")));
+        assert!(received.len() > 1);
+        assert!(!context
+            .storage
+            .docs()
+            .exists("missing".into())
+            .await
+            .unwrap());
+    }
+}
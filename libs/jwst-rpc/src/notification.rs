@@ -0,0 +1,149 @@
+use lib0::{
+    decoding::{Cursor, Read},
+    encoding::Write,
+};
+use y_sync::sync::Message;
+use yrs::updates::{decoder::Decode, encoder::Encode};
+
+/// Custom sync message sent by the server to every connection of a workspace,
+/// carries a [ServerNotification].
+pub(crate) const MSG_NOTIFICATION: u8 = 103;
+
+const NOTIFY_LOCKED: u32 = 0;
+const NOTIFY_UNLOCKED: u32 = 1;
+const NOTIFY_FORCE_RESYNC: u32 = 2;
+const NOTIFY_ANNOUNCEMENT: u32 = 3;
+
+/// Out-of-band message pushed by the server to the clients of a workspace,
+/// see [ContextImpl::notify_room]. Only connected clients receive it, nothing is replayed later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerNotification {
+    /// the workspace was locked, edits are rejected until it is unlocked
+    Locked { by: String },
+    /// the workspace can be edited again
+    Unlocked,
+    /// the client should drop its sync state and start a full sync
+    ForceResync,
+    /// a message to show to the users
+    Announcement(String),
+}
+
+impl ServerNotification {
+    pub fn decode(binary: &[u8]) -> Option<Self> {
+        let Message::Custom(MSG_NOTIFICATION, data) = Message::decode_v1(binary).ok()? else {
+            return None;
+        };
+        let mut cursor = Cursor::new(&data);
+        let tag: u32 = cursor.read_var().ok()?;
+
+        match tag {
+            NOTIFY_LOCKED => Some(Self::Locked {
+                by: cursor.read_string().ok()?.to_owned(),
+            }),
+            NOTIFY_UNLOCKED => Some(Self::Unlocked),
+            NOTIFY_FORCE_RESYNC => Some(Self::ForceResync),
+            NOTIFY_ANNOUNCEMENT => Some(Self::Announcement(cursor.read_string().ok()?.to_owned())),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buffer = vec![];
+        match self {
+            Self::Locked { by } => {
+                buffer.write_var(NOTIFY_LOCKED);
+                buffer.write_string(by);
+            }
+            Self::Unlocked => buffer.write_var(NOTIFY_UNLOCKED),
+            Self::ForceResync => buffer.write_var(NOTIFY_FORCE_RESYNC),
+            Self::Announcement(message) => {
+                buffer.write_var(NOTIFY_ANNOUNCEMENT);
+                buffer.write_string(message);
+            }
+        }
+        Message::Custom(MSG_NOTIFICATION, buffer).encode_v1()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ChannelItem, Channels, ContextImpl};
+    use jwst_storage::JwstStorage;
+    use tokio::sync::mpsc::channel;
+
+    struct TestContext {
+        storage: JwstStorage,
+        channel: Channels,
+    }
+
+    impl ContextImpl<'_> for TestContext {
+        fn get_storage(&self) -> &JwstStorage {
+            &self.storage
+        }
+
+        fn get_channel(&self) -> &Channels {
+            &self.channel
+        }
+    }
+
+    #[test]
+    fn notification_codec() {
+        for notification in [
+            ServerNotification::Locked { by: "admin".into() },
+            ServerNotification::Unlocked,
+            ServerNotification::ForceResync,
+            ServerNotification::Announcement("maintenance at 12:00".into()),
+        ] {
+            assert_eq!(
+                ServerNotification::decode(&notification.encode()),
+                Some(notification)
+            );
+        }
+        assert_eq!(
+            ServerNotification::decode(&Message::Custom(MSG_NOTIFICATION, vec![9]).encode_v1()),
+            None
+        );
+        assert_eq!(ServerNotification::decode(&[]), None);
+    }
+
+    #[tokio::test]
+    async fn notify_workspace_connections() {
+        let context = TestContext {
+            storage: JwstStorage::new("sqlite::memory:").await.unwrap(),
+            channel: Default::default(),
+        };
+
+        let (a_tx, mut a_rx) = channel(1);
+        let (b_tx, mut b_rx) = channel(1);
+        let (other_tx, mut other_rx) = channel(1);
+        {
+            let mut channels = context.channel.write().await;
+            channels.insert(ChannelItem::new("test", "a"), a_tx);
+            channels.insert(ChannelItem::new("test", "b"), b_tx);
+            channels.insert(ChannelItem::new("other", "c"), other_tx);
+        }
+
+        let notification = ServerNotification::Locked { by: "admin".into() };
+        assert_eq!(context.notify_room("test", notification.clone()).await, 2);
+        for rx in [&mut a_rx, &mut b_rx] {
+            let message = rx.recv().await.unwrap().unwrap();
+            assert_eq!(
+                ServerNotification::decode(&message),
+                Some(notification.clone())
+            );
+        }
+        assert!(other_rx.try_recv().is_err());
+
+        // full channels miss the notification, it is not queued for later
+        context.channel.read().await.values().for_each(|tx| {
+            let _ = tx.try_send(Some(vec![]));
+        });
+        assert_eq!(
+            context
+                .notify_room("test", ServerNotification::Unlocked)
+                .await,
+            0
+        );
+    }
+}
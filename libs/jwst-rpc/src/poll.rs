@@ -0,0 +1,313 @@
+use super::{debug, ContextImpl};
+use anyhow::Context;
+use jwst::{
+    JwstError, JwstResult, PermissionError, SyncPeer, SyncValidationError, Workspace,
+    WorkspacePermission,
+};
+use std::sync::Arc;
+use tokio::{
+    sync::Notify,
+    time::{timeout_at, Duration, Instant},
+};
+use y_sync::sync::{Message, MessageReader, SyncMessage};
+use yrs::{
+    updates::{
+        decoder::DecoderV1,
+        encoder::{Encode, Encoder, EncoderV1},
+    },
+    ReadTxn, StateVector, Transact,
+};
+
+// apply the updates of a poll request like the frames of a websocket of the user of `peer`,
+// returns the state vector of the client including the updates it just sent
+fn apply_poll_message(
+    workspace: &mut Workspace,
+    message: &[u8],
+    peer: &mut SyncPeer,
+) -> JwstResult<StateVector> {
+    let mut decoder = DecoderV1::from(message);
+    let mut state_vector = StateVector::default();
+    let mut updates = false;
+    // the messages besides the state vector, answered by the poll instead
+    let mut frame = EncoderV1::new();
+    for msg in MessageReader::new(&mut decoder) {
+        match msg.context("failed to decode sync message")? {
+            Message::Sync(SyncMessage::SyncStep1(sv)) => state_vector = sv,
+            msg => {
+                updates |= matches!(
+                    msg,
+                    Message::Sync(SyncMessage::SyncStep2(_) | SyncMessage::Update(_))
+                );
+                msg.encode(&mut frame);
+            }
+        }
+    }
+
+    // read only users poll with their state vector alone
+    if updates {
+        let permission = workspace.with_trx(|t| workspace.get_permission(&t.trx, peer.user()));
+        if permission < WorkspacePermission::Write {
+            return Err(PermissionError::Denied {
+                user: peer.user().id.clone(),
+                required: WorkspacePermission::Write,
+            }
+            .into());
+        }
+    }
+
+    let before = workspace.doc().transact().state_vector();
+    let frame = frame.to_vec();
+    if !frame.is_empty() {
+        workspace
+            .sync_decode_untrusted_message(&frame, peer)
+            .map_err(|e| match e {
+                SyncValidationError::Permission(e) => JwstError::Permission(e),
+                e => anyhow::Error::from(e)
+                    .context("rejected sync message")
+                    .into(),
+            })?;
+    }
+
+    // the client already has what it sent, as long as it was up to date with the client ids
+    for (client, clock) in workspace.doc().transact().state_vector().iter() {
+        if state_vector.get(client) >= before.get(client) {
+            state_vector.set_max(*client, *clock);
+        }
+    }
+
+    Ok(state_vector)
+}
+
+fn encode_delta(workspace: &mut Workspace, state_vector: StateVector) -> JwstResult<Vec<u8>> {
+    let mut encoder = EncoderV1::new();
+    if let Some(reply) = workspace
+        .sync_handle_message(Message::Sync(SyncMessage::SyncStep1(state_vector)))
+        .context("failed to encode sync step 2")?
+    {
+        reply.encode(&mut encoder);
+    }
+    Ok(encoder.to_vec())
+}
+
+/// Sync a workspace over request/response, for clients that can't open a websocket.
+///
+/// `message` holds the same sync messages as a websocket frame: a `SyncStep1` with the
+/// state vector of the client, followed by its local updates if any. The updates are
+/// checked and applied like the ones of a websocket of the
+/// [user](ContextImpl::workspace_user) `identifier`, then a `SyncStep2` with the missing
+/// content is returned as soon as there is something new for the client. Returns `None`
+/// if nothing changed before `timeout`, and [JwstError::Permission] if the user may not
+/// write to the workspace but sent updates.
+pub async fn handle_poll(
+    context: Arc<impl ContextImpl<'static> + Send + Sync + 'static>,
+    workspace_id: String,
+    identifier: &str,
+    message: &[u8],
+    timeout: Duration,
+) -> JwstResult<Option<Vec<u8>>> {
+    let deadline = Instant::now() + timeout;
+    let storage = context.get_storage();
+    let mut workspace = storage.get_workspace(&workspace_id).await?;
+    let mut peer = SyncPeer::new(context.workspace_user(&workspace_id, identifier).await);
+
+    let state_vector = apply_poll_message(&mut workspace, message, &mut peer)?;
+    storage
+        .full_migrate(workspace_id.clone(), None, false)
+        .await;
+    debug!("{workspace_id} polled, waiting for changes");
+
+    // subscribe after the updates of the client are applied, so they don't wake up the poll
+    let changed = Arc::new(Notify::new());
+    let _sub = workspace.observe({
+        let changed = changed.clone();
+        move |_, _| changed.notify_one()
+    });
+
+    let has_new_content = workspace
+        .doc()
+        .transact()
+        .state_vector()
+        .iter()
+        .any(|(client, clock)| *clock > state_vector.get(client));
+    if !has_new_content && timeout_at(deadline, changed.notified()).await.is_err() {
+        return Ok(None);
+    }
+
+    encode_delta(&mut workspace, state_vector).map(Some)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Channels;
+    use async_trait::async_trait;
+    use jwst::WorkspaceUser;
+    use jwst_storage::JwstStorage;
+    use yrs::{updates::decoder::Decode, Doc, Map, Update};
+
+    struct TestContext {
+        storage: JwstStorage,
+        channel: Channels,
+    }
+
+    #[async_trait]
+    impl ContextImpl<'_> for TestContext {
+        fn get_storage(&self) -> &JwstStorage {
+            &self.storage
+        }
+
+        fn get_channel(&self) -> &Channels {
+            &self.channel
+        }
+
+        // `reader` may only read the workspace
+        async fn workspace_user(&self, _workspace_id: &str, identifier: &str) -> WorkspaceUser {
+            let granted = match identifier {
+                "reader" => WorkspacePermission::ReadOnly,
+                _ => WorkspacePermission::Write,
+            };
+            WorkspaceUser::new(identifier, granted)
+        }
+    }
+
+    async fn context() -> Arc<TestContext> {
+        let storage = JwstStorage::new("sqlite::memory:").await.unwrap();
+        storage.create_workspace("test").await.unwrap();
+        Arc::new(TestContext {
+            storage,
+            channel: Default::default(),
+        })
+    }
+
+    fn poll_message(doc: &Doc, update: Option<Vec<u8>>) -> Vec<u8> {
+        let mut encoder = EncoderV1::new();
+        Message::Sync(SyncMessage::SyncStep1(doc.transact().state_vector())).encode(&mut encoder);
+        if let Some(update) = update {
+            Message::Sync(SyncMessage::Update(update)).encode(&mut encoder);
+        }
+        encoder.to_vec()
+    }
+
+    fn apply_reply(doc: &Doc, reply: &[u8]) {
+        let mut decoder = DecoderV1::from(reply);
+        for msg in MessageReader::new(&mut decoder) {
+            match msg.unwrap() {
+                Message::Sync(SyncMessage::SyncStep2(update)) => doc
+                    .transact_mut()
+                    .apply_update(Update::decode_v1(&update).unwrap()),
+                msg => panic!("unexpected message: {msg:?}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn poll_sync() {
+        let context = context().await;
+        let timeout = Duration::from_secs(5);
+
+        // local update is applied and nothing else is pending on the server
+        let client = Doc::with_client_id(1);
+        let map = client.get_or_insert_map("test");
+        let update = {
+            let mut trx = client.transact_mut();
+            map.insert(&mut trx, "client", "a");
+            trx.encode_update_v1()
+        };
+        let poll = tokio::spawn({
+            let context = context.clone();
+            let message = poll_message(&client, Some(update));
+            async move { handle_poll(context, "test".into(), "user", &message, timeout).await }
+        });
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!poll.is_finished());
+
+        // a change on the server answers the pending poll
+        context
+            .storage
+            .get_workspace("test")
+            .await
+            .unwrap()
+            .with_trx(|mut t| {
+                t.create("block", "text");
+            });
+        let reply = poll.await.unwrap().unwrap().unwrap();
+        apply_reply(&client, &reply);
+
+        let server = context.storage.get_workspace("test").await.unwrap();
+        assert!(server.with_trx(|t| server.exists(&t.trx, "block")));
+        assert_eq!(
+            client.transact().state_vector(),
+            server.doc().transact().state_vector()
+        );
+        let doc = server.doc();
+        let map = doc.get_or_insert_map("test");
+        let trx = doc.transact();
+        assert_eq!(
+            map.get(&trx, "client").map(|v| v.to_string(&trx)),
+            Some("a".to_owned())
+        );
+        drop(trx);
+
+        // an outdated client is answered immediately
+        let reply = handle_poll(
+            context.clone(),
+            "test".into(),
+            "user",
+            &poll_message(&Doc::new(), None),
+            timeout,
+        )
+        .await
+        .unwrap();
+        assert!(reply.is_some());
+
+        // nothing new until timeout
+        let reply = handle_poll(
+            context,
+            "test".into(),
+            "user",
+            &poll_message(&client, None),
+            Duration::from_millis(100),
+        )
+        .await
+        .unwrap();
+        assert_eq!(reply, None);
+    }
+
+    #[tokio::test]
+    async fn poll_read_only() {
+        let context = context().await;
+        let timeout = Duration::from_secs(5);
+
+        let client = Doc::with_client_id(1);
+        let map = client.get_or_insert_map("test");
+        let update = {
+            let mut trx = client.transact_mut();
+            map.insert(&mut trx, "client", "a");
+            trx.encode_update_v1()
+        };
+        let result = handle_poll(
+            context.clone(),
+            "test".into(),
+            "reader",
+            &poll_message(&client, Some(update)),
+            timeout,
+        )
+        .await;
+        assert!(matches!(result, Err(JwstError::Permission(_))));
+        let server = context.storage.get_workspace("test").await.unwrap();
+        assert_eq!(server.doc().transact().state_vector().get(&1), 0);
+
+        // the state vector alone is answered
+        let reply = handle_poll(
+            context,
+            "test".into(),
+            "reader",
+            &poll_message(&Doc::new(), None),
+            timeout,
+        )
+        .await
+        .unwrap();
+        assert!(reply.is_some());
+    }
+}
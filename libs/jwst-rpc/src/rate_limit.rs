@@ -0,0 +1,348 @@
+use dashmap::DashMap;
+use std::{
+    fmt,
+    str::FromStr,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+/// Default limit of the REST mutations of a workspace, see [MutationLimits].
+pub const DEFAULT_MUTATION_RATE_LIMIT: RateLimit = RateLimit {
+    rate: 10.0,
+    burst: 50,
+};
+
+/// `rate` requests per second on average, up to `burst` of them at once after being idle.
+/// A rate of 0 disables the limit. Written as `rate/burst`, like `10/50`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub rate: f64,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// Checked limit, a limited rate needs a burst of at least 1.
+    pub fn new(rate: f64, burst: u32) -> Result<Self, String> {
+        let limit = Self { rate, burst };
+        if !rate.is_finite() || (!limit.is_unlimited() && burst == 0) {
+            return Err(format!("invalid rate limit {limit}"));
+        }
+        Ok(limit)
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.rate <= 0.0
+    }
+}
+
+impl fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.rate, self.burst)
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (rate, burst) = s
+            .split_once('/')
+            .ok_or_else(|| format!("expected `rate/burst`, got {s:?}"))?;
+        Self::new(
+            rate.trim()
+                .parse()
+                .map_err(|e| format!("invalid rate: {e}"))?,
+            burst
+                .trim()
+                .parse()
+                .map_err(|e| format!("invalid burst: {e}"))?,
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    limit: RateLimit,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            refilled: now,
+        }
+    }
+
+    // the time until the next token if there is none left
+    fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.rate).min(self.limit.burst as f64);
+        self.refilled = now;
+        // tolerate rounding, the wait is computed from the same values
+        if self.tokens >= 1.0 - f64::EPSILON {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / self.limit.rate,
+            ))
+        }
+    }
+}
+
+/// Token buckets limiting the REST mutations of each workspace, so a single client
+/// can't flood a workspace with changes and slow it down for everyone else.
+///
+/// Workspaces use the default limit unless one is set for them, limits can be changed
+/// at any time and apply to the next request. The limits of the workspaces are only kept
+/// while they are in use, [MutationLimits::cleanup] drops them with the buckets, so
+/// callers keeping them elsewhere load them again, see [MutationLimits::is_known].
+#[derive(Debug)]
+pub struct MutationLimits {
+    default: RwLock<RateLimit>,
+    // `None` for the workspaces known to use the default
+    limits: DashMap<String, Option<RateLimit>>,
+    buckets: DashMap<String, Bucket>,
+}
+
+impl Default for MutationLimits {
+    fn default() -> Self {
+        Self::new(DEFAULT_MUTATION_RATE_LIMIT)
+    }
+}
+
+impl MutationLimits {
+    pub fn new(default: RateLimit) -> Self {
+        Self {
+            default: RwLock::new(default),
+            limits: DashMap::new(),
+            buckets: DashMap::new(),
+        }
+    }
+
+    pub fn default_limit(&self) -> RateLimit {
+        *self.default.read().unwrap()
+    }
+
+    pub fn set_default_limit(&self, limit: RateLimit) {
+        *self.default.write().unwrap() = limit;
+    }
+
+    /// Set the limit of a workspace, `None` makes it use the default limit.
+    pub fn set_limit(&self, workspace_id: &str, limit: Option<RateLimit>) {
+        self.limits.insert(workspace_id.to_owned(), limit);
+    }
+
+    /// `true` once [MutationLimits::set_limit] was called for the workspace, so callers
+    /// keeping the limits elsewhere know if they have to be loaded.
+    pub fn is_known(&self, workspace_id: &str) -> bool {
+        self.limits.contains_key(workspace_id)
+    }
+
+    /// Limit that applies to the workspace.
+    pub fn limit(&self, workspace_id: &str) -> RateLimit {
+        self.limits
+            .get(workspace_id)
+            .and_then(|limit| *limit)
+            .unwrap_or_else(|| self.default_limit())
+    }
+
+    /// Take a token for a mutation of the workspace, returns the time until the next
+    /// token if the workspace is over its limit.
+    pub fn check(&self, workspace_id: &str) -> Result<(), Duration> {
+        self.check_at(workspace_id, Instant::now())
+    }
+
+    fn check_at(&self, workspace_id: &str, now: Instant) -> Result<(), Duration> {
+        let limit = self.limit(workspace_id);
+        if limit.is_unlimited() {
+            self.buckets.remove(workspace_id);
+            return Ok(());
+        }
+        let mut bucket = self
+            .buckets
+            .entry(workspace_id.to_owned())
+            .or_insert_with(|| Bucket::new(limit, now));
+        if bucket.limit != limit {
+            // a changed limit doesn't refill the bucket
+            let tokens = bucket.tokens.min(limit.burst as f64);
+            *bucket = Bucket {
+                tokens,
+                ..Bucket::new(limit, bucket.refilled)
+            };
+        }
+        bucket.take(now)
+    }
+
+    /// Drop the buckets that refilled completely, they are the same as new ones, and the
+    /// limits of the workspaces left without a bucket.
+    pub fn cleanup(&self) {
+        self.cleanup_at(Instant::now())
+    }
+
+    fn cleanup_at(&self, now: Instant) {
+        self.buckets.retain(|_, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.refilled).as_secs_f64();
+            bucket.tokens + elapsed * bucket.limit.rate < bucket.limit.burst as f64
+        });
+        self.limits.retain(|id, _| self.buckets.contains_key(id));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn limits(rate: f64, burst: u32) -> MutationLimits {
+        MutationLimits::new(RateLimit { rate, burst })
+    }
+
+    #[test]
+    fn parse_rate_limit() {
+        assert_eq!(
+            "2.5/10".parse::<RateLimit>(),
+            Ok(RateLimit {
+                rate: 2.5,
+                burst: 10
+            })
+        );
+        assert!("0/0".parse::<RateLimit>().unwrap().is_unlimited());
+        assert!("10".parse::<RateLimit>().is_err());
+        assert!("10/0".parse::<RateLimit>().is_err());
+        assert!("inf/10".parse::<RateLimit>().is_err());
+        assert_eq!(
+            DEFAULT_MUTATION_RATE_LIMIT.to_string().parse(),
+            Ok(DEFAULT_MUTATION_RATE_LIMIT)
+        );
+    }
+
+    #[test]
+    fn burst_across_refill() {
+        // a token every 100ms
+        let limits = limits(10.0, 3);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limits.check_at("ws", start), Ok(()));
+        }
+        let wait = limits.check_at("ws", start).unwrap_err();
+        assert!(wait > Duration::from_millis(99) && wait < Duration::from_millis(101));
+
+        // just before the next token
+        let before = start + Duration::from_millis(99);
+        assert!(limits.check_at("ws", before).is_err());
+        // the token refills at 100ms, one request passes, the next one waits again
+        let after = start + Duration::from_millis(100);
+        assert_eq!(limits.check_at("ws", after), Ok(()));
+        let wait = limits.check_at("ws", after).unwrap_err();
+        assert!(wait > Duration::from_millis(99) && wait < Duration::from_millis(101));
+
+        // a burst straddling the refill of the next token: 1 now, 1 after the refill
+        let straddle = start + Duration::from_millis(199);
+        assert!(limits.check_at("ws", straddle).is_err());
+        let refilled = start + Duration::from_millis(201);
+        assert_eq!(limits.check_at("ws", refilled), Ok(()));
+        assert!(limits.check_at("ws", refilled).is_err());
+
+        // idle for long, only the burst is available
+        let idle = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(limits.check_at("ws", idle), Ok(()));
+        }
+        assert!(limits.check_at("ws", idle).is_err());
+    }
+
+    #[test]
+    fn limits_per_workspace() {
+        let limits = limits(1.0, 1);
+        let now = Instant::now();
+
+        assert_eq!(limits.check_at("a", now), Ok(()));
+        assert!(limits.check_at("a", now).is_err());
+        // other workspaces have their own bucket
+        assert_eq!(limits.check_at("b", now), Ok(()));
+
+        assert!(!limits.is_known("a"));
+        limits.set_limit(
+            "a",
+            Some(RateLimit {
+                rate: 0.0,
+                burst: 0,
+            }),
+        );
+        assert!(limits.is_known("a"));
+        for _ in 0..100 {
+            assert_eq!(limits.check_at("a", now), Ok(()));
+        }
+
+        // back to the default, the bucket starts full
+        limits.set_limit("a", None);
+        assert_eq!(limits.limit("a"), limits.default_limit());
+        assert_eq!(limits.check_at("a", now), Ok(()));
+        assert!(limits.check_at("a", now).is_err());
+    }
+
+    #[test]
+    fn cleanup_idle_workspaces() {
+        let limits = limits(1.0, 1);
+        let now = Instant::now();
+
+        assert_eq!(limits.check_at("busy", now), Ok(()));
+        limits.set_limit("busy", None);
+        // unlimited workspaces have no bucket
+        limits.set_limit(
+            "unlimited",
+            Some(RateLimit {
+                rate: 0.0,
+                burst: 0,
+            }),
+        );
+        assert_eq!(limits.check_at("unlimited", now), Ok(()));
+
+        limits.cleanup_at(now);
+        assert!(limits.is_known("busy"));
+        assert!(!limits.is_known("unlimited"));
+        assert_eq!(limits.buckets.len(), 1);
+
+        // refilled, nothing is left of the workspace
+        limits.cleanup_at(now + Duration::from_secs(1));
+        assert!(!limits.is_known("busy"));
+        assert!(limits.buckets.is_empty());
+    }
+
+    #[test]
+    fn change_limit_at_runtime() {
+        let limits = limits(1.0, 2);
+        let now = Instant::now();
+
+        assert_eq!(limits.check_at("ws", now), Ok(()));
+        assert_eq!(limits.check_at("ws", now), Ok(()));
+        assert!(limits.check_at("ws", now).is_err());
+
+        // a larger limit doesn't grant a new burst, it refills faster
+        limits.set_default_limit(RateLimit {
+            rate: 100.0,
+            burst: 10,
+        });
+        assert!(limits.check_at("ws", now).is_err());
+        let later = now + Duration::from_millis(10);
+        assert_eq!(limits.check_at("ws", later), Ok(()));
+        assert!(limits.check_at("ws", later).is_err());
+
+        // a smaller burst caps the tokens left
+        let idle = now + Duration::from_secs(1);
+        assert_eq!(limits.check_at("ws", idle), Ok(()));
+        limits.set_limit(
+            "ws",
+            Some(RateLimit {
+                rate: 1.0,
+                burst: 1,
+            }),
+        );
+        assert_eq!(limits.check_at("ws", idle), Ok(()));
+        assert!(limits.check_at("ws", idle).is_err());
+    }
+}
@@ -0,0 +1,463 @@
+use super::*;
+use crate::broadcast::broadcast_now;
+use jwst::DocStorage;
+use lib0::decoding::{Cursor, Read};
+use std::collections::HashSet;
+use y_sync::sync::{Message as YMessage, SyncMessage};
+use yrs::{
+    diff_updates_v1, encode_state_vector_from_update_v1,
+    updates::{decoder::Decode, encoder::Encode},
+    StateVector, Update,
+};
+
+const MSG_SYNC: usize = 0;
+const MSG_AWARENESS: usize = 1;
+const MSG_SYNC_STEP_1: usize = 0;
+const MSG_SYNC_STEP_2: usize = 1;
+const MSG_SYNC_UPDATE: usize = 2;
+
+/// Workspaces whose sync connections are relayed: frames are passed to the other
+/// connections of the workspace as they are received, without loading the workspace.
+///
+/// Meant for clients that keep their own copy of the workspace, the server only
+/// knows the stored update log, so nothing that reads the doc of the workspace
+/// can be served for them.
+#[derive(Debug, Clone, Default)]
+pub struct RelayWorkspaces {
+    workspaces: HashSet<String>,
+    all: bool,
+    persist: bool,
+}
+
+impl RelayWorkspaces {
+    /// Comma separated workspace ids, `*` relays every workspace.
+    pub fn parse(list: &str) -> Self {
+        let workspaces = list
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(str::to_owned)
+            .collect::<HashSet<_>>();
+        Self {
+            all: workspaces.contains("*"),
+            workspaces,
+            persist: true,
+        }
+    }
+
+    /// Store the relayed updates so connecting clients get the changes made while they
+    /// were away, enabled by default. Without it clients only get the updates sent while
+    /// they are connected.
+    pub fn persist(mut self, persist: bool) -> Self {
+        self.persist = persist;
+        self
+    }
+
+    pub fn contains(&self, workspace_id: &str) -> bool {
+        self.all || self.workspaces.contains(workspace_id)
+    }
+
+    pub fn is_persisted(&self) -> bool {
+        self.persist
+    }
+}
+
+// what a frame carries, the payload is borrowed from the frame
+enum Frame<'a> {
+    StateVector(&'a [u8]),
+    Update(&'a [u8]),
+    Awareness,
+    Other,
+}
+
+fn read_frame(binary: &[u8]) -> Option<Frame<'_>> {
+    let mut cursor = Cursor::new(binary);
+    match cursor.read_var::<usize>().ok()? {
+        MSG_SYNC => match cursor.read_var::<usize>().ok()? {
+            MSG_SYNC_STEP_1 => Some(Frame::StateVector(cursor.read_buf().ok()?)),
+            MSG_SYNC_STEP_2 | MSG_SYNC_UPDATE => Some(Frame::Update(cursor.read_buf().ok()?)),
+            _ => None,
+        },
+        MSG_AWARENESS => Some(Frame::Awareness),
+        _ => Some(Frame::Other),
+    }
+}
+
+// stored updates merged into one, `None` if there are none. The merged update replaces
+// the stored ones, so the log doesn't grow with every relayed update
+async fn stored_update(
+    context: &Arc<impl ContextImpl<'static> + Send + Sync + 'static>,
+    workspace_id: &str,
+) -> Option<Vec<u8>> {
+    context
+        .get_storage()
+        .merge_stored_updates(workspace_id)
+        .await
+        .map_err(|e| error!("failed to merge updates of {workspace_id}: {e}"))
+        .ok()
+        .flatten()
+}
+
+// frame with the stored changes missing from `state_vector`, everything if it's `None`
+async fn sync_step_2(
+    context: &Arc<impl ContextImpl<'static> + Send + Sync + 'static>,
+    workspace_id: &str,
+    state_vector: Option<&[u8]>,
+) -> Option<Vec<u8>> {
+    let update = match stored_update(context, workspace_id).await {
+        Some(update) => match state_vector {
+            Some(state_vector) => diff_updates_v1(&update, state_vector)
+                .map_err(|e| warn!("failed to diff updates of {workspace_id}: {e}"))
+                .ok()?,
+            None => update,
+        },
+        None => Update::new().encode_v1(),
+    };
+    Some(YMessage::Sync(SyncMessage::SyncStep2(update)).encode_v1())
+}
+
+// frame asking the client for the changes missing from the storage
+async fn sync_step_1(
+    context: &Arc<impl ContextImpl<'static> + Send + Sync + 'static>,
+    workspace_id: &str,
+) -> Vec<u8> {
+    let state_vector = stored_update(context, workspace_id)
+        .await
+        .and_then(|update| encode_state_vector_from_update_v1(&update).ok())
+        .and_then(|state_vector| StateVector::decode_v1(&state_vector).ok())
+        .unwrap_or_default();
+    YMessage::Sync(SyncMessage::SyncStep1(state_vector)).encode_v1()
+}
+
+// handle a frame of the client, returns the replies for it
+async fn relay_frame(
+    context: &Arc<impl ContextImpl<'static> + Send + Sync + 'static>,
+    item: &ChannelItem,
+    persist: bool,
+    binary: Vec<u8>,
+) -> Option<Vec<u8>> {
+    match read_frame(&binary) {
+        Some(Frame::StateVector(state_vector)) => {
+            sync_step_2(context, &item.workspace, Some(state_vector)).await
+        }
+        Some(Frame::Update(update)) => {
+            // the doc isn't loaded to apply it, so check the update can be before it is stored
+            if let Err(e) = Update::decode_v1(update) {
+                warn!("rejected relay update for {}: {}", item.workspace, e);
+                return None;
+            }
+            if persist {
+                if let Err(e) = context
+                    .get_storage()
+                    .docs()
+                    .write_update(item.workspace.clone(), update)
+                    .await
+                {
+                    error!("failed to store update of {}: {}", item.workspace, e);
+                }
+            }
+            // the state before the update is not known without the doc, connections that
+            // miss it catch up with the whole stored log
+            broadcast_now(item, binary, context, persist.then(StateVector::default)).await;
+            None
+        }
+        Some(Frame::Awareness) => {
+            broadcast_now(item, binary, context, None).await;
+            None
+        }
+        Some(Frame::Other) => None,
+        None => {
+            warn!("rejected relay message for {}", item.workspace);
+            None
+        }
+    }
+}
+
+/// Sync connection of a relayed workspace, see [RelayWorkspaces].
+///
+/// Doc updates and awareness frames are broadcast to the other connections in the order
+/// they are received, doc updates that decode are also appended to the stored update log
+/// if relayed workspaces are persisted. A state vector is answered with the stored updates
+/// the client is missing, merging the stored log into one update. Sessions and awareness
+/// rate limits don't apply to relayed connections.
+pub(crate) async fn handle_relay_socket(
+    socket: WebSocket,
+    workspace_id: String,
+    context: Arc<impl ContextImpl<'static> + Send + Sync + 'static>,
+    identifier: String,
+) {
+    info!("{} relay workspace {}", identifier, workspace_id);
+    let persist = context
+        .relay_workspaces()
+        .map_or(false, RelayWorkspaces::is_persisted);
+
+    let (mut socket_tx, mut socket_rx) = socket.split();
+    let (tx, mut rx) = channel(100);
+    let bandwidth = ConnectionBandwidth::new(
+        context.bandwidth_usage(),
+        &workspace_id,
+        context
+            .authenticated_user(&workspace_id, &identifier)
+            .await
+            .as_deref(),
+    );
+
+    let channel_item = ChannelItem::new(&workspace_id, &identifier);
+    context
+        .get_channel()
+        .write()
+        .await
+        .insert(channel_item.clone(), tx.clone());
+    debug!("{workspace_id} add relay channel: {identifier}");
+
+    if persist {
+        let init = sync_step_1(&context, &workspace_id).await;
+        // the channel was just created, it has room for it
+        tx.try_send(Some(init)).ok();
+    }
+
+    loop {
+        tokio::select! {
+            msg = socket_rx.next() => {
+                let Some(Ok(msg)) = msg else {
+                    // client disconnected
+                    break;
+                };
+                if let Message::Binary(binary) = msg {
+                    bandwidth.received(binary.len());
+                    if let Some(reply) = relay_frame(&context, &channel_item, persist, binary).await {
+                        if tx.send(Some(reply)).await.is_err() {
+                            // client disconnected
+                            break;
+                        }
+                    }
+                }
+            },
+            _ = channel_item.missed.wait() => {
+                debug!("{workspace_id} resync {identifier} after a dropped update");
+                if let Some(data) = sync_step_2(&context, &workspace_id, None).await {
+                    if tx.send(Some(data)).await.is_err() {
+                        // client disconnected
+                        break;
+                    }
+                }
+            },
+            Some(msg) = rx.recv() => {
+                bandwidth.sent(msg.as_ref().map(Vec::len).unwrap_or_default());
+                if let Err(e) = socket_tx
+                    .send(msg.map(Message::Binary).unwrap_or(Message::Close(None)))
+                    .await
+                {
+                    error!("send error: {}", e);
+                    break;
+                }
+            },
+        }
+    }
+
+    context.get_channel().write().await.remove(&channel_item);
+    debug!("{identifier} left relayed {workspace_id}");
+    bandwidth
+        .close(context.bandwidth_usage(), context.get_storage())
+        .await;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{client::prepare_connection, Channels};
+    use axum::{
+        extract::{ws::WebSocketUpgrade, Path, State},
+        routing::get,
+        Router,
+    };
+    use jwst::Workspace;
+    use std::{net::SocketAddr, time::Instant};
+    use tokio_tungstenite::tungstenite::Message as ClientMessage;
+    use yrs::{ReadTxn, Transact};
+
+    struct TestContext {
+        storage: JwstStorage,
+        channel: Channels,
+        relay: RelayWorkspaces,
+    }
+
+    impl ContextImpl<'_> for TestContext {
+        fn get_storage(&self) -> &JwstStorage {
+            &self.storage
+        }
+
+        fn get_channel(&self) -> &Channels {
+            &self.channel
+        }
+
+        fn relay_workspaces(&self) -> Option<&RelayWorkspaces> {
+            Some(&self.relay)
+        }
+    }
+
+    async fn server(relay: RelayWorkspaces) -> (SocketAddr, Arc<TestContext>) {
+        let context = Arc::new(TestContext {
+            storage: JwstStorage::new("sqlite::memory:").await.unwrap(),
+            channel: Default::default(),
+            relay,
+        });
+        let app = Router::new()
+            .route(
+                "/collaboration/:workspace",
+                get(
+                    |ws: WebSocketUpgrade,
+                     Path(workspace): Path<String>,
+                     State(context): State<Arc<TestContext>>| async move {
+                        ws.on_upgrade(move |socket| {
+                            handle_socket(socket, workspace, context, nanoid::nanoid!())
+                        })
+                    },
+                ),
+            )
+            .with_state(context.clone());
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        (addr, context)
+    }
+
+    // update frames each creating a block
+    fn updates(count: usize) -> Vec<Vec<u8>> {
+        let client = Workspace::new("client");
+        (0..count)
+            .map(|i| {
+                let before = client.doc().transact().state_vector();
+                client.with_trx(|mut t| {
+                    t.create(format!("block{i}"), "affine:text");
+                });
+                sync_encode_update(&client.doc().transact().encode_diff_v1(&before))
+            })
+            .collect()
+    }
+
+    // update frames received within a second of each other
+    async fn receive_updates(socket: &mut crate::client::Socket, count: usize) -> Vec<Vec<u8>> {
+        let mut received = vec![];
+        while received.len() < count {
+            match timeout(Duration::from_secs(1), socket.next()).await {
+                Ok(Some(Ok(ClientMessage::Binary(binary)))) => {
+                    if let Ok(YMessage::Sync(SyncMessage::Update(_))) = YMessage::decode_v1(&binary)
+                    {
+                        received.push(binary);
+                    }
+                }
+                Ok(Some(Ok(_))) => {}
+                _ => break,
+            }
+        }
+        received
+    }
+
+    // frames per second relayed from one connection of `workspace` to another
+    async fn throughput(addr: SocketAddr, workspace: &str, frames: &[Vec<u8>]) -> f64 {
+        let url = format!("ws://{addr}/collaboration/{workspace}");
+        let mut sender = prepare_connection(&url).await.unwrap();
+        let mut receiver = prepare_connection(&url).await.unwrap();
+        // let both connections join
+        sleep(Duration::from_millis(200)).await;
+
+        let start = Instant::now();
+        for frame in frames {
+            sender
+                .send(ClientMessage::Binary(frame.clone()))
+                .await
+                .unwrap();
+        }
+        let received = receive_updates(&mut receiver, frames.len()).await;
+        assert_eq!(received.len(), frames.len());
+        frames.len() as f64 / start.elapsed().as_secs_f64()
+    }
+
+    #[test]
+    fn relay_workspaces() {
+        let relay = RelayWorkspaces::parse("a, b,");
+        assert!(relay.contains("a") && relay.contains("b"));
+        assert!(!relay.contains("c"));
+        assert!(relay.is_persisted());
+        assert!(RelayWorkspaces::parse("*").contains("c"));
+        assert!(!RelayWorkspaces::parse("").persist(false).is_persisted());
+    }
+
+    #[tokio::test]
+    async fn relay_updates() {
+        let (addr, context) = server(RelayWorkspaces::parse("relay")).await;
+        let url = format!("ws://{addr}/collaboration/relay");
+        let frames = updates(3);
+
+        let mut sender = prepare_connection(&url).await.unwrap();
+        let mut receiver = prepare_connection(&url).await.unwrap();
+        sleep(Duration::from_millis(200)).await;
+        // neither stored nor passed on
+        sender
+            .send(ClientMessage::Binary(sync_encode_update(&[255])))
+            .await
+            .unwrap();
+        for frame in &frames {
+            sender
+                .send(ClientMessage::Binary(frame.clone()))
+                .await
+                .unwrap();
+        }
+        // passed on in the order they were sent
+        assert_eq!(receive_updates(&mut receiver, frames.len()).await, frames);
+
+        // stored before they are passed on, without loading the workspace
+        assert_eq!(
+            context.storage.stored_updates("relay").await.unwrap().len(),
+            frames.len()
+        );
+        assert!(context.storage.docs().remote().get("relay").is_none());
+
+        // new connections are answered from the stored updates
+        let mut socket = prepare_connection(&url).await.unwrap();
+        let client = Workspace::new("relay");
+        socket
+            .send(ClientMessage::Binary(client.sync_init_message().unwrap()))
+            .await
+            .unwrap();
+        let mut synced = false;
+        while let Ok(Some(Ok(ClientMessage::Binary(binary)))) =
+            timeout(Duration::from_secs(1), socket.next()).await
+        {
+            if let Ok(YMessage::Sync(SyncMessage::SyncStep2(update))) = YMessage::decode_v1(&binary)
+            {
+                client.apply_updates(&[update]).unwrap();
+                synced = true;
+                break;
+            }
+        }
+        assert!(synced);
+        client.with_trx(|t| {
+            for i in 0..frames.len() {
+                assert!(client.exists(&t.trx, &format!("block{i}")));
+            }
+        });
+        // merged into one to answer it
+        assert_eq!(
+            context.storage.stored_updates("relay").await.unwrap().len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn relay_throughput() {
+        const FRAMES: usize = 200;
+        let (addr, _) = server(RelayWorkspaces::parse("relay")).await;
+        let frames = updates(FRAMES);
+
+        let full = throughput(addr, "full", &frames).await;
+        let relay = throughput(addr, "relay", &frames).await;
+        // relaying skips applying the updates to the doc, it keeps up with the full mode
+        assert!(
+            relay * 2. >= full,
+            "relay mode: {relay:.0} frames/s, full mode: {full:.0} frames/s"
+        );
+    }
+}
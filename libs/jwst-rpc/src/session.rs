@@ -0,0 +1,529 @@
+use super::{debug, trace};
+use dashmap::DashMap;
+use jwst::Workspace;
+use lib0::{
+    decoding::{Cursor, Read},
+    encoding::Write,
+};
+use nanoid::nanoid;
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use y_sync::sync::{Message, SyncMessage};
+use yrs::{
+    updates::{
+        decoder::Decode,
+        encoder::{Encode, Encoder, EncoderV1},
+    },
+    UpdateSubscription,
+};
+
+/// Custom sync message sent by the server, carries the session token and the last sequence
+/// of the update log the client is known to have.
+pub(crate) const MSG_SESSION: u8 = 100;
+/// Custom sync message sent by a reconnecting client as its first frame instead of a full sync.
+pub(crate) const MSG_RESUME: u8 = 101;
+/// Custom sync message sent by the client for every [MSG_SESSION] it receives,
+/// confirms it has the updates up to that sequence.
+pub(crate) const MSG_ACK: u8 = 102;
+
+/// Default time a disconnected session can be resumed.
+pub const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(60);
+/// Default number of updates kept per workspace for resumption.
+pub const DEFAULT_UPDATE_LOG_SIZE: usize = 1000;
+
+fn encode_token(tag: u8, token: &str, seq: u64) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.write_string(token);
+    buf.write_var(seq);
+    Message::Custom(tag, buf).encode_v1()
+}
+
+fn decode_token(tag: u8, binary: &[u8]) -> Option<(String, u64)> {
+    match Message::decode_v1(binary).ok()? {
+        Message::Custom(t, data) if t == tag => {
+            let mut cursor = Cursor::new(&data);
+            let token = cursor.read_string().ok()?.to_owned();
+            let seq = cursor.read_var().ok()?;
+            Some((token, seq))
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn encode_session(token: &str, seq: u64) -> Vec<u8> {
+    encode_token(MSG_SESSION, token, seq)
+}
+
+pub(crate) fn decode_session(binary: &[u8]) -> Option<(String, u64)> {
+    decode_token(MSG_SESSION, binary)
+}
+
+pub(crate) fn encode_resume(token: &str, seq: u64) -> Vec<u8> {
+    encode_token(MSG_RESUME, token, seq)
+}
+
+pub(crate) fn decode_resume(binary: &[u8]) -> Option<(String, u64)> {
+    decode_token(MSG_RESUME, binary)
+}
+
+pub(crate) fn encode_ack(token: &str, seq: u64) -> Vec<u8> {
+    encode_token(MSG_ACK, token, seq)
+}
+
+pub(crate) fn decode_ack(binary: &[u8]) -> Option<(String, u64)> {
+    decode_token(MSG_ACK, binary)
+}
+
+#[derive(Default)]
+struct UpdateLogState {
+    // sequence of the latest update, 0 if nothing was logged yet
+    head: u64,
+    updates: VecDeque<Vec<u8>>,
+}
+
+/// Recent updates of a workspace, numbered by a sequence that only grows.
+/// The oldest updates are dropped once `capacity` is reached.
+struct UpdateLog {
+    capacity: usize,
+    state: Mutex<UpdateLogState>,
+}
+
+impl UpdateLog {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Default::default(),
+        }
+    }
+
+    fn push(&self, update: Vec<u8>) {
+        let mut state = self.state.lock().unwrap();
+        if state.updates.len() >= self.capacity {
+            state.updates.pop_front();
+        }
+        state.updates.push_back(update);
+        state.head += 1;
+    }
+
+    fn head(&self) -> u64 {
+        self.state.lock().unwrap().head
+    }
+
+    /// Updates logged after `seq`, `None` if some of them were already dropped
+    /// or `seq` was never issued by this log.
+    fn since(&self, seq: u64) -> Option<Vec<Vec<u8>>> {
+        let state = self.state.lock().unwrap();
+        let first = state.head - state.updates.len() as u64;
+        if seq < first || seq > state.head {
+            return None;
+        }
+        Some(
+            state
+                .updates
+                .iter()
+                .skip((seq - first) as usize)
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+// the log of a workspace and the observer filling it
+struct LogEntry {
+    log: Arc<UpdateLog>,
+    _sub: Option<UpdateSubscription>,
+}
+
+// the subscription is only kept to be dropped, like the ones [Workspace] holds
+unsafe impl Send for LogEntry {}
+unsafe impl Sync for LogEntry {}
+
+struct SessionEntry {
+    workspace: String,
+    expires: Instant,
+}
+
+/// Session of a connection, the client presents its token on reconnect to receive
+/// the updates it missed instead of running a full sync.
+pub(crate) struct SyncSession {
+    token: String,
+    log: Arc<UpdateLog>,
+    acked: u64,
+    pending: u64,
+    // last sequence the client confirmed with [MSG_ACK]
+    confirmed: u64,
+}
+
+impl SyncSession {
+    pub(crate) fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Message telling the client its token and the sequence it can resume from.
+    pub(crate) fn message(&self) -> Vec<u8> {
+        encode_session(&self.token, self.acked)
+    }
+
+    /// Advance the acknowledged sequence, returns the message to send to the client.
+    ///
+    /// Updates are broadcast to the connection asynchronously, so the sequence lags one
+    /// tick behind the log to be sure everything up to it was flushed to the socket.
+    /// Replaying an update the client already has is harmless.
+    pub(crate) fn tick(&mut self) -> Vec<u8> {
+        self.acked = self.pending;
+        self.pending = self.log.head();
+        self.message()
+    }
+
+    /// Record a [MSG_ACK] of the client, sequences it was never sent are ignored.
+    pub(crate) fn confirm(&mut self, seq: u64) {
+        if seq <= self.acked {
+            self.confirmed = self.confirmed.max(seq);
+        }
+    }
+
+    /// Frame with the updates logged since the client last confirmed its sequence,
+    /// `None` if some of them were already dropped from the log.
+    pub(crate) fn missed(&self) -> Option<Vec<u8>> {
+        let mut encoder = EncoderV1::new();
+        for update in self.log.since(self.confirmed)? {
+            Message::Sync(SyncMessage::Update(update)).encode(&mut encoder);
+        }
+        Some(encoder.to_vec())
+    }
+}
+
+/// Update logs and sessions used to resume sync connections.
+pub struct SyncSessions {
+    ttl: Duration,
+    log_size: usize,
+    // only kept for the workspaces with a session
+    logs: DashMap<String, LogEntry>,
+    sessions: DashMap<String, SessionEntry>,
+}
+
+impl Default for SyncSessions {
+    fn default() -> Self {
+        Self::new(DEFAULT_SESSION_TTL, DEFAULT_UPDATE_LOG_SIZE)
+    }
+}
+
+impl SyncSessions {
+    /// Sessions can be resumed within `ttl` after the connection closed, as long as
+    /// the client missed less than `log_size` updates.
+    pub fn new(ttl: Duration, log_size: usize) -> Self {
+        Self {
+            ttl,
+            log_size,
+            logs: DashMap::new(),
+            sessions: DashMap::new(),
+        }
+    }
+
+    fn log(&self, workspace: &mut Workspace) -> Arc<UpdateLog> {
+        self.logs
+            .entry(workspace.id())
+            .or_insert_with(|| {
+                let log = Arc::new(UpdateLog::new(self.log_size));
+                let sub = workspace.observe({
+                    let log = log.clone();
+                    move |_, e| log.push(e.update.clone())
+                });
+                LogEntry { log, _sub: sub }
+            })
+            .log
+            .clone()
+    }
+
+    // drop the expired sessions and the logs no session can resume from anymore,
+    // connected sessions are kept alive by [SyncSessions::touch]
+    fn cleanup(&self) {
+        let now = Instant::now();
+        self.sessions.retain(|_, session| session.expires > now);
+        let workspaces = self
+            .sessions
+            .iter()
+            .map(|session| session.workspace.clone())
+            .collect::<HashSet<_>>();
+        self.logs
+            .retain(|workspace, _| workspaces.contains(workspace));
+    }
+
+    /// Start the session of a new connection, resuming `resume` if it's still valid.
+    ///
+    /// Returns the session and a frame with the updates the client missed,
+    /// or `None` if the client needs a full sync.
+    pub(crate) fn start(
+        &self,
+        workspace: &mut Workspace,
+        resume: Option<(String, u64)>,
+    ) -> (SyncSession, Option<Vec<u8>>) {
+        let workspace_id = workspace.id();
+        self.cleanup();
+        let log = self.log(workspace);
+        let now = Instant::now();
+
+        if let Some((token, seq)) = resume {
+            let valid = self
+                .sessions
+                .get(&token)
+                .map(|session| session.workspace == workspace_id)
+                .unwrap_or_default();
+            // read before the updates, anything logged in between is sent again later
+            let head = log.head();
+            match log.since(seq).filter(|_| valid) {
+                Some(updates) => {
+                    debug!("{workspace_id} resume session {token} after {seq}");
+                    let mut encoder = EncoderV1::new();
+                    for update in updates {
+                        Message::Sync(SyncMessage::Update(update)).encode(&mut encoder);
+                    }
+                    self.touch(&token);
+                    let session = SyncSession {
+                        token,
+                        log,
+                        acked: head,
+                        pending: head,
+                        confirmed: head,
+                    };
+                    return (session, Some(encoder.to_vec()));
+                }
+                None => debug!("{workspace_id} session {token} is stale, fallback to full sync"),
+            }
+        }
+
+        let token = nanoid!();
+        trace!("{workspace_id} start session {token}");
+        self.sessions.insert(
+            token.clone(),
+            SessionEntry {
+                workspace: workspace_id,
+                expires: now + self.ttl,
+            },
+        );
+        let head = log.head();
+        let session = SyncSession {
+            token,
+            log,
+            acked: head,
+            pending: head,
+            confirmed: head,
+        };
+        (session, None)
+    }
+
+    /// Keep a session alive for another `ttl`.
+    pub(crate) fn touch(&self, token: &str) {
+        if let Some(mut session) = self.sessions.get_mut(token) {
+            session.expires = Instant::now() + self.ttl;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{handle_socket, Channels, ContextImpl};
+    use axum::{
+        extract::{ws::WebSocketUpgrade, State},
+        routing::get,
+        Router,
+    };
+    use futures::{SinkExt, StreamExt};
+    use jwst::sync_encode_update;
+    use jwst_storage::JwstStorage;
+    use std::net::SocketAddr;
+    use tokio::{net::TcpStream, time::timeout};
+    use tokio_tungstenite::{
+        connect_async, tungstenite::Message as WsMessage, MaybeTlsStream, WebSocketStream,
+    };
+    use y_sync::sync::MessageReader;
+    use yrs::updates::decoder::DecoderV1;
+
+    type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    struct TestContext {
+        storage: JwstStorage,
+        channel: Channels,
+        sessions: SyncSessions,
+    }
+
+    impl ContextImpl<'_> for TestContext {
+        fn get_storage(&self) -> &JwstStorage {
+            &self.storage
+        }
+
+        fn get_channel(&self) -> &Channels {
+            &self.channel
+        }
+
+        fn sync_sessions(&self) -> Option<&SyncSessions> {
+            Some(&self.sessions)
+        }
+    }
+
+    async fn server() -> (Arc<TestContext>, SocketAddr) {
+        let storage = JwstStorage::new("sqlite::memory:").await.unwrap();
+        storage.create_workspace("test").await.unwrap();
+        let context = Arc::new(TestContext {
+            storage,
+            channel: Default::default(),
+            sessions: Default::default(),
+        });
+
+        let app = Router::new()
+            .route(
+                "/",
+                get(
+                    |ws: WebSocketUpgrade, State(context): State<Arc<TestContext>>| async move {
+                        ws.on_upgrade(move |socket| {
+                            handle_socket(socket, "test".into(), context, "test".into())
+                        })
+                    },
+                ),
+            )
+            .with_state(context.clone());
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        (context, addr)
+    }
+
+    async fn connect(addr: SocketAddr, first: Vec<u8>) -> Socket {
+        let mut socket = connect_async(format!("ws://{addr}/")).await.unwrap().0;
+        socket.send(WsMessage::Binary(first)).await.unwrap();
+        socket
+    }
+
+    // frames received until the server stays quiet
+    async fn receive(socket: &mut Socket) -> Vec<Vec<u8>> {
+        let mut frames = vec![];
+        while let Ok(Some(Ok(msg))) = timeout(Duration::from_millis(500), socket.next()).await {
+            if let WsMessage::Binary(binary) = msg {
+                frames.push(binary);
+            }
+        }
+        frames
+    }
+
+    fn messages(frames: &[Vec<u8>]) -> Vec<Message> {
+        frames
+            .iter()
+            .flat_map(|frame| {
+                let mut decoder = DecoderV1::from(frame.as_slice());
+                MessageReader::new(&mut decoder)
+                    .map(Result::unwrap)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn update_log_window() {
+        let log = UpdateLog::new(2);
+        assert_eq!(log.since(0), Some(vec![]));
+
+        log.push(vec![1]);
+        log.push(vec![2]);
+        log.push(vec![3]);
+        assert_eq!(log.head(), 3);
+        assert_eq!(log.since(1), Some(vec![vec![2], vec![3]]));
+        assert_eq!(log.since(3), Some(vec![]));
+        // the first update was pruned
+        assert_eq!(log.since(0), None);
+        // never issued
+        assert_eq!(log.since(4), None);
+    }
+
+    #[test]
+    fn drop_logs_without_sessions() {
+        let sessions = SyncSessions::new(Duration::from_millis(50), 10);
+        let mut a = Workspace::new("a");
+        let mut b = Workspace::new("b");
+
+        sessions.start(&mut a, None);
+        std::thread::sleep(Duration::from_millis(100));
+        let (session, _) = sessions.start(&mut b, None);
+        // the session of `a` expired, so did its log
+        assert_eq!(sessions.logs.len(), 1);
+        assert!(sessions.logs.contains_key("b"));
+
+        // the log is owned by the sessions, no update is logged once it is dropped
+        let log = session.log.clone();
+        std::thread::sleep(Duration::from_millis(100));
+        sessions.start(&mut a, None);
+        assert!(!sessions.logs.contains_key("b"));
+        b.with_trx(|mut t| {
+            t.create("block", "text");
+        });
+        assert_eq!(log.head(), 0);
+    }
+
+    #[tokio::test]
+    async fn resume_session() {
+        let (context, addr) = server().await;
+        let mut client = Workspace::new("test");
+
+        // full sync on the first connection
+        let mut socket = connect(addr, client.sync_init_message().unwrap()).await;
+        let frames = receive(&mut socket).await;
+        let (token, seq) = frames.iter().find_map(|f| decode_session(f)).unwrap();
+        for frame in frames {
+            for reply in client.sync_decode_message(&frame) {
+                socket.send(WsMessage::Binary(reply)).await.unwrap();
+            }
+        }
+
+        // the connection is killed right after a local edit
+        client.with_trx(|mut t| {
+            t.create("local", "text");
+        });
+        socket
+            .send(WsMessage::Binary(sync_encode_update(
+                &client.sync_migration(),
+            )))
+            .await
+            .unwrap();
+        receive(&mut socket).await;
+        drop(socket);
+
+        let server = context.storage.get_workspace("test").await.unwrap();
+        assert!(server.with_trx(|t| server.exists(&t.trx, "local")));
+        server.with_trx(|mut t| {
+            t.create("remote", "text");
+        });
+
+        // only the missed updates are sent on reconnect, next to the state vector of the server
+        let mut socket = connect(addr, encode_resume(&token, seq)).await;
+        let frames = receive(&mut socket).await;
+        assert_eq!(
+            frames.iter().find_map(|f| decode_session(f)).unwrap().0,
+            token
+        );
+        assert!(messages(&frames).iter().all(|msg| matches!(
+            msg,
+            Message::Sync(SyncMessage::Update(_) | SyncMessage::SyncStep1(_))
+                | Message::Custom(MSG_SESSION, _)
+        )));
+        for frame in frames {
+            client.sync_decode_message(&frame);
+        }
+        assert!(client.with_trx(|t| client.exists(&t.trx, "remote")));
+
+        // unknown sessions fall back to a full sync
+        let mut socket = connect(addr, encode_resume("unknown", 0)).await;
+        let frames = receive(&mut socket).await;
+        assert_ne!(
+            frames.iter().find_map(|f| decode_session(f)).unwrap().0,
+            "unknown"
+        );
+        assert!(messages(&frames)
+            .iter()
+            .any(|msg| matches!(msg, Message::Sync(SyncMessage::SyncStep1(_)))));
+    }
+}
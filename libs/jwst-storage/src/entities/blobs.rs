@@ -12,6 +12,7 @@ pub struct Model {
     pub blob: Vec<u8>,
     pub length: i64,
     pub timestamp: DateTimeWithTimeZone,
+    pub key_version: i16,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -10,6 +10,9 @@ pub struct Model {
     pub workspace: String,
     pub timestamp: DateTimeWithTimeZone,
     pub blob: Vec<u8>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub chunks: Option<String>,
+    pub key_version: i16,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -2,5 +2,13 @@
 
 pub mod prelude;
 
+pub mod bandwidth_usage;
+pub mod blob_upload_chunks;
+pub mod blob_uploads;
 pub mod blobs;
+pub mod doc_chunks;
+pub mod doc_restore_points;
 pub mod docs;
+pub mod workspace_flags;
+pub mod workspace_guids;
+pub mod workspace_kv;
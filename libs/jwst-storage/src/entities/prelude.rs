@@ -1,4 +1,12 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.10.6
 
+pub use super::bandwidth_usage::Entity as BandwidthUsage;
+pub use super::blob_upload_chunks::Entity as BlobUploadChunks;
+pub use super::blob_uploads::Entity as BlobUploads;
 pub use super::blobs::Entity as Blobs;
+pub use super::doc_chunks::Entity as DocChunks;
+pub use super::doc_restore_points::Entity as DocRestorePoints;
 pub use super::docs::Entity as Docs;
+pub use super::workspace_flags::Entity as WorkspaceFlags;
+pub use super::workspace_guids::Entity as WorkspaceGuids;
+pub use super::workspace_kv::Entity as WorkspaceKv;
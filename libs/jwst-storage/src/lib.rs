@@ -20,7 +20,13 @@ use std::{io::Cursor, num::NonZeroU32, path::PathBuf, sync::Arc, time::Duration}
 use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use url::Url;
 
-pub use storage::JwstStorage;
+pub use storage::{
+    ArchiveImport, ArchiveManifest, Bandwidth, BandwidthScope, BlobAudit, BlobUploadError,
+    DuplicatePlugin, DuplicatePluginRegister, JwstStorage, PropertyChange, RestorePoint,
+    StorageConfig, StorageEncryption, StorageTransaction, TenantId, TenantStorage,
+    WorkspaceStorageStats, DEFAULT_BLOB_UPLOAD_TTL, DEFAULT_MAX_BLOB_UPLOAD, MAX_CHECKED_BLOBS,
+    PLAINTEXT_KEY_VERSION,
+};
 
 pub struct Bucket {
     bucket: Arc<RateLimiter<NotKeyed, InMemoryState, QuantaClock, NoOpMiddleware<QuantaInstant>>>,
@@ -2,6 +2,14 @@ pub use sea_orm_migration::prelude::*;
 
 mod m20220101_000001_initial_blob_table;
 mod m20220101_000002_initial_doc_table;
+mod m20230301_000001_doc_chunk_table;
+mod m20230401_000001_doc_restore_point_table;
+mod m20230415_000001_workspace_flag_table;
+mod m20230501_000001_row_key_version;
+mod m20230515_000001_bandwidth_usage_table;
+mod m20230520_000001_workspace_guid_table;
+mod m20230605_000001_blob_upload_table;
+mod m20230610_000001_workspace_kv_table;
 mod schema;
 
 pub struct Migrator;
@@ -12,6 +20,14 @@ impl MigratorTrait for Migrator {
         vec![
             Box::new(m20220101_000001_initial_blob_table::Migration),
             Box::new(m20220101_000002_initial_doc_table::Migration),
+            Box::new(m20230301_000001_doc_chunk_table::Migration),
+            Box::new(m20230401_000001_doc_restore_point_table::Migration),
+            Box::new(m20230415_000001_workspace_flag_table::Migration),
+            Box::new(m20230501_000001_row_key_version::Migration),
+            Box::new(m20230515_000001_bandwidth_usage_table::Migration),
+            Box::new(m20230520_000001_workspace_guid_table::Migration),
+            Box::new(m20230605_000001_blob_upload_table::Migration),
+            Box::new(m20230610_000001_workspace_kv_table::Migration),
         ]
     }
 }
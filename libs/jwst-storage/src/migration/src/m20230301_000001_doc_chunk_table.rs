@@ -0,0 +1,60 @@
+use super::schema::{DocChunks, Docs};
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20230301_000001_doc_chunk_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Content addressed chunks shared by large doc updates across workspaces.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(DocChunks::Table)
+                    .col(
+                        ColumnDef::new(DocChunks::Hash)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(DocChunks::Blob).binary().not_null())
+                    .col(ColumnDef::new(DocChunks::Length).big_integer().not_null())
+                    .col(ColumnDef::new(DocChunks::Refs).big_integer().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // comma separated chunk hashes, `NULL` means the update is stored inline
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Docs::Table)
+                    .add_column(ColumnDef::new(Docs::Chunks).text().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Docs::Table)
+                    .drop_column(Docs::Chunks)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(DocChunks::Table).to_owned())
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use super::schema::DocRestorePoints;
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20230401_000001_doc_restore_point_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Yjs snapshots of the doc taken when the stored updates are merged.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(DocRestorePoints::Table)
+                    .col(
+                        ColumnDef::new(DocRestorePoints::Id)
+                            .integer()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(DocRestorePoints::Workspace)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(DocRestorePoints::Timestamp)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(DocRestorePoints::Snapshot)
+                            .binary()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("workspaces_restore_point")
+                    .table(DocRestorePoints::Table)
+                    .col(DocRestorePoints::Workspace)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(Index::drop().name("workspaces_restore_point").to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(DocRestorePoints::Table).to_owned())
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,45 @@
+use super::schema::WorkspaceFlags;
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20230415_000001_workspace_flag_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Feature flags of workspaces, kept out of the doc so clients can't change them.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(WorkspaceFlags::Table)
+                    .col(
+                        ColumnDef::new(WorkspaceFlags::Workspace)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(WorkspaceFlags::Flag).string().not_null())
+                    .col(ColumnDef::new(WorkspaceFlags::Enabled).boolean().not_null())
+                    .primary_key(
+                        Index::create()
+                            .col(WorkspaceFlags::Workspace)
+                            .col(WorkspaceFlags::Flag),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(WorkspaceFlags::Table).to_owned())
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use super::schema::BandwidthUsage;
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20230515_000001_bandwidth_usage_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Bytes sent to and received from the clients of a workspace or a user.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(BandwidthUsage::Table)
+                    .col(ColumnDef::new(BandwidthUsage::Scope).string().not_null())
+                    .col(ColumnDef::new(BandwidthUsage::Id).string().not_null())
+                    .col(
+                        ColumnDef::new(BandwidthUsage::Sent)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(BandwidthUsage::Received)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .primary_key(
+                        Index::create()
+                            .col(BandwidthUsage::Scope)
+                            .col(BandwidthUsage::Id),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(BandwidthUsage::Table).to_owned())
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+use super::schema::WorkspaceGuids;
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20230520_000001_workspace_guid_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Guid of the doc of each workspace, kept when the doc is reloaded from its updates.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(WorkspaceGuids::Table)
+                    .col(
+                        ColumnDef::new(WorkspaceGuids::Workspace)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(WorkspaceGuids::Guid).string().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(WorkspaceGuids::Table).to_owned())
+            .await?;
+        Ok(())
+    }
+}
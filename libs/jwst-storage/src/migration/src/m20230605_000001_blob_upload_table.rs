@@ -0,0 +1,76 @@
+use super::schema::{BlobUploadChunks, BlobUploads};
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20230605_000001_blob_upload_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Blobs uploaded in chunks, kept until the upload is completed or abandoned.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(BlobUploads::Table)
+                    .col(
+                        ColumnDef::new(BlobUploads::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(BlobUploads::Workspace).string().not_null())
+                    .col(ColumnDef::new(BlobUploads::Hash).string())
+                    .col(ColumnDef::new(BlobUploads::Length).big_integer().not_null())
+                    .col(
+                        ColumnDef::new(BlobUploads::UpdatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(BlobUploadChunks::Table)
+                    .col(ColumnDef::new(BlobUploadChunks::Upload).string().not_null())
+                    .col(
+                        ColumnDef::new(BlobUploadChunks::Offset)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(BlobUploadChunks::Blob).binary().not_null())
+                    .col(
+                        ColumnDef::new(BlobUploadChunks::KeyVersion)
+                            .small_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .primary_key(
+                        Index::create()
+                            .col(BlobUploadChunks::Upload)
+                            .col(BlobUploadChunks::Offset),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(BlobUploadChunks::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(BlobUploads::Table).to_owned())
+            .await?;
+        Ok(())
+    }
+}
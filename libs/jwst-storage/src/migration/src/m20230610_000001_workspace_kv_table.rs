@@ -0,0 +1,47 @@
+use super::schema::WorkspaceKv;
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20230610_000001_workspace_kv_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Server side state of workspaces, kept out of the doc so it's not synced to clients.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(WorkspaceKv::Table)
+                    .col(ColumnDef::new(WorkspaceKv::Workspace).string().not_null())
+                    .col(ColumnDef::new(WorkspaceKv::Name).string().not_null())
+                    .col(ColumnDef::new(WorkspaceKv::Value).binary().not_null())
+                    .col(
+                        ColumnDef::new(WorkspaceKv::KeyVersion)
+                            .small_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .primary_key(
+                        Index::create()
+                            .col(WorkspaceKv::Workspace)
+                            .col(WorkspaceKv::Name),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(WorkspaceKv::Table).to_owned())
+            .await?;
+        Ok(())
+    }
+}
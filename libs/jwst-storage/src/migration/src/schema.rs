@@ -8,6 +8,7 @@ pub enum Blobs {
     Blob,
     Length,
     Timestamp,
+    KeyVersion,
 }
 
 #[derive(Iden)]
@@ -17,4 +18,76 @@ pub enum Docs {
     Workspace,
     Timestamp,
     Blob,
+    Chunks,
+    KeyVersion,
+}
+
+#[derive(Iden)]
+pub enum DocChunks {
+    Table,
+    Hash,
+    Blob,
+    Length,
+    Refs,
+}
+
+#[derive(Iden)]
+pub enum DocRestorePoints {
+    Table,
+    Id,
+    Workspace,
+    Timestamp,
+    Snapshot,
+}
+
+#[derive(Iden)]
+pub enum WorkspaceFlags {
+    Table,
+    Workspace,
+    Flag,
+    Enabled,
+}
+
+#[derive(Iden)]
+pub enum BandwidthUsage {
+    Table,
+    Scope,
+    Id,
+    Sent,
+    Received,
+}
+
+#[derive(Iden)]
+pub enum WorkspaceGuids {
+    Table,
+    Workspace,
+    Guid,
+}
+
+#[derive(Iden)]
+pub enum BlobUploads {
+    Table,
+    Id,
+    Workspace,
+    Hash,
+    Length,
+    UpdatedAt,
+}
+
+#[derive(Iden)]
+pub enum BlobUploadChunks {
+    Table,
+    Upload,
+    Offset,
+    Blob,
+    KeyVersion,
+}
+
+#[derive(Iden)]
+pub enum WorkspaceKv {
+    Table,
+    Workspace,
+    Name,
+    Value,
+    KeyVersion,
 }
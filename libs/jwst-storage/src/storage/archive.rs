@@ -0,0 +1,321 @@
+use super::*;
+use crate::utils::URL_SAFE_ENGINE;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, path::Path};
+use tokio::fs;
+use yrs::{updates::decoder::Decode, StateVector};
+
+const MANIFEST: &str = "manifest.json";
+const UPDATE: &str = "update.bin";
+const BLOBS: &str = "blobs";
+
+/// Describes an archive written by [JwstStorage::export_workspace_archive], stored
+/// as `manifest.json` next to the doc update and the blobs of the archive.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchiveManifest {
+    /// Hash of the content of the archive and of its base, checked on import.
+    pub id: String,
+    pub workspace: String,
+    /// Guid of the doc, `None` in archives made before it was recorded.
+    #[serde(default)]
+    pub guid: Option<String>,
+    /// Id of the archive this one is incremental to, `None` for a full archive.
+    pub base: Option<String>,
+    /// State vector of the doc when the archive was made, lib0 v1 encoded as base64.
+    pub state_vector: String,
+    /// Blobs of the workspace when the archive was made.
+    pub blobs: Vec<String>,
+    /// Blobs stored in the archive, in the order of the files in `blobs/`,
+    /// incremental archives only store the ones their base doesn't list.
+    pub included_blobs: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ArchiveManifest {
+    /// Read the manifest of an archive, `path` is the archive directory or its manifest.
+    pub async fn load(path: impl AsRef<Path>) -> JwstResult<Self> {
+        let path = path.as_ref();
+        let path = if path.is_dir() {
+            path.join(MANIFEST)
+        } else {
+            path.to_owned()
+        };
+        let manifest = fs::read(&path).await?;
+        Ok(serde_json::from_slice(&manifest)
+            .context(format!("invalid archive manifest {}", path.display()))?)
+    }
+
+    fn state_vector(&self) -> JwstResult<StateVector> {
+        let state_vector = URL_SAFE_ENGINE
+            .decode(&self.state_vector)
+            .context("invalid state vector encoding")?;
+        Ok(StateVector::decode_v1(&state_vector).context("invalid state vector")?)
+    }
+}
+
+/// What [JwstStorage::import_workspace_archives_with] restored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveImport {
+    /// Manifest of the last archive of the chain.
+    pub manifest: ArchiveManifest,
+    /// Guid of the archived doc mapped to the guid of the imported one, clients have
+    /// to update the references they cached. Empty if the identity was preserved.
+    pub guids: BTreeMap<String, String>,
+}
+
+// covers everything an archive restores, so a changed or reordered archive is detected
+fn archive_id(
+    base: Option<&str>,
+    guid: Option<&str>,
+    state_vector: &str,
+    update: &[u8],
+    blobs: &[(String, Vec<u8>)],
+) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(base.unwrap_or_default());
+    // left out if missing so older archives still match
+    if let Some(guid) = guid {
+        hasher.update(guid);
+    }
+    hasher.update(state_vector);
+    hasher.update(Sha256::digest(update));
+    for (hash, blob) in blobs {
+        hasher.update(hash);
+        hasher.update(Sha256::digest(blob));
+    }
+    URL_SAFE_ENGINE.encode(hasher.finalize())
+}
+
+impl JwstStorage {
+    /// Write the doc and the blobs of a workspace into the directory `dir`.
+    pub async fn export_workspace_archive(
+        &self,
+        workspace_id: &str,
+        dir: impl AsRef<Path>,
+    ) -> JwstResult<ArchiveManifest> {
+        self.export_archive(workspace_id, None, dir.as_ref()).await
+    }
+
+    /// Like [JwstStorage::export_workspace_archive], but only with the doc changes
+    /// since `base` was made and the blobs `base` doesn't list. Restoring it needs
+    /// the whole chain of archives it is based on, see [JwstStorage::import_workspace_archives].
+    pub async fn export_workspace_archive_incremental(
+        &self,
+        workspace_id: &str,
+        base: &ArchiveManifest,
+        dir: impl AsRef<Path>,
+    ) -> JwstResult<ArchiveManifest> {
+        if base.workspace != workspace_id {
+            return Err(anyhow::anyhow!(
+                "base archive {} is an archive of {}, not {workspace_id}",
+                base.id,
+                base.workspace
+            )
+            .into());
+        }
+        self.export_archive(workspace_id, Some(base), dir.as_ref())
+            .await
+    }
+
+    async fn export_archive(
+        &self,
+        workspace_id: &str,
+        base: Option<&ArchiveManifest>,
+        dir: &Path,
+    ) -> JwstResult<ArchiveManifest> {
+        let since = base
+            .map(|base| base.state_vector())
+            .transpose()?
+            .unwrap_or_default();
+        let (guid, update, state_vector) = {
+            let workspace = self.get_workspace(workspace_id).await?;
+            let doc = workspace.doc();
+            let trx = doc.transact();
+            (
+                workspace.guid(),
+                trx.encode_state_as_update_v1(&since),
+                URL_SAFE_ENGINE.encode(trx.state_vector().encode_v1()),
+            )
+        };
+
+        let blobs = self
+            .blobs
+            .hashes(workspace_id)
+            .await
+            .context(format!("Failed to list blobs of {workspace_id}"))?;
+        let mut included = vec![];
+        for hash in &blobs {
+            if !matches!(base, Some(base) if base.blobs.contains(hash)) {
+                let blob = self
+                    .blobs
+                    .get(workspace_id, hash)
+                    .await
+                    .context(format!("Failed to read blob {hash} of {workspace_id}"))?;
+                included.push((hash.clone(), blob.blob));
+            }
+        }
+
+        fs::create_dir_all(dir.join(BLOBS)).await?;
+        fs::write(dir.join(UPDATE), &update).await?;
+        for (i, (_, blob)) in included.iter().enumerate() {
+            fs::write(dir.join(BLOBS).join(i.to_string()), blob).await?;
+        }
+
+        let base = base.map(|base| base.id.clone());
+        let manifest = ArchiveManifest {
+            id: archive_id(
+                base.as_deref(),
+                Some(&guid),
+                &state_vector,
+                &update,
+                &included,
+            ),
+            workspace: workspace_id.to_owned(),
+            guid: Some(guid),
+            base,
+            state_vector,
+            blobs,
+            included_blobs: included.into_iter().map(|(hash, _)| hash).collect(),
+            created_at: Utc::now(),
+        };
+        fs::write(
+            dir.join(MANIFEST),
+            serde_json::to_vec_pretty(&manifest).context("failed to encode manifest")?,
+        )
+        .await?;
+
+        info!(
+            "export archive {} of {}: {} bytes of updates, {} blobs",
+            manifest.id,
+            workspace_id,
+            update.len(),
+            manifest.included_blobs.len()
+        );
+        Ok(manifest)
+    }
+
+    /// Restore a workspace from a full archive followed by the incremental archives
+    /// based on it, in order. The whole chain is checked before anything is restored,
+    /// returns the manifest of the last archive.
+    pub async fn import_workspace_archives<P>(&self, dirs: &[P]) -> JwstResult<ArchiveManifest>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(self
+            .import_workspace_archives_with(dirs, false)
+            .await?
+            .manifest)
+    }
+
+    /// Like [JwstStorage::import_workspace_archives], with `preserve_identity` the doc
+    /// keeps the guid it had when it was archived, so the references of clients stay
+    /// valid. It should only be used to move or restore a workspace, not to copy it.
+    /// Otherwise a workspace that doesn't exist yet gets a new guid.
+    pub async fn import_workspace_archives_with<P>(
+        &self,
+        dirs: &[P],
+        preserve_identity: bool,
+    ) -> JwstResult<ArchiveImport>
+    where
+        P: AsRef<Path>,
+    {
+        let mut chain: Vec<(ArchiveManifest, Vec<u8>, Vec<(String, Vec<u8>)>)> = vec![];
+        for dir in dirs {
+            let dir = dir.as_ref();
+            let manifest = ArchiveManifest::load(dir).await?;
+            let expected_base = chain.last().map(|(base, ..)| &base.id);
+            if manifest.base.as_ref() != expected_base {
+                return Err(anyhow::anyhow!(
+                    "archive {} is based on {:?}, expected {:?}",
+                    dir.display(),
+                    manifest.base,
+                    expected_base
+                )
+                .into());
+            }
+            if matches!(chain.first(), Some((first, ..)) if first.workspace != manifest.workspace) {
+                return Err(anyhow::anyhow!(
+                    "archive {} belongs to another workspace",
+                    dir.display()
+                )
+                .into());
+            }
+
+            let update = fs::read(dir.join(UPDATE)).await?;
+            let mut blobs = vec![];
+            for (i, hash) in manifest.included_blobs.iter().enumerate() {
+                blobs.push((
+                    hash.clone(),
+                    fs::read(dir.join(BLOBS).join(i.to_string())).await?,
+                ));
+            }
+            let id = archive_id(
+                manifest.base.as_deref(),
+                manifest.guid.as_deref(),
+                &manifest.state_vector,
+                &update,
+                &blobs,
+            );
+            if id != manifest.id {
+                return Err(anyhow::anyhow!(
+                    "archive {} doesn't match its manifest",
+                    dir.display()
+                )
+                .into());
+            }
+
+            chain.push((manifest, update, blobs));
+        }
+        let Some((last, ..)) = chain.last() else {
+            return Err(anyhow::anyhow!("no archive to import").into());
+        };
+        let last = last.clone();
+
+        let workspace_id = last.workspace.as_str();
+        check_unscoped(workspace_id)?;
+        if preserve_identity {
+            let Some(guid) = &last.guid else {
+                return Err(anyhow::anyhow!("archive {} has no doc guid", last.id).into());
+            };
+            self.docs.0.set_guid(workspace_id, guid).await?;
+        }
+        let workspace = self.create_workspace(workspace_id).await?;
+        let updates = chain
+            .iter()
+            .map(|(_, update, _)| update.as_slice())
+            .collect::<Vec<_>>();
+        workspace
+            .apply_updates(&updates)
+            .context(format!("invalid update in the archives of {workspace_id}"))?;
+        if !self.full_migrate(workspace_id.to_owned(), None, true).await {
+            return Err(anyhow::anyhow!("failed to store the doc of {workspace_id}").into());
+        }
+
+        for (hash, blob) in chain.iter().flat_map(|(_, _, blobs)| blobs) {
+            self.blobs
+                .insert(workspace_id, hash, blob)
+                .await
+                .context(format!("Failed to restore blob {hash} of {workspace_id}"))?;
+        }
+
+        let guids = last
+            .guid
+            .iter()
+            .filter(|guid| **guid != workspace.guid())
+            .map(|guid| (guid.clone(), workspace.guid()))
+            .collect();
+
+        info!(
+            "import {} archives of {}: {}",
+            chain.len(),
+            workspace_id,
+            last.id
+        );
+        Ok(ArchiveImport {
+            manifest: last,
+            guids,
+        })
+    }
+}
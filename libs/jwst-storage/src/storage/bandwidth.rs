@@ -0,0 +1,101 @@
+use super::{entities::prelude::*, *};
+use sea_orm::sea_query::{Expr, OnConflict};
+
+type BandwidthActiveModel = super::entities::bandwidth_usage::ActiveModel;
+type BandwidthColumn = <BandwidthUsage as EntityTrait>::Column;
+
+/// What traffic is accounted to, see [JwstStorage::record_bandwidth].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BandwidthScope {
+    Workspace(String),
+    User(String),
+}
+
+impl BandwidthScope {
+    fn key(&self) -> (&'static str, &str) {
+        match self {
+            Self::Workspace(id) => ("workspace", id),
+            Self::User(id) => ("user", id),
+        }
+    }
+}
+
+/// Bytes sent to and received from the clients.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Bandwidth {
+    pub sent: u64,
+    pub received: u64,
+}
+
+impl Bandwidth {
+    pub fn is_empty(&self) -> bool {
+        self.sent == 0 && self.received == 0
+    }
+}
+
+impl std::ops::Add for Bandwidth {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            sent: self.sent + other.sent,
+            received: self.received + other.received,
+        }
+    }
+}
+
+impl JwstStorage {
+    /// Add traffic to the stored totals of `scope`.
+    pub async fn record_bandwidth(
+        &self,
+        scope: &BandwidthScope,
+        bandwidth: Bandwidth,
+    ) -> JwstResult<()> {
+        if bandwidth.is_empty() {
+            return Ok(());
+        }
+        let (kind, id) = scope.key();
+        let (sent, received) = (bandwidth.sent as i64, bandwidth.received as i64);
+
+        let _lock = self.bucket.get_lock().await;
+        BandwidthUsage::insert(BandwidthActiveModel {
+            scope: Set(kind.into()),
+            id: Set(id.into()),
+            sent: Set(sent),
+            received: Set(received),
+        })
+        .on_conflict(
+            OnConflict::columns([BandwidthColumn::Scope, BandwidthColumn::Id])
+                .value(
+                    BandwidthColumn::Sent,
+                    Expr::col(BandwidthColumn::Sent).add(sent),
+                )
+                .value(
+                    BandwidthColumn::Received,
+                    Expr::col(BandwidthColumn::Received).add(received),
+                )
+                .to_owned(),
+        )
+        .exec(&self.pool)
+        .await
+        .context(format!("Failed to record bandwidth of {kind} {id}"))?;
+
+        Ok(())
+    }
+
+    /// Traffic stored for `scope`, nothing if none was recorded.
+    pub async fn bandwidth(&self, scope: &BandwidthScope) -> JwstResult<Bandwidth> {
+        let (kind, id) = scope.key();
+
+        let _lock = self.bucket.get_lock().await;
+        Ok(BandwidthUsage::find_by_id((kind.into(), id.into()))
+            .one(&self.pool)
+            .await
+            .context(format!("Failed to get bandwidth of {kind} {id}"))?
+            .map(|usage| Bandwidth {
+                sent: usage.sent as u64,
+                received: usage.received as u64,
+            })
+            .unwrap_or_default())
+    }
+}
@@ -1,4 +1,4 @@
-use super::{entities::prelude::*, utils::get_hash, *};
+use super::{encryption, entities::prelude::*, utils::get_hash, *};
 use bytes::Bytes;
 use jwst::{BlobMetadata, BlobStorage};
 use jwst_storage_migration::{Migrator, MigratorTrait};
@@ -12,32 +12,63 @@ type BlobColumn = <Blobs as EntityTrait>::Column;
 pub struct BlobAutoStorage {
     bucket: Arc<Bucket>,
     pool: DatabaseConnection,
+    encryption: Option<StorageEncryption>,
+    // accepts the keys of tenant workspaces, see [super::TenantStorage]
+    tenant_keys: bool,
 }
 
 impl BlobAutoStorage {
-    pub async fn init_with_pool(pool: DatabaseConnection, bucket: Arc<Bucket>) -> JwstResult<Self> {
+    pub async fn init_with_pool(
+        pool: DatabaseConnection,
+        bucket: Arc<Bucket>,
+        encryption: Option<StorageEncryption>,
+    ) -> JwstResult<Self> {
         Migrator::up(&pool, None)
             .await
             .context("failed to run migration")?;
-        Ok(Self { bucket, pool })
+        Ok(Self {
+            bucket,
+            pool,
+            encryption,
+            tenant_keys: false,
+        })
     }
 
     pub async fn init_pool(database: &str) -> JwstResult<Self> {
         let is_sqlite = is_sqlite(database);
         let pool = create_connection(database, is_sqlite).await?;
 
-        Self::init_with_pool(pool, get_bucket(is_sqlite)).await
+        Self::init_with_pool(pool, get_bucket(is_sqlite), None).await
+    }
+
+    pub(in crate::storage) fn tenant_view(&self) -> Self {
+        Self {
+            tenant_keys: true,
+            ..self.clone()
+        }
+    }
+
+    fn check(&self, table: &str) -> Result<(), DbErr> {
+        if self.tenant_keys {
+            return Ok(());
+        }
+        check_unscoped(table).map_err(|e| DbErr::Custom(e.to_string()))
     }
 
     pub async fn all(&self, table: &str) -> Result<Vec<BlobModel>, DbErr> {
+        self.check(table)?;
         let _lock = self.bucket.get_lock().await;
         Blobs::find()
             .filter(BlobColumn::Workspace.eq(table))
             .all(&self.pool)
-            .await
+            .await?
+            .into_iter()
+            .map(|model| open(self.encryption.as_ref(), model))
+            .collect()
     }
 
     pub async fn count(&self, table: &str) -> Result<u64, DbErr> {
+        self.check(table)?;
         let _lock = self.bucket.get_lock().await;
         Blobs::find()
             .filter(BlobColumn::Workspace.eq(table))
@@ -45,15 +76,126 @@ impl BlobAutoStorage {
             .await
     }
 
+    pub async fn hashes(&self, table: &str) -> Result<Vec<String>, DbErr> {
+        self.check(table)?;
+        let _lock = self.bucket.get_lock().await;
+        #[derive(FromQueryResult)]
+        struct Hash {
+            hash: String,
+        }
+
+        Ok(Blobs::find()
+            .select_only()
+            .column(BlobColumn::Hash)
+            .filter(BlobColumn::Workspace.eq(table))
+            .into_model::<Hash>()
+            .all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|h| h.hash)
+            .collect())
+    }
+
+    /// Number of blobs stored in the workspace and their total size.
+    pub async fn usage(&self, table: &str) -> Result<(u64, u64), DbErr> {
+        self.check(table)?;
+        let _lock = self.bucket.get_lock().await;
+        #[derive(FromQueryResult)]
+        struct Length {
+            length: i64,
+        }
+
+        let lengths = Blobs::find()
+            .select_only()
+            .column(BlobColumn::Length)
+            .filter(BlobColumn::Workspace.eq(table))
+            .into_model::<Length>()
+            .all(&self.pool)
+            .await?;
+        Ok((
+            lengths.len() as u64,
+            lengths.iter().map(|l| l.length as u64).sum(),
+        ))
+    }
+
     pub async fn exists(&self, table: &str, hash: &str) -> Result<bool, DbErr> {
+        self.check(table)?;
+        let _lock = self.bucket.get_lock().await;
+        Self::exists_in(&self.pool, table, hash).await
+    }
+
+    /// Hashes among `hashes` that are stored in the workspace.
+    pub(super) async fn stored_hashes(
+        &self,
+        table: &str,
+        hashes: &[String],
+    ) -> Result<Vec<String>, DbErr> {
+        self.check(table)?;
         let _lock = self.bucket.get_lock().await;
+        #[derive(FromQueryResult)]
+        struct Hash {
+            hash: String,
+        }
+
+        let mut stored = Vec::new();
+        // keep the query under the bind parameter limit of sqlite
+        for chunk in hashes.chunks(500) {
+            stored.extend(
+                Blobs::find()
+                    .select_only()
+                    .column(BlobColumn::Hash)
+                    .filter(BlobColumn::Workspace.eq(table))
+                    .filter(BlobColumn::Hash.is_in(chunk.iter().cloned()))
+                    .into_model::<Hash>()
+                    .all(&self.pool)
+                    .await?
+                    .into_iter()
+                    .map(|h| h.hash),
+            );
+        }
+        Ok(stored)
+    }
+
+    /// Hashes among `hashes` that are stored in the workspace with their size, in a single query.
+    pub(super) async fn stored_sizes(
+        &self,
+        table: &str,
+        hashes: &[String],
+    ) -> Result<Vec<(String, u64)>, DbErr> {
+        self.check(table)?;
+        let _lock = self.bucket.get_lock().await;
+        #[derive(FromQueryResult)]
+        struct Size {
+            hash: String,
+            length: i64,
+        }
+
+        Ok(Blobs::find()
+            .select_only()
+            .column(BlobColumn::Hash)
+            .column(BlobColumn::Length)
+            .filter(BlobColumn::Workspace.eq(table))
+            .filter(BlobColumn::Hash.is_in(hashes.iter().cloned()))
+            .into_model::<Size>()
+            .all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|s| (s.hash, s.length as u64))
+            .collect())
+    }
+
+    pub(super) async fn exists_in<C>(conn: &C, table: &str, hash: &str) -> Result<bool, DbErr>
+    where
+        C: ConnectionTrait,
+    {
         Blobs::find_by_id((table.into(), hash.into()))
-            .count(&self.pool)
+            .count(conn)
             .await
             .map(|c| c > 0)
     }
 
     pub async fn metadata(&self, table: &str, hash: &str) -> Result<BlobMetadata, DbErr> {
+        self.check(table)?;
         let _lock = self.bucket.get_lock().await;
         #[derive(FromQueryResult)]
         struct Metadata {
@@ -77,49 +219,134 @@ impl BlobAutoStorage {
     }
 
     pub async fn insert(&self, table: &str, hash: &str, blob: &[u8]) -> Result<(), DbErr> {
+        self.check(table)?;
         let _lock = self.bucket.get_lock().await;
-        if !self.exists(table, hash).await? {
+        Self::insert_in(&self.pool, self.encryption.as_ref(), table, hash, blob).await
+    }
+
+    pub(super) async fn insert_in<C>(
+        conn: &C,
+        encryption: Option<&StorageEncryption>,
+        table: &str,
+        hash: &str,
+        blob: &[u8],
+    ) -> Result<(), DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        if !Self::exists_in(conn, table, hash).await? {
+            let (key_version, sealed) =
+                encryption::seal(encryption, blob).map_err(|e| DbErr::Custom(e.to_string()))?;
             Blobs::insert(BlobActiveModel {
                 workspace: Set(table.into()),
                 hash: Set(hash.into()),
-                blob: Set(blob.into()),
+                blob: Set(sealed.into_owned()),
                 length: Set(blob.len().try_into().unwrap()),
                 timestamp: Set(Utc::now().into()),
+                key_version: Set(key_version),
             })
-            .exec(&self.pool)
+            .exec(conn)
             .await?;
         }
 
         Ok(())
     }
 
+    /// Store a blob from a stream like [BlobStorage::put_blob], returns its hash
+    /// and whether the same blob was already stored in the workspace.
+    pub async fn put_blob_dedup(
+        &self,
+        workspace: Option<String>,
+        stream: impl Stream<Item = Bytes> + Send,
+    ) -> JwstResult<(String, bool)> {
+        let workspace = workspace.unwrap_or("__default__".into());
+        self.check(&workspace).context("failed to store blob")?;
+
+        let (hash, blob) = get_hash(stream).await;
+
+        let _lock = self.bucket.get_lock().await;
+        let exists = Self::exists_in(&self.pool, &workspace, &hash)
+            .await
+            .context("failed to query blob")?;
+        if !exists {
+            Self::insert_in(
+                &self.pool,
+                self.encryption.as_ref(),
+                &workspace,
+                &hash,
+                &blob,
+            )
+            .await
+            .context("failed to insert blob")?;
+        }
+
+        Ok((hash, exists))
+    }
+
     pub async fn get(&self, table: &str, hash: &str) -> Result<BlobModel, DbErr> {
+        self.check(table)?;
         let _lock = self.bucket.get_lock().await;
+        Self::get_in(&self.pool, self.encryption.as_ref(), table, hash).await
+    }
+
+    pub(super) async fn get_in<C>(
+        conn: &C,
+        encryption: Option<&StorageEncryption>,
+        table: &str,
+        hash: &str,
+    ) -> Result<BlobModel, DbErr>
+    where
+        C: ConnectionTrait,
+    {
         Blobs::find_by_id((table.into(), hash.into()))
-            .one(&self.pool)
+            .one(conn)
             .await
             .and_then(|r| r.ok_or(DbErr::Query(RuntimeErr::Internal("blob not exists".into()))))
+            .and_then(|model| open(encryption, model))
     }
 
     pub async fn delete(&self, table: &str, hash: &str) -> Result<bool, DbErr> {
+        self.check(table)?;
         let _lock = self.bucket.get_lock().await;
+        Self::delete_in(&self.pool, table, hash).await
+    }
+
+    pub(super) async fn delete_in<C>(conn: &C, table: &str, hash: &str) -> Result<bool, DbErr>
+    where
+        C: ConnectionTrait,
+    {
         Blobs::delete_by_id((table.into(), hash.into()))
-            .exec(&self.pool)
+            .exec(conn)
             .await
             .map(|r| r.rows_affected == 1)
     }
 
     pub async fn drop(&self, table: &str) -> Result<(), DbErr> {
+        self.check(table)?;
         let _lock = self.bucket.get_lock().await;
+        Self::drop_in(&self.pool, table).await
+    }
+
+    pub(super) async fn drop_in<C>(conn: &C, table: &str) -> Result<(), DbErr>
+    where
+        C: ConnectionTrait,
+    {
         Blobs::delete_many()
             .filter(BlobColumn::Workspace.eq(table))
-            .exec(&self.pool)
+            .exec(conn)
             .await?;
 
         Ok(())
     }
 }
 
+// the stored blob in plain text
+fn open(encryption: Option<&StorageEncryption>, mut model: BlobModel) -> Result<BlobModel, DbErr> {
+    model.blob = encryption::open(encryption, model.key_version, model.blob)
+        .map_err(|e| DbErr::Custom(e.to_string()))?;
+    Ok(model)
+}
+
 #[async_trait]
 impl BlobStorage for BlobAutoStorage {
     type Read = ReaderStream<Cursor<Vec<u8>>>;
@@ -167,6 +394,13 @@ impl BlobStorage for BlobAutoStorage {
             Err(JwstError::WorkspaceNotFound(workspace_id))
         }
     }
+    async fn list_blobs(&self, workspace_id: Option<String>) -> JwstResult<Vec<String>> {
+        let workspace_id = workspace_id.unwrap_or("__default__".into());
+        Ok(self
+            .hashes(&workspace_id)
+            .await
+            .context(format!("Failed to list blobs of {workspace_id}"))?)
+    }
     async fn delete_workspace(&self, workspace_id: String) -> JwstResult<()> {
         if self.drop(&workspace_id).await.is_ok() {
             Ok(())
@@ -193,7 +427,8 @@ pub async fn blobs_storage_test(pool: &BlobAutoStorage) -> anyhow::Result<()> {
             hash: "test".into(),
             blob: vec![1, 2, 3, 4],
             length: 4,
-            timestamp: all.get(0).unwrap().timestamp
+            timestamp: all.get(0).unwrap().timestamp,
+            key_version: 0,
         }]
     );
     assert_eq!(pool.count("basic").await?, 1);
@@ -210,7 +445,8 @@ pub async fn blobs_storage_test(pool: &BlobAutoStorage) -> anyhow::Result<()> {
             hash: "test1".into(),
             blob: vec![1, 2, 3, 4],
             length: 4,
-            timestamp: all.get(0).unwrap().timestamp
+            timestamp: all.get(0).unwrap().timestamp,
+            key_version: 0,
         }]
     );
     assert_eq!(pool.count("basic").await?, 1);
@@ -222,5 +458,18 @@ pub async fn blobs_storage_test(pool: &BlobAutoStorage) -> anyhow::Result<()> {
 
     pool.drop("basic").await?;
 
+    // deduplicated stream upload
+    let upload = || futures::stream::iter(vec![Bytes::from_static(&[1, 2]), Bytes::from(vec![3])]);
+    let (hash, exists) = pool.put_blob_dedup(Some("basic".into()), upload()).await?;
+    assert!(!exists);
+    assert_eq!(pool.get("basic", &hash).await?.blob, vec![1, 2, 3]);
+    assert_eq!(
+        pool.put_blob_dedup(Some("basic".into()), upload()).await?,
+        (hash, true)
+    );
+    assert_eq!(pool.count("basic").await?, 1);
+
+    pool.drop("basic").await?;
+
     Ok(())
 }
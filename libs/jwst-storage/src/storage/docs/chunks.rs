@@ -0,0 +1,267 @@
+use super::{entities::prelude::*, utils::URL_SAFE_ENGINE, *};
+use base64::Engine;
+use sea_orm::sea_query::{Expr, OnConflict};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+
+/// Updates smaller than this are always stored inline.
+pub(super) const CHUNK_THRESHOLD: usize = 64 * 1024;
+
+const MIN_CHUNK_SIZE: usize = 2 * 1024;
+const MAX_CHUNK_SIZE: usize = 64 * 1024;
+// 13 bits gives an average chunk size of about 8KB, the high bits are used
+// so that a boundary depends on the last 64 bytes instead of the last 13
+const CHUNK_MASK: u64 = !0 << (64 - 13);
+
+type DocChunksActiveModel = super::entities::doc_chunks::ActiveModel;
+type DocChunksColumn = <DocChunks as EntityTrait>::Column;
+
+const fn gear_table() -> [u64; 256] {
+    // splitmix64, any fixed pseudo random table works
+    let mut table = [0u64; 256];
+    let mut seed = 0x9E37_79B9_7F4A_7C15u64;
+    let mut i = 0;
+    while i < 256 {
+        seed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+static GEAR: [u64; 256] = gear_table();
+
+fn next_boundary(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK_SIZE {
+        return data.len();
+    }
+
+    let max = data.len().min(MAX_CHUNK_SIZE);
+    let mut hash = 0u64;
+    for (i, byte) in data.iter().enumerate().take(max).skip(MIN_CHUNK_SIZE) {
+        hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+        if hash & CHUNK_MASK == 0 {
+            return i + 1;
+        }
+    }
+    max
+}
+
+/// Split data into content defined chunks (gear hash), so an insertion only
+/// changes the chunks around it and the rest can still be shared.
+pub(super) fn split_chunks(data: &[u8]) -> Vec<&[u8]> {
+    let mut chunks = vec![];
+    let mut rest = data;
+    while !rest.is_empty() {
+        let (chunk, remain) = rest.split_at(next_boundary(rest));
+        chunks.push(chunk);
+        rest = remain;
+    }
+    chunks
+}
+
+fn chunk_hash(chunk: &[u8]) -> String {
+    URL_SAFE_ENGINE.encode(Sha256::digest(chunk))
+}
+
+fn chunk_list(chunks: &str) -> impl Iterator<Item = &str> {
+    chunks.split(',').filter(|hash| !hash.is_empty())
+}
+
+async fn store_chunks<C>(conn: &C, blob: &[u8]) -> JwstResult<String>
+where
+    C: ConnectionTrait,
+{
+    let mut hashes = vec![];
+    for chunk in split_chunks(blob) {
+        let hash = chunk_hash(chunk);
+
+        // identical chunks can be stored concurrently, the upsert only keeps one of them
+        DocChunks::insert(DocChunksActiveModel {
+            hash: Set(hash.clone()),
+            blob: Set(chunk.into()),
+            length: Set(chunk.len() as i64),
+            refs: Set(1),
+        })
+        .on_conflict(
+            OnConflict::column(DocChunksColumn::Hash)
+                .value(
+                    DocChunksColumn::Refs,
+                    Expr::col(DocChunksColumn::Refs).add(1),
+                )
+                .to_owned(),
+        )
+        .exec(conn)
+        .await
+        .context("failed to store chunk")?;
+
+        hashes.push(hash);
+    }
+
+    Ok(hashes.join(","))
+}
+
+/// Returns the blob and the chunk list to store in a docs row,
+/// large updates are moved into the shared chunk table if `chunked-docs` is enabled.
+pub(super) async fn pack<C>(conn: &C, blob: &[u8]) -> JwstResult<(Vec<u8>, Option<String>)>
+where
+    C: ConnectionTrait,
+{
+    if cfg!(feature = "chunked-docs") && blob.len() >= CHUNK_THRESHOLD {
+        let chunks = store_chunks(conn, blob).await?;
+        trace!("pack {}bytes into chunks: {chunks}", blob.len());
+        Ok((vec![], Some(chunks)))
+    } else {
+        Ok((blob.into(), None))
+    }
+}
+
+/// Reassemble a blob from its chunk list.
+pub(super) async fn unpack<C>(conn: &C, chunks: &str) -> JwstResult<Vec<u8>>
+where
+    C: ConnectionTrait,
+{
+    let stored = DocChunks::find()
+        .filter(DocChunksColumn::Hash.is_in(chunk_list(chunks)))
+        .all(conn)
+        .await
+        .context("failed to load chunks")?
+        .into_iter()
+        .map(|chunk| (chunk.hash, chunk.blob))
+        .collect::<HashMap<_, _>>();
+
+    let mut blob = vec![];
+    for hash in chunk_list(chunks) {
+        let chunk = stored
+            .get(hash)
+            .ok_or_else(|| anyhow::anyhow!("chunk {hash} is missing"))?;
+        blob.extend_from_slice(chunk);
+    }
+    Ok(blob)
+}
+
+/// Total size of the chunks referenced by the chunk lists, a chunk counts once per reference.
+pub(super) async fn length<C>(conn: &C, lists: &[String]) -> JwstResult<u64>
+where
+    C: ConnectionTrait,
+{
+    #[derive(FromQueryResult)]
+    struct Length {
+        hash: String,
+        length: i64,
+    }
+
+    let hashes = lists
+        .iter()
+        .flat_map(|list| chunk_list(list))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    let mut lengths = HashMap::new();
+    // keep the query under the bind parameter limit of sqlite
+    for hashes in hashes.chunks(500) {
+        lengths.extend(
+            DocChunks::find()
+                .select_only()
+                .column(DocChunksColumn::Hash)
+                .column(DocChunksColumn::Length)
+                .filter(DocChunksColumn::Hash.is_in(hashes.iter().copied()))
+                .into_model::<Length>()
+                .all(conn)
+                .await
+                .context("failed to measure chunks")?
+                .into_iter()
+                .map(|chunk| (chunk.hash, chunk.length as u64)),
+        );
+    }
+
+    Ok(lists
+        .iter()
+        .flat_map(|list| chunk_list(list))
+        .filter_map(|hash| lengths.get(hash))
+        .sum())
+}
+
+/// Drop the references of a chunk list, chunks no longer referenced are deleted.
+pub(super) async fn release<C>(conn: &C, chunks: &str) -> JwstResult<()>
+where
+    C: ConnectionTrait,
+{
+    for hash in chunk_list(chunks) {
+        DocChunks::update_many()
+            .col_expr(
+                DocChunksColumn::Refs,
+                Expr::col(DocChunksColumn::Refs).sub(1),
+            )
+            .filter(DocChunksColumn::Hash.eq(hash))
+            .exec(conn)
+            .await
+            .context("failed to release chunk")?;
+    }
+
+    DocChunks::delete_many()
+        .filter(DocChunksColumn::Refs.lte(0))
+        .exec(conn)
+        .await
+        .context("failed to delete unused chunks")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(feature = "chunked-docs")]
+pub(super) async fn chunk_usage<C>(conn: &C) -> anyhow::Result<(usize, i64)>
+where
+    C: ConnectionTrait,
+{
+    let chunks = DocChunks::find().all(conn).await?;
+    Ok((chunks.len(), chunks.iter().map(|c| c.length).sum()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_bytes(len: usize) -> Vec<u8> {
+        (0..len).map(|_| rand::random::<u8>()).collect()
+    }
+
+    #[test]
+    fn split_chunks_roundtrip() {
+        assert!(split_chunks(&[]).is_empty());
+        assert_eq!(split_chunks(&[1, 2, 3]), vec![&[1u8, 2, 3][..]]);
+
+        let data = random_bytes(1024 * 1024);
+        let chunks = split_chunks(&data);
+
+        assert_eq!(chunks.concat(), data);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.len() <= MAX_CHUNK_SIZE));
+        assert!(chunks[..chunks.len() - 1]
+            .iter()
+            .all(|c| c.len() > MIN_CHUNK_SIZE));
+    }
+
+    #[test]
+    fn split_chunks_resync_after_insertion() {
+        let data = random_bytes(1024 * 1024);
+        let mut edited = data.clone();
+        edited.splice(300 * 1024..300 * 1024, random_bytes(100));
+
+        let origin = split_chunks(&data)
+            .into_iter()
+            .map(chunk_hash)
+            .collect::<Vec<_>>();
+        let changed = split_chunks(&edited)
+            .into_iter()
+            .map(chunk_hash)
+            .filter(|hash| !origin.contains(hash))
+            .count();
+
+        // only the chunks around the insertion differ
+        assert!(changed <= 3, "{changed} chunks changed");
+    }
+}
@@ -1,12 +1,30 @@
-use super::{entities::prelude::*, *};
+use super::{chunks, entities::prelude::*, guids, restore_points, *};
 use dashmap::mapref::entry::Entry;
-use jwst::{sync_encode_update, DocStorage, Workspace};
+use futures::stream::{self, BoxStream, StreamExt};
+use jwst::{
+    sync_encode_update, DocStorage, PropertyHistory, PropertyWrite, Workspace, WorkspaceBuilder,
+    WorkspacePlugins,
+};
 use jwst_storage_migration::{Migrator, MigratorTrait};
-use std::panic::{catch_unwind, AssertUnwindSafe};
-use yrs::{updates::decoder::Decode, Doc, Options, ReadTxn, StateVector, Transact, Update};
+use sea_orm::{sea_query::Expr, DbBackend, QueryOrder, Statement};
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::RwLock,
+};
+use tokio::sync::broadcast::{channel, error::RecvError, Receiver};
+use yrs::{
+    updates::{
+        decoder::Decode,
+        encoder::{Encode, Encoder, EncoderV1},
+    },
+    Doc, Options, ReadTxn, Snapshot, StateVector, Transact, Update,
+};
 
 const MAX_TRIM_UPDATE_LIMIT: u64 = 500;
 
+// postgres channel notified with the workspace id when one of its updates is stored
+const UPDATE_CHANNEL: &str = "jwst_doc_updates";
+
 fn migrate_update(updates: Vec<<Docs as EntityTrait>::Model>, doc: Doc) -> Doc {
     {
         let mut trx = doc.transact_mut();
@@ -33,6 +51,29 @@ fn migrate_update(updates: Vec<<Docs as EntityTrait>::Model>, doc: Doc) -> Doc {
     doc
 }
 
+// deleted items are only kept for restore points, which depend on them
+fn storage_doc(guid: Option<&str>) -> Doc {
+    let mut options = Options {
+        skip_gc: cfg!(feature = "restore-points"),
+        ..Default::default()
+    };
+    if let Some(guid) = guid {
+        options.guid = guid.into();
+    }
+    Doc::with_options(options)
+}
+
+/// A change of a block property made by a stored update, see [JwstStorage::property_history].
+///
+/// [JwstStorage::property_history]: crate::JwstStorage::property_history
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertyChange {
+    /// Id of the stored update, later updates have higher ones.
+    pub seq: i32,
+    pub timestamp: DateTimeWithTimeZone,
+    pub write: PropertyWrite,
+}
+
 type DocsModel = <Docs as EntityTrait>::Model;
 type DocsActiveModel = super::entities::docs::ActiveModel;
 type DocsColumn = <Docs as EntityTrait>::Column;
@@ -42,10 +83,35 @@ pub struct DocDBStorage {
     pub(super) pool: DatabaseConnection,
     workspaces: DashMap<String, Workspace>,
     remote: DashMap<String, Sender<Vec<u8>>>,
+    // raw stored updates, for update streams when the database can't notify them
+    updates: Arc<DashMap<String, Sender<Vec<u8>>>>,
+    // plugins of the workspaces loaded from now on
+    plugins: RwLock<WorkspacePlugins>,
+    encryption: Option<StorageEncryption>,
+}
+
+// an update stream of a workspace, the channel of the workspace is removed with its
+// last stream
+struct UpdateSubscription {
+    rx: Option<Receiver<Vec<u8>>>,
+    updates: Arc<DashMap<String, Sender<Vec<u8>>>>,
+    table: String,
+}
+
+impl Drop for UpdateSubscription {
+    fn drop(&mut self) {
+        drop(self.rx.take());
+        self.updates
+            .remove_if(&self.table, |_, tx| tx.receiver_count() == 0);
+    }
 }
 
 impl DocDBStorage {
-    pub async fn init_with_pool(pool: DatabaseConnection, bucket: Arc<Bucket>) -> JwstResult<Self> {
+    pub async fn init_with_pool(
+        pool: DatabaseConnection,
+        bucket: Arc<Bucket>,
+        encryption: Option<StorageEncryption>,
+    ) -> JwstResult<Self> {
         Migrator::up(&pool, None)
             .await
             .context("failed to run migration")?;
@@ -55,6 +121,9 @@ impl DocDBStorage {
             pool,
             workspaces: DashMap::new(),
             remote: DashMap::new(),
+            updates: Arc::default(),
+            plugins: Default::default(),
+            encryption,
         })
     }
 
@@ -62,28 +131,112 @@ impl DocDBStorage {
         let is_sqlite = is_sqlite(database);
         let pool = create_connection(database, is_sqlite).await?;
 
-        Self::init_with_pool(pool, get_bucket(is_sqlite)).await
+        Self::init_with_pool(pool, get_bucket(is_sqlite), None).await
     }
 
     pub fn remote(&self) -> &DashMap<String, Sender<Vec<u8>>> {
         &self.remote
     }
 
-    async fn all<C>(conn: &C, table: &str) -> JwstResult<Vec<DocsModel>>
+    pub fn set_plugins(&self, plugins: WorkspacePlugins) {
+        *self.plugins.write().unwrap() = plugins;
+    }
+
+    async fn all<C>(
+        conn: &C,
+        encryption: Option<&StorageEncryption>,
+        table: &str,
+    ) -> JwstResult<Vec<DocsModel>>
     where
         C: ConnectionTrait,
     {
         trace!("start scan all: {table}");
-        let models = Docs::find()
+        let mut models = Docs::find()
             .filter(DocsColumn::Workspace.eq(table))
             .all(conn)
             .await
             .context("failed to scan all updates")?;
+        for model in models.iter_mut() {
+            Self::load_blob(conn, encryption, model).await?;
+        }
         trace!("end scan all: {table}, {}", models.len());
         Ok(models)
     }
 
-    async fn count<C>(conn: &C, table: &str) -> JwstResult<u64>
+    /// Stored updates of the workspace as they were written, oldest first.
+    pub(in crate::storage) async fn updates<C>(
+        conn: &C,
+        encryption: Option<&StorageEncryption>,
+        table: &str,
+    ) -> JwstResult<Vec<Vec<u8>>>
+    where
+        C: ConnectionTrait,
+    {
+        let mut models = Self::all(conn, encryption, table).await?;
+        models.sort_by_key(|model| model.id);
+        Ok(models.into_iter().map(|model| model.blob).collect())
+    }
+
+    // updates stored after the one with `id`, oldest first
+    #[cfg(feature = "postgres")]
+    async fn since<C>(
+        conn: &C,
+        encryption: Option<&StorageEncryption>,
+        table: &str,
+        id: i32,
+    ) -> JwstResult<Vec<DocsModel>>
+    where
+        C: ConnectionTrait,
+    {
+        let mut models = Docs::find()
+            .filter(DocsColumn::Workspace.eq(table))
+            .filter(DocsColumn::Id.gt(id))
+            .order_by_asc(DocsColumn::Id)
+            .all(conn)
+            .await
+            .context("failed to scan new updates")?;
+        for model in models.iter_mut() {
+            Self::load_blob(conn, encryption, model).await?;
+        }
+        Ok(models)
+    }
+
+    // reassemble the chunks of the update and decrypt it
+    async fn load_blob<C>(
+        conn: &C,
+        encryption: Option<&StorageEncryption>,
+        model: &mut DocsModel,
+    ) -> JwstResult<()>
+    where
+        C: ConnectionTrait,
+    {
+        if let Some(list) = &model.chunks {
+            model.blob = chunks::unpack(conn, list).await?;
+        }
+        model.blob = encryption::open(
+            encryption,
+            model.key_version,
+            std::mem::take(&mut model.blob),
+        )
+        .context(format!("failed to read update {}", model.id))?;
+        Ok(())
+    }
+
+    /// Id of the newest stored update, 0 if there is none.
+    pub(in crate::storage) async fn last_id<C>(conn: &C, table: &str) -> JwstResult<i32>
+    where
+        C: ConnectionTrait,
+    {
+        Ok(Docs::find()
+            .filter(DocsColumn::Workspace.eq(table))
+            .order_by_desc(DocsColumn::Id)
+            .one(conn)
+            .await
+            .context("failed to find last update")?
+            .map_or(0, |model| model.id))
+    }
+
+    pub(in crate::storage) async fn count<C>(conn: &C, table: &str) -> JwstResult<u64>
     where
         C: ConnectionTrait,
     {
@@ -97,15 +250,90 @@ impl DocDBStorage {
         Ok(count)
     }
 
-    async fn insert<C>(conn: &C, table: &str, blob: &[u8]) -> JwstResult<()>
+    /// Number of stored updates, their stored size including their chunks and
+    /// when the oldest one was written.
+    pub(in crate::storage) async fn usage<C>(
+        conn: &C,
+        table: &str,
+    ) -> JwstResult<(u64, u64, Option<DateTimeWithTimeZone>)>
+    where
+        C: ConnectionTrait,
+    {
+        #[derive(FromQueryResult)]
+        struct InlineLength {
+            length: Option<i64>,
+        }
+        #[derive(FromQueryResult)]
+        struct ChunkList {
+            chunks: String,
+        }
+        #[derive(FromQueryResult)]
+        struct Oldest {
+            timestamp: DateTimeWithTimeZone,
+        }
+
+        // the sum is a decimal in mysql
+        let sum = match conn.get_database_backend() {
+            DbBackend::MySql => "CAST(SUM(LENGTH(blob)) AS SIGNED)",
+            _ => "SUM(LENGTH(blob))",
+        };
+        let count = Self::count(conn, table).await?;
+        let inline = Docs::find()
+            .select_only()
+            .column_as(Expr::cust(sum), "length")
+            .filter(DocsColumn::Workspace.eq(table))
+            .into_model::<InlineLength>()
+            .one(conn)
+            .await
+            .context("failed to measure updates")?
+            .and_then(|sum| sum.length)
+            .unwrap_or_default();
+        let lists = Docs::find()
+            .select_only()
+            .column(DocsColumn::Chunks)
+            .filter(DocsColumn::Workspace.eq(table))
+            .filter(DocsColumn::Chunks.is_not_null())
+            .into_model::<ChunkList>()
+            .all(conn)
+            .await
+            .context("failed to scan chunked updates")?
+            .into_iter()
+            .map(|list| list.chunks)
+            .collect::<Vec<_>>();
+        let chunked = chunks::length(conn, &lists).await?;
+        let oldest = Docs::find()
+            .select_only()
+            .column(DocsColumn::Timestamp)
+            .filter(DocsColumn::Workspace.eq(table))
+            .order_by_asc(DocsColumn::Id)
+            .into_model::<Oldest>()
+            .one(conn)
+            .await
+            .context("failed to find oldest update")?
+            .map(|oldest| oldest.timestamp);
+
+        Ok((count, inline as u64 + chunked, oldest))
+    }
+
+    async fn insert<C>(
+        conn: &C,
+        encryption: Option<&StorageEncryption>,
+        table: &str,
+        blob: &[u8],
+    ) -> JwstResult<()>
     where
         C: ConnectionTrait,
     {
         trace!("start insert: {table}");
+        // encrypted updates are random bytes, their chunks are never shared
+        let (key_version, blob) = encryption::seal(encryption, blob)?;
+        let (blob, chunks) = chunks::pack(conn, &blob).await?;
         Docs::insert(DocsActiveModel {
             workspace: Set(table.into()),
             timestamp: Set(Utc::now().into()),
-            blob: Set(blob.into()),
+            blob: Set(blob),
+            chunks: Set(chunks),
+            key_version: Set(key_version),
             ..Default::default()
         })
         .exec(conn)
@@ -115,11 +343,20 @@ impl DocDBStorage {
         Ok(())
     }
 
-    async fn replace_with<C>(conn: &C, table: &str, blob: Vec<u8>) -> JwstResult<()>
+    async fn replace_with<C>(
+        conn: &C,
+        encryption: Option<&StorageEncryption>,
+        table: &str,
+        blob: Vec<u8>,
+    ) -> JwstResult<()>
     where
         C: ConnectionTrait,
     {
         trace!("start replace: {table}");
+        let (key_version, blob) = encryption::seal(encryption, &blob)?;
+        // reference the new chunks before releasing the old ones so shared chunks are kept
+        let (blob, chunks) = chunks::pack(conn, &blob).await?;
+        Self::release_chunks(conn, table).await?;
         Docs::delete_many()
             .filter(DocsColumn::Workspace.eq(table))
             .exec(conn)
@@ -129,6 +366,8 @@ impl DocDBStorage {
             workspace: Set(table.into()),
             timestamp: Set(Utc::now().into()),
             blob: Set(blob),
+            chunks: Set(chunks),
+            key_version: Set(key_version),
             ..Default::default()
         })
         .exec(conn)
@@ -138,88 +377,369 @@ impl DocDBStorage {
         Ok(())
     }
 
-    async fn drop<C>(conn: &C, table: &str) -> JwstResult<()>
+    async fn release_chunks<C>(conn: &C, table: &str) -> JwstResult<()>
+    where
+        C: ConnectionTrait,
+    {
+        let chunked = Docs::find()
+            .filter(DocsColumn::Workspace.eq(table))
+            .filter(DocsColumn::Chunks.is_not_null())
+            .all(conn)
+            .await
+            .context("failed to scan chunked updates")?;
+        for model in chunked {
+            if let Some(list) = model.chunks {
+                chunks::release(conn, &list).await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(in crate::storage) async fn drop<C>(conn: &C, table: &str) -> JwstResult<()>
     where
         C: ConnectionTrait,
     {
         trace!("start drop: {table}");
+        Self::release_chunks(conn, table).await?;
         Docs::delete_many()
             .filter(DocsColumn::Workspace.eq(table))
             .exec(conn)
             .await
             .context("failed to delete updates")?;
+        restore_points::drop(conn, table).await?;
+        guids::drop(conn, table).await?;
+        kv::drop(conn, table).await?;
         trace!("end drop: {table}");
         Ok(())
     }
 
     async fn update<C>(&self, conn: &C, table: &str, blob: Vec<u8>) -> JwstResult<()>
+    where
+        C: ConnectionTrait,
+    {
+        Self::store_update(conn, self.encryption.as_ref(), table, &blob).await?;
+        self.broadcast_update(table, &blob);
+
+        Ok(())
+    }
+
+    /// Store an update, merge all updates of the workspace when there are too many of them.
+    pub(in crate::storage) async fn store_update<C>(
+        conn: &C,
+        encryption: Option<&StorageEncryption>,
+        table: &str,
+        blob: &[u8],
+    ) -> JwstResult<()>
     where
         C: ConnectionTrait,
     {
         trace!("start update: {table}");
         if Self::count(conn, table).await? > MAX_TRIM_UPDATE_LIMIT - 1 {
-            let data = Self::all(conn, table).await?;
+            let mut data = Self::all(conn, encryption, table).await?;
+            // the update that triggers the merge is part of the merged state
+            data.push(DocsModel {
+                id: 0,
+                workspace: table.into(),
+                timestamp: Utc::now().into(),
+                blob: blob.into(),
+                chunks: None,
+                key_version: PLAINTEXT_KEY_VERSION.into(),
+            });
 
-            let data = tokio::task::spawn_blocking(move || {
-                let doc = migrate_update(data, Doc::default());
+            let (data, snapshot) = tokio::task::spawn_blocking(move || {
+                let doc = migrate_update(data, storage_doc(None));
 
                 let trx = doc.transact();
-                trx.encode_state_as_update_v1(&StateVector::default())
+                (
+                    trx.encode_state_as_update_v1(&StateVector::default()),
+                    cfg!(feature = "restore-points").then(|| trx.snapshot().encode_v1()),
+                )
             })
             .await
             .context("failed to merge update")?;
 
-            Self::replace_with(conn, table, data).await?;
+            Self::replace_with(conn, encryption, table, data).await?;
+            if let Some(snapshot) = snapshot {
+                restore_points::record(conn, table, snapshot).await?;
+            }
         } else {
-            Self::insert(conn, table, &blob).await?;
+            Self::insert(conn, encryption, table, blob).await?;
+        }
+        // delivered to the listeners once the surrounding transaction commits
+        if conn.get_database_backend() == DbBackend::Postgres {
+            conn.execute(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                "SELECT pg_notify($1, $2)",
+                vec![UPDATE_CHANNEL.into(), table.into()],
+            ))
+            .await
+            .context("failed to notify update")?;
         }
         trace!("end update: {table}");
 
+        Ok(())
+    }
+
+    pub(in crate::storage) async fn restore_points<C>(
+        conn: &C,
+        table: &str,
+    ) -> JwstResult<Vec<restore_points::RestorePoint>>
+    where
+        C: ConnectionTrait,
+    {
+        restore_points::list(conn, table).await
+    }
+
+    /// The doc at a restore point, encoded as an update.
+    ///
+    /// Yjs updates can only add to a doc, so instead of reverse deltas a restore point
+    /// is a snapshot that is applied to the current doc, no stored update has to be kept.
+    pub(in crate::storage) async fn restore<C>(
+        conn: &C,
+        encryption: Option<&StorageEncryption>,
+        table: &str,
+        point: i32,
+    ) -> JwstResult<Option<Vec<u8>>>
+    where
+        C: ConnectionTrait,
+    {
+        let Some(snapshot) = restore_points::get(conn, table, point).await? else {
+            return Ok(None);
+        };
+        let snapshot = Snapshot::decode_v1(&snapshot).context("failed to decode restore point")?;
+        let data = Self::all(conn, encryption, table).await?;
+
+        tokio::task::spawn_blocking(move || -> JwstResult<Option<Vec<u8>>> {
+            let doc = migrate_update(data, storage_doc(None));
+
+            let mut encoder = EncoderV1::new();
+            doc.transact()
+                .encode_state_from_snapshot(&snapshot, &mut encoder)
+                .map_err(|e| anyhow::anyhow!("failed to restore doc: {e:?}"))?;
+            Ok(Some(encoder.to_vec()))
+        })
+        .await
+        .context("failed to spawn restore thread")?
+    }
+
+    /// Changes of a property of a block made by the stored updates, oldest first. The
+    /// updates are replayed once they are read, without holding the bucket lock.
+    pub(in crate::storage) async fn property_history(
+        &self,
+        table: &str,
+        block: &str,
+        key: &str,
+    ) -> JwstResult<Vec<PropertyChange>> {
+        let mut models = {
+            let _lock = self.bucket.get_lock().await;
+            Self::all(&self.pool, self.encryption.as_ref(), table).await?
+        };
+        models.sort_by_key(|model| model.id);
+        let mut history = PropertyHistory::new(block, key);
+
+        tokio::task::spawn_blocking(move || {
+            let mut changes = vec![];
+            for model in models {
+                let write = history
+                    .apply(&model.blob)
+                    .map_err(|e| anyhow::anyhow!("failed to decode update {}: {e:?}", model.id))?;
+                if let Some(write) = write {
+                    changes.push(PropertyChange {
+                        seq: model.id,
+                        timestamp: model.timestamp,
+                        write,
+                    });
+                }
+            }
+            Ok::<_, JwstError>(changes)
+        })
+        .await
+        .context("failed to spawn history thread")?
+    }
+
+    /// Replace the stored updates of the workspace with its full state. The state is encoded
+    /// under the storage lock, so no update is stored between encoding it and removing the
+    /// stored ones.
+    pub(in crate::storage) async fn flush(
+        &self,
+        table: &str,
+        workspace: &Workspace,
+    ) -> JwstResult<()> {
+        debug!("flush: get lock");
+        let _lock = self.bucket.get_lock().await;
+
+        let update = workspace.sync_migration();
+        Self::full_migrate(&self.pool, self.encryption.as_ref(), table, update)
+            .await
+            .context("Failed to store workspace")
+            .map_err(JwstError::StorageError)?;
+
+        Ok(())
+    }
+
+    /// Send a stored update to the clients subscribed to the workspace.
+    pub(in crate::storage) fn broadcast_update(&self, table: &str, blob: &[u8]) {
         debug!("update {}bytes to {}", blob.len(), table);
         if let Entry::Occupied(remote) = self.remote.entry(table.into()) {
             let broadcast = &remote.get();
-            if let Err(e) = broadcast.send(sync_encode_update(&blob)) {
+            if let Err(e) = broadcast.send(sync_encode_update(blob)) {
                 warn!("send update to pipeline failed: {:?}", e);
             }
         }
+        if let Entry::Occupied(updates) = self.updates.entry(table.into()) {
+            // only fails without subscribers
+            updates.get().send(blob.to_vec()).ok();
+        }
         trace!("end update broadcast: {table}");
+    }
+
+    /// Updates of a workspace as they are stored, see [JwstStorage::workspace_update_stream].
+    ///
+    /// [JwstStorage::workspace_update_stream]: crate::JwstStorage::workspace_update_stream
+    pub(in crate::storage) async fn update_stream(
+        &self,
+        table: &str,
+    ) -> JwstResult<BoxStream<'static, Vec<u8>>> {
+        #[cfg(feature = "postgres")]
+        if self.pool.get_database_backend() == DbBackend::Postgres {
+            return self.listen(table).await;
+        }
+
+        let rx = self
+            .updates
+            .entry(table.into())
+            .or_insert_with(|| channel(100).0)
+            .subscribe();
+        let subscription = UpdateSubscription {
+            rx: Some(rx),
+            updates: self.updates.clone(),
+            table: table.to_owned(),
+        };
+        Ok(stream::unfold(subscription, |mut subscription| async move {
+            let rx = subscription.rx.as_mut()?;
+            loop {
+                match rx.recv().await {
+                    Ok(update) => return Some((update, subscription)),
+                    Err(RecvError::Lagged(skipped)) => warn!(
+                        "update stream of {} skipped {skipped} updates",
+                        subscription.table
+                    ),
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+        .boxed())
+    }
 
+    // postgres notifies the updates stored by every process sharing the database
+    #[cfg(feature = "postgres")]
+    async fn listen(&self, table: &str) -> JwstResult<BoxStream<'static, Vec<u8>>> {
+        use sea_orm::sqlx::postgres::PgListener;
+        use std::collections::VecDeque;
+
+        let mut listener = PgListener::connect_with(self.pool.get_postgres_connection_pool())
+            .await
+            .context("failed to connect update listener")?;
+        listener
+            .listen(UPDATE_CHANNEL)
+            .await
+            .context("failed to listen for updates")?;
+        // updates stored before listening are not part of the stream
+        let last = Self::last_id(&self.pool, table).await?;
+
+        let pool = self.pool.clone();
+        let encryption = self.encryption.clone();
+        let table = table.to_owned();
+        Ok(stream::unfold(
+            (listener, last, VecDeque::new()),
+            move |(mut listener, mut last, mut pending)| {
+                let (pool, encryption, table) = (pool.clone(), encryption.clone(), table.clone());
+                async move {
+                    loop {
+                        if let Some(update) = pending.pop_front() {
+                            return Some((update, (listener, last, pending)));
+                        }
+                        match listener.recv().await {
+                            Ok(notification) if notification.payload() == table => {}
+                            Ok(_) => continue,
+                            Err(e) => {
+                                error!("update listener of {table} failed: {e}");
+                                return None;
+                            }
+                        }
+                        match Self::since(&pool, encryption.as_ref(), &table, last).await {
+                            Ok(models) => {
+                                for model in models {
+                                    last = last.max(model.id);
+                                    pending.push_back(model.blob);
+                                }
+                            }
+                            Err(e) => warn!("failed to read new updates of {table}: {e}"),
+                        }
+                    }
+                }
+            },
+        )
+        .boxed())
+    }
+
+    /// Load the doc of a workspace with `guid` from now on, e.g. to keep the identity
+    /// of an imported doc. Already loaded copies of the workspace keep their guid.
+    pub(in crate::storage) async fn set_guid(&self, table: &str, guid: &str) -> JwstResult<()> {
+        let _lock = self.bucket.get_lock().await;
+        guids::set(&self.pool, table, guid).await?;
+        self.remove_cache(table);
         Ok(())
     }
 
-    async fn full_migrate<C>(&self, conn: &C, table: &str, blob: Vec<u8>) -> JwstResult<()>
+    pub(in crate::storage) fn remove_cache(&self, table: &str) {
+        debug!("delete workspace cache: {table}");
+        self.workspaces.remove(table);
+    }
+
+    pub(in crate::storage) async fn full_migrate<C>(
+        conn: &C,
+        encryption: Option<&StorageEncryption>,
+        table: &str,
+        blob: Vec<u8>,
+    ) -> JwstResult<()>
     where
         C: ConnectionTrait,
     {
         info!("start full migrate: {table}");
         if Self::count(conn, table).await? > 0 {
             info!("full migrate1.1: {table}");
-            Self::replace_with(conn, table, blob).await?;
+            Self::replace_with(conn, encryption, table, blob).await?;
         } else {
             info!("full migrate1.2: {table}");
-            Self::insert(conn, table, &blob).await?;
+            Self::insert(conn, encryption, table, &blob).await?;
         }
         info!("end full migrate: {table}");
         Ok(())
     }
 
-    async fn create_doc<C>(conn: &C, workspace: &str) -> JwstResult<Doc>
+    async fn create_doc<C>(
+        conn: &C,
+        encryption: Option<&StorageEncryption>,
+        workspace: &str,
+    ) -> JwstResult<Doc>
     where
         C: ConnectionTrait,
     {
         trace!("start create doc: {workspace}");
-        let mut doc = Doc::with_options(Options {
-            skip_gc: true,
-            ..Default::default()
-        });
+        let guid = guids::get(conn, workspace).await?;
+        let mut doc = storage_doc(guid.as_deref());
+        if guid.is_none() {
+            guids::set(conn, workspace, &doc.guid()).await?;
+        }
 
-        let all_data = Self::all(conn, workspace).await?;
+        let all_data = Self::all(conn, encryption, workspace).await?;
 
         if all_data.is_empty() {
             let update = doc
                 .transact()
                 .encode_state_as_update_v1(&StateVector::default());
-            Self::insert(conn, workspace, &update).await?;
+            Self::insert(conn, encryption, workspace, &update).await?;
         } else {
             doc = migrate_update(all_data, doc);
         }
@@ -257,12 +777,15 @@ impl DocStorage for DocDBStorage {
                 info!("init workspace cache: {workspace_id}");
                 let pool = self.pool.clone();
                 let id = workspace_id.clone();
-                let doc = Self::create_doc(&pool, &id)
+                let doc = Self::create_doc(&pool, self.encryption.as_ref(), &id)
                     .await
                     .context("failed to create workspace")
                     .map_err(JwstError::StorageError)?;
 
-                let ws = Workspace::from_doc(doc, workspace_id);
+                let ws = WorkspaceBuilder::new(workspace_id)
+                    .doc(doc)
+                    .plugins(self.plugins.read().unwrap().clone())
+                    .build();
                 Ok(v.insert(ws).clone())
             }
         }
@@ -274,7 +797,7 @@ impl DocStorage for DocDBStorage {
 
         trace!("write_doc: {:?}", data);
 
-        self.full_migrate(&self.pool, &workspace_id, data)
+        Self::full_migrate(&self.pool, self.encryption.as_ref(), &workspace_id, data)
             .await
             .context("Failed to store workspace")
             .map_err(JwstError::StorageError)?;
@@ -299,8 +822,7 @@ impl DocStorage for DocDBStorage {
         debug!("delete workspace: get lock");
         let _lock = self.bucket.get_lock().await;
 
-        debug!("delete workspace cache: {workspace_id}");
-        self.workspaces.remove(&workspace_id);
+        self.remove_cache(&workspace_id);
         DocDBStorage::drop(&self.pool, &workspace_id)
             .await
             .context("Failed to delete workspace")
@@ -320,37 +842,41 @@ pub async fn docs_storage_test(pool: &DocDBStorage) -> anyhow::Result<()> {
     assert_eq!(DocDBStorage::count(conn, "basic").await?, 0);
 
     // first insert
-    DocDBStorage::insert(conn, "basic", &[1, 2, 3, 4]).await?;
-    DocDBStorage::insert(conn, "basic", &[2, 2, 3, 4]).await?;
+    DocDBStorage::insert(conn, None, "basic", &[1, 2, 3, 4]).await?;
+    DocDBStorage::insert(conn, None, "basic", &[2, 2, 3, 4]).await?;
     assert_eq!(DocDBStorage::count(conn, "basic").await?, 2);
 
     // second insert
-    DocDBStorage::replace_with(conn, "basic", vec![3, 2, 3, 4]).await?;
+    DocDBStorage::replace_with(conn, None, "basic", vec![3, 2, 3, 4]).await?;
 
-    let all = DocDBStorage::all(conn, "basic").await?;
+    let all = DocDBStorage::all(conn, None, "basic").await?;
     assert_eq!(
         all,
         vec![DocsModel {
             id: all.get(0).unwrap().id,
             workspace: "basic".into(),
             timestamp: all.get(0).unwrap().timestamp,
-            blob: vec![3, 2, 3, 4]
+            blob: vec![3, 2, 3, 4],
+            chunks: None,
+            key_version: 0,
         }]
     );
     assert_eq!(DocDBStorage::count(conn, "basic").await?, 1);
 
     DocDBStorage::drop(conn, "basic").await?;
 
-    DocDBStorage::insert(conn, "basic", &[1, 2, 3, 4]).await?;
+    DocDBStorage::insert(conn, None, "basic", &[1, 2, 3, 4]).await?;
 
-    let all = DocDBStorage::all(conn, "basic").await?;
+    let all = DocDBStorage::all(conn, None, "basic").await?;
     assert_eq!(
         all,
         vec![DocsModel {
             id: all.get(0).unwrap().id,
             workspace: "basic".into(),
             timestamp: all.get(0).unwrap().timestamp,
-            blob: vec![1, 2, 3, 4]
+            blob: vec![1, 2, 3, 4],
+            chunks: None,
+            key_version: 0,
         }]
     );
     assert_eq!(DocDBStorage::count(conn, "basic").await?, 1);
@@ -358,6 +884,142 @@ pub async fn docs_storage_test(pool: &DocDBStorage) -> anyhow::Result<()> {
     Ok(())
 }
 
+#[cfg(test)]
+pub async fn update_stream_test(pool: &DocDBStorage) -> anyhow::Result<()> {
+    let first = pool.update_stream("pruned").await?;
+    let second = pool.update_stream("pruned").await?;
+    assert_eq!(pool.updates.len(), 1);
+
+    // the channel of a workspace is kept until its last stream is dropped
+    drop(first);
+    assert_eq!(pool.updates.len(), 1);
+    drop(second);
+    assert!(pool.updates.is_empty());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(feature = "chunked-docs")]
+pub async fn chunked_docs_test(pool: &DocDBStorage) -> anyhow::Result<()> {
+    let conn = &pool.pool;
+
+    let seed: Vec<u8> = (0..1024 * 512).map(|_| rand::random::<u8>()).collect();
+    let mut forked = seed.clone();
+    forked.splice(1024 * 200..1024 * 200, [1, 2, 3, 4, 5, 6, 7, 8]);
+
+    // small updates are kept inline
+    DocDBStorage::insert(conn, None, "template", &[1, 2, 3, 4]).await?;
+    assert_eq!(
+        DocDBStorage::all(conn, None, "template").await?[0].chunks,
+        None
+    );
+
+    DocDBStorage::replace_with(conn, None, "template", seed.clone()).await?;
+    DocDBStorage::replace_with(conn, None, "clone", seed.clone()).await?;
+    DocDBStorage::replace_with(conn, None, "fork", forked.clone()).await?;
+
+    for (workspace, expected) in [("template", &seed), ("clone", &seed), ("fork", &forked)] {
+        let all = DocDBStorage::all(conn, None, workspace).await?;
+        assert_eq!(all.len(), 1);
+        assert!(all[0].chunks.is_some());
+        assert_eq!(&all[0].blob, expected);
+    }
+
+    // the clone is free and the fork only stores the chunks around the edit
+    let (_, stored) = chunks::chunk_usage(conn).await?;
+    let inline = seed.len() * 2 + forked.len();
+    assert!((stored as usize) < seed.len() + 3 * 64 * 1024);
+    assert!((stored as usize) < inline / 2);
+
+    // the usage of a workspace counts the chunks it references
+    let (updates, bytes, oldest) = DocDBStorage::usage(conn, "fork").await?;
+    assert_eq!((updates, bytes as usize), (1, forked.len()));
+    assert!(oldest.is_some());
+
+    // shared chunks outlive the workspace they were first written by
+    DocDBStorage::drop(conn, "template").await?;
+    assert_eq!(DocDBStorage::all(conn, None, "clone").await?[0].blob, seed);
+
+    DocDBStorage::drop(conn, "clone").await?;
+    DocDBStorage::drop(conn, "fork").await?;
+    assert_eq!(chunks::chunk_usage(conn).await?, (0, 0));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(feature = "restore-points")]
+pub async fn restore_points_test(pool: &DocDBStorage) -> anyhow::Result<()> {
+    use yrs::{GetString, Text};
+
+    let conn = &pool.pool;
+    let doc = storage_doc(None);
+    let text = doc.get_or_insert_text("content");
+
+    // every merge records a restore point, check the content at each of them
+    let mut raw_log = 0;
+    let mut expected = vec![];
+    for i in 1..=MAX_TRIM_UPDATE_LIMIT * 3 + 1 {
+        let update = {
+            let mut trx = doc.transact_mut();
+            if i % 10 == 0 {
+                text.remove_range(&mut trx, 0, 1);
+            } else {
+                text.push(&mut trx, "a");
+            }
+            trx.encode_update_v1()
+        };
+        raw_log += update.len();
+        DocDBStorage::store_update(conn, None, "restore", &update).await?;
+        if i > 1 && i % MAX_TRIM_UPDATE_LIMIT == 1 {
+            expected.push(text.get_string(&doc.transact()));
+        }
+    }
+
+    let points = DocDBStorage::restore_points(conn, "restore").await?;
+    assert_eq!(points.len(), 3);
+    for (point, expected) in points.iter().zip(expected) {
+        let update = DocDBStorage::restore(conn, None, "restore", point.id)
+            .await?
+            .unwrap();
+        let restored = Doc::new();
+        restored
+            .transact_mut()
+            .apply_update(Update::decode_v1(&update)?);
+        let content = restored.get_or_insert_text("content");
+        assert_eq!(content.get_string(&restored.transact()), expected);
+    }
+    assert_eq!(
+        DocDBStorage::restore(conn, None, "restore", -1).await?,
+        None
+    );
+
+    // restore points are much smaller than the update log they replace
+    let stored = DocRestorePoints::find()
+        .all(conn)
+        .await?
+        .iter()
+        .map(|point| point.snapshot.len())
+        .sum::<usize>();
+    assert!(stored < raw_log / 10);
+
+    // only the newest restore points are kept
+    for _ in 0..restore_points::MAX_RESTORE_POINTS {
+        restore_points::record(conn, "restore", vec![]).await?;
+    }
+    let kept = DocDBStorage::restore_points(conn, "restore").await?;
+    assert_eq!(kept.len(), restore_points::MAX_RESTORE_POINTS);
+    assert!(kept.iter().all(|point| point.id > points[2].id));
+
+    DocDBStorage::drop(conn, "restore").await?;
+    assert!(DocDBStorage::restore_points(conn, "restore")
+        .await?
+        .is_empty());
+
+    Ok(())
+}
+
 #[cfg(test)]
 #[cfg(feature = "postgres")]
 pub async fn full_migration_test(pool: &DocDBStorage) -> anyhow::Result<()> {
@@ -403,7 +1065,7 @@ pub async fn full_migration_test(pool: &DocDBStorage) -> anyhow::Result<()> {
     }
 
     assert_eq!(
-        DocDBStorage::all(&pool.pool, "full_migration_1")
+        DocDBStorage::all(&pool.pool, None, "full_migration_1")
             .await?
             .into_iter()
             .map(|d| d.blob)
@@ -412,7 +1074,7 @@ pub async fn full_migration_test(pool: &DocDBStorage) -> anyhow::Result<()> {
     );
 
     assert_eq!(
-        DocDBStorage::all(&pool.pool, "full_migration_2")
+        DocDBStorage::all(&pool.pool, None, "full_migration_2")
             .await?
             .into_iter()
             .map(|d| d.blob)
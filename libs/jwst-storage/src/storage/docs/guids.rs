@@ -0,0 +1,48 @@
+use super::{entities::prelude::*, *};
+use sea_orm::sea_query::OnConflict;
+
+type GuidsActiveModel = super::entities::workspace_guids::ActiveModel;
+type GuidsColumn = <WorkspaceGuids as EntityTrait>::Column;
+
+/// Guid of the doc of a workspace, `None` before the doc is loaded for the first time.
+pub(super) async fn get<C>(conn: &C, table: &str) -> JwstResult<Option<String>>
+where
+    C: ConnectionTrait,
+{
+    Ok(WorkspaceGuids::find_by_id(table.to_owned())
+        .one(conn)
+        .await
+        .context("failed to get doc guid")?
+        .map(|row| row.guid))
+}
+
+pub(super) async fn set<C>(conn: &C, table: &str, guid: &str) -> JwstResult<()>
+where
+    C: ConnectionTrait,
+{
+    WorkspaceGuids::insert(GuidsActiveModel {
+        workspace: Set(table.into()),
+        guid: Set(guid.into()),
+    })
+    .on_conflict(
+        OnConflict::column(GuidsColumn::Workspace)
+            .update_column(GuidsColumn::Guid)
+            .to_owned(),
+    )
+    .exec(conn)
+    .await
+    .context("failed to set doc guid")?;
+    Ok(())
+}
+
+pub(super) async fn drop<C>(conn: &C, table: &str) -> JwstResult<()>
+where
+    C: ConnectionTrait,
+{
+    WorkspaceGuids::delete_many()
+        .filter(GuidsColumn::Workspace.eq(table))
+        .exec(conn)
+        .await
+        .context("failed to delete doc guid")?;
+    Ok(())
+}
@@ -1,38 +1,99 @@
+mod chunks;
 mod database;
+mod guids;
+mod restore_points;
 
 use super::*;
 use dashmap::DashMap;
-use database::DocDBStorage;
+pub(super) use database::DocDBStorage;
+pub use database::PropertyChange;
+use jwst::WorkspacePlugins;
+pub use restore_points::RestorePoint;
 use tokio::sync::broadcast::Sender;
 
+#[cfg(test)]
+#[cfg(feature = "chunked-docs")]
+pub(super) use database::chunked_docs_test;
 #[cfg(test)]
 pub(super) use database::docs_storage_test;
 #[cfg(test)]
 #[cfg(feature = "postgres")]
 pub(super) use database::full_migration_test;
+#[cfg(test)]
+#[cfg(feature = "restore-points")]
+pub(super) use database::restore_points_test;
+#[cfg(test)]
+pub(super) use database::update_stream_test;
 
 #[derive(Clone)]
-pub struct DocAutoStorage(pub(super) Arc<DocDBStorage>);
+pub struct DocAutoStorage(
+    pub(super) Arc<DocDBStorage>,
+    // accepts the storage keys of tenants, only set for the views of [super::TenantStorage]
+    bool,
+);
 
 impl DocAutoStorage {
-    pub async fn init_with_pool(pool: DatabaseConnection, bucket: Arc<Bucket>) -> JwstResult<Self> {
-        Ok(Self(Arc::new(
-            DocDBStorage::init_with_pool(pool, bucket).await?,
-        )))
+    pub async fn init_with_pool(
+        pool: DatabaseConnection,
+        bucket: Arc<Bucket>,
+        encryption: Option<StorageEncryption>,
+    ) -> JwstResult<Self> {
+        Ok(Self(
+            Arc::new(DocDBStorage::init_with_pool(pool, bucket, encryption).await?),
+            false,
+        ))
     }
 
     pub async fn init_pool(database: &str) -> JwstResult<Self> {
-        Ok(Self(Arc::new(DocDBStorage::init_pool(database).await?)))
+        Ok(Self(
+            Arc::new(DocDBStorage::init_pool(database).await?),
+            false,
+        ))
     }
 
     pub fn remote(&self) -> &DashMap<String, Sender<Vec<u8>>> {
         self.0.remote()
     }
+
+    /// Plugins of the workspaces loaded from now on, already loaded ones keep theirs.
+    pub fn set_workspace_plugins(&self, plugins: WorkspacePlugins) {
+        self.0.set_plugins(plugins)
+    }
+
+    /// Replace the stored updates of the workspace with its full state, see
+    /// [DocDBStorage::flush].
+    pub(in crate::storage) async fn flush(
+        &self,
+        id: String,
+        workspace: Workspace,
+    ) -> JwstResult<()> {
+        self.check(&id)?;
+        let db = self.0.clone();
+        tokio::task::spawn_blocking(move || {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(async move { db.flush(&id, &workspace).await })
+        })
+        .await
+        .context("failed to spawn query thread")?
+    }
+
+    pub(in crate::storage) fn tenant_view(&self) -> Self {
+        Self(self.0.clone(), true)
+    }
+
+    fn check(&self, id: &str) -> JwstResult<()> {
+        if self.1 {
+            Ok(())
+        } else {
+            check_unscoped(id)
+        }
+    }
 }
 
 #[async_trait]
 impl DocStorage for DocAutoStorage {
     async fn exists(&self, id: String) -> JwstResult<bool> {
+        self.check(&id)?;
         let db = self.0.clone();
         tokio::task::spawn_blocking(move || {
             let rt = tokio::runtime::Runtime::new().unwrap();
@@ -43,6 +104,7 @@ impl DocStorage for DocAutoStorage {
     }
 
     async fn get(&self, id: String) -> JwstResult<Workspace> {
+        self.check(&id)?;
         let db = self.0.clone();
         tokio::task::spawn_blocking(move || {
             let rt = tokio::runtime::Runtime::new().unwrap();
@@ -53,6 +115,7 @@ impl DocStorage for DocAutoStorage {
     }
 
     async fn write_full_update(&self, id: String, data: Vec<u8>) -> JwstResult<()> {
+        self.check(&id)?;
         let db = self.0.clone();
         tokio::task::spawn_blocking(move || {
             let rt = tokio::runtime::Runtime::new().unwrap();
@@ -63,6 +126,7 @@ impl DocStorage for DocAutoStorage {
     }
 
     async fn write_update(&self, id: String, data: &[u8]) -> JwstResult<()> {
+        self.check(&id)?;
         let db = self.0.clone();
         let data = data.to_vec();
         tokio::task::spawn_blocking(move || {
@@ -74,6 +138,7 @@ impl DocStorage for DocAutoStorage {
     }
 
     async fn delete(&self, id: String) -> JwstResult<()> {
+        self.check(&id)?;
         let db = self.0.clone();
         tokio::task::spawn_blocking(move || {
             let rt = tokio::runtime::Runtime::new().unwrap();
@@ -0,0 +1,98 @@
+use super::{entities::prelude::*, *};
+use sea_orm::QueryOrder;
+
+/// Restore points kept for each workspace, the oldest are pruned when a new one is recorded.
+pub(super) const MAX_RESTORE_POINTS: usize = 10;
+
+type RestorePointsActiveModel = super::entities::doc_restore_points::ActiveModel;
+type RestorePointsColumn = <DocRestorePoints as EntityTrait>::Column;
+
+/// A stored state of a workspace that it can be restored to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestorePoint {
+    pub id: i32,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Store the Yjs snapshot of a merged doc and prune the oldest restore points.
+///
+/// A snapshot is only the state vector and delete set of the doc, the content
+/// is read from the merged doc, which keeps deleted items as gc is disabled.
+pub(super) async fn record<C>(conn: &C, table: &str, snapshot: Vec<u8>) -> JwstResult<()>
+where
+    C: ConnectionTrait,
+{
+    DocRestorePoints::insert(RestorePointsActiveModel {
+        workspace: Set(table.into()),
+        timestamp: Set(Utc::now().into()),
+        snapshot: Set(snapshot),
+        ..Default::default()
+    })
+    .exec(conn)
+    .await
+    .context("failed to insert restore point")?;
+
+    let expired = DocRestorePoints::find()
+        .filter(RestorePointsColumn::Workspace.eq(table))
+        .order_by_desc(RestorePointsColumn::Id)
+        .all(conn)
+        .await
+        .context("failed to scan restore points")?
+        .into_iter()
+        .skip(MAX_RESTORE_POINTS)
+        .map(|point| point.id)
+        .collect::<Vec<_>>();
+    if !expired.is_empty() {
+        trace!("prune {} restore points: {table}", expired.len());
+        DocRestorePoints::delete_many()
+            .filter(RestorePointsColumn::Id.is_in(expired))
+            .exec(conn)
+            .await
+            .context("failed to prune restore points")?;
+    }
+
+    Ok(())
+}
+
+/// Restore points of a workspace, oldest first.
+pub(super) async fn list<C>(conn: &C, table: &str) -> JwstResult<Vec<RestorePoint>>
+where
+    C: ConnectionTrait,
+{
+    Ok(DocRestorePoints::find()
+        .filter(RestorePointsColumn::Workspace.eq(table))
+        .order_by_asc(RestorePointsColumn::Id)
+        .all(conn)
+        .await
+        .context("failed to list restore points")?
+        .into_iter()
+        .map(|point| RestorePoint {
+            id: point.id,
+            timestamp: point.timestamp.with_timezone(&Utc),
+        })
+        .collect())
+}
+
+pub(super) async fn get<C>(conn: &C, table: &str, id: i32) -> JwstResult<Option<Vec<u8>>>
+where
+    C: ConnectionTrait,
+{
+    Ok(DocRestorePoints::find_by_id(id)
+        .filter(RestorePointsColumn::Workspace.eq(table))
+        .one(conn)
+        .await
+        .context("failed to get restore point")?
+        .map(|point| point.snapshot))
+}
+
+pub(super) async fn drop<C>(conn: &C, table: &str) -> JwstResult<()>
+where
+    C: ConnectionTrait,
+{
+    DocRestorePoints::delete_many()
+        .filter(RestorePointsColumn::Workspace.eq(table))
+        .exec(conn)
+        .await
+        .context("failed to delete restore points")?;
+    Ok(())
+}
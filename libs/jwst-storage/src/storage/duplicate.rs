@@ -0,0 +1,225 @@
+use super::*;
+use jwst::plugins::{PluginImpl, PluginRegister};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex as SyncMutex, Weak,
+};
+use tokio::{runtime::Handle, sync::Notify, task::JoinHandle, time::sleep};
+use yrs::{merge_updates_v1, ReadTxn, StateVector};
+
+// backoff between the attempts to write to an unavailable secondary storage
+const RETRY_MIN_DELAY: Duration = Duration::from_millis(100);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Default)]
+struct DuplicateQueue {
+    // the updates not written yet merged into one, so the secondary lags by one update at most
+    pending: SyncMutex<Option<Vec<u8>>>,
+    // the update being written, it's merged back if the write fails
+    writing: AtomicBool,
+    // a failed update couldn't be merged back, the next update is replaced by the whole doc
+    resync: AtomicBool,
+    // the plugins were dropped, the task exits once the queue is drained
+    closed: AtomicBool,
+    notify: Notify,
+}
+
+impl DuplicateQueue {
+    // `full_state` encodes the whole doc, it's queued in place of what can't be merged
+    fn push(&self, workspace_id: &str, update: Vec<u8>, full_state: impl FnOnce() -> Vec<u8>) {
+        let mut pending = self.pending.lock().unwrap();
+        let update = match pending.take() {
+            _ if self.resync.swap(false, Ordering::SeqCst) => full_state(),
+            Some(queued) => merge_updates_v1(&[&queued, &update]).unwrap_or_else(|e| {
+                error!("failed to merge duplicated updates of {workspace_id}, resync: {e}");
+                full_state()
+            }),
+            None => update,
+        };
+        *pending = Some(update);
+        drop(pending);
+        self.notify.notify_one();
+    }
+
+    // the flag is changed under the lock, so the lag never misses the update being written
+    fn pop(&self) -> Option<Vec<u8>> {
+        let mut pending = self.pending.lock().unwrap();
+        let update = pending.take();
+        self.writing.store(update.is_some(), Ordering::SeqCst);
+        update
+    }
+
+    fn done(&self, workspace_id: &str, failed: Option<Vec<u8>>) {
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(failed) = failed {
+            let update = match pending.take() {
+                Some(queued) => merge_updates_v1(&[&failed, &queued]).unwrap_or_else(|e| {
+                    error!("failed to merge duplicated updates of {workspace_id}, resync: {e}");
+                    self.resync.store(true, Ordering::SeqCst);
+                    queued
+                }),
+                None => failed,
+            };
+            *pending = Some(update);
+        }
+        self.writing.store(false, Ordering::SeqCst);
+    }
+
+    fn is_behind(&self) -> bool {
+        let pending = self.pending.lock().unwrap();
+        pending.is_some() || self.writing.load(Ordering::SeqCst)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+}
+
+async fn duplicate_updates(
+    workspace_id: String,
+    secondary: Arc<JwstStorage>,
+    queue: Arc<DuplicateQueue>,
+) {
+    let mut delay = RETRY_MIN_DELAY;
+    loop {
+        let Some(update) = queue.pop() else {
+            if queue.is_closed() {
+                break;
+            }
+            queue.notify.notified().await;
+            continue;
+        };
+
+        match secondary
+            .docs()
+            .write_update(workspace_id.clone(), &update)
+            .await
+        {
+            Ok(()) => {
+                queue.done(&workspace_id, None);
+                delay = RETRY_MIN_DELAY;
+            }
+            Err(e) if queue.is_closed() => {
+                error!("failed to duplicate updates of {workspace_id}, giving up: {e}");
+                break;
+            }
+            Err(e) => {
+                warn!("failed to duplicate update of {workspace_id}, retry in {delay:?}: {e}");
+                queue.done(&workspace_id, Some(update));
+                sleep(delay).await;
+                delay = (delay * 2).min(RETRY_MAX_DELAY);
+            }
+        }
+    }
+}
+
+// the background task duplicating a workspace, shared by the plugins set up for it
+struct DuplicateTask {
+    queue: Arc<DuplicateQueue>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for DuplicateTask {
+    // the queued updates are still written once the workspace is gone
+    fn drop(&mut self) {
+        self.queue.closed.store(true, Ordering::SeqCst);
+        self.queue.notify.notify_one();
+    }
+}
+
+/// Mirrors every update of a workspace to a secondary storage as it happens, so the
+/// secondary can take over if the primary storage is lost.
+///
+/// Updates are written by a background task in the order they were made. Failed writes
+/// are retried with a backoff, meanwhile the updates made are merged into one, so the
+/// secondary storage is never more than one update behind. The doc as it is when the
+/// plugin is set up is written first, so the secondary storage doesn't miss what was
+/// written before.
+///
+/// Clones of a workspace share its plugin. A workspace that is loaded again while the
+/// previous instance is still around shares its background task, as do the clones of
+/// the register, so the updates of a workspace are written by one task at a time.
+#[derive(Clone)]
+pub struct DuplicatePluginRegister {
+    secondary: Arc<JwstStorage>,
+    runtime: Handle,
+    tasks: Arc<SyncMutex<HashMap<String, Weak<DuplicateTask>>>>,
+}
+
+impl DuplicatePluginRegister {
+    /// The background tasks run on the current runtime, workspaces are loaded on
+    /// short-lived ones which would stop them.
+    ///
+    /// # Panics
+    ///
+    /// Outside of a Tokio runtime.
+    pub fn new(secondary: Arc<JwstStorage>) -> Self {
+        Self {
+            secondary,
+            runtime: Handle::current(),
+            tasks: Default::default(),
+        }
+    }
+
+    // the task of the workspace, started if it has none
+    fn task(&self, workspace_id: &str) -> Arc<DuplicateTask> {
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|_, task| task.strong_count() > 0);
+        if let Some(task) = tasks.get(workspace_id).and_then(Weak::upgrade) {
+            return task;
+        }
+
+        let queue = Arc::new(DuplicateQueue::default());
+        let task = Arc::new(DuplicateTask {
+            task: self.runtime.spawn(duplicate_updates(
+                workspace_id.to_owned(),
+                self.secondary.clone(),
+                queue.clone(),
+            )),
+            queue,
+        });
+        tasks.insert(workspace_id.to_owned(), Arc::downgrade(&task));
+        task
+    }
+}
+
+impl PluginRegister for DuplicatePluginRegister {
+    type Plugin = DuplicatePlugin;
+
+    fn setup(self, ws: &mut Workspace) -> Result<DuplicatePlugin, Box<dyn std::error::Error>> {
+        let workspace_id = ws.id();
+        let task = self.task(&workspace_id);
+        task.queue
+            .push(&workspace_id, ws.sync_migration(), || ws.sync_migration());
+
+        let sub = ws
+            .observe({
+                let queue = task.queue.clone();
+                move |trx, e| {
+                    queue.push(&workspace_id, e.update.clone(), || {
+                        trx.encode_state_as_update_v1(&StateVector::default())
+                    })
+                }
+            })
+            .ok_or("failed to observe the workspace updates")?;
+
+        Ok(DuplicatePlugin { task, _sub: sub })
+    }
+}
+
+/// Plugin set up by [DuplicatePluginRegister].
+pub struct DuplicatePlugin {
+    task: Arc<DuplicateTask>,
+    _sub: yrs::UpdateSubscription,
+}
+
+impl DuplicatePlugin {
+    /// Updates not written to the secondary storage yet, they are merged into one so this
+    /// is `Some(1)` while the secondary storage is behind and `Some(0)` once it caught up.
+    /// `None` if the updates are not duplicated anymore, the background task stopped.
+    pub fn lag(&self) -> Option<usize> {
+        (!self.task.task.is_finished()).then(|| self.task.queue.is_behind() as usize)
+    }
+}
+
+impl PluginImpl for DuplicatePlugin {}
@@ -0,0 +1,173 @@
+use super::*;
+use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit, Nonce};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rand::{thread_rng, Rng};
+use std::borrow::Cow;
+
+const NONCE_SIZE: usize = 12;
+
+/// Key version of the rows stored in plain text, e.g. the ones written before
+/// encryption was enabled.
+pub const PLAINTEXT_KEY_VERSION: u8 = 0;
+
+/// AES-256-GCM keys the doc updates and blobs are encrypted with, see [StorageConfig::encryption].
+///
+/// Each row stores the version of the key it was encrypted with next to its own nonce, so
+/// a key can be rotated: new rows use the current key, the retired keys are only kept to
+/// read the rows written with them.
+#[derive(Clone)]
+pub struct StorageEncryption {
+    current: u8,
+    keys: HashMap<u8, Aes256Gcm>,
+}
+
+impl StorageEncryption {
+    /// Encrypt the rows written from now on with a 32 bytes `key`, e.g. fetched from a KMS.
+    pub fn new(version: u8, key: &[u8]) -> JwstResult<Self> {
+        Ok(Self {
+            current: version,
+            keys: HashMap::from([(version, cipher(version, key)?)]),
+        })
+    }
+
+    /// Keep a retired key to read the rows that were encrypted with it.
+    pub fn with_retired_key(mut self, version: u8, key: &[u8]) -> JwstResult<Self> {
+        if self.keys.contains_key(&version) {
+            return Err(anyhow::anyhow!("storage key {version} is set twice").into());
+        }
+        self.keys.insert(version, cipher(version, key)?);
+        Ok(self)
+    }
+
+    /// Parse a comma separated list of `version:key` with base64 encoded keys,
+    /// the first one is the current key and the others are retired keys.
+    pub fn parse(keys: &str) -> JwstResult<Self> {
+        let mut encryption: Option<Self> = None;
+        for entry in keys
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let (version, key) = entry
+                .split_once(':')
+                .context("storage key must be `version:key`")?;
+            let version = version
+                .parse()
+                .context(format!("invalid storage key version {version}"))?;
+            let key = STANDARD
+                .decode(key)
+                .context(format!("invalid encoding of storage key {version}"))?;
+            encryption = Some(match encryption {
+                Some(encryption) => encryption.with_retired_key(version, &key)?,
+                None => Self::new(version, &key)?,
+            });
+        }
+        match encryption {
+            Some(encryption) => Ok(encryption),
+            None => Err(anyhow::anyhow!("no storage key found").into()),
+        }
+    }
+
+    fn encrypt(&self, data: &[u8]) -> JwstResult<Vec<u8>> {
+        let nonce: [u8; NONCE_SIZE] = thread_rng().gen();
+        let mut encrypted = self.keys[&self.current]
+            .encrypt(Nonce::from_slice(&nonce), data)
+            .map_err(|_| anyhow::anyhow!("failed to encrypt row"))?;
+        encrypted.extend(nonce);
+        Ok(encrypted)
+    }
+}
+
+fn cipher(version: u8, key: &[u8]) -> JwstResult<Aes256Gcm> {
+    if version == PLAINTEXT_KEY_VERSION {
+        return Err(anyhow::anyhow!(
+            "storage key version {PLAINTEXT_KEY_VERSION} means plain text"
+        )
+        .into());
+    }
+    Ok(Aes256Gcm::new_from_slice(key).map_err(|_| {
+        anyhow::anyhow!("storage key {version} must be 32 bytes, got {}", key.len())
+    })?)
+}
+
+/// Encrypt a row with the current key, returns the key version to store with the row.
+pub(super) fn seal<'a>(
+    encryption: Option<&StorageEncryption>,
+    data: &'a [u8],
+) -> JwstResult<(i16, Cow<'a, [u8]>)> {
+    match encryption {
+        Some(encryption) => Ok((
+            encryption.current.into(),
+            Cow::Owned(encryption.encrypt(data)?),
+        )),
+        None => Ok((PLAINTEXT_KEY_VERSION.into(), Cow::Borrowed(data))),
+    }
+}
+
+/// Decrypt a row stored with the key `version`.
+pub(super) fn open(
+    encryption: Option<&StorageEncryption>,
+    version: i16,
+    data: Vec<u8>,
+) -> JwstResult<Vec<u8>> {
+    if version == PLAINTEXT_KEY_VERSION.into() {
+        return Ok(data);
+    }
+    let cipher = u8::try_from(version)
+        .ok()
+        .and_then(|version| encryption?.keys.get(&version))
+        .context(format!(
+            "row is encrypted with unknown storage key {version}"
+        ))?;
+    if data.len() < NONCE_SIZE {
+        return Err(anyhow::anyhow!("encrypted row is too short").into());
+    }
+    let (content, nonce) = data.split_at(data.len() - NONCE_SIZE);
+    Ok(cipher
+        .decrypt(Nonce::from_slice(nonce), content)
+        .map_err(|_| anyhow::anyhow!("failed to decrypt row with storage key {version}"))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypt_rows() -> anyhow::Result<()> {
+        let old = StorageEncryption::new(1, &[1; 32])?;
+        let (version, sealed) = seal(Some(&old), b"update")?;
+        assert_eq!(version, 1);
+        assert_ne!(&*sealed, b"update");
+        assert_eq!(open(Some(&old), version, sealed.to_vec())?, b"update");
+
+        // the same data gets another nonce each time
+        assert_ne!(seal(Some(&old), b"update")?.1, sealed);
+
+        // rotated keys
+        let keys = format!(
+            "2:{},1:{}",
+            STANDARD.encode([2; 32]),
+            STANDARD.encode([1; 32])
+        );
+        let rotated = StorageEncryption::parse(&keys)?;
+        assert_eq!(open(Some(&rotated), 1, sealed.to_vec())?, b"update");
+        assert_eq!(seal(Some(&rotated), b"update")?.0, 2);
+        assert!(open(
+            Some(&StorageEncryption::new(1, &[2; 32])?),
+            1,
+            sealed.to_vec()
+        )
+        .is_err());
+        assert!(open(None, 1, sealed.to_vec()).is_err());
+
+        // plain text rows
+        assert_eq!(seal(None, b"update")?, (0, Cow::Borrowed(&b"update"[..])));
+        assert_eq!(open(Some(&rotated), 0, b"update".to_vec())?, b"update");
+
+        assert!(StorageEncryption::new(0, &[1; 32]).is_err());
+        assert!(StorageEncryption::new(1, &[1; 16]).is_err());
+        assert!(StorageEncryption::parse("").is_err());
+        assert!(StorageEncryption::parse("1:invalid key").is_err());
+        Ok(())
+    }
+}
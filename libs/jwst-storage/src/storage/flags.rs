@@ -0,0 +1,86 @@
+use super::{entities::prelude::*, *};
+use sea_orm::sea_query::OnConflict;
+use std::collections::BTreeMap;
+use tokio::sync::broadcast::Receiver;
+
+type FlagsActiveModel = super::entities::workspace_flags::ActiveModel;
+type FlagsColumn = <WorkspaceFlags as EntityTrait>::Column;
+
+impl JwstStorage {
+    /// Feature flags of a workspace, they are stored apart from the doc
+    /// so clients can't change them.
+    pub async fn workspace_flags(&self, workspace_id: &str) -> JwstResult<BTreeMap<String, bool>> {
+        check_unscoped(workspace_id)?;
+        let _lock = self.bucket.get_lock().await;
+        Ok(WorkspaceFlags::find()
+            .filter(FlagsColumn::Workspace.eq(workspace_id))
+            .all(&self.pool)
+            .await
+            .context(format!("Failed to get flags of {workspace_id}"))?
+            .into_iter()
+            .map(|flag| (flag.flag, flag.enabled))
+            .collect())
+    }
+
+    pub async fn set_workspace_flag(
+        &self,
+        workspace_id: &str,
+        flag: &str,
+        enabled: bool,
+    ) -> JwstResult<()> {
+        check_unscoped(workspace_id)?;
+        {
+            let _lock = self.bucket.get_lock().await;
+            WorkspaceFlags::insert(FlagsActiveModel {
+                workspace: Set(workspace_id.into()),
+                flag: Set(flag.into()),
+                enabled: Set(enabled),
+            })
+            .on_conflict(
+                OnConflict::columns([FlagsColumn::Workspace, FlagsColumn::Flag])
+                    .update_column(FlagsColumn::Enabled)
+                    .to_owned(),
+            )
+            .exec(&self.pool)
+            .await
+            .context(format!("Failed to set flag {flag} of {workspace_id}"))?;
+        }
+
+        info!("set flag {} of {}: {}", flag, workspace_id, enabled);
+        self.notify_flag_change(workspace_id);
+        Ok(())
+    }
+
+    /// Remove a flag of a workspace, returns `false` if it was not set.
+    pub async fn clear_workspace_flag(&self, workspace_id: &str, flag: &str) -> JwstResult<bool> {
+        check_unscoped(workspace_id)?;
+        let cleared = {
+            let _lock = self.bucket.get_lock().await;
+            WorkspaceFlags::delete_many()
+                .filter(FlagsColumn::Workspace.eq(workspace_id))
+                .filter(FlagsColumn::Flag.eq(flag))
+                .exec(&self.pool)
+                .await
+                .context(format!("Failed to clear flag {flag} of {workspace_id}"))?
+                .rows_affected
+                > 0
+        };
+
+        if cleared {
+            info!("clear flag {} of {}", flag, workspace_id);
+            self.notify_flag_change(workspace_id);
+        }
+        Ok(cleared)
+    }
+
+    /// Ids of the workspaces whose flags changed through this storage, to refresh
+    /// cached flags, changes made before subscribing are not received.
+    pub fn subscribe_flag_changes(&self) -> Receiver<String> {
+        self.flag_changes.subscribe()
+    }
+
+    fn notify_flag_change(&self, workspace_id: &str) {
+        // no receiver is not an error, nothing caches the flags
+        let _ = self.flag_changes.send(workspace_id.into());
+    }
+}
@@ -0,0 +1,122 @@
+use super::{encryption, entities::prelude::*, *};
+use sea_orm::{sea_query::OnConflict, QueryOrder};
+
+type KvActiveModel = super::entities::workspace_kv::ActiveModel;
+type KvColumn = <WorkspaceKv as EntityTrait>::Column;
+
+// the queries of [JwstStorage] and [StorageTransaction]
+pub(super) async fn kv_set<C: ConnectionTrait>(
+    conn: &C,
+    encryption: Option<&StorageEncryption>,
+    workspace_id: &str,
+    key: &str,
+    value: &[u8],
+) -> JwstResult<()> {
+    let (key_version, value) = encryption::seal(encryption, value)?;
+
+    WorkspaceKv::insert(KvActiveModel {
+        workspace: Set(workspace_id.into()),
+        name: Set(key.into()),
+        value: Set(value.into_owned()),
+        key_version: Set(key_version),
+    })
+    .on_conflict(
+        OnConflict::columns([KvColumn::Workspace, KvColumn::Name])
+            .update_columns([KvColumn::Value, KvColumn::KeyVersion])
+            .to_owned(),
+    )
+    .exec(conn)
+    .await
+    .context(format!("Failed to set {key} of {workspace_id}"))?;
+    Ok(())
+}
+
+pub(super) async fn kv_get<C: ConnectionTrait>(
+    conn: &C,
+    encryption: Option<&StorageEncryption>,
+    workspace_id: &str,
+    key: &str,
+) -> JwstResult<Option<Vec<u8>>> {
+    WorkspaceKv::find_by_id((workspace_id.to_owned(), key.to_owned()))
+        .one(conn)
+        .await
+        .context(format!("Failed to get {key} of {workspace_id}"))?
+        .map(|entry| encryption::open(encryption, entry.key_version, entry.value))
+        .transpose()
+}
+
+pub(super) async fn kv_delete<C: ConnectionTrait>(
+    conn: &C,
+    workspace_id: &str,
+    key: &str,
+) -> JwstResult<bool> {
+    let result = WorkspaceKv::delete_by_id((workspace_id.to_owned(), key.to_owned()))
+        .exec(conn)
+        .await
+        .context(format!("Failed to delete {key} of {workspace_id}"))?;
+    Ok(result.rows_affected > 0)
+}
+
+// the values of a deleted workspace
+pub(super) async fn drop<C: ConnectionTrait>(conn: &C, workspace_id: &str) -> JwstResult<()> {
+    WorkspaceKv::delete_many()
+        .filter(KvColumn::Workspace.eq(workspace_id))
+        .exec(conn)
+        .await
+        .context(format!("Failed to delete values of {workspace_id}"))?;
+    Ok(())
+}
+
+impl JwstStorage {
+    /// Store a value of a workspace under `key`, replacing the previous one. Values are
+    /// kept apart from the doc, so they are not synced to clients, e.g. server side state
+    /// of plugins.
+    pub async fn kv_set(&self, workspace_id: &str, key: &str, value: &[u8]) -> JwstResult<()> {
+        check_unscoped(workspace_id)?;
+        let _lock = self.bucket.get_lock().await;
+        kv_set(
+            &self.pool,
+            self.encryption.as_ref(),
+            workspace_id,
+            key,
+            value,
+        )
+        .await
+    }
+
+    pub async fn kv_get(&self, workspace_id: &str, key: &str) -> JwstResult<Option<Vec<u8>>> {
+        check_unscoped(workspace_id)?;
+        let _lock = self.bucket.get_lock().await;
+        kv_get(&self.pool, self.encryption.as_ref(), workspace_id, key).await
+    }
+
+    /// Remove a value of a workspace, returns `false` if it was not set.
+    pub async fn kv_delete(&self, workspace_id: &str, key: &str) -> JwstResult<bool> {
+        check_unscoped(workspace_id)?;
+        let _lock = self.bucket.get_lock().await;
+        kv_delete(&self.pool, workspace_id, key).await
+    }
+
+    /// Keys of the values set for a workspace, sorted.
+    pub async fn kv_keys(&self, workspace_id: &str) -> JwstResult<Vec<String>> {
+        check_unscoped(workspace_id)?;
+        let _lock = self.bucket.get_lock().await;
+        #[derive(FromQueryResult)]
+        struct Key {
+            name: String,
+        }
+
+        Ok(WorkspaceKv::find()
+            .select_only()
+            .column(KvColumn::Name)
+            .filter(KvColumn::Workspace.eq(workspace_id))
+            .order_by_asc(KvColumn::Name)
+            .into_model::<Key>()
+            .all(&self.pool)
+            .await
+            .context(format!("Failed to list keys of {workspace_id}"))?
+            .into_iter()
+            .map(|key| key.name)
+            .collect())
+    }
+}
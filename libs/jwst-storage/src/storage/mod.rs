@@ -1,42 +1,134 @@
+mod archive;
+mod bandwidth;
 mod blobs;
 mod docs;
+mod duplicate;
+mod encryption;
+mod flags;
+mod kv;
+mod tenant;
 mod tests;
+mod transaction;
+mod uploads;
+
+pub use archive::{ArchiveImport, ArchiveManifest};
+pub use bandwidth::{Bandwidth, BandwidthScope};
+pub use docs::{PropertyChange, RestorePoint};
+pub use duplicate::{DuplicatePlugin, DuplicatePluginRegister};
+pub use encryption::{StorageEncryption, PLAINTEXT_KEY_VERSION};
+pub use tenant::{TenantId, TenantStorage};
+pub use transaction::StorageTransaction;
+pub use uploads::{BlobUploadError, DEFAULT_BLOB_UPLOAD_TTL, DEFAULT_MAX_BLOB_UPLOAD};
 
 use super::*;
 use blobs::BlobAutoStorage;
-use docs::DocAutoStorage;
-use std::{collections::HashMap, time::Instant};
-use tokio::sync::Mutex;
+use docs::{DocAutoStorage, DocDBStorage};
+use futures::future::BoxFuture;
+use jwst::{BlobReference, ContentStats};
+use sea_orm::TransactionTrait;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
+};
+use tenant::check_unscoped;
+use tokio::sync::{
+    broadcast::{channel, Sender},
+    Mutex,
+};
+use yrs::{merge_updates_v1, updates::encoder::Encode, ReadTxn, Transact};
+
+/// Most hashes [JwstStorage::check_blobs] accepts at once.
+pub const MAX_CHECKED_BLOBS: usize = 500;
+
+// content stats walk the whole doc, reuse them for a while if nothing changed
+const CONTENT_STATS_TTL: Duration = Duration::from_secs(30);
+
+/// Inconsistencies between the blob references in a workspace and its stored blobs.
+#[derive(Debug, Default, PartialEq)]
+pub struct BlobAudit {
+    /// References to blobs that are not stored.
+    pub missing: Vec<BlobReference>,
+    /// Stored blobs that are not referenced by any block.
+    pub unreferenced: Vec<String>,
+}
+
+/// Size of a workspace, both its content and what is stored for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkspaceStorageStats {
+    pub content: ContentStats,
+    /// Number of stored updates, they are merged into one once there are too many.
+    pub updates: u64,
+    pub update_bytes: u64,
+    /// Time since the oldest stored update was written.
+    pub snapshot_age: Option<Duration>,
+    pub blobs: u64,
+    pub blob_bytes: u64,
+    /// Traffic with the clients of the workspace recorded so far.
+    pub bandwidth: Bandwidth,
+}
+
+/// Options of [JwstStorage::new_with_config].
+#[derive(Clone, Default)]
+pub struct StorageConfig {
+    /// Encrypt the doc updates and blobs written from now on, `None` stores them in plain text.
+    /// Rows stored in plain text stay readable either way.
+    pub encryption: Option<StorageEncryption>,
+    /// Most bytes a chunked blob upload may grow to, `None` for [DEFAULT_MAX_BLOB_UPLOAD].
+    pub max_blob_upload: Option<u64>,
+}
 
 pub struct JwstStorage {
     pool: DatabaseConnection,
+    bucket: Arc<Bucket>,
     blobs: BlobAutoStorage,
     docs: DocAutoStorage,
     last_migrate: Mutex<HashMap<String, Instant>>,
+    content_stats: Mutex<HashMap<String, (Vec<u8>, Instant, ContentStats)>>,
+    flag_changes: Sender<String>,
+    encryption: Option<StorageEncryption>,
+    max_blob_upload: u64,
 }
 
 impl JwstStorage {
     pub async fn new(database: &str) -> JwstResult<Self> {
+        Self::new_with_config(database, StorageConfig::default()).await
+    }
+
+    pub async fn new_with_config(database: &str, config: StorageConfig) -> JwstResult<Self> {
         let is_sqlite = is_sqlite(database);
         let pool = create_connection(database, is_sqlite).await?;
         let bucket = get_bucket(is_sqlite);
 
-        let blobs = BlobAutoStorage::init_with_pool(pool.clone(), bucket.clone())
-            .await
-            .context("Failed to init blobs")?;
-        let docs = DocAutoStorage::init_with_pool(pool.clone(), bucket.clone())
-            .await
-            .context("Failed to init docs")?;
+        let blobs = BlobAutoStorage::init_with_pool(
+            pool.clone(),
+            bucket.clone(),
+            config.encryption.clone(),
+        )
+        .await
+        .context("Failed to init blobs")?;
+        let docs =
+            DocAutoStorage::init_with_pool(pool.clone(), bucket.clone(), config.encryption.clone())
+                .await
+                .context("Failed to init docs")?;
 
         Ok(Self {
             pool,
+            bucket,
             blobs,
             docs,
             last_migrate: Mutex::new(HashMap::new()),
+            content_stats: Mutex::new(HashMap::new()),
+            flag_changes: channel(128).0,
+            encryption: config.encryption,
+            max_blob_upload: config.max_blob_upload.unwrap_or(DEFAULT_MAX_BLOB_UPLOAD),
         })
     }
 
     pub async fn new_with_sqlite(file: &str) -> JwstResult<Self> {
+        Self::new_with_sqlite_config(file, StorageConfig::default()).await
+    }
+
+    pub async fn new_with_sqlite_config(file: &str, config: StorageConfig) -> JwstResult<Self> {
         use std::fs::create_dir;
 
         let data = PathBuf::from("./data");
@@ -44,12 +136,15 @@ impl JwstStorage {
             create_dir(&data).context("Failed to create data directory")?;
         }
 
-        Self::new(&format!(
-            "sqlite:{}?mode=rwc",
-            data.join(PathBuf::from(file).name_str())
-                .with_extension("db")
-                .display()
-        ))
+        Self::new_with_config(
+            &format!(
+                "sqlite:{}?mode=rwc",
+                data.join(PathBuf::from(file).name_str())
+                    .with_extension("db")
+                    .display()
+            ),
+            config,
+        )
         .await
     }
 
@@ -73,6 +168,37 @@ impl JwstStorage {
         func(self.pool.clone()).await
     }
 
+    /// Run multiple doc, blob and workspace value operations in one database transaction,
+    /// nothing is written if `func` returns an error.
+    pub async fn transaction<R, F>(&self, func: F) -> JwstResult<R>
+    where
+        F: for<'t> FnOnce(&'t StorageTransaction) -> BoxFuture<'t, JwstResult<R>>,
+    {
+        // the database isolates the transaction, the bucket lock is only taken to commit
+        // so `func` can take its time without blocking the other writes
+        let trx = StorageTransaction::new(
+            self.pool
+                .begin()
+                .await
+                .context("failed to begin transaction")?,
+            self.encryption.clone(),
+        );
+
+        match func(&trx).await {
+            Ok(ret) => {
+                let _lock = self.bucket.get_lock().await;
+                trx.commit(&self.docs.0).await?;
+                Ok(ret)
+            }
+            Err(e) => {
+                if let Err(e) = trx.rollback().await {
+                    error!("failed to rollback transaction: {}", e);
+                }
+                Err(e)
+            }
+        }
+    }
+
     pub async fn create_workspace<S>(&self, workspace_id: S) -> JwstResult<Workspace>
     where
         S: AsRef<str>,
@@ -112,32 +238,282 @@ impl JwstStorage {
         }
     }
 
+    /// Size of each of `hashes` stored in the workspace, `None` for the missing ones.
+    /// Fails if there are more than [MAX_CHECKED_BLOBS] hashes.
+    pub async fn check_blobs(
+        &self,
+        workspace_id: &str,
+        hashes: &[String],
+    ) -> JwstResult<HashMap<String, Option<u64>>> {
+        if hashes.len() > MAX_CHECKED_BLOBS {
+            return Err(anyhow::anyhow!(
+                "can't check more than {MAX_CHECKED_BLOBS} blobs at once, got {}",
+                hashes.len()
+            )
+            .into());
+        }
+
+        let stored = self
+            .blobs
+            .stored_sizes(workspace_id, hashes)
+            .await
+            .context(format!("Failed to check blobs of {workspace_id}"))?
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+
+        Ok(hashes
+            .iter()
+            .map(|hash| (hash.clone(), stored.get(hash).copied()))
+            .collect())
+    }
+
+    /// Cross-check the blobs referenced in `property_keys` of the workspace blocks
+    /// against the stored blobs of the workspace.
+    pub async fn audit_blob_references<K>(
+        &self,
+        workspace_id: &str,
+        property_keys: &[K],
+    ) -> JwstResult<BlobAudit>
+    where
+        K: AsRef<str>,
+    {
+        let references = {
+            let workspace = self.get_workspace(workspace_id).await?;
+            let doc = workspace.doc();
+            let trx = doc.transact();
+            workspace.find_blob_references(&trx, property_keys)
+        };
+        let stored = self
+            .blobs
+            .hashes(workspace_id)
+            .await
+            .context(format!("Failed to list blobs of {workspace_id}"))?;
+
+        let referenced = references
+            .iter()
+            .map(|r| r.hash.as_str())
+            .collect::<HashSet<_>>();
+        let mut unreferenced = stored
+            .iter()
+            .filter(|hash| !referenced.contains(hash.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
+        unreferenced.sort();
+
+        let stored = stored.into_iter().collect::<HashSet<_>>();
+        let missing = references
+            .into_iter()
+            .filter(|r| !stored.contains(&r.hash))
+            .collect();
+
+        Ok(BlobAudit {
+            missing,
+            unreferenced,
+        })
+    }
+
+    /// Collect the content and storage statistics of a workspace.
+    ///
+    /// The content statistics are cached for a short while as long as the
+    /// state vector of the workspace doesn't change.
+    pub async fn workspace_stats(&self, workspace_id: &str) -> JwstResult<WorkspaceStorageStats> {
+        let workspace = self.get_workspace(workspace_id).await?;
+        let state_vector = workspace.doc().transact().state_vector().encode_v1();
+
+        let cached = self
+            .content_stats
+            .lock()
+            .await
+            .get(workspace_id)
+            .filter(|(sv, ts, _)| *sv == state_vector && ts.elapsed() < CONTENT_STATS_TTL)
+            .map(|(_, _, stats)| stats.clone());
+        let content = match cached {
+            Some(stats) => stats,
+            None => {
+                let stats = tokio::task::spawn_blocking(move || {
+                    let doc = workspace.doc();
+                    let trx = doc.transact();
+                    workspace.content_stats(&trx)
+                })
+                .await
+                .context("failed to spawn stats thread")?;
+                let mut cache = self.content_stats.lock().await;
+                // expired entries are dropped, deleted workspaces are never looked up again
+                cache.retain(|_, (_, ts, _)| ts.elapsed() < CONTENT_STATS_TTL);
+                cache.insert(
+                    workspace_id.to_owned(),
+                    (state_vector, Instant::now(), stats.clone()),
+                );
+                stats
+            }
+        };
+
+        let (updates, update_bytes, oldest) = {
+            let _lock = self.bucket.get_lock().await;
+            DocDBStorage::usage(&self.pool, workspace_id).await?
+        };
+        let (blobs, blob_bytes) = self
+            .blobs
+            .usage(workspace_id)
+            .await
+            .context(format!("Failed to count blobs of {workspace_id}"))?;
+        let bandwidth = self
+            .bandwidth(&BandwidthScope::Workspace(workspace_id.into()))
+            .await?;
+
+        Ok(WorkspaceStorageStats {
+            content,
+            updates,
+            update_bytes,
+            snapshot_age: oldest.and_then(|ts| Utc::now().signed_duration_since(ts).to_std().ok()),
+            blobs,
+            blob_bytes,
+            bandwidth,
+        })
+    }
+
+    /// Restore points of the workspace, oldest first. With the `restore-points` feature
+    /// one is recorded each time the stored updates are merged, only the newest are kept.
+    pub async fn restore_points(&self, workspace_id: &str) -> JwstResult<Vec<RestorePoint>> {
+        check_unscoped(workspace_id)?;
+        let _lock = self.bucket.get_lock().await;
+        DocDBStorage::restore_points(&self.pool, workspace_id).await
+    }
+
+    /// The workspace as it was at a restore point, encoded as an update that can be
+    /// loaded into a new workspace. `None` if there is no such restore point.
+    pub async fn restore_update(
+        &self,
+        workspace_id: &str,
+        point: i32,
+    ) -> JwstResult<Option<Vec<u8>>> {
+        check_unscoped(workspace_id)?;
+        let _lock = self.bucket.get_lock().await;
+        DocDBStorage::restore(&self.pool, self.encryption.as_ref(), workspace_id, point).await
+    }
+
+    /// Updates stored for the workspace, oldest first, read without loading the workspace.
+    /// Empty if the workspace doesn't exist.
+    pub async fn stored_updates(&self, workspace_id: &str) -> JwstResult<Vec<Vec<u8>>> {
+        check_unscoped(workspace_id)?;
+        let _lock = self.bucket.get_lock().await;
+        DocDBStorage::updates(&self.pool, self.encryption.as_ref(), workspace_id).await
+    }
+
+    /// Merge the updates stored for the workspace into one without loading it, store it in
+    /// place of them and return it, e.g. to compact workspaces that are only relayed.
+    /// `None` if nothing is stored for the workspace.
+    pub async fn merge_stored_updates(&self, workspace_id: &str) -> JwstResult<Option<Vec<u8>>> {
+        check_unscoped(workspace_id)?;
+        let _lock = self.bucket.get_lock().await;
+        let mut updates =
+            DocDBStorage::updates(&self.pool, self.encryption.as_ref(), workspace_id).await?;
+        if updates.len() < 2 {
+            return Ok(updates.pop());
+        }
+        let merged = merge_updates_v1(&updates.iter().map(Vec::as_slice).collect::<Vec<_>>())
+            .context("failed to merge stored updates")?;
+        DocDBStorage::full_migrate(
+            &self.pool,
+            self.encryption.as_ref(),
+            workspace_id,
+            merged.clone(),
+        )
+        .await?;
+        debug!("merged {} stored updates of {workspace_id}", updates.len());
+
+        Ok(Some(merged))
+    }
+
+    /// Id of the newest update stored for the workspace, it grows with every stored update.
+    /// `None` if nothing is stored for the workspace.
+    pub async fn last_update_seq(&self, workspace_id: &str) -> JwstResult<Option<i32>> {
+        check_unscoped(workspace_id)?;
+        let _lock = self.bucket.get_lock().await;
+        let id = DocDBStorage::last_id(&self.pool, workspace_id).await?;
+        Ok((id > 0).then_some(id))
+    }
+
+    /// Who changed a property of a block and when, oldest first. `key` is the name of the
+    /// property without the `prop:` prefix.
+    ///
+    /// All stored updates of the workspace are decoded and replayed, so this is slow for
+    /// large workspaces. Changes made before the stored updates were last merged into one
+    /// are reported as a single change by all of their writers. Fails if a stored update
+    /// can't be decoded.
+    pub async fn property_history(
+        &self,
+        workspace_id: &str,
+        block: &str,
+        key: &str,
+    ) -> JwstResult<Vec<PropertyChange>> {
+        check_unscoped(workspace_id)?;
+        self.docs.0.property_history(workspace_id, block, key).await
+    }
+
+    /// Updates of the workspace as they are stored from now on, e.g. to keep a replica of
+    /// the workspace in memory without polling. With Postgres the updates stored by every
+    /// process sharing the database are included, other databases only include the updates
+    /// stored by this process.
+    pub async fn workspace_update_stream(
+        &self,
+        workspace_id: &str,
+    ) -> JwstResult<impl Stream<Item = Vec<u8>>> {
+        check_unscoped(workspace_id)?;
+        self.docs.0.update_stream(workspace_id).await
+    }
+
+    /// Write the current state of a workspace to the database right away, without the
+    /// throttle of [JwstStorage::full_migrate], and return once it is committed.
+    pub async fn flush_workspace(&self, workspace_id: &str) -> JwstResult<()> {
+        check_unscoped(workspace_id)?;
+        let mut map = self.last_migrate.lock().await;
+        let workspace = self.docs.get(workspace_id.into()).await?;
+        self.docs.flush(workspace_id.into(), workspace).await?;
+        map.insert(workspace_id.into(), Instant::now());
+        debug!("flushed workspace: {workspace_id}");
+
+        Ok(())
+    }
+
     pub async fn full_migrate(
         &self,
         workspace_id: String,
         update: Option<Vec<u8>>,
         force: bool,
+    ) -> bool {
+        if let Err(e) = check_unscoped(&workspace_id) {
+            warn!("full migrate: {e}");
+            return false;
+        }
+        self.full_migrate_in(&self.docs, workspace_id, update, force)
+            .await
+    }
+
+    // `docs` is a view of the docs of this storage, see [TenantStorage]
+    pub(super) async fn full_migrate_in(
+        &self,
+        docs: &DocAutoStorage,
+        workspace_id: String,
+        update: Option<Vec<u8>>,
+        force: bool,
     ) -> bool {
         let mut map = self.last_migrate.lock().await;
         let ts = map.entry(workspace_id.clone()).or_insert(Instant::now());
 
         if ts.elapsed().as_secs() > 5 || force {
             info!("full migrate: {workspace_id}");
-            if let Ok(workspace) = self.docs.get(workspace_id.clone()).await {
-                let update = if let Some(update) = update {
-                    if let Err(e) = self.docs.delete(workspace_id.clone()).await {
+            if let Ok(workspace) = docs.get(workspace_id.clone()).await {
+                let written = if let Some(update) = update {
+                    if let Err(e) = docs.delete(workspace_id.clone()).await {
                         error!("full_migrate write error: {}", e.to_string());
                         return false;
                     };
-                    update
+                    docs.write_full_update(workspace_id.clone(), update).await
                 } else {
-                    workspace.sync_migration()
+                    docs.flush(workspace_id.clone(), workspace).await
                 };
-                if let Err(e) = self
-                    .docs
-                    .write_full_update(workspace_id.clone(), update)
-                    .await
-                {
+                if let Err(e) = written {
                     error!("db write error: {}", e.to_string());
                     return false;
                 }
@@ -0,0 +1,184 @@
+use super::*;
+use anyhow::anyhow;
+use bytes::Bytes;
+use jwst::{BlobMetadata, BlobStorage, DocStorage};
+
+// never part of a tenant id, so the tenant of a storage key is everything before the first one
+const TENANT_SEPARATOR: char = ':';
+
+/// Id of a tenant in a multi-tenant deployment, made of ascii letters, digits, `-` and `_`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TenantId(String);
+
+impl TenantId {
+    pub fn new(id: impl Into<String>) -> JwstResult<Self> {
+        let id = id.into();
+        if id.is_empty()
+            || !id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(anyhow!("invalid tenant id: {id:?}").into());
+        }
+        Ok(Self(id))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Reject the storage keys of tenant workspaces, which are only reachable through
+/// [TenantStorage].
+pub(super) fn check_unscoped(workspace_id: &str) -> JwstResult<()> {
+    if workspace_id.contains(TENANT_SEPARATOR) {
+        return Err(anyhow!("workspace id {workspace_id:?} is reserved for tenants").into());
+    }
+    Ok(())
+}
+
+/// Docs and blobs of a single tenant, see [JwstStorage::tenant].
+///
+/// Workspaces are stored under the key `{tenant}:{workspace}`, there is no way to
+/// build the key of another tenant from a workspace id passed to this scope.
+/// Workspaces returned by this scope carry the storage key as their id.
+pub struct TenantStorage<'a> {
+    storage: &'a JwstStorage,
+    tenant: TenantId,
+    docs: DocAutoStorage,
+    blobs: BlobAutoStorage,
+}
+
+impl JwstStorage {
+    /// Scope the storage to a tenant, all workspace ids passed to the scope are
+    /// resolved inside the tenant.
+    pub fn tenant(&self, tenant: TenantId) -> TenantStorage<'_> {
+        TenantStorage {
+            storage: self,
+            tenant,
+            docs: self.docs.tenant_view(),
+            blobs: self.blobs.tenant_view(),
+        }
+    }
+}
+
+impl TenantStorage<'_> {
+    pub fn tenant_id(&self) -> &TenantId {
+        &self.tenant
+    }
+
+    /// The key of a workspace of this tenant in the underlying storage.
+    pub fn storage_key(&self, workspace_id: &str) -> String {
+        format!("{}{TENANT_SEPARATOR}{workspace_id}", self.tenant.0)
+    }
+
+    // report the workspace id of the caller instead of the storage key
+    fn scope_error(&self, workspace_id: &str, e: JwstError) -> JwstError {
+        match e {
+            JwstError::WorkspaceNotFound(_) => JwstError::WorkspaceNotFound(workspace_id.into()),
+            e => e,
+        }
+    }
+
+    pub async fn create_workspace(&self, workspace_id: &str) -> JwstResult<Workspace> {
+        let key = self.storage_key(workspace_id);
+        Ok(self
+            .docs
+            .get(key)
+            .await
+            .context(format!("Failed to create workspace {workspace_id}"))?)
+    }
+
+    pub async fn get_workspace(&self, workspace_id: &str) -> JwstResult<Workspace> {
+        let key = self.storage_key(workspace_id);
+        if self
+            .docs
+            .exists(key.clone())
+            .await
+            .context(format!("Failed to check workspace {workspace_id}"))?
+        {
+            Ok(self
+                .docs
+                .get(key)
+                .await
+                .context(format!("Failed to get workspace {workspace_id}"))?)
+        } else {
+            Err(JwstError::WorkspaceNotFound(workspace_id.into()))
+        }
+    }
+
+    pub async fn delete_workspace(&self, workspace_id: &str) -> JwstResult<()> {
+        let key = self.storage_key(workspace_id);
+        self.docs.delete(key.clone()).await?;
+        self.blobs.delete_workspace(key).await
+    }
+
+    pub async fn full_migrate(
+        &self,
+        workspace_id: &str,
+        update: Option<Vec<u8>>,
+        force: bool,
+    ) -> bool {
+        self.storage
+            .full_migrate_in(&self.docs, self.storage_key(workspace_id), update, force)
+            .await
+    }
+
+    pub async fn get_blob(
+        &self,
+        workspace_id: &str,
+        hash: String,
+    ) -> JwstResult<<BlobAutoStorage as BlobStorage>::Read> {
+        self.blobs
+            .get_blob(Some(self.storage_key(workspace_id)), hash)
+            .await
+            .map_err(|e| self.scope_error(workspace_id, e))
+    }
+
+    pub async fn get_blob_metadata(
+        &self,
+        workspace_id: &str,
+        hash: String,
+    ) -> JwstResult<BlobMetadata> {
+        self.blobs
+            .get_metadata(Some(self.storage_key(workspace_id)), hash)
+            .await
+            .map_err(|e| self.scope_error(workspace_id, e))
+    }
+
+    pub async fn put_blob(
+        &self,
+        workspace_id: &str,
+        stream: impl Stream<Item = Bytes> + Send,
+    ) -> JwstResult<String> {
+        self.blobs
+            .put_blob(Some(self.storage_key(workspace_id)), stream)
+            .await
+            .map_err(|e| self.scope_error(workspace_id, e))
+    }
+
+    pub async fn delete_blob(&self, workspace_id: &str, hash: String) -> JwstResult<()> {
+        self.blobs
+            .delete_blob(Some(self.storage_key(workspace_id)), hash)
+            .await
+            .map_err(|e| self.scope_error(workspace_id, e))
+    }
+
+    /// Check which of `hashes` are already stored in the workspace,
+    /// the result is in the order of `hashes`.
+    pub async fn blobs_exist(
+        &self,
+        workspace_id: &str,
+        hashes: &[String],
+    ) -> JwstResult<Vec<bool>> {
+        let stored = self
+            .blobs
+            .stored_hashes(&self.storage_key(workspace_id), hashes)
+            .await
+            .context(format!("Failed to check blobs of {workspace_id}"))?
+            .into_iter()
+            .collect::<HashSet<_>>();
+
+        Ok(hashes.iter().map(|hash| stored.contains(hash)).collect())
+    }
+}
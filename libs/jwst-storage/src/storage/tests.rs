@@ -1,9 +1,10 @@
 #[cfg(test)]
-use super::{blobs::blobs_storage_test, docs::docs_storage_test, *};
+use super::{blobs::blobs_storage_test, docs::docs_storage_test, transaction::transaction_test, *};
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::BTreeMap;
 
     #[tokio::test]
     async fn sqlite_storage_test() -> anyhow::Result<()> {
@@ -15,6 +16,852 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn sqlite_transaction_test() -> anyhow::Result<()> {
+        let storage = JwstStorage::new("sqlite::memory:").await?;
+        transaction_test(&storage).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_blob_audit_test() -> anyhow::Result<()> {
+        use jwst::{BlobReference, DEFAULT_BLOB_PROPERTY_KEYS};
+
+        let storage = JwstStorage::new("sqlite::memory:").await?;
+        let workspace = storage.create_workspace("audit").await?;
+        workspace.with_trx(|mut t| {
+            let page = t.create("page", "affine:page");
+            let image = t.create("image", "affine:embed");
+            image.set(&mut t.trx, "sourceId", "stored");
+            let broken = t.create("broken", "affine:embed");
+            broken.set(&mut t.trx, "sourceId", "restored_partially");
+            page.push_children(&mut t.trx, &image);
+            page.push_children(&mut t.trx, &broken);
+        });
+
+        storage
+            .blobs()
+            .insert("audit", "stored", &[1, 2, 3])
+            .await?;
+        storage
+            .blobs()
+            .insert("audit", "orphan", &[4, 5, 6])
+            .await?;
+        // blobs of other workspaces are not checked
+        storage.blobs().insert("other", "other", &[7, 8, 9]).await?;
+
+        assert_eq!(
+            storage
+                .audit_blob_references("audit", DEFAULT_BLOB_PROPERTY_KEYS)
+                .await?,
+            BlobAudit {
+                missing: vec![BlobReference {
+                    block_id: "broken".into(),
+                    property: "sourceId".into(),
+                    hash: "restored_partially".into(),
+                }],
+                unreferenced: vec!["orphan".into()],
+            }
+        );
+
+        // no property is checked, so every blob is unreferenced
+        let audit = storage.audit_blob_references::<&str>("audit", &[]).await?;
+        assert!(audit.missing.is_empty());
+        assert_eq!(audit.unreferenced, vec!["orphan", "stored"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_check_blobs_test() -> anyhow::Result<()> {
+        let storage = JwstStorage::new("sqlite::memory:").await?;
+        storage.blobs().insert("check", "a", &[1, 2]).await?;
+        storage.blobs().insert("other", "b", &[3]).await?;
+
+        let hashes = ["a", "b", "a", "c"].map(String::from);
+        assert_eq!(
+            storage.check_blobs("check", &hashes).await?,
+            HashMap::from([
+                ("a".to_owned(), Some(2)),
+                ("b".to_owned(), None),
+                ("c".to_owned(), None),
+            ])
+        );
+        assert!(storage.check_blobs("check", &[]).await?.is_empty());
+
+        let hashes = (0..=MAX_CHECKED_BLOBS)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>();
+        assert!(storage.check_blobs("check", &hashes).await.is_err());
+        assert_eq!(
+            storage
+                .check_blobs("check", &hashes[..MAX_CHECKED_BLOBS])
+                .await?
+                .len(),
+            MAX_CHECKED_BLOBS
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_workspace_flags_test() -> anyhow::Result<()> {
+        let storage = JwstStorage::new("sqlite::memory:").await?;
+        let mut changes = storage.subscribe_flag_changes();
+        assert!(storage.workspace_flags("flags").await?.is_empty());
+
+        storage.set_workspace_flag("flags", "subdocs", true).await?;
+        storage
+            .set_workspace_flag("flags", "v2_encoding", true)
+            .await?;
+        storage
+            .set_workspace_flag("flags", "v2_encoding", false)
+            .await?;
+        storage.set_workspace_flag("other", "subdocs", true).await?;
+        assert_eq!(
+            storage.workspace_flags("flags").await?,
+            BTreeMap::from([("subdocs".into(), true), ("v2_encoding".into(), false)])
+        );
+
+        assert!(storage.clear_workspace_flag("flags", "subdocs").await?);
+        assert!(!storage.clear_workspace_flag("flags", "subdocs").await?);
+        assert_eq!(
+            storage.workspace_flags("flags").await?,
+            BTreeMap::from([("v2_encoding".into(), false)])
+        );
+
+        // every change is notified once
+        for workspace in ["flags", "flags", "flags", "other", "flags"] {
+            assert_eq!(changes.recv().await?, workspace);
+        }
+        assert!(changes.try_recv().is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_workspace_stats_test() -> anyhow::Result<()> {
+        let storage = JwstStorage::new("sqlite::memory:").await?;
+        let workspace = storage.create_workspace("stats").await?;
+        workspace.with_trx(|mut t| {
+            let page = t.create("page", "affine:page");
+            let text = t.create("text", "affine:paragraph");
+            page.push_children(&mut t.trx, &text);
+        });
+        assert!(storage.full_migrate("stats".into(), None, true).await);
+        storage.blobs().insert("stats", "a", &[1, 2, 3]).await?;
+        storage.blobs().insert("other", "b", &[4]).await?;
+
+        let stats = storage.workspace_stats("stats").await?;
+        assert_eq!(stats.content.blocks, 2);
+        assert_eq!(stats.content.max_depth, 2);
+        assert_eq!(stats.updates, 1);
+        assert!(stats.update_bytes > 0);
+        assert!(stats.snapshot_age.is_some());
+        assert_eq!((stats.blobs, stats.blob_bytes), (1, 3));
+
+        // a changed doc is not served from the cache
+        workspace.with_trx(|mut t| {
+            t.create("another", "affine:paragraph");
+        });
+        assert_eq!(storage.workspace_stats("stats").await?.content.blocks, 3);
+
+        assert!(matches!(
+            storage.workspace_stats("missing").await,
+            Err(JwstError::WorkspaceNotFound(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_flush_workspace_test() -> anyhow::Result<()> {
+        let storage = JwstStorage::new("sqlite::memory:").await?;
+        let workspace = storage.create_workspace("flush").await?;
+        storage.flush_workspace("flush").await?;
+
+        workspace.with_trx(|mut t| {
+            t.create("page", "affine:page");
+        });
+        // throttled right after the flush
+        assert!(storage.full_migrate("flush".into(), None, false).await);
+        storage.docs.0.remove_cache("flush");
+        let reloaded = storage.get_workspace("flush").await?;
+        reloaded.with_trx(|t| assert!(!reloaded.exists(&t.trx, "page")));
+
+        reloaded.with_trx(|mut t| {
+            t.create("page", "affine:page");
+        });
+        storage.flush_workspace("flush").await?;
+        storage.docs.0.remove_cache("flush");
+        let reloaded = storage.get_workspace("flush").await?;
+        reloaded.with_trx(|t| assert!(reloaded.exists(&t.trx, "page")));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_stored_updates_test() -> anyhow::Result<()> {
+        let storage = JwstStorage::new("sqlite::memory:").await?;
+        assert!(storage.stored_updates("updates").await?.is_empty());
+
+        let workspace = Workspace::new("updates");
+        workspace.with_trx(|mut t| {
+            t.create("block", "affine:text");
+        });
+        let update = workspace.sync_migration();
+        storage
+            .docs()
+            .write_update("updates".into(), &update)
+            .await?;
+        storage
+            .docs()
+            .write_update("updates".into(), &[0, 0])
+            .await?;
+
+        assert_eq!(
+            storage.stored_updates("updates").await?,
+            vec![update, vec![0, 0]]
+        );
+
+        assert_eq!(storage.merge_stored_updates("missing").await?, None);
+        let merged = storage.merge_stored_updates("updates").await?.unwrap();
+        assert_eq!(storage.stored_updates("updates").await?, vec![merged]);
+        let reloaded = storage.get_workspace("updates").await?;
+        reloaded.with_trx(|t| assert!(reloaded.exists(&t.trx, "block")));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_property_history_test() -> anyhow::Result<()> {
+        use lib0::any::Any;
+        use yrs::{updates::decoder::Decode, Doc, Update};
+
+        let storage = JwstStorage::new("sqlite::memory:").await?;
+        assert_eq!(storage.last_update_seq("history").await?, None);
+
+        // two clients renaming the page one after another
+        let writers =
+            [1, 2].map(|client| Workspace::from_doc(Doc::with_client_id(client), "history"));
+        for (writer, title) in [(0, "draft"), (1, "final"), (0, "published")] {
+            let workspace = &writers[writer];
+            let doc = workspace.doc();
+            let before = doc.transact().state_vector();
+            workspace.with_trx(|mut t| {
+                let page = workspace
+                    .get(&t.trx, "page")
+                    .unwrap_or_else(|| t.create("page", "affine:page"));
+                page.set(&mut t.trx, "title", title);
+            });
+            let update = doc.transact().encode_state_as_update_v1(&before);
+            storage
+                .docs()
+                .write_update("history".into(), &update)
+                .await?;
+            for other in &writers {
+                other
+                    .doc()
+                    .transact_mut()
+                    .apply_update(Update::decode_v1(&update)?);
+            }
+        }
+
+        let history = storage.property_history("history", "page", "title").await?;
+        let last = storage.last_update_seq("history").await?;
+        assert_eq!(history.last().map(|change| change.seq), last);
+        assert!(history.windows(2).all(|w| w[0].seq < w[1].seq));
+        assert_eq!(
+            history
+                .into_iter()
+                .map(|change| (change.write.clients, change.write.new))
+                .collect::<Vec<_>>(),
+            vec![
+                (vec![1], Some(Any::String("draft".into()))),
+                (vec![2], Some(Any::String("final".into()))),
+                (vec![1], Some(Any::String("published".into()))),
+            ]
+        );
+        assert!(storage
+            .property_history("history", "page", "missing")
+            .await?
+            .is_empty());
+
+        // an update that doesn't decode fails the history instead of leaving out its changes
+        storage
+            .docs()
+            .write_update("history".into(), &[255])
+            .await?;
+        assert!(storage
+            .property_history("history", "page", "title")
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_workspace_kv_test() -> anyhow::Result<()> {
+        let storage = JwstStorage::new("sqlite::memory:").await?;
+        assert_eq!(storage.kv_get("kv", "checkpoint").await?, None);
+        assert!(storage.kv_keys("kv").await?.is_empty());
+
+        storage.kv_set("kv", "checkpoint", b"1").await?;
+        storage.kv_set("kv", "checkpoint", b"2").await?;
+        storage.kv_set("kv", "config", b"{}").await?;
+        storage.kv_set("other", "checkpoint", b"3").await?;
+        assert_eq!(
+            storage.kv_get("kv", "checkpoint").await?,
+            Some(b"2".to_vec())
+        );
+        assert_eq!(storage.kv_keys("kv").await?, vec!["checkpoint", "config"]);
+
+        assert!(storage.kv_delete("kv", "checkpoint").await?);
+        assert!(!storage.kv_delete("kv", "checkpoint").await?);
+        assert_eq!(storage.kv_get("kv", "checkpoint").await?, None);
+        assert_eq!(storage.kv_keys("kv").await?, vec!["config"]);
+        assert_eq!(
+            storage.kv_get("other", "checkpoint").await?,
+            Some(b"3".to_vec())
+        );
+
+        // deleted along with the workspace
+        storage.create_workspace("kv").await?;
+        storage.docs().delete("kv".into()).await?;
+        assert!(storage.kv_keys("kv").await?.is_empty());
+        assert_eq!(storage.kv_keys("other").await?, vec!["checkpoint"]);
+
+        // values of encrypted storages are sealed like the docs
+        let encrypted = JwstStorage::new_with_config(
+            "sqlite::memory:",
+            StorageConfig {
+                encryption: Some(StorageEncryption::new(1, &[7; 32])?),
+                ..Default::default()
+            },
+        )
+        .await?;
+        encrypted.kv_set("kv", "secret", b"value").await?;
+        let stored = crate::entities::prelude::WorkspaceKv::find()
+            .one(&encrypted.pool)
+            .await?
+            .unwrap();
+        assert_ne!(stored.value, b"value");
+        assert_eq!(
+            encrypted.kv_get("kv", "secret").await?,
+            Some(b"value".to_vec())
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_duplicate_plugin_test() -> anyhow::Result<()> {
+        use jwst::WorkspacePlugins;
+
+        let secondary = Arc::new(JwstStorage::new("sqlite::memory:").await?);
+        let storage = JwstStorage::new("sqlite::memory:").await?;
+        storage.docs().set_workspace_plugins(
+            WorkspacePlugins::none().register(DuplicatePluginRegister::new(secondary.clone())),
+        );
+
+        let workspace = storage.create_workspace("duplicate").await?;
+        workspace.with_trx(|mut t| {
+            t.create("first", "affine:text");
+        });
+        workspace.with_trx(|mut t| {
+            t.create("second", "affine:text");
+        });
+
+        let lag = || {
+            workspace
+                .with_plugin::<DuplicatePlugin, _>(|plugin| plugin.lag())
+                .flatten()
+        };
+        for _ in 0..100 {
+            if lag() == Some(0) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(lag(), Some(0));
+
+        let mirrored = secondary.get_workspace("duplicate").await?;
+        mirrored.with_trx(|t| {
+            assert!(mirrored.get(&t.trx, "first").is_some());
+            assert!(mirrored.get(&t.trx, "second").is_some());
+        });
+
+        // a workspace loaded again shares the task of the instance still around
+        storage.docs().0.remove_cache("duplicate");
+        let reloaded = storage.get_workspace("duplicate").await?;
+        drop(workspace);
+        reloaded.with_trx(|mut t| {
+            t.create("third", "affine:text");
+        });
+        let lag = || {
+            reloaded
+                .with_plugin::<DuplicatePlugin, _>(|plugin| plugin.lag())
+                .flatten()
+        };
+        for _ in 0..100 {
+            if lag() == Some(0) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(lag(), Some(0));
+        secondary.docs().0.remove_cache("duplicate");
+        let mirrored = secondary.get_workspace("duplicate").await?;
+        mirrored.with_trx(|t| assert!(mirrored.get(&t.trx, "third").is_some()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_blob_upload_test() -> anyhow::Result<()> {
+        use bytes::Bytes;
+        use futures::stream;
+
+        let storage = JwstStorage::new("sqlite::memory:").await?;
+        let (hash, _) =
+            crate::utils::get_hash(stream::iter([Bytes::from_static(b"hello world")])).await;
+
+        let upload = storage.init_blob_upload("upload", Some(&hash)).await?;
+        assert_eq!(
+            storage
+                .append_blob_upload("upload", &upload, 0, b"hello")
+                .await?,
+            5
+        );
+        // a retried chunk is rejected with the length to resume from
+        assert!(matches!(
+            storage
+                .append_blob_upload("upload", &upload, 0, b"hello")
+                .await,
+            Err(BlobUploadError::OffsetMismatch {
+                offset: 0,
+                length: 5
+            })
+        ));
+        assert_eq!(storage.blob_upload_length("upload", &upload).await?, 5);
+        storage
+            .append_blob_upload("upload", &upload, 5, b" world")
+            .await?;
+        // uploads are only visible in their workspace
+        assert!(matches!(
+            storage.complete_blob_upload("other", &upload).await,
+            Err(BlobUploadError::NotFound(_))
+        ));
+
+        assert_eq!(storage.complete_blob_upload("upload", &upload).await?, hash);
+        assert_eq!(
+            storage.blobs().get("upload", &hash).await?.blob,
+            b"hello world"
+        );
+        assert!(matches!(
+            storage.blob_upload_length("upload", &upload).await,
+            Err(BlobUploadError::NotFound(_))
+        ));
+
+        // the assembled blob must have the announced hash
+        let upload = storage.init_blob_upload("upload", Some("invalid")).await?;
+        storage
+            .append_blob_upload("upload", &upload, 0, b"other")
+            .await?;
+        assert!(matches!(
+            storage.complete_blob_upload("upload", &upload).await,
+            Err(BlobUploadError::HashMismatch { expected, .. }) if expected == "invalid"
+        ));
+        assert!(!storage.blobs().exists("upload", "invalid").await?);
+
+        let upload = storage.init_blob_upload("upload", None).await?;
+        storage
+            .append_blob_upload("upload", &upload, 0, b"abandoned")
+            .await?;
+        assert_eq!(
+            storage
+                .cleanup_blob_uploads(Duration::from_secs(60))
+                .await?,
+            0
+        );
+        assert_eq!(storage.cleanup_blob_uploads(Duration::ZERO).await?, 1);
+        assert!(matches!(
+            storage.append_blob_upload("upload", &upload, 9, b"!").await,
+            Err(BlobUploadError::NotFound(_))
+        ));
+
+        // uploads can't grow past the limit
+        let storage = JwstStorage::new_with_config(
+            "sqlite::memory:",
+            StorageConfig {
+                max_blob_upload: Some(8),
+                ..Default::default()
+            },
+        )
+        .await?;
+        let upload = storage.init_blob_upload("upload", None).await?;
+        storage
+            .append_blob_upload("upload", &upload, 0, b"hello")
+            .await?;
+        assert!(matches!(
+            storage
+                .append_blob_upload("upload", &upload, 5, b" world")
+                .await,
+            Err(BlobUploadError::TooLarge { max: 8 })
+        ));
+        storage
+            .append_blob_upload("upload", &upload, 5, b"!!!")
+            .await?;
+        let hash = storage.complete_blob_upload("upload", &upload).await?;
+        assert_eq!(
+            storage.blobs().get("upload", &hash).await?.blob,
+            b"hello!!!"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_bandwidth_test() -> anyhow::Result<()> {
+        let storage = JwstStorage::new("sqlite::memory:").await?;
+        storage.create_workspace("traffic").await?;
+        let workspace = BandwidthScope::Workspace("traffic".into());
+        let user = BandwidthScope::User("traffic".into());
+        assert_eq!(storage.bandwidth(&workspace).await?, Bandwidth::default());
+
+        let bandwidth = Bandwidth {
+            sent: 100,
+            received: 10,
+        };
+        storage.record_bandwidth(&workspace, bandwidth).await?;
+        storage.record_bandwidth(&workspace, bandwidth).await?;
+        storage.record_bandwidth(&user, bandwidth).await?;
+        assert_eq!(
+            storage.bandwidth(&workspace).await?,
+            Bandwidth {
+                sent: 200,
+                received: 20
+            }
+        );
+        assert_eq!(storage.bandwidth(&user).await?, bandwidth);
+        assert_eq!(
+            storage.workspace_stats("traffic").await?.bandwidth,
+            storage.bandwidth(&workspace).await?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_encryption_test() -> anyhow::Result<()> {
+        use crate::entities::prelude::{Blobs, Docs};
+        use yrs::{ReadTxn, StateVector, Transact};
+
+        let key = StorageEncryption::new(1, &[1; 32])?;
+        let storage = JwstStorage::new_with_config(
+            "sqlite::memory:",
+            StorageConfig {
+                encryption: Some(key.clone()),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let workspace = storage.create_workspace("encrypted").await?;
+        workspace.with_trx(|mut t| {
+            t.create("page", "affine:page");
+        });
+        assert!(storage.full_migrate("encrypted".into(), None, true).await);
+        storage
+            .blobs()
+            .insert("encrypted", "blob", &[1, 2, 3])
+            .await?;
+
+        let docs = Docs::find().all(&storage.pool).await?;
+        assert_eq!(docs.len(), 1);
+        assert_eq!(docs[0].key_version, 1);
+        assert_ne!(docs[0].blob, workspace.sync_migration());
+        let blobs = Blobs::find().all(&storage.pool).await?;
+        assert_eq!((blobs[0].key_version, blobs[0].length), (1, 3));
+        assert_ne!(blobs[0].blob, vec![1, 2, 3]);
+
+        // rows written before encryption was enabled are still readable
+        let legacy = Workspace::new("encrypted");
+        legacy.with_trx(|mut t| {
+            t.create("legacy", "affine:page");
+        });
+        let update = legacy
+            .doc()
+            .transact()
+            .encode_state_as_update_v1(&StateVector::default());
+        DocDBStorage::store_update(&storage.pool, None, "encrypted", &update).await?;
+        BlobAutoStorage::insert_in(&storage.pool, None, "encrypted", "legacy", &[4, 5]).await?;
+
+        storage.docs().0.remove_cache("encrypted");
+        let workspace = storage.get_workspace("encrypted").await?;
+        workspace.with_trx(|t| {
+            assert!(t.ws.exists(&t.trx, "page"));
+            assert!(t.ws.exists(&t.trx, "legacy"));
+        });
+        assert_eq!(
+            storage.blobs().get("encrypted", "blob").await?.blob,
+            vec![1, 2, 3]
+        );
+        assert_eq!(
+            storage.blobs().get("encrypted", "legacy").await?.blob,
+            vec![4, 5]
+        );
+        assert_eq!(storage.blobs().metadata("encrypted", "blob").await?.size, 3);
+
+        // a rotated key keeps the old one to read the rows encrypted with it
+        let rotated = StorageEncryption::new(2, &[2; 32])?.with_retired_key(1, &[1; 32])?;
+        let blob =
+            BlobAutoStorage::get_in(&storage.pool, Some(&rotated), "encrypted", "blob").await?;
+        assert_eq!(blob.blob, vec![1, 2, 3]);
+        let without_old_key = StorageEncryption::new(2, &[2; 32])?;
+        assert!(BlobAutoStorage::get_in(
+            &storage.pool,
+            Some(&without_old_key),
+            "encrypted",
+            "blob"
+        )
+        .await
+        .is_err());
+        assert!(
+            BlobAutoStorage::get_in(&storage.pool, None, "encrypted", "blob")
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_tenant_test() -> anyhow::Result<()> {
+        use bytes::Bytes;
+        use futures::stream;
+
+        assert!(TenantId::new("").is_err());
+        assert!(TenantId::new("a:b").is_err());
+
+        let storage = JwstStorage::new("sqlite::memory:").await?;
+        let first = storage.tenant(TenantId::new("first")?);
+        let second = storage.tenant(TenantId::new("second")?);
+
+        let workspace = first.create_workspace("shared").await?;
+        assert_eq!(workspace.id(), "first:shared");
+        workspace.with_trx(|mut t| {
+            t.create("block", "text");
+        });
+        assert!(first.full_migrate("shared", None, true).await);
+        let hash = first
+            .put_blob("shared", stream::iter([Bytes::from_static(b"blob")]))
+            .await?;
+
+        // the same workspace id in another tenant is another workspace
+        assert!(matches!(
+            second.get_workspace("shared").await,
+            Err(JwstError::WorkspaceNotFound(id)) if id == "shared"
+        ));
+        assert!(second.get_blob("shared", hash.clone()).await.is_err());
+        assert_eq!(
+            second.blobs_exist("shared", &[hash.clone()]).await?,
+            vec![false]
+        );
+        // ids can't escape the tenant
+        assert!(second.get_workspace("../first:shared").await.is_err());
+
+        // the storage keys of tenants are reserved in the unscoped storage
+        assert!(storage.get_workspace("first:shared").await.is_err());
+        assert!(storage.create_workspace("first:other").await.is_err());
+        assert!(storage.blobs().count("first:shared").await.is_err());
+        assert!(storage.stored_updates("first:shared").await.is_err());
+        assert!(
+            !storage
+                .full_migrate("first:shared".into(), None, true)
+                .await
+        );
+
+        let workspace = first.get_workspace("shared").await?;
+        workspace.with_trx(|t| assert!(workspace.exists(&t.trx, "block")));
+        assert_eq!(
+            first.blobs_exist("shared", &[hash.clone()]).await?,
+            vec![true]
+        );
+
+        first.delete_workspace("shared").await?;
+        assert!(first.get_workspace("shared").await.is_err());
+        assert_eq!(first.blobs_exist("shared", &[hash]).await?, vec![false]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_archive_test() -> anyhow::Result<()> {
+        use rand::{distributions::Alphanumeric, thread_rng, Rng};
+
+        let dir = std::env::temp_dir().join(format!(
+            "jwst-archive-{}",
+            thread_rng()
+                .sample_iter(Alphanumeric)
+                .take(8)
+                .map(char::from)
+                .collect::<String>()
+        ));
+        let (full, incremental) = (dir.join("full"), dir.join("incremental"));
+
+        let storage = JwstStorage::new("sqlite::memory:").await?;
+        let workspace = storage.create_workspace("archive").await?;
+        workspace.with_trx(|mut t| {
+            t.create("page", "affine:page");
+        });
+        storage.blobs().insert("archive", "a", &[1, 2]).await?;
+        let base = storage.export_workspace_archive("archive", &full).await?;
+        assert_eq!(base.base, None);
+        assert_eq!(base.included_blobs, vec!["a".to_owned()]);
+
+        workspace.with_trx(|mut t| {
+            let page = workspace.get(&t.trx, "page").unwrap();
+            page.set(&mut t.trx, "title", "changed");
+            t.create("text", "affine:paragraph");
+        });
+        storage.blobs().insert("archive", "b", &[3]).await?;
+        let manifest = storage
+            .export_workspace_archive_incremental("archive", &base, &incremental)
+            .await?;
+        assert_eq!(manifest.base, Some(base.id.clone()));
+        assert_eq!(manifest.included_blobs, vec!["b".to_owned()]);
+        assert_eq!(ArchiveManifest::load(&incremental).await?, manifest);
+
+        // the chain has to start at the full archive and be in order
+        let restored = JwstStorage::new("sqlite::memory:").await?;
+        assert!(restored
+            .import_workspace_archives(&[&incremental])
+            .await
+            .is_err());
+        assert!(restored
+            .import_workspace_archives(&[&incremental, &full])
+            .await
+            .is_err());
+
+        assert_eq!(
+            restored
+                .import_workspace_archives(&[&full, &incremental])
+                .await?,
+            manifest
+        );
+        let copy = restored.get_workspace("archive").await?;
+        assert_eq!(
+            copy.doc().transact().state_vector(),
+            workspace.doc().transact().state_vector()
+        );
+        copy.with_trx(|t| {
+            let page = copy.get(&t.trx, "page").unwrap();
+            assert_eq!(
+                page.get(&t.trx, "title").map(|v| v.to_string()),
+                Some("changed".into())
+            );
+            assert!(copy.exists(&t.trx, "text"));
+        });
+        assert_eq!(restored.blobs().get("archive", "b").await?.blob, vec![3]);
+        assert_eq!(restored.blobs().get("archive", "a").await?.blob, vec![1, 2]);
+
+        // the doc identity is kept on request, also once the doc is reloaded
+        assert_eq!(manifest.guid, Some(workspace.guid()));
+        assert_ne!(copy.guid(), workspace.guid());
+        let moved = JwstStorage::new("sqlite::memory:").await?;
+        let import = moved
+            .import_workspace_archives_with(&[&full, &incremental], true)
+            .await?;
+        assert!(import.guids.is_empty());
+        moved.docs.0.remove_cache("archive");
+        assert_eq!(
+            moved.get_workspace("archive").await?.guid(),
+            workspace.guid()
+        );
+
+        let copied = JwstStorage::new("sqlite::memory:").await?;
+        let import = copied
+            .import_workspace_archives_with(&[&full, &incremental], false)
+            .await?;
+        assert_eq!(import.manifest, manifest);
+        assert_eq!(
+            import.guids,
+            BTreeMap::from([(
+                workspace.guid(),
+                copied.get_workspace("archive").await?.guid()
+            )])
+        );
+
+        // a modified archive is rejected
+        std::fs::write(incremental.join("blobs").join("0"), [4])?;
+        let tampered = JwstStorage::new("sqlite::memory:").await?;
+        assert!(tampered
+            .import_workspace_archives(&[&full, &incremental])
+            .await
+            .is_err());
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_workspace_update_stream_test() -> anyhow::Result<()> {
+        use super::super::docs::update_stream_test;
+        use futures::StreamExt;
+
+        let storage = JwstStorage::new("sqlite::memory:").await?;
+        storage.create_workspace("stream").await?;
+        let mut updates = storage.workspace_update_stream("stream").await?;
+
+        let workspace = Workspace::new("stream");
+        workspace.with_trx(|mut t| {
+            t.create("block", "affine:text");
+        });
+        let update = workspace.sync_migration();
+        storage
+            .docs()
+            .write_update("stream".into(), &update)
+            .await?;
+        // updates of other workspaces are not included
+        storage.docs().write_update("other".into(), &[0, 0]).await?;
+        storage
+            .docs()
+            .write_update("stream".into(), &[0, 0])
+            .await?;
+
+        assert_eq!(updates.next().await, Some(update));
+        assert_eq!(updates.next().await, Some(vec![0, 0]));
+        drop(updates);
+
+        update_stream_test(&storage.docs().0).await?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "chunked-docs")]
+    #[tokio::test]
+    async fn sqlite_chunked_docs_test() -> anyhow::Result<()> {
+        use super::super::docs::chunked_docs_test;
+
+        let storage = JwstStorage::new("sqlite::memory:").await?;
+        chunked_docs_test(&storage.docs().0).await?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "restore-points")]
+    #[tokio::test]
+    async fn sqlite_restore_points_test() -> anyhow::Result<()> {
+        use super::super::docs::restore_points_test;
+
+        let storage = JwstStorage::new("sqlite::memory:").await?;
+        restore_points_test(&storage.docs().0).await?;
+
+        Ok(())
+    }
+
     #[ignore = "need postgres server"]
     #[cfg(feature = "postgres")]
     #[tokio::test]
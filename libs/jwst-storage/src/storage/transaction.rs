@@ -0,0 +1,195 @@
+use super::{docs::DocDBStorage, kv, *};
+use sea_orm::DatabaseTransaction;
+use std::sync::Mutex;
+
+// side effects that must wait until the transaction is committed
+enum Committed {
+    Update(String, Vec<u8>),
+    Delete(String),
+}
+
+/// Reads and writes docs, blobs and workspace values within one database transaction,
+/// see [JwstStorage::transaction].
+pub struct StorageTransaction {
+    trx: DatabaseTransaction,
+    encryption: Option<StorageEncryption>,
+    committed: Mutex<Vec<Committed>>,
+}
+
+impl StorageTransaction {
+    pub(super) fn new(trx: DatabaseTransaction, encryption: Option<StorageEncryption>) -> Self {
+        Self {
+            trx,
+            encryption,
+            committed: Mutex::new(vec![]),
+        }
+    }
+
+    pub(super) async fn commit(self, docs: &DocDBStorage) -> JwstResult<()> {
+        self.trx
+            .commit()
+            .await
+            .context("failed to commit transaction")?;
+
+        for committed in self.committed.into_inner().unwrap() {
+            match committed {
+                Committed::Update(workspace, update) => docs.broadcast_update(&workspace, &update),
+                Committed::Delete(workspace) => docs.remove_cache(&workspace),
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(super) async fn rollback(self) -> JwstResult<()> {
+        self.trx
+            .rollback()
+            .await
+            .context("failed to rollback transaction")?;
+        Ok(())
+    }
+
+    pub async fn doc_exists(&self, workspace: &str) -> JwstResult<bool> {
+        check_unscoped(workspace)?;
+        Ok(DocDBStorage::count(&self.trx, workspace).await? > 0)
+    }
+
+    pub async fn write_update(&self, workspace: &str, update: &[u8]) -> JwstResult<()> {
+        check_unscoped(workspace)?;
+        DocDBStorage::store_update(&self.trx, self.encryption.as_ref(), workspace, update).await?;
+        self.committed
+            .lock()
+            .unwrap()
+            .push(Committed::Update(workspace.into(), update.into()));
+        Ok(())
+    }
+
+    pub async fn write_full_update(&self, workspace: &str, update: Vec<u8>) -> JwstResult<()> {
+        check_unscoped(workspace)?;
+        DocDBStorage::full_migrate(&self.trx, self.encryption.as_ref(), workspace, update).await
+    }
+
+    pub async fn delete_doc(&self, workspace: &str) -> JwstResult<()> {
+        check_unscoped(workspace)?;
+        DocDBStorage::drop(&self.trx, workspace).await?;
+        self.committed
+            .lock()
+            .unwrap()
+            .push(Committed::Delete(workspace.into()));
+        Ok(())
+    }
+
+    pub async fn blob_exists(&self, workspace: &str, hash: &str) -> JwstResult<bool> {
+        check_unscoped(workspace)?;
+        Ok(BlobAutoStorage::exists_in(&self.trx, workspace, hash)
+            .await
+            .context("failed to check blob")?)
+    }
+
+    pub async fn get_blob(&self, workspace: &str, hash: &str) -> JwstResult<Vec<u8>> {
+        check_unscoped(workspace)?;
+        Ok(
+            BlobAutoStorage::get_in(&self.trx, self.encryption.as_ref(), workspace, hash)
+                .await
+                .context("failed to get blob")?
+                .blob,
+        )
+    }
+
+    pub async fn insert_blob(&self, workspace: &str, hash: &str, blob: &[u8]) -> JwstResult<()> {
+        check_unscoped(workspace)?;
+        Ok(
+            BlobAutoStorage::insert_in(&self.trx, self.encryption.as_ref(), workspace, hash, blob)
+                .await
+                .context("failed to insert blob")?,
+        )
+    }
+
+    pub async fn delete_blob(&self, workspace: &str, hash: &str) -> JwstResult<bool> {
+        check_unscoped(workspace)?;
+        Ok(BlobAutoStorage::delete_in(&self.trx, workspace, hash)
+            .await
+            .context("failed to delete blob")?)
+    }
+
+    pub async fn delete_blobs(&self, workspace: &str) -> JwstResult<()> {
+        check_unscoped(workspace)?;
+        Ok(BlobAutoStorage::drop_in(&self.trx, workspace)
+            .await
+            .context("failed to delete blobs")?)
+    }
+
+    /// See [JwstStorage::kv_set].
+    pub async fn kv_set(&self, workspace: &str, key: &str, value: &[u8]) -> JwstResult<()> {
+        check_unscoped(workspace)?;
+        kv::kv_set(&self.trx, self.encryption.as_ref(), workspace, key, value).await
+    }
+
+    pub async fn kv_get(&self, workspace: &str, key: &str) -> JwstResult<Option<Vec<u8>>> {
+        check_unscoped(workspace)?;
+        kv::kv_get(&self.trx, self.encryption.as_ref(), workspace, key).await
+    }
+
+    pub async fn kv_delete(&self, workspace: &str, key: &str) -> JwstResult<bool> {
+        check_unscoped(workspace)?;
+        kv::kv_delete(&self.trx, workspace, key).await
+    }
+}
+
+#[cfg(test)]
+pub async fn transaction_test(storage: &JwstStorage) -> anyhow::Result<()> {
+    use futures::FutureExt;
+
+    // a failure in the middle rolls back every write
+    let ret = storage
+        .transaction(|trx| {
+            async move {
+                trx.write_update("import", &[1, 2, 3, 4]).await?;
+                trx.insert_blob("import", "hash", &[1, 2, 3]).await?;
+                trx.kv_set("import", "meta", &[1]).await?;
+                assert!(trx.doc_exists("import").await?);
+                assert!(trx.blob_exists("import", "hash").await?);
+                Err::<(), _>(JwstError::WorkspaceNotInitialized("import".into()))
+            }
+            .boxed()
+        })
+        .await;
+    assert!(ret.is_err());
+    assert_eq!(DocDBStorage::count(&storage.pool, "import").await?, 0);
+    assert!(!storage.blobs().exists("import", "hash").await?);
+    assert_eq!(storage.kv_get("import", "meta").await?, None);
+
+    // everything is written when the transaction succeeds
+    storage
+        .transaction(|trx| {
+            async move {
+                trx.write_update("import", &[1, 2, 3, 4]).await?;
+                trx.insert_blob("import", "hash", &[1, 2, 3]).await?;
+                trx.kv_set("import", "meta", &[1]).await?;
+                assert_eq!(trx.kv_get("import", "meta").await?, Some(vec![1]));
+                Ok(())
+            }
+            .boxed()
+        })
+        .await?;
+    assert_eq!(DocDBStorage::count(&storage.pool, "import").await?, 1);
+    assert_eq!(
+        storage.blobs().get("import", "hash").await?.blob,
+        vec![1, 2, 3]
+    );
+    assert_eq!(storage.kv_get("import", "meta").await?, Some(vec![1]));
+
+    storage
+        .transaction(|trx| {
+            async move {
+                trx.delete_doc("import").await?;
+                trx.delete_blobs("import").await
+            }
+            .boxed()
+        })
+        .await?;
+    assert_eq!(DocDBStorage::count(&storage.pool, "import").await?, 0);
+    assert!(!storage.blobs().exists("import", "hash").await?);
+
+    Ok(())
+}
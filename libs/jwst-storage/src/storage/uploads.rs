@@ -0,0 +1,246 @@
+use super::{encryption, entities::prelude::*, *};
+use crate::utils::URL_SAFE_ENGINE;
+use base64::Engine;
+use sea_orm::{ConnectionTrait, DatabaseTransaction, QueryOrder};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+type UploadModel = <BlobUploads as EntityTrait>::Model;
+type UploadActiveModel = super::entities::blob_uploads::ActiveModel;
+type UploadColumn = <BlobUploads as EntityTrait>::Column;
+type ChunkActiveModel = super::entities::blob_upload_chunks::ActiveModel;
+type ChunkColumn = <BlobUploadChunks as EntityTrait>::Column;
+
+/// Default time an upload is kept without new chunks, see [JwstStorage::cleanup_blob_uploads].
+pub const DEFAULT_BLOB_UPLOAD_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Default of [StorageConfig::max_blob_upload], the completed blob is held in memory.
+pub const DEFAULT_MAX_BLOB_UPLOAD: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum BlobUploadError {
+    #[error("upload {0} not found")]
+    NotFound(String),
+    #[error("chunk at offset {offset} doesn't continue the upload of {length} bytes")]
+    OffsetMismatch { offset: u64, length: u64 },
+    #[error("upload would exceed {max} bytes")]
+    TooLarge { max: u64 },
+    #[error("upload {0} was appended to while it was completed")]
+    Changed(String),
+    #[error("uploaded blob has hash {actual}, expected {expected}")]
+    HashMismatch { expected: String, actual: String },
+    #[error(transparent)]
+    Storage(#[from] JwstError),
+}
+
+type UploadResult<T> = Result<T, BlobUploadError>;
+
+async fn find_upload<C>(conn: &C, workspace: &str, upload_id: &str) -> UploadResult<UploadModel>
+where
+    C: ConnectionTrait,
+{
+    BlobUploads::find_by_id(upload_id.to_owned())
+        .filter(UploadColumn::Workspace.eq(workspace))
+        .one(conn)
+        .await
+        .context("failed to find blob upload")
+        .map_err(JwstError::StorageError)?
+        .ok_or_else(|| BlobUploadError::NotFound(upload_id.to_owned()))
+}
+
+async fn remove_upload(trx: &DatabaseTransaction, upload_ids: Vec<String>) -> JwstResult<()> {
+    BlobUploadChunks::delete_many()
+        .filter(ChunkColumn::Upload.is_in(upload_ids.clone()))
+        .exec(trx)
+        .await
+        .context("failed to delete blob upload chunks")?;
+    BlobUploads::delete_many()
+        .filter(UploadColumn::Id.is_in(upload_ids))
+        .exec(trx)
+        .await
+        .context("failed to delete blob uploads")?;
+    Ok(())
+}
+
+impl JwstStorage {
+    async fn begin(&self) -> JwstResult<DatabaseTransaction> {
+        Ok(self
+            .pool
+            .begin()
+            .await
+            .context("failed to begin transaction")?)
+    }
+
+    /// Start uploading a blob to the workspace in chunks, returns the id of the upload.
+    /// With `hash` the blob is only stored if it has this hash once completed.
+    pub async fn init_blob_upload(
+        &self,
+        workspace: &str,
+        hash: Option<&str>,
+    ) -> JwstResult<String> {
+        check_unscoped(workspace)?;
+        let id = URL_SAFE_ENGINE.encode(rand::random::<[u8; 18]>());
+
+        let _lock = self.bucket.get_lock().await;
+        BlobUploads::insert(UploadActiveModel {
+            id: Set(id.clone()),
+            workspace: Set(workspace.into()),
+            hash: Set(hash.map(Into::into)),
+            length: Set(0),
+            updated_at: Set(Utc::now().into()),
+        })
+        .exec(&self.pool)
+        .await
+        .context(format!("Failed to start blob upload to {workspace}"))?;
+
+        Ok(id)
+    }
+
+    /// Bytes uploaded so far, where the next chunk starts.
+    pub async fn blob_upload_length(&self, workspace: &str, upload_id: &str) -> UploadResult<u64> {
+        let _lock = self.bucket.get_lock().await;
+        Ok(find_upload(&self.pool, workspace, upload_id).await?.length as u64)
+    }
+
+    /// Append a chunk to an upload, returns the bytes uploaded so far. `offset` must be the
+    /// end of the upload, a client that lost track of it resumes from the length reported by
+    /// [BlobUploadError::OffsetMismatch]. Chunks past [StorageConfig::max_blob_upload] are
+    /// rejected.
+    pub async fn append_blob_upload(
+        &self,
+        workspace: &str,
+        upload_id: &str,
+        offset: u64,
+        chunk: &[u8],
+    ) -> UploadResult<u64> {
+        let _lock = self.bucket.get_lock().await;
+        let trx = self.begin().await?;
+
+        let upload = find_upload(&trx, workspace, upload_id).await?;
+        let length = upload.length as u64;
+        if offset != length {
+            return Err(BlobUploadError::OffsetMismatch { offset, length });
+        }
+        if chunk.is_empty() {
+            return Ok(length);
+        }
+        if length + chunk.len() as u64 > self.max_blob_upload {
+            return Err(BlobUploadError::TooLarge {
+                max: self.max_blob_upload,
+            });
+        }
+
+        let (key_version, sealed) = encryption::seal(self.encryption.as_ref(), chunk)?;
+        BlobUploadChunks::insert(ChunkActiveModel {
+            upload: Set(upload_id.into()),
+            offset: Set(offset as i64),
+            blob: Set(sealed.into_owned()),
+            key_version: Set(key_version),
+        })
+        .exec(&trx)
+        .await
+        .context("failed to store blob upload chunk")
+        .map_err(JwstError::StorageError)?;
+
+        let length = length + chunk.len() as u64;
+        let mut upload: UploadActiveModel = upload.into();
+        upload.length = Set(length as i64);
+        upload.updated_at = Set(Utc::now().into());
+        upload
+            .update(&trx)
+            .await
+            .context("failed to update blob upload")
+            .map_err(JwstError::StorageError)?;
+
+        trx.commit()
+            .await
+            .context("failed to commit blob upload chunk")
+            .map_err(JwstError::StorageError)?;
+        Ok(length)
+    }
+
+    /// Store the uploaded chunks as a blob of the workspace and return its hash. The upload
+    /// is gone afterwards, also if the blob doesn't have the hash it was started with.
+    pub async fn complete_blob_upload(
+        &self,
+        workspace: &str,
+        upload_id: &str,
+    ) -> UploadResult<String> {
+        // the chunks are read one at a time without the lock, so other writes aren't held up
+        // by large uploads, chunks appended meanwhile are caught once the lock is taken
+        let upload = find_upload(&self.pool, workspace, upload_id).await?;
+        let mut hasher = Sha256::new();
+        let mut blob = Vec::with_capacity(upload.length as usize);
+        while (blob.len() as i64) < upload.length {
+            let chunk = BlobUploadChunks::find()
+                .filter(ChunkColumn::Upload.eq(upload_id))
+                .filter(ChunkColumn::Offset.eq(blob.len() as i64))
+                .one(&self.pool)
+                .await
+                .context("failed to read blob upload chunk")
+                .map_err(JwstError::StorageError)?
+                // completed or abandoned meanwhile
+                .ok_or_else(|| BlobUploadError::NotFound(upload_id.to_owned()))?;
+            let chunk = encryption::open(self.encryption.as_ref(), chunk.key_version, chunk.blob)?;
+            hasher.update(&chunk);
+            blob.extend(chunk);
+        }
+        let hash = URL_SAFE_ENGINE.encode(hasher.finalize());
+
+        let _lock = self.bucket.get_lock().await;
+        let trx = self.begin().await?;
+        if find_upload(&trx, workspace, upload_id).await?.length != upload.length {
+            return Err(BlobUploadError::Changed(upload_id.to_owned()));
+        }
+
+        remove_upload(&trx, vec![upload_id.to_owned()]).await?;
+        let result = match upload.hash {
+            Some(expected) if expected != hash => Err(BlobUploadError::HashMismatch {
+                expected,
+                actual: hash,
+            }),
+            _ => {
+                BlobAutoStorage::insert_in(&trx, self.encryption.as_ref(), workspace, &hash, &blob)
+                    .await
+                    .context("failed to insert blob")
+                    .map_err(JwstError::StorageError)?;
+                Ok(hash)
+            }
+        };
+
+        trx.commit()
+            .await
+            .context("failed to complete blob upload")
+            .map_err(JwstError::StorageError)?;
+        result
+    }
+
+    /// Drop the uploads that got no chunk for longer than `ttl`, returns how many.
+    pub async fn cleanup_blob_uploads(&self, ttl: Duration) -> JwstResult<usize> {
+        let deadline: DateTimeWithTimeZone = (Utc::now()
+            - chrono::Duration::from_std(ttl).context("blob upload ttl is too long")?)
+        .into();
+
+        let _lock = self.bucket.get_lock().await;
+        let trx = self.begin().await?;
+        let abandoned = BlobUploads::find()
+            .filter(UploadColumn::UpdatedAt.lt(deadline))
+            .all(&trx)
+            .await
+            .context("failed to find abandoned blob uploads")?
+            .into_iter()
+            .map(|upload| upload.id)
+            .collect::<Vec<_>>();
+        if abandoned.is_empty() {
+            return Ok(0);
+        }
+
+        remove_upload(&trx, abandoned.clone()).await?;
+        trx.commit()
+            .await
+            .context("failed to drop abandoned blob uploads")?;
+        info!("dropped {} abandoned blob uploads", abandoned.len());
+
+        Ok(abandoned.len())
+    }
+}
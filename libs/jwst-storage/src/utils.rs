@@ -8,7 +8,7 @@ use bytes::Bytes;
 use futures::stream::{iter, StreamExt};
 use sha2::{Digest, Sha256};
 
-const URL_SAFE_ENGINE: GeneralPurpose = GeneralPurpose::new(&URL_SAFE, PAD);
+pub(crate) const URL_SAFE_ENGINE: GeneralPurpose = GeneralPurpose::new(&URL_SAFE, PAD);
 
 pub async fn get_hash(stream: impl Stream<Item = Bytes> + Send) -> (String, Vec<u8>) {
     let mut hasher = Sha256::new();
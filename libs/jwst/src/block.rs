@@ -1,10 +1,23 @@
-use super::{constants::sys, utils::JS_INT_RANGE, *};
+use super::{
+    constants::sys,
+    utils::JS_INT_RANGE,
+    workspaces::{insert_system_key, remove_system_key},
+    *,
+};
 use lib0::any::Any;
 use serde::{Serialize, Serializer};
-use std::collections::HashMap;
+use serde_json::Value as JsonValue;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    hash::Hasher,
+    time::Duration,
+};
+use thiserror::Error;
+use url::Url;
 use yrs::{
-    types::ToJson, Array, ArrayPrelim, ArrayRef, Doc, Map, MapPrelim, MapRef, ReadTxn, Transact,
-    TransactionMut,
+    types::{ToJson, Value},
+    Array, ArrayPrelim, ArrayRef, Doc, Map, MapPrelim, MapRef, ReadTxn, Transact, TransactionMut,
 };
 
 #[derive(Debug, PartialEq, Clone)]
@@ -21,10 +34,157 @@ pub struct Block {
     block: MapRef,
     children: ArrayRef,
     updated: ArrayRef,
+    system_keys: SystemKeyPolicy,
+    clock: WorkspaceClock,
 }
 
 unsafe impl Send for Block {}
 
+/// Name of a block property. System properties, the ones prefixed with `sys:` such as the
+/// flavour or the children, are stored as is and can only be written with
+/// [Workspace::with_system_access], except by the workspace keeping its own ones like the
+/// tree and the timestamps up to date. The other ones are stored with a `prop:` prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PropertyKey<'a>(&'a str);
+
+impl<'a> PropertyKey<'a> {
+    pub fn new(key: &'a str) -> Self {
+        Self(key)
+    }
+
+    pub fn is_system(&self) -> bool {
+        self.0.starts_with(sys::PREFIX)
+    }
+
+    pub fn as_str(&self) -> &'a str {
+        self.0
+    }
+
+    // the property stored at `stored` in the map of a block, `None` for the `prop:sys:*`
+    // keys written before system keys were protected, they would pass for system properties
+    pub(crate) fn from_stored(stored: &'a str) -> Option<Self> {
+        match stored.strip_prefix("prop:") {
+            Some(key) => Some(Self(key)).filter(|key| !key.is_system()),
+            None => Some(Self(stored)),
+        }
+    }
+
+    // key of the property in the map of the block
+    fn stored(&self) -> Cow<'a, str> {
+        if self.is_system() {
+            Cow::Borrowed(self.0)
+        } else {
+            Cow::Owned(format!("prop:{}", self.0))
+        }
+    }
+}
+
+impl<'a> From<&'a str> for PropertyKey<'a> {
+    fn from(key: &'a str) -> Self {
+        Self::new(key)
+    }
+}
+
+/// Properties that differ between a block and a snapshot of it, see [Block::diff].
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BlockPropDiff {
+    /// Properties missing from the snapshot, with their current value.
+    pub added: BTreeMap<String, JsonValue>,
+    /// Properties the block no longer has, with their value in the snapshot.
+    pub removed: BTreeMap<String, JsonValue>,
+    /// Properties with another value, as the value in the snapshot and the current one.
+    pub changed: BTreeMap<String, (JsonValue, JsonValue)>,
+}
+
+/// Schemes accepted by [Block::set_url], other ones such as `javascript:` or `data:`
+/// could run scripts in the clients rendering the link.
+pub const ALLOWED_URL_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+#[derive(Debug, Error)]
+pub enum UrlError {
+    #[error("invalid url: {0}")]
+    Invalid(#[from] url::ParseError),
+    #[error("url scheme {0} is not allowed")]
+    Scheme(String),
+    /// The url can't be stored at the key, like a system property written without
+    /// system access, see [Block::try_set].
+    #[error(transparent)]
+    Denied(#[from] JwstError),
+}
+
+fn parse_url(url: &str) -> Result<Url, UrlError> {
+    let url = Url::parse(url.trim())?;
+    if !ALLOWED_URL_SCHEMES.contains(&url.scheme()) {
+        return Err(UrlError::Scheme(url.scheme().to_owned()));
+    }
+    Ok(url)
+}
+
+// FNV-1a, unlike the std and ahash hashers it isn't seeded per process
+struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl StableHasher {
+    // length prefixed, so the boundaries of the fields are part of the hash
+    fn write_field(&mut self, bytes: &[u8]) {
+        self.write(&(bytes.len() as u64).to_le_bytes());
+        self.write(bytes);
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+// JSON of a value with the keys of its maps sorted, maps don't keep an order
+fn canonical_json(value: &Any, json: &mut String) {
+    match value {
+        Any::Map(map) => {
+            json.push('{');
+            let entries = map.iter().collect::<BTreeMap<_, _>>();
+            for (index, (key, value)) in entries.into_iter().enumerate() {
+                if index > 0 {
+                    json.push(',');
+                }
+                Any::String(key.as_str().into()).to_json(json);
+                json.push(':');
+                canonical_json(value, json);
+            }
+            json.push('}');
+        }
+        Any::Array(values) => {
+            json.push('[');
+            for (index, value) in values.iter().enumerate() {
+                if index > 0 {
+                    json.push(',');
+                }
+                canonical_json(value, json);
+            }
+            json.push(']');
+        }
+        value => value.to_json(json),
+    }
+}
+
+impl BlockPropDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
 impl Block {
     // Create a new block, skip create if block is already created.
     pub fn new<B, F>(
@@ -54,17 +214,24 @@ impl Block {
                 .unwrap();
 
             // init default schema
-            block.insert(trx, sys::FLAVOR, flavor.as_ref());
-            block.insert(trx, sys::VERSION, ArrayPrelim::from([1, 0]));
-            block.insert(
+            insert_system_key(trx, &block, PropertyKey::new(sys::FLAVOR), flavor.as_ref());
+            insert_system_key(
+                trx,
+                &block,
+                PropertyKey::new(sys::VERSION),
+                ArrayPrelim::from([1, 0]),
+            );
+            insert_system_key(
                 trx,
-                sys::CHILDREN,
+                &block,
+                PropertyKey::new(sys::CHILDREN),
                 ArrayPrelim::<Vec<String>, String>::from(vec![]),
             );
-            block.insert(
+            insert_system_key(
                 trx,
-                sys::CREATED,
-                chrono::Utc::now().timestamp_millis() as f64,
+                &block,
+                PropertyKey::new(sys::CREATED),
+                workspace.clock().now() as f64,
             );
 
             workspace
@@ -88,6 +255,8 @@ impl Block {
                 block,
                 children,
                 updated,
+                system_keys: workspace.system_keys().clone(),
+                clock: workspace.clock().clone(),
             };
 
             block.log_update(trx, HistoryOperation::Add);
@@ -101,44 +270,88 @@ impl Block {
         T: ReadTxn,
         B: AsRef<str>,
     {
-        let block = workspace.blocks.get(trx, block_id.as_ref())?.to_ymap()?;
-        let updated = workspace.updated.get(trx, block_id.as_ref())?.to_yarray()?;
+        let block = workspace.blocks.get(trx, block_id.as_ref())?;
+        let updated = workspace.updated.get(trx, block_id.as_ref());
 
-        let children = block.get(trx, sys::CHILDREN)?.to_yarray()?;
-
-        Some(Self {
-            id: block_id.as_ref().to_string(),
-            doc: workspace.doc(),
-            operator,
+        Self::from_raw_parts(
+            trx,
+            block_id.as_ref().to_string(),
+            &workspace.doc(),
             block,
-            children,
             updated,
-        })
+            operator,
+        )
+        .map(|block| block.with_settings(workspace.system_keys(), workspace.clock()))
+        .map_err(|e| warn!("{}", e))
+        .ok()
     }
 
+    /// Build a block from its entries in the workspace `blocks` and `updated` maps,
+    /// returns [JwstError::MalformedBlock] if the doc stores something unexpected there.
+    /// The system properties of the block can't be written, it doesn't know its workspace.
     pub fn from_raw_parts<T: ReadTxn>(
         trx: &T,
         id: String,
         doc: &Doc,
-        block: MapRef,
-        updated: ArrayRef,
+        block: Value,
+        updated: Option<Value>,
         operator: u64,
-    ) -> Block {
-        let children = block.get(trx, sys::CHILDREN).unwrap().to_yarray().unwrap();
-        Self {
+    ) -> JwstResult<Block> {
+        let malformed = |expected, found: Option<&Value>| JwstError::MalformedBlock {
+            id: id.clone(),
+            expected,
+            found: found.map(value_type).unwrap_or("nothing").to_string(),
+        };
+
+        let block = match block {
+            Value::YMap(block) => block,
+            block => return Err(malformed("map", Some(&block))),
+        };
+        let updated = match updated {
+            Some(Value::YArray(updated)) => updated,
+            updated => return Err(malformed("updated array", updated.as_ref())),
+        };
+        let children = match block.get(trx, sys::CHILDREN) {
+            Some(Value::YArray(children)) => children,
+            children => return Err(malformed("children array", children.as_ref())),
+        };
+
+        Ok(Self {
             id,
             doc: doc.clone(),
             operator,
             block,
             children,
             updated,
-        }
+            system_keys: SystemKeyPolicy::default(),
+            clock: WorkspaceClock::default(),
+        })
+    }
+
+    // follow the system key policy and the clock of the workspace of the block
+    pub(crate) fn with_settings(
+        mut self,
+        system_keys: &SystemKeyPolicy,
+        clock: &WorkspaceClock,
+    ) -> Self {
+        self.system_keys = system_keys.clone();
+        self.clock = clock.clone();
+        self
     }
 
     pub(crate) fn log_update(&self, trx: &mut TransactionMut, action: HistoryOperation) {
+        // every logged mutation is a new revision
+        let revision = self.revision(trx) + 1;
+        insert_system_key(
+            trx,
+            &self.block,
+            PropertyKey::new(sys::REVISION),
+            revision as f64,
+        );
+
         let array = ArrayPrelim::from([
             Any::Number(self.operator as f64),
-            Any::Number(chrono::Utc::now().timestamp_millis() as f64),
+            Any::Number(self.clock.now() as f64),
             Any::String(Box::from(action.to_string())),
         ]);
 
@@ -149,7 +362,7 @@ impl Block {
     where
         T: ReadTxn,
     {
-        let key = format!("prop:{key}");
+        let key = PropertyKey::new(key).stored();
         self.block
             .get(trx, &key)
             .and_then(|v| match v.to_json(trx) {
@@ -161,11 +374,27 @@ impl Block {
             })
     }
 
+    /// Set a property, `Null` removes it. System properties are only written with
+    /// [Workspace::with_system_access], otherwise nothing is written and only a warning
+    /// is logged, use [Block::try_set] to get the error.
     pub fn set<T>(&self, trx: &mut TransactionMut, key: &str, value: T)
     where
         T: Into<Any>,
     {
-        let key = format!("prop:{key}");
+        if let Err(e) = self.try_set(trx, key, value) {
+            warn!("refuse to set property of {}: {}", self.id, e);
+        }
+    }
+
+    /// Same as [Block::set], but a system property written without system access is
+    /// reported as [JwstError::SystemKeyDenied].
+    pub fn try_set<T>(&self, trx: &mut TransactionMut, key: &str, value: T) -> JwstResult<()>
+    where
+        T: Into<Any>,
+    {
+        let key = PropertyKey::new(key);
+        self.system_keys.check(key)?;
+        let key = key.stored().into_owned();
         match value.into() {
             Any::Bool(bool) => {
                 self.block.insert(trx, key, bool);
@@ -193,6 +422,17 @@ impl Block {
             }
             Any::Buffer(_) | Any::Array(_) | Any::Map(_) => {}
         }
+        Ok(())
+    }
+
+    /// Remove a property, a system property without system access is reported as
+    /// [JwstError::SystemKeyDenied] like by [Block::try_set].
+    pub fn try_unset(&self, trx: &mut TransactionMut, key: &str) -> JwstResult<()> {
+        let key = PropertyKey::new(key);
+        self.system_keys.check(key)?;
+        self.block.remove(trx, key.stored().as_ref());
+        self.log_update(trx, HistoryOperation::Delete);
+        Ok(())
     }
 
     pub fn id(&self) -> String {
@@ -231,6 +471,98 @@ impl Block {
             .unwrap()
     }
 
+    // pinned blocks are protected from removal
+    pub fn is_pinned<T>(&self, trx: &T) -> bool
+    where
+        T: ReadTxn,
+    {
+        matches!(
+            self.block.get(trx, sys::PINNED).map(|v| v.to_json(trx)),
+            Some(Any::Bool(true))
+        )
+    }
+
+    pub fn set_pinned(&self, trx: &mut TransactionMut, pinned: bool) {
+        if pinned {
+            insert_system_key(trx, &self.block, PropertyKey::new(sys::PINNED), true);
+        } else {
+            remove_system_key(trx, &self.block, PropertyKey::new(sys::PINNED));
+        }
+        self.log_update(trx, HistoryOperation::Update);
+    }
+
+    /// Id of the blob holding the preview image of the block, e.g. a page cover.
+    pub fn thumbnail<T>(&self, trx: &T) -> Option<String>
+    where
+        T: ReadTxn,
+    {
+        self.block
+            .get(trx, sys::THUMBNAIL)
+            .and_then(|c| match c.to_json(trx) {
+                Any::String(s) if !s.is_empty() => Some(s.to_string()),
+                _ => None,
+            })
+    }
+
+    pub fn set_thumbnail(&self, trx: &mut TransactionMut, blob_id: &str) {
+        insert_system_key(trx, &self.block, PropertyKey::new(sys::THUMBNAIL), blob_id);
+        self.log_update(trx, HistoryOperation::Update);
+    }
+
+    /// Url stored at `key`, e.g. the link of an embed block. `None` if it's missing,
+    /// or if it was set with [Block::set] to something [Block::set_url] refuses.
+    pub fn get_url<T>(&self, trx: &T, key: &str) -> Option<Url>
+    where
+        T: ReadTxn,
+    {
+        match self.get(trx, key) {
+            Some(Any::String(url)) => parse_url(&url).ok(),
+            _ => None,
+        }
+    }
+
+    /// Store the normalized form of `url` at `key`, only [ALLOWED_URL_SCHEMES] are accepted.
+    pub fn set_url(&self, trx: &mut TransactionMut, key: &str, url: &str) -> Result<(), UrlError> {
+        let url = parse_url(url)?;
+        self.try_set(trx, key, url.as_str())?;
+        Ok(())
+    }
+
+    /// Take the advisory lock of the block for `client_id` until `ttl` elapses,
+    /// returns `false` if another client holds it. The lock is shared with peers
+    /// through awareness and is never written to the doc.
+    pub fn try_lock(&self, workspace: &Workspace, client_id: u64, ttl: Duration) -> bool {
+        workspace.try_lock_block(&self.id, client_id, ttl)
+    }
+
+    /// Release the lock of the block if `client_id` holds it.
+    pub fn unlock(&self, workspace: &Workspace, client_id: u64) {
+        workspace.unlock_block(&self.id, client_id)
+    }
+
+    pub fn locked_by(&self, workspace: &Workspace) -> Option<BlockLock> {
+        workspace.block_lock(&self.id)
+    }
+
+    /// Revision of the block, bumped by every local mutation, 0 for blocks created before
+    /// revisions were tracked. Use it to detect that a block changed since it was read.
+    ///
+    /// Concurrent edits of the same revision on different peers produce the same revision,
+    /// the merged block keeps one of them. So a changed revision always means the block
+    /// changed, but an unchanged revision doesn't guarantee the block didn't.
+    pub fn revision<T>(&self, trx: &T) -> u64
+    where
+        T: ReadTxn,
+    {
+        self.block
+            .get(trx, sys::REVISION)
+            .and_then(|c| match c.to_json(trx) {
+                Any::Number(n) => Some(n as u64),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
     pub fn created<T>(&self, trx: &T) -> u64
     where
         T: ReadTxn,
@@ -244,21 +576,29 @@ impl Block {
             .unwrap_or_default()
     }
 
-    pub fn updated<T>(&self, trx: &T) -> u64
+    // the most recent timestamp recorded in the block's `updated` history
+    // return None if the block has never been logged
+    pub fn update_at<T>(&self, trx: &T) -> Option<u64>
     where
         T: ReadTxn,
     {
         self.updated
             .iter(trx)
             .filter_map(|v| v.to_yarray())
-            .last()
-            .and_then(|a| {
+            .filter_map(|a| {
                 a.get(trx, 1).and_then(|i| match i.to_json(trx) {
                     Any::Number(n) => Some(n as u64),
                     _ => None,
                 })
             })
-            .unwrap_or_else(|| self.created(trx))
+            .max()
+    }
+
+    pub fn updated<T>(&self, trx: &T) -> u64
+    where
+        T: ReadTxn,
+    {
+        self.update_at(trx).unwrap_or_else(|| self.created(trx))
     }
 
     pub fn history<T>(&self, trx: &T) -> Vec<BlockHistory>
@@ -322,14 +662,100 @@ impl Block {
         self.block
             .iter(trx)
             .filter_map(|(key, val)| {
-                key.strip_prefix("prop:")
-                    .map(|stripped| (stripped.to_owned(), val.to_json(trx)))
+                PropertyKey::from_stored(key)
+                    .filter(|key| !key.is_system())
+                    .map(|key| (key.as_str().to_owned(), val.to_json(trx)))
             })
             .collect()
     }
 
+    /// Hash of the flavour and properties of the block, `sys:*` fields like children and
+    /// history are left out. Compare it between polls to skip serializing unchanged blocks,
+    /// hashes are the same in every process and build, nested maps are hashed by key order.
+    pub fn content_hash<T>(&self, trx: &T) -> u64
+    where
+        T: ReadTxn,
+    {
+        let content = self.content(trx).into_iter().collect::<BTreeMap<_, _>>();
+
+        let mut hasher = StableHasher::default();
+        hasher.write_field(self.flavor(trx).as_bytes());
+        for (key, value) in content {
+            let mut json = String::new();
+            canonical_json(&value, &mut json);
+            hasher.write_field(key.as_bytes());
+            hasher.write_field(json.as_bytes());
+        }
+        hasher.finish()
+    }
+
+    /// Short plain text of the block for search results: the first `max_chars` characters
+    /// of its text, or title if it has no text, ellipsized with `...` if truncated.
+    /// Blocks without either are named by their flavour, like `[image block]`.
+    pub fn render_preview<T: ReadTxn>(&self, trx: &T, max_chars: usize) -> String {
+        let text = ["text", "title"]
+            .into_iter()
+            .find_map(|key| match self.get(trx, key) {
+                Some(Any::String(text)) => Some(text),
+                _ => None,
+            });
+        match text {
+            Some(text) => match text.char_indices().nth(max_chars) {
+                Some((end, _)) => format!("{}...", &text[..end]),
+                None => text.into(),
+            },
+            None => {
+                let flavor = self.flavor(trx);
+                let name = flavor.rsplit(':').next().unwrap_or_default();
+                format!("[{name} block]")
+            }
+        }
+    }
+
+    /// Compare the properties of the block with a snapshot of it, taken earlier in the
+    /// JSON the block serializes to. Nested values are compared as a whole, with numbers
+    /// compared by value so `1` and `1.0` are the same.
+    pub fn diff<T>(&self, trx: &T, other_json: &JsonValue) -> BlockPropDiff
+    where
+        T: ReadTxn,
+    {
+        let mut previous = other_json
+            .as_object()
+            .map(|snapshot| {
+                snapshot
+                    .iter()
+                    .filter_map(|(key, value)| {
+                        PropertyKey::from_stored(key)
+                            .filter(|key| !key.is_system())
+                            .map(|key| (key.as_str(), value))
+                    })
+                    .collect::<HashMap<_, _>>()
+            })
+            .unwrap_or_default();
+
+        let mut diff = BlockPropDiff::default();
+        for (key, value) in self.content(trx) {
+            let value = serde_json::to_value(&value).unwrap_or_default();
+            match previous.remove(key.as_str()) {
+                None => {
+                    diff.added.insert(key, value);
+                }
+                Some(before) if !json_eq(before, &value) => {
+                    diff.changed.insert(key, (before.clone(), value));
+                }
+                Some(_) => {}
+            }
+        }
+        diff.removed = previous
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value.clone()))
+            .collect();
+
+        diff
+    }
+
     fn set_parent(&self, trx: &mut TransactionMut, block_id: String) {
-        self.block.insert(trx, sys::PARENT, block_id);
+        insert_system_key(trx, &self.block, PropertyKey::new(sys::PARENT), block_id);
     }
 
     pub fn push_children(&self, trx: &mut TransactionMut, block: &Block) {
@@ -418,13 +844,51 @@ impl Block {
     }
 }
 
+// like `==`, but numbers are compared by value
+pub(crate) fn json_eq(a: &JsonValue, b: &JsonValue) -> bool {
+    match (a, b) {
+        (JsonValue::Number(a), JsonValue::Number(b)) => a.as_f64() == b.as_f64(),
+        (JsonValue::Array(a), JsonValue::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| json_eq(a, b))
+        }
+        (JsonValue::Object(a), JsonValue::Object(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).map_or(false, |b| json_eq(a, b)))
+        }
+        _ => a == b,
+    }
+}
+
+fn value_type(value: &Value) -> &'static str {
+    match value {
+        Value::Any(Any::Null | Any::Undefined) => "null",
+        Value::Any(Any::String(_)) => "string",
+        Value::Any(Any::Number(_) | Any::BigInt(_)) => "number",
+        Value::Any(_) => "primitive value",
+        Value::YText(_) => "text",
+        Value::YArray(_) => "array",
+        Value::YMap(_) => "map",
+        _ => "xml or subdoc",
+    }
+}
+
+// leave the keys out of the JSON of a block map that are not properties, see
+// [PropertyKey::from_stored], for the exports of the raw block maps
+pub(crate) fn retain_properties(block: &mut Any) {
+    if let Any::Map(map) = block {
+        map.retain(|key, _| PropertyKey::from_stored(key).is_some());
+    }
+}
+
 impl Serialize for Block {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
         let trx = self.doc.transact();
-        let any = self.block.to_json(&trx);
+        let mut any = self.block.to_json(&trx);
+        retain_properties(&mut any);
         any.serialize(serializer)
     }
 }
@@ -456,6 +920,147 @@ mod tests {
         });
     }
 
+    #[test]
+    fn pinned_block() {
+        let workspace = Workspace::new("test");
+
+        workspace.with_trx(|mut t| {
+            let block = t.create("root", "affine:page");
+            assert!(!block.is_pinned(&t.trx));
+
+            block.set_pinned(&mut t.trx, true);
+            assert!(block.is_pinned(&t.trx));
+        });
+
+        workspace.with_trx(|mut t| {
+            assert!(matches!(
+                t.try_remove("root"),
+                Err(JwstError::PinnedBlock(id)) if id == "root"
+            ));
+            assert!(!t.remove("root"));
+            assert!(workspace.exists(&t.trx, "root"));
+
+            let block = workspace.get(&t.trx, "root").unwrap();
+            block.set_pinned(&mut t.trx, false);
+            assert!(!block.is_pinned(&t.trx));
+            assert!(t.remove("root"));
+        });
+    }
+
+    #[test]
+    fn block_url() {
+        let workspace = Workspace::new("test");
+
+        workspace.with_trx(|mut t| {
+            let block = t.create("embed", "affine:embed");
+            block
+                .set_url(&mut t.trx, "url", " HTTPS://Example.com/a b")
+                .unwrap();
+            assert_eq!(
+                block.get(&t.trx, "url"),
+                Some(Any::String("https://example.com/a%20b".into()))
+            );
+            assert_eq!(
+                block.get_url(&t.trx, "url").map(String::from),
+                Some("https://example.com/a%20b".to_owned())
+            );
+            block
+                .set_url(&mut t.trx, "mail", "mailto:user@example.com")
+                .unwrap();
+
+            for url in ["javascript:alert(1)", "data:text/html,<script></script>"] {
+                assert!(matches!(
+                    block.set_url(&mut t.trx, "url", url),
+                    Err(UrlError::Scheme(_))
+                ));
+            }
+            assert!(matches!(
+                block.set_url(&mut t.trx, "url", "example.com"),
+                Err(UrlError::Invalid(_))
+            ));
+            assert!(block.get_url(&t.trx, "url").is_some());
+            assert!(matches!(
+                block.set_url(&mut t.trx, "sys:url", "https://example.com"),
+                Err(UrlError::Denied(JwstError::SystemKeyDenied(_)))
+            ));
+            assert!(block.get(&t.trx, "sys:url").is_none());
+
+            // stored without validation
+            block.set(&mut t.trx, "url", "javascript:alert(1)");
+            assert!(block.get_url(&t.trx, "url").is_none());
+            assert!(block.get_url(&t.trx, "missing").is_none());
+        });
+    }
+
+    #[test]
+    fn thumbnail() {
+        let workspace = Workspace::new("test");
+
+        workspace.with_trx(|mut t| {
+            let block = t.create("page", "affine:page");
+            assert_eq!(block.thumbnail(&t.trx), None);
+
+            block.set_thumbnail(&mut t.trx, "blob");
+            assert_eq!(block.thumbnail(&t.trx), Some("blob".into()));
+            // not a property of the block
+            assert!(block.content(&t.trx).is_empty());
+        });
+    }
+
+    #[test]
+    fn revision() {
+        use yrs::{updates::decoder::Decode, StateVector, Update};
+
+        let sync = |from: &Workspace, to: &Workspace| {
+            let update = from
+                .doc()
+                .transact()
+                .encode_state_as_update_v1(&StateVector::default());
+            to.doc()
+                .transact_mut()
+                .apply_update(Update::decode_v1(&update).unwrap());
+        };
+
+        let local = Workspace::from_doc(Doc::with_client_id(1), "test");
+        let remote = Workspace::from_doc(Doc::with_client_id(2), "test");
+
+        local.with_trx(|mut t| {
+            let block = t.create("block", "text");
+            assert_eq!(block.revision(&t.trx), 1);
+
+            let child = t.create("child", "text");
+            block.set(&mut t.trx, "title", "hello");
+            block.push_children(&mut t.trx, &child);
+            assert_eq!(block.revision(&t.trx), 3);
+            // reads don't change the revision
+            block.get(&t.trx, "title");
+            assert_eq!(block.revision(&t.trx), 3);
+        });
+        sync(&local, &remote);
+
+        // concurrent edits from the same revision
+        local.with_trx(|mut t| {
+            let block = local.get(&t.trx, "block").unwrap();
+            block.set(&mut t.trx, "title", "local");
+        });
+        remote.with_trx(|mut t| {
+            let block = remote.get(&t.trx, "block").unwrap();
+            block.set(&mut t.trx, "text", "remote");
+        });
+        sync(&local, &remote);
+        sync(&remote, &local);
+
+        for workspace in [&local, &remote] {
+            workspace.with_trx(|t| {
+                let block = workspace.get(&t.trx, "block").unwrap();
+                // the revision moved on, though both edits are seen as revision 4
+                assert_eq!(block.revision(&t.trx), 4);
+                assert_eq!(block.get(&t.trx, "title").unwrap().to_string(), "local");
+                assert_eq!(block.get(&t.trx, "text").unwrap().to_string(), "remote");
+            });
+        }
+    }
+
     #[test]
     fn set_value() {
         let workspace = Workspace::new("test");
@@ -557,6 +1162,24 @@ mod tests {
         });
     }
 
+    #[test]
+    fn update_at() {
+        let workspace = Workspace::new("test");
+
+        workspace.with_trx(|mut t| {
+            let block = t.create("a", "affine:text");
+
+            let created = block.update_at(&t.trx).unwrap();
+            assert!(block.created(&t.trx) <= created);
+
+            block.set(&mut t.trx, "test", 1);
+
+            let updated = block.update_at(&t.trx).unwrap();
+            assert!(created <= updated);
+            assert_eq!(block.updated(&t.trx), updated);
+        });
+    }
+
     #[test]
     fn history() {
         use yrs::Doc;
@@ -635,4 +1258,170 @@ mod tests {
             assert!(false)
         }
     }
+
+    #[test]
+    fn diff() {
+        let workspace = Workspace::new("test");
+        let block = workspace.with_trx(|mut t| {
+            let block = t.create("a", "affine:text");
+            block.set(&mut t.trx, "title", "title");
+            block.set(&mut t.trx, "count", 1.0);
+            block.set(&mut t.trx, "removed", true);
+            block.block.insert(
+                &mut t.trx,
+                "prop:meta",
+                Any::Map(Box::new(HashMap::from([(
+                    "tags".to_owned(),
+                    Any::Array(Box::from([Any::String(Box::from("a"))])),
+                )]))),
+            );
+            block
+        });
+        let snapshot = serde_json::to_value(&block).unwrap();
+
+        workspace.with_trx(|t| assert!(block.diff(&t.trx, &snapshot).is_empty()));
+        // numbers captured as integers are unchanged
+        let mut integers = snapshot.clone();
+        integers["prop:count"] = serde_json::json!(1);
+        workspace.with_trx(|t| assert!(block.diff(&t.trx, &integers).is_empty()));
+
+        workspace.with_trx(|mut t| {
+            block.set(&mut t.trx, "title", "new title");
+            block.set(&mut t.trx, "removed", Any::Null);
+            block.set(&mut t.trx, "added", 2.0);
+            block.block.insert(
+                &mut t.trx,
+                "prop:meta",
+                Any::Map(Box::new(HashMap::from([(
+                    "tags".to_owned(),
+                    Any::Array(Box::from([Any::String(Box::from("b"))])),
+                )]))),
+            );
+        });
+
+        let diff = workspace.with_trx(|t| block.diff(&t.trx, &snapshot));
+        assert_eq!(
+            diff,
+            BlockPropDiff {
+                added: BTreeMap::from([("added".to_owned(), serde_json::json!(2.0))]),
+                removed: BTreeMap::from([("removed".to_owned(), serde_json::json!(true))]),
+                changed: BTreeMap::from([
+                    (
+                        "meta".to_owned(),
+                        (
+                            serde_json::json!({ "tags": ["a"] }),
+                            serde_json::json!({ "tags": ["b"] })
+                        )
+                    ),
+                    (
+                        "title".to_owned(),
+                        (serde_json::json!("title"), serde_json::json!("new title"))
+                    ),
+                ]),
+            }
+        );
+    }
+
+    #[test]
+    fn content_hash() {
+        let workspace = Workspace::new("test");
+        let (a, b, hash) = workspace.with_trx(|mut t| {
+            let a = t.create("a", "affine:paragraph");
+            let b = t.create("b", "affine:paragraph");
+            for block in [&a, &b] {
+                block.set(&mut t.trx, "text", "hello");
+                block.set(&mut t.trx, "count", 1);
+            }
+            let hash = a.content_hash(&t.trx);
+            assert_eq!(hash, b.content_hash(&t.trx));
+            (a, b, hash)
+        });
+
+        // children and history are not part of the content
+        workspace.with_trx(|mut t| {
+            let child = t.create("child", "affine:paragraph");
+            a.push_children(&mut t.trx, &child);
+            assert_eq!(a.content_hash(&t.trx), hash);
+
+            a.set(&mut t.trx, "text", "changed");
+            assert_ne!(a.content_hash(&t.trx), hash);
+            a.set(&mut t.trx, "text", "hello");
+            assert_eq!(a.content_hash(&t.trx), hash);
+
+            let page = t.create("page", "affine:page");
+            page.set(&mut t.trx, "text", "hello");
+            page.set(&mut t.trx, "count", 1);
+            assert_ne!(page.content_hash(&t.trx), b.content_hash(&t.trx));
+
+            // maps are hashed by key order, not by the order of their entries
+            let entries = (0..32)
+                .map(|i| (format!("key{i}"), Any::Number(i as f64)))
+                .collect::<Vec<_>>();
+            let forward = Any::Map(Box::new(entries.iter().cloned().collect()));
+            let backward = Any::Map(Box::new(entries.into_iter().rev().collect()));
+            a.set(&mut t.trx, "nested", forward);
+            b.set(&mut t.trx, "nested", backward);
+            assert_eq!(a.content_hash(&t.trx), b.content_hash(&t.trx));
+        });
+
+        // the hasher isn't seeded
+        let mut hasher = StableHasher::default();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn render_preview() {
+        let workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            let paragraph = t.create("paragraph", "affine:paragraph");
+            paragraph.set(&mut t.trx, "text", "hello world");
+            assert_eq!(paragraph.render_preview(&t.trx, 5), "hello...");
+            assert_eq!(paragraph.render_preview(&t.trx, 11), "hello world");
+            assert_eq!(paragraph.render_preview(&t.trx, 100), "hello world");
+
+            // counted in characters, not bytes
+            let cjk = t.create("cjk", "affine:paragraph");
+            cjk.set(&mut t.trx, "text", "人民日报");
+            assert_eq!(cjk.render_preview(&t.trx, 2), "人民...");
+
+            let page = t.create("page", "affine:page");
+            page.set(&mut t.trx, "title", "title");
+            assert_eq!(page.render_preview(&t.trx, 10), "title");
+
+            let image = t.create("image", "affine:image");
+            image.set(&mut t.trx, "width", 100);
+            assert_eq!(image.render_preview(&t.trx, 10), "[image block]");
+        });
+    }
+
+    #[test]
+    fn property_keys() {
+        assert!(PropertyKey::new(sys::FLAVOR).is_system());
+        assert!(!PropertyKey::new("title").is_system());
+        assert!(!PropertyKey::new("system:title").is_system());
+
+        let workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            let block = t.create("test", "affine:text");
+            block.set(&mut t.trx, "title", "hello");
+            // system properties are read as stored
+            assert_eq!(
+                block.get(&t.trx, sys::FLAVOR),
+                Some(Any::String("affine:text".into()))
+            );
+
+            // written as a regular property before system keys were protected
+            block
+                .block
+                .insert(&mut t.trx, "prop:sys:flavor", "affine:page");
+            assert_eq!(
+                block.content(&t.trx),
+                HashMap::from([("title".to_owned(), Any::String("hello".into()))])
+            );
+            let snapshot = serde_json::to_value(&block.block.to_json(&t.trx)).unwrap();
+            assert!(block.diff(&t.trx, &snapshot).is_empty());
+            assert_eq!(block.flavor(&t.trx), "affine:text");
+        });
+    }
 }
@@ -1,5 +1,8 @@
 /// The sys constants.
 pub mod sys {
+    /// `sys:`, the prefix of the system properties of a block
+    pub const PREFIX: &str = "sys:";
+
     /// `sys:children`
     pub const CHILDREN: &str = "sys:children";
 
@@ -9,9 +12,24 @@ pub mod sys {
     /// `sys:flavor`
     pub const FLAVOR: &str = "sys:flavor";
 
+    /// `sys:pinned`
+    pub const PINNED: &str = "sys:pinned";
+
     /// `sys:parent`
     pub const PARENT: &str = "sys:parent";
 
     /// `sys:version`
     pub const VERSION: &str = "sys:version";
+
+    /// `sys:rev`
+    pub const REVISION: &str = "sys:rev";
+
+    /// `sys:thumbnail`
+    pub const THUMBNAIL: &str = "sys:thumbnail";
+
+    /// `sys:stub`, marks a block exported without its content by `Workspace::encode_subset`
+    pub const STUB: &str = "sys:stub";
+
+    /// `sys:blocked`, a workspace metadata key
+    pub const BLOCKED: &str = "sys:blocked";
 }
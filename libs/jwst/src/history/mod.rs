@@ -1,5 +1,7 @@
+mod property;
 mod raw;
 mod record;
 
+pub use property::{PropertyHistory, PropertyWrite};
 pub use raw::{parse_history, parse_history_client, RawHistory};
 pub use record::{BlockHistory, HistoryOperation};
@@ -0,0 +1,291 @@
+use lib0::{any::Any, error::Error};
+use serde::Serialize;
+use yrs::{
+    block::{Item, ID},
+    types::TypePtr,
+    updates::decoder::Decode,
+    Doc, Map, MapRef, Transact, Update, Value,
+};
+
+/// A change of a block property made by a stored update, see [PropertyHistory].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PropertyWrite {
+    /// Clients that wrote the property in the update, a merged update can hold the
+    /// concurrent writes of several clients. Empty if the update only removed it.
+    pub clients: Vec<u64>,
+    /// `None` if the property was not set.
+    pub old: Option<Any>,
+    pub new: Option<Any>,
+}
+
+// the items written to an entry of a map, as ranges of clocks of a client. Only the first
+// item of an entry names the map it belongs to, the following ones point to the item they
+// replaced, so the entry is followed from update to update
+#[derive(Default)]
+struct EntryItems(Vec<(u64, u32, u32)>);
+
+impl EntryItems {
+    fn contains(&self, id: &ID) -> bool {
+        self.0
+            .iter()
+            .any(|(client, start, end)| id.client == *client && (*start..*end).contains(&id.clock))
+    }
+
+    // track the items of the update written to `key` of the map `in_map` accepts, returns the
+    // clients that wrote them
+    fn track(&mut self, items: &[&Item], key: &str, in_map: impl Fn(&TypePtr) -> bool) -> Vec<u64> {
+        let mut clients = vec![];
+        // an item can point to one that comes later in the update
+        loop {
+            let found = clients.len();
+            for item in items {
+                if item.parent_sub.as_deref() != Some(key) || self.contains(&item.id) {
+                    continue;
+                }
+                let replaced = [item.origin, item.right_origin]
+                    .iter()
+                    .flatten()
+                    .any(|id| self.contains(id));
+                if in_map(&item.parent) || replaced {
+                    self.0
+                        .push((item.id.client, item.id.clock, item.id.clock + item.len()));
+                    clients.push(item.id.client);
+                }
+            }
+            if clients.len() == found {
+                break;
+            }
+        }
+        clients
+    }
+}
+
+/// Finds out who changed a property of a block by replaying the stored updates of a
+/// workspace one after another.
+///
+/// Only changes of the value are reported, so the losing one of two concurrent writes
+/// stored as separate updates is left out.
+pub struct PropertyHistory {
+    doc: Doc,
+    blocks: MapRef,
+    block: String,
+    key: String,
+    current: Option<Any>,
+    // the items of the block in the blocks map and of the property in the block
+    block_items: EntryItems,
+    key_items: EntryItems,
+}
+
+impl PropertyHistory {
+    /// `key` is the name of the property, without the `prop:` prefix.
+    pub fn new<B: Into<String>, K: AsRef<str>>(block: B, key: K) -> Self {
+        let doc = Doc::new();
+        let blocks = doc.get_or_insert_map("blocks");
+        Self {
+            doc,
+            blocks,
+            block: block.into(),
+            key: format!("prop:{}", key.as_ref()),
+            current: None,
+            block_items: EntryItems::default(),
+            key_items: EntryItems::default(),
+        }
+    }
+
+    /// Apply the next stored update, returns the change of the property it made.
+    pub fn apply(&mut self, update: &[u8]) -> Result<Option<PropertyWrite>, Error> {
+        let update = Update::decode_v1(update)?;
+        // the block the property belongs to may be part of an earlier update
+        let items = update.as_items();
+        self.block_items.track(
+            &items,
+            &self.block,
+            |parent| matches!(parent, TypePtr::Named(name) if name.as_ref() == "blocks"),
+        );
+        let block_items = &self.block_items;
+        let mut clients = self.key_items.track(
+            &items,
+            &self.key,
+            |parent| matches!(parent, TypePtr::ID(id) if block_items.contains(id)),
+        );
+        clients.sort_unstable();
+        clients.dedup();
+        drop(items);
+
+        self.doc.transact_mut().apply_update(update);
+
+        let value = self.value();
+        if value == self.current {
+            return Ok(None);
+        }
+        let old = std::mem::replace(&mut self.current, value.clone());
+        Ok(Some(PropertyWrite {
+            clients,
+            old,
+            new: value,
+        }))
+    }
+
+    fn value(&self) -> Option<Any> {
+        let trx = self.doc.transact();
+        let block = self.blocks.get(&trx, &self.block)?.to_ymap()?;
+        match block.get(&trx, &self.key)? {
+            Value::Any(any) => Some(any),
+            // shared types, e.g. a text, are reported with their content
+            value => Some(Any::String(value.to_string(&trx).into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Workspace, WorkspaceTransaction};
+    use yrs::{merge_updates_v1, ReadTxn};
+
+    fn writer(client: u64) -> Workspace {
+        Workspace::from_doc(Doc::with_client_id(client), "test")
+    }
+
+    // the update written by the transaction, as it is stored
+    fn record(workspace: &Workspace, f: impl FnOnce(&mut WorkspaceTransaction)) -> Vec<u8> {
+        let doc = workspace.doc();
+        let before = doc.transact().state_vector();
+        workspace.with_trx(|mut t| f(&mut t));
+        let trx = doc.transact();
+        trx.encode_state_as_update_v1(&before)
+    }
+
+    fn write(workspace: &Workspace, title: &str) -> Vec<u8> {
+        record(workspace, |t| {
+            let block = workspace
+                .get(&t.trx, "page")
+                .unwrap_or_else(|| t.create("page", "affine:page"));
+            block.set(&mut t.trx, "title", title);
+        })
+    }
+
+    fn sync(from: &Workspace, to: &Workspace) {
+        let to = to.doc();
+        let update = from
+            .doc()
+            .transact()
+            .encode_state_as_update_v1(&to.transact().state_vector());
+        to.transact_mut()
+            .apply_update(Update::decode_v1(&update).unwrap());
+    }
+
+    fn title(workspace: &Workspace) -> Option<Any> {
+        workspace.with_trx(|t| workspace.get(&t.trx, "page")?.get(&t.trx, "title"))
+    }
+
+    #[test]
+    fn sequential_writers() {
+        let (a, b) = (writer(1), writer(2));
+
+        let first = write(&a, "draft");
+        sync(&a, &b);
+        let second = write(&b, "final");
+        sync(&b, &a);
+        let unrelated = record(&a, |t| {
+            let other = t.create("other", "affine:paragraph");
+            other.set(&mut t.trx, "title", "other");
+        });
+        let third = write(&a, "published");
+
+        let mut history = PropertyHistory::new("page", "title");
+        let writes = [first, second, unrelated, third]
+            .iter()
+            .map(|update| history.apply(update).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            writes,
+            vec![
+                Some(PropertyWrite {
+                    clients: vec![1],
+                    old: None,
+                    new: Some(Any::String("draft".into())),
+                }),
+                Some(PropertyWrite {
+                    clients: vec![2],
+                    old: Some(Any::String("draft".into())),
+                    new: Some(Any::String("final".into())),
+                }),
+                None,
+                Some(PropertyWrite {
+                    clients: vec![1],
+                    old: Some(Any::String("final".into())),
+                    new: Some(Any::String("published".into())),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn other_blocks() {
+        let (a, b) = (writer(1), writer(2));
+        let first = write(&a, "draft");
+        sync(&a, &b);
+
+        // stored as one update, only the writer of the title of the page is reported
+        let other = record(&b, |t| {
+            let other = t.create("other", "affine:paragraph");
+            other.set(&mut t.trx, "title", "other");
+        });
+        let renamed = write(&a, "final");
+        let update = merge_updates_v1(&[renamed.as_slice(), other.as_slice()]).unwrap();
+
+        let mut history = PropertyHistory::new("page", "title");
+        history.apply(&first).unwrap();
+        assert_eq!(
+            history.apply(&update).unwrap(),
+            Some(PropertyWrite {
+                clients: vec![1],
+                old: Some(Any::String("draft".into())),
+                new: Some(Any::String("final".into())),
+            })
+        );
+    }
+
+    #[test]
+    fn concurrent_writers() {
+        let (a, b) = (writer(1), writer(2));
+        let first = write(&a, "draft");
+        sync(&a, &b);
+
+        // both rename the page before seeing the other rename
+        let from_a = write(&a, "from a");
+        let from_b = write(&b, "from b");
+        sync(&a, &b);
+        sync(&b, &a);
+        let merged = title(&a);
+        assert_eq!(merged, title(&b));
+
+        // stored one after another, the value ends up as merged
+        let mut history = PropertyHistory::new("page", "title");
+        history.apply(&first).unwrap();
+        let writes = [&from_a, &from_b]
+            .iter()
+            .filter_map(|update| history.apply(update).unwrap())
+            .collect::<Vec<_>>();
+        assert!(!writes.is_empty());
+        assert_eq!(writes.last().unwrap().new, merged);
+        for write in &writes {
+            assert!(write.clients == vec![1] || write.clients == vec![2]);
+        }
+
+        // stored as one merged update, both writers are reported
+        let mut history = PropertyHistory::new("page", "title");
+        history.apply(&first).unwrap();
+        let update = merge_updates_v1(&[from_a.as_slice(), from_b.as_slice()]).unwrap();
+        assert_eq!(
+            history.apply(&update).unwrap(),
+            Some(PropertyWrite {
+                clients: vec![1, 2],
+                old: Some(Any::String("draft".into())),
+                new: merged,
+            })
+        );
+    }
+}
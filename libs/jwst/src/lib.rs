@@ -6,13 +6,34 @@ mod workspaces;
 
 pub mod constants;
 
-pub use block::Block;
+pub use block::{Block, BlockPropDiff, PropertyKey, UrlError, ALLOWED_URL_SCHEMES};
 pub use history::{
-    parse_history, parse_history_client, BlockHistory, HistoryOperation, RawHistory,
+    parse_history, parse_history_client, BlockHistory, HistoryOperation, PropertyHistory,
+    PropertyWrite, RawHistory,
 };
 pub use log::{debug, error, info, trace, warn};
 pub use types::{BlobMetadata, BlobStorage, DocStorage, JwstError, JwstResult};
 pub use utils::sync_encode_update;
-pub use workspaces::{MapSubscription, Workspace, WorkspaceTransaction};
+pub use workspaces::{
+    ApplyError, BlobReference, BlockChange, BlockChangeKind, BlockChanges, BlockLink, BlockLock,
+    BlockRef, ChangesSubscription, ChildrenSplice, CompactError, ConflictResolver, ContentStats,
+    ExportError, GcError, InsertError, JsonExport, LinkError, MapSubscription, MergeError,
+    MetadataChangeEvent, MetadataError, MetadataSubscription, ObserverPanicPolicy, PatchError,
+    PatchFailure, PatchOperation, PermissionError, PluginError, SelectionError, SerializeOptions,
+    SyncPeer, SyncValidationError, SystemKeyPolicy, TimestampRepair, UserTransaction, Workspace,
+    WorkspaceBuilder, WorkspaceChanges, WorkspaceClock, WorkspaceMetadata, WorkspacePermission,
+    WorkspacePlugins, WorkspaceStats, WorkspaceTransaction, WorkspaceUser,
+    DEFAULT_BLOB_PROPERTY_KEYS, DEFAULT_LINK_PROPERTY_KEYS, DEFAULT_MAX_BLOCK_DEPTH,
+    DEFAULT_MAX_MESSAGE_BYTES, MAX_CLOCK_SKEW,
+};
+#[cfg(feature = "workspace-export-sqlite")]
+pub use workspaces::{ImportError, SQLITE_SCHEMA_VERSION};
 #[cfg(feature = "workspace-search")]
-pub use workspaces::{SearchResult, SearchResults};
+pub use workspaces::{SearchFilter, SearchOptions, SearchResult, SearchResults};
+
+/// Traits to implement workspace plugins, see [Workspace::register_plugin].
+/// The interface is experimental and may change in any release.
+#[cfg(feature = "experimental-plugins")]
+pub mod plugins {
+    pub use crate::workspaces::{PluginImpl, PluginRegister};
+}
@@ -1,4 +1,4 @@
-use super::Workspace;
+use super::{PermissionError, Workspace};
 use async_trait::async_trait;
 use bytes::Bytes;
 use chrono::NaiveDateTime;
@@ -19,6 +19,22 @@ pub enum JwstError {
     WorkspaceNotInitialized(String),
     #[error("workspace {0} not found")]
     WorkspaceNotFound(String),
+    #[error("block {0} not found")]
+    BlockNotFound(String),
+    #[error("block {0} is pinned")]
+    PinnedBlock(String),
+    #[error("block {id} would be nested deeper than {max} levels")]
+    MaxDepthExceeded { id: String, max: usize },
+    #[error("block {id} is malformed: expected {expected}, found {found}")]
+    MalformedBlock {
+        id: String,
+        expected: &'static str,
+        found: String,
+    },
+    #[error(transparent)]
+    Permission(#[from] PermissionError),
+    #[error("system property {0} can only be written with system access")]
+    SystemKeyDenied(String),
 }
 
 pub type JwstResult<T> = Result<T, JwstError>;
@@ -52,5 +68,7 @@ pub trait BlobStorage {
         stream: impl Stream<Item = Bytes> + Send,
     ) -> JwstResult<String>;
     async fn delete_blob(&self, workspace: Option<String>, id: String) -> JwstResult<()>;
+    /// Ids of all blobs stored for the workspace.
+    async fn list_blobs(&self, workspace: Option<String>) -> JwstResult<Vec<String>>;
     async fn delete_workspace(&self, workspace_id: String) -> JwstResult<()>;
 }
@@ -0,0 +1,241 @@
+use super::*;
+use crate::BlobStorage;
+use lib0::any::Any;
+use serde::Serialize;
+use std::collections::HashSet;
+use thiserror::Error;
+use utoipa::ToSchema;
+use yrs::{types::ToJson, Map, ReadTxn, Transact};
+
+/// Block properties AFFiNE uses to store the hash of a blob, e.g. images.
+pub const DEFAULT_BLOB_PROPERTY_KEYS: &[&str] = &["sourceId"];
+
+#[derive(Debug, Error)]
+pub enum GcError {
+    #[error("failed to list the blobs of workspace {0}")]
+    List(String, #[source] JwstError),
+    #[error("failed to delete blob {0}")]
+    Delete(String, #[source] JwstError),
+}
+
+// strings anywhere in a value, blob ids can be nested in lists of attachments
+fn collect_strings(value: &Any, strings: &mut HashSet<String>) {
+    match value {
+        Any::String(string) => {
+            strings.insert(string.to_string());
+        }
+        Any::Array(values) => values.iter().for_each(|v| collect_strings(v, strings)),
+        Any::Map(values) => values.values().for_each(|v| collect_strings(v, strings)),
+        _ => {}
+    }
+}
+
+/// A block property that references a blob, see [Workspace::find_blob_references].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, ToSchema)]
+pub struct BlobReference {
+    pub block_id: String,
+    pub property: String,
+    pub hash: String,
+}
+
+impl Workspace {
+    /// Collect blob hashes stored in the given block properties,
+    /// use [DEFAULT_BLOB_PROPERTY_KEYS] for the properties AFFiNE writes.
+    pub fn find_blob_references<T, K>(&self, trx: &T, property_keys: &[K]) -> Vec<BlobReference>
+    where
+        T: ReadTxn,
+        K: AsRef<str>,
+    {
+        let mut references = self.blocks(trx, |blocks| {
+            blocks
+                .flat_map(|block| {
+                    property_keys
+                        .iter()
+                        .filter_map(|key| match block.get(trx, key.as_ref()) {
+                            Some(Any::String(hash)) if !hash.is_empty() => Some(BlobReference {
+                                block_id: block.id(),
+                                property: key.as_ref().to_owned(),
+                                hash: hash.to_string(),
+                            }),
+                            _ => None,
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        });
+        references.sort();
+        references
+    }
+
+    // every string a blob id could be stored in, whatever the property: block
+    // properties, block thumbnails and the workspace metadata, e.g. its avatar
+    fn referenced_blobs<T: ReadTxn>(&self, trx: &T) -> HashSet<String> {
+        let mut referenced = HashSet::new();
+        self.blocks(trx, |blocks| {
+            for block in blocks {
+                for value in block.content(trx).values() {
+                    collect_strings(value, &mut referenced);
+                }
+                referenced.extend(block.thumbnail(trx));
+            }
+        });
+        for (_, value) in self.metadata.iter(trx) {
+            collect_strings(&value.to_json(trx), &mut referenced);
+        }
+        referenced
+    }
+
+    /// Delete the stored blobs of this workspace that no block references anymore,
+    /// e.g. after [WorkspaceTransaction::bulk_delete]. Returns the number of deleted blobs.
+    ///
+    /// Blobs uploaded but not referenced yet are deleted too, so this should not run
+    /// while clients are adding blobs.
+    pub async fn garbage_collect_orphaned_blobs<B: BlobStorage>(
+        &self,
+        blobs: &B,
+    ) -> Result<usize, GcError> {
+        let referenced = {
+            let doc = self.doc();
+            let trx = doc.transact();
+            self.referenced_blobs(&trx)
+        };
+
+        let stored = blobs
+            .list_blobs(Some(self.id()))
+            .await
+            .map_err(|e| GcError::List(self.id(), e))?;
+        let mut deleted = 0;
+        for hash in stored {
+            if referenced.contains(&hash) {
+                continue;
+            }
+            trace!("delete orphaned blob {} of {}", hash, self.id());
+            blobs
+                .delete_blob(Some(self.id()), hash.clone())
+                .await
+                .map_err(|e| GcError::Delete(hash, e))?;
+            deleted += 1;
+        }
+
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{BlobMetadata, JwstResult};
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use futures::{executor::block_on, stream, Stream, StreamExt};
+    use std::sync::Mutex;
+
+    // ids of the stored blobs, a blob is stored with its content as id
+    #[derive(Default)]
+    struct MemoryBlobs(Mutex<HashSet<String>>);
+
+    #[async_trait]
+    impl BlobStorage for MemoryBlobs {
+        type Read = stream::Empty<Bytes>;
+
+        async fn get_blob(&self, _: Option<String>, _: String) -> JwstResult<Self::Read> {
+            Ok(stream::empty())
+        }
+        async fn get_metadata(&self, _: Option<String>, id: String) -> JwstResult<BlobMetadata> {
+            Err(JwstError::BlockNotFound(id))
+        }
+        async fn put_blob(
+            &self,
+            _: Option<String>,
+            stream: impl Stream<Item = Bytes> + Send,
+        ) -> JwstResult<String> {
+            let content = stream.collect::<Vec<_>>().await.concat();
+            let id = String::from_utf8_lossy(&content).into_owned();
+            self.0.lock().unwrap().insert(id.clone());
+            Ok(id)
+        }
+        async fn delete_blob(&self, _: Option<String>, id: String) -> JwstResult<()> {
+            self.0.lock().unwrap().remove(&id);
+            Ok(())
+        }
+        async fn list_blobs(&self, _: Option<String>) -> JwstResult<Vec<String>> {
+            Ok(self.0.lock().unwrap().iter().cloned().collect())
+        }
+        async fn delete_workspace(&self, _: String) -> JwstResult<()> {
+            self.0.lock().unwrap().clear();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn find_blob_references() {
+        let workspace = Workspace::new("blob_refs");
+
+        workspace.with_trx(|mut t| {
+            let page = t.create("page", "affine:page");
+            let image = t.create("image", "affine:embed");
+            image.set(&mut t.trx, "sourceId", "hash1");
+            image.set(&mut t.trx, "cover", "hash2");
+            let empty = t.create("empty", "affine:embed");
+            empty.set(&mut t.trx, "sourceId", "");
+            page.push_children(&mut t.trx, &image);
+            page.push_children(&mut t.trx, &empty);
+        });
+
+        workspace.with_trx(|t| {
+            assert_eq!(
+                workspace.find_blob_references(&t.trx, DEFAULT_BLOB_PROPERTY_KEYS),
+                vec![BlobReference {
+                    block_id: "image".into(),
+                    property: "sourceId".into(),
+                    hash: "hash1".into(),
+                }]
+            );
+            assert_eq!(
+                workspace
+                    .find_blob_references(&t.trx, &["cover", "sourceId"])
+                    .into_iter()
+                    .map(|r| (r.property, r.hash))
+                    .collect::<Vec<_>>(),
+                vec![
+                    ("cover".to_owned(), "hash2".to_owned()),
+                    ("sourceId".to_owned(), "hash1".to_owned())
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn garbage_collect_orphaned_blobs() {
+        let workspace = Workspace::new("blob_gc");
+        workspace.with_trx(|mut t| {
+            let image = t.create("image", "affine:embed");
+            image.set(&mut t.trx, "sourceId", "image");
+            let page = t.create("page", "affine:page");
+            page.set_thumbnail(&mut t.trx, "thumbnail");
+            let deleted = t.create("deleted", "affine:embed");
+            deleted.set(&mut t.trx, "sourceId", "deleted");
+            t.set_metadata("avatar", "avatar").unwrap();
+        });
+        workspace.with_trx(|mut t| t.remove("deleted"));
+
+        let blobs = MemoryBlobs::default();
+        for hash in ["image", "thumbnail", "avatar", "deleted", "unknown"] {
+            let stored = block_on(blobs.put_blob(None, stream::iter([Bytes::from(hash)])));
+            assert_eq!(stored.unwrap(), hash);
+        }
+
+        assert_eq!(
+            block_on(workspace.garbage_collect_orphaned_blobs(&blobs)).unwrap(),
+            2
+        );
+        assert_eq!(
+            *blobs.0.lock().unwrap(),
+            HashSet::from(["image".into(), "thumbnail".into(), "avatar".into()])
+        );
+        assert_eq!(
+            block_on(workspace.garbage_collect_orphaned_blobs(&blobs)).unwrap(),
+            0
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use super::{plugins::WorkspacePlugins, *};
+use yrs::Doc;
+
+/// Build a [Workspace] with another set of plugins than [Workspace::from_doc],
+/// e.g. without the search index or with its indexes kept on disk.
+pub struct WorkspaceBuilder {
+    id: String,
+    doc: Option<Doc>,
+    plugins: WorkspacePlugins,
+}
+
+impl WorkspaceBuilder {
+    pub fn new<S: AsRef<str>>(id: S) -> Self {
+        Self {
+            id: id.as_ref().to_owned(),
+            doc: None,
+            plugins: WorkspacePlugins::default(),
+        }
+    }
+
+    /// Load the workspace from an existing doc instead of an empty one.
+    pub fn doc(self, doc: Doc) -> Self {
+        Self {
+            doc: Some(doc),
+            ..self
+        }
+    }
+
+    /// Plugins set up for the workspace and each of its clones, the default ones if not set.
+    pub fn plugins(self, plugins: WorkspacePlugins) -> Self {
+        Self { plugins, ..self }
+    }
+
+    pub fn build(self) -> Workspace {
+        Workspace::with_plugins(self.doc.unwrap_or_default(), self.id, self.plugins)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{plugins::PluginRegister, *};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[derive(Clone, Default)]
+    struct CountingRegister {
+        setups: Arc<AtomicUsize>,
+    }
+
+    struct CountingPlugin;
+
+    impl plugins::PluginImpl for CountingPlugin {}
+
+    impl PluginRegister for CountingRegister {
+        type Plugin = CountingPlugin;
+
+        fn setup(self, _ws: &mut Workspace) -> Result<CountingPlugin, Box<dyn std::error::Error>> {
+            self.setups.fetch_add(1, Ordering::SeqCst);
+            Ok(CountingPlugin)
+        }
+    }
+
+    fn linked_workspace(builder: WorkspaceBuilder) -> Workspace {
+        let workspace = builder.build();
+        workspace.with_trx(|mut t| {
+            t.create("a", "affine:page");
+            let x = t.create("x", "affine:embed");
+            x.set(&mut t.trx, "pageId", "a");
+        });
+        workspace
+    }
+
+    #[test]
+    fn default_plugins() {
+        let workspace = linked_workspace(WorkspaceBuilder::new("test"));
+        workspace.with_trx(|t| assert_eq!(workspace.backlinks(&t.trx, "a").len(), 1));
+        #[cfg(feature = "workspace-search")]
+        assert!(workspace.search("page").is_ok());
+    }
+
+    #[test]
+    fn without_plugins() {
+        let workspace =
+            linked_workspace(WorkspaceBuilder::new("test").plugins(WorkspacePlugins::none()));
+        workspace.with_trx(|t| assert!(workspace.backlinks(&t.trx, "a").is_empty()));
+        #[cfg(feature = "workspace-search")]
+        assert!(workspace.search("page").is_err());
+
+        let searchless = WorkspaceBuilder::new("test")
+            .plugins(WorkspacePlugins::default().search(false))
+            .build();
+        #[cfg(feature = "workspace-search")]
+        assert!(searchless.search("page").is_err());
+        assert!(searchless
+            .with_plugin::<plugins::BacklinksPluginImpl, _>(|_| ())
+            .is_some());
+    }
+
+    #[test]
+    fn custom_plugins() {
+        let register = CountingRegister::default();
+        let workspace = WorkspaceBuilder::new("test")
+            .plugins(WorkspacePlugins::none().register(register.clone()))
+            .build();
+        assert!(workspace.with_plugin::<CountingPlugin, _>(|_| ()).is_some());
+        assert_eq!(register.setups.load(Ordering::SeqCst), 1);
+
+        // clones share the plugins of the workspace
+        let clone = workspace.clone();
+        assert!(clone.with_plugin::<CountingPlugin, _>(|_| ()).is_some());
+        assert_eq!(register.setups.load(Ordering::SeqCst), 1);
+
+        // registered later, for the workspace and all of its clones
+        let mut workspace = Workspace::new("test");
+        let clone = workspace.clone();
+        let other = CountingRegister::default();
+        workspace.register_plugin(other.clone()).unwrap();
+        assert!(workspace.with_plugin::<CountingPlugin, _>(|_| ()).is_some());
+        assert!(clone.with_plugin::<CountingPlugin, _>(|_| ()).is_some());
+        assert!(workspace
+            .clone()
+            .with_plugin::<CountingPlugin, _>(|_| ())
+            .is_some());
+        assert_eq!(other.setups.load(Ordering::SeqCst), 1);
+    }
+}
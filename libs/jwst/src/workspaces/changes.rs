@@ -0,0 +1,565 @@
+use super::*;
+use crate::constants::sys;
+use serde::Serialize;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::Arc,
+};
+use yrs::{
+    types::{
+        array::ArrayEvent, map::MapEvent, Change, EntryChange, Event, Events, Path, PathSegment,
+    },
+    DeepObservable, Map, ReadTxn, Subscription, Transact, TransactionMut, Value,
+};
+
+pub type ChangesSubscription = Subscription<Arc<dyn Fn(&TransactionMut, &Events)>>;
+
+/// Children inserted and removed at a position of the children list.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct ChildrenSplice {
+    /// Position of the splice in the updated children list.
+    pub index: u32,
+    pub inserted: Vec<String>,
+    /// Number of children removed at `index`.
+    pub removed: u32,
+}
+
+/// What changed in a block during a transaction.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct BlockChanges {
+    /// Properties that were set or removed, `sys:children` is reported in `children` instead
+    /// and `sys:rev` is left out.
+    pub keys: BTreeSet<String>,
+    pub children: Vec<ChildrenSplice>,
+}
+
+/// Blocks changed by a committed transaction.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct WorkspaceChanges {
+    pub added: BTreeSet<String>,
+    pub removed: BTreeSet<String>,
+    pub updated: BTreeMap<String, BlockChanges>,
+}
+
+/// How a block was changed, see [BlockChange].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockChangeKind {
+    Added,
+    Removed,
+    Updated(BlockChanges),
+}
+
+/// A block changed by a committed transaction, along with its flavor.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BlockChange {
+    pub id: String,
+    pub flavor: String,
+    pub kind: BlockChangeKind,
+}
+
+fn path_keys(path: Path) -> Vec<String> {
+    path.into_iter()
+        .map(|segment| match segment {
+            PathSegment::Key(key) => key.to_string(),
+            PathSegment::Index(index) => index.to_string(),
+        })
+        .collect()
+}
+
+impl WorkspaceChanges {
+    /// Collect the changes from the deep events of the `blocks` map.
+    pub fn from_events(trx: &TransactionMut, events: &Events) -> Self {
+        let mut changes = Self::default();
+
+        for event in events.iter() {
+            match event {
+                Event::Map(event) => changes.map_event(trx, event),
+                Event::Array(event) => changes.array_event(trx, event),
+                Event::Text(event) => changes.nested_event(path_keys(event.path())),
+                _ => {}
+            }
+        }
+
+        // content of new blocks is not reported separately
+        let Self { added, updated, .. } = &mut changes;
+        updated.retain(|id, _| !added.contains(id));
+
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.updated.is_empty()
+    }
+
+    fn block(&mut self, id: String) -> &mut BlockChanges {
+        self.updated.entry(id).or_default()
+    }
+
+    fn map_event(&mut self, trx: &TransactionMut, event: &MapEvent) {
+        let path = path_keys(event.path());
+        match path.as_slice() {
+            // the blocks map itself
+            [] => {
+                for (id, change) in event.keys(trx) {
+                    match change {
+                        EntryChange::Inserted(_) => {
+                            self.added.insert(id.to_string());
+                        }
+                        EntryChange::Removed(_) => {
+                            self.removed.insert(id.to_string());
+                        }
+                        EntryChange::Updated(_, _) => {
+                            // replaced by a new map
+                            self.removed.insert(id.to_string());
+                            self.added.insert(id.to_string());
+                        }
+                    }
+                }
+            }
+            [block] => {
+                let keys = event
+                    .keys(trx)
+                    .keys()
+                    .map(|key| key.to_string())
+                    .filter(|key| key != sys::CHILDREN && key != sys::REVISION)
+                    .collect::<Vec<_>>();
+                self.block(block.clone()).keys.extend(keys);
+            }
+            _ => self.nested_event(path),
+        }
+    }
+
+    fn array_event(&mut self, trx: &TransactionMut, event: &ArrayEvent) {
+        let path = path_keys(event.path());
+        let [block, key] = path.as_slice() else {
+            return self.nested_event(path);
+        };
+        if key != sys::CHILDREN {
+            return self.nested_event(path);
+        }
+
+        let mut index = 0;
+        let mut splices: Vec<ChildrenSplice> = vec![];
+        for change in event.delta(trx) {
+            if let Change::Retain(len) = change {
+                index += len;
+                continue;
+            }
+
+            // changes following each other directly belong to the same splice
+            let follows = matches!(
+                splices.last(),
+                Some(last) if last.index + last.inserted.len() as u32 == index
+            );
+            if !follows {
+                splices.push(ChildrenSplice {
+                    index,
+                    ..Default::default()
+                });
+            }
+            let splice = splices.last_mut().unwrap();
+
+            match change {
+                Change::Added(values) => {
+                    splice
+                        .inserted
+                        .extend(values.iter().map(|value| value.to_string(trx)));
+                    index += values.len() as u32;
+                }
+                Change::Removed(len) => splice.removed += len,
+                Change::Retain(_) => {}
+            }
+        }
+        self.block(block.clone()).children.extend(splices);
+    }
+
+    // changes inside a property holding a shared type, e.g. the text of a block
+    fn nested_event(&mut self, path: Vec<String>) {
+        if let [block, key, ..] = path.as_slice() {
+            self.block(block.clone()).keys.insert(key.clone());
+        }
+    }
+}
+
+fn block_flavor<T: ReadTxn>(trx: &T, block: Value) -> Option<String> {
+    let block = block.to_ymap()?;
+    block
+        .get(trx, sys::FLAVOR)
+        .map(|flavor| flavor.to_string(trx))
+}
+
+impl Workspace {
+    /// Observe the blocks changed by every committed transaction.
+    pub fn observe_changes(
+        &mut self,
+        f: impl Fn(&TransactionMut, &WorkspaceChanges) + 'static,
+    ) -> ChangesSubscription {
+        self.blocks.observe_deep(move |trx, events| {
+            let changes = WorkspaceChanges::from_events(trx, events);
+            if !changes.is_empty() {
+                f(trx, &changes)
+            }
+        })
+    }
+
+    /// Observe the changes of the blocks with one of the given flavors. `f` is only called
+    /// if a transaction changed such a block and gets just the changes of these blocks,
+    /// the removed ones first.
+    ///
+    /// Removed blocks can't be read anymore, so the flavor of the matching blocks is kept
+    /// by the subscription. A block whose flavor is changed to one of the given flavors is
+    /// reported as added, one whose flavor is changed to another flavor as removed.
+    pub fn observe_filtered(
+        &mut self,
+        flavors: HashSet<String>,
+        f: impl Fn(&[BlockChange]) + 'static,
+    ) -> ChangesSubscription {
+        let blocks = self.blocks.clone();
+        let matching = {
+            let doc = self.doc();
+            let trx = doc.transact();
+            let matching = blocks
+                .iter(&trx)
+                .filter_map(|(id, block)| {
+                    let flavor = block_flavor(&trx, block)?;
+                    flavors.contains(&flavor).then(|| (id.to_owned(), flavor))
+                })
+                .collect::<HashMap<_, _>>();
+            RefCell::new(matching)
+        };
+
+        self.observe_changes(move |trx, changes| {
+            let mut matching = matching.borrow_mut();
+            let (mut removed, mut added, mut updated) = (vec![], vec![], vec![]);
+            let change = |id: &String, flavor: &String, kind| BlockChange {
+                id: id.clone(),
+                flavor: flavor.clone(),
+                kind,
+            };
+
+            for id in &changes.removed {
+                if let Some(flavor) = matching.remove(id) {
+                    removed.push(change(id, &flavor, BlockChangeKind::Removed));
+                }
+            }
+            for id in &changes.added {
+                let Some(flavor) = blocks.get(trx, id).and_then(|b| block_flavor(trx, b)) else {
+                    continue;
+                };
+                if flavors.contains(&flavor) {
+                    added.push(change(id, &flavor, BlockChangeKind::Added));
+                    matching.insert(id.clone(), flavor);
+                }
+            }
+            for (id, block) in &changes.updated {
+                if block.keys.contains(sys::FLAVOR) {
+                    let flavor = blocks.get(trx, id).and_then(|b| block_flavor(trx, b));
+                    match (matching.contains_key(id), flavor) {
+                        (true, Some(flavor)) if flavors.contains(&flavor) => {
+                            matching.insert(id.clone(), flavor);
+                        }
+                        (true, _) => {
+                            let flavor = matching.remove(id).unwrap_or_default();
+                            removed.push(change(id, &flavor, BlockChangeKind::Removed));
+                            continue;
+                        }
+                        (false, Some(flavor)) if flavors.contains(&flavor) => {
+                            added.push(change(id, &flavor, BlockChangeKind::Added));
+                            matching.insert(id.clone(), flavor);
+                            continue;
+                        }
+                        (false, _) => continue,
+                    }
+                }
+                if let Some(flavor) = matching.get(id) {
+                    updated.push(change(id, flavor, BlockChangeKind::Updated(block.clone())));
+                }
+            }
+
+            let filtered = [removed, added, updated].concat();
+            if !filtered.is_empty() {
+                f(&filtered)
+            }
+        })
+    }
+
+    /// Observe the blocks added by every committed transaction, in id order. Blocks
+    /// can only be read through a transaction, so the one that added them is passed along.
+    ///
+    /// The subscription keeps the doc alive until it is dropped.
+    pub fn observe_block_created(
+        &mut self,
+        f: impl Fn(&TransactionMut, &Block) + 'static,
+    ) -> ChangesSubscription {
+        let doc = self.doc();
+        let client_id = self.client_id();
+        let (blocks, updated) = (self.blocks.clone(), self.updated.clone());
+        let (system_keys, clock) = (self.system_keys.clone(), self.clock.clone());
+        self.observe_changes(move |trx, changes| {
+            for id in &changes.added {
+                let Some(block) = blocks.get(trx, id) else {
+                    continue;
+                };
+                match Block::from_raw_parts(
+                    trx,
+                    id.clone(),
+                    &doc,
+                    block,
+                    updated.get(trx, id),
+                    client_id,
+                ) {
+                    Ok(block) => f(trx, &block.with_settings(&system_keys, &clock)),
+                    Err(e) => warn!("skip created block: {}", e),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn record(
+        workspace: &mut Workspace,
+    ) -> (Arc<Mutex<Vec<WorkspaceChanges>>>, ChangesSubscription) {
+        let changes = Arc::new(Mutex::new(vec![]));
+        let sub = workspace.observe_changes({
+            let changes = changes.clone();
+            move |_, c| changes.lock().unwrap().push(c.clone())
+        });
+        (changes, sub)
+    }
+
+    #[test]
+    fn changed_keys() {
+        let mut workspace = Workspace::new("test");
+        let (changes, _sub) = record(&mut workspace);
+
+        workspace.with_trx(|mut t| {
+            let block = t.create("block", "text");
+            block.set(&mut t.trx, "title", "hello");
+        });
+        assert_eq!(
+            changes.lock().unwrap().pop(),
+            Some(WorkspaceChanges {
+                added: BTreeSet::from(["block".into()]),
+                ..Default::default()
+            })
+        );
+
+        // property set
+        workspace.with_trx(|mut t| {
+            let block = workspace.get(&t.trx, "block").unwrap();
+            block.set(&mut t.trx, "title", "world");
+            block.set(&mut t.trx, "text", "content");
+        });
+        let updated = changes.lock().unwrap().pop().unwrap().updated;
+        assert_eq!(
+            updated["block"].keys,
+            BTreeSet::from(["prop:title".into(), "prop:text".into()])
+        );
+        assert!(updated["block"].children.is_empty());
+
+        // property delete
+        workspace.with_trx(|mut t| {
+            let block = workspace.blocks.get(&t.trx, "block").unwrap();
+            block.to_ymap().unwrap().remove(&mut t.trx, "prop:text");
+        });
+        let updated = changes.lock().unwrap().pop().unwrap().updated;
+        assert_eq!(updated["block"].keys, BTreeSet::from(["prop:text".into()]));
+
+        // removed blocks
+        workspace.with_trx(|mut t| {
+            t.create("other", "text");
+        });
+        workspace.with_trx(|mut t| t.remove("other"));
+        assert_eq!(
+            changes.lock().unwrap().pop().unwrap().removed,
+            BTreeSet::from(["other".into()])
+        );
+    }
+
+    #[test]
+    fn block_created() {
+        let mut workspace = Workspace::new("test");
+        let created = Arc::new(Mutex::new(vec![]));
+        let _sub = workspace.observe_block_created({
+            let created = created.clone();
+            move |trx, block| {
+                created
+                    .lock()
+                    .unwrap()
+                    .push((block.id(), block.flavor(trx)))
+            }
+        });
+
+        workspace.with_trx(|mut t| {
+            t.create("b", "affine:paragraph");
+            t.create("a", "affine:page");
+        });
+        workspace.with_trx(|mut t| {
+            let block = workspace.get(&t.trx, "a").unwrap();
+            block.set(&mut t.trx, "title", "hello");
+            t.remove("b");
+        });
+        workspace.with_trx(|mut t| {
+            t.create("c", "affine:paragraph");
+        });
+
+        assert_eq!(
+            *created.lock().unwrap(),
+            vec![
+                ("a".to_owned(), "affine:page".to_owned()),
+                ("b".to_owned(), "affine:paragraph".to_owned()),
+                ("c".to_owned(), "affine:paragraph".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn children_splice() {
+        let mut workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            let block = t.create("block", "text");
+            for id in ["a", "b", "c"] {
+                let child = t.create(id, "text");
+                block.push_children(&mut t.trx, &child);
+            }
+        });
+        let (changes, _sub) = record(&mut workspace);
+
+        workspace.with_trx(|mut t| {
+            let block = workspace.get(&t.trx, "block").unwrap();
+            let b = workspace.get(&t.trx, "b").unwrap();
+            block.remove_children(&mut t.trx, &b);
+            let d = t.create("d", "text");
+            block.insert_children_at(&mut t.trx, &d, 2);
+        });
+
+        let changes = changes.lock().unwrap().pop().unwrap();
+        assert_eq!(changes.added, BTreeSet::from(["d".into()]));
+        assert_eq!(
+            changes.updated["block"].children,
+            vec![
+                ChildrenSplice {
+                    index: 1,
+                    inserted: vec![],
+                    removed: 1
+                },
+                ChildrenSplice {
+                    index: 2,
+                    inserted: vec!["d".into()],
+                    removed: 0
+                },
+            ]
+        );
+        // sys:children is only reported as splices
+        assert!(!changes.updated["block"].keys.contains(sys::CHILDREN));
+    }
+
+    #[test]
+    fn filtered_by_flavor() {
+        let mut workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            t.create("page", "affine:page");
+            t.create("note", "affine:note");
+        });
+
+        let received = Arc::new(Mutex::new(vec![]));
+        let _sub = workspace.observe_filtered(
+            HashSet::from(["affine:page".into(), "affine:paragraph".into()]),
+            {
+                let received = received.clone();
+                move |changes| received.lock().unwrap().push(changes.to_vec())
+            },
+        );
+
+        // no matching block changed
+        workspace.with_trx(|mut t| {
+            let note = workspace.get(&t.trx, "note").unwrap();
+            note.set(&mut t.trx, "title", "note");
+            t.create("other", "affine:note");
+        });
+        assert!(received.lock().unwrap().is_empty());
+
+        workspace.with_trx(|mut t| {
+            let page = workspace.get(&t.trx, "page").unwrap();
+            page.set(&mut t.trx, "title", "page");
+            let note = workspace.get(&t.trx, "note").unwrap();
+            note.set(&mut t.trx, "title", "changed");
+            t.create("paragraph", "affine:paragraph");
+        });
+        workspace.with_trx(|mut t| {
+            t.remove("paragraph");
+            t.remove("note");
+        });
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![
+                vec![
+                    BlockChange {
+                        id: "paragraph".into(),
+                        flavor: "affine:paragraph".into(),
+                        kind: BlockChangeKind::Added,
+                    },
+                    BlockChange {
+                        id: "page".into(),
+                        flavor: "affine:page".into(),
+                        kind: BlockChangeKind::Updated(BlockChanges {
+                            keys: BTreeSet::from(["prop:title".into()]),
+                            children: vec![],
+                        }),
+                    },
+                ],
+                vec![BlockChange {
+                    id: "paragraph".into(),
+                    flavor: "affine:paragraph".into(),
+                    kind: BlockChangeKind::Removed,
+                }],
+            ]
+        );
+
+        // blocks enter and leave the flavors when their flavor changes
+        workspace.with_trx(|mut t| {
+            t.create("paragraph", "affine:paragraph");
+        });
+        received.lock().unwrap().clear();
+        workspace.rename_flavour("affine:note", "affine:paragraph");
+        workspace.rename_flavour("affine:page", "affine:note");
+        workspace.rename_flavour("affine:paragraph", "affine:page");
+        let received = received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|changes| {
+                changes
+                    .iter()
+                    .map(|change| {
+                        let kind = match change.kind {
+                            BlockChangeKind::Added => "added",
+                            BlockChangeKind::Removed => "removed",
+                            BlockChangeKind::Updated(_) => "updated",
+                        };
+                        (change.id.clone(), change.flavor.clone(), kind)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            received,
+            vec![
+                vec![("other".into(), "affine:paragraph".into(), "added")],
+                vec![("page".into(), "affine:page".into(), "removed")],
+                vec![
+                    ("other".into(), "affine:page".into(), "updated"),
+                    ("paragraph".into(), "affine:page".into(), "updated"),
+                ],
+            ]
+        );
+    }
+}
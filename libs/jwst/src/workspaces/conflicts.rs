@@ -0,0 +1,328 @@
+use super::*;
+use lib0::any::Any;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+use thiserror::Error;
+use yrs::{
+    block::{Item, ItemContent, ID},
+    types::TypePtr,
+    updates::decoder::Decode,
+    ReadTxn, StateVector, Transact, TransactionAcqError, Update,
+};
+
+#[derive(Debug, Error)]
+pub enum ApplyError {
+    #[error("invalid update: {0}")]
+    Invalid(#[from] lib0::error::Error),
+    #[error(transparent)]
+    Transaction(#[from] TransactionAcqError),
+}
+
+/// Merge strategy for a block field edited concurrently, see [Workspace::apply_with_resolver].
+pub trait ConflictResolver {
+    /// Value of `field` of `block_id` once an update is applied, given the `local` value
+    /// and the `remote` one written without knowing it.
+    fn resolve(&self, block_id: &str, field: &str, local: Any, remote: Any) -> Any;
+}
+
+impl<F> ConflictResolver for F
+where
+    F: Fn(&str, &str, Any, Any) -> Any,
+{
+    fn resolve(&self, block_id: &str, field: &str, local: Any, remote: Any) -> Any {
+        self(block_id, field, local, remote)
+    }
+}
+
+// map an entry is stored in: a root type or the item of a nested map
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Parent {
+    Root(Arc<str>),
+    Item(ID),
+}
+
+type EntryIndex = HashMap<ID, (Parent, Arc<str>)>;
+
+// a value written to a key of a map
+struct MapEntry {
+    id: ID,
+    origin: Option<ID>,
+    parent: Parent,
+    key: Arc<str>,
+    value: Option<Any>,
+}
+
+struct Conflict {
+    id: ID,
+    block_id: String,
+    field: String,
+    local: Any,
+    remote: Any,
+}
+
+// only the first entry of a key carries its parent and key, the entries
+// overwriting it are resolved through their origin
+fn index_entries(items: &[&Item], index: &mut EntryIndex) {
+    let mut pending = items.to_vec();
+    loop {
+        let unresolved = pending.len();
+        pending.retain(|item| {
+            let entry = match (&item.parent, &item.parent_sub) {
+                (TypePtr::Named(name), Some(key)) => {
+                    Some((Parent::Root(name.clone()), key.clone()))
+                }
+                (TypePtr::ID(id), Some(key)) => Some((Parent::Item(*id), key.clone())),
+                (TypePtr::Unknown, _) => item.origin.and_then(|origin| index.get(&origin).cloned()),
+                // list items
+                _ => return false,
+            };
+            match entry {
+                Some(entry) => {
+                    index.insert(item.id, entry);
+                    false
+                }
+                None => true,
+            }
+        });
+        if pending.len() == unresolved {
+            break;
+        }
+    }
+}
+
+fn map_entries(items: &[&Item], index: &EntryIndex) -> Vec<MapEntry> {
+    items
+        .iter()
+        .filter_map(|item| {
+            let (parent, key) = index.get(&item.id)?.clone();
+            Some(MapEntry {
+                id: item.id,
+                origin: item.origin,
+                parent,
+                key,
+                value: match &item.content {
+                    ItemContent::Any(values) => values.last().cloned(),
+                    _ => None,
+                },
+            })
+        })
+        .collect()
+}
+
+// entries of each key that no other entry was written after, more than one if
+// they were written concurrently
+fn heads(entries: &[MapEntry]) -> HashMap<(Parent, Arc<str>), Vec<&MapEntry>> {
+    let overwritten = entries
+        .iter()
+        .filter_map(|entry| entry.origin)
+        .collect::<HashSet<_>>();
+    let mut heads: HashMap<_, Vec<_>> = HashMap::new();
+    for entry in entries
+        .iter()
+        .filter(|entry| !overwritten.contains(&entry.id))
+    {
+        heads
+            .entry((entry.parent.clone(), entry.key.clone()))
+            .or_default()
+            .push(entry);
+    }
+    heads
+}
+
+impl Workspace {
+    /// Apply an update, and let `resolver` merge the block fields it sets concurrently
+    /// to a local change, instead of the last writer wins rule of yrs.
+    ///
+    /// A field conflicts if the update writes it without knowing its current local value.
+    /// The resolved value is written in the same transaction as the update, as a local
+    /// change that the other clients receive with the next sync. Only plain values are
+    /// resolved, nested types and removed fields are left to yrs.
+    ///
+    /// The local doc is decoded to find the fields of the update, so this costs about as
+    /// much as encoding the whole doc.
+    pub fn apply_with_resolver(
+        &mut self,
+        update: &[u8],
+        resolver: &dyn ConflictResolver,
+    ) -> Result<(), ApplyError> {
+        let update = Update::decode_v1(update)?;
+        let doc = self.doc();
+        let mut trx = doc.try_transact_mut()?;
+
+        let conflicts = {
+            let local = trx.encode_state_as_update_v1(&StateVector::default());
+            let local = Update::decode_v1(&local)?;
+            let local_items = local.as_items();
+            let remote_items = update.as_items();
+            let mut index = EntryIndex::new();
+            index_entries(&local_items, &mut index);
+            let local_entries = map_entries(&local_items, &index);
+            index_entries(&remote_items, &mut index);
+
+            let before = trx.state_vector();
+            let remote_entries = map_entries(&remote_items, &index)
+                .into_iter()
+                .filter(|entry| before.get(&entry.id.client) <= entry.id.clock)
+                .collect::<Vec<_>>();
+
+            let blocks = index
+                .iter()
+                .filter(|(_, (parent, _))| *parent == Parent::Root("blocks".into()))
+                .map(|(id, (_, block_id))| (*id, block_id.clone()))
+                .collect::<HashMap<_, _>>();
+            let local_heads = heads(&local_entries);
+
+            let mut conflicts = vec![];
+            for ((parent, key), remote) in heads(&remote_entries) {
+                let (Parent::Item(block_item), Some(field)) = (&parent, key.strip_prefix("prop:"))
+                else {
+                    continue;
+                };
+                let Some(block) = blocks.get(block_item).and_then(|id| self.get(&trx, id)) else {
+                    continue;
+                };
+                let Some(remote) = remote.iter().find(|entry| {
+                    matches!(
+                        entry.value,
+                        Some(Any::Bool(_) | Any::Number(_) | Any::BigInt(_) | Any::String(_))
+                    )
+                }) else {
+                    continue;
+                };
+                let knows_local = local_heads
+                    .get(&(parent.clone(), key.clone()))
+                    .into_iter()
+                    .flatten()
+                    .filter(|entry| entry.value.is_some())
+                    .all(|entry| remote.origin == Some(entry.id));
+                if let (false, Some(local), Some(value)) =
+                    (knows_local, block.get(&trx, field), &remote.value)
+                {
+                    conflicts.push(Conflict {
+                        id: remote.id,
+                        block_id: block.id(),
+                        field: field.to_owned(),
+                        local,
+                        remote: value.clone(),
+                    });
+                }
+            }
+            conflicts
+        };
+
+        trx.apply_update(update);
+
+        let after = trx.state_vector();
+        for conflict in conflicts {
+            // still waiting for the updates it depends on
+            if after.get(&conflict.id.client) <= conflict.id.clock {
+                continue;
+            }
+            let Some(block) = self.get(&trx, &conflict.block_id) else {
+                continue;
+            };
+            let value = resolver.resolve(
+                &conflict.block_id,
+                &conflict.field,
+                conflict.local,
+                conflict.remote,
+            );
+            if block.get(&trx, &conflict.field).as_ref() != Some(&value) {
+                trace!("resolved {}.{}", conflict.block_id, conflict.field);
+                block.set(&mut trx, &conflict.field, value);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    fn count<T: ReadTxn>(workspace: &Workspace, trx: &T) -> Option<Any> {
+        workspace.get(trx, "block").unwrap().get(trx, "count")
+    }
+
+    #[test]
+    fn apply_with_resolver() {
+        let mut local = Workspace::new("test");
+        local.with_trx(|mut t| {
+            let block = t.create("block", "affine:text");
+            block.set(&mut t.trx, "count", 1_i64);
+            block.set(&mut t.trx, "title", "base");
+        });
+        let remote = Workspace::new("test");
+        remote.apply_updates(&[local.sync_migration()]).unwrap();
+
+        local.with_trx(|mut t| {
+            let block = local.get(&t.trx, "block").unwrap();
+            block.set(&mut t.trx, "count", 5_i64);
+        });
+        remote.with_trx(|mut t| {
+            let block = remote.get(&t.trx, "block").unwrap();
+            block.set(&mut t.trx, "count", 3_i64);
+            block.set(&mut t.trx, "title", "remote");
+        });
+
+        let calls = RefCell::new(vec![]);
+        let max = |block_id: &str, field: &str, local: Any, remote: Any| {
+            calls.borrow_mut().push((
+                block_id.to_owned(),
+                field.to_owned(),
+                local.clone(),
+                remote.clone(),
+            ));
+            match (local, remote) {
+                (Any::Number(local), Any::Number(remote)) => Any::Number(local.max(remote)),
+                (_, remote) => remote,
+            }
+        };
+
+        local
+            .apply_with_resolver(&remote.sync_migration(), &max)
+            .unwrap();
+        assert_eq!(
+            calls.take(),
+            vec![(
+                "block".to_owned(),
+                "count".to_owned(),
+                Any::Number(5.),
+                Any::Number(3.)
+            )]
+        );
+        local.with_trx(|t| {
+            assert_eq!(count(&local, &t.trx), Some(Any::Number(5.)));
+            // only changed remotely
+            let block = local.get(&t.trx, "block").unwrap();
+            assert_eq!(
+                block.get(&t.trx, "title"),
+                Some(Any::String("remote".into()))
+            );
+        });
+
+        // the resolved value overwrites both sides
+        remote.apply_updates(&[local.sync_migration()]).unwrap();
+        remote.with_trx(|t| assert_eq!(count(&remote, &t.trx), Some(Any::Number(5.))));
+
+        // changes made after seeing the local value don't conflict
+        remote.with_trx(|mut t| {
+            let block = remote.get(&t.trx, "block").unwrap();
+            block.set(&mut t.trx, "count", 2_i64);
+        });
+        local
+            .apply_with_resolver(&remote.sync_migration(), &max)
+            .unwrap();
+        assert!(calls.borrow().is_empty());
+        local.with_trx(|t| assert_eq!(count(&local, &t.trx), Some(Any::Number(2.))));
+
+        assert!(matches!(
+            local.apply_with_resolver(&[255, 255], &max),
+            Err(ApplyError::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,207 @@
+use super::*;
+use lib0::any::Any;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use utoipa::ToSchema;
+use yrs::{
+    types::{ToJson, Value},
+    Map, ReadTxn,
+};
+
+/// Size and shape of the blocks of a workspace, see [Workspace::content_stats].
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ContentStats {
+    pub blocks: usize,
+    /// Number of blocks of each flavour.
+    pub flavours: BTreeMap<String, usize>,
+    /// Number of parent to child links.
+    pub children_edges: usize,
+    /// Levels of the deepest block tree, 1 for a tree without children.
+    pub max_depth: usize,
+    /// Approximate size of the block properties, as JSON.
+    pub property_bytes: usize,
+    /// Pages moved to the trash, from the page list in the workspace metadata.
+    pub trashed_pages: usize,
+}
+
+impl Workspace {
+    /// Statistics of the blocks, walks the whole workspace so it's costly for large docs.
+    pub fn content_stats<T>(&self, trx: &T) -> ContentStats
+    where
+        T: ReadTxn,
+    {
+        let mut stats = ContentStats::default();
+        let mut children = HashMap::new();
+        let mut parents = HashMap::new();
+
+        self.blocks(trx, |blocks| {
+            for block in blocks {
+                stats.blocks += 1;
+                *stats.flavours.entry(block.flavor(trx)).or_default() += 1;
+                stats.property_bytes += block
+                    .content(trx)
+                    .iter()
+                    .map(|(key, value)| {
+                        key.len() + serde_json::to_vec(value).map(|v| v.len()).unwrap_or(0)
+                    })
+                    .sum::<usize>();
+
+                let block_children = block.children(trx);
+                stats.children_edges += block_children.len();
+                parents.insert(block.id(), block.parent(trx));
+                children.insert(block.id(), block_children);
+            }
+        });
+
+        // walk the trees from the blocks without an existing parent,
+        // visited blocks are skipped in case of cycles
+        let mut visited = HashSet::new();
+        let mut stack = parents
+            .iter()
+            .filter(|(_, parent)| !matches!(parent, Some(parent) if children.contains_key(parent)))
+            .map(|(id, _)| (id.as_str(), 1))
+            .collect::<Vec<_>>();
+        while let Some((id, depth)) = stack.pop() {
+            if !visited.insert(id) {
+                continue;
+            }
+            stats.max_depth = stats.max_depth.max(depth);
+            if let Some(block_children) = children.get(id) {
+                stack.extend(
+                    block_children
+                        .iter()
+                        .filter(|child| children.contains_key(*child))
+                        .map(|child| (child.as_str(), depth + 1)),
+                );
+            }
+        }
+
+        if let Some(Any::Array(pages)) = self.metadata.get(trx, "pages").map(|p| p.to_json(trx)) {
+            stats.trashed_pages = pages
+                .iter()
+                .filter(|page| match page {
+                    Any::Map(page) => matches!(page.get("trash"), Some(Any::Bool(true))),
+                    _ => false,
+                })
+                .count();
+        }
+
+        stats
+    }
+
+    /// Approximate storage cost of a block, as the length of its yrs map serialized to JSON.
+    pub fn block_size_bytes<T>(&self, trx: &T, block_id: &str) -> Option<usize>
+    where
+        T: ReadTxn,
+    {
+        match self.blocks.get(trx, block_id) {
+            Some(Value::YMap(block)) => Some(json_size(&block.to_json(trx))),
+            _ => None,
+        }
+    }
+
+    /// The `n` biggest blocks by [Workspace::block_size_bytes], largest first.
+    pub fn largest_blocks<T>(&self, trx: &T, n: usize) -> Vec<(String, usize)>
+    where
+        T: ReadTxn,
+    {
+        let mut sizes = self
+            .blocks
+            .iter(trx)
+            .filter_map(|(id, block)| match block {
+                Value::YMap(block) => Some((id.to_owned(), json_size(&block.to_json(trx)))),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        sizes.sort_by(|(a_id, a), (b_id, b)| b.cmp(a).then_with(|| a_id.cmp(b_id)));
+        sizes.truncate(n);
+        sizes
+    }
+}
+
+fn json_size(value: &Any) -> usize {
+    serde_json::to_vec(value).map(|v| v.len()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use yrs::{Array, ArrayPrelim};
+
+    #[test]
+    fn content_stats() {
+        let workspace = Workspace::new("test");
+        assert_eq!(
+            workspace.with_trx(|t| workspace.content_stats(&t.trx)),
+            ContentStats::default()
+        );
+
+        workspace.with_trx(|mut t| {
+            let page = t.create("page", "affine:page");
+            let frame = t.create("frame", "affine:frame");
+            let text = t.create("text", "affine:paragraph");
+            let other = t.create("other", "affine:paragraph");
+            page.push_children(&mut t.trx, &frame);
+            frame.push_children(&mut t.trx, &text);
+            frame.push_children(&mut t.trx, &other);
+            text.set(&mut t.trx, "text", "hello");
+
+            let pages = workspace.metadata.insert(
+                &mut t.trx,
+                "pages",
+                ArrayPrelim::<Vec<Any>, Any>::from(vec![]),
+            );
+            for trash in [true, false] {
+                let page = HashMap::from([("trash".to_owned(), Any::Bool(trash))]);
+                pages.push_back(&mut t.trx, Any::Map(Box::new(page)));
+            }
+        });
+
+        let stats = workspace.with_trx(|t| workspace.content_stats(&t.trx));
+        assert_eq!(
+            stats,
+            ContentStats {
+                blocks: 4,
+                flavours: BTreeMap::from([
+                    ("affine:frame".into(), 1),
+                    ("affine:page".into(), 1),
+                    ("affine:paragraph".into(), 2),
+                ]),
+                children_edges: 3,
+                max_depth: 3,
+                // "text" + "\"hello\""
+                property_bytes: 11,
+                trashed_pages: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn block_size() {
+        let workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            t.create("small", "affine:paragraph");
+            let large = t.create("large", "affine:paragraph");
+            large.set(
+                &mut t.trx,
+                "text",
+                "hello world, this is a longer paragraph",
+            );
+        });
+
+        workspace.with_trx(|t| {
+            let small = workspace.block_size_bytes(&t.trx, "small").unwrap();
+            let large = workspace.block_size_bytes(&t.trx, "large").unwrap();
+            assert!(small > 0);
+            assert!(large > small);
+            assert_eq!(workspace.block_size_bytes(&t.trx, "missing"), None);
+
+            assert_eq!(
+                workspace.largest_blocks(&t.trx, 1),
+                vec![("large".to_owned(), large)]
+            );
+            assert_eq!(workspace.largest_blocks(&t.trx, 10).len(), 2);
+            assert!(workspace.largest_blocks(&t.trx, 0).is_empty());
+        });
+    }
+}
@@ -0,0 +1,395 @@
+use super::*;
+use lib0::any::Any;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Write,
+    io,
+};
+use thiserror::Error;
+use yrs::{Map, ReadTxn, Transact};
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("block {0} not found")]
+    BlockNotFound(String),
+    #[error("block {0} is referenced more than once")]
+    DuplicateReference(String),
+    #[error("block {0} is nested deeper than the max block depth")]
+    MaxDepthExceeded(String),
+    #[error(transparent)]
+    Format(#[from] std::fmt::Error),
+    #[cfg(feature = "workspace-export-sqlite")]
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+const DEFAULT_CALLOUT_EMOJI: &str = "💡";
+
+// a line of [Workspace::write_ndjson]
+#[derive(Serialize)]
+struct NdjsonBlock<'a> {
+    id: &'a str,
+    flavour: String,
+    // sorted so the output is stable
+    props: BTreeMap<String, Any>,
+    parent: Option<String>,
+}
+
+fn text_prop<T: ReadTxn>(trx: &T, block: &Block, key: &str) -> String {
+    block
+        .get(trx, key)
+        .map(|v| match v {
+            Any::String(s) => s.to_string(),
+            v => v.to_string(),
+        })
+        .unwrap_or_default()
+}
+
+fn escape_cell(cell: &str) -> String {
+    cell.replace('|', "\\|").replace('\n', "<br>")
+}
+
+struct NotionExporter<'a, T: ReadTxn> {
+    trx: &'a T,
+    workspace: &'a Workspace,
+    visited: HashSet<String>,
+    output: String,
+}
+
+impl<'a, T: ReadTxn> NotionExporter<'a, T> {
+    // children pointing to removed blocks are left out of the export
+    fn block(&self, block_id: &str) -> Option<Block> {
+        let block = self.workspace.get(self.trx, block_id);
+        if block.is_none() {
+            warn!("skip missing block {} in export", block_id);
+        }
+        block
+    }
+
+    // `depth` is the indentation of list items, `level` the nesting of the block
+    fn export_block(
+        &mut self,
+        block_id: &str,
+        depth: usize,
+        level: usize,
+    ) -> Result<(), ExportError> {
+        if !self.visited.insert(block_id.to_owned()) {
+            return Err(ExportError::DuplicateReference(block_id.to_owned()));
+        }
+        // trees from before the depth limit could overflow the stack
+        if level > self.workspace.max_block_depth() {
+            return Err(ExportError::MaxDepthExceeded(block_id.to_owned()));
+        }
+
+        let trx = self.trx;
+        let Some(block) = self.block(block_id) else {
+            return Ok(());
+        };
+        let indent = "  ".repeat(depth);
+        let text = text_prop(trx, &block, "text");
+
+        match block.flavor(trx).as_str() {
+            "affine:page" => {
+                let title = text_prop(trx, &block, "title");
+                writeln!(self.output, "<!-- affine-block-id: {block_id} -->")?;
+                writeln!(self.output, "# {title}\n")?;
+            }
+            "affine:paragraph" => match text_prop(trx, &block, "type").as_str() {
+                level @ ("h1" | "h2" | "h3" | "h4" | "h5" | "h6") => {
+                    let level = level[1..].parse::<usize>().unwrap_or(1);
+                    writeln!(self.output, "<!-- affine-block-id: {block_id} -->")?;
+                    writeln!(self.output, "{} {text}\n", "#".repeat(level))?;
+                }
+                "quote" => writeln!(self.output, "{indent}> {text}\n")?,
+                _ => writeln!(self.output, "{indent}{text}\n")?,
+            },
+            "affine:callout" => {
+                let emoji = block
+                    .get(trx, "emoji")
+                    .map(|e| e.to_string())
+                    .unwrap_or_else(|| DEFAULT_CALLOUT_EMOJI.to_owned());
+                writeln!(self.output, "> {emoji} {text}\n")?;
+            }
+            "affine:list" => {
+                let marker = match text_prop(trx, &block, "type").as_str() {
+                    "numbered" => "1.".to_owned(),
+                    "todo" => match block.get(trx, "checked") {
+                        Some(Any::Bool(true)) => "- [x]".to_owned(),
+                        _ => "- [ ]".to_owned(),
+                    },
+                    _ => "-".to_owned(),
+                };
+                writeln!(self.output, "{indent}{marker} {text}")?;
+                for child in block.children(trx) {
+                    self.export_block(&child, depth + 1, level + 1)?;
+                }
+                if depth == 0 {
+                    writeln!(self.output)?;
+                }
+                return Ok(());
+            }
+            "affine:code" => {
+                let language = text_prop(trx, &block, "language");
+                writeln!(self.output, "```{language}\n{text}\n```\n")?;
+            }
+            "affine:divider" => writeln!(self.output, "---\n")?,
+            "affine:database" => {
+                self.export_database(&block)?;
+                return Ok(());
+            }
+            _ => {
+                if !text.is_empty() {
+                    writeln!(self.output, "{indent}{text}\n")?;
+                }
+            }
+        }
+
+        for child in block.children(trx) {
+            self.export_block(&child, depth, level + 1)?;
+        }
+
+        Ok(())
+    }
+
+    // database rows are the children of the database block,
+    // the row text is used as the title column and other props become columns
+    fn export_database(&mut self, block: &Block) -> Result<(), ExportError> {
+        let trx = self.trx;
+        let title = text_prop(trx, block, "title");
+        if !title.is_empty() {
+            writeln!(self.output, "**{}**\n", escape_cell(&title))?;
+        }
+
+        let mut rows = vec![];
+        for row_id in block.children(trx) {
+            if !self.visited.insert(row_id.clone()) {
+                return Err(ExportError::DuplicateReference(row_id));
+            }
+            if let Some(row) = self.block(&row_id) {
+                rows.push(row.content(trx));
+            }
+        }
+
+        let mut columns = rows
+            .iter()
+            .flat_map(|row| row.keys())
+            .filter(|key| key.as_str() != "text")
+            .cloned()
+            .collect::<Vec<_>>();
+        columns.sort();
+        columns.dedup();
+
+        write!(self.output, "| Title |")?;
+        for column in &columns {
+            write!(self.output, " {} |", escape_cell(column))?;
+        }
+        write!(self.output, "\n| --- |")?;
+        for _ in &columns {
+            write!(self.output, " --- |")?;
+        }
+        writeln!(self.output)?;
+
+        for row in rows {
+            let cell = |key: &str| match row.get(key) {
+                Some(Any::String(s)) => escape_cell(s),
+                Some(Any::Null | Any::Undefined) | None => String::new(),
+                Some(v) => escape_cell(&v.to_string()),
+            };
+            write!(self.output, "| {} |", cell("text"))?;
+            for column in &columns {
+                write!(self.output, " {} |", cell(column))?;
+            }
+            writeln!(self.output)?;
+        }
+        writeln!(self.output)?;
+
+        Ok(())
+    }
+}
+
+impl Workspace {
+    /// Export the workspace as Notion-compatible Markdown.
+    ///
+    /// Headings are preceded by a comment carrying the AFFiNE block id, callouts are
+    /// rendered as blockquotes prefixed with their emoji and databases as Markdown tables.
+    pub fn export_to_notion_format(&self) -> Result<String, ExportError> {
+        let doc = self.doc();
+        let trx = doc.transact();
+
+        let mut roots = self.blocks(&trx, |blocks| {
+            blocks
+                .filter(|block| block.parent(&trx).is_none())
+                .map(|block| (block.created(&trx), block.id()))
+                .collect::<Vec<_>>()
+        });
+        roots.sort();
+
+        let mut exporter = NotionExporter {
+            trx: &trx,
+            workspace: self,
+            visited: HashSet::new(),
+            output: String::new(),
+        };
+        for (_, block_id) in roots {
+            exporter.export_block(&block_id, 0, 1)?;
+        }
+
+        Ok(exporter.output.trim_end().to_owned() + "\n")
+    }
+
+    /// Export the blocks as newline-delimited JSON ordered by block id, one
+    /// `{"id", "flavour", "props", "parent"}` object per line. Blocks are serialized
+    /// one at a time, wrap `w` in a [io::BufWriter] when it is a file or a socket.
+    pub fn write_ndjson<W: io::Write>(&self, mut w: W) -> io::Result<()> {
+        let doc = self.doc();
+        let trx = doc.transact();
+
+        let mut ids = self
+            .blocks
+            .keys(&trx)
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        ids.sort();
+        for id in ids {
+            // malformed blocks are skipped like everywhere else
+            let Some(block) = self.get(&trx, &id) else {
+                continue;
+            };
+            serde_json::to_writer(
+                &mut w,
+                &NdjsonBlock {
+                    id: &id,
+                    flavour: block.flavor(&trx),
+                    props: block.content(&trx).into_iter().collect(),
+                    parent: block.parent(&trx),
+                },
+            )?;
+            w.write_all(b"\n")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn export_to_notion_format() {
+        let workspace = Workspace::new("export");
+
+        workspace.with_trx(|mut t| {
+            let page = t.create("page", "affine:page");
+            page.set(&mut t.trx, "title", "Hello");
+
+            let heading = t.create("heading", "affine:paragraph");
+            heading.set(&mut t.trx, "type", "h2");
+            heading.set(&mut t.trx, "text", "Section");
+            page.push_children(&mut t.trx, &heading);
+
+            let callout = t.create("callout", "affine:callout");
+            callout.set(&mut t.trx, "emoji", "🔥");
+            callout.set(&mut t.trx, "text", "Hot tip");
+            page.push_children(&mut t.trx, &callout);
+
+            let database = t.create("database", "affine:database");
+            page.push_children(&mut t.trx, &database);
+
+            let row = t.create("row", "affine:paragraph");
+            row.set(&mut t.trx, "text", "Task | 1");
+            row.set(&mut t.trx, "status", "done");
+            database.push_children(&mut t.trx, &row);
+        });
+
+        assert_eq!(
+            workspace.export_to_notion_format().unwrap(),
+            [
+                "<!-- affine-block-id: page -->",
+                "# Hello",
+                "",
+                "<!-- affine-block-id: heading -->",
+                "## Section",
+                "",
+                "> 🔥 Hot tip",
+                "",
+                "| Title | status |",
+                "| --- | --- |",
+                "| Task \\| 1 | done |",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn write_ndjson() {
+        let workspace = Workspace::new("export");
+
+        workspace.with_trx(|mut t| {
+            let page = t.create("page", "affine:page");
+            page.set(&mut t.trx, "title", "Hello");
+            let text = t.create("b-text", "affine:paragraph");
+            text.set(&mut t.trx, "text", "World");
+            text.set(&mut t.trx, "type", "h1");
+            page.push_children(&mut t.trx, &text);
+        });
+
+        let mut output = vec![];
+        workspace.write_ndjson(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            [
+                r#"{"id":"b-text","flavour":"affine:paragraph","props":{"text":"World","type":"h1"},"parent":"page"}"#,
+                r#"{"id":"page","flavour":"affine:page","props":{"title":"Hello"},"parent":null}"#,
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn export_missing_block() {
+        let workspace = Workspace::new("export");
+
+        workspace.with_trx(|mut t| {
+            let page = t.create("page", "affine:page");
+            page.set(&mut t.trx, "title", "Page");
+            let child = t.create("child", "affine:paragraph");
+            page.push_children(&mut t.trx, &child);
+            let sibling = t.create("sibling", "affine:paragraph");
+            sibling.set(&mut t.trx, "text", "kept");
+            page.push_children(&mut t.trx, &sibling);
+            t.remove("child");
+        });
+
+        // the dangling child is skipped, the rest of the page is exported
+        assert_eq!(
+            workspace.export_to_notion_format().unwrap(),
+            "<!-- affine-block-id: page -->\n# Page\n\nkept\n"
+        );
+    }
+
+    #[test]
+    fn export_over_deep_tree() {
+        let workspace = Workspace::new("export");
+
+        // nested with the low level API, which doesn't check the depth
+        workspace.with_trx(|mut t| {
+            let mut parent = t.create("block0", "affine:paragraph");
+            for i in 1..10 {
+                let child = t.create(format!("block{i}"), "affine:paragraph");
+                parent.push_children(&mut t.trx, &child);
+                parent = child;
+            }
+        });
+        assert!(workspace.export_to_notion_format().is_ok());
+
+        workspace.set_max_block_depth(5);
+        assert!(matches!(
+            workspace.export_to_notion_format(),
+            Err(ExportError::MaxDepthExceeded(id)) if id == "block5"
+        ));
+    }
+}
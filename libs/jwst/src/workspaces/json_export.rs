@@ -0,0 +1,190 @@
+use super::*;
+use crate::block::retain_properties;
+use std::collections::HashSet;
+use yrs::{types::ToJson, Map, ReadTxn, Transact};
+
+/// Serializes a workspace into the same JSON as its `Serialize` impl, a few blocks
+/// at a time, so a large workspace can be streamed without holding a transaction
+/// for the whole export. See [Workspace::json_export].
+///
+/// Every chunk reads the doc in its own transaction: blocks removed while the export
+/// runs are left out, other changes show up if they happen before the block is read.
+pub struct JsonExport {
+    workspace: Workspace,
+    ids: Vec<String>,
+    blocks_per_chunk: usize,
+    // position in the blocks and then the updated entries of `ids`
+    next: usize,
+    // whether the current object has an entry yet, to place the commas
+    written: bool,
+    finished: bool,
+}
+
+impl Workspace {
+    /// Prepare a chunked JSON export of the workspace, or only of `root` and its
+    /// descendants. Blocks are exported ordered by id, `blocks_per_chunk` of them
+    /// (and later their history) per chunk.
+    pub fn json_export(
+        self,
+        root: Option<&str>,
+        blocks_per_chunk: usize,
+    ) -> JwstResult<JsonExport> {
+        let ids = {
+            let doc = self.doc();
+            let trx = doc.transact();
+            let mut ids = match root {
+                Some(root) => self.subtree(&trx, root)?,
+                None => self.blocks.keys(&trx).map(str::to_owned).collect(),
+            };
+            ids.sort();
+            ids
+        };
+
+        Ok(JsonExport {
+            workspace: self,
+            ids,
+            blocks_per_chunk: blocks_per_chunk.max(1),
+            next: 0,
+            written: false,
+            finished: false,
+        })
+    }
+
+    // ids of a block and its descendants, children missing from the doc are skipped
+    fn subtree<T: ReadTxn>(&self, trx: &T, root: &str) -> JwstResult<Vec<String>> {
+        let root = self
+            .get(trx, root)
+            .ok_or_else(|| JwstError::BlockNotFound(root.to_owned()))?;
+
+        let mut visited = HashSet::from([root.id()]);
+        let mut pending = vec![root];
+        while let Some(block) = pending.pop() {
+            for child in block.children(trx) {
+                if visited.contains(&child) {
+                    continue;
+                }
+                if let Some(child) = self.get(trx, &child) {
+                    visited.insert(child.id());
+                    pending.push(child);
+                }
+            }
+        }
+
+        Ok(visited.into_iter().collect())
+    }
+}
+
+impl JsonExport {
+    /// Number of blocks in the export.
+    pub fn blocks(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// The next piece of the JSON document, `None` once it is complete.
+    pub fn next_chunk(&mut self) -> serde_json::Result<Option<Vec<u8>>> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        let len = self.ids.len();
+        let mut chunk = vec![];
+        if self.next == 0 {
+            chunk.extend_from_slice(br#"{"blocks":{"#);
+        }
+
+        let doc = self.workspace.doc();
+        let trx = doc.transact();
+        let end = (self.next + self.blocks_per_chunk).min(len * 2);
+        while self.next < end {
+            if self.next == len {
+                chunk.extend_from_slice(br#"},"updated":{"#);
+                self.written = false;
+            }
+            let (map, id, is_block) = if self.next < len {
+                (&self.workspace.blocks, &self.ids[self.next], true)
+            } else {
+                (&self.workspace.updated, &self.ids[self.next - len], false)
+            };
+            self.next += 1;
+
+            let Some(value) = map.get(&trx, id) else {
+                continue;
+            };
+            let mut value = value.to_json(&trx);
+            if is_block {
+                retain_properties(&mut value);
+            }
+            if self.written {
+                chunk.push(b',');
+            }
+            self.written = true;
+            serde_json::to_writer(&mut chunk, id)?;
+            chunk.push(b':');
+            serde_json::to_writer(&mut chunk, &value)?;
+        }
+
+        if self.next == len * 2 {
+            if len == 0 {
+                chunk.extend_from_slice(br#"},"updated":{"#);
+            }
+            chunk.extend_from_slice(b"}}");
+            self.finished = true;
+        }
+
+        Ok(Some(chunk))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::Value;
+
+    fn export(workspace: &Workspace, root: Option<&str>) -> (Value, usize) {
+        let mut export = workspace.clone().json_export(root, 3).unwrap();
+        let (mut json, mut chunks) = (vec![], 0);
+        while let Some(chunk) = export.next_chunk().unwrap() {
+            json.extend(chunk);
+            chunks += 1;
+        }
+        (serde_json::from_slice(&json).unwrap(), chunks)
+    }
+
+    #[test]
+    fn json_export() {
+        let workspace = Workspace::new("test");
+        let (json, chunks) = export(&workspace, None);
+        assert_eq!(json, serde_json::to_value(&workspace).unwrap());
+        assert_eq!(chunks, 1);
+
+        workspace.with_trx(|mut t| {
+            let page = t.create("page", "affine:page");
+            for i in 0..10 {
+                let block = t.create(format!("block{i}"), "affine:paragraph");
+                block.set(&mut t.trx, "text", format!("text {i}"));
+                page.push_children(&mut t.trx, &block);
+            }
+            let nested = t.create("nested", "affine:paragraph");
+            workspace
+                .get(&t.trx, "block0")
+                .unwrap()
+                .push_children(&mut t.trx, &nested);
+            t.create("other", "affine:page");
+        });
+
+        // 13 blocks and their history, 3 per chunk
+        let (json, chunks) = export(&workspace, None);
+        assert_eq!(json, serde_json::to_value(&workspace).unwrap());
+        assert_eq!(chunks, 9);
+
+        let (json, _) = export(&workspace, Some("block0"));
+        let blocks = json["blocks"].as_object().unwrap();
+        assert_eq!(blocks.keys().collect::<Vec<_>>(), vec!["block0", "nested"]);
+        assert_eq!(json["updated"].as_object().unwrap().len(), 2);
+
+        assert!(matches!(
+            workspace.clone().json_export(Some("missing"), 3),
+            Err(JwstError::BlockNotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,253 @@
+use super::*;
+use lib0::any::Any;
+use std::collections::{BTreeSet, HashMap};
+use thiserror::Error;
+use yrs::{types::Value, Array, ArrayPrelim, Map, ReadTxn, TransactionMut};
+
+#[derive(Debug, Error)]
+pub enum LinkError {
+    #[error("block {0} doesn't exist")]
+    BlockNotFound(String),
+    #[error("link from {0} has no relation type")]
+    MissingRelation(String),
+}
+
+/// A relationship between two blocks other than nesting, e.g. a page mentioning another one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockLink {
+    pub from: String,
+    pub to: String,
+    pub rel_type: String,
+    /// Milliseconds since the unix epoch.
+    pub created_at: u64,
+}
+
+impl BlockLink {
+    pub(super) fn from_any(from: &str, value: Value) -> Option<Self> {
+        let Value::Any(Any::Map(entry)) = value else {
+            return None;
+        };
+        match (
+            entry.get("to"),
+            entry.get("rel_type"),
+            entry.get("created_at"),
+        ) {
+            (Some(Any::String(to)), Some(Any::String(rel_type)), created_at) => Some(Self {
+                from: from.to_owned(),
+                to: to.to_string(),
+                rel_type: rel_type.to_string(),
+                created_at: match created_at {
+                    Some(Any::Number(n)) => *n as u64,
+                    Some(Any::BigInt(n)) => *n as u64,
+                    _ => 0,
+                },
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Workspace {
+    /// Record that `from` links to `to`. Links are stored in the `space:links` map,
+    /// keyed by the id of the source block. Linking the same blocks with the same
+    /// relation again keeps the existing link.
+    pub fn link_blocks(
+        &self,
+        trx: &mut TransactionMut,
+        from: &str,
+        to: &str,
+        rel_type: &str,
+    ) -> Result<(), LinkError> {
+        for id in [from, to] {
+            if !self.exists(trx, id) {
+                return Err(LinkError::BlockNotFound(id.to_owned()));
+            }
+        }
+        if rel_type.is_empty() {
+            return Err(LinkError::MissingRelation(from.to_owned()));
+        }
+
+        let links = match self.links.get(trx, from) {
+            Some(Value::YArray(links)) => links,
+            _ => self
+                .links
+                .insert(trx, from, ArrayPrelim::<Vec<Any>, Any>::from(vec![])),
+        };
+        if links
+            .iter(trx)
+            .filter_map(|link| BlockLink::from_any(from, link))
+            .any(|link| link.to == to && link.rel_type == rel_type)
+        {
+            return Ok(());
+        }
+
+        trace!("link block {} to {}: {}", from, to, rel_type);
+        links.push_back(
+            trx,
+            Any::Map(Box::new(HashMap::from([
+                ("to".to_owned(), Any::String(Box::from(to))),
+                ("rel_type".to_owned(), Any::String(Box::from(rel_type))),
+                (
+                    "created_at".to_owned(),
+                    Any::Number(self.clock.now() as f64),
+                ),
+            ]))),
+        );
+
+        Ok(())
+    }
+
+    /// Links from a block to other blocks, in link order. Links to removed blocks are skipped.
+    pub fn block_links<T: ReadTxn>(&self, trx: &T, block_id: &str) -> Vec<BlockLink> {
+        match self.links.get(trx, block_id) {
+            Some(Value::YArray(links)) if self.exists(trx, block_id) => links
+                .iter(trx)
+                .filter_map(|link| BlockLink::from_any(block_id, link))
+                .filter(|link| self.exists(trx, &link.to))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Links from other blocks to a block, ordered by source block and link order.
+    /// Links from removed blocks are skipped. Like [Workspace::backlinks], links made
+    /// in `trx` itself are not included yet.
+    pub fn block_backlinks<T: ReadTxn>(&self, trx: &T, block_id: &str) -> Vec<BlockLink> {
+        use plugins::BacklinksPluginImpl;
+
+        if !self.exists(trx, block_id) {
+            return vec![];
+        }
+
+        let links_to = |from: &str| match self.links.get(trx, from) {
+            Some(Value::YArray(links)) if self.exists(trx, from) => links
+                .iter(trx)
+                .filter_map(|link| BlockLink::from_any(from, link))
+                .filter(|link| link.to == block_id)
+                .collect(),
+            _ => vec![],
+        };
+
+        // the backlinks index knows the sources, without it all links are scanned
+        let sources = self
+            .with_plugin::<BacklinksPluginImpl, _>(|plugin| plugin.backlinks(self, trx, block_id))
+            .map(|sources| {
+                sources
+                    .into_iter()
+                    .map(|source| source.block_id)
+                    .collect::<BTreeSet<_>>()
+            })
+            .unwrap_or_else(|| self.links.keys(trx).map(|from| from.to_owned()).collect());
+        sources.iter().flat_map(|from| links_to(from)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_blocks() {
+        let workspace = Workspace::new("test");
+        let targets = |links: Vec<BlockLink>| {
+            links
+                .into_iter()
+                .map(|link| (link.from, link.to, link.rel_type))
+                .collect::<Vec<_>>()
+        };
+        let link = |from: &str, to: &str, rel_type: &str| {
+            (from.to_owned(), to.to_owned(), rel_type.to_owned())
+        };
+
+        workspace.with_trx(|mut t| {
+            t.create("a", "affine:page");
+            t.create("b", "affine:page");
+            t.create("c", "affine:page");
+
+            workspace
+                .link_blocks(&mut t.trx, "a", "b", "mention")
+                .unwrap();
+            workspace
+                .link_blocks(&mut t.trx, "a", "c", "mention")
+                .unwrap();
+            workspace
+                .link_blocks(&mut t.trx, "c", "b", "reference")
+                .unwrap();
+            // linking twice keeps a single link
+            workspace
+                .link_blocks(&mut t.trx, "a", "b", "mention")
+                .unwrap();
+            workspace
+                .link_blocks(&mut t.trx, "a", "b", "reference")
+                .unwrap();
+
+            assert_eq!(
+                targets(workspace.block_links(&t.trx, "a")),
+                vec![
+                    link("a", "b", "mention"),
+                    link("a", "c", "mention"),
+                    link("a", "b", "reference"),
+                ]
+            );
+        });
+
+        workspace.with_trx(|mut t| {
+            assert_eq!(
+                targets(workspace.block_backlinks(&t.trx, "b")),
+                vec![
+                    link("a", "b", "mention"),
+                    link("a", "b", "reference"),
+                    link("c", "b", "reference"),
+                ]
+            );
+            // the backlinks index has the links too
+            assert_eq!(
+                workspace
+                    .backlinks(&t.trx, "b")
+                    .into_iter()
+                    .map(|source| (source.block_id, source.property))
+                    .collect::<Vec<_>>(),
+                vec![
+                    ("a".to_owned(), "mention".to_owned()),
+                    ("a".to_owned(), "reference".to_owned()),
+                    ("c".to_owned(), "reference".to_owned()),
+                ]
+            );
+            assert!(workspace.block_links(&t.trx, "b").is_empty());
+            assert!(workspace.block_links(&t.trx, "a")[0].created_at > 0);
+
+            t.remove("c");
+        });
+
+        workspace.with_trx(|mut t| {
+            assert_eq!(workspace.block_links(&t.trx, "a").len(), 2);
+            assert_eq!(workspace.block_backlinks(&t.trx, "b").len(), 2);
+            assert_eq!(workspace.backlinks(&t.trx, "b").len(), 2);
+
+            assert!(matches!(
+                workspace.link_blocks(&mut t.trx, "a", "missing", "mention"),
+                Err(LinkError::BlockNotFound(id)) if id == "missing"
+            ));
+            assert!(matches!(
+                workspace.link_blocks(&mut t.trx, "a", "b", ""),
+                Err(LinkError::MissingRelation(_))
+            ));
+        });
+
+        // without the backlinks index, all links are scanned
+        let workspace = WorkspaceBuilder::new("test")
+            .plugins(WorkspacePlugins::none())
+            .build();
+        workspace.with_trx(|mut t| {
+            t.create("a", "affine:page");
+            t.create("b", "affine:page");
+            workspace
+                .link_blocks(&mut t.trx, "a", "b", "mention")
+                .unwrap();
+            assert_eq!(
+                targets(workspace.block_backlinks(&t.trx, "b")),
+                vec![link("a", "b", "mention")]
+            );
+        });
+    }
+}
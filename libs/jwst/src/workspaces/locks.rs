@@ -0,0 +1,146 @@
+use super::*;
+use serde_json::{json, Map as JsonMap, Value as JsonValue};
+use std::time::Duration;
+
+// key of the awareness state holding the locks taken by a client
+const LOCKS: &str = "locks";
+
+/// Advisory lock of a block, shared with peers through awareness.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockLock {
+    /// Client holding the lock.
+    pub client: u64,
+    /// Unix timestamp in milliseconds after which the lock is released.
+    pub expires: i64,
+}
+
+impl BlockLock {
+    fn parse(state: &JsonValue, block_id: &str) -> Option<Self> {
+        let lock = state.get(LOCKS)?.get(block_id)?;
+        Some(Self {
+            client: lock.get("client")?.as_u64()?,
+            expires: lock.get("expires")?.as_i64()?,
+        })
+    }
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+fn into_object(value: JsonValue) -> Option<JsonMap<String, JsonValue>> {
+    match value {
+        JsonValue::Object(object) => Some(object),
+        _ => None,
+    }
+}
+
+impl Workspace {
+    /// Current holder of the lock of a block, expired locks are ignored.
+    /// If peers locked the block concurrently, the lowest client id wins.
+    pub(crate) fn block_lock(&self, block_id: &str) -> Option<BlockLock> {
+        let now = now();
+        self.awareness_states()
+            .values()
+            .filter_map(|state| BlockLock::parse(state, block_id))
+            .filter(|lock| lock.expires > now)
+            .min_by_key(|lock| lock.client)
+    }
+
+    pub(crate) fn try_lock_block(&self, block_id: &str, client_id: u64, ttl: Duration) -> bool {
+        if matches!(self.block_lock(block_id), Some(lock) if lock.client != client_id) {
+            return false;
+        }
+
+        self.update_local_locks(|locks| {
+            locks.insert(
+                block_id.into(),
+                json!({ "client": client_id, "expires": now() + ttl.as_millis() as i64 }),
+            );
+        });
+        true
+    }
+
+    pub(crate) fn unlock_block(&self, block_id: &str, client_id: u64) {
+        self.update_local_locks(|locks| {
+            let held = locks
+                .get(block_id)
+                .and_then(|lock| lock.get("client")?.as_u64())
+                == Some(client_id);
+            if held {
+                locks.remove(block_id);
+            }
+        });
+    }
+
+    // locks live in the local awareness state, so they are broadcast to peers
+    // and dropped with the state when the client disconnects
+    fn update_local_locks(&self, f: impl FnOnce(&mut JsonMap<String, JsonValue>)) {
+        let client_id = self.client_id();
+        let mut awareness = self.awareness.write().unwrap();
+
+        let mut state = awareness
+            .clients()
+            .get(&client_id)
+            .and_then(|state| serde_json::from_str(state).ok())
+            .and_then(into_object)
+            .unwrap_or_default();
+        let mut locks = state
+            .remove(LOCKS)
+            .and_then(into_object)
+            .unwrap_or_default();
+
+        let now = now();
+        locks.retain(|_, lock| {
+            lock.get("expires")
+                .and_then(|expires| expires.as_i64())
+                .map_or(false, |expires| expires > now)
+        });
+        f(&mut locks);
+
+        state.insert(LOCKS.into(), JsonValue::Object(locks));
+        awareness.set_local_state(JsonValue::Object(state).to_string());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use yrs::Doc;
+
+    #[test]
+    fn block_lock() {
+        let local = Workspace::from_doc(Doc::with_client_id(1), "test");
+        let mut remote = Workspace::from_doc(Doc::with_client_id(2), "test");
+        let ttl = Duration::from_secs(10);
+
+        local
+            .awareness
+            .write()
+            .unwrap()
+            .set_local_state(r#"{"user":{"name":"alice"}}"#);
+
+        let block = local.with_trx(|mut t| t.create("block", "text"));
+        assert!(block.try_lock(&local, 1, ttl));
+        assert_eq!(block.locked_by(&local).map(|lock| lock.client), Some(1));
+        // the holder can refresh its lock
+        assert!(block.try_lock(&local, 1, ttl));
+        // the rest of the state is kept
+        assert_eq!(local.awareness_states()[&1]["user"]["name"], "alice");
+
+        // peers see the lock through awareness
+        remote.sync_decode_message(&local.sync_init_message().unwrap());
+        assert_eq!(remote.block_lock("block").map(|lock| lock.client), Some(1));
+        assert!(!remote.try_lock_block("block", 2, ttl));
+
+        block.unlock(&local, 1);
+        assert_eq!(block.locked_by(&local), None);
+        remote.sync_decode_message(&local.sync_init_message().unwrap());
+        assert!(remote.try_lock_block("block", 2, ttl));
+
+        // expired locks are released
+        assert!(local.try_lock_block("other", 1, Duration::ZERO));
+        assert_eq!(local.block_lock("other"), None);
+        assert!(local.try_lock_block("other", 3, ttl));
+    }
+}
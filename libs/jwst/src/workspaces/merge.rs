@@ -0,0 +1,170 @@
+use super::*;
+use thiserror::Error;
+use yrs::{
+    types::Value, updates::decoder::Decode, Map, ReadTxn, Transact, TransactionAcqError, Update,
+};
+
+#[derive(Debug, Error)]
+pub enum MergeError {
+    #[error("can't merge metadata of workspace {found} into {expected}")]
+    WorkspaceMismatch { expected: String, found: String },
+    #[error(transparent)]
+    Transaction(#[from] TransactionAcqError),
+}
+
+#[derive(Debug, Error)]
+pub enum CompactError {
+    #[error("can't compact workspace {found} into {expected}")]
+    WorkspaceMismatch { expected: String, found: String },
+    #[error(transparent)]
+    Transaction(#[from] TransactionAcqError),
+    #[error("failed to decode the compacted update")]
+    Decode(#[from] lib0::error::Error),
+}
+
+impl Workspace {
+    /// Bring the metadata of `other` into this workspace without touching the blocks.
+    ///
+    /// Yjs updates always cover the whole doc, so instead of applying a state vector
+    /// diff of `space:meta` the entries are compared one by one, and the values of `other`
+    /// win where they differ. Entries only present here are kept. Nested shared types like
+    /// the page list are edited through their own operations and are left to a full sync.
+    pub fn merge_metadata(&self, other: &Workspace) -> Result<(), MergeError> {
+        if self.id != other.id {
+            return Err(MergeError::WorkspaceMismatch {
+                expected: self.id.clone(),
+                found: other.id.clone(),
+            });
+        }
+
+        let entries = {
+            let doc = other.doc();
+            let trx = doc.try_transact()?;
+            other
+                .metadata
+                .iter(&trx)
+                .filter_map(|(key, value)| match value {
+                    Value::Any(any) => Some((key.to_owned(), any)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let doc = self.doc();
+        let mut trx = doc.try_transact_mut()?;
+        for (key, value) in entries {
+            let current = self.metadata.get(&trx, &key);
+            if !matches!(current, Some(Value::Any(current)) if current == value) {
+                trace!("merge metadata: {}", key);
+                self.metadata.insert(&mut trx, key, value);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Bring `target` up to date with this workspace in a single update, e.g. to hand a
+    /// workspace over to another server without replaying its update history.
+    ///
+    /// Only what `target` is missing according to its state vector is encoded, the full
+    /// state if it's empty. Changes only present in `target` are kept.
+    pub fn compact_to(&self, target: &mut Workspace) -> Result<(), CompactError> {
+        if self.id != target.id {
+            return Err(CompactError::WorkspaceMismatch {
+                expected: target.id.clone(),
+                found: self.id.clone(),
+            });
+        }
+
+        let state_vector = target.doc().try_transact()?.state_vector();
+        let update = self
+            .doc()
+            .try_transact()?
+            .encode_state_as_update_v1(&state_vector);
+        trace!("compact {} into {} bytes", self.id, update.len());
+
+        let update = Update::decode_v1(&update)?;
+        target.doc().try_transact_mut()?.apply_update(update);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lib0::any::Any;
+
+    #[test]
+    fn merge_metadata() {
+        let local = Workspace::new("test");
+        local.with_trx(|mut t| {
+            t.set_metadata("name", "local").unwrap();
+            t.set_metadata("description", "kept").unwrap();
+        });
+
+        let remote = Workspace::new("test");
+        remote.with_trx(|mut t| {
+            t.set_metadata("name", "remote").unwrap();
+            t.set_metadata("avatar", "hash").unwrap();
+            t.create("block", "affine:page");
+        });
+
+        local.merge_metadata(&remote).unwrap();
+        local.with_trx(|t| {
+            let get = |key| local.metadata.get(&t.trx, key).map(|v| v.to_string(&t.trx));
+            assert_eq!(get("name"), Some("remote".into()));
+            assert_eq!(get("avatar"), Some("hash".into()));
+            assert_eq!(get("description"), Some("kept".into()));
+            // blocks are not synced
+            assert!(!local.exists(&t.trx, "block"));
+        });
+
+        assert!(matches!(
+            local.merge_metadata(&Workspace::new("other")),
+            Err(MergeError::WorkspaceMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn compact_to() {
+        let source = Workspace::new("test");
+        source.with_trx(|mut t| {
+            t.set_metadata("name", "source").unwrap();
+            let page = t.create("page", "affine:page");
+            let text = t.create("text", "affine:text");
+            page.push_children(&mut t.trx, &text);
+        });
+        // the history of many small updates is compacted
+        for i in 0..10 {
+            source.with_trx(|mut t| {
+                let text = t.ws.get(&t.trx, "text").unwrap();
+                text.set(&mut t.trx, "content", i.to_string());
+            });
+        }
+
+        let mut empty = Workspace::new("test");
+        source.compact_to(&mut empty).unwrap();
+        assert_eq!(empty.sync_migration(), source.sync_migration());
+
+        // a target with a part of the history only gets the rest
+        let mut partial = empty;
+        partial.with_trx(|mut t| {
+            t.create("local", "affine:text");
+        });
+        source.with_trx(|mut t| {
+            t.create("remote", "affine:text");
+        });
+        source.compact_to(&mut partial).unwrap();
+        partial.with_trx(|t| {
+            assert!(partial.exists(&t.trx, "local"));
+            assert!(partial.exists(&t.trx, "remote"));
+            let text = partial.get(&t.trx, "text").unwrap();
+            assert_eq!(text.get(&t.trx, "content"), Some(Any::String("9".into())));
+        });
+
+        assert!(matches!(
+            source.compact_to(&mut Workspace::new("other")),
+            Err(CompactError::WorkspaceMismatch { .. })
+        ));
+    }
+}
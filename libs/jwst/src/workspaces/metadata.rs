@@ -1,23 +1,52 @@
-use std::collections::HashMap;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    pin::Pin,
+    task::{Context, Poll},
+};
 
+use super::{
+    info, MapSubscription, PermissionError, Workspace, WorkspacePermission, WorkspaceTransaction,
+};
+use futures::Stream;
 use lib0::any::Any;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use yrs::{Map, MapRef, Transaction};
+use thiserror::Error;
+use tokio::sync::mpsc::unbounded_channel;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use yrs::{
+    types::{EntryChange, ToJson},
+    Map, MapRef, ReadTxn, Transaction,
+};
 
-#[derive(Debug, Clone, JsonSchema, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, JsonSchema, Serialize, Deserialize)]
 pub struct WorkspaceMetadata {
     pub name: Option<String>,
-    // pub avatar: Option<String>,
+    pub avatar: Option<String>,
 }
 
-impl From<(&'_ Transaction<'_>, MapRef)> for WorkspaceMetadata {
-    fn from((trx, map): (&Transaction, MapRef)) -> Self {
+impl WorkspaceMetadata {
+    fn read<T: ReadTxn>(trx: &T, map: &MapRef) -> Self {
         Self {
             name: map.get(trx, "name").map(|s| s.to_string(trx)),
-            // avatar: map.get(trx, "avatar").map(|s| s.to_string(trx)),
+            avatar: map.get(trx, "avatar").map(|s| s.to_string(trx)),
         }
     }
+
+    /// Revision of the name and avatar, it changes whenever one of them does.
+    /// Conditional edits are based on it, see [WorkspaceTransaction::set_name].
+    pub fn revision(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        (&self.name, &self.avatar).hash(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+}
+
+impl From<(&'_ Transaction<'_>, MapRef)> for WorkspaceMetadata {
+    fn from((trx, map): (&Transaction, MapRef)) -> Self {
+        Self::read(trx, &map)
+    }
 }
 
 impl From<WorkspaceMetadata> for Any {
@@ -26,9 +55,239 @@ impl From<WorkspaceMetadata> for Any {
         if let Some(name) = val.name {
             map.insert("name".to_owned(), name.into());
         }
-        // if let Some(avatar) = val.avatar {
-        //     map.insert("avatar".to_owned(), avatar.into());
-        // }
+        if let Some(avatar) = val.avatar {
+            map.insert("avatar".to_owned(), avatar.into());
+        }
         Any::Map(map.into())
     }
 }
+
+#[derive(Debug, Error)]
+pub enum MetadataError {
+    /// The metadata is not at the expected revision anymore, someone else edited it.
+    #[error("workspace metadata changed since revision {expected}")]
+    Conflict {
+        expected: String,
+        current: WorkspaceMetadata,
+    },
+    #[error(transparent)]
+    Permission(#[from] PermissionError),
+}
+
+/// Keys of the workspace metadata changed by a transaction, see [Workspace::subscribe_to_metadata].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetadataChangeEvent {
+    /// Inserted, updated and removed keys, sorted.
+    pub changed_keys: Vec<String>,
+    /// Values of the inserted and updated keys, removed keys are left out.
+    pub new_values: HashMap<String, Any>,
+}
+
+/// Stream of [MetadataChangeEvent], the metadata is observed until it's dropped.
+pub struct MetadataSubscription {
+    events: UnboundedReceiverStream<MetadataChangeEvent>,
+    _sub: MapSubscription,
+}
+
+impl Stream for MetadataSubscription {
+    type Item = MetadataChangeEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.events).poll_next(cx)
+    }
+}
+
+impl Workspace {
+    /// Stream the changes of the workspace metadata, e.g. its name or avatar, without
+    /// observing the blocks. Changes are sent once their transaction is committed.
+    pub fn subscribe_to_metadata(&mut self) -> MetadataSubscription {
+        let (tx, rx) = unbounded_channel();
+        let sub = self.observe_metadata(move |trx, event| {
+            let mut changed_keys = vec![];
+            let mut new_values = HashMap::new();
+            for (key, change) in event.keys(trx) {
+                changed_keys.push(key.to_string());
+                match change {
+                    EntryChange::Inserted(value) | EntryChange::Updated(_, value) => {
+                        new_values.insert(key.to_string(), value.to_json(trx));
+                    }
+                    EntryChange::Removed(_) => {}
+                }
+            }
+            changed_keys.sort();
+            // the stream was dropped before the subscription
+            let _ = tx.send(MetadataChangeEvent {
+                changed_keys,
+                new_values,
+            });
+        });
+        MetadataSubscription {
+            events: UnboundedReceiverStream::new(rx),
+            _sub: sub,
+        }
+    }
+}
+
+impl WorkspaceTransaction<'_> {
+    /// Rename the workspace and return the metadata afterwards. With `revision`, the name
+    /// is only changed if the metadata is still at this [WorkspaceMetadata::revision],
+    /// otherwise the current metadata is returned in [MetadataError::Conflict].
+    pub fn set_name(
+        &mut self,
+        name: &str,
+        revision: Option<&str>,
+    ) -> Result<WorkspaceMetadata, MetadataError> {
+        self.set_metadata_at("name", name, revision)
+    }
+
+    /// Change the avatar of the workspace, conditional like [WorkspaceTransaction::set_name].
+    pub fn set_avatar(
+        &mut self,
+        avatar: &str,
+        revision: Option<&str>,
+    ) -> Result<WorkspaceMetadata, MetadataError> {
+        self.set_metadata_at("avatar", avatar, revision)
+    }
+
+    // the revision is checked in the same transaction as the write, so concurrent
+    // edits of the workspace are applied one after another and only one of them matches
+    fn set_metadata_at(
+        &mut self,
+        key: &str,
+        value: &str,
+        revision: Option<&str>,
+    ) -> Result<WorkspaceMetadata, MetadataError> {
+        self.check_permission(WorkspacePermission::Write)?;
+        let current = WorkspaceMetadata::read(&self.trx, &self.ws.metadata);
+        if let Some(expected) = revision {
+            if expected != current.revision() {
+                return Err(MetadataError::Conflict {
+                    expected: expected.to_owned(),
+                    current,
+                });
+            }
+        }
+
+        info!("set metadata: {}", key);
+        self.ws.metadata.insert(&mut self.trx, key, value);
+        Ok(WorkspaceMetadata::read(&self.trx, &self.ws.metadata))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{FutureExt, StreamExt};
+    use yrs::Transact;
+
+    #[test]
+    fn metadata_revision() {
+        let workspace = Workspace::new("test");
+        let initial = workspace.metadata();
+        assert_eq!(initial.revision(), workspace.metadata().revision());
+
+        let renamed = workspace
+            .with_trx(|mut t| t.set_name("renamed", Some(&initial.revision())))
+            .unwrap();
+        assert_eq!(renamed.name.as_deref(), Some("renamed"));
+        assert_eq!(renamed, workspace.metadata());
+        assert_ne!(renamed.revision(), initial.revision());
+
+        // unconditional edits always apply
+        let updated = workspace
+            .with_trx(|mut t| t.set_avatar("hash", None))
+            .unwrap();
+        assert_eq!(updated.avatar.as_deref(), Some("hash"));
+
+        // based on an outdated revision
+        match workspace.with_trx(|mut t| t.set_name("stale", Some(&renamed.revision()))) {
+            Err(MetadataError::Conflict { current, .. }) => assert_eq!(current, updated),
+            other => panic!("unexpected result: {other:?}"),
+        }
+        assert_eq!(workspace.metadata(), updated);
+    }
+
+    #[test]
+    fn concurrent_conditional_rename() {
+        let workspace = Workspace::new("test");
+        workspace
+            .with_trx(|mut t| t.set_metadata("name", "initial"))
+            .unwrap();
+        let revision = workspace.metadata().revision();
+
+        let barrier = std::sync::Arc::new(std::sync::Barrier::new(2));
+        let handles = ["first", "second"].map(|name| {
+            let workspace = workspace.clone();
+            let revision = revision.clone();
+            let barrier = barrier.clone();
+            std::thread::spawn(move || {
+                barrier.wait();
+                // a doc only has one transaction at a time, wait for the other to commit
+                loop {
+                    if let Some(result) =
+                        workspace.try_with_trx(|mut t| t.set_name(name, Some(&revision)))
+                    {
+                        break result;
+                    }
+                    std::thread::yield_now();
+                }
+            })
+        });
+        let results = handles.map(|handle| handle.join().unwrap());
+
+        let succeeded = results
+            .iter()
+            .filter_map(|result| result.as_ref().ok())
+            .collect::<Vec<_>>();
+        assert_eq!(succeeded.len(), 1);
+        let winner = succeeded[0].clone();
+        assert_eq!(workspace.metadata(), winner);
+
+        // the loser gets the metadata written by the winner
+        let conflict = results.into_iter().find_map(Result::err).unwrap();
+        match conflict {
+            MetadataError::Conflict { expected, current } => {
+                assert_eq!(expected, revision);
+                assert_eq!(current, winner);
+            }
+            other => panic!("unexpected error: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn subscribe_to_metadata() {
+        let mut workspace = Workspace::new("test");
+        let mut events = workspace.subscribe_to_metadata();
+
+        workspace.with_trx(|mut t| {
+            t.set_metadata("name", "test").unwrap();
+            t.set_metadata("avatar", "hash").unwrap();
+        });
+        assert_eq!(
+            events.next().now_or_never().flatten(),
+            Some(MetadataChangeEvent {
+                changed_keys: vec!["avatar".into(), "name".into()],
+                new_values: HashMap::from([
+                    ("avatar".into(), Any::String("hash".into())),
+                    ("name".into(), Any::String("test".into())),
+                ]),
+            })
+        );
+
+        // block changes are not sent
+        workspace.with_trx(|mut t| {
+            t.create("block", "affine:text");
+        });
+        assert!(events.next().now_or_never().is_none());
+
+        let doc = workspace.doc();
+        workspace.metadata.remove(&mut doc.transact_mut(), "avatar");
+        assert_eq!(
+            events.next().now_or_never().flatten(),
+            Some(MetadataChangeEvent {
+                changed_keys: vec!["avatar".into()],
+                new_values: HashMap::new(),
+            })
+        );
+    }
+}
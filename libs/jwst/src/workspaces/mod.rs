@@ -1,13 +1,60 @@
+mod blob_refs;
+mod builder;
+mod changes;
+mod conflicts;
+mod content_stats;
+mod export;
+mod json_export;
+mod links;
+mod locks;
+mod merge;
 mod metadata;
+mod patch;
+mod permissions;
+mod pins;
 mod plugins;
+mod selection;
+#[cfg(feature = "workspace-export-sqlite")]
+mod sqlite;
+mod sync_validation;
+mod system_keys;
+mod timestamps;
 mod transaction;
 mod workspace;
 
-use super::{error, info, trace, Block};
-use metadata::WorkspaceMetadata;
+use super::{error, info, trace, warn, Block, JwstError, JwstResult};
 use plugins::PluginMap;
 
+pub use blob_refs::{BlobReference, GcError, DEFAULT_BLOB_PROPERTY_KEYS};
+pub use builder::WorkspaceBuilder;
+pub use changes::{
+    BlockChange, BlockChangeKind, BlockChanges, ChangesSubscription, ChildrenSplice,
+    WorkspaceChanges,
+};
+pub use conflicts::{ApplyError, ConflictResolver};
+pub use content_stats::ContentStats;
+pub use export::ExportError;
+pub use json_export::JsonExport;
+pub use links::{BlockLink, LinkError};
+pub use locks::BlockLock;
+pub use merge::{CompactError, MergeError};
+pub use metadata::{MetadataChangeEvent, MetadataError, MetadataSubscription, WorkspaceMetadata};
+pub use patch::{PatchError, PatchFailure, PatchOperation};
+pub use permissions::{PermissionError, UserTransaction, WorkspacePermission, WorkspaceUser};
+pub use plugins::{
+    BlockRef, PluginError, PluginImpl, PluginRegister, WorkspacePlugins, DEFAULT_LINK_PROPERTY_KEYS,
+};
 #[cfg(feature = "workspace-search")]
-pub use plugins::{SearchResult, SearchResults};
-pub use transaction::WorkspaceTransaction;
-pub use workspace::{MapSubscription, Workspace};
+pub use plugins::{SearchFilter, SearchOptions, SearchResult, SearchResults};
+pub use selection::SelectionError;
+#[cfg(feature = "workspace-export-sqlite")]
+pub use sqlite::{ImportError, SQLITE_SCHEMA_VERSION};
+pub use sync_validation::{SyncPeer, SyncValidationError, DEFAULT_MAX_MESSAGE_BYTES};
+pub use system_keys::SystemKeyPolicy;
+pub(crate) use system_keys::{insert_system_key, remove_system_key};
+pub use timestamps::{TimestampRepair, WorkspaceClock, MAX_CLOCK_SKEW};
+pub use transaction::{InsertError, WorkspaceTransaction};
+pub use workspace::{
+    MapSubscription, ObserverPanicPolicy, SerializeOptions, Workspace, WorkspaceStats,
+    DEFAULT_MAX_BLOCK_DEPTH,
+};
@@ -0,0 +1,727 @@
+use super::*;
+use crate::{block::json_eq, constants::sys, PropertyKey};
+use lib0::any::Any;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+use yrs::{ReadTxn, StateVector};
+
+/// An edit of the blocks in the style of JSON Patch (RFC 6902), for clients that can't
+/// sync the doc themselves. Paths point to the blocks by id:
+/// - `/{block}`: a block, the value holds its properties and `sys:flavor`.
+/// - `/{block}/{key}`: a property of a block.
+/// - `/{block}/sys:children/{index}`: a child of a block by position, `-` appends. The value
+///   is the id of the child, adding it moves it from its current parent.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    Add {
+        path: String,
+        value: JsonValue,
+    },
+    Remove {
+        path: String,
+    },
+    Replace {
+        path: String,
+        value: JsonValue,
+    },
+    /// Fails the patch if the value at `path` is different, e.g. to only change a property
+    /// nobody changed since the client read it.
+    Test {
+        path: String,
+        value: JsonValue,
+    },
+}
+
+impl PatchOperation {
+    fn path(&self) -> &str {
+        match self {
+            Self::Add { path, .. }
+            | Self::Remove { path }
+            | Self::Replace { path, .. }
+            | Self::Test { path, .. } => path,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum PatchFailure {
+    #[error("invalid path")]
+    InvalidPath,
+    #[error("operation not supported for the path")]
+    Unsupported,
+    #[error("nothing at the path")]
+    NotFound,
+    #[error("invalid value")]
+    InvalidValue,
+    #[error("value is different")]
+    TestFailed,
+    #[error(transparent)]
+    Rejected(#[from] JwstError),
+}
+
+#[derive(Debug, Error)]
+pub enum PatchError {
+    #[error("operation {index} on {path} failed: {reason}")]
+    Operation {
+        index: usize,
+        path: String,
+        reason: PatchFailure,
+    },
+}
+
+enum Target {
+    Block(String),
+    Property(String, String),
+    /// `None` is the end of the children.
+    Child(String, Option<usize>),
+}
+
+impl Target {
+    // JSON pointer, `~1` and `~0` escape `/` and `~`
+    fn parse(path: &str) -> Option<Self> {
+        let segments = path
+            .strip_prefix('/')?
+            .split('/')
+            .map(|segment| segment.replace("~1", "/").replace("~0", "~"))
+            .collect::<Vec<_>>();
+        match segments.as_slice() {
+            [block] if !block.is_empty() => Some(Self::Block(block.clone())),
+            [block, key] => Some(Self::Property(block.clone(), key.clone())),
+            [block, children, index] if children == sys::CHILDREN => {
+                let index = match index.as_str() {
+                    "-" => None,
+                    index => Some(index.parse().ok()?),
+                };
+                Some(Self::Child(block.clone(), index))
+            }
+            _ => None,
+        }
+    }
+}
+
+type PatchResult = Result<(), PatchFailure>;
+
+fn property_value(value: &JsonValue) -> Result<Any, PatchFailure> {
+    match serde_json::from_value::<Any>(value.clone()) {
+        // blocks only hold scalar properties, removing is a separate operation
+        Ok(Any::Null | Any::Undefined | Any::Array(_) | Any::Map(_) | Any::Buffer(_)) | Err(_) => {
+            Err(PatchFailure::InvalidValue)
+        }
+        Ok(value) => Ok(value),
+    }
+}
+
+/// The blocks a patch runs on: a [Preview] to check the whole patch first, then the
+/// transaction itself. Writes are only made to blocks that exist.
+trait PatchTarget {
+    fn workspace(&self) -> &Workspace;
+    fn exists(&mut self, id: &str) -> bool;
+    fn property(&mut self, id: &str, key: &str) -> Option<Any>;
+    /// Names of the properties, without the system ones.
+    fn keys(&mut self, id: &str) -> Vec<String>;
+    fn children(&mut self, id: &str) -> Vec<String>;
+    fn parent(&mut self, id: &str) -> Option<String>;
+    fn create(&mut self, id: &str, flavor: &str) -> JwstResult<()>;
+    fn set(&mut self, id: &str, key: &str, value: Any) -> JwstResult<()>;
+    fn unset(&mut self, id: &str, key: &str) -> JwstResult<()>;
+    /// Remove a block, detaching it from its parent.
+    fn remove(&mut self, id: &str) -> JwstResult<()>;
+    fn detach(&mut self, parent: &str, child: &str);
+    /// Move `child` into `parent` at `index`, `None` appends.
+    fn attach(&mut self, parent: &str, child: &str, index: Option<usize>) -> JwstResult<()>;
+}
+
+impl PatchTarget for WorkspaceTransaction<'_> {
+    fn workspace(&self) -> &Workspace {
+        self.ws
+    }
+
+    fn exists(&mut self, id: &str) -> bool {
+        self.ws.exists(&self.trx, id)
+    }
+
+    fn property(&mut self, id: &str, key: &str) -> Option<Any> {
+        self.ws.get(&self.trx, id)?.get(&self.trx, key)
+    }
+
+    fn keys(&mut self, id: &str) -> Vec<String> {
+        self.ws
+            .get(&self.trx, id)
+            .map(|block| block.content(&self.trx).into_keys().collect())
+            .unwrap_or_default()
+    }
+
+    fn children(&mut self, id: &str) -> Vec<String> {
+        self.ws
+            .get(&self.trx, id)
+            .map(|block| block.children(&self.trx))
+            .unwrap_or_default()
+    }
+
+    fn parent(&mut self, id: &str) -> Option<String> {
+        self.ws.get(&self.trx, id)?.parent(&self.trx)
+    }
+
+    fn create(&mut self, id: &str, flavor: &str) -> JwstResult<()> {
+        self.try_create(id, flavor).map(|_| ())
+    }
+
+    fn set(&mut self, id: &str, key: &str, value: Any) -> JwstResult<()> {
+        match self.ws.get(&self.trx, id) {
+            Some(block) => block.try_set(&mut self.trx, key, value),
+            None => Ok(()),
+        }
+    }
+
+    fn unset(&mut self, id: &str, key: &str) -> JwstResult<()> {
+        match self.ws.get(&self.trx, id) {
+            Some(block) => block.try_unset(&mut self.trx, key),
+            None => Ok(()),
+        }
+    }
+
+    fn remove(&mut self, id: &str) -> JwstResult<()> {
+        if let Some(parent) = self.parent(id) {
+            self.detach(&parent, id);
+        }
+        self.try_remove(id).map(|_| ())
+    }
+
+    fn detach(&mut self, parent: &str, child: &str) {
+        if let (Some(parent), Some(child)) = (
+            self.ws.get(&self.trx, parent),
+            self.ws.get(&self.trx, child),
+        ) {
+            parent.remove_children(&mut self.trx, &child);
+        }
+    }
+
+    fn attach(&mut self, parent: &str, child: &str, index: Option<usize>) -> JwstResult<()> {
+        match (
+            self.ws.get(&self.trx, parent),
+            self.ws.get(&self.trx, child),
+        ) {
+            (Some(parent), Some(child)) => {
+                self.move_child(&parent, &child, index.map(|index| index as u32))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+struct PreviewBlock {
+    properties: HashMap<String, Any>,
+    children: Vec<String>,
+    parent: Option<String>,
+    pinned: bool,
+}
+
+/// The blocks as the operations so far left them, read from the transaction when first
+/// touched. It is checked like the transaction checks its writes, but nothing is written.
+struct Preview<'a, 'b> {
+    t: &'a WorkspaceTransaction<'b>,
+    // `None` for blocks that don't exist
+    blocks: HashMap<String, Option<PreviewBlock>>,
+}
+
+impl<'a, 'b> Preview<'a, 'b> {
+    fn new(t: &'a WorkspaceTransaction<'b>) -> Self {
+        Self {
+            t,
+            blocks: HashMap::new(),
+        }
+    }
+
+    fn block(&mut self, id: &str) -> Option<&mut PreviewBlock> {
+        let t = self.t;
+        self.blocks
+            .entry(id.to_owned())
+            .or_insert_with(|| {
+                t.ws.get(&t.trx, id).map(|block| PreviewBlock {
+                    properties: block.content(&t.trx),
+                    children: block.children(&t.trx),
+                    parent: block.parent(&t.trx),
+                    pinned: block.is_pinned(&t.trx),
+                })
+            })
+            .as_mut()
+    }
+
+    // same as `ancestors` and `subtree_height` of the transaction
+    fn check_nesting(&mut self, parent: &str, child: &str) -> JwstResult<()> {
+        let mut ancestors = HashSet::from([parent.to_owned()]);
+        let mut current = parent.to_owned();
+        while let Some(next) = self.parent(&current) {
+            if !self.children(&next).contains(&current) || !ancestors.insert(next.clone()) {
+                break;
+            }
+            current = next;
+        }
+
+        let mut height = 0;
+        let mut visited = HashSet::new();
+        let mut stack = vec![(child.to_owned(), 1)];
+        while let Some((block, depth)) = stack.pop() {
+            if !visited.insert(block.clone()) {
+                continue;
+            }
+            height = height.max(depth);
+            for child in self.children(&block) {
+                if self.exists(&child) {
+                    stack.push((child, depth + 1));
+                }
+            }
+        }
+
+        let max = self.t.ws.max_block_depth();
+        if ancestors.len() + height > max {
+            return Err(JwstError::MaxDepthExceeded {
+                id: child.to_owned(),
+                max,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl PatchTarget for Preview<'_, '_> {
+    fn workspace(&self) -> &Workspace {
+        self.t.ws
+    }
+
+    fn exists(&mut self, id: &str) -> bool {
+        self.block(id).is_some()
+    }
+
+    fn property(&mut self, id: &str, key: &str) -> Option<Any> {
+        // like `Block::get`, only scalar values are read
+        self.block(id)?
+            .properties
+            .get(key)
+            .filter(|value| {
+                !matches!(
+                    value,
+                    Any::Null | Any::Undefined | Any::Array(_) | Any::Buffer(_) | Any::Map(_)
+                )
+            })
+            .cloned()
+    }
+
+    fn keys(&mut self, id: &str) -> Vec<String> {
+        self.block(id)
+            .map(|block| {
+                block
+                    .properties
+                    .keys()
+                    .filter(|key| !PropertyKey::new(key).is_system())
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn children(&mut self, id: &str) -> Vec<String> {
+        self.block(id)
+            .map(|block| block.children.clone())
+            .unwrap_or_default()
+    }
+
+    fn parent(&mut self, id: &str) -> Option<String> {
+        self.block(id)?.parent.clone()
+    }
+
+    fn create(&mut self, id: &str, _flavor: &str) -> JwstResult<()> {
+        self.t.check_permission(WorkspacePermission::Write)?;
+        self.blocks.insert(
+            id.to_owned(),
+            Some(PreviewBlock {
+                properties: HashMap::new(),
+                children: vec![],
+                parent: None,
+                pinned: false,
+            }),
+        );
+        Ok(())
+    }
+
+    fn set(&mut self, id: &str, key: &str, value: Any) -> JwstResult<()> {
+        self.t.ws.system_keys().check(PropertyKey::new(key))?;
+        if let Some(block) = self.block(id) {
+            block.properties.insert(key.to_owned(), value);
+        }
+        Ok(())
+    }
+
+    fn unset(&mut self, id: &str, key: &str) -> JwstResult<()> {
+        self.t.ws.system_keys().check(PropertyKey::new(key))?;
+        if let Some(block) = self.block(id) {
+            block.properties.remove(key);
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, id: &str) -> JwstResult<()> {
+        self.t.check_permission(WorkspacePermission::Write)?;
+        if matches!(self.block(id), Some(block) if block.pinned) {
+            return Err(JwstError::PinnedBlock(id.to_owned()));
+        }
+        if let Some(parent) = self.parent(id) {
+            self.detach(&parent, id);
+        }
+        self.blocks.insert(id.to_owned(), None);
+        Ok(())
+    }
+
+    fn detach(&mut self, parent: &str, child: &str) {
+        if !self.exists(child) {
+            return;
+        }
+        if let Some(block) = self.block(parent) {
+            block.children.retain(|id| id != child);
+            // like `Block::remove_children`, the child keeps pointing to the parent
+            if let Some(child) = self.block(child) {
+                child.parent = Some(parent.to_owned());
+            }
+        }
+    }
+
+    fn attach(&mut self, parent: &str, child: &str, index: Option<usize>) -> JwstResult<()> {
+        self.t.check_permission(WorkspacePermission::Write)?;
+        self.check_nesting(parent, child)?;
+        if let Some(block) = self.block(parent) {
+            block.children.retain(|id| id != child);
+            match index {
+                Some(index) if index < block.children.len() => {
+                    block.children.insert(index, child.to_owned())
+                }
+                _ => block.children.push(child.to_owned()),
+            }
+        }
+        if let Some(block) = self.block(child) {
+            block.parent = Some(parent.to_owned());
+        }
+        Ok(())
+    }
+}
+
+fn set_block(t: &mut impl PatchTarget, id: &str, value: &JsonValue, replace: bool) -> PatchResult {
+    let JsonValue::Object(content) = value else {
+        return Err(PatchFailure::InvalidValue);
+    };
+    // the flavour is only used to create the block, the other properties go through the
+    // system key policy of the workspace
+    let mut properties = vec![];
+    for (key, value) in content.iter().filter(|(key, _)| *key != sys::FLAVOR) {
+        t.workspace().system_keys().check(PropertyKey::new(key))?;
+        properties.push((key.as_str(), property_value(value)?));
+    }
+
+    if !t.exists(id) {
+        if replace {
+            return Err(PatchFailure::NotFound);
+        }
+        let Some(JsonValue::String(flavor)) = content.get(sys::FLAVOR) else {
+            return Err(PatchFailure::InvalidValue);
+        };
+        t.create(id, flavor)?;
+    }
+    if replace {
+        for key in t.keys(id) {
+            if !content.contains_key(&key) {
+                t.unset(id, &key)?;
+            }
+        }
+    }
+    for (key, value) in properties {
+        t.set(id, key, value)?;
+    }
+    Ok(())
+}
+
+fn remove_block(t: &mut impl PatchTarget, id: &str) -> PatchResult {
+    if !t.exists(id) {
+        return Err(PatchFailure::NotFound);
+    }
+    t.remove(id)?;
+    Ok(())
+}
+
+fn add_child(
+    t: &mut impl PatchTarget,
+    parent: &str,
+    index: Option<usize>,
+    value: &JsonValue,
+) -> PatchResult {
+    let JsonValue::String(child) = value else {
+        return Err(PatchFailure::InvalidValue);
+    };
+    if !t.exists(child) {
+        return Err(PatchFailure::InvalidValue);
+    }
+    if matches!(index, Some(index) if index > t.children(parent).len()) {
+        return Err(PatchFailure::NotFound);
+    }
+
+    if let Some(previous) = t.parent(child).filter(|previous| previous != parent) {
+        t.detach(&previous, child);
+    }
+    t.attach(parent, child, index)?;
+    Ok(())
+}
+
+fn remove_child(t: &mut impl PatchTarget, parent: &str, index: Option<usize>) -> PatchResult {
+    let child = index
+        .and_then(|index| t.children(parent).into_iter().nth(index))
+        .filter(|child| t.exists(child))
+        .ok_or(PatchFailure::NotFound)?;
+    t.detach(parent, &child);
+    Ok(())
+}
+
+fn apply_operation(t: &mut impl PatchTarget, operation: &PatchOperation) -> PatchResult {
+    let target = Target::parse(operation.path()).ok_or(PatchFailure::InvalidPath)?;
+    match (target, operation) {
+        (Target::Block(id), PatchOperation::Add { value, .. }) => set_block(t, &id, value, false),
+        (Target::Block(id), PatchOperation::Replace { value, .. }) => {
+            set_block(t, &id, value, true)
+        }
+        (Target::Block(id), PatchOperation::Remove { .. }) => remove_block(t, &id),
+        (Target::Block(_), PatchOperation::Test { .. }) => Err(PatchFailure::Unsupported),
+        (Target::Property(id, key), operation) => {
+            // system properties are kept by the workspace, only the policy decides whether
+            // they are written
+            if PropertyKey::new(&key).is_system() {
+                if !matches!(operation, PatchOperation::Test { .. }) {
+                    t.workspace().system_keys().check(PropertyKey::new(&key))?;
+                }
+                return Err(PatchFailure::Unsupported);
+            }
+            if !t.exists(&id) {
+                return Err(PatchFailure::NotFound);
+            }
+            let current = t.property(&id, &key);
+            match operation {
+                PatchOperation::Add { value, .. } => t.set(&id, &key, property_value(value)?)?,
+                PatchOperation::Replace { value, .. } => {
+                    let value = property_value(value)?;
+                    current.ok_or(PatchFailure::NotFound)?;
+                    t.set(&id, &key, value)?
+                }
+                PatchOperation::Remove { .. } => {
+                    current.ok_or(PatchFailure::NotFound)?;
+                    t.unset(&id, &key)?
+                }
+                PatchOperation::Test { value, .. } => {
+                    let current = serde_json::to_value(current).unwrap_or_default();
+                    if !json_eq(&current, value) {
+                        return Err(PatchFailure::TestFailed);
+                    }
+                }
+            }
+            Ok(())
+        }
+        (Target::Child(id, index), operation) => {
+            if !t.exists(&id) {
+                return Err(PatchFailure::NotFound);
+            }
+            match operation {
+                PatchOperation::Add { value, .. } => add_child(t, &id, index, value),
+                PatchOperation::Remove { .. } => remove_child(t, &id, index),
+                PatchOperation::Replace { value, .. } => {
+                    remove_child(t, &id, index)?;
+                    add_child(t, &id, index, value)
+                }
+                PatchOperation::Test { value, .. } => {
+                    let current = index.and_then(|index| t.children(&id).into_iter().nth(index));
+                    match (current, value) {
+                        (Some(current), JsonValue::String(value)) if current == *value => Ok(()),
+                        _ => Err(PatchFailure::TestFailed),
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn apply_operations(
+    t: &mut impl PatchTarget,
+    operations: &[PatchOperation],
+) -> Result<(), PatchError> {
+    for (index, operation) in operations.iter().enumerate() {
+        apply_operation(t, operation).map_err(|reason| PatchError::Operation {
+            index,
+            path: operation.path().to_owned(),
+            reason,
+        })?;
+    }
+    Ok(())
+}
+
+impl Workspace {
+    /// Apply JSON Patch style edits, see [PatchOperation], either all of them or none.
+    ///
+    /// The whole patch is checked against a preview of the blocks it touches first, then
+    /// applied in one transaction of the workspace, so the changes merge with concurrent
+    /// edits like the changes of any other client. Returns the update and the state vector
+    /// afterwards, the client can compare it to notice changes of others.
+    pub fn apply_patch(
+        &self,
+        operations: &[PatchOperation],
+    ) -> Result<(Vec<u8>, StateVector), PatchError> {
+        self.with_trx(|mut t| {
+            apply_operations(&mut Preview::new(&t), operations)?;
+            apply_operations(&mut t, operations)?;
+            Ok((t.trx.encode_update_v1(), t.trx.state_vector()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn patch(workspace: &Workspace, operations: JsonValue) -> Result<StateVector, PatchError> {
+        let operations = serde_json::from_value::<Vec<PatchOperation>>(operations).unwrap();
+        workspace.apply_patch(&operations).map(|(_, sv)| sv)
+    }
+
+    #[test]
+    fn apply_patch() {
+        let workspace = Workspace::new("test");
+        let state_vector = patch(
+            &workspace,
+            json!([
+                {"op": "add", "path": "/page", "value": {"sys:flavor": "affine:page", "title": "hello"}},
+                {"op": "add", "path": "/a~1b", "value": {"sys:flavor": "affine:paragraph"}},
+                {"op": "add", "path": "/text", "value": {"sys:flavor": "affine:paragraph", "text": "world"}},
+                {"op": "add", "path": "/page/sys:children/-", "value": "text"},
+                {"op": "add", "path": "/page/sys:children/0", "value": "a/b"},
+                {"op": "replace", "path": "/page/title", "value": "changed"},
+                {"op": "test", "path": "/page/title", "value": "changed"},
+            ]),
+        )
+        .unwrap();
+        assert_eq!(state_vector, workspace.doc().transact().state_vector());
+
+        workspace.with_trx(|t| {
+            let page = workspace.get(&t.trx, "page").unwrap();
+            assert_eq!(page.flavor(&t.trx), "affine:page");
+            assert_eq!(
+                page.get(&t.trx, "title"),
+                Some(Any::String("changed".into()))
+            );
+            assert_eq!(page.children(&t.trx), vec!["a/b", "text"]);
+        });
+
+        patch(
+            &workspace,
+            json!([
+                {"op": "remove", "path": "/page/sys:children/0"},
+                {"op": "remove", "path": "/text"},
+                {"op": "replace", "path": "/page", "value": {"subtitle": "new", "draft": true}},
+                {"op": "remove", "path": "/page/draft"},
+            ]),
+        )
+        .unwrap();
+        workspace.with_trx(|t| {
+            let page = workspace.get(&t.trx, "page").unwrap();
+            assert!(page.children(&t.trx).is_empty());
+            // removed properties are gone, not set to null
+            assert_eq!(
+                page.content(&t.trx),
+                HashMap::from([("subtitle".to_owned(), Any::String("new".into()))])
+            );
+            assert!(workspace.get(&t.trx, "text").is_none());
+            assert!(workspace.get(&t.trx, "a/b").is_some());
+        });
+    }
+
+    #[test]
+    fn failed_patch_changes_nothing() {
+        let workspace = Workspace::new("test");
+        patch(
+            &workspace,
+            json!([{"op": "add", "path": "/page", "value": {"sys:flavor": "affine:page", "title": "hello"}}]),
+        )
+        .unwrap();
+        let before = workspace.doc().transact().state_vector();
+        workspace.set_max_block_depth(2);
+
+        let result = patch(
+            &workspace,
+            json!([
+                {"op": "replace", "path": "/page/title", "value": "changed"},
+                {"op": "test", "path": "/page/title", "value": "hello"},
+            ]),
+        );
+        assert!(matches!(
+            result,
+            Err(PatchError::Operation {
+                index: 1,
+                reason: PatchFailure::TestFailed,
+                ..
+            })
+        ));
+
+        for (operations, failure) in [
+            (
+                json!([{"op": "remove", "path": "/missing"}]),
+                "nothing at the path",
+            ),
+            (
+                json!([{"op": "add", "path": "/page/sys:created", "value": 1}]),
+                "system property sys:created can only be written with system access",
+            ),
+            (
+                json!([{"op": "replace", "path": "/page", "value": {"sys:parent": "block"}}]),
+                "system property sys:parent can only be written with system access",
+            ),
+            (
+                json!([{"op": "add", "path": "/page/list", "value": [1, 2]}]),
+                "invalid value",
+            ),
+            (
+                json!([{"op": "add", "path": "/block", "value": {}}]),
+                "invalid value",
+            ),
+            (
+                json!([{"op": "remove", "path": "/page/sys:children/0"}]),
+                "nothing at the path",
+            ),
+            // the operations see the changes of the ones before
+            (
+                json!([
+                    {"op": "add", "path": "/block", "value": {"sys:flavor": "affine:paragraph"}},
+                    {"op": "add", "path": "/page/sys:children/-", "value": "block"},
+                    {"op": "remove", "path": "/page/title"},
+                    {"op": "remove", "path": "/page/title"},
+                ]),
+                "nothing at the path",
+            ),
+            (
+                json!([
+                    {"op": "add", "path": "/a", "value": {"sys:flavor": "affine:paragraph"}},
+                    {"op": "add", "path": "/b", "value": {"sys:flavor": "affine:paragraph"}},
+                    {"op": "add", "path": "/a/sys:children/-", "value": "b"},
+                    {"op": "add", "path": "/page/sys:children/-", "value": "a"},
+                ]),
+                "block a would be nested deeper than 2 levels",
+            ),
+        ] {
+            let error = patch(&workspace, operations).unwrap_err();
+            assert!(error.to_string().ends_with(failure), "{error}");
+        }
+
+        assert_eq!(workspace.doc().transact().state_vector(), before);
+        workspace.with_trx(|t| {
+            let page = workspace.get(&t.trx, "page").unwrap();
+            assert_eq!(page.get(&t.trx, "title"), Some(Any::String("hello".into())));
+            assert!(workspace.get(&t.trx, "block").is_none());
+            assert!(workspace.get(&t.trx, "a").is_none());
+        });
+    }
+}
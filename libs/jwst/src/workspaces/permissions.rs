@@ -0,0 +1,276 @@
+use super::*;
+use lib0::any::Any;
+use std::ops::Deref;
+use thiserror::Error;
+use yrs::{types::Value, Map, ReadTxn};
+
+/// What a user may do in a workspace, stored in the `space:permissions` map.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WorkspacePermission {
+    /// The user can read and sync the workspace, its edits are rejected.
+    ReadOnly,
+    /// The user can edit blocks and metadata.
+    #[default]
+    Write,
+    /// The user can also change the permissions of other users.
+    Admin,
+}
+
+impl WorkspacePermission {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::ReadOnly => "read_only",
+            Self::Write => "write",
+            Self::Admin => "admin",
+        }
+    }
+
+    fn parse(permission: &str) -> Option<Self> {
+        match permission {
+            "read_only" => Some(Self::ReadOnly),
+            "write" => Some(Self::Write),
+            "admin" => Some(Self::Admin),
+            _ => None,
+        }
+    }
+}
+
+/// A user authenticated by the server, see [Workspace::with_user_trx].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkspaceUser {
+    pub id: String,
+    /// Permission the server granted the user, used unless an admin
+    /// [set another one](WorkspaceTransaction::set_permission) in the workspace.
+    pub granted: WorkspacePermission,
+}
+
+impl WorkspaceUser {
+    pub fn new<S: AsRef<str>>(id: S, granted: WorkspacePermission) -> Self {
+        Self {
+            id: id.as_ref().into(),
+            granted,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum PermissionError {
+    #[error("user {user} needs {required:?} permission")]
+    Denied {
+        user: String,
+        required: WorkspacePermission,
+    },
+}
+
+impl Workspace {
+    /// Permission of a user, keyed by the id of the user so it can't be changed by
+    /// picking another client id, [WorkspaceUser::granted] if none was set.
+    pub fn get_permission<T: ReadTxn>(&self, trx: &T, user: &WorkspaceUser) -> WorkspacePermission {
+        match self.permissions.get(trx, &user.id) {
+            Some(Value::Any(Any::String(permission))) => {
+                WorkspacePermission::parse(&permission).unwrap_or(user.granted)
+            }
+            _ => user.granted,
+        }
+    }
+
+    // `None` is the server itself, which may do anything
+    pub(super) fn check_permission<T: ReadTxn>(
+        &self,
+        trx: &T,
+        user: Option<&WorkspaceUser>,
+        required: WorkspacePermission,
+    ) -> Result<(), PermissionError> {
+        match user {
+            Some(user) if self.get_permission(trx, user) < required => {
+                Err(PermissionError::Denied {
+                    user: user.id.clone(),
+                    required,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Like [Workspace::with_trx], but the changes are made for `user`, so only those that
+    /// check its permission are available.
+    pub fn with_user_trx<T>(&self, user: WorkspaceUser, f: impl FnOnce(UserTransaction) -> T) -> T {
+        self.with_trx(|mut trx| {
+            trx.user = Some(user);
+            f(UserTransaction(trx))
+        })
+    }
+}
+
+impl WorkspaceTransaction<'_> {
+    /// The user this transaction changes the workspace for, `None` for the server itself.
+    pub fn user(&self) -> Option<&WorkspaceUser> {
+        self.user.as_ref()
+    }
+
+    /// Check that the [user](WorkspaceTransaction::user) has at least `required`
+    /// permission, see [Workspace::get_permission].
+    pub fn check_permission(&self, required: WorkspacePermission) -> Result<(), PermissionError> {
+        self.ws
+            .check_permission(&self.trx, self.user.as_ref(), required)
+    }
+
+    /// Grant `permission` to the user `user_id`, the user of the transaction needs to be
+    /// an admin. Entries are shared with every peer of the doc, and checked where the
+    /// workspace applies changes of a user: by [UserTransaction] and by
+    /// [Workspace::validate_sync_message].
+    pub fn set_permission(
+        &mut self,
+        user_id: &str,
+        permission: WorkspacePermission,
+    ) -> Result<(), PermissionError> {
+        self.check_permission(WorkspacePermission::Admin)?;
+        trace!("set permission of {}: {:?}", user_id, permission);
+        self.ws
+            .permissions
+            .insert(&mut self.trx, user_id, permission.as_str());
+        Ok(())
+    }
+}
+
+/// A [WorkspaceTransaction] of an authenticated user, see [Workspace::with_user_trx].
+/// The transaction can be read through, but only changes checking the permission of the
+/// user are exposed, blocks can't be changed through it directly.
+pub struct UserTransaction<'a>(WorkspaceTransaction<'a>);
+
+impl<'a> Deref for UserTransaction<'a> {
+    type Target = WorkspaceTransaction<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl UserTransaction<'_> {
+    /// See [WorkspaceTransaction::try_create].
+    pub fn create<B, F>(&mut self, block_id: B, flavor: F) -> JwstResult<Block>
+    where
+        B: AsRef<str>,
+        F: AsRef<str>,
+    {
+        self.0.try_create(block_id, flavor)
+    }
+
+    /// See [WorkspaceTransaction::insert_at].
+    pub fn insert_at(
+        &mut self,
+        parent_id: &str,
+        index: usize,
+        child_id: &str,
+        flavour: &str,
+    ) -> Result<Block, InsertError> {
+        self.0.insert_at(parent_id, index, child_id, flavour)
+    }
+
+    /// See [WorkspaceTransaction::try_remove].
+    pub fn remove<S: AsRef<str>>(&mut self, block_id: S) -> JwstResult<bool> {
+        self.0.try_remove(block_id)
+    }
+
+    /// See [WorkspaceTransaction::bulk_delete].
+    pub fn bulk_delete(&mut self, block_ids: &[&str]) -> JwstResult<usize> {
+        self.0.bulk_delete(block_ids)
+    }
+
+    /// See [WorkspaceTransaction::move_child].
+    pub fn move_child(
+        &mut self,
+        parent: &Block,
+        child: &Block,
+        pos: Option<u32>,
+    ) -> JwstResult<()> {
+        self.0.move_child(parent, child, pos)
+    }
+
+    /// See [WorkspaceTransaction::set_metadata].
+    pub fn set_metadata(&mut self, key: &str, value: impl Into<Any>) -> JwstResult<()> {
+        self.0.set_metadata(key, value)
+    }
+
+    /// See [WorkspaceTransaction::set_permission].
+    pub fn set_permission(
+        &mut self,
+        user_id: &str,
+        permission: WorkspacePermission,
+    ) -> Result<(), PermissionError> {
+        self.0.set_permission(user_id, permission)
+    }
+
+    pub fn commit(&mut self) {
+        self.0.commit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permissions() {
+        let workspace = Workspace::from_doc(yrs::Doc::with_client_id(1), "test");
+        let admin = WorkspaceUser::new("admin", WorkspacePermission::Admin);
+        let writer = WorkspaceUser::new("writer", WorkspacePermission::Write);
+        let reader = WorkspaceUser::new("reader", WorkspacePermission::ReadOnly);
+
+        workspace.with_user_trx(writer.clone(), |mut t| {
+            assert_eq!(
+                workspace.get_permission(&t.trx, &writer),
+                WorkspacePermission::Write
+            );
+            t.create("a", "affine:page").unwrap();
+            assert!(matches!(
+                t.set_permission("reader", WorkspacePermission::Admin),
+                Err(PermissionError::Denied {
+                    required: WorkspacePermission::Admin,
+                    ..
+                })
+            ));
+        });
+        workspace.with_user_trx(admin, |mut t| {
+            t.set_permission("writer", WorkspacePermission::ReadOnly)
+                .unwrap();
+        });
+
+        // the entry of the user applies whatever client it syncs with
+        for user in [writer, reader] {
+            workspace.with_user_trx(user.clone(), |mut t| {
+                assert!(matches!(
+                    t.check_permission(WorkspacePermission::Write),
+                    Err(PermissionError::Denied { user: denied, required: WorkspacePermission::Write })
+                        if denied == user.id
+                ));
+                assert!(matches!(
+                    t.create("b", "affine:page"),
+                    Err(JwstError::Permission(_))
+                ));
+                assert!(matches!(
+                    t.insert_at("a", 0, "b", "affine:paragraph"),
+                    Err(InsertError::Rejected(JwstError::Permission(_)))
+                ));
+                assert!(t.remove("a").is_err());
+                assert!(t.bulk_delete(&["a"]).is_err());
+                assert!(t.set_metadata("name", "renamed").is_err());
+            });
+        }
+
+        workspace.with_trx(|mut t| {
+            assert!(workspace.exists(&t.trx, "a"));
+            assert!(!workspace.exists(&t.trx, "b"));
+            assert!(workspace.metadata.get(&t.trx, "name").is_none());
+
+            // the server itself isn't checked
+            assert!(t.user().is_none());
+            t.set_permission("reader", WorkspacePermission::Write)
+                .unwrap();
+        });
+        workspace.with_user_trx(
+            WorkspaceUser::new("reader", WorkspacePermission::ReadOnly),
+            |mut t| assert!(t.create("b", "affine:page").is_ok()),
+        );
+    }
+}
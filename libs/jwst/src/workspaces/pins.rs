@@ -0,0 +1,85 @@
+use super::*;
+use yrs::{types::Value, Array, ArrayPrelim, Map, ReadTxn, TransactionMut};
+
+// metadata key of the ids of the pinned blocks, in pin order
+const PINS: &str = "space:pins";
+
+impl Workspace {
+    /// Pin a block for quick access, or unpin it. These pins are bookmarks shared
+    /// by the users of the workspace, unlike [Block::set_pinned] they don't protect
+    /// the block from removal.
+    pub fn pin_block(
+        &self,
+        trx: &mut TransactionMut,
+        block_id: &str,
+        pinned: bool,
+    ) -> JwstResult<()> {
+        if !self.exists(trx, block_id) {
+            return Err(JwstError::BlockNotFound(block_id.to_owned()));
+        }
+
+        let pins = match self.metadata.get(trx, PINS) {
+            Some(Value::YArray(pins)) => pins,
+            _ if !pinned => return Ok(()),
+            _ => self
+                .metadata
+                .insert(trx, PINS, ArrayPrelim::<Vec<String>, String>::from(vec![])),
+        };
+        let position = pins.iter(trx).position(|id| id.to_string(trx) == block_id);
+        match (position, pinned) {
+            (None, true) => pins.push_back(trx, block_id),
+            (Some(position), false) => pins.remove(trx, position as u32),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Pinned blocks in pin order, pins of removed blocks are skipped.
+    pub fn pinned_blocks<T: ReadTxn>(&self, trx: &T) -> Vec<Block> {
+        match self.metadata.get(trx, PINS) {
+            Some(Value::YArray(pins)) => pins
+                .iter(trx)
+                .filter_map(|id| self.get(trx, id.to_string(trx)))
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pin_block() {
+        let workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            t.create("a", "affine:page");
+            t.create("b", "affine:page");
+            t.create("c", "affine:page");
+            assert!(workspace.pinned_blocks(&t.trx).is_empty());
+
+            workspace.pin_block(&mut t.trx, "b", true).unwrap();
+            workspace.pin_block(&mut t.trx, "a", true).unwrap();
+            workspace.pin_block(&mut t.trx, "c", true).unwrap();
+            // pinning twice keeps a single pin
+            workspace.pin_block(&mut t.trx, "b", true).unwrap();
+            workspace.pin_block(&mut t.trx, "a", false).unwrap();
+            workspace.pin_block(&mut t.trx, "a", false).unwrap();
+
+            t.remove("c");
+            let pinned = workspace
+                .pinned_blocks(&t.trx)
+                .iter()
+                .map(|b| b.id())
+                .collect::<Vec<_>>();
+            assert_eq!(pinned, vec!["b"]);
+
+            assert!(matches!(
+                workspace.pin_block(&mut t.trx, "missing", true),
+                Err(JwstError::BlockNotFound(id)) if id == "missing"
+            ));
+        });
+    }
+}
@@ -0,0 +1,445 @@
+//! Reverse index of the links stored in block properties and the links made with
+//! [Workspace::link_blocks], see [Workspace::backlinks].
+
+use super::*;
+use lib0::any::Any;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use utoipa::ToSchema;
+use yrs::{
+    types::{Event, PathSegment},
+    updates::{decoder::Decode, encoder::Encode},
+    Array, DeepObservable, Map, ReadTxn, StateVector, Transact, Value,
+};
+
+/// Block properties AFFiNE uses to store the id of a linked page.
+pub const DEFAULT_LINK_PROPERTY_KEYS: &[&str] = &["pageId"];
+
+// name of the persisted index in the directory of the search index
+const INDEX_FILE: &str = "backlinks.json";
+
+/// A block property linking to another block, see [Workspace::backlinks].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema)]
+pub struct BlockRef {
+    pub block_id: String,
+    /// The property holding the link, the relation type for the links made with
+    /// [Workspace::link_blocks].
+    pub property: String,
+}
+
+// links of a block as (property, target)
+type Links = Vec<(String, String)>;
+
+#[derive(Serialize, Deserialize)]
+struct PersistedIndex<L> {
+    // state vector of the doc the index was built from, lib0 v1 encoded
+    state_vector: Vec<u8>,
+    links: L,
+}
+
+#[derive(Default)]
+struct Backlinks {
+    // the index has to be rebuilt from the whole doc before it can be used
+    stale: bool,
+    // outbound links of every block, used to drop its backlinks when it changes
+    links: HashMap<String, Links>,
+    backlinks: HashMap<String, BTreeSet<BlockRef>>,
+}
+
+impl Backlinks {
+    fn new(links: HashMap<String, Links>) -> Self {
+        let mut index = Self::default();
+        for (block_id, links) in links {
+            index.set_links(block_id, links);
+        }
+        index
+    }
+
+    fn set_links(&mut self, block_id: String, links: Links) {
+        for (property, target) in self.links.remove(&block_id).unwrap_or_default() {
+            if let Some(sources) = self.backlinks.get_mut(&target) {
+                sources.remove(&BlockRef {
+                    block_id: block_id.clone(),
+                    property,
+                });
+                if sources.is_empty() {
+                    self.backlinks.remove(&target);
+                }
+            }
+        }
+
+        for (property, target) in &links {
+            self.backlinks
+                .entry(target.clone())
+                .or_default()
+                .insert(BlockRef {
+                    block_id: block_id.clone(),
+                    property: property.clone(),
+                });
+        }
+        if !links.is_empty() {
+            self.links.insert(block_id, links);
+        }
+    }
+
+    fn save(
+        &self,
+        path: &Path,
+        state_vector: &StateVector,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let index = serde_json::to_vec(&PersistedIndex {
+            state_vector: state_vector.encode_v1(),
+            links: &self.links,
+        })?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, index)?;
+        Ok(())
+    }
+}
+
+// blocks changed by committed transactions and not indexed yet
+struct Changes {
+    blocks: HashSet<String>,
+    // state vector of the doc once the changed blocks are indexed
+    state_vector: StateVector,
+}
+
+pub(crate) struct BacklinksPluginImpl {
+    properties: Vec<String>,
+    path: Option<PathBuf>,
+    changes: Arc<Mutex<Changes>>,
+    index: Mutex<Backlinks>,
+    // need to keep so they get dropped with this plugin
+    _changes_sub: ChangesSubscription,
+    _links_sub: ChangesSubscription,
+}
+
+impl PluginImpl for BacklinksPluginImpl {}
+
+impl BacklinksPluginImpl {
+    fn block_links<T: ReadTxn>(&self, ws: &Workspace, trx: &T, block: &Block) -> Links {
+        let content = block.content(trx);
+        let mut links = Links::new();
+        if let Some(Value::YArray(stored)) = ws.links.get(trx, &block.id()) {
+            for link in stored.iter(trx) {
+                if let Some(link) = BlockLink::from_any(&block.id(), link) {
+                    let link = (link.rel_type, link.to);
+                    if !links.contains(&link) {
+                        links.push(link);
+                    }
+                }
+            }
+        }
+        for property in &self.properties {
+            let targets = match content.get(property) {
+                Some(Any::String(target)) => vec![target.to_string()],
+                Some(Any::Array(targets)) => targets
+                    .iter()
+                    .filter_map(|target| match target {
+                        Any::String(target) => Some(target.to_string()),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => vec![],
+            };
+            for target in targets {
+                let link = (property.clone(), target);
+                if !link.1.is_empty() && !links.contains(&link) {
+                    links.push(link);
+                }
+            }
+        }
+        links
+    }
+
+    // bring the index up to date with the committed transactions
+    fn refresh<T: ReadTxn>(&self, ws: &Workspace, trx: &T) {
+        let mut index = self.index.lock().unwrap();
+        let (changed, state_vector) = {
+            let mut changes = self.changes.lock().unwrap();
+            (
+                std::mem::take(&mut changes.blocks),
+                changes.state_vector.clone(),
+            )
+        };
+
+        if index.stale {
+            let links = ws.blocks(trx, |blocks| {
+                blocks
+                    .map(|block| (block.id(), self.block_links(ws, trx, &block)))
+                    .filter(|(_, links)| !links.is_empty())
+                    .collect()
+            });
+            *index = Backlinks::new(links);
+        } else if !changed.is_empty() {
+            for block_id in changed {
+                let links = ws
+                    .get(trx, &block_id)
+                    .map(|block| self.block_links(ws, trx, &block))
+                    .unwrap_or_default();
+                index.set_links(block_id, links);
+            }
+        } else {
+            return;
+        }
+
+        if let Some(path) = &self.path {
+            if let Err(e) = index.save(path, &state_vector) {
+                warn!("failed to save backlinks of {}: {}", ws.id(), e);
+            }
+        }
+    }
+
+    pub(crate) fn backlinks<T: ReadTxn>(
+        &self,
+        ws: &Workspace,
+        trx: &T,
+        block_id: &str,
+    ) -> Vec<BlockRef> {
+        self.refresh(ws, trx);
+        self.index
+            .lock()
+            .unwrap()
+            .backlinks
+            .get(block_id)
+            .map(|sources| sources.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+pub(crate) struct BacklinksPluginRegister {
+    properties: Vec<String>,
+    dir: Option<PathBuf>,
+}
+
+impl Default for BacklinksPluginRegister {
+    fn default() -> Self {
+        Self {
+            properties: DEFAULT_LINK_PROPERTY_KEYS
+                .iter()
+                .map(|key| key.to_string())
+                .collect(),
+            dir: None,
+        }
+    }
+}
+
+impl BacklinksPluginRegister {
+    /// Keep the index in `path`, next to the search index, instead of rebuilding it
+    /// after a restart. A persisted index is only used if the doc didn't change since.
+    pub fn persisted_directory(path: PathBuf) -> Self {
+        Self {
+            dir: Some(path),
+            ..Default::default()
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn properties<K: AsRef<str>>(self, properties: &[K]) -> Self {
+        Self {
+            properties: properties
+                .iter()
+                .map(|key| key.as_ref().to_owned())
+                .collect(),
+            ..self
+        }
+    }
+}
+
+impl PluginRegister for BacklinksPluginRegister {
+    type Plugin = BacklinksPluginImpl;
+    fn setup(self, ws: &mut Workspace) -> Result<BacklinksPluginImpl, Box<dyn std::error::Error>> {
+        let state_vector = ws.doc().transact().state_vector();
+        let path = self.dir.map(|dir| dir.join(INDEX_FILE));
+
+        // a missing or unreadable index is rebuilt on first use
+        let persisted = path
+            .as_ref()
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|index| {
+                serde_json::from_slice::<PersistedIndex<HashMap<String, Links>>>(&index).ok()
+            });
+        let index = match persisted {
+            Some(index)
+                if StateVector::decode_v1(&index.state_vector).ok().as_ref()
+                    == Some(&state_vector) =>
+            {
+                Backlinks::new(index.links)
+            }
+            _ => Backlinks {
+                stale: true,
+                ..Default::default()
+            },
+        };
+
+        let changes = Arc::new(Mutex::new(Changes {
+            blocks: HashSet::new(),
+            state_vector,
+        }));
+        let sub = ws.observe_changes({
+            let changes = changes.clone();
+            move |trx, c| {
+                let mut changes = changes.lock().unwrap();
+                changes.blocks.extend(
+                    c.added
+                        .iter()
+                        .chain(&c.removed)
+                        .chain(c.updated.keys())
+                        .cloned(),
+                );
+                changes.state_vector = trx.state_vector();
+            }
+        });
+
+        // links made with [Workspace::link_blocks] are kept outside of the blocks
+        let links_sub = ws.links.clone().observe_deep({
+            let changes = changes.clone();
+            move |trx, events| {
+                let mut changes = changes.lock().unwrap();
+                for event in events.iter() {
+                    let path = match event {
+                        Event::Map(event) => event.path(),
+                        Event::Array(event) => event.path(),
+                        _ => continue,
+                    };
+                    match (event, path.front()) {
+                        // links of a block added or removed
+                        (Event::Map(event), None) => changes
+                            .blocks
+                            .extend(event.keys(trx).keys().map(|from| from.to_string())),
+                        // a link pushed to the links of a block
+                        (_, Some(PathSegment::Key(from))) => {
+                            changes.blocks.insert(from.to_string());
+                        }
+                        _ => {}
+                    }
+                }
+                changes.state_vector = trx.state_vector();
+            }
+        });
+
+        Ok(BacklinksPluginImpl {
+            properties: self.properties,
+            path,
+            changes,
+            index: Mutex::new(index),
+            _changes_sub: sub,
+            _links_sub: links_sub,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use yrs::{Doc, Update};
+
+    fn sources(refs: Vec<BlockRef>) -> Vec<String> {
+        refs.into_iter().map(|r| r.block_id).collect()
+    }
+
+    #[test]
+    fn backlinks() {
+        let workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            t.create("a", "affine:page");
+            t.create("b", "affine:page");
+            let x = t.create("x", "affine:embed");
+            x.set(&mut t.trx, "pageId", "b");
+            let y = t.create("y", "affine:embed");
+            y.set(&mut t.trx, "pageId", "b");
+        });
+        workspace.with_trx(|t| {
+            assert_eq!(
+                workspace.backlinks(&t.trx, "b"),
+                vec![
+                    BlockRef {
+                        block_id: "x".into(),
+                        property: "pageId".into()
+                    },
+                    BlockRef {
+                        block_id: "y".into(),
+                        property: "pageId".into()
+                    },
+                ]
+            );
+            assert!(workspace.backlinks(&t.trx, "a").is_empty());
+        });
+
+        workspace.with_trx(|mut t| {
+            let y = workspace.get(&t.trx, "y").unwrap();
+            y.set(&mut t.trx, "pageId", "a");
+        });
+        workspace.with_trx(|t| {
+            assert_eq!(sources(workspace.backlinks(&t.trx, "a")), vec!["y"]);
+            assert_eq!(sources(workspace.backlinks(&t.trx, "b")), vec!["x"]);
+        });
+
+        // removing the block or the property drops the backlink
+        workspace.with_trx(|mut t| {
+            t.remove("x");
+            let y = workspace.get(&t.trx, "y").unwrap();
+            y.set(&mut t.trx, "pageId", Any::Null);
+        });
+        workspace.with_trx(|t| {
+            assert!(workspace.backlinks(&t.trx, "a").is_empty());
+            assert!(workspace.backlinks(&t.trx, "b").is_empty());
+        });
+    }
+
+    #[test]
+    fn persisted_backlinks() {
+        let dir = std::env::temp_dir().join(format!("jwst-backlinks-{}", std::process::id()));
+        let register = || BacklinksPluginRegister::persisted_directory(dir.clone());
+        let stale = |workspace: &Workspace| {
+            workspace
+                .with_plugin::<BacklinksPluginImpl, _>(|plugin| plugin.index.lock().unwrap().stale)
+                .unwrap()
+        };
+        let load = |update: &[u8]| {
+            let doc = Doc::new();
+            doc.transact_mut()
+                .apply_update(Update::decode_v1(update).unwrap());
+            let workspace = Workspace::from_doc(doc, "test");
+            insert_plugin(workspace, register()).unwrap()
+        };
+
+        let workspace = insert_plugin(Workspace::new("test"), register()).unwrap();
+        workspace.with_trx(|mut t| {
+            t.create("a", "affine:page");
+            let x = t.create("x", "affine:embed");
+            x.set(&mut t.trx, "pageId", "a");
+        });
+        workspace.with_trx(|t| {
+            assert_eq!(sources(workspace.backlinks(&t.trx, "a")), vec!["x"]);
+        });
+        let update = workspace.sync_migration();
+
+        // the doc didn't change, the persisted index is used as is
+        let restored = load(&update);
+        assert!(!stale(&restored));
+        restored.with_trx(|t| {
+            assert_eq!(sources(restored.backlinks(&t.trx, "a")), vec!["x"]);
+        });
+
+        // the doc changed since the index was saved, it is rebuilt
+        restored.with_trx(|mut t| {
+            let y = t.create("y", "affine:embed");
+            y.set(&mut t.trx, "pageId", "a");
+        });
+        let changed = load(&restored.sync_migration());
+        assert!(stale(&changed));
+        changed.with_trx(|t| {
+            assert_eq!(sources(changed.backlinks(&t.trx, "a")), vec!["x", "y"]);
+        });
+        assert!(!stale(&changed));
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
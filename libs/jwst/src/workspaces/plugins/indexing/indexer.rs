@@ -1,16 +1,37 @@
 use super::{PluginImpl, Workspace};
 use lib0::any::Any;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+use std::ops::Bound;
 use std::sync::{atomic::AtomicU32, Arc};
-use tantivy::{collector::TopDocs, query::QueryParser, schema::*, Index, ReloadPolicy};
+use tantivy::{
+    collector::TopDocs,
+    query::{
+        AllQuery, BooleanQuery, ConstScoreQuery, Occur, Query, QueryParser, RangeQuery, TermQuery,
+    },
+    schema::*,
+    Index, ReloadPolicy,
+};
 use utoipa::ToSchema;
+use yrs::ReadTxn;
+
+// blocks returned by a search
+const SEARCH_LIMIT: usize = 10;
+// characters of the block text in [SearchResult::snippet]
+const SNIPPET_MAX_CHARS: usize = 120;
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct SearchResult {
     pub block_id: String,
     pub score: f32,
+    /// `None` if the block has no flavor.
+    pub flavor: Option<String>,
+    /// Creation timestamp (ms), `None` if the block has no creation time.
+    pub created: Option<u64>,
+    /// Text preview of the block, see [`Block::render_preview`].
+    ///
+    /// [`Block::render_preview`]: crate::Block::render_preview
+    pub snippet: Option<String>,
 }
 
 /// Returned from [`Workspace::search`]
@@ -19,22 +40,198 @@ pub struct SearchResult {
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct SearchResults(Vec<SearchResult>);
 
+impl SearchResults {
+    // previews of the found blocks, blocks removed since the last update of the index
+    // have none
+    pub(crate) fn render_snippets<T: ReadTxn>(&mut self, trx: &T, workspace: &Workspace) {
+        for result in &mut self.0 {
+            result.snippet = workspace
+                .get(trx, &result.block_id)
+                .map(|block| block.render_preview(trx, SNIPPET_MAX_CHARS));
+        }
+    }
+}
+
+/// Query and filters of [`Workspace::search`], all filters are optional.
+/// The updated range is checked against the blocks matching the other filters,
+/// as layout changes update blocks without reindexing them.
+///
+/// [`Workspace::search`]: crate::Workspace::search
+#[derive(Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SearchOptions {
+    /// Search by title, text and tags, an empty query matches every block.
+    pub query: String,
+    /// Only blocks of these flavors, e.g. `affine:paragraph`.
+    #[serde(default)]
+    pub flavors: Vec<String>,
+    /// Only blocks created at or after this timestamp (ms).
+    pub created_after: Option<u64>,
+    /// Only blocks created at or before this timestamp (ms).
+    pub created_before: Option<u64>,
+    /// Only blocks last updated at or after this timestamp (ms).
+    pub updated_after: Option<u64>,
+    /// Only blocks last updated at or before this timestamp (ms).
+    pub updated_before: Option<u64>,
+    /// Only this block and its descendants.
+    pub root: Option<String>,
+}
+
+/// Metadata filters of [`Workspace::search_with_filter`], see [`SearchOptions`]
+/// for all the filters.
+///
+/// [`Workspace::search_with_filter`]: crate::Workspace::search_with_filter
+#[derive(Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SearchFilter {
+    /// Only blocks of these flavors, e.g. `affine:paragraph`.
+    #[serde(default)]
+    pub flavors: Vec<String>,
+    /// Only blocks created at or after this timestamp (ms).
+    pub created_after: Option<u64>,
+    /// Only blocks created at or before this timestamp (ms).
+    pub created_before: Option<u64>,
+}
+
+impl From<(&str, SearchFilter)> for SearchOptions {
+    fn from((query, filter): (&str, SearchFilter)) -> Self {
+        Self {
+            query: query.to_owned(),
+            flavors: filter.flavors,
+            created_after: filter.created_after,
+            created_before: filter.created_before,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&str> for SearchOptions {
+    fn from(query: &str) -> Self {
+        Self {
+            query: query.to_owned(),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&String> for SearchOptions {
+    fn from(query: &String) -> Self {
+        query.as_str().into()
+    }
+}
+
+impl From<String> for SearchOptions {
+    fn from(query: String) -> Self {
+        Self {
+            query,
+            ..Default::default()
+        }
+    }
+}
+
+/// Everything the index knows about a block.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct IndexedBlock {
+    flavor: String,
+    title: Option<String>,
+    text: Option<String>,
+    tags: Vec<String>,
+    created: u64,
+    // the block itself followed by its ancestors
+    path: Vec<String>,
+}
+
 pub struct IndexingPluginImpl {
     // /// `true` if the text search has not yet populated the Tantivy index
     // /// `false` if there should only be incremental changes necessary to the blocks.
     // first_index: bool,
     pub(super) queue_reindex: Arc<AtomicU32>,
     pub(super) schema: Schema,
-    pub(super) index: Rc<Index>,
+    pub(super) index: Arc<Index>,
     pub(super) query_parser: QueryParser,
+    // every block as of the last reindex,
+    // used to skip updates that don't change anything indexed
+    pub(super) indexed: HashMap<String, IndexedBlock>,
+    // blocks found in a persisted index when it was opened, the ones removed from the
+    // workspace in the meantime are dropped on the first reindex
+    pub(super) persisted: HashSet<String>,
+    // last update of every block, layout changes update blocks without reindexing them
+    // so the updated range of [SearchOptions] is checked against this
+    pub(super) updated: HashMap<String, u64>,
     // need to keep so it gets dropped with this plugin
     pub(super) _update_sub: Option<yrs::UpdateSubscription>,
 }
 
 impl IndexingPluginImpl {
-    pub fn search<S: AsRef<str>>(
+    fn field(&self, name: &str) -> Field {
+        self.schema.get_field(name).unwrap()
+    }
+
+    fn build_query(
         &self,
-        query: S,
+        options: &SearchOptions,
+    ) -> Result<Box<dyn Query>, Box<dyn std::error::Error>> {
+        let query: Box<dyn Query> = if options.query.trim().is_empty() {
+            Box::new(AllQuery)
+        } else {
+            self.query_parser.parse_query(&options.query)?
+        };
+
+        let term = |field: Field, value: &str| -> Box<dyn Query> {
+            Box::new(TermQuery::new(
+                Term::from_field_text(field, value),
+                IndexRecordOption::Basic,
+            ))
+        };
+        let range = |field: &str, from: Option<u64>, to: Option<u64>| -> Option<Box<dyn Query>> {
+            if from.is_none() && to.is_none() {
+                return None;
+            }
+            Some(Box::new(RangeQuery::new_u64_bounds(
+                self.field(field),
+                from.map_or(Bound::Unbounded, Bound::Included),
+                to.map_or(Bound::Unbounded, Bound::Included),
+            )))
+        };
+
+        let mut filters = vec![];
+        if !options.flavors.is_empty() {
+            let flavor = self.field("flavor");
+            filters.push(Box::new(BooleanQuery::union(
+                options
+                    .flavors
+                    .iter()
+                    .map(|value| term(flavor, value))
+                    .collect(),
+            )) as Box<dyn Query>);
+        }
+        filters.extend(range(
+            "created",
+            options.created_after,
+            options.created_before,
+        ));
+        if let Some(root) = &options.root {
+            filters.push(term(self.field("path"), root));
+        }
+
+        if filters.is_empty() {
+            return Ok(query);
+        }
+
+        // filters must not affect the score of matched blocks
+        Ok(Box::new(BooleanQuery::new(
+            std::iter::once((Occur::Must, query))
+                .chain(filters.into_iter().map(|filter| {
+                    (
+                        Occur::Must,
+                        Box::new(ConstScoreQuery::new(filter, 0.0)) as Box<dyn Query>,
+                    )
+                }))
+                .collect(),
+        )))
+    }
+
+    pub fn search<O: Into<SearchOptions>>(
+        &self,
+        options: O,
     ) -> Result<SearchResults, Box<dyn std::error::Error>> {
         let mut items = Vec::new();
 
@@ -44,20 +241,49 @@ impl IndexingPluginImpl {
             .reload_policy(ReloadPolicy::OnCommit)
             .try_into()?;
         let searcher = reader.searcher();
-        let query = self.query_parser.parse_query(query.as_ref())?;
-        let top_docs = searcher.search(&query, &TopDocs::with_limit(10))?;
+        let options = options.into();
+        let query = self.build_query(&options)?;
+        let updated =
+            (options.updated_after.is_some() || options.updated_before.is_some()).then(|| {
+                options.updated_after.unwrap_or(u64::MIN)
+                    ..=options.updated_before.unwrap_or(u64::MAX)
+            });
+        // the updated range is checked after the search, so every match is a candidate
+        let limit = match updated {
+            Some(_) => (searcher.num_docs() as usize).max(SEARCH_LIMIT),
+            None => SEARCH_LIMIT,
+        };
+        let top_docs = searcher.search(&query, &TopDocs::with_limit(limit))?;
         // The actual documents still need to be retrieved from Tantivy’s store.
         // Since the body field was not configured as stored, the document returned will only contain a title.
 
         if !top_docs.is_empty() {
-            let block_id_field = self.schema.get_field("block_id").unwrap();
+            let block_id_field = self.field("block_id");
+            let flavor_field = self.field("flavor");
+            let created_field = self.field("created");
 
             for (score, doc_address) in top_docs {
+                if items.len() == SEARCH_LIMIT {
+                    break;
+                }
                 let retrieved_doc = searcher.doc(doc_address)?;
                 if let Some(Value::Str(id)) = retrieved_doc.get_first(block_id_field) {
+                    if let Some(updated) = &updated {
+                        if !matches!(self.updated.get(id), Some(time) if updated.contains(time)) {
+                            continue;
+                        }
+                    }
                     items.push(SearchResult {
                         block_id: id.to_string(),
                         score,
+                        flavor: retrieved_doc
+                            .get_first(flavor_field)
+                            .and_then(|flavor| flavor.as_text())
+                            .map(str::to_owned),
+                        created: retrieved_doc
+                            .get_first(created_field)
+                            .and_then(|created| created.as_u64()),
+                        snippet: None,
                     });
                 } else {
                     let to_json = self.schema.to_json(&retrieved_doc);
@@ -70,43 +296,100 @@ impl IndexingPluginImpl {
     }
 }
 
+fn string_prop(value: Option<&Any>) -> Option<String> {
+    match value {
+        Some(Any::String(str)) => Some(str.to_string()),
+        _ => None,
+    }
+}
+
+// tags are a list of strings, a single string is a single tag
+fn tags_prop(value: Option<&Any>) -> Vec<String> {
+    match value {
+        Some(Any::Array(tags)) => tags
+            .iter()
+            .filter_map(|tag| string_prop(Some(tag)))
+            .collect(),
+        value => string_prop(value).into_iter().collect(),
+    }
+}
+
+// the block itself followed by its ancestors, the parent of a block is the block
+// that actually lists it as a child, so detached blocks are their own root
+fn block_path(id: &str, parents: &HashMap<String, String>) -> Vec<String> {
+    let mut path = vec![id.to_owned()];
+    let mut visited = HashSet::from([id]);
+    let mut current = id;
+    while let Some(parent) = parents.get(current) {
+        if !visited.insert(parent.as_str()) {
+            break;
+        }
+        path.push(parent.clone());
+        current = parent.as_str();
+    }
+    path
+}
+
 impl PluginImpl for IndexingPluginImpl {
     fn on_update(&mut self, ws: &Workspace) -> Result<(), Box<dyn std::error::Error>> {
         let curr = self.queue_reindex.load(std::sync::atomic::Ordering::SeqCst);
         if curr > 0 {
-            // TODO: reindex
-
             let re_index_list = ws.with_trx(|t| {
                 ws.blocks(&t.trx, |blocks| {
-                    let mut re_index_list =
-                        HashMap::<String, (Option<String>, Option<String>)>::new();
+                    let mut parents = HashMap::new();
+                    let mut updated = HashMap::new();
+                    let mut re_index_list = HashMap::<String, IndexedBlock>::new();
                     for block in blocks {
-                        let title = block.content(&t.trx).get("title").map(ToOwned::to_owned);
-                        let body = block.content(&t.trx).get("text").map(ToOwned::to_owned);
+                        updated.insert(block.id(), block.updated(&t.trx));
+                        for child in block.children(&t.trx) {
+                            parents.insert(child, block.id());
+                        }
+
+                        let content = block.content(&t.trx);
                         re_index_list.insert(
                             block.id(),
-                            (
-                                title.and_then(|a| match a {
-                                    Any::String(str) => Some(str.to_string()),
-                                    _ => None,
-                                }),
-                                body.and_then(|a| match a {
-                                    Any::String(str) => Some(str.to_string()),
-                                    _ => None,
-                                }),
-                            ),
+                            IndexedBlock {
+                                flavor: block.flavor(&t.trx),
+                                title: string_prop(content.get("title")),
+                                text: string_prop(content.get("text")),
+                                tags: tags_prop(content.get("tags")),
+                                created: block.created(&t.trx),
+                                path: vec![],
+                            },
                         );
                     }
 
-                    re_index_list
+                    for (id, block) in re_index_list.iter_mut() {
+                        block.path = block_path(id, &parents);
+                    }
+
+                    (re_index_list, updated)
                 })
             });
+            let (re_index_list, updated) = re_index_list;
+            self.updated = updated;
 
-            // dbg!((txn, upd));
-            // println!("got update event: {items}");
-            // just re-index stupidly
-            self.re_index_content(re_index_list)
-                .map_err(|err| format!("Error during reindex: {err:?}"))?;
+            let changed = re_index_list
+                .iter()
+                .filter(|(id, block)| self.indexed.get(*id) != Some(block))
+                .map(|(id, block)| (id.clone(), block.clone()))
+                .collect::<Vec<_>>();
+            let removed = self
+                .indexed
+                .keys()
+                .chain(&self.persisted)
+                .filter(|id| !re_index_list.contains_key(*id))
+                .cloned()
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect::<Vec<_>>();
+
+            if !changed.is_empty() || !removed.is_empty() {
+                self.re_index_content(changed, removed)
+                    .map_err(|err| format!("Error during reindex: {err:?}"))?;
+            }
+            self.indexed = re_index_list;
+            self.persisted.clear();
         }
 
         // reset back down now that the update was applied
@@ -118,32 +401,47 @@ impl PluginImpl for IndexingPluginImpl {
 }
 
 impl IndexingPluginImpl {
-    fn re_index_content<BlockIdTitleAndTextIter>(
+    fn re_index_content(
         &mut self,
-        blocks: BlockIdTitleAndTextIter,
-    ) -> Result<(), Box<dyn std::error::Error>>
-    where
-        // TODO: use a structure with better names than tuples?
-        BlockIdTitleAndTextIter: IntoIterator<Item = (String, (Option<String>, Option<String>))>,
-    {
-        let block_id_field = self.schema.get_field("block_id").unwrap();
-        let title_field = self.schema.get_field("title").unwrap();
-        let body_field = self.schema.get_field("body").unwrap();
+        blocks: Vec<(String, IndexedBlock)>,
+        removed: Vec<String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let block_id_field = self.field("block_id");
+        let flavor_field = self.field("flavor");
+        let title_field = self.field("title");
+        let body_field = self.field("body");
+        let tags_field = self.field("tags");
+        let created_field = self.field("created");
+        let path_field = self.field("path");
 
         let mut writer = self
             .index
             .writer(50_000_000)
             .map_err(|err| format!("Error creating writer: {err:?}"))?;
 
-        for (block_id, (block_title_opt, block_text_opt)) in blocks {
+        for block_id in removed {
+            writer.delete_term(Term::from_field_text(block_id_field, &block_id));
+        }
+
+        for (block_id, block) in blocks {
+            // replace the previously indexed version of the block
+            writer.delete_term(Term::from_field_text(block_id_field, &block_id));
             let mut block_doc = Document::new();
             block_doc.add_text(block_id_field, block_id);
-            if let Some(block_title) = block_title_opt {
+            block_doc.add_text(flavor_field, block.flavor);
+            if let Some(block_title) = block.title {
                 block_doc.add_text(title_field, block_title);
             }
-            if let Some(block_text) = block_text_opt {
+            if let Some(block_text) = block.text {
                 block_doc.add_text(body_field, block_text);
             }
+            for tag in block.tags {
+                block_doc.add_text(tags_field, tag);
+            }
+            block_doc.add_u64(created_field, block.created);
+            for ancestor in block.path {
+                block_doc.add_text(path_field, ancestor);
+            }
             writer.add_document(block_doc)?;
         }
 
@@ -159,6 +457,7 @@ impl IndexingPluginImpl {
 mod test {
     use super::super::*;
     use super::*;
+    use yrs::{ArrayPrelim, Map};
 
     // out of order for now, in the future, this can be made in order by sorting before
     // we reduce to just the block ids. Then maybe we could first sort on score, then sort on
@@ -271,4 +570,284 @@ mod test {
             })
             .is_some());
     }
+
+    #[test]
+    fn skip_reindex_without_text_change() {
+        let workspace = Workspace::from_doc(Default::default(), "wk-layout");
+        let opstamp = |workspace: &Workspace| {
+            workspace
+                .with_plugin::<IndexingPluginImpl, u64>(|search_plugin| {
+                    search_plugin.index.load_metas().unwrap().opstamp
+                })
+                .unwrap()
+        };
+
+        workspace.with_trx(|mut t| {
+            let page = t.create("page", "affine:page");
+            let a = t.create("a", "affine:text");
+            let b = t.create("b", "affine:text");
+            a.set(&mut t.trx, "text", "alpha");
+            b.set(&mut t.trx, "text", "beta");
+            page.push_children(&mut t.trx, &a);
+            page.push_children(&mut t.trx, &b);
+        });
+        workspace
+            .update_plugin::<IndexingPluginImpl>()
+            .expect("update text search plugin");
+        let indexed = opstamp(&workspace);
+
+        // layout only: move a block and touch a non-text property
+        workspace.with_trx(|mut t| {
+            let page = workspace.get(&t.trx, "page").unwrap();
+            let b = workspace.get(&t.trx, "b").unwrap();
+            page.insert_children_at(&mut t.trx, &b, 0);
+            b.set(&mut t.trx, "collapsed", true);
+        });
+        workspace
+            .update_plugin::<IndexingPluginImpl>()
+            .expect("update text search plugin");
+        assert_eq!(opstamp(&workspace), indexed);
+
+        // the updated range follows the layout change anyway
+        let updated = workspace.with_trx(|t| workspace.get(&t.trx, "b").unwrap().updated(&t.trx));
+        let found = workspace
+            .with_plugin::<IndexingPluginImpl, _>(|search_plugin| {
+                search_plugin.search(SearchOptions {
+                    updated_after: Some(updated),
+                    ..Default::default()
+                })
+            })
+            .unwrap()
+            .unwrap();
+        assert!(found.0.iter().any(|result| result.block_id == "b"));
+
+        // text changes are still picked up and replace the old content
+        workspace.with_trx(|mut t| {
+            let a = workspace.get(&t.trx, "a").unwrap();
+            a.set(&mut t.trx, "text", "gamma");
+            t.remove("b");
+        });
+        workspace
+            .update_plugin::<IndexingPluginImpl>()
+            .expect("update text search plugin");
+        assert!(opstamp(&workspace) > indexed);
+
+        assert!(workspace
+            .with_plugin::<IndexingPluginImpl, ()>(|search_plugin| {
+                expect_search_gives_ids!(search_plugin, "gamma", &["a"]);
+                expect_search_gives_ids!(search_plugin, "alpha", &[] as &[&str]);
+                expect_search_gives_ids!(search_plugin, "beta", &[] as &[&str]);
+            })
+            .is_some());
+    }
+
+    #[test]
+    fn search_with_filters() {
+        let workspace = Workspace::from_doc(Default::default(), "wk-filter");
+
+        workspace.with_trx(|mut t| {
+            let page = t.create("page", "affine:page");
+            let frame = t.create("frame", "affine:frame");
+            let heading = t.create("heading", "affine:heading");
+            let paragraph = t.create("paragraph", "affine:paragraph");
+            let other = t.create("other", "affine:paragraph");
+            heading.set(&mut t.trx, "text", "filter heading");
+            paragraph.set(&mut t.trx, "text", "filter paragraph");
+            other.set(&mut t.trx, "text", "filter other");
+            page.push_children(&mut t.trx, &frame);
+            frame.push_children(&mut t.trx, &heading);
+            frame.push_children(&mut t.trx, &paragraph);
+        });
+
+        let search = |options: SearchOptions| {
+            let mut ids = workspace
+                .search(options)
+                .expect("no error searching")
+                .0
+                .into_iter()
+                .map(|r| r.block_id)
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(
+            search(SearchOptions {
+                query: "filter".into(),
+                flavors: vec!["affine:heading".into()],
+                ..Default::default()
+            }),
+            vec!["heading"]
+        );
+        assert_eq!(
+            search(SearchOptions {
+                query: "filter".into(),
+                root: Some("page".into()),
+                ..Default::default()
+            }),
+            vec!["heading", "paragraph"]
+        );
+        assert_eq!(
+            search(SearchOptions {
+                root: Some("frame".into()),
+                ..Default::default()
+            }),
+            vec!["frame", "heading", "paragraph"]
+        );
+
+        let (created, updated) = workspace.with_trx(|t| {
+            let block = workspace.get(&t.trx, "heading").unwrap();
+            (block.created(&t.trx), block.updated(&t.trx))
+        });
+        assert_eq!(
+            search(SearchOptions {
+                query: "filter".into(),
+                created_after: Some(created + 60_000),
+                ..Default::default()
+            }),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            search(SearchOptions {
+                query: "filter".into(),
+                flavors: vec!["affine:heading".into()],
+                created_before: Some(created),
+                updated_after: Some(updated),
+                ..Default::default()
+            }),
+            vec!["heading"]
+        );
+    }
+
+    #[test]
+    fn search_metadata() {
+        let workspace = Workspace::from_doc(Default::default(), "wk-metadata");
+
+        workspace.with_trx(|mut t| {
+            let heading = t.create("heading", "affine:heading");
+            let paragraph = t.create("paragraph", "affine:paragraph");
+            heading.set(&mut t.trx, "text", "metadata heading");
+            // clients write tags as an array, which `Block::set` doesn't support
+            let heading = workspace
+                .blocks
+                .get(&t.trx, "heading")
+                .and_then(|b| b.to_ymap());
+            heading.unwrap().insert(
+                &mut t.trx,
+                "prop:tags",
+                ArrayPrelim::from(["roadmap", "draft"]),
+            );
+            paragraph.set(&mut t.trx, "text", "metadata paragraph");
+            paragraph.set(&mut t.trx, "tags", "roadmap");
+        });
+
+        let search = |query: &str, filter: SearchFilter| {
+            let mut ids = workspace
+                .search_with_filter(query, filter)
+                .expect("no error searching")
+                .0
+                .into_iter()
+                .map(|r| r.block_id)
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(
+            search("roadmap", Default::default()),
+            vec!["heading", "paragraph"]
+        );
+        assert_eq!(search("draft", Default::default()), vec!["heading"]);
+        assert_eq!(
+            search(
+                "roadmap",
+                SearchFilter {
+                    flavors: vec!["affine:paragraph".into()],
+                    ..Default::default()
+                }
+            ),
+            vec!["paragraph"]
+        );
+
+        let created =
+            workspace.with_trx(|t| workspace.get(&t.trx, "heading").unwrap().created(&t.trx));
+        assert!(search(
+            "metadata",
+            SearchFilter {
+                created_after: Some(created + 60_000),
+                ..Default::default()
+            }
+        )
+        .is_empty());
+
+        let results = workspace.search("draft").expect("no error searching").0;
+        assert_eq!(results[0].flavor.as_deref(), Some("affine:heading"));
+        assert_eq!(results[0].created, Some(created));
+        assert_eq!(results[0].snippet.as_deref(), Some("metadata heading"));
+    }
+
+    #[test]
+    fn search_subtree_after_move() {
+        let workspace = Workspace::from_doc(Default::default(), "wk-subtree");
+
+        workspace.with_trx(|mut t| {
+            let a = t.create("a", "affine:page");
+            let b = t.create("b", "affine:page");
+            let parent = t.create("parent", "affine:frame");
+            a.push_children(&mut t.trx, &parent);
+            // more matches outside the subtree than the result limit
+            for i in 0..20 {
+                let child = t.create(format!("child{i:02}"), "affine:paragraph");
+                child.set(&mut t.trx, "text", "moved");
+                if i < 3 {
+                    parent.push_children(&mut t.trx, &child);
+                } else {
+                    b.push_children(&mut t.trx, &child);
+                }
+            }
+        });
+
+        let search = |root: &str| {
+            let mut ids = workspace
+                .search(SearchOptions {
+                    query: "moved".into(),
+                    root: Some(root.into()),
+                    ..Default::default()
+                })
+                .expect("no error searching")
+                .0
+                .into_iter()
+                .map(|r| r.block_id)
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(search("a"), vec!["child00", "child01", "child02"]);
+        assert_eq!(search("parent"), vec!["child00", "child01", "child02"]);
+
+        // move the whole subtree to another page
+        workspace.with_trx(|mut t| {
+            let a = workspace.get(&t.trx, "a").unwrap();
+            let b = workspace.get(&t.trx, "b").unwrap();
+            let parent = workspace.get(&t.trx, "parent").unwrap();
+            a.remove_children(&mut t.trx, &parent);
+            b.push_children(&mut t.trx, &parent);
+        });
+
+        assert!(search("a").is_empty());
+        assert_eq!(search("parent"), vec!["child00", "child01", "child02"]);
+
+        // move a single block out of the subtree
+        workspace.with_trx(|mut t| {
+            let a = workspace.get(&t.trx, "a").unwrap();
+            let parent = workspace.get(&t.trx, "parent").unwrap();
+            let child = workspace.get(&t.trx, "child01").unwrap();
+            parent.remove_children(&mut t.trx, &child);
+            a.push_children(&mut t.trx, &child);
+        });
+
+        assert_eq!(search("a"), vec!["child01"]);
+        assert_eq!(search("parent"), vec!["child00", "child02"]);
+    }
 }
@@ -2,8 +2,8 @@ mod indexer;
 mod register;
 mod tokenizer;
 
-use super::{PluginImpl, PluginRegister, Workspace};
+use super::{info, PluginImpl, PluginRegister, Workspace};
 use tokenizer::{tokenizers_register, GRAM_TOKENIZER};
 
-pub use indexer::{IndexingPluginImpl, SearchResult, SearchResults};
+pub use indexer::{IndexingPluginImpl, SearchFilter, SearchOptions, SearchResult, SearchResults};
 pub(super) use register::IndexingPluginRegister;
@@ -1,21 +1,115 @@
 use super::*;
 use std::{
-    path::PathBuf,
-    rc::Rc,
-    sync::{atomic::AtomicU32, Arc},
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicU32, Arc, Mutex, Weak},
 };
 use tantivy::{
-    query::QueryParser,
-    schema::{IndexRecordOption, Schema, TextFieldIndexing, TextOptions, STORED, STRING},
-    Index,
+    collector::DocSetCollector,
+    query::{AllQuery, QueryParser},
+    schema::{
+        Field, IndexRecordOption, Schema, TextFieldIndexing, TextOptions, FAST, INDEXED, STORED,
+        STRING,
+    },
+    Index, ReloadPolicy,
 };
 
+// bump on every change of the schema below, persisted indexes of another version are
+// dropped and rebuilt
+const SCHEMA_VERSION: u32 = 2;
+// persisted indexes are kept in a sub directory named after their schema version
+const INDEX_DIR_PREFIX: &str = "search-v";
+
+// the sub directory of the index with the current schema, indexes with another schema
+// are removed, the directory may be shared with other plugins
+fn versioned_index_dir(dir: &Path) -> io::Result<PathBuf> {
+    let current = format!("{INDEX_DIR_PREFIX}{SCHEMA_VERSION}");
+    std::fs::create_dir_all(dir)?;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with(INDEX_DIR_PREFIX) && name != current {
+            info!("drop search index {:?} of an old schema", entry.path());
+            std::fs::remove_dir_all(entry.path())?;
+        }
+    }
+    remove_unversioned_index(dir)?;
+
+    let index_dir = dir.join(current);
+    std::fs::create_dir_all(&index_dir)?;
+    Ok(index_dir)
+}
+
+// indexes were kept in the directory itself before the schema was versioned,
+// remove the files tantivy manages there
+fn remove_unversioned_index(dir: &Path) -> io::Result<()> {
+    let managed = dir.join(".managed.json");
+    let Ok(files) = std::fs::read(&managed) else {
+        return Ok(());
+    };
+    info!("drop unversioned search index in {:?}", dir);
+    for file in serde_json::from_slice::<Vec<PathBuf>>(&files).unwrap_or_default() {
+        // only plain file names, never a path out of the directory
+        if file.file_name() == Some(file.as_os_str()) {
+            std::fs::remove_file(dir.join(file)).ok();
+        }
+    }
+    for file in ["meta.json", ".tantivy-meta.lock", ".tantivy-writer.lock"] {
+        std::fs::remove_file(dir.join(file)).ok();
+    }
+    std::fs::remove_file(managed)
+}
+
+// indexes opened from a directory, every directory is opened once and its index shared
+// by the workspaces using it, as only one writer can hold the lock of a directory
+static OPENED_INDEXES: Mutex<Vec<(PathBuf, Weak<Index>)>> = Mutex::new(Vec::new());
+
+fn open_persisted_index(
+    dir: &Path,
+    schema: &Schema,
+) -> Result<Arc<Index>, Box<dyn std::error::Error>> {
+    let mut opened = OPENED_INDEXES.lock().unwrap();
+    opened.retain(|(_, index)| index.strong_count() > 0);
+    let index = opened
+        .iter()
+        .find(|(path, _)| path == dir)
+        .and_then(|(_, index)| index.upgrade());
+    if let Some(index) = index {
+        return Ok(index);
+    }
+
+    let directory = tantivy::directory::MmapDirectory::open(versioned_index_dir(dir)?)?;
+    let index = Arc::new(Index::open_or_create(directory, schema.clone())?);
+    tokenizers_register(index.tokenizers());
+    opened.push((dir.to_owned(), Arc::downgrade(&index)));
+    Ok(index)
+}
+
+// ids of the blocks in an index, a persisted index may still have blocks that were
+// removed from the workspace while it wasn't loaded
+fn indexed_block_ids(index: &Index, block_id: Field) -> tantivy::Result<HashSet<String>> {
+    let searcher = index
+        .reader_builder()
+        .reload_policy(ReloadPolicy::Manual)
+        .try_into()?
+        .searcher();
+    let mut ids = HashSet::new();
+    for address in searcher.search(&AllQuery, &DocSetCollector)? {
+        let doc = searcher.doc(address)?;
+        if let Some(id) = doc.get_first(block_id).and_then(|id| id.as_text()) {
+            ids.insert(id.to_owned());
+        }
+    }
+    Ok(ids)
+}
+
 #[derive(Debug)]
 enum IndexingStorageKind {
     /// Store index in memory (default)
     Ram,
     /// Store index in a specific directory
-    #[allow(dead_code)]
     PersistedDirectory(PathBuf),
 }
 
@@ -38,7 +132,6 @@ impl IndexingPluginRegister {
         }
     }
 
-    #[allow(dead_code)]
     pub fn persisted_directory(path: PathBuf) -> Self {
         Self {
             storage_kind: IndexingStorageKind::PersistedDirectory(path),
@@ -58,24 +151,31 @@ impl PluginRegister for IndexingPluginRegister {
         let mut schema_builder = Schema::builder();
         schema_builder.add_text_field("block_id", STRING | STORED);
         schema_builder.add_text_field("title", options.clone()); // props:title
-        schema_builder.add_text_field("body", options); // props:text
+        schema_builder.add_text_field("body", options.clone()); // props:text
+        schema_builder.add_text_field("tags", options); // props:tags
+
+        // filters of SearchOptions
+        schema_builder.add_text_field("flavor", STRING | STORED); // sys:flavor
+        schema_builder.add_u64_field("created", INDEXED | FAST | STORED); // sys:created
+        schema_builder.add_text_field("path", STRING); // block id and its ancestors
         let schema = schema_builder.build();
 
-        let index_dir: Box<dyn tantivy::Directory> = match &self.storage_kind {
-            IndexingStorageKind::Ram => Box::new(tantivy::directory::RamDirectory::create()),
+        let (index, persisted) = match &self.storage_kind {
+            IndexingStorageKind::Ram => {
+                let index = Index::create_in_ram(schema.clone());
+                tokenizers_register(index.tokenizers());
+                (Arc::new(index), HashSet::new())
+            }
             IndexingStorageKind::PersistedDirectory(dir) => {
-                Box::new(tantivy::directory::MmapDirectory::open(dir)?)
+                let index = open_persisted_index(dir, &schema)?;
+                let persisted = indexed_block_ids(&index, schema.get_field("block_id").unwrap())?;
+                (index, persisted)
             }
         };
 
-        let index = Rc::new({
-            let index = Index::open_or_create(index_dir, schema.clone())?;
-            tokenizers_register(index.tokenizers());
-            index
-        });
-
         let title = schema.get_field("title").unwrap();
         let body = schema.get_field("body").unwrap();
+        let tags = schema.get_field("tags").unwrap();
 
         let queue_reindex = Arc::new(AtomicU32::new(
             // require an initial re-index by setting the default above 0
@@ -102,11 +202,104 @@ impl PluginRegister for IndexingPluginRegister {
 
         Ok(IndexingPluginImpl {
             schema,
-            query_parser: QueryParser::for_index(&index, vec![title, body]),
+            query_parser: QueryParser::for_index(&index, vec![title, body, tags]),
             index,
             queue_reindex,
+            indexed: Default::default(),
+            persisted,
+            updated: Default::default(),
             // needs to drop sub with everything else
             _update_sub: sub,
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::super::super::insert_plugin;
+    use super::*;
+
+    #[test]
+    fn rebuild_index_of_old_schema() {
+        let dir = std::env::temp_dir().join(format!("jwst-search-{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+
+        // an unversioned index with an older schema, and another file in the directory
+        let mut schema_builder = Schema::builder();
+        schema_builder.add_text_field("block_id", STRING | STORED);
+        schema_builder.add_text_field("title", STRING);
+        let old = schema_builder.build();
+        std::fs::create_dir_all(&dir).unwrap();
+        Index::create_in_dir(&dir, old.clone()).unwrap();
+        std::fs::create_dir_all(dir.join("search-v1")).unwrap();
+        std::fs::write(dir.join("backlinks.json"), "{}").unwrap();
+
+        let workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            let block = t.create("a", "affine:text");
+            block.set(&mut t.trx, "title", "persisted title");
+        });
+        let register = || IndexingPluginRegister::persisted_directory(dir.clone());
+        let workspace = insert_plugin(workspace, register()).unwrap();
+        let found = serde_json::to_value(workspace.search("persisted").unwrap()).unwrap();
+        assert_eq!(found.as_array().map(|found| found.len()), Some(1));
+        assert_eq!(found[0]["block_id"], "a");
+
+        assert!(!dir.join("meta.json").exists());
+        assert!(!dir.join("search-v1").exists());
+        assert!(dir.join(format!("search-v{SCHEMA_VERSION}")).exists());
+        assert!(dir.join("backlinks.json").exists());
+
+        // the index of the current schema is opened again
+        drop(workspace);
+        let workspace = insert_plugin(Workspace::new("test"), register()).unwrap();
+        assert!(Index::open_in_dir(dir.join(format!("search-v{SCHEMA_VERSION}"))).is_ok());
+        drop(workspace);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn drop_blocks_removed_before_reopen() {
+        let dir = std::env::temp_dir().join(format!("jwst-search-removed-{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        let register = || IndexingPluginRegister::persisted_directory(dir.clone());
+        let ids = |workspace: &Workspace| {
+            let found = serde_json::to_value(workspace.search("persisted").unwrap()).unwrap();
+            let mut ids = found
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|result| result["block_id"].as_str().unwrap().to_owned())
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        let workspace = |blocks: &[&str]| {
+            let workspace = Workspace::new("test");
+            workspace.with_trx(|mut t| {
+                for id in blocks {
+                    let block = t.create(*id, "affine:text");
+                    block.set(&mut t.trx, "title", "persisted title");
+                }
+            });
+            insert_plugin(workspace, register()).unwrap()
+        };
+
+        let first = workspace(&["a", "b"]);
+        assert_eq!(ids(&first), vec!["a", "b"]);
+
+        // another workspace of the same directory shares the opened index
+        let second = workspace(&["a", "b"]);
+        assert_eq!(ids(&second), vec!["a", "b"]);
+        drop(first);
+        drop(second);
+
+        // b was removed while the index wasn't loaded
+        let reopened = workspace(&["a"]);
+        assert_eq!(ids(&reopened), vec!["a"]);
+        drop(reopened);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -1,36 +1,202 @@
+mod backlinks;
 #[cfg(feature = "workspace-search")]
 mod indexing;
 mod plugin;
 
 use super::*;
+use std::{any::TypeId, path::PathBuf, sync::Arc};
 
+pub(super) use backlinks::BacklinksPluginImpl;
+pub use backlinks::{BlockRef, DEFAULT_LINK_PROPERTY_KEYS};
 #[cfg(feature = "workspace-search")]
 pub(super) use indexing::IndexingPluginImpl;
-pub(super) use plugin::{PluginImpl, PluginMap, PluginRegister};
+pub(super) use plugin::PluginMap;
+pub use plugin::{PluginError, PluginImpl, PluginRegister};
 
 #[cfg(feature = "workspace-search")]
-pub use indexing::{SearchResult, SearchResults};
+pub use indexing::{SearchFilter, SearchOptions, SearchResult, SearchResults};
 
 /// Setup a [WorkspacePlugin] and insert it into the [Workspace].
 /// See [plugins].
+#[cfg(test)]
 fn insert_plugin(
     mut workspace: Workspace,
     config: impl PluginRegister,
 ) -> Result<Workspace, Box<dyn std::error::Error>> {
-    let plugin = config.setup(&mut workspace)?;
-    workspace.plugins.insert_plugin(plugin)?;
+    setup_plugin(&mut workspace, config)?;
 
     Ok(workspace)
 }
 
-/// Setup plugin: [indexing]
-pub(super) fn setup_plugin(workspace: Workspace) -> Workspace {
-    // default plugins
-    if cfg!(feature = "workspace-search") {
-        // Set up indexing plugin
-        insert_plugin(workspace, indexing::IndexingPluginRegister::default())
-            .expect("Failed to setup search plugin")
-    } else {
-        workspace
+pub(super) fn setup_plugin(
+    workspace: &mut Workspace,
+    config: impl PluginRegister,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let plugin = config.setup(workspace)?;
+    workspace.plugins.insert_plugin(plugin)?;
+
+    Ok(())
+}
+
+// sets up a registered plugin for a new workspace
+pub(super) type PluginSetup =
+    Arc<dyn Fn(&mut Workspace) -> Result<(), Box<dyn std::error::Error>> + Send + Sync>;
+
+pub(super) fn plugin_setup<R>(register: R) -> (TypeId, PluginSetup)
+where
+    R: PluginRegister + Clone + Send + Sync + 'static,
+{
+    let setup = move |workspace: &mut Workspace| setup_plugin(workspace, register.clone());
+    (TypeId::of::<R::Plugin>(), Arc::new(setup))
+}
+
+// name of the index directory of a workspace, unique for every id and never a path
+fn index_directory_name(id: &str) -> String {
+    let mut name = String::with_capacity(id.len());
+    for byte in id.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' {
+            name.push(byte as char);
+        } else {
+            name.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    name
+}
+
+/// Plugins set up for a workspace, shared by its clones, see [WorkspaceBuilder::plugins].
+///
+/// The default are the [backlinks] index and, with the `workspace-search` feature,
+/// the [indexing] plugin for [Workspace::search], both kept in memory.
+#[derive(Clone)]
+pub struct WorkspacePlugins {
+    backlinks: bool,
+    #[cfg_attr(not(feature = "workspace-search"), allow(dead_code))]
+    search: bool,
+    index_directory: Option<PathBuf>,
+    custom: Vec<(TypeId, PluginSetup)>,
+}
+
+impl Default for WorkspacePlugins {
+    fn default() -> Self {
+        Self {
+            backlinks: true,
+            search: cfg!(feature = "workspace-search"),
+            index_directory: None,
+            custom: vec![],
+        }
+    }
+}
+
+impl WorkspacePlugins {
+    /// No plugins at all, [Workspace::backlinks] returns nothing and [Workspace::search] fails.
+    pub fn none() -> Self {
+        Self {
+            backlinks: false,
+            search: false,
+            index_directory: None,
+            custom: vec![],
+        }
+    }
+
+    /// Enable or disable the search index, it's never set up without the
+    /// `workspace-search` feature.
+    pub fn search(self, enabled: bool) -> Self {
+        Self {
+            search: enabled && cfg!(feature = "workspace-search"),
+            ..self
+        }
+    }
+
+    /// Keep the indexes of each workspace in a sub directory named after it,
+    /// instead of rebuilding them in memory every time the workspace is loaded.
+    /// Characters of the workspace id other than ASCII letters, digits, `-` and `_`
+    /// are percent encoded in the name, so the id never escapes the directory.
+    pub fn index_directory(self, directory: PathBuf) -> Self {
+        Self {
+            index_directory: Some(directory),
+            ..self
+        }
+    }
+
+    /// Set up another plugin after the built-in ones, replacing the registered
+    /// config of the same plugin type. The interface is experimental.
+    pub fn register<R>(mut self, register: R) -> Self
+    where
+        R: PluginRegister + Clone + Send + Sync + 'static,
+    {
+        self.push(plugin_setup(register));
+        self
+    }
+
+    pub(super) fn push(&mut self, (plugin, setup): (TypeId, PluginSetup)) {
+        self.custom.retain(|(registered, _)| *registered != plugin);
+        self.custom.push((plugin, setup));
+    }
+
+    /// Set up the plugins, a plugin that fails is logged and left out.
+    /// See [plugins]: [backlinks], [indexing].
+    pub(super) fn setup(&self, workspace: &mut Workspace) {
+        let directory = self
+            .index_directory
+            .as_ref()
+            .map(|directory| directory.join(index_directory_name(&workspace.id())));
+
+        if self.backlinks {
+            let register = match &directory {
+                Some(directory) => {
+                    backlinks::BacklinksPluginRegister::persisted_directory(directory.clone())
+                }
+                None => backlinks::BacklinksPluginRegister::default(),
+            };
+            if let Err(e) = setup_plugin(workspace, register) {
+                error!(
+                    "failed to setup backlinks plugin of {}: {}",
+                    workspace.id(),
+                    e
+                );
+            }
+        }
+
+        #[cfg(feature = "workspace-search")]
+        self.setup_search(workspace, directory);
+
+        for (_, setup) in &self.custom {
+            if let Err(e) = setup(workspace) {
+                error!("failed to setup plugin of {}: {}", workspace.id(), e);
+            }
+        }
+    }
+
+    #[cfg(feature = "workspace-search")]
+    fn setup_search(&self, workspace: &mut Workspace, directory: Option<PathBuf>) {
+        if !self.search {
+            return;
+        }
+        let result = match directory {
+            Some(directory) => std::fs::create_dir_all(&directory)
+                .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)
+                .and_then(|_| {
+                    let register = indexing::IndexingPluginRegister::persisted_directory(directory);
+                    setup_plugin(workspace, register)
+                }),
+            None => setup_plugin(workspace, indexing::IndexingPluginRegister::default()),
+        };
+        if let Err(e) = result {
+            error!("failed to setup search plugin of {}: {}", workspace.id(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn index_directory_names() {
+        assert_eq!(index_directory_name("Workspace-1_a"), "Workspace-1_a");
+        assert_eq!(index_directory_name("../a"), "%2E%2E%2Fa");
+        assert_eq!(index_directory_name("/etc"), "%2Fetc");
+        assert_eq!(index_directory_name("tenant:%"), "tenant%3A%25");
+        assert_eq!(index_directory_name("工"), "%E5%B7%A5");
     }
 }
@@ -1,11 +1,31 @@
-//! Plugins are an internal experimental interface for extending the [Workspace].
+//! Plugins are an experimental interface for extending the [Workspace], public with the
+//! `experimental-plugins` feature.
 
 use super::*;
-use std::sync::{Arc, RwLock};
+use std::{
+    any::type_name,
+    collections::BTreeSet,
+    sync::{Arc, RwLock},
+};
+use thiserror::Error;
+use tokio::sync::broadcast::{channel, Receiver, Sender};
 use type_map::TypeMap;
 
+// errors kept for subscribers that fall behind
+const PLUGIN_ERROR_CAPACITY: usize = 64;
+
+/// Error returned by a plugin while updating, see [Workspace::subscribe_plugin_errors].
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+#[error("plugin {plugin} failed to update workspace {workspace}: {message}")]
+pub struct PluginError {
+    pub workspace: String,
+    /// Type name of the plugin.
+    pub plugin: &'static str,
+    pub message: String,
+}
+
 /// A configuration from which a [WorkspacePlugin] can be created from.
-pub(crate) trait PluginRegister {
+pub trait PluginRegister {
     type Plugin: PluginImpl;
     // Do we need self?
     fn setup(self, ws: &mut Workspace) -> Result<Self::Plugin, Box<dyn std::error::Error>>;
@@ -16,7 +36,7 @@ pub(crate) trait PluginRegister {
 /// A workspace plugin which comes from a corresponding [WorkspacePluginConfig::setup].
 /// In that setup call, the plugin will have initial access to the whole [Workspace],
 /// and will be able to add listeners to changes to blocks in the [Workspace].
-pub(crate) trait PluginImpl: 'static {
+pub trait PluginImpl: 'static {
     /// IDEA 1/10:
     /// This update is called sometime between when we know changes have been made to the workspace
     /// and the time when we will get the plugin to query its data (e.g. search())
@@ -26,23 +46,51 @@ pub(crate) trait PluginImpl: 'static {
     }
 }
 
-#[derive(Default)]
+#[derive(Clone)]
 pub(crate) struct PluginMap {
     /// We store plugins into the TypeMap, so that their ownership is tied to [Workspace].
     /// This enables us to properly manage lifetimes of observers which will subscribe
     /// into events that the [Workspace] experiences, like block updates.
     map: Arc<RwLock<TypeMap>>,
+    /// Type names of the plugins in the map, see [PluginMap::names].
+    names: Arc<RwLock<BTreeSet<&'static str>>>,
+    /// Errors of all plugins.
+    errors: Sender<PluginError>,
+}
+
+impl Default for PluginMap {
+    fn default() -> Self {
+        Self {
+            map: Default::default(),
+            names: Default::default(),
+            errors: channel(PLUGIN_ERROR_CAPACITY).0,
+        }
+    }
 }
 
 impl PluginMap {
+    pub(crate) fn subscribe_errors(&self) -> Receiver<PluginError> {
+        self.errors.subscribe()
+    }
+
     pub(crate) fn insert_plugin<P: PluginImpl>(
         &self,
         plugin: P,
     ) -> Result<&Self, Box<dyn std::error::Error>> {
         self.map.write().unwrap().insert(plugin);
+        let name = type_name::<P>();
+        self.names
+            .write()
+            .unwrap()
+            .insert(name.rsplit("::").next().unwrap_or(name));
         Ok(self)
     }
 
+    /// Type names of the plugins without their module path, sorted.
+    pub(crate) fn names(&self) -> Vec<&'static str> {
+        self.names.read().unwrap().iter().copied().collect()
+    }
+
     pub(crate) fn with_plugin<P: PluginImpl, T>(&self, cb: impl Fn(&P) -> T) -> Option<T> {
         let map = self.map.read().unwrap();
         let plugin = map.get::<P>();
@@ -56,8 +104,51 @@ impl PluginMap {
         let mut map = self.map.write().unwrap();
         let plugin = map.get_mut::<P>().ok_or("Plugin not found")?;
 
-        plugin.on_update(ws)?;
+        if let Err(e) = plugin.on_update(ws) {
+            // no subscribers is fine, the error is still returned to the caller
+            let _ = self.errors.send(PluginError {
+                workspace: ws.id(),
+                plugin: type_name::<P>(),
+                message: e.to_string(),
+            });
+            return Err(e);
+        }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::{executor::block_on, StreamExt};
+
+    struct FailingPlugin;
+
+    impl PluginImpl for FailingPlugin {
+        fn on_update(&mut self, _ws: &Workspace) -> Result<(), Box<dyn std::error::Error>> {
+            Err("index is corrupted".into())
+        }
+    }
+
+    #[test]
+    fn plugin_errors() {
+        let workspace = Workspace::new("test");
+        let errors = workspace.subscribe_plugin_errors();
+        futures::pin_mut!(errors);
+
+        // plugins of a clone report to the same subscribers
+        let clone = workspace.clone();
+        clone.plugins.insert_plugin(FailingPlugin).unwrap();
+        assert!(clone.update_plugin::<FailingPlugin>().is_err());
+
+        assert_eq!(
+            block_on(errors.next()),
+            Some(PluginError {
+                workspace: "test".into(),
+                plugin: type_name::<FailingPlugin>(),
+                message: "index is corrupted".into(),
+            })
+        );
+    }
+}
@@ -0,0 +1,321 @@
+use super::*;
+use crate::{constants::sys, PropertyKey};
+use lib0::any::Any;
+use std::collections::BTreeSet;
+use thiserror::Error;
+use yrs::{
+    types::{text::TextPrelim, Value},
+    Array, ArrayPrelim, ArrayRef, Doc, Map, MapPrelim, MapRef, ReadTxn, StateVector, Transact,
+    TransactionMut,
+};
+
+#[derive(Debug, Error)]
+pub enum SelectionError {
+    #[error("blocks not found: {0:?}")]
+    MissingBlocks(Vec<String>),
+}
+
+impl Workspace {
+    /// Encode `block_ids` and their descendants as a v1 update of a fresh doc, which can be
+    /// applied to any workspace with [Workspace::apply_updates], e.g. to share a single page.
+    ///
+    /// The selected blocks are copied with their history, the parent of a block is dropped
+    /// when it is not selected. Rich text is copied as plain text. Applying the update to a
+    /// workspace that already has blocks with the same ids merges them like concurrent edits.
+    pub fn encode_selection(&self, block_ids: &[&str]) -> Result<Vec<u8>, SelectionError> {
+        let doc = self.doc();
+        let trx = doc.transact();
+
+        let missing = block_ids
+            .iter()
+            .filter(|id| !self.exists(&trx, id))
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(SelectionError::MissingBlocks(missing));
+        }
+
+        let selected = self.descendants(&trx, block_ids);
+        trace!("encode selection of {} blocks", selected.len());
+        Ok(self.encode_blocks(&trx, &selected, &BTreeSet::new()))
+    }
+
+    /// Encode the blocks matching `predicate` as a v1 update of a fresh doc, like
+    /// [Workspace::encode_selection] without including the descendants, e.g.
+    /// `encode_subset(&trx, |b, t| b.flavor(t) == "affine:page")` exports only the pages.
+    ///
+    /// Children of the matching blocks that don't match are exported as stubs, which keep
+    /// their flavor, creation time and parent and are marked with [sys::STUB], but have no
+    /// properties, children or history. Fails if a matching block has a child that doesn't
+    /// exist in the workspace.
+    pub fn encode_subset<T: ReadTxn>(
+        &self,
+        trx: &T,
+        predicate: impl Fn(&Block, &T) -> bool,
+    ) -> Result<Vec<u8>, ExportError> {
+        let included = self.blocks(trx, |blocks| {
+            blocks
+                .filter(|block| predicate(block, trx))
+                .collect::<Vec<_>>()
+        });
+        let selected = included
+            .iter()
+            .map(|block| block.id())
+            .collect::<BTreeSet<_>>();
+
+        let mut stubs = BTreeSet::new();
+        for child in included.iter().flat_map(|block| block.children(trx)) {
+            if selected.contains(&child) {
+                continue;
+            }
+            if !self.exists(trx, &child) {
+                return Err(ExportError::BlockNotFound(child));
+            }
+            stubs.insert(child);
+        }
+
+        trace!(
+            "encode subset of {} blocks and {} stubs",
+            selected.len(),
+            stubs.len()
+        );
+        Ok(self.encode_blocks(trx, &selected, &stubs))
+    }
+
+    // copy the blocks with their history and the stubs into a fresh doc, the parent of a
+    // copied block is dropped when it is not copied too
+    fn encode_blocks<T: ReadTxn>(
+        &self,
+        trx: &T,
+        selected: &BTreeSet<String>,
+        stubs: &BTreeSet<String>,
+    ) -> Vec<u8> {
+        let copied = |id: &String| selected.contains(id) || stubs.contains(id);
+
+        let selection = Doc::new();
+        let blocks = selection.get_or_insert_map("blocks");
+        let updated = selection.get_or_insert_map("updated");
+        let mut copy = selection.transact_mut();
+        for id in selected {
+            if let Some(Value::YMap(block)) = self.blocks.get(trx, id) {
+                let target = blocks.insert(&mut copy, id.as_str(), MapPrelim::<Any>::new());
+                for (key, value) in block.iter(trx) {
+                    let detached = key == sys::PARENT && !copied(&value.clone().to_string(trx));
+                    if !detached {
+                        copy_to_map(trx, &mut copy, &target, key, value);
+                    }
+                }
+            }
+            if let Some(history) = self.updated.get(trx, id) {
+                copy_to_map(trx, &mut copy, &updated, id, history);
+            }
+        }
+        for id in stubs {
+            if let Some(Value::YMap(block)) = self.blocks.get(trx, id) {
+                let target = blocks.insert(&mut copy, id.as_str(), MapPrelim::<Any>::new());
+                for key in [sys::FLAVOR, sys::VERSION, sys::CREATED, sys::PARENT] {
+                    if let Some(value) = block.get(trx, key) {
+                        copy_to_map(trx, &mut copy, &target, key, value);
+                    }
+                }
+                insert_system_key(
+                    &mut copy,
+                    &target,
+                    PropertyKey::new(sys::CHILDREN),
+                    ArrayPrelim::<Vec<String>, String>::from(vec![]),
+                );
+                insert_system_key(&mut copy, &target, PropertyKey::new(sys::STUB), true);
+                updated.insert(
+                    &mut copy,
+                    id.as_str(),
+                    ArrayPrelim::<Vec<Any>, Any>::from(vec![]),
+                );
+            }
+        }
+        copy.commit();
+        drop(copy);
+
+        selection
+            .transact()
+            .encode_state_as_update_v1(&StateVector::default())
+    }
+
+    // ids of the blocks and their descendants, children missing from the doc are skipped
+    fn descendants<T: ReadTxn>(&self, trx: &T, block_ids: &[&str]) -> BTreeSet<String> {
+        let mut selected = BTreeSet::new();
+        let mut pending = block_ids
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>();
+        while let Some(id) = pending.pop() {
+            let Some(block) = self.get(trx, &id) else {
+                continue;
+            };
+            if selected.insert(id) {
+                pending.extend(block.children(trx));
+            }
+        }
+        selected
+    }
+}
+
+fn copy_to_map<T: ReadTxn>(
+    trx: &T,
+    copy: &mut TransactionMut,
+    map: &MapRef,
+    key: &str,
+    value: Value,
+) {
+    match value {
+        Value::Any(any) => {
+            map.insert(copy, key, any);
+        }
+        text @ Value::YText(_) => {
+            map.insert(copy, key, TextPrelim::new(text.to_string(trx)));
+        }
+        Value::YArray(array) => {
+            let target = map.insert(copy, key, ArrayPrelim::<Vec<Any>, Any>::from(vec![]));
+            copy_array(trx, copy, &array, &target);
+        }
+        Value::YMap(inner) => {
+            let target = map.insert(copy, key, MapPrelim::<Any>::new());
+            for (key, value) in inner.iter(trx) {
+                copy_to_map(trx, copy, &target, key, value);
+            }
+        }
+        _ => warn!("skip {} of unsupported type", key),
+    }
+}
+
+fn copy_array<T: ReadTxn>(trx: &T, copy: &mut TransactionMut, array: &ArrayRef, target: &ArrayRef) {
+    for value in array.iter(trx) {
+        match value {
+            Value::Any(any) => {
+                target.push_back(copy, any);
+            }
+            text @ Value::YText(_) => {
+                target.push_back(copy, TextPrelim::new(text.to_string(trx)));
+            }
+            Value::YArray(array) => {
+                let nested = target.push_back(copy, ArrayPrelim::<Vec<Any>, Any>::from(vec![]));
+                copy_array(trx, copy, &array, &nested);
+            }
+            Value::YMap(inner) => {
+                let nested = target.push_back(copy, MapPrelim::<Any>::new());
+                for (key, value) in inner.iter(trx) {
+                    copy_to_map(trx, copy, &nested, key, value);
+                }
+            }
+            _ => warn!("skip array item of unsupported type"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_selection() {
+        let workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            let root = t.create("root", "affine:page");
+            let page = t.create("page", "affine:page");
+            let a = t.create("a", "affine:paragraph");
+            let b = t.create("b", "affine:paragraph");
+            b.set(&mut t.trx, "text", "nested");
+            t.create("c", "affine:paragraph");
+            root.push_children(&mut t.trx, &page);
+            page.push_children(&mut t.trx, &a);
+            a.push_children(&mut t.trx, &b);
+        });
+
+        let update = workspace.encode_selection(&["page"]).unwrap();
+
+        let shared = Workspace::new("shared");
+        shared.with_trx(|mut t| {
+            t.create("existing", "affine:page");
+        });
+        shared.apply_updates(&[update]).unwrap();
+        shared.with_trx(|t| {
+            for id in ["existing", "page", "a", "b"] {
+                assert!(shared.exists(&t.trx, id), "{id}");
+            }
+            for id in ["root", "c"] {
+                assert!(!shared.exists(&t.trx, id), "{id}");
+            }
+            let page = shared.get(&t.trx, "page").unwrap();
+            assert_eq!(page.parent(&t.trx), None);
+            assert_eq!(page.children(&t.trx), vec!["a".to_string()]);
+            let b = shared.get(&t.trx, "b").unwrap();
+            assert_eq!(b.parent(&t.trx), Some("a".to_string()));
+            assert_eq!(b.get(&t.trx, "text"), Some(Any::String("nested".into())));
+        });
+
+        assert!(matches!(
+            workspace.encode_selection(&["page", "x", "y"]),
+            Err(SelectionError::MissingBlocks(missing)) if missing == ["x", "y"]
+        ));
+    }
+
+    #[test]
+    fn encode_subset() {
+        let workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            let root = t.create("root", "affine:page");
+            let page = t.create("page", "affine:page");
+            page.set(&mut t.trx, "title", "subset");
+            let a = t.create("a", "affine:paragraph");
+            a.set(&mut t.trx, "text", "stub");
+            let b = t.create("b", "affine:paragraph");
+            root.push_children(&mut t.trx, &page);
+            page.push_children(&mut t.trx, &a);
+            a.push_children(&mut t.trx, &b);
+        });
+
+        let update = workspace
+            .with_trx(|t| {
+                t.ws.encode_subset(&t.trx, |b, t| b.flavor(t) == "affine:page")
+            })
+            .unwrap();
+
+        let shared = Workspace::new("shared");
+        shared.apply_updates(&[update]).unwrap();
+        shared.with_trx(|t| {
+            for id in ["root", "page", "a"] {
+                assert!(shared.exists(&t.trx, id), "{id}");
+            }
+            assert!(!shared.exists(&t.trx, "b"));
+
+            let page = shared.get(&t.trx, "page").unwrap();
+            assert_eq!(page.parent(&t.trx), Some("root".to_string()));
+            assert_eq!(page.children(&t.trx), vec!["a".to_string()]);
+            assert_eq!(
+                page.get(&t.trx, "title"),
+                Some(Any::String("subset".into()))
+            );
+
+            let a = shared.get(&t.trx, "a").unwrap();
+            assert_eq!(a.flavor(&t.trx), "affine:paragraph");
+            assert_eq!(a.parent(&t.trx), Some("page".to_string()));
+            assert!(a.children(&t.trx).is_empty());
+            assert_eq!(a.get(&t.trx, "text"), None);
+            let Some(Value::YMap(stub)) = shared.blocks.get(&t.trx, "a") else {
+                panic!("stub not found");
+            };
+            assert_eq!(
+                stub.get(&t.trx, sys::STUB).map(|v| v.to_string(&t.trx)),
+                Some("true".to_string())
+            );
+        });
+
+        // a dangling child can't be stubbed
+        workspace.with_trx(|mut t| {
+            t.ws.blocks.remove(&mut t.trx, "a");
+        });
+        assert!(matches!(
+            workspace.with_trx(|t| t.ws.encode_subset(&t.trx, |b, t| b.flavor(t) == "affine:page")),
+            Err(ExportError::BlockNotFound(id)) if id == "a"
+        ));
+    }
+}
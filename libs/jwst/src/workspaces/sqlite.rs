@@ -0,0 +1,303 @@
+use super::*;
+use rusqlite::{params, Connection, OpenFlags};
+use std::collections::HashMap;
+use thiserror::Error;
+use yrs::{updates::decoder::Decode, Doc, ReadTxn, StateVector, Transact, Update};
+
+/// Version of the file layout written by [`Workspace::export_sqlite`],
+/// bump it whenever the tables change in an incompatible way.
+pub const SQLITE_SCHEMA_VERSION: i64 = 1;
+
+#[derive(Debug, Error)]
+pub enum ImportError {
+    #[error("schema version {found:?} is not supported, expected {expected}")]
+    SchemaMismatch { expected: i64, found: Option<i64> },
+    #[error("workspace {0} not found")]
+    WorkspaceNotFound(String),
+    #[error("failed to decode update: {0}")]
+    Update(#[from] lib0::error::Error),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+// same tables as the migrations of jwst-storage, so the file can be opened by `JwstStorage`
+const SCHEMA: &str = r#"
+CREATE TABLE "blobs" (
+    "workspace" text NOT NULL,
+    "hash" text NOT NULL,
+    "blob" blob NOT NULL,
+    "length" integer NOT NULL,
+    "timestamp" text NOT NULL,
+    PRIMARY KEY ("workspace", "hash")
+);
+CREATE INDEX "blobs_list" ON "blobs" ("workspace");
+CREATE TABLE "docs" (
+    "id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "workspace" text NOT NULL,
+    "timestamp" text NOT NULL,
+    "blob" blob NOT NULL,
+    "chunks" text NULL
+);
+CREATE INDEX "workspaces_update" ON "docs" ("workspace");
+CREATE TABLE "doc_chunks" (
+    "hash" text NOT NULL PRIMARY KEY,
+    "blob" blob NOT NULL,
+    "length" integer NOT NULL,
+    "refs" integer NOT NULL
+);
+CREATE TABLE "seaql_migrations" (
+    "version" text NOT NULL PRIMARY KEY,
+    "applied_at" integer NOT NULL
+);
+CREATE TABLE "schema_version" (
+    "version" integer NOT NULL
+);
+"#;
+
+// names of the migrations the schema above is equivalent to
+const MIGRATIONS: &[&str] = &[
+    "m20220101_000001_initial_blob_table",
+    "m20220101_000001_initial_doc_table",
+    "m20230301_000001_doc_chunk_table",
+];
+
+impl Workspace {
+    /// Write the workspace and its blobs (keyed by hash) into a new SQLite database at `path`.
+    pub fn export_sqlite(
+        &self,
+        path: &str,
+        blobs: &HashMap<String, Vec<u8>>,
+    ) -> Result<(), ExportError> {
+        let update = self
+            .doc()
+            .transact()
+            .encode_state_as_update_v1(&StateVector::default());
+        let now = chrono::Utc::now();
+        let timestamp = now.to_rfc3339();
+
+        let mut conn = Connection::open(path)?;
+        let trx = conn.transaction()?;
+
+        trx.execute_batch(SCHEMA)?;
+        trx.execute(
+            r#"INSERT INTO "schema_version" ("version") VALUES (?1)"#,
+            params![SQLITE_SCHEMA_VERSION],
+        )?;
+        for migration in MIGRATIONS {
+            trx.execute(
+                r#"INSERT INTO "seaql_migrations" ("version", "applied_at") VALUES (?1, ?2)"#,
+                params![migration, now.timestamp()],
+            )?;
+        }
+
+        trx.execute(
+            r#"INSERT INTO "docs" ("workspace", "timestamp", "blob") VALUES (?1, ?2, ?3)"#,
+            params![self.id(), timestamp, update],
+        )?;
+        for (hash, blob) in blobs {
+            trx.execute(
+                r#"INSERT INTO "blobs" ("workspace", "hash", "blob", "length", "timestamp") VALUES (?1, ?2, ?3, ?4, ?5)"#,
+                params![self.id(), hash, blob, blob.len() as i64, timestamp],
+            )?;
+        }
+
+        trx.commit()?;
+
+        Ok(())
+    }
+
+    /// Read a workspace exported by [`Workspace::export_sqlite`], returns the workspace
+    /// with its updates replayed in insertion order and its blobs keyed by hash.
+    pub fn from_sqlite(
+        path: &str,
+        id: &str,
+    ) -> Result<(Workspace, HashMap<String, Vec<u8>>), ImportError> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+        // files written before the version table existed are rejected as well
+        let found = conn
+            .query_row(r#"SELECT "version" FROM "schema_version""#, [], |row| {
+                row.get(0)
+            })
+            .ok();
+        if found != Some(SQLITE_SCHEMA_VERSION) {
+            return Err(ImportError::SchemaMismatch {
+                expected: SQLITE_SCHEMA_VERSION,
+                found,
+            });
+        }
+
+        let mut stmt = conn.prepare(
+            r#"SELECT "blob", "chunks" FROM "docs" WHERE "workspace" = ?1 ORDER BY "id""#,
+        )?;
+        let rows = stmt
+            .query_map(params![id], |row| {
+                Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Option<String>>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        if rows.is_empty() {
+            return Err(ImportError::WorkspaceNotFound(id.to_owned()));
+        }
+
+        let doc = Doc::new();
+        {
+            let mut trx = doc.transact_mut();
+            for (blob, chunks) in rows {
+                let update = match chunks {
+                    Some(chunks) => unpack_chunks(&conn, &chunks)?,
+                    None => blob,
+                };
+                trx.apply_update(Update::decode_v1(&update)?);
+            }
+            trx.commit();
+        }
+
+        let mut stmt =
+            conn.prepare(r#"SELECT "hash", "blob" FROM "blobs" WHERE "workspace" = ?1"#)?;
+        let blobs = stmt
+            .query_map(params![id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<HashMap<_, _>, _>>()?;
+
+        Ok((Workspace::from_doc(doc, id), blobs))
+    }
+}
+
+// docs rows written by jwst-storage may keep large updates in the shared chunk table
+fn unpack_chunks(conn: &Connection, chunks: &str) -> Result<Vec<u8>, rusqlite::Error> {
+    let mut blob = vec![];
+    for hash in chunks.split(',').filter(|hash| !hash.is_empty()) {
+        let chunk: Vec<u8> = conn.query_row(
+            r#"SELECT "blob" FROM "doc_chunks" WHERE "hash" = ?1"#,
+            params![hash],
+            |row| row.get(0),
+        )?;
+        blob.extend(chunk);
+    }
+    Ok(blob)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn export_sqlite() {
+        let path = std::env::temp_dir().join(format!(
+            "jwst-export-{}-{}.db",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos()
+        ));
+        let path = path.to_str().unwrap();
+
+        let workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            let block = t.create("block", "text");
+            block.set(&mut t.trx, "text", "hello");
+        });
+        let blobs = HashMap::from([("hash".to_owned(), vec![1, 2, 3])]);
+
+        workspace.export_sqlite(path, &blobs).unwrap();
+        // the file can't be exported into twice
+        assert!(workspace.export_sqlite(path, &blobs).is_err());
+
+        let conn = Connection::open(path).unwrap();
+        let (id, update): (String, Vec<u8>) = conn
+            .query_row(r#"SELECT "workspace", "blob" FROM "docs""#, [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(id, "test");
+
+        let doc = Doc::new();
+        doc.transact_mut()
+            .apply_update(Update::decode_v1(&update).unwrap());
+        let imported = Workspace::from_doc(doc, "test");
+        assert_eq!(
+            imported.with_trx(|t| imported.get(&t.trx, "block").unwrap().get(&t.trx, "text")),
+            Some("hello".into())
+        );
+
+        let (hash, blob, length): (String, Vec<u8>, i64) = conn
+            .query_row(
+                r#"SELECT "hash", "blob", "length" FROM "blobs" WHERE "workspace" = 'test'"#,
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!((hash.as_str(), blob, length), ("hash", vec![1, 2, 3], 3));
+
+        let migrations: i64 = conn
+            .query_row(r#"SELECT COUNT(*) FROM "seaql_migrations""#, [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(migrations, MIGRATIONS.len() as i64);
+
+        let version: i64 = conn
+            .query_row(r#"SELECT "version" FROM "schema_version""#, [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(version, SQLITE_SCHEMA_VERSION);
+
+        drop(conn);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn from_sqlite() {
+        let path = std::env::temp_dir().join(format!(
+            "jwst-import-{}-{}.db",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos()
+        ));
+        let path = path.to_str().unwrap();
+
+        let workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            let block = t.create("block", "text");
+            block.set(&mut t.trx, "text", "hello");
+        });
+        let blobs = HashMap::from([("hash".to_owned(), vec![1, 2, 3])]);
+        workspace.export_sqlite(path, &blobs).unwrap();
+
+        // later updates are replayed on top of the exported state
+        let update = workspace.with_trx(|mut t| {
+            let block = workspace.get(&t.trx, "block").unwrap();
+            block.set(&mut t.trx, "text", "world");
+            t.trx.encode_update_v1()
+        });
+        let conn = Connection::open(path).unwrap();
+        conn.execute(
+            r#"INSERT INTO "docs" ("workspace", "timestamp", "blob") VALUES ('test', '', ?1)"#,
+            params![update],
+        )
+        .unwrap();
+
+        let (imported, imported_blobs) = Workspace::from_sqlite(path, "test").unwrap();
+        assert_eq!(imported.id(), "test");
+        assert_eq!(
+            imported.with_trx(|t| imported.get(&t.trx, "block").unwrap().get(&t.trx, "text")),
+            Some("world".into())
+        );
+        assert_eq!(imported_blobs, blobs);
+
+        assert!(matches!(
+            Workspace::from_sqlite(path, "other"),
+            Err(ImportError::WorkspaceNotFound(id)) if id == "other"
+        ));
+
+        conn.execute(r#"UPDATE "schema_version" SET "version" = 0"#, [])
+            .unwrap();
+        assert!(matches!(
+            Workspace::from_sqlite(path, "test"),
+            Err(ImportError::SchemaMismatch {
+                expected: SQLITE_SCHEMA_VERSION,
+                found: Some(0)
+            })
+        ));
+
+        drop(conn);
+        std::fs::remove_file(path).unwrap();
+    }
+}
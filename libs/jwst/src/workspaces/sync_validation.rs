@@ -0,0 +1,828 @@
+use super::*;
+use crate::{constants::sys, PropertyKey};
+use lib0::{
+    any::Any,
+    decoding::{Cursor, Read},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Range,
+    sync::atomic::Ordering,
+};
+use thiserror::Error;
+use y_sync::sync::{Error, Message, MessageReader, SyncMessage};
+use yrs::{
+    block::{Item, ID},
+    types::{Branch, TypePtr, Value},
+    updates::{decoder::DecoderV1, encoder::Encode},
+    Map, MapRef, Origin, ReadTxn, StateVector, Transact,
+};
+
+/// Default of [Workspace::max_message_bytes].
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 32 * 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum SyncValidationError {
+    #[error("sync message of {size} bytes exceeds the limit of {max} bytes")]
+    TooLarge { size: usize, max: usize },
+    #[error("sync message is empty")]
+    Empty,
+    #[error("invalid sync message: {0}")]
+    Invalid(#[from] Error),
+    #[error("client {0} is blocked")]
+    BlockedClient(u64),
+    #[error("update deletes content before its client is known")]
+    UnknownClient,
+    #[error("update depends on content missing from the workspace")]
+    MissingDependency,
+    #[error("update modifies reserved metadata {0}")]
+    ReservedKey(String),
+    #[error(transparent)]
+    Permission(#[from] PermissionError),
+}
+
+/// The user a connection syncs a workspace for, and the clients it uses, learned from
+/// the updates and the awareness it sends. Deletes don't carry the client that made them,
+/// so [Workspace::validate_sync_messages] checks them against these clients.
+#[derive(Debug, Clone)]
+pub struct SyncPeer {
+    user: WorkspaceUser,
+    clients: BTreeSet<u64>,
+}
+
+impl SyncPeer {
+    pub fn new(user: WorkspaceUser) -> Self {
+        Self {
+            user,
+            clients: BTreeSet::new(),
+        }
+    }
+
+    /// The user authenticated for the connection, its updates are checked against the
+    /// [permission](Workspace::get_permission) of the user.
+    pub fn user(&self) -> &WorkspaceUser {
+        &self.user
+    }
+
+    /// Clients of the connection seen so far.
+    pub fn clients(&self) -> impl Iterator<Item = u64> + '_ {
+        self.clients.iter().copied()
+    }
+}
+
+impl Workspace {
+    /// Most bytes a sync frame from a client may have, see [Workspace::validate_sync_message].
+    pub fn max_message_bytes(&self) -> usize {
+        self.max_message_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Change [Workspace::max_message_bytes] of this workspace and its clones.
+    pub fn set_max_message_bytes(&self, bytes: usize) {
+        self.max_message_bytes.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Clients whose updates are rejected by [Workspace::validate_sync_message],
+    /// stored in the workspace metadata so every server sharing the doc applies them.
+    pub fn blocked_clients<T: ReadTxn>(&self, trx: &T) -> Vec<u64> {
+        match self.metadata.get(trx, sys::BLOCKED) {
+            Some(Value::Any(Any::Array(clients))) => clients
+                .iter()
+                .filter_map(|client| match client {
+                    Any::Number(client) => Some(*client as u64),
+                    Any::BigInt(client) => Some(*client as u64),
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Decode a message received from an untrusted client and check it before it is
+    /// handled: the frame must fit in [Workspace::max_message_bytes], and an update must
+    /// neither come from a [blocked client](Workspace::blocked_clients) nor change the
+    /// `sys:*` keys of the workspace metadata. The user of `peer` needs
+    /// [write permission](Workspace::get_permission), and admin permission to change
+    /// permissions, whatever client ids the update uses. Only the first message of the frame is returned, see
+    /// [Workspace::validate_sync_messages] for the others.
+    ///
+    /// The structs and the delete set of an update are checked where they are, without
+    /// applying it, so checking one costs about as much as decoding it. Deletes are made
+    /// by the clients of `peer`, a connection that deletes content before it sent an
+    /// update or an awareness state of its own is rejected.
+    pub fn validate_sync_message(
+        &self,
+        binary: &[u8],
+        peer: &mut SyncPeer,
+    ) -> Result<Message, SyncValidationError> {
+        self.validate_sync_messages(binary, peer)?
+            .into_iter()
+            .next()
+            .ok_or(SyncValidationError::Empty)
+    }
+
+    /// [Workspace::validate_sync_message] for every message of a frame, the frame is
+    /// rejected as a whole if any of them is invalid. The clients of an accepted frame
+    /// are added to `peer`.
+    pub fn validate_sync_messages(
+        &self,
+        binary: &[u8],
+        peer: &mut SyncPeer,
+    ) -> Result<Vec<Message>, SyncValidationError> {
+        self.validate_frame(binary, peer)
+            .map(|(messages, _)| messages)
+    }
+
+    /// Validate a frame from an untrusted client and handle its messages like
+    /// [Workspace::sync_decode_message], nothing is applied if the frame is rejected.
+    /// Timestamps the client wrote into the blocks it changed that are off by more than
+    /// [MAX_CLOCK_SKEW] from the [clock](Workspace::clock) of the workspace are replaced
+    /// by the current time once the frame is applied.
+    ///
+    /// Updates are applied in transactions whose origin is the id of the user of `peer`,
+    /// so observers can tell who changed the workspace.
+    pub fn sync_decode_untrusted_message(
+        &mut self,
+        binary: &[u8],
+        peer: &mut SyncPeer,
+    ) -> Result<Vec<Vec<u8>>, SyncValidationError> {
+        let (messages, changed) = self.validate_frame(binary, peer)?;
+        let created = self.with_trx(|t| self.created_timestamps(&t.trx, &changed));
+        let replies = messages
+            .into_iter()
+            .filter_map(|msg| self.sync_handle_user_message(msg, &peer.user).ok()?)
+            .map(|reply| reply.encode_v1())
+            .collect();
+        if !created.is_empty() {
+            self.with_trx(|mut t| self.normalize_timestamps(&mut t.trx, &created));
+        }
+        Ok(replies)
+    }
+
+    // [Workspace::sync_handle_message] with the updates applied in transactions of `user`
+    fn sync_handle_user_message(
+        &mut self,
+        msg: Message,
+        user: &WorkspaceUser,
+    ) -> Result<Option<Message>, Error> {
+        let origin = || Some(Origin::from(user.id.as_str()));
+        match msg {
+            Message::Sync(SyncMessage::SyncStep2(update)) => {
+                self.apply_updates_with_origin(&[update], origin())?;
+                Ok(None)
+            }
+            Message::Sync(SyncMessage::Update(update)) => {
+                let (update, _) = self.apply_updates_with_origin(&[update], origin())?;
+                Ok(Some(Message::Sync(SyncMessage::Update(update))))
+            }
+            msg => self.sync_handle_message(msg),
+        }
+    }
+
+    // the messages of a frame and the ids of the blocks its updates change
+    fn validate_frame(
+        &self,
+        binary: &[u8],
+        peer: &mut SyncPeer,
+    ) -> Result<(Vec<Message>, BTreeSet<String>), SyncValidationError> {
+        let max = self.max_message_bytes();
+        if binary.len() > max {
+            return Err(SyncValidationError::TooLarge {
+                size: binary.len(),
+                max,
+            });
+        }
+
+        let mut decoder = DecoderV1::from(binary);
+        let messages = MessageReader::new(&mut decoder).collect::<Result<Vec<_>, _>>()?;
+        let mut clients = peer.clients.clone();
+        let mut changed = BTreeSet::new();
+        for message in &messages {
+            match message {
+                Message::Sync(SyncMessage::SyncStep2(update) | SyncMessage::Update(update)) => {
+                    self.validate_update(update, &peer.user, &mut clients, &mut changed)?
+                }
+                Message::Awareness(update) => clients.extend(update.clients.keys()),
+                _ => {}
+            }
+        }
+        peer.clients = clients;
+        Ok((messages, changed))
+    }
+
+    // `clients` are the clients of the connection of `user`, the clients of the update
+    // are added, and the blocks the new structs of the update are in to `changed`
+    fn validate_update(
+        &self,
+        update: &[u8],
+        user: &WorkspaceUser,
+        clients: &mut BTreeSet<u64>,
+        changed: &mut BTreeSet<String>,
+    ) -> Result<(), SyncValidationError> {
+        let update = DecodedUpdate::decode(update).map_err(Error::from)?;
+
+        let doc = self.doc();
+        let trx = doc.transact();
+        let state = trx.state_vector();
+        let targets = Targets {
+            workspace: self,
+            trx: &trx,
+            update: &update,
+        };
+
+        let mut required = WorkspacePermission::Write;
+        let mut changed_by = BTreeSet::new();
+        let mut blocks = BTreeSet::new();
+        for (index, update_struct) in update.structs.iter().enumerate() {
+            let id = update_struct.id;
+            // already integrated
+            if id.clock.saturating_add(update_struct.len) <= state.get(&id.client) {
+                continue;
+            }
+            changed_by.insert(id.client);
+            if update_struct.item.is_some() {
+                match targets.of_struct(index, 0)? {
+                    Target::Reserved(key) => return Err(SyncValidationError::ReservedKey(key)),
+                    Target::Permissions => required = WorkspacePermission::Admin,
+                    Target::Block(id) => {
+                        blocks.insert(id);
+                    }
+                    Target::Other => {}
+                }
+            }
+        }
+
+        let mut deletes = false;
+        for (client, range) in &update.deletes {
+            for target in targets.of_deleted(*client, range, &state)? {
+                deletes = true;
+                match target {
+                    Target::Reserved(key) => return Err(SyncValidationError::ReservedKey(key)),
+                    Target::Permissions => required = WorkspacePermission::Admin,
+                    Target::Block(_) | Target::Other => {}
+                }
+            }
+        }
+        if deletes {
+            if clients.is_empty() && changed_by.is_empty() {
+                return Err(SyncValidationError::UnknownClient);
+            }
+            changed_by.extend(clients.iter());
+        }
+
+        let blocked = self.blocked_clients(&trx);
+        if let Some(client) = changed_by.iter().find(|client| blocked.contains(client)) {
+            return Err(SyncValidationError::BlockedClient(*client));
+        }
+        // permissions are checked against the state before the update,
+        // so a user can't grant itself more
+        if !changed_by.is_empty() {
+            self.check_permission(&trx, Some(user), required)?;
+        }
+
+        clients.extend(changed_by);
+        changed.extend(blocks);
+        Ok(())
+    }
+}
+
+impl WorkspaceTransaction<'_> {
+    /// Replace the [blocked clients](Workspace::blocked_clients) of the workspace.
+    pub fn set_blocked_clients(&mut self, clients: &[u64]) {
+        if clients.is_empty() {
+            remove_system_key(
+                &mut self.trx,
+                &self.ws.metadata,
+                PropertyKey::new(sys::BLOCKED),
+            );
+        } else {
+            let clients = clients
+                .iter()
+                .map(|client| Any::Number(*client as f64))
+                .collect::<Vec<_>>();
+            insert_system_key(
+                &mut self.trx,
+                &self.ws.metadata,
+                PropertyKey::new(sys::BLOCKED),
+                Any::Array(clients.into_boxed_slice()),
+            );
+        }
+    }
+}
+
+const BLOCK_GC: u8 = 0;
+const BLOCK_SKIP: u8 = 10;
+const CONTENT_DELETED: u8 = 1;
+const CONTENT_JSON: u8 = 2;
+const CONTENT_BINARY: u8 = 3;
+const CONTENT_STRING: u8 = 4;
+const CONTENT_EMBED: u8 = 5;
+const CONTENT_FORMAT: u8 = 6;
+const CONTENT_TYPE: u8 = 7;
+const CONTENT_ANY: u8 = 8;
+const CONTENT_DOC: u8 = 9;
+const TYPE_XML_ELEMENT: u32 = 3;
+const TYPE_XML_HOOK: u32 = 5;
+const HAS_ORIGIN: u8 = 0b1000_0000;
+const HAS_RIGHT_ORIGIN: u8 = 0b0100_0000;
+const HAS_PARENT_SUB: u8 = 0b0010_0000;
+
+// where an item of an update is inserted, only encoded if it has no origins
+enum UpdateParent {
+    Root(String),
+    Item(ID),
+}
+
+struct UpdateItem {
+    origin: Option<ID>,
+    right_origin: Option<ID>,
+    parent: Option<UpdateParent>,
+    parent_sub: Option<String>,
+}
+
+struct UpdateStruct {
+    id: ID,
+    len: u32,
+    // `None` for garbage collected ranges and skips
+    item: Option<UpdateItem>,
+}
+
+// the structs and the delete set of a v1 update, without their content
+struct DecodedUpdate {
+    structs: Vec<UpdateStruct>,
+    // struct index by client and clock
+    index: BTreeMap<(u64, u32), usize>,
+    deletes: Vec<(u64, Range<u32>)>,
+}
+
+impl DecodedUpdate {
+    fn decode(update: &[u8]) -> Result<Self, lib0::error::Error> {
+        let mut cursor = Cursor::new(update);
+        let mut structs = vec![];
+        let clients: u32 = cursor.read_var()?;
+        for _ in 0..clients {
+            let count: u32 = cursor.read_var()?;
+            let client: u64 = cursor.read_var()?;
+            let mut clock: u32 = cursor.read_var()?;
+            for _ in 0..count {
+                let info = cursor.read_u8()?;
+                let (len, item) = match info & 0b1_1111 {
+                    BLOCK_GC | BLOCK_SKIP => (cursor.read_var()?, None),
+                    content => {
+                        let origin = match info & HAS_ORIGIN {
+                            0 => None,
+                            _ => Some(read_id(&mut cursor)?),
+                        };
+                        let right_origin = match info & HAS_RIGHT_ORIGIN {
+                            0 => None,
+                            _ => Some(read_id(&mut cursor)?),
+                        };
+                        let (parent, parent_sub) = if origin.is_none() && right_origin.is_none() {
+                            let parent = match cursor.read_var::<u32>()? {
+                                1 => UpdateParent::Root(cursor.read_string()?.to_owned()),
+                                _ => UpdateParent::Item(read_id(&mut cursor)?),
+                            };
+                            let parent_sub = match info & HAS_PARENT_SUB {
+                                0 => None,
+                                _ => Some(cursor.read_string()?.to_owned()),
+                            };
+                            (Some(parent), parent_sub)
+                        } else {
+                            (None, None)
+                        };
+                        let item = UpdateItem {
+                            origin,
+                            right_origin,
+                            parent,
+                            parent_sub,
+                        };
+                        (skip_content(&mut cursor, content)?, Some(item))
+                    }
+                };
+                structs.push(UpdateStruct {
+                    id: ID::new(client, clock),
+                    len,
+                    item,
+                });
+                clock = clock.saturating_add(len);
+            }
+        }
+
+        let mut deletes = vec![];
+        let clients: u32 = cursor.read_var()?;
+        for _ in 0..clients {
+            let client: u64 = cursor.read_var()?;
+            let ranges: u32 = cursor.read_var()?;
+            for _ in 0..ranges {
+                let clock: u32 = cursor.read_var()?;
+                let len: u32 = cursor.read_var()?;
+                deletes.push((client, clock..clock.saturating_add(len)));
+            }
+        }
+
+        let index = structs
+            .iter()
+            .enumerate()
+            .map(|(index, update_struct)| {
+                ((update_struct.id.client, update_struct.id.clock), index)
+            })
+            .collect();
+        Ok(Self {
+            structs,
+            index,
+            deletes,
+        })
+    }
+
+    // the struct of the update containing `id`
+    fn find(&self, id: &ID) -> Option<usize> {
+        let (_, index) = self
+            .index
+            .range((id.client, 0)..=(id.client, id.clock))
+            .next_back()?;
+        let update_struct = &self.structs[*index];
+        (id.clock < update_struct.id.clock.saturating_add(update_struct.len)).then_some(*index)
+    }
+}
+
+fn read_id(cursor: &mut Cursor) -> Result<ID, lib0::error::Error> {
+    Ok(ID::new(cursor.read_var()?, cursor.read_var()?))
+}
+
+// skip the content of an item, returns its length in clock ticks
+fn skip_content(cursor: &mut Cursor, content: u8) -> Result<u32, lib0::error::Error> {
+    Ok(match content {
+        CONTENT_DELETED => cursor.read_var()?,
+        CONTENT_JSON => {
+            let len: u32 = cursor.read_var()?;
+            for _ in 0..len {
+                cursor.read_string()?;
+            }
+            len
+        }
+        CONTENT_BINARY => {
+            cursor.read_buf()?;
+            1
+        }
+        CONTENT_STRING => cursor.read_string()?.encode_utf16().count() as u32,
+        CONTENT_EMBED => {
+            cursor.read_string()?;
+            1
+        }
+        CONTENT_FORMAT => {
+            cursor.read_string()?;
+            cursor.read_string()?;
+            1
+        }
+        CONTENT_TYPE => {
+            let type_ref: u32 = cursor.read_var()?;
+            if matches!(type_ref, TYPE_XML_ELEMENT | TYPE_XML_HOOK) {
+                cursor.read_string()?;
+            }
+            1
+        }
+        CONTENT_ANY => {
+            let len: u32 = cursor.read_var()?;
+            for _ in 0..len {
+                Any::decode(cursor)?;
+            }
+            len
+        }
+        CONTENT_DOC => {
+            cursor.read_string()?;
+            Any::decode(cursor)?;
+            1
+        }
+        // moves are not used by workspaces
+        _ => return Err(lib0::error::Error::UnexpectedValue),
+    })
+}
+
+// what an item of an update changes
+enum Target {
+    // a `sys:*` key of the workspace metadata
+    Reserved(String),
+    Permissions,
+    // a block or its history
+    Block(String),
+    Other,
+}
+
+// the root map of the workspace an item is in
+enum Root {
+    Metadata,
+    Permissions,
+    Blocks,
+    Other,
+}
+
+// nesting followed to find the target of an item, deeper items are rejected
+const MAX_TARGET_DEPTH: usize = 256;
+
+// finds the targets of the items of an update and of the workspace
+struct Targets<'a, T: ReadTxn> {
+    workspace: &'a Workspace,
+    trx: &'a T,
+    update: &'a DecodedUpdate,
+}
+
+impl<T: ReadTxn> Targets<'_, T> {
+    fn of_id(&self, id: &ID, depth: usize) -> Result<Target, SyncValidationError> {
+        if depth > MAX_TARGET_DEPTH {
+            return Err(SyncValidationError::MissingDependency);
+        }
+        if let Some(index) = self.update.find(id) {
+            return self.of_struct(index, depth + 1);
+        }
+        match self.trx.store().blocks.get_block(id) {
+            Some(block) => match block.as_item() {
+                Some(item) => self.of_item(item, depth + 1),
+                None => Ok(Target::Other),
+            },
+            None => Err(SyncValidationError::MissingDependency),
+        }
+    }
+
+    // an item of the update has the parent and the key of its origins, and
+    // the target of its parent if that is nested
+    fn of_struct(&self, index: usize, depth: usize) -> Result<Target, SyncValidationError> {
+        let Some(item) = &self.update.structs[index].item else {
+            return Ok(Target::Other);
+        };
+        match (&item.parent, &item.origin, &item.right_origin) {
+            (Some(UpdateParent::Root(name)), _, _) => {
+                let root = match name.as_str() {
+                    "space:meta" => Root::Metadata,
+                    "space:permissions" => Root::Permissions,
+                    "blocks" | "updated" => Root::Blocks,
+                    _ => Root::Other,
+                };
+                Ok(self.of_entry(root, item.parent_sub.as_deref()))
+            }
+            (Some(UpdateParent::Item(parent)), _, _) => self.of_id(parent, depth),
+            (None, Some(origin), _) | (None, None, Some(origin)) => self.of_id(origin, depth),
+            (None, None, None) => Err(SyncValidationError::MissingDependency),
+        }
+    }
+
+    fn of_item(&self, item: &Item, depth: usize) -> Result<Target, SyncValidationError> {
+        let TypePtr::Branch(parent) = &item.parent else {
+            return Ok(Target::Other);
+        };
+        let branch: &Branch = parent;
+        let is = |root: &MapRef| std::ptr::eq(branch, AsRef::<Branch>::as_ref(root));
+        match parent.item_id() {
+            Some(parent) => self.of_id(&parent, depth),
+            None => {
+                let workspace = self.workspace;
+                let root = if is(&workspace.metadata) {
+                    Root::Metadata
+                } else if is(&workspace.permissions) {
+                    Root::Permissions
+                } else if is(&workspace.blocks) || is(&workspace.updated) {
+                    Root::Blocks
+                } else {
+                    Root::Other
+                };
+                Ok(self.of_entry(root, item.parent_sub.as_deref()))
+            }
+        }
+    }
+
+    fn of_entry(&self, root: Root, key: Option<&str>) -> Target {
+        match (root, key) {
+            (Root::Metadata, Some(key)) if PropertyKey::new(key).is_system() => {
+                Target::Reserved(key.into())
+            }
+            (Root::Permissions, _) => Target::Permissions,
+            (Root::Blocks, Some(id)) => Target::Block(id.into()),
+            _ => Target::Other,
+        }
+    }
+
+    // targets of the items of the workspace in a deleted range that are not deleted yet
+    fn of_deleted(
+        &self,
+        client: u64,
+        range: &Range<u32>,
+        state: &StateVector,
+    ) -> Result<Vec<Target>, SyncValidationError> {
+        let end = range.end.min(state.get(&client));
+        let mut targets = vec![];
+        let mut clock = range.start;
+        while clock < end {
+            let Some(block) = self.trx.store().blocks.get_block(&ID::new(client, clock)) else {
+                break;
+            };
+            clock = (block.id().clock + block.len()).max(clock + 1);
+            match block.as_item() {
+                Some(item) if !item.is_deleted() => targets.push(self.of_item(item, 0)?),
+                _ => {}
+            }
+        }
+        Ok(targets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+    use y_sync::awareness::{AwarenessUpdate, AwarenessUpdateEntry};
+    use yrs::{updates::decoder::Decode, Doc, MapPrelim, TransactionMut, Update};
+
+    fn update_message(doc: &Doc, edit: impl FnOnce(&mut TransactionMut)) -> Vec<u8> {
+        let before = doc.transact().state_vector();
+        let mut trx = doc.transact_mut();
+        edit(&mut trx);
+        trx.commit();
+        Message::Sync(SyncMessage::Update(trx.encode_state_as_update_v1(&before))).encode_v1()
+    }
+
+    fn writer() -> SyncPeer {
+        SyncPeer::new(WorkspaceUser::new("writer", WorkspacePermission::Write))
+    }
+
+    #[test]
+    fn validate_sync_message() {
+        let mut workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            t.create("a", "affine:page");
+            t.set_blocked_clients(&[42]);
+        });
+
+        let client = Doc::with_client_id(1);
+        client
+            .transact_mut()
+            .apply_update(Update::decode_v1(&workspace.sync_migration()).unwrap());
+        let blocks = client.get_or_insert_map("blocks");
+        let metadata = client.get_or_insert_map("space:meta");
+
+        // regular edits, sys keys of blocks included, are accepted
+        let edit = update_message(&client, |trx| {
+            blocks.insert(trx, "b", MapPrelim::<Any>::from(HashMap::new()));
+            metadata.insert(trx, "name", "renamed");
+        });
+        let mut peer = writer();
+        assert!(matches!(
+            workspace.validate_sync_message(&edit, &mut writer()),
+            Ok(Message::Sync(SyncMessage::Update(_)))
+        ));
+        let origins = Arc::new(Mutex::new(vec![]));
+        let sub = workspace.observe_changes({
+            let origins = origins.clone();
+            move |trx, _| origins.lock().unwrap().push(trx.origin().cloned())
+        });
+        assert!(workspace
+            .sync_decode_untrusted_message(&edit, &mut peer)
+            .is_ok());
+        drop(sub);
+        assert_eq!(peer.clients().collect::<Vec<_>>(), vec![1]);
+        // applied on behalf of the user of the connection
+        assert_eq!(
+            origins.lock().unwrap().first(),
+            Some(&Some(Origin::from("writer")))
+        );
+        workspace.with_trx(|t| assert!(workspace.exists(&t.trx, "b")));
+
+        // clients can't unblock themselves, neither by overwriting nor by removing
+        let other = Doc::with_client_id(3);
+        other
+            .transact_mut()
+            .apply_update(Update::decode_v1(&workspace.sync_migration()).unwrap());
+        let other_metadata = other.get_or_insert_map("space:meta");
+        let unblock = update_message(&other, |trx| {
+            other_metadata.insert(trx, sys::BLOCKED, Any::Array(Box::new([])));
+        });
+        assert!(matches!(
+            workspace.validate_sync_message(&unblock, &mut peer),
+            Err(SyncValidationError::ReservedKey(key)) if key == sys::BLOCKED
+        ));
+        let unblock = update_message(&other, |trx| {
+            other_metadata.remove(trx, sys::BLOCKED);
+        });
+        assert!(matches!(
+            workspace.validate_sync_message(&unblock, &mut peer),
+            Err(SyncValidationError::ReservedKey(key)) if key == sys::BLOCKED
+        ));
+        assert!(workspace
+            .sync_decode_untrusted_message(&unblock, &mut peer)
+            .is_err());
+        workspace.with_trx(|t| assert_eq!(workspace.blocked_clients(&t.trx), vec![42]));
+
+        let blocked = Doc::with_client_id(42);
+        let blocked_blocks = blocked.get_or_insert_map("blocks");
+        let edit = update_message(&blocked, |trx| {
+            blocked_blocks.insert(trx, "c", MapPrelim::<Any>::from(HashMap::new()));
+        });
+        assert!(matches!(
+            workspace.validate_sync_message(&edit, &mut writer()),
+            Err(SyncValidationError::BlockedClient(42))
+        ));
+
+        // deletes are made by the clients of the connection
+        let delete = update_message(&client, |trx| {
+            blocks.remove(trx, "b");
+        });
+        assert!(matches!(
+            workspace.validate_sync_message(&delete, &mut writer()),
+            Err(SyncValidationError::UnknownClient)
+        ));
+        let mut blocked_peer = writer();
+        let awareness = Message::Awareness(AwarenessUpdate {
+            clients: HashMap::from([(
+                42,
+                AwarenessUpdateEntry {
+                    clock: 1,
+                    json: "{}".into(),
+                },
+            )]),
+        })
+        .encode_v1();
+        assert!(workspace
+            .validate_sync_message(&awareness, &mut blocked_peer)
+            .is_ok());
+        assert!(matches!(
+            workspace.validate_sync_message(&delete, &mut blocked_peer),
+            Err(SyncValidationError::BlockedClient(42))
+        ));
+        assert!(workspace.validate_sync_message(&delete, &mut peer).is_ok());
+
+        workspace.set_max_message_bytes(4);
+        assert!(matches!(
+            workspace.validate_sync_message(&edit, &mut writer()),
+            Err(SyncValidationError::TooLarge { max: 4, .. })
+        ));
+        workspace.set_max_message_bytes(DEFAULT_MAX_MESSAGE_BYTES);
+
+        assert!(matches!(
+            workspace.validate_sync_message(&[], &mut writer()),
+            Err(SyncValidationError::Empty)
+        ));
+        assert!(matches!(
+            workspace.validate_sync_message(&[0, 2, 200], &mut writer()),
+            Err(SyncValidationError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn validate_permissions() {
+        let mut workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            t.set_permission("reader", WorkspacePermission::ReadOnly)
+                .unwrap();
+        });
+
+        let client = Doc::with_client_id(1);
+        client
+            .transact_mut()
+            .apply_update(Update::decode_v1(&workspace.sync_migration()).unwrap());
+        let permissions = client.get_or_insert_map("space:permissions");
+        let blocks = client.get_or_insert_map("blocks");
+
+        // writers can't change permissions
+        let escalate = update_message(&client, |trx| {
+            permissions.insert(trx, "writer", "admin");
+        });
+        assert!(matches!(
+            workspace.validate_sync_message(&escalate, &mut writer()),
+            Err(SyncValidationError::Permission(PermissionError::Denied {
+                user,
+                required: WorkspacePermission::Admin
+            })) if user == "writer"
+        ));
+        assert!(workspace
+            .sync_decode_untrusted_message(&escalate, &mut writer())
+            .is_err());
+        let writer_user = writer().user().clone();
+        workspace.with_trx(|t| {
+            assert_eq!(
+                workspace.get_permission(&t.trx, &writer_user),
+                WorkspacePermission::Write
+            )
+        });
+
+        // the permission belongs to the user, whatever client id it picks
+        let edit = update_message(&client, |trx| {
+            blocks.insert(trx, "a", MapPrelim::<Any>::from(HashMap::new()));
+        });
+        let mut reader = SyncPeer::new(WorkspaceUser::new("reader", WorkspacePermission::Write));
+        assert!(matches!(
+            workspace.validate_sync_message(&edit, &mut reader),
+            Err(SyncValidationError::Permission(_))
+        ));
+        let mut granted = SyncPeer::new(WorkspaceUser::new("guest", WorkspacePermission::ReadOnly));
+        assert!(workspace
+            .validate_sync_message(&edit, &mut granted)
+            .is_err());
+        assert!(workspace
+            .validate_sync_message(&edit, &mut writer())
+            .is_ok());
+        let mut admin = SyncPeer::new(WorkspaceUser::new("admin", WorkspacePermission::Admin));
+        assert!(workspace
+            .validate_sync_message(&escalate, &mut admin)
+            .is_ok());
+    }
+}
@@ -0,0 +1,237 @@
+use super::*;
+use crate::{constants::sys, PropertyKey};
+use std::{
+    sync::{Arc, Mutex},
+    thread::{self, ThreadId},
+};
+use yrs::{block::Prelim, Map, MapRef, TransactionMut};
+
+/// Whether the system properties of the blocks, see [PropertyKey::is_system], can be
+/// written. Shared by a workspace, its clones and their blocks, writes are denied unless
+/// they are made in a transaction of [Workspace::with_system_access].
+#[derive(Debug, Clone, Default)]
+pub struct SystemKeyPolicy {
+    // thread running the transaction with system access, the doc only allows one write
+    // transaction at a time, so the other threads can't be in it
+    scope: Arc<Mutex<Option<ThreadId>>>,
+}
+
+impl PartialEq for SystemKeyPolicy {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.scope, &other.scope)
+    }
+}
+
+impl SystemKeyPolicy {
+    /// `true` if `key` can be written in the current transaction.
+    pub fn allows(&self, key: PropertyKey) -> bool {
+        !key.is_system() || *self.scope.lock().unwrap() == Some(thread::current().id())
+    }
+
+    /// Reports a key that can't be written as [JwstError::SystemKeyDenied].
+    pub fn check(&self, key: PropertyKey) -> JwstResult<()> {
+        if self.allows(key) {
+            Ok(())
+        } else {
+            Err(JwstError::SystemKeyDenied(key.as_str().to_owned()))
+        }
+    }
+
+    fn enter(&self) -> SystemAccess<'_> {
+        *self.scope.lock().unwrap() = Some(thread::current().id());
+        SystemAccess(self)
+    }
+}
+
+// allows the system writes until dropped
+struct SystemAccess<'a>(&'a SystemKeyPolicy);
+
+impl Drop for SystemAccess<'_> {
+    fn drop(&mut self) {
+        *self.0.scope.lock().unwrap() = None;
+    }
+}
+
+// Write a system property the workspace keeps itself: the flavour, version and creation
+// time of new blocks, revisions, the tree, pins, thumbnails, repaired timestamps and the
+// blocked clients. They are written on behalf of any user, so the policy isn't asked,
+// every other write of a system property goes through [SystemKeyPolicy::check].
+pub(crate) fn insert_system_key<V: Prelim>(
+    trx: &mut TransactionMut,
+    map: &MapRef,
+    key: PropertyKey,
+    value: V,
+) {
+    debug_assert!(key.is_system(), "{} is not a system key", key.as_str());
+    map.insert(trx, key.as_str(), value);
+}
+
+// same as [insert_system_key], for removing the property
+pub(crate) fn remove_system_key(trx: &mut TransactionMut, map: &MapRef, key: PropertyKey) {
+    debug_assert!(key.is_system(), "{} is not a system key", key.as_str());
+    map.remove(trx, key.as_str());
+}
+
+impl Workspace {
+    /// Policy on writing the system properties of the blocks of this workspace.
+    pub fn system_keys(&self) -> &SystemKeyPolicy {
+        &self.system_keys
+    }
+
+    /// Like [Workspace::with_trx], but the system properties of the blocks can be written
+    /// in the transaction, for migrations and tools like [Workspace::rename_flavour].
+    pub fn with_system_access<T>(&self, f: impl FnOnce(WorkspaceTransaction) -> T) -> T {
+        self.with_trx(|t| {
+            let _access = self.system_keys.enter();
+            f(t)
+        })
+    }
+
+    /// Change the flavour of the blocks of flavour `from` to `to`, returns the number of
+    /// changed blocks.
+    pub fn rename_flavour(&self, from: &str, to: &str) -> usize {
+        self.with_system_access(|mut t| {
+            let blocks = self.get_blocks_by_flavour(&t.trx, from);
+            for block in &blocks {
+                block.set(&mut t.trx, sys::FLAVOR, to);
+            }
+            info!(
+                "renamed flavour of {} blocks: {} -> {}",
+                blocks.len(),
+                from,
+                to
+            );
+            blocks.len()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lib0::any::Any;
+
+    #[test]
+    fn system_keys_denied() {
+        let workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            let block = t.create("block", "affine:text");
+            assert!(matches!(
+                block.try_set(&mut t.trx, sys::FLAVOR, "affine:page"),
+                Err(JwstError::SystemKeyDenied(key)) if key == sys::FLAVOR
+            ));
+            block.set(&mut t.trx, "sys:custom", "value");
+            block.set(&mut t.trx, "title", "hello");
+
+            assert_eq!(block.flavor(&t.trx), "affine:text");
+            assert_eq!(block.get(&t.trx, "sys:custom"), None);
+            assert_eq!(
+                block.get(&t.trx, "title"),
+                Some(Any::String("hello".into()))
+            );
+        });
+    }
+
+    #[test]
+    fn system_access() {
+        let workspace = Workspace::new("test");
+        workspace.with_system_access(|mut t| {
+            let block = t.create("block", "affine:text");
+            block.try_set(&mut t.trx, "sys:custom", "value").unwrap();
+            assert_eq!(
+                block.get(&t.trx, "sys:custom"),
+                Some(Any::String("value".into()))
+            );
+        });
+
+        // only for the transaction, and on the blocks read through clones too
+        let clone = workspace.clone();
+        clone.with_trx(|mut t| {
+            let block = clone.get(&t.trx, "block").unwrap();
+            assert!(block.try_set(&mut t.trx, "sys:custom", Any::Null).is_err());
+            assert!(clone
+                .system_keys()
+                .check(PropertyKey::new("sys:custom"))
+                .is_err());
+        });
+        clone.with_system_access(|mut t| {
+            let block = clone.get(&t.trx, "block").unwrap();
+            block.try_set(&mut t.trx, "sys:custom", Any::Null).unwrap();
+            assert_eq!(block.get(&t.trx, "sys:custom"), None);
+        });
+    }
+
+    #[test]
+    fn system_access_other_thread() {
+        let workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| t.create("block", "affine:text"));
+
+        workspace.with_system_access(|_| {
+            let policy = workspace.system_keys().clone();
+            let allowed = thread::spawn(move || policy.allows(PropertyKey::new(sys::FLAVOR)));
+            assert!(!allowed.join().unwrap());
+        });
+    }
+
+    #[test]
+    fn legacy_keys_not_exported() {
+        let workspace = Workspace::new("test");
+        let block = workspace.with_trx(|mut t| {
+            let block = t.create("block", "affine:text");
+            block.set(&mut t.trx, "title", "hello");
+            // written as a regular property before system keys were protected
+            let map = workspace
+                .blocks
+                .get(&t.trx, "block")
+                .and_then(|block| block.to_ymap())
+                .unwrap();
+            map.insert(&mut t.trx, "prop:sys:flavor", "affine:page");
+            block
+        });
+
+        let json = serde_json::to_value(&block).unwrap();
+        assert_eq!(json["sys:flavor"], "affine:text");
+        assert_eq!(json["prop:title"], "hello");
+        assert!(json.get("prop:sys:flavor").is_none());
+
+        let json = serde_json::to_value(&workspace).unwrap();
+        assert!(json["blocks"]["block"].get("prop:sys:flavor").is_none());
+        let mut export = workspace.clone().json_export(None, 1).unwrap();
+        let mut exported = vec![];
+        while let Some(chunk) = export.next_chunk().unwrap() {
+            exported.extend(chunk);
+        }
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&exported).unwrap(),
+            json
+        );
+    }
+
+    #[test]
+    fn rename_flavour() {
+        let workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            t.create("a", "affine:text");
+            t.create("b", "affine:text");
+            t.create("c", "affine:page");
+        });
+
+        assert_eq!(
+            workspace.rename_flavour("affine:text", "affine:paragraph"),
+            2
+        );
+        workspace.with_trx(|t| {
+            assert_eq!(
+                workspace
+                    .get_blocks_by_flavour(&t.trx, "affine:paragraph")
+                    .len(),
+                2
+            );
+            assert!(workspace
+                .get_blocks_by_flavour(&t.trx, "affine:text")
+                .is_empty());
+            let c = workspace.get(&t.trx, "c").unwrap();
+            assert_eq!(c.flavor(&t.trx), "affine:page");
+        });
+    }
+}
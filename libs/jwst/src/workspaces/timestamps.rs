@@ -0,0 +1,435 @@
+use super::*;
+use crate::{constants::sys, PropertyKey};
+use lib0::any::Any;
+use serde::Serialize;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, BinaryHeap},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use utoipa::ToSchema;
+use yrs::{types::Value, Array, ArrayRef, Map, MapRef, ReadTxn, TransactionMut};
+
+/// How far a block timestamp may be off the clock of the workspace before it's treated
+/// as broken.
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(24 * 60 * 60);
+
+type ClockFn = Arc<dyn Fn() -> i64 + Send + Sync>;
+
+/// Source of the unix timestamps in milliseconds the workspace writes: the creation time
+/// and the history of the blocks, the creation time of links and the `updated_at`
+/// metadata. Shared by a workspace, its clones and their blocks, the system clock unless
+/// [another one is set](Workspace::set_clock).
+#[derive(Clone)]
+pub struct WorkspaceClock(Arc<RwLock<ClockFn>>);
+
+impl WorkspaceClock {
+    pub fn now(&self) -> i64 {
+        let clock = self.0.read().unwrap().clone();
+        clock()
+    }
+}
+
+impl Default for WorkspaceClock {
+    fn default() -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(|| {
+            chrono::Utc::now().timestamp_millis()
+        }))))
+    }
+}
+
+impl std::fmt::Debug for WorkspaceClock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("WorkspaceClock").field(&self.now()).finish()
+    }
+}
+
+impl PartialEq for WorkspaceClock {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// A block timestamp found or replaced by [Workspace::validate_timestamps] and
+/// [Workspace::repair_timestamps].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct TimestampRepair {
+    pub block_id: String,
+    /// Position of the entry in the block history, `None` for the creation time of the block.
+    pub history_index: Option<u32>,
+    /// Unix timestamp in milliseconds written by the client.
+    pub original: i64,
+    pub repaired: i64,
+}
+
+fn number(value: Option<Value>) -> Option<f64> {
+    match value {
+        Some(Value::Any(Any::Number(number))) => Some(number),
+        Some(Value::Any(Any::BigInt(number))) => Some(number as f64),
+        _ => None,
+    }
+}
+
+fn block_map<T: ReadTxn>(workspace: &Workspace, trx: &T, id: &str) -> Option<MapRef> {
+    match workspace.blocks.get(trx, id) {
+        Some(Value::YMap(block)) => Some(block),
+        _ => None,
+    }
+}
+
+// the `[client, timestamp, action]` entries of the history of a block
+fn history_entries<T: ReadTxn>(workspace: &Workspace, trx: &T, id: &str) -> Vec<ArrayRef> {
+    match workspace.updated.get(trx, id) {
+        Some(Value::YArray(history)) => history
+            .iter(trx)
+            .filter_map(|entry| entry.to_yarray())
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn set_history_timestamp(trx: &mut TransactionMut, entry: &ArrayRef, timestamp: i64) {
+    entry.remove(trx, 1);
+    entry.insert(trx, 1, timestamp as f64);
+}
+
+impl Workspace {
+    /// The clock the timestamps of this workspace are taken from.
+    pub fn clock(&self) -> &WorkspaceClock {
+        &self.clock
+    }
+
+    /// Take the timestamps of this workspace, its clones and their blocks from `clock`,
+    /// which returns unix timestamps in milliseconds.
+    pub fn set_clock(&self, clock: impl Fn() -> i64 + Send + Sync + 'static) {
+        *self.clock.0.write().unwrap() = Arc::new(clock);
+    }
+
+    /// Block timestamps written by clients with broken clocks: creation times and history
+    /// entries before `created_at`, the creation time of the workspace, or more than
+    /// [MAX_CLOCK_SKEW] ahead of the [clock](Workspace::clock). They are reported with
+    /// `created_at` as the value [Workspace::repair_timestamps] replaces them with.
+    pub fn validate_timestamps<T: ReadTxn>(
+        &self,
+        trx: &T,
+        created_at: i64,
+    ) -> Vec<TimestampRepair> {
+        let latest = self.clock.now() + MAX_CLOCK_SKEW.as_millis() as i64;
+        let is_valid = |timestamp: i64| (created_at..=latest).contains(&timestamp);
+        let repair = |block_id: &str, history_index, original| TimestampRepair {
+            block_id: block_id.to_owned(),
+            history_index,
+            original,
+            repaired: created_at,
+        };
+
+        let mut repairs = vec![];
+        for id in self.blocks.keys(trx) {
+            let created =
+                block_map(self, trx, id).and_then(|block| number(block.get(trx, sys::CREATED)));
+            if let Some(created) = created.filter(|created| !is_valid(*created as i64)) {
+                repairs.push(repair(id, None, created as i64));
+            }
+            for (index, entry) in history_entries(self, trx, id).into_iter().enumerate() {
+                if let Some(timestamp) =
+                    number(entry.get(trx, 1)).filter(|ts| !is_valid(*ts as i64))
+                {
+                    repairs.push(repair(id, Some(index as u32), timestamp as i64));
+                }
+            }
+        }
+        repairs
+    }
+
+    /// Replace the timestamps reported by [Workspace::validate_timestamps] with
+    /// `created_at`, returns the replaced values.
+    pub fn repair_timestamps(
+        &self,
+        trx: &mut TransactionMut,
+        created_at: i64,
+    ) -> Vec<TimestampRepair> {
+        let repairs = self.validate_timestamps(&*trx, created_at);
+        for repair in &repairs {
+            match repair.history_index {
+                None => {
+                    if let Some(block) = block_map(self, &*trx, &repair.block_id) {
+                        insert_system_key(
+                            trx,
+                            &block,
+                            PropertyKey::new(sys::CREATED),
+                            created_at as f64,
+                        );
+                    }
+                }
+                Some(index) => {
+                    let entries = history_entries(self, &*trx, &repair.block_id);
+                    if let Some(entry) = entries.get(index as usize) {
+                        set_history_timestamp(trx, entry, created_at);
+                    }
+                }
+            }
+        }
+
+        if !repairs.is_empty() {
+            info!("repaired {} timestamps of {}", repairs.len(), self.id());
+        }
+        repairs
+    }
+
+    // creation times of the blocks before an untrusted update changes them,
+    // see [Workspace::normalize_timestamps]
+    pub(super) fn created_timestamps<T: ReadTxn>(
+        &self,
+        trx: &T,
+        blocks: &BTreeSet<String>,
+    ) -> BTreeMap<String, Option<i64>> {
+        blocks
+            .iter()
+            .map(|id| {
+                let created =
+                    block_map(self, trx, id).and_then(|block| number(block.get(trx, sys::CREATED)));
+                (id.clone(), created.map(|created| created as i64))
+            })
+            .collect()
+    }
+
+    // replace the timestamps an untrusted client wrote into the blocks it changed with
+    // the time of the clock if they are off by more than [MAX_CLOCK_SKEW]: a changed
+    // creation time, and the history entries ahead of the clock or before the creation
+    // of the block. `created` are the creation times before the change.
+    pub(super) fn normalize_timestamps(
+        &self,
+        trx: &mut TransactionMut,
+        created: &BTreeMap<String, Option<i64>>,
+    ) -> Vec<TimestampRepair> {
+        let now = self.clock.now();
+        let skew = MAX_CLOCK_SKEW.as_millis() as i64;
+        let mut repairs = vec![];
+        for (id, before) in created {
+            let Some(block) = block_map(self, &*trx, id) else {
+                continue;
+            };
+            let mut created = number(block.get(&*trx, sys::CREATED)).map(|created| created as i64);
+            if let Some(original) = created.filter(|created| {
+                Some(*created) != *before && !(now - skew..=now + skew).contains(created)
+            }) {
+                insert_system_key(trx, &block, PropertyKey::new(sys::CREATED), now as f64);
+                created = Some(now);
+                repairs.push(TimestampRepair {
+                    block_id: id.clone(),
+                    history_index: None,
+                    original,
+                    repaired: now,
+                });
+            }
+
+            let earliest = created.unwrap_or(i64::MIN);
+            for (index, entry) in history_entries(self, &*trx, id).into_iter().enumerate() {
+                let Some(timestamp) = number(entry.get(&*trx, 1)).map(|ts| ts as i64) else {
+                    continue;
+                };
+                if timestamp < earliest || timestamp > now + skew {
+                    set_history_timestamp(trx, &entry, now);
+                    repairs.push(TimestampRepair {
+                        block_id: id.clone(),
+                        history_index: Some(index as u32),
+                        original: timestamp,
+                        repaired: now,
+                    });
+                }
+            }
+        }
+
+        if !repairs.is_empty() {
+            warn!(
+                "normalized {} client timestamps of {}",
+                repairs.len(),
+                self.id()
+            );
+        }
+        repairs
+    }
+
+    /// A page of the well-formed blocks, the most recently updated first and blocks updated
+    /// at the same time by id. A block is updated at the time of its last history entry,
+    /// or at its creation. Only that timestamp is read for the other blocks, and only the
+    /// blocks of the page are built.
+    pub fn blocks_sorted_by_updated<T: ReadTxn>(
+        &self,
+        trx: &T,
+        offset: usize,
+        limit: usize,
+    ) -> Vec<Block> {
+        let size = offset.saturating_add(limit);
+        if size == 0 {
+            return vec![];
+        }
+
+        // the `size` most recently updated blocks, the least recent one on top
+        let mut page = BinaryHeap::new();
+        for (id, block) in self.blocks.iter(trx) {
+            let Value::YMap(block) = block else {
+                continue;
+            };
+            let last_entry = match self.updated.get(trx, id) {
+                Some(Value::YArray(history)) => history
+                    .len(trx)
+                    .checked_sub(1)
+                    .and_then(|last| history.get(trx, last))
+                    .and_then(|entry| entry.to_yarray())
+                    .and_then(|entry| number(entry.get(trx, 1))),
+                _ => None,
+            };
+            let updated = last_entry
+                .or_else(|| number(block.get(trx, sys::CREATED)))
+                .unwrap_or_default() as i64;
+            page.push((Reverse(updated), id));
+            if page.len() > size {
+                page.pop();
+            }
+        }
+
+        page.into_sorted_vec()
+            .into_iter()
+            .skip(offset)
+            .filter_map(|(_, id)| self.get(trx, id))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use y_sync::sync::{Message, SyncMessage};
+    use yrs::{updates::encoder::Encode, ReadTxn, Transact};
+
+    const NOW: i64 = 1_700_000_000_000;
+    const HOUR: i64 = 60 * 60 * 1000;
+
+    // what a client with a broken clock writes into the history of a block
+    fn set_history(workspace: &Workspace, trx: &mut TransactionMut, id: &str, ts: i64) {
+        let entry = history_entries(workspace, &*trx, id).remove(0);
+        set_history_timestamp(trx, &entry, ts);
+    }
+
+    fn sorted_ids(workspace: &Workspace, offset: usize, limit: usize) -> Vec<String> {
+        workspace.with_trx(|t| {
+            workspace
+                .blocks_sorted_by_updated(&t.trx, offset, limit)
+                .iter()
+                .map(|block| block.id())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn clock() {
+        let workspace = Workspace::new("test");
+        workspace.set_clock(|| NOW);
+        let clone = workspace.clone();
+        clone.with_trx(|mut t| {
+            let block = t.create("a", "affine:paragraph");
+            assert_eq!(block.created(&t.trx), NOW as u64);
+            assert_eq!(block.updated(&t.trx), NOW as u64);
+        });
+
+        workspace.set_clock(|| NOW + HOUR);
+        workspace.with_trx(|mut t| {
+            let block = workspace.get(&t.trx, "a").unwrap();
+            block.set(&mut t.trx, "text", "updated");
+            assert_eq!(block.created(&t.trx), NOW as u64);
+            assert_eq!(block.updated(&t.trx), (NOW + HOUR) as u64);
+        });
+    }
+
+    #[test]
+    fn repair_timestamps() {
+        let created_at = NOW - HOUR;
+        let workspace = Workspace::new("test");
+        workspace.set_clock(|| NOW);
+        workspace.with_trx(|mut t| {
+            for id in ["a", "b", "c", "d"] {
+                t.create(id, "affine:paragraph");
+            }
+            set_history(&workspace, &mut t.trx, "d", NOW - 1);
+            // 2106 and 1970
+            set_history(&workspace, &mut t.trx, "a", 4_294_967_295_000);
+            set_history(&workspace, &mut t.trx, "b", 0);
+            let b = block_map(&workspace, &t.trx, "b").unwrap();
+            insert_system_key(&mut t.trx, &b, PropertyKey::new(sys::CREATED), 0.0);
+        });
+        assert_eq!(sorted_ids(&workspace, 0, 10), vec!["a", "c", "d", "b"]);
+
+        let expected = vec![
+            ("a".to_owned(), Some(0), 4_294_967_295_000, created_at),
+            ("b".to_owned(), None, 0, created_at),
+            ("b".to_owned(), Some(0), 0, created_at),
+        ];
+        let report = |repairs: Vec<TimestampRepair>| {
+            let mut repairs = repairs
+                .into_iter()
+                .map(|r| (r.block_id, r.history_index, r.original, r.repaired))
+                .collect::<Vec<_>>();
+            repairs.sort();
+            repairs
+        };
+
+        // validating changes nothing
+        let found = workspace.with_trx(|t| workspace.validate_timestamps(&t.trx, created_at));
+        assert_eq!(report(found), expected);
+        assert_eq!(sorted_ids(&workspace, 0, 10), vec!["a", "c", "d", "b"]);
+
+        let repairs =
+            workspace.with_trx(|mut t| workspace.repair_timestamps(&mut t.trx, created_at));
+        assert_eq!(report(repairs), expected);
+
+        // the repaired blocks sort as updated when the workspace was created
+        assert_eq!(sorted_ids(&workspace, 0, 10), vec!["c", "d", "a", "b"]);
+        assert_eq!(sorted_ids(&workspace, 1, 2), vec!["d", "a"]);
+        assert!(sorted_ids(&workspace, 4, 10).is_empty());
+        assert!(sorted_ids(&workspace, 0, 0).is_empty());
+        workspace.with_trx(|t| {
+            assert!(workspace.validate_timestamps(&t.trx, created_at).is_empty());
+        });
+    }
+
+    #[test]
+    fn normalize_untrusted_timestamps() {
+        let mut server = Workspace::new("test");
+        server.set_clock(|| NOW);
+        server.with_trx(|mut t| {
+            t.create("old", "affine:paragraph");
+        });
+
+        // a client whose clock is in 2106
+        let client = Workspace::from_doc(Default::default(), "test");
+        client.apply_updates(&[server.sync_migration()]).unwrap();
+        client.set_clock(|| 4_294_967_295_000);
+        let before = client.doc().transact().state_vector();
+        client.with_trx(|mut t| {
+            t.create("new", "affine:paragraph");
+            let old = client.get(&t.trx, "old").unwrap();
+            old.set(&mut t.trx, "text", "edited");
+        });
+        let update = client.doc().transact().encode_state_as_update_v1(&before);
+        let message = Message::Sync(SyncMessage::Update(update)).encode_v1();
+
+        let mut peer = SyncPeer::new(WorkspaceUser::new("writer", WorkspacePermission::Write));
+        server
+            .sync_decode_untrusted_message(&message, &mut peer)
+            .unwrap();
+        server.with_trx(|t| {
+            let new = server.get(&t.trx, "new").unwrap();
+            assert_eq!(new.created(&t.trx), NOW as u64);
+            assert_eq!(new.updated(&t.trx), NOW as u64);
+            let old = server.get(&t.trx, "old").unwrap();
+            assert_eq!(old.get(&t.trx, "text"), Some(Any::String("edited".into())));
+            assert_eq!(old.created(&t.trx), NOW as u64);
+            assert_eq!(old.updated(&t.trx), NOW as u64);
+        });
+        assert!(server
+            .with_trx(|t| server.validate_timestamps(&t.trx, NOW - HOUR))
+            .is_empty());
+    }
+}
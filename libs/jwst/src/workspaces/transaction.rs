@@ -2,19 +2,57 @@ use crate::utils::JS_INT_RANGE;
 
 use super::*;
 use lib0::any::Any;
-use yrs::{Map, TransactionMut};
+use std::collections::HashSet;
+use thiserror::Error;
+use yrs::{Map, Origin, ReadTxn, TransactionMut};
+
+#[derive(Debug, Error)]
+pub enum InsertError {
+    #[error("parent block {0} doesn't exist")]
+    ParentNotFound(String),
+    #[error("index {index} is out of bounds of the {len} children of {parent}")]
+    OutOfBounds {
+        parent: String,
+        index: usize,
+        len: usize,
+    },
+    #[error("block {0} already exists")]
+    ChildExists(String),
+    #[error(transparent)]
+    Rejected(#[from] JwstError),
+}
 
 pub struct WorkspaceTransaction<'a> {
     pub ws: &'a Workspace,
     pub trx: TransactionMut<'a>,
+    pub(super) user: Option<WorkspaceUser>,
 }
 
 unsafe impl Send for WorkspaceTransaction<'_> {}
 
 impl WorkspaceTransaction<'_> {
+    // remove a block, pinned blocks are kept
     pub fn remove<S: AsRef<str>>(&mut self, block_id: S) -> bool {
+        self.try_remove(block_id).unwrap_or_else(|e| {
+            warn!("refuse to remove block: {}", e);
+            false
+        })
+    }
+
+    // same as [WorkspaceTransaction::remove], but pinned blocks and a read only
+    // user are reported as an error
+    pub fn try_remove<S: AsRef<str>>(&mut self, block_id: S) -> JwstResult<bool> {
+        self.check_permission(WorkspacePermission::Write)?;
+        if matches!(
+            self.ws.get(&self.trx, block_id.as_ref()),
+            Some(block) if block.is_pinned(&self.trx)
+        ) {
+            return Err(JwstError::PinnedBlock(block_id.as_ref().to_owned()));
+        }
+
         info!("remove block: {}", block_id.as_ref());
-        self.ws
+        Ok(self
+            .ws
             .blocks
             .remove(&mut self.trx, block_id.as_ref())
             .is_some()
@@ -22,11 +60,43 @@ impl WorkspaceTransaction<'_> {
                 .ws
                 .updated
                 .remove(&mut self.trx, block_id.as_ref())
-                .is_some()
+                .is_some())
+    }
+
+    // remove blocks and detach them from their parents in this transaction,
+    // pinned and missing blocks are skipped, return the number of removed blocks
+    pub fn bulk_delete(&mut self, block_ids: &[&str]) -> JwstResult<usize> {
+        self.check_permission(WorkspacePermission::Write)?;
+
+        let mut removed = 0;
+        for block_id in block_ids {
+            let Some(block) = self.ws.get(&self.trx, block_id) else {
+                continue;
+            };
+            if block.is_pinned(&self.trx) {
+                warn!("refuse to remove pinned block: {}", block_id);
+                continue;
+            }
+
+            if let Some(parent) = block
+                .parent(&self.trx)
+                .and_then(|parent| self.ws.get(&self.trx, parent))
+            {
+                parent.remove_children(&mut self.trx, &block);
+            }
+            self.ws.blocks.remove(&mut self.trx, block_id);
+            self.ws.updated.remove(&mut self.trx, block_id);
+            removed += 1;
+        }
+
+        info!("bulk delete: {} of {} blocks", removed, block_ids.len());
+        Ok(removed)
     }
 
     // create a block with specified flavor
     // if block exists, return the exists block
+    // permissions are not checked, transactions of a user create blocks with
+    // [WorkspaceTransaction::try_create], see [UserTransaction]
     pub fn create<B, F>(&mut self, block_id: B, flavor: F) -> Block
     where
         B: AsRef<str>,
@@ -42,7 +112,94 @@ impl WorkspaceTransaction<'_> {
         )
     }
 
-    pub fn set_metadata(&mut self, key: &str, value: impl Into<Any>) {
+    // same as [WorkspaceTransaction::create], but a read only user is reported as an error
+    pub fn try_create<B, F>(&mut self, block_id: B, flavor: F) -> JwstResult<Block>
+    where
+        B: AsRef<str>,
+        F: AsRef<str>,
+    {
+        self.check_permission(WorkspacePermission::Write)?;
+        Ok(self.create(block_id, flavor))
+    }
+
+    // create a block with specified flavor and insert it into the children of `parent_id`
+    // at `index`, `index` equal to the number of children appends it
+    // nothing is created if the parent doesn't exist, `child_id` already exists, `index` is
+    // past the end, the user can't write or the block would exceed [Workspace::max_block_depth]
+    pub fn insert_at(
+        &mut self,
+        parent_id: &str,
+        index: usize,
+        child_id: &str,
+        flavour: &str,
+    ) -> Result<Block, InsertError> {
+        self.check_permission(WorkspacePermission::Write)
+            .map_err(JwstError::from)?;
+        let parent = self
+            .ws
+            .get(&self.trx, parent_id)
+            .ok_or_else(|| InsertError::ParentNotFound(parent_id.to_owned()))?;
+        // existing blocks are moved with [WorkspaceTransaction::move_child]
+        if self.ws.exists(&self.trx, child_id) {
+            return Err(InsertError::ChildExists(child_id.to_owned()));
+        }
+        let len = parent.children(&self.trx).len();
+        if index > len {
+            return Err(InsertError::OutOfBounds {
+                parent: parent_id.to_owned(),
+                index,
+                len,
+            });
+        }
+        // the new block is a single level under the parent
+        let max = self.ws.max_block_depth();
+        if ancestors(&self.trx, self.ws, &parent) + 1 > max {
+            return Err(JwstError::MaxDepthExceeded {
+                id: child_id.to_owned(),
+                max,
+            }
+            .into());
+        }
+
+        let child = self.create(child_id, flavour);
+        parent.insert_children_at(&mut self.trx, &child, index as u32);
+        Ok(child)
+    }
+
+    // check that `child` and its descendants stay within [Workspace::max_block_depth]
+    // when `child` is inserted into `parent`
+    pub fn check_nesting(&self, parent: &Block, child: &Block) -> JwstResult<()> {
+        let max = self.ws.max_block_depth();
+        if ancestors(&self.trx, self.ws, parent) + subtree_height(&self.trx, self.ws, child) > max {
+            return Err(JwstError::MaxDepthExceeded {
+                id: child.id(),
+                max,
+            });
+        }
+        Ok(())
+    }
+
+    // move `child` into `parent` at `pos`, or at the end if `pos` is `None`,
+    // nothing changes if it would exceed [Workspace::max_block_depth]
+    pub fn move_child(
+        &mut self,
+        parent: &Block,
+        child: &Block,
+        pos: Option<u32>,
+    ) -> JwstResult<()> {
+        self.check_permission(WorkspacePermission::Write)?;
+        self.check_nesting(parent, child)?;
+        match pos {
+            Some(pos) => parent.insert_children_at(&mut self.trx, child, pos),
+            None => parent.push_children(&mut self.trx, child),
+        }
+        Ok(())
+    }
+
+    // a read only user is reported as an error
+    pub fn set_metadata(&mut self, key: &str, value: impl Into<Any>) -> JwstResult<()> {
+        self.check_permission(WorkspacePermission::Write)?;
+
         info!("set metadata: {}", key);
         let key = key.to_string();
         match value.into() {
@@ -69,9 +226,54 @@ impl WorkspaceTransaction<'_> {
             }
             Any::Buffer(_) | Any::Array(_) | Any::Map(_) => {}
         }
+        Ok(())
+    }
+
+    // the origin this transaction was created with, see [Workspace::with_trx_origin]
+    pub fn origin(&self) -> Option<&Origin> {
+        self.trx.origin()
     }
 
     pub fn commit(&mut self) {
         self.trx.commit();
     }
 }
+
+// levels from the root to `block`, including both, only parents that still list
+// the block as a child count and a cycle ends the walk
+fn ancestors<T: ReadTxn>(trx: &T, ws: &Workspace, block: &Block) -> usize {
+    let mut visited = HashSet::from([block.id()]);
+    let mut current = block.clone();
+    while let Some(parent) = current
+        .parent(trx)
+        .and_then(|id| ws.get(trx, id))
+        .filter(|parent| parent.exists_children(trx, &current.id()).is_some())
+    {
+        if !visited.insert(parent.id()) {
+            break;
+        }
+        current = parent;
+    }
+    visited.len()
+}
+
+// levels of the tree under `block`, including it, walked without recursion
+fn subtree_height<T: ReadTxn>(trx: &T, ws: &Workspace, block: &Block) -> usize {
+    let mut height = 0;
+    let mut visited = HashSet::new();
+    let mut stack = vec![(block.clone(), 1)];
+    while let Some((block, depth)) = stack.pop() {
+        if !visited.insert(block.id()) {
+            continue;
+        }
+        height = height.max(depth);
+        stack.extend(
+            block
+                .children(trx)
+                .into_iter()
+                .filter_map(|child| ws.get(trx, child))
+                .map(|child| (child, depth + 1)),
+        );
+    }
+    height
+}
@@ -1,38 +1,151 @@
-use super::{plugins::setup_plugin, *};
+use super::{
+    plugins::{setup_plugin, WorkspacePlugins},
+    *,
+};
+use crate::block::retain_properties;
+use lib0::any::Any;
 use serde::{ser::SerializeMap, Serialize, Serializer};
-use std::sync::{Arc, RwLock};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU8, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
+use utoipa::ToSchema;
 use y_sync::{
     awareness::{Awareness, Event, Subscription as AwarenessSubscription},
     sync::{DefaultProtocol, Error, Message, MessageReader, Protocol, SyncMessage},
 };
 use yrs::{
-    types::{map::MapEvent, ToJson},
+    types::{map::MapEvent, ToJson, Value},
     updates::{
         decoder::{Decode, DecoderV1},
         encoder::{Encode, Encoder, EncoderV1},
     },
-    Doc, Map, MapRef, Observable, ReadTxn, StateVector, Subscription, Transact, TransactionMut,
-    Update, UpdateEvent, UpdateSubscription,
+    Doc, Map, MapRef, Observable, Origin, ReadTxn, StateVector, Subscription, Transact,
+    TransactionMut, Update, UpdateEvent, UpdateSubscription,
 };
 
 static PROTOCOL: DefaultProtocol = DefaultProtocol;
 
 use super::PluginMap;
-use plugins::PluginImpl;
+use futures::{future, Stream, StreamExt};
+use plugins::{BlockRef, PluginError, PluginImpl, PluginRegister};
+use tokio_stream::wrappers::BroadcastStream;
 
 pub type MapSubscription = Subscription<Arc<dyn Fn(&TransactionMut, &MapEvent)>>;
 
+/// CRDT internals of a workspace, see [Workspace::stats].
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct WorkspaceStats {
+    /// Number of clients in the state vector.
+    pub clients: usize,
+    /// Clock of every client, compare it with the state vector of a client to find divergence.
+    pub state_vector: HashMap<u64, u32>,
+    /// Sum of all client clocks, i.e. the number of integrated operations.
+    pub total_ops: u64,
+    /// `true` if received updates are waiting for missing updates before they can be integrated.
+    pub pending_update: bool,
+    /// `true` if received deletions are waiting for missing updates.
+    pub pending_delete_set: bool,
+    /// The clocks that must be received before the pending update can be integrated.
+    pub missing: HashMap<u64, u32>,
+    /// `false` if deleted content is kept (`skip_gc`).
+    pub gc_enabled: bool,
+}
+
+/// When each client last changed its awareness state.
+struct AwarenessActivity {
+    updated: Arc<RwLock<HashMap<u64, Instant>>>,
+    // keep tracking as long as any clone of the workspace is alive
+    _sub: AwarenessSubscription<Event>,
+}
+
+impl AwarenessActivity {
+    fn new(awareness: &mut Awareness) -> Self {
+        let updated = Arc::new(RwLock::new(HashMap::new()));
+        let sub = awareness.on_update({
+            let updated = updated.clone();
+            move |_, e| {
+                let now = Instant::now();
+                let mut updated = updated.write().unwrap();
+                for client in e.added().iter().chain(e.updated()) {
+                    updated.insert(*client, now);
+                }
+                for client in e.removed() {
+                    updated.remove(client);
+                }
+            }
+        });
+
+        Self { updated, _sub: sub }
+    }
+
+    fn touch(&self, clients: &[u64]) {
+        let now = Instant::now();
+        let mut updated = self.updated.write().unwrap();
+        for client in clients {
+            updated.insert(*client, now);
+        }
+    }
+
+    fn is_active(&self, client: u64, within: Duration) -> bool {
+        self.updated
+            .read()
+            .unwrap()
+            .get(&client)
+            .map_or(false, |updated| updated.elapsed() <= within)
+    }
+}
+
+/// Default of [Workspace::max_block_depth].
+pub const DEFAULT_MAX_BLOCK_DEPTH: usize = 1000;
+
+/// What happens when a callback passed to [Workspace::observe] panics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ObserverPanicPolicy {
+    /// Log the panic and keep the transaction and the other observers running.
+    #[default]
+    LogAndContinue,
+    /// Log the panic and abort the process.
+    Abort,
+    /// Resume the panic in the thread that committed the transaction.
+    Propagate,
+}
+
+impl From<u8> for ObserverPanicPolicy {
+    fn from(policy: u8) -> Self {
+        match policy {
+            1 => Self::Abort,
+            2 => Self::Propagate,
+            _ => Self::LogAndContinue,
+        }
+    }
+}
+
 pub struct Workspace {
     id: String,
-    awareness: Arc<RwLock<Awareness>>,
+    pub(super) awareness: Arc<RwLock<Awareness>>,
+    awareness_activity: Arc<AwarenessActivity>,
     pub(crate) blocks: MapRef,
     pub(crate) updated: MapRef,
     pub(crate) metadata: MapRef,
+    pub(super) links: MapRef,
+    pub(super) permissions: MapRef,
+    max_block_depth: Arc<AtomicUsize>,
+    pub(super) max_message_bytes: Arc<AtomicUsize>,
+    observer_panic_policy: Arc<AtomicU8>,
+    pub(super) system_keys: SystemKeyPolicy,
+    pub(super) clock: WorkspaceClock,
     /// We store plugins so that their ownership is tied to [Workspace].
     /// This enables us to properly manage lifetimes of observers which will subscribe
     /// into events that the [Workspace] experiences, like block updates.
     ///
     /// Public just for the crate as we experiment with the plugins interface.
+    /// Shared by the clones of the workspace, so each plugin is set up once.
     /// See [plugins].
     pub(super) plugins: PluginMap,
 }
@@ -47,64 +160,152 @@ impl Workspace {
     }
 
     pub fn from_doc<S: AsRef<str>>(doc: Doc, id: S) -> Workspace {
+        Self::with_plugins(doc, id, WorkspacePlugins::default())
+    }
+
+    /// See [WorkspaceBuilder].
+    pub(super) fn with_plugins<S: AsRef<str>>(
+        doc: Doc,
+        id: S,
+        plugins: WorkspacePlugins,
+    ) -> Workspace {
         let blocks = doc.get_or_insert_map("blocks");
         let updated = doc.get_or_insert_map("updated");
         let metadata = doc.get_or_insert_map("space:meta");
+        let links = doc.get_or_insert_map("space:links");
+        let permissions = doc.get_or_insert_map("space:permissions");
 
-        setup_plugin(Self {
-            id: id.as_ref().to_string(),
-            awareness: Arc::new(RwLock::new(Awareness::new(doc))),
+        let mut awareness = Awareness::new(doc);
+        let awareness_activity = Arc::new(AwarenessActivity::new(&mut awareness));
+
+        let mut workspace = Self::from_raw(
+            id,
+            Arc::new(RwLock::new(awareness)),
+            awareness_activity,
             blocks,
             updated,
             metadata,
-            plugins: Default::default(),
-        })
+            links,
+            permissions,
+            Arc::new(AtomicUsize::new(DEFAULT_MAX_BLOCK_DEPTH)),
+            Arc::new(AtomicUsize::new(DEFAULT_MAX_MESSAGE_BYTES)),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        );
+        plugins.setup(&mut workspace);
+        workspace
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn from_raw<S: AsRef<str>>(
         id: S,
         awareness: Arc<RwLock<Awareness>>,
+        awareness_activity: Arc<AwarenessActivity>,
         blocks: MapRef,
         updated: MapRef,
         metadata: MapRef,
+        links: MapRef,
+        permissions: MapRef,
+        max_block_depth: Arc<AtomicUsize>,
+        max_message_bytes: Arc<AtomicUsize>,
+        observer_panic_policy: Arc<AtomicU8>,
+        system_keys: SystemKeyPolicy,
+        clock: WorkspaceClock,
+        plugins: PluginMap,
     ) -> Workspace {
-        setup_plugin(Self {
+        Self {
             id: id.as_ref().to_string(),
             awareness,
+            awareness_activity,
             blocks,
             updated,
             metadata,
-            plugins: Default::default(),
-        })
+            links,
+            permissions,
+            max_block_depth,
+            max_message_bytes,
+            observer_panic_policy,
+            system_keys,
+            clock,
+            plugins,
+        }
+    }
+
+    /// Set up a plugin for this workspace and all of its clones, replacing the plugin
+    /// of the same type. The interface is experimental.
+    /// See [plugins].
+    pub fn register_plugin<R>(&mut self, register: R) -> Result<(), Box<dyn std::error::Error>>
+    where
+        R: PluginRegister + Clone + Send + Sync + 'static,
+    {
+        setup_plugin(self, register)
     }
 
     /// Allow the plugin to run any necessary updates it could have flagged via observers.
     /// See [plugins].
-    pub(super) fn update_plugin<P: PluginImpl>(&self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn update_plugin<P: PluginImpl>(&self) -> Result<(), Box<dyn std::error::Error>> {
         self.plugins.update_plugin::<P>(self)
     }
 
-    /// See [plugins].
-    pub(super) fn with_plugin<P: PluginImpl, T>(&self, cb: impl Fn(&P) -> T) -> Option<T> {
+    /// `None` if the plugin isn't set up for this workspace. See [plugins].
+    pub fn with_plugin<P: PluginImpl, T>(&self, cb: impl Fn(&P) -> T) -> Option<T> {
         self.plugins.with_plugin::<P, T>(cb)
     }
 
+    /// Errors returned by the plugins of this workspace and its clones, e.g. when the
+    /// search index fails to update. Errors are only received after subscribing,
+    /// and skipped if the stream is not polled fast enough.
+    pub fn subscribe_plugin_errors(&self) -> impl Stream<Item = PluginError> {
+        BroadcastStream::new(self.plugins.subscribe_errors())
+            .filter_map(|error| future::ready(error.ok()))
+    }
+
     #[cfg(feature = "workspace-search")]
-    pub fn search<S: AsRef<str>>(
+    pub fn search<O: Into<SearchOptions>>(
         &self,
-        options: S,
+        options: O,
     ) -> Result<SearchResults, Box<dyn std::error::Error>> {
         use plugins::IndexingPluginImpl;
 
         // refresh index if doc has update
         self.update_plugin::<IndexingPluginImpl>()?;
 
-        let options = options.as_ref();
+        let options = options.into();
 
-        self.with_plugin::<IndexingPluginImpl, Result<SearchResults, Box<dyn std::error::Error>>>(
-            |search_plugin| search_plugin.search(options),
-        )
-        .expect("text search was set up by default")
+        let mut results = self
+            .with_plugin::<IndexingPluginImpl, Result<SearchResults, Box<dyn std::error::Error>>>(
+                |search_plugin| search_plugin.search(options.clone()),
+            )
+            .expect("text search was set up by default")?;
+        results.render_snippets(&self.doc().transact(), self);
+        Ok(results)
+    }
+
+    /// [Workspace::search] restricted by block metadata.
+    #[cfg(feature = "workspace-search")]
+    pub fn search_with_filter(
+        &self,
+        query: &str,
+        filter: SearchFilter,
+    ) -> Result<SearchResults, Box<dyn std::error::Error>> {
+        self.search((query, filter))
+    }
+
+    /// Blocks linking to a block through one of [DEFAULT_LINK_PROPERTY_KEYS], ordered by id.
+    /// The index follows committed transactions, links set in `trx` itself are not included yet.
+    /// Always empty if the workspace was built without the backlinks plugin.
+    ///
+    /// [DEFAULT_LINK_PROPERTY_KEYS]: crate::DEFAULT_LINK_PROPERTY_KEYS
+    pub fn backlinks<T: ReadTxn>(&self, trx: &T, block_id: &str) -> Vec<BlockRef> {
+        use plugins::BacklinksPluginImpl;
+
+        if !self.exists(trx, block_id) {
+            return vec![];
+        }
+        self.with_plugin::<BacklinksPluginImpl, _>(|plugin| plugin.backlinks(self, trx, block_id))
+            .unwrap_or_default()
     }
 
     pub fn search_result(&self, query: String) -> String {
@@ -119,6 +320,24 @@ impl Workspace {
         let trx = WorkspaceTransaction {
             trx: doc.transact_mut(),
             ws: self,
+            user: None,
+        };
+
+        f(trx)
+    }
+
+    /// Like [Workspace::with_trx], but tags the transaction with an origin so observers
+    /// can tell server-initiated changes (migrations, cleanups) apart from user edits.
+    pub fn with_trx_origin<T>(
+        &self,
+        origin: impl Into<Origin>,
+        f: impl FnOnce(WorkspaceTransaction) -> T,
+    ) -> T {
+        let doc = self.doc();
+        let trx = WorkspaceTransaction {
+            trx: doc.transact_mut_with(origin),
+            ws: self,
+            user: None,
         };
 
         f(trx)
@@ -127,7 +346,11 @@ impl Workspace {
     pub fn try_with_trx<T>(&self, f: impl FnOnce(WorkspaceTransaction) -> T) -> Option<T> {
         match self.doc().try_transact_mut() {
             Ok(trx) => {
-                let trx = WorkspaceTransaction { trx, ws: self };
+                let trx = WorkspaceTransaction {
+                    trx,
+                    ws: self,
+                    user: None,
+                };
                 Some(f(trx))
             }
             Err(e) => {
@@ -145,10 +368,36 @@ impl Workspace {
         (&self.doc().transact(), self.metadata.clone()).into()
     }
 
+    /// Bump the `updated_at` metadata to now without touching any block, so activity
+    /// reaches the other devices and document lists can be sorted by it.
+    pub fn touch(&self) {
+        self.with_trx(|mut t| {
+            let now = self.clock.now() as f64;
+            self.metadata.insert(&mut t.trx, "updated_at", now);
+        });
+    }
+
+    /// Most levels of nested blocks, including the root, that
+    /// [WorkspaceTransaction::move_child] accepts and exports walk into.
+    pub fn max_block_depth(&self) -> usize {
+        self.max_block_depth.load(Ordering::Relaxed)
+    }
+
+    /// Change [Workspace::max_block_depth] of this workspace and its clones.
+    pub fn set_max_block_depth(&self, depth: usize) {
+        self.max_block_depth.store(depth, Ordering::Relaxed);
+    }
+
     pub fn client_id(&self) -> u64 {
         self.doc().client_id()
     }
 
+    /// Globally unique id of the doc, clients cache docs and reference subdocs by it.
+    /// A new doc gets a random one, build the workspace from a doc with the guid to keep.
+    pub fn guid(&self) -> String {
+        self.doc().guid().to_string()
+    }
+
     // get a block if exists
     pub fn get<T, S>(&self, trx: &T, block_id: S) -> Option<Block>
     where
@@ -158,10 +407,25 @@ impl Workspace {
         Block::from(trx, self, block_id, self.client_id())
     }
 
+    /// Same as [Workspace::get], but tells a malformed block apart from a missing one.
+    pub fn try_get<T, S>(&self, trx: &T, block_id: S) -> JwstResult<Option<Block>>
+    where
+        T: ReadTxn,
+        S: AsRef<str>,
+    {
+        let block_id = block_id.as_ref();
+        self.blocks
+            .get(trx, block_id)
+            .map(|block| self.read_block(trx, block_id, block))
+            .transpose()
+    }
+
     pub fn block_count(&self) -> u32 {
         self.blocks.len(&self.doc().transact())
     }
 
+    /// Iterate over all well-formed blocks, malformed ones are skipped,
+    /// see [Workspace::malformed_blocks].
     #[inline]
     pub fn blocks<T, R>(&self, trx: &T, cb: impl Fn(Box<dyn Iterator<Item = Block> + '_>) -> R) -> R
     where
@@ -170,21 +434,41 @@ impl Workspace {
         let iterator =
             self.blocks
                 .iter(trx)
-                .zip(self.updated.iter(trx))
-                .map(|((id, block), (_, updated))| {
-                    Block::from_raw_parts(
-                        trx,
-                        id.to_owned(),
-                        &self.doc(),
-                        block.to_ymap().unwrap(),
-                        updated.to_yarray().unwrap(),
-                        self.client_id(),
-                    )
+                .filter_map(|(id, block)| match self.read_block(trx, id, block) {
+                    Ok(block) => Some(block),
+                    Err(e) => {
+                        warn!("skip block: {}", e);
+                        None
+                    }
                 });
 
         cb(Box::new(iterator))
     }
 
+    /// Blocks that can't be read because the doc stores unexpected types for them,
+    /// each one is a [JwstError::MalformedBlock].
+    pub fn malformed_blocks<T>(&self, trx: &T) -> Vec<JwstError>
+    where
+        T: ReadTxn,
+    {
+        self.blocks
+            .iter(trx)
+            .filter_map(|(id, block)| self.read_block(trx, id, block).err())
+            .collect()
+    }
+
+    fn read_block<T: ReadTxn>(&self, trx: &T, id: &str, block: Value) -> JwstResult<Block> {
+        Block::from_raw_parts(
+            trx,
+            id.to_owned(),
+            &self.doc(),
+            block,
+            self.updated.get(trx, id),
+            self.client_id(),
+        )
+        .map(|block| block.with_settings(&self.system_keys, &self.clock))
+    }
+
     pub fn get_blocks_by_flavour<T>(&self, trx: &T, flavour: &str) -> Vec<Block>
     where
         T: ReadTxn,
@@ -196,6 +480,56 @@ impl Workspace {
         })
     }
 
+    /// Properties of the blocks of a flavour as a table: a row per block, ordered by
+    /// block id, starting with the block id and followed by a column per key of `columns`.
+    /// Properties a block doesn't have are `None`.
+    pub fn export_flavour_table<T>(
+        &self,
+        trx: &T,
+        flavour: &str,
+        columns: &[&str],
+    ) -> Vec<Vec<Option<Any>>>
+    where
+        T: ReadTxn,
+    {
+        let mut blocks = self.get_blocks_by_flavour(trx, flavour);
+        blocks.sort_by_key(|block| block.id());
+        blocks
+            .into_iter()
+            .map(|block| {
+                let mut content = block.content(trx);
+                std::iter::once(Some(Any::String(block.id().into())))
+                    .chain(columns.iter().map(|column| content.remove(*column)))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Blocks whose `field` prop equals `value`, string values can be compared
+    /// case insensitively. This is a linear scan over all blocks (O(n)),
+    /// use [Workspace::search] when the text is indexed.
+    pub fn find_blocks_by_field<T>(
+        &self,
+        trx: &T,
+        field: &str,
+        value: &Any,
+        case_insensitive: bool,
+    ) -> Vec<Block>
+    where
+        T: ReadTxn,
+    {
+        self.blocks(trx, |blocks| {
+            blocks
+                .filter(|block| match (block.get(trx, field), value) {
+                    (Some(Any::String(prop)), Any::String(value)) if case_insensitive => {
+                        prop.to_lowercase() == value.to_lowercase()
+                    }
+                    (prop, value) => prop.as_ref() == Some(value),
+                })
+                .collect::<Vec<_>>()
+        })
+    }
+
     pub fn observe_metadata(
         &mut self,
         f: impl Fn(&TransactionMut, &MapEvent) + 'static,
@@ -210,6 +544,106 @@ impl Workspace {
         self.awareness.write().unwrap().on_update(f)
     }
 
+    /// Current awareness state of every client, states that are not valid JSON are skipped.
+    pub fn awareness_states(&self) -> HashMap<u64, serde_json::Value> {
+        self.awareness
+            .read()
+            .unwrap()
+            .clients()
+            .iter()
+            .filter_map(|(client, state)| match serde_json::from_str(state) {
+                Ok(state) => Some((*client, state)),
+                Err(e) => {
+                    warn!("invalid awareness state of client {}: {}", client, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Awareness states of clients that changed their state within the given duration,
+    /// clients without a state are skipped.
+    pub fn active_awareness_states(&self, within: Duration) -> HashMap<u64, serde_json::Value> {
+        self.awareness_states()
+            .into_iter()
+            .filter(|(client, state)| {
+                !(state.is_null() || state.as_object().map_or(false, |state| state.is_empty()))
+                    && self.awareness_activity.is_active(*client, within)
+            })
+            .collect()
+    }
+
+    /// Count clients as active in [Workspace::active_awareness_states] without applying
+    /// their state, e.g. while a throttled awareness update waits to be applied.
+    pub fn refresh_awareness_activity(&self, clients: &[u64]) {
+        self.awareness_activity.touch(clients);
+    }
+
+    /// Diagnostic stats of the underlying CRDT document.
+    pub fn stats<T>(&self, trx: &T) -> WorkspaceStats
+    where
+        T: ReadTxn,
+    {
+        let state_vector = trx
+            .state_vector()
+            .iter()
+            .map(|(client, clock)| (*client, *clock))
+            .collect::<HashMap<_, _>>();
+        let store = trx.store();
+        let missing = store
+            .pending
+            .as_ref()
+            .map(|pending| {
+                pending
+                    .missing
+                    .iter()
+                    .map(|(client, clock)| (*client, *clock))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        WorkspaceStats {
+            clients: state_vector.len(),
+            total_ops: state_vector.values().map(|clock| *clock as u64).sum(),
+            state_vector,
+            pending_update: store.pending.is_some(),
+            pending_delete_set: store.pending_ds.is_some(),
+            missing,
+            gc_enabled: !store.options.skip_gc,
+        }
+    }
+
+    /// Summary of the workspace for logs and crash reports, e.g.
+    /// `Workspace(id=abc, blocks=42, clients=3, ops=1024, plugins=[BacklinksPluginImpl])`.
+    ///
+    /// `ops` is the number of integrated operations, the encoded size of the doc is left
+    /// out as it takes encoding the whole doc. The counts are `?` while a transaction is
+    /// open, so it can describe the workspace in the errors raised inside one.
+    pub fn describe(&self) -> String {
+        let counts = self.doc().try_transact().ok().map(|trx| {
+            let state_vector = trx.state_vector();
+            (
+                self.blocks.len(&trx),
+                state_vector.len(),
+                state_vector
+                    .iter()
+                    .map(|(_, clock)| *clock as u64)
+                    .sum::<u64>(),
+            )
+        });
+        let (blocks, clients, ops) = match counts {
+            Some((blocks, clients, ops)) => {
+                (blocks.to_string(), clients.to_string(), ops.to_string())
+            }
+            None => ("?".into(), "?".into(), "?".into()),
+        };
+        format!(
+            "Workspace(id={}, blocks={blocks}, clients={clients}, ops={ops}, plugins=[{}])",
+            self.id,
+            self.plugins.names().join(", ")
+        )
+    }
+
     /// Check if the block exists in this workspace's blocks.
     pub fn exists<T>(&self, trx: &T, block_id: &str) -> bool
     where
@@ -218,18 +652,36 @@ impl Workspace {
         self.blocks.contains_key(trx, block_id.as_ref())
     }
 
-    /// Subscribe to update events.
+    /// How panics in [Workspace::observe] callbacks are handled.
+    pub fn observer_panic_policy(&self) -> ObserverPanicPolicy {
+        self.observer_panic_policy.load(Ordering::Relaxed).into()
+    }
+
+    /// Change [Workspace::observer_panic_policy] of this workspace and its clones,
+    /// existing subscriptions included.
+    pub fn set_observer_panic_policy(&self, policy: ObserverPanicPolicy) {
+        self.observer_panic_policy
+            .store(policy as u8, Ordering::Relaxed);
+    }
+
+    /// Subscribe to update events, see [Workspace::observer_panic_policy] for panics in `f`.
     pub fn observe(
         &mut self,
         f: impl Fn(&TransactionMut, &UpdateEvent) + 'static,
     ) -> Option<UpdateSubscription> {
-        use std::panic::{catch_unwind, AssertUnwindSafe};
+        use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
         let doc = self.awareness.read().unwrap().doc().clone();
+        let policy = self.observer_panic_policy.clone();
 
         match catch_unwind(AssertUnwindSafe(move || {
             doc.observe_update_v1(move |trx, evt| {
                 if let Err(e) = catch_unwind(AssertUnwindSafe(|| f(trx, evt))) {
                     error!("panic in observe callback: {:?}", e);
+                    match policy.load(Ordering::Relaxed).into() {
+                        ObserverPanicPolicy::LogAndContinue => {}
+                        ObserverPanicPolicy::Abort => std::process::abort(),
+                        ObserverPanicPolicy::Propagate => resume_unwind(e),
+                    }
                 }
             })
             .ok()
@@ -258,6 +710,76 @@ impl Workspace {
         Ok(encoder.to_vec())
     }
 
+    /// Apply an update and return what it effectively changed in the doc.
+    ///
+    /// The delta can differ from `update`: parts that are already integrated are left out,
+    /// and parts waiting for missing dependencies are only included once they arrive,
+    /// so relays should forward the delta instead of the incoming update.
+    pub fn apply_update_and_get_delta(&self, update: &[u8]) -> Result<Vec<u8>, Error> {
+        self.apply_updates(&[update]).map(|(delta, _)| delta)
+    }
+
+    /// Apply updates in a single transaction, like [Workspace::apply_update_and_get_delta],
+    /// and return the merged delta with the state vector of the doc afterwards.
+    /// Nothing is applied if one of the updates can't be decoded.
+    pub fn apply_updates<U: AsRef<[u8]>>(
+        &self,
+        updates: &[U],
+    ) -> Result<(Vec<u8>, StateVector), Error> {
+        self.apply_updates_with_origin(updates, None)
+    }
+
+    // [Workspace::apply_updates] in a transaction of `origin`, observers can tell who
+    // made the changes by it
+    pub(super) fn apply_updates_with_origin<U: AsRef<[u8]>>(
+        &self,
+        updates: &[U],
+        origin: Option<Origin>,
+    ) -> Result<(Vec<u8>, StateVector), Error> {
+        let updates = updates
+            .iter()
+            .map(|update| Update::decode_v1(update.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let doc = self.doc();
+        let mut txn = match origin {
+            Some(origin) => doc.transact_mut_with(origin),
+            None => doc.transact_mut(),
+        };
+        for update in updates {
+            txn.apply_update(update);
+        }
+        txn.commit();
+        trace!("changed_parent_types: {:?}", txn.changed_parent_types());
+        trace!("before_state: {:?}", txn.before_state());
+        trace!("after_state: {:?}", txn.after_state());
+        Ok((txn.encode_update_v1(), txn.state_vector()))
+    }
+
+    /// Apply `updates` in `order`, a permutation of their indexes, one transaction each.
+    /// Updates arriving before the ones they depend on are kept pending by the doc, so
+    /// every order of the same updates has to converge to the same state.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn apply_updates_in_order(
+        &self,
+        updates: Vec<Vec<u8>>,
+        order: &[usize],
+    ) -> Result<(), Error> {
+        let mut sorted = order.to_vec();
+        sorted.sort_unstable();
+        assert!(
+            sorted.into_iter().eq(0..updates.len()),
+            "{order:?} is not a permutation of {} updates",
+            updates.len()
+        );
+
+        let doc = self.doc();
+        for &index in order {
+            let update = Update::decode_v1(&updates[index])?;
+            doc.transact_mut().apply_update(update);
+        }
+        Ok(())
+    }
+
     pub fn sync_handle_message(&mut self, msg: Message) -> Result<Option<Message>, Error> {
         trace!("processing message: {:?}", msg);
         match msg {
@@ -270,14 +792,7 @@ impl Workspace {
                     Update::decode_v1(&update)?,
                 ),
                 SyncMessage::Update(update) => {
-                    let doc = self.doc();
-                    let mut txn = doc.transact_mut();
-                    txn.apply_update(Update::decode_v1(&update)?);
-                    txn.commit();
-                    trace!("changed_parent_types: {:?}", txn.changed_parent_types());
-                    trace!("before_state: {:?}", txn.before_state());
-                    trace!("after_state: {:?}", txn.after_state());
-                    let update = txn.encode_update_v1();
+                    let update = self.apply_update_and_get_delta(&update)?;
                     Ok(Some(Message::Sync(SyncMessage::Update(update))))
                 }
             },
@@ -304,28 +819,108 @@ impl Workspace {
     }
 }
 
-impl Serialize for Workspace {
+/// Controls what [Workspace::to_json_with] emits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerializeOptions {
+    /// Include the `updated` map holding the history of every block.
+    pub include_updated: bool,
+    /// Include the workspace metadata.
+    pub include_metadata: bool,
+    pub pretty: bool,
+}
+
+impl Default for SerializeOptions {
+    // same output as the `Serialize` impl of `Workspace`
+    fn default() -> Self {
+        Self {
+            include_updated: true,
+            include_metadata: false,
+            pretty: false,
+        }
+    }
+}
+
+struct SerializeWith<'a> {
+    workspace: &'a Workspace,
+    options: SerializeOptions,
+}
+
+impl Serialize for SerializeWith<'_> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let doc = self.doc();
+        let Self { workspace, options } = self;
+        let doc = workspace.doc();
         let trx = doc.transact();
-        let mut map = serializer.serialize_map(Some(2))?;
-        map.serialize_entry("blocks", &self.blocks.to_json(&trx))?;
-        map.serialize_entry("updated", &self.updated.to_json(&trx))?;
+        let len = 1 + options.include_updated as usize + options.include_metadata as usize;
+        let mut map = serializer.serialize_map(Some(len))?;
+        let mut blocks = workspace.blocks.to_json(&trx);
+        if let Any::Map(blocks) = &mut blocks {
+            blocks.values_mut().for_each(retain_properties);
+        }
+        map.serialize_entry("blocks", &blocks)?;
+        if options.include_updated {
+            map.serialize_entry("updated", &workspace.updated.to_json(&trx))?;
+        }
+        if options.include_metadata {
+            map.serialize_entry("metadata", &workspace.metadata.to_json(&trx))?;
+        }
         map.end()
     }
 }
 
+impl Workspace {
+    /// Serialize the workspace into JSON, leaving out the parts not needed by `options`.
+    pub fn to_json_with(&self, options: SerializeOptions) -> serde_json::Result<String> {
+        let value = SerializeWith {
+            workspace: self,
+            options,
+        };
+        if options.pretty {
+            serde_json::to_string_pretty(&value)
+        } else {
+            serde_json::to_string(&value)
+        }
+    }
+}
+
+impl Serialize for Workspace {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        SerializeWith {
+            workspace: self,
+            options: SerializeOptions::default(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl std::fmt::Display for Workspace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.describe())
+    }
+}
+
 impl Clone for Workspace {
     fn clone(&self) -> Self {
         Self::from_raw(
             &self.id,
             self.awareness.clone(),
+            self.awareness_activity.clone(),
             self.blocks.clone(),
             self.updated.clone(),
             self.metadata.clone(),
+            self.links.clone(),
+            self.permissions.clone(),
+            self.max_block_depth.clone(),
+            self.max_message_bytes.clone(),
+            self.observer_panic_policy.clone(),
+            self.system_keys.clone(),
+            self.clock.clone(),
+            self.plugins.clone(),
         )
     }
 }
@@ -333,8 +928,11 @@ impl Clone for Workspace {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::{constants::sys, PropertyKey};
     use log::info;
-    use yrs::{updates::decoder::Decode, Doc, StateVector, Update};
+    use yrs::{
+        updates::decoder::Decode, ArrayPrelim, Doc, MapPrelim, Options, StateVector, Update,
+    };
 
     #[test]
     fn doc_load_test() {
@@ -419,4 +1017,596 @@ mod test {
         let workspace = Workspace::from_doc(doc, "test");
         assert_eq!(workspace.client_id(), 123);
     }
+
+    #[test]
+    fn export_flavour_table() {
+        let workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            let row = t.create("row2", "affine:database");
+            row.set(&mut t.trx, "title", "second");
+            let row = t.create("row1", "affine:database");
+            row.set(&mut t.trx, "title", "first");
+            row.set(&mut t.trx, "done", true);
+            t.create("page", "affine:page")
+                .set(&mut t.trx, "title", "page");
+        });
+
+        workspace.with_trx(|t| {
+            assert_eq!(
+                workspace.export_flavour_table(&t.trx, "affine:database", &["title", "done"]),
+                vec![
+                    vec![
+                        Some(Any::String("row1".into())),
+                        Some(Any::String("first".into())),
+                        Some(Any::Bool(true)),
+                    ],
+                    vec![
+                        Some(Any::String("row2".into())),
+                        Some(Any::String("second".into())),
+                        None,
+                    ],
+                ]
+            );
+            assert!(workspace
+                .export_flavour_table(&t.trx, "affine:list", &["title"])
+                .is_empty());
+        });
+    }
+
+    #[test]
+    fn awareness_states() {
+        let workspace = Workspace::from_doc(Doc::with_client_id(1), "test");
+        assert!(workspace.awareness_states().is_empty());
+
+        workspace
+            .awareness
+            .write()
+            .unwrap()
+            .set_local_state(r#"{"user":{"name":"alice"}}"#);
+
+        assert_eq!(
+            workspace.awareness_states(),
+            HashMap::from([(1, serde_json::json!({ "user": { "name": "alice" } }))])
+        );
+    }
+
+    #[test]
+    fn active_awareness_states() {
+        let workspace = Workspace::from_doc(Doc::with_client_id(1), "test");
+        workspace
+            .awareness
+            .write()
+            .unwrap()
+            .set_local_state(r#"{"user":{"name":"alice"}}"#);
+
+        let within = Duration::from_millis(100);
+        assert_eq!(workspace.active_awareness_states(within).len(), 1);
+        // shared by clones of the workspace
+        assert_eq!(workspace.clone().active_awareness_states(within).len(), 1);
+
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(workspace.active_awareness_states(within).is_empty());
+        workspace.refresh_awareness_activity(&[1]);
+        assert_eq!(workspace.active_awareness_states(within).len(), 1);
+
+        // empty state is not a collaborator
+        workspace.awareness.write().unwrap().set_local_state("{}");
+        assert!(workspace.active_awareness_states(within).is_empty());
+        assert_eq!(workspace.awareness_states().len(), 1);
+    }
+
+    #[test]
+    fn to_json_with() {
+        let workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            t.create("block", "text");
+            t.set_metadata("name", "test").unwrap();
+        });
+
+        let json = |options| {
+            serde_json::from_str::<serde_json::Value>(&workspace.to_json_with(options).unwrap())
+                .unwrap()
+        };
+
+        // default options keep the output of `Serialize`
+        assert_eq!(
+            json(SerializeOptions::default()),
+            serde_json::to_value(&workspace).unwrap()
+        );
+
+        let render = json(SerializeOptions {
+            include_updated: false,
+            include_metadata: true,
+            pretty: true,
+        });
+        assert!(render.get("blocks").unwrap().get("block").is_some());
+        assert!(render.get("updated").is_none());
+        assert_eq!(render["metadata"]["name"], "test");
+
+        let pretty = workspace
+            .to_json_with(SerializeOptions {
+                pretty: true,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(pretty.contains('\n'));
+    }
+
+    #[test]
+    fn stats() {
+        let workspace = Workspace::from_doc(Doc::with_client_id(1), "test");
+        workspace.with_trx(|mut t| {
+            let block = t.create("block", "text");
+            block.set(&mut t.trx, "text", "hello");
+        });
+
+        let stats = workspace.with_trx(|t| workspace.stats(&t.trx));
+        assert_eq!(stats.clients, 1);
+        assert!(stats.total_ops > 0);
+        assert_eq!(stats.state_vector.get(&1), Some(&(stats.total_ops as u32)));
+        assert!(!stats.pending_update);
+        assert!(stats.gc_enabled);
+
+        // an update that depends on content we never received stays pending
+        let remote = Doc::with_client_id(2);
+        let first = remote.get_or_insert_map("blocks");
+        first.insert(&mut remote.transact_mut(), "a", "a");
+        let sv = remote.transact().state_vector();
+        first.insert(&mut remote.transact_mut(), "b", "b");
+        let update = remote.transact().encode_state_as_update_v1(&sv);
+
+        workspace
+            .doc()
+            .transact_mut()
+            .apply_update(Update::decode_v1(&update).unwrap());
+
+        let stats = workspace.with_trx(|t| workspace.stats(&t.trx));
+        assert!(stats.pending_update);
+        assert!(stats.missing.contains_key(&2));
+        assert_eq!(stats.clients, 1);
+    }
+
+    #[test]
+    fn describe() {
+        let workspace = Workspace::from_doc(Doc::with_client_id(1), "test");
+        workspace.with_trx(|mut t| {
+            t.create("a", "text");
+            t.create("b", "text");
+        });
+        let ops = workspace.with_trx(|t| workspace.stats(&t.trx).total_ops);
+        let plugins = if cfg!(feature = "workspace-search") {
+            "BacklinksPluginImpl, IndexingPluginImpl"
+        } else {
+            "BacklinksPluginImpl"
+        };
+
+        let description = workspace.describe();
+        assert_eq!(
+            description,
+            format!("Workspace(id=test, blocks=2, clients=1, ops={ops}, plugins=[{plugins}])")
+        );
+        assert_eq!(workspace.to_string(), description);
+
+        // doesn't wait for an open transaction
+        workspace.with_trx(|_| {
+            assert_eq!(
+                workspace.describe(),
+                format!("Workspace(id=test, blocks=?, clients=?, ops=?, plugins=[{plugins}])")
+            );
+        });
+        assert_eq!(
+            WorkspaceBuilder::new("empty")
+                .plugins(WorkspacePlugins::none())
+                .build()
+                .describe(),
+            "Workspace(id=empty, blocks=0, clients=0, ops=0, plugins=[])"
+        );
+    }
+
+    #[test]
+    fn malformed_blocks() {
+        let workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            t.create("block", "text");
+        });
+
+        {
+            let doc = workspace.doc();
+            let mut trx = doc.transact_mut();
+            workspace.blocks.insert(&mut trx, "string", "not a block");
+            // block without children
+            workspace.blocks.insert(
+                &mut trx,
+                "no_children",
+                MapPrelim::<Any>::from(HashMap::new()),
+            );
+            workspace
+                .updated
+                .insert(&mut trx, "no_children", ArrayPrelim::<_, String>::from([]));
+            // block without history
+            let block = workspace.blocks.insert(
+                &mut trx,
+                "no_updated",
+                MapPrelim::<Any>::from(HashMap::new()),
+            );
+            insert_system_key(
+                &mut trx,
+                &block,
+                PropertyKey::new(sys::CHILDREN),
+                ArrayPrelim::<_, String>::from([]),
+            );
+        }
+
+        workspace.with_trx(|t| {
+            let ids = workspace.blocks(&t.trx, |blocks| blocks.map(|b| b.id()).collect::<Vec<_>>());
+            assert_eq!(ids, vec!["block"]);
+            assert_eq!(workspace.get_blocks_by_flavour(&t.trx, "text").len(), 1);
+
+            let mut malformed = workspace
+                .malformed_blocks(&t.trx)
+                .into_iter()
+                .map(|e| match e {
+                    JwstError::MalformedBlock {
+                        id,
+                        expected,
+                        found,
+                    } => (id, expected, found),
+                    e => panic!("unexpected error: {e}"),
+                })
+                .collect::<Vec<_>>();
+            malformed.sort();
+            assert_eq!(
+                malformed,
+                vec![
+                    (
+                        "no_children".to_owned(),
+                        "children array",
+                        "nothing".to_owned()
+                    ),
+                    (
+                        "no_updated".to_owned(),
+                        "updated array",
+                        "nothing".to_owned()
+                    ),
+                    ("string".to_owned(), "map", "string".to_owned()),
+                ]
+            );
+
+            assert!(workspace.get(&t.trx, "string").is_none());
+            assert!(matches!(
+                workspace.try_get(&t.trx, "string"),
+                Err(JwstError::MalformedBlock { id, .. }) if id == "string"
+            ));
+            assert!(matches!(workspace.try_get(&t.trx, "missing"), Ok(None)));
+            assert!(matches!(workspace.try_get(&t.trx, "block"), Ok(Some(_))));
+        });
+    }
+
+    #[test]
+    fn find_blocks_by_field() {
+        let workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            let block = t.create("a", "text");
+            block.set(&mut t.trx, "title", "Hello");
+            block.set(&mut t.trx, "done", true);
+            let block = t.create("b", "text");
+            block.set(&mut t.trx, "title", "hello");
+            block.set(&mut t.trx, "done", false);
+            t.create("c", "text");
+        });
+
+        workspace.with_trx(|t| {
+            let find = |field: &str, value: Any, case_insensitive| {
+                let mut ids = workspace
+                    .find_blocks_by_field(&t.trx, field, &value, case_insensitive)
+                    .iter()
+                    .map(|b| b.id())
+                    .collect::<Vec<_>>();
+                ids.sort();
+                ids
+            };
+
+            assert_eq!(find("title", "hello".into(), false), vec!["b"]);
+            assert_eq!(find("title", "hello".into(), true), vec!["a", "b"]);
+            assert_eq!(find("done", true.into(), true), vec!["a"]);
+            assert!(find("title", "world".into(), true).is_empty());
+            assert!(find("missing", "hello".into(), false).is_empty());
+        });
+    }
+
+    #[test]
+    fn with_trx_origin() {
+        let workspace = Workspace::new("test");
+
+        workspace.with_trx(|t| assert_eq!(t.origin(), None));
+
+        workspace.with_trx_origin("migration", |mut t| {
+            t.create("block", "text");
+            assert_eq!(t.origin(), Some(&Origin::from("migration")));
+            assert_eq!(t.trx.origin(), Some(&Origin::from("migration")));
+        });
+    }
+
+    #[test]
+    fn guid() {
+        let workspace = Workspace::new("test");
+        assert_ne!(workspace.guid(), Workspace::new("test").guid());
+        assert_eq!(workspace.clone().guid(), workspace.guid());
+
+        let doc = Doc::with_options(Options {
+            guid: "stable".into(),
+            ..Default::default()
+        });
+        assert_eq!(Workspace::from_doc(doc, "test").guid(), "stable");
+    }
+
+    #[test]
+    fn apply_update_and_get_delta() {
+        let remote = Workspace::new("test");
+        remote.with_trx(|mut t| {
+            t.create("block", "text");
+        });
+        let update = remote.sync_migration();
+
+        let relay = Workspace::new("test");
+        let delta = relay.apply_update_and_get_delta(&update).unwrap();
+
+        let downstream = Workspace::new("test");
+        downstream.apply_update_and_get_delta(&delta).unwrap();
+        downstream.with_trx(|t| assert!(downstream.exists(&t.trx, "block")));
+
+        // nothing changes when the same update arrives again
+        let again = relay.apply_update_and_get_delta(&update).unwrap();
+        assert!(again.len() < delta.len());
+        let again = downstream.apply_update_and_get_delta(&again).unwrap();
+        assert!(again.len() < delta.len());
+
+        assert!(relay.apply_update_and_get_delta(&[255, 255]).is_err());
+    }
+
+    #[test]
+    fn apply_updates() {
+        let remote = Workspace::new("test");
+        let updates = ["a", "b"]
+            .iter()
+            .map(|id| {
+                remote.with_trx(|mut t| {
+                    t.create(id, "text");
+                    t.trx.encode_update_v1()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut local = Workspace::new("test");
+        let commits = Arc::new(RwLock::new(0));
+        let _sub = local.observe({
+            let commits = commits.clone();
+            move |_, _| *commits.write().unwrap() += 1
+        });
+        let (delta, state_vector) = local.apply_updates(&updates).unwrap();
+        assert_eq!(*commits.read().unwrap(), 1);
+        assert_eq!(state_vector, remote.doc().transact().state_vector());
+        local.with_trx(|t| {
+            assert!(local.exists(&t.trx, "a"));
+            assert!(local.exists(&t.trx, "b"));
+        });
+
+        let downstream = Workspace::new("test");
+        downstream.apply_update_and_get_delta(&delta).unwrap();
+        assert_eq!(downstream.block_count(), 2);
+
+        // a malformed update rejects the whole batch
+        let other = Workspace::new("test");
+        let batch = [updates[0].clone(), vec![255, 255]];
+        assert!(other.apply_updates(&batch).is_err());
+        assert_eq!(other.block_count(), 0);
+    }
+
+    #[test]
+    fn apply_updates_in_order() {
+        let (a, b) = (
+            Workspace::from_doc(Doc::with_client_id(1), "test"),
+            Workspace::from_doc(Doc::with_client_id(2), "test"),
+        );
+        let mut updates = vec![];
+        // the second update of each client depends on its first one
+        for (workspace, id) in [(&a, "a"), (&b, "b")] {
+            updates.push(workspace.with_trx(|mut t| {
+                let block = t.create(id, "text");
+                block.set(&mut t.trx, "title", id);
+                t.trx.encode_update_v1()
+            }));
+            updates.push(workspace.with_trx(|mut t| {
+                let block = workspace.get(&t.trx, id).unwrap();
+                block.set(&mut t.trx, "title", format!("{id} edited"));
+                t.set_metadata("name", id).unwrap();
+                t.trx.encode_update_v1()
+            }));
+        }
+
+        let orders: [&[usize]; 4] = [&[0, 1, 2, 3], &[3, 2, 1, 0], &[1, 3, 0, 2], &[2, 0, 3, 1]];
+        let states = orders
+            .iter()
+            .map(|order| {
+                let workspace = Workspace::new("test");
+                workspace
+                    .apply_updates_in_order(updates.clone(), order)
+                    .unwrap();
+                workspace.with_trx(|t| {
+                    assert_eq!(
+                        workspace.get(&t.trx, "a").unwrap().get(&t.trx, "title"),
+                        Some(Any::String("a edited".into()))
+                    );
+                });
+                serde_json::to_value(&workspace).unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert!(states.windows(2).all(|pair| pair[0] == pair[1]));
+    }
+
+    #[test]
+    #[should_panic(expected = "not a permutation")]
+    fn apply_updates_in_invalid_order() {
+        let workspace = Workspace::new("test");
+        workspace
+            .apply_updates_in_order(vec![vec![], vec![]], &[0, 0])
+            .unwrap();
+    }
+
+    #[test]
+    fn bulk_delete() {
+        let workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            let page = t.create("page", "affine:page");
+            for id in ["a", "b", "c"] {
+                let block = t.create(id, "affine:paragraph");
+                page.push_children(&mut t.trx, &block);
+            }
+            t.create("pinned", "affine:paragraph")
+                .set_pinned(&mut t.trx, true);
+        });
+
+        workspace.with_trx(|mut t| {
+            assert_eq!(
+                t.bulk_delete(&["a", "c", "a", "missing", "pinned"])
+                    .unwrap(),
+                2
+            );
+
+            assert_eq!(workspace.blocks.len(&t.trx), 3);
+            assert_eq!(workspace.updated.len(&t.trx), 3);
+            assert!(!workspace.exists(&t.trx, "a"));
+            assert!(workspace.exists(&t.trx, "pinned"));
+            let page = workspace.get(&t.trx, "page").unwrap();
+            assert_eq!(page.children(&t.trx), vec!["b"]);
+        });
+    }
+
+    #[test]
+    fn touch() {
+        let mut workspace = Workspace::new("test");
+        let updates = Arc::new(RwLock::new(0));
+        let _sub = workspace.observe({
+            let updates = updates.clone();
+            move |_, _| *updates.write().unwrap() += 1
+        });
+
+        workspace.touch();
+        assert_eq!(*updates.read().unwrap(), 1);
+        workspace.with_trx(|t| assert!(workspace.metadata.get(&t.trx, "updated_at").is_some()));
+        assert_eq!(workspace.block_count(), 0);
+    }
+
+    #[test]
+    fn observer_panic_policy() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let mut workspace = Workspace::new("test");
+        assert_eq!(
+            workspace.observer_panic_policy(),
+            ObserverPanicPolicy::LogAndContinue
+        );
+        let _sub = workspace.observe(|_, _| panic!("observer failed"));
+
+        workspace.with_trx(|mut t| {
+            t.create("a", "affine:page");
+        });
+        assert!(workspace.with_trx(|t| workspace.exists(&t.trx, "a")));
+
+        workspace
+            .clone()
+            .set_observer_panic_policy(ObserverPanicPolicy::Propagate);
+        assert!(catch_unwind(AssertUnwindSafe(|| {
+            workspace.with_trx(|mut t| {
+                t.create("b", "affine:page");
+            })
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn max_block_depth() {
+        let workspace = Workspace::new("test");
+        assert_eq!(workspace.max_block_depth(), DEFAULT_MAX_BLOCK_DEPTH);
+        workspace.clone().set_max_block_depth(3);
+        assert_eq!(workspace.max_block_depth(), 3);
+
+        workspace.with_trx(|mut t| {
+            let a = t.create("a", "affine:page");
+            let b = t.create("b", "affine:frame");
+            let c = t.create("c", "affine:paragraph");
+            let d = t.create("d", "affine:paragraph");
+            t.move_child(&a, &b, None).unwrap();
+            t.move_child(&b, &c, Some(0)).unwrap();
+
+            // a > b > c > d
+            assert!(matches!(
+                t.move_child(&c, &d, None),
+                Err(JwstError::MaxDepthExceeded { id, max: 3 }) if id == "d"
+            ));
+            assert!(c.children(&t.trx).is_empty());
+
+            // d > a > b > c
+            assert!(t.move_child(&d, &a, None).is_err());
+            assert!(d.children(&t.trx).is_empty());
+
+            // siblings don't add levels
+            t.move_child(&b, &d, None).unwrap();
+            assert_eq!(b.children(&t.trx), vec!["c", "d"]);
+
+            // moving a block out of a tree no longer counts the old parents
+            a.remove_children(&mut t.trx, &b);
+            let e = t.create("e", "affine:paragraph");
+            t.move_child(&e, &b, None).unwrap();
+        });
+    }
+
+    #[test]
+    fn insert_at() {
+        let workspace = Workspace::new("test");
+        workspace.with_trx(|mut t| {
+            let page = t.create("page", "affine:page");
+            t.insert_at("page", 0, "b", "affine:paragraph").unwrap();
+            t.insert_at("page", 0, "a", "affine:paragraph").unwrap();
+            let c = t.insert_at("page", 2, "c", "affine:paragraph").unwrap();
+            assert_eq!(page.children(&t.trx), vec!["a", "b", "c"]);
+            assert_eq!(c.flavor(&t.trx), "affine:paragraph");
+            assert_eq!(c.parent(&t.trx).as_deref(), Some("page"));
+
+            assert!(matches!(
+                t.insert_at("missing", 0, "d", "affine:paragraph"),
+                Err(InsertError::ParentNotFound(id)) if id == "missing"
+            ));
+            assert!(matches!(
+                t.insert_at("page", 4, "d", "affine:paragraph"),
+                Err(InsertError::OutOfBounds {
+                    index: 4,
+                    len: 3,
+                    ..
+                })
+            ));
+            assert!(!workspace.exists(&t.trx, "d"));
+
+            // existing blocks are not spliced in, nor is the parent itself
+            for id in ["a", "page"] {
+                assert!(matches!(
+                    t.insert_at("page", 0, id, "affine:paragraph"),
+                    Err(InsertError::ChildExists(child)) if child == id
+                ));
+            }
+            assert_eq!(page.children(&t.trx), vec!["a", "b", "c"]);
+        });
+
+        workspace.clone().set_max_block_depth(2);
+        workspace.with_trx(|mut t| {
+            assert!(matches!(
+                t.insert_at("a", 0, "d", "affine:paragraph"),
+                Err(InsertError::Rejected(JwstError::MaxDepthExceeded {
+                    max: 2,
+                    ..
+                }))
+            ));
+            assert!(!workspace.exists(&t.trx, "d"));
+        });
+    }
 }